@@ -0,0 +1,195 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::Args;
+use serde::Serialize;
+
+use raptor_cdn::codec::manifest::{to_hex, validate_manifest, DecoderLimits, Manifest};
+use raptor_cdn::store::anti_entropy::DirShardStore;
+use raptor_cdn::store::audit::{take_inventory, Auditor, BlockAudit, BlockStatus, ObjectInventory, ShardInventory};
+use super::print_json;
+
+#[derive(Args)]
+pub struct AuditArgs {
+    /// Directory of shards, one per block, as kept by a storage node.
+    shard_dir: PathBuf,
+    /// Manifest of an object to check every block of. Repeat for more objects. Without any, the shards are only
+    /// listed.
+    #[arg(long = "manifest")]
+    manifests: Vec<PathBuf>,
+    /// Print the results as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest, String> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+    };
+    let manifest = match Manifest::read_from(&data[..]) {
+        Ok(manifest) => manifest,
+        Err(error) => return Err(format!("failed to parse {}: {}", path.display(), error)),
+    };
+    if let Err(issues) = validate_manifest(&manifest, &DecoderLimits::default()) {
+        return Err(format!("bad manifest {}: {:?}", path.display(), issues));
+    }
+    return Ok(manifest);
+}
+
+#[derive(Serialize)]
+struct ShardReport {
+    block_id: u32,
+    symbols: usize,
+    /// Encoding symbol ids held, as [start, end) ranges.
+    esi_ranges: Vec<(u32, u32)>,
+    foreign_symbols: usize,
+    bytes: u64,
+    error: Option<String>,
+}
+
+impl ShardReport {
+    fn new(shard: &ShardInventory) -> ShardReport {
+        return ShardReport {
+            block_id: shard.block_id,
+            symbols: shard.symbols,
+            esi_ranges: shard.esi_ranges.iter().map(|x| (x.start, x.end)).collect(),
+            foreign_symbols: shard.foreign_symbols,
+            bytes: shard.bytes,
+            error: shard.error.clone(),
+        };
+    }
+
+    fn print(&self) {
+        let ranges: Vec<String> = self.esi_ranges.iter().map(|(start, end)| format!("{}..{}", start, end)).collect();
+        println!("  block {:>6} symbols {:>6} bytes {:>10} esi {}", self.block_id, self.symbols, self.bytes, ranges.join(", "));
+        if self.foreign_symbols > 0 {
+            println!("    {} symbols of other blocks", self.foreign_symbols);
+        }
+        if let Some(error) = self.error.as_ref() {
+            println!("    unreadable: {}", error);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BlockReport {
+    block_id: u32,
+    expected: bool,
+    /// "ok", "missing", "unreadable", "too few symbols" or "hash mismatch".
+    status: &'static str,
+    /// Why the shard is unreadable, or how many more symbols it needs.
+    detail: Option<String>,
+    invalid_symbols: u32,
+    shard: Option<ShardReport>,
+}
+
+impl BlockReport {
+    fn new(block: &BlockAudit) -> BlockReport {
+        let (status, detail) = match &block.status {
+            BlockStatus::Ok => ("ok", None),
+            BlockStatus::Missing => ("missing", None),
+            BlockStatus::Unreadable(error) => ("unreadable", Some(error.clone())),
+            BlockStatus::TooFewSymbols(needed) => ("too few symbols", Some(format!("{} more needed", needed))),
+            BlockStatus::HashMismatch => ("hash mismatch", None),
+        };
+        return BlockReport {
+            block_id: block.block_id,
+            expected: block.expected,
+            status,
+            detail,
+            invalid_symbols: block.invalid_symbols,
+            shard: block.shard.as_ref().map(ShardReport::new),
+        };
+    }
+}
+
+#[derive(Serialize)]
+struct ObjectReport {
+    object_id: String,
+    /// Whether every block can be decoded from its shard, None without a manifest to check against.
+    can_serve: Option<bool>,
+    bytes: u64,
+    /// Blocks checked against the manifest.
+    blocks: Vec<BlockReport>,
+    /// Shards listed without a manifest.
+    shards: Vec<ShardReport>,
+}
+
+impl ObjectReport {
+    fn from_inventory(object: &ObjectInventory) -> ObjectReport {
+        return ObjectReport {
+            object_id: to_hex(&object.object_id),
+            can_serve: None,
+            bytes: object.get_bytes(),
+            blocks: Vec::new(),
+            shards: object.shards.iter().map(ShardReport::new).collect(),
+        };
+    }
+
+    fn print(&self) {
+        let bad: Vec<&BlockReport> = self.blocks.iter().filter(|x| x.status != "ok").collect();
+        match self.can_serve {
+            None => println!("object {}: {} shards, {} bytes, no manifest", self.object_id, self.shards.len(), self.bytes),
+            Some(true) => println!("object {}: can serve, {} blocks, {} bytes", self.object_id, self.blocks.len(), self.bytes),
+            Some(false) => println!("object {}: can not serve, {} of {} blocks bad", self.object_id, bad.len(), self.blocks.len()),
+        }
+        for shard in self.shards.iter() {
+            shard.print();
+        }
+        for block in bad.iter() {
+            let detail = block.detail.as_ref().map(|x| format!(", {}", x)).unwrap_or_default();
+            let expected = if block.expected { "" } else { " (not placed on this node)" };
+            println!("  block {:>6} {}{}{}", block.block_id, block.status, detail, expected);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuditReport {
+    objects: Vec<ObjectReport>,
+}
+
+pub fn run(args: AuditArgs) -> Result<(), String> {
+    if !args.shard_dir.is_dir() {
+        return Err(format!("{} is not a directory", args.shard_dir.display()));
+    }
+    let store = match DirShardStore::new(&args.shard_dir) {
+        Ok(store) => Arc::new(store),
+        Err(error) => return Err(format!("failed to open {}: {}", args.shard_dir.display(), error)),
+    };
+    let manifests = args.manifests.iter().map(|x| read_manifest(x)).collect::<Result<Vec<Manifest>, String>>()?;
+
+    let mut report = AuditReport { objects: Vec::new() };
+    if manifests.is_empty() {
+        let inventory = take_inventory(&*store).map_err(|error| format!("failed to list {}: {}", args.shard_dir.display(), error))?;
+        report.objects = inventory.iter().map(ObjectReport::from_inventory).collect();
+    } else {
+        let audit = Auditor::new(store).run(&manifests).map_err(|error| format!("failed to list {}: {}", args.shard_dir.display(), error))?;
+        for object in audit.objects.iter() {
+            report.objects.push(ObjectReport {
+                object_id: to_hex(&object.object_id),
+                can_serve: Some(object.can_serve()),
+                bytes: object.blocks.iter().filter_map(|x| x.shard.as_ref()).map(|x| x.bytes).sum(),
+                blocks: object.blocks.iter().map(BlockReport::new).collect(),
+                shards: Vec::new(),
+            });
+        }
+        report.objects.extend(audit.unknown.iter().map(ObjectReport::from_inventory));
+    }
+
+    if args.json {
+        print_json(&report, false)?;
+    } else {
+        for object in report.objects.iter() {
+            object.print();
+        }
+    }
+
+    let failed = report.objects.iter().filter(|x| x.can_serve == Some(false)).count();
+    if failed > 0 {
+        return Err(format!("{} of {} objects can not be served", failed, manifests.len()));
+    }
+    return Ok(());
+}
@@ -0,0 +1,409 @@
+use std::io::IoSlice;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+
+use raptor_cdn::codec::decoder::RaptorQDecoder;
+use raptor_cdn::codec::encoder::{EncodedBlock, EncoderConfig, RaptorQEncoder, TailStrategy};
+use raptor_cdn::codec::native::{NativeDecoder, NativeEncoder};
+use raptor_cdn::codec::numa::{EncodePlacement, NumaPolicy};
+use raptor_cdn::codec::producer::SymbolProducer;
+use raptor_cdn::codec::small::{SmallDecoder, SmallEncoder, SMALL_OBJECT_LIMIT};
+use raptor_cdn::sim::channel::LossyChannel;
+use super::print_json;
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Object sizes, in bytes, with an optional K, M or G suffix.
+    #[arg(long, value_delimiter = ',', value_parser = parse_size, default_value = "1M,16M")]
+    sizes: Vec<usize>,
+    /// Packet sizes, in bytes.
+    #[arg(long, value_delimiter = ',', default_value = "1280")]
+    packet_sizes: Vec<u16>,
+    /// Packet loss to simulate, in percent.
+    #[arg(long, value_delimiter = ',', default_value = "0,5,20")]
+    loss: Vec<u32>,
+    /// Also bench raptorq's own multi-source-block encoder and decoder, for comparison.
+    #[arg(long)]
+    native: bool,
+    /// Also bench encoding the blocks of each object at once with this placement against encoding them one by one:
+    /// off, interleave or nodes:<ids>. May be given more than once, e.g. to compare off with interleave.
+    #[arg(long)]
+    placement: Vec<NumaPolicy>,
+    /// Encode threads per NUMA node for --placement, or in all with off. 0 for one per CPU.
+    #[arg(long, default_value_t = 0)]
+    threads_per_node: usize,
+    /// Print the results as JSON once the bench is done, instead of tables as it goes.
+    #[arg(long)]
+    json: bool,
+}
+
+/// Parses a size like 512, 64K, 100M or 1G. Suffixes are powers of 1024.
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((index, 'K')) | Some((index, 'k')) => (&value[..index], 1 << 10),
+        Some((index, 'M')) | Some((index, 'm')) => (&value[..index], 1 << 20),
+        Some((index, 'G')) | Some((index, 'g')) => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+
+    match digits.parse::<usize>().ok().and_then(|x| x.checked_mul(multiplier)) {
+        Some(size) if size > 0 => return Ok(size),
+        _ => return Err(format!("{} is not a valid size", value)),
+    }
+}
+
+/// Results of encoding and decoding one object.
+struct BenchResult {
+    encode_time: Duration,
+    decode_time: Duration,
+    /// Time from the first symbol sent to the object being decoded.
+    latency: Duration,
+    symbols_received: u64,
+    symbol_count: usize,
+}
+
+fn bench_once(data: &[u8], packet_size: u16, loss: u32) -> Result<BenchResult, String> {
+    let start = Instant::now();
+    let encoder = match RaptorQEncoder::new(packet_size, data) {
+        Ok(encoder) => encoder,
+        Err(error) => return Err(format!("failed to create encoder: {:?}", error)),
+    };
+    let encode_time = start.elapsed();
+
+    let symbol_count: usize = encoder.get_block_encoders().iter().map(|x| x.get_symbol_count()).sum();
+    let mut decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+        Ok(decoder) => decoder,
+        Err(error) => return Err(format!("failed to create decoder: {:?}", error)),
+    };
+    let mut producer = SymbolProducer::new(encoder);
+    let session_id = producer.open_session();
+    let mut channel = LossyChannel::new(loss as f64 / 100.0);
+
+    let start = Instant::now();
+    let mut decode_time = Duration::ZERO;
+    loop {
+        let blocks = match producer.next_symbols(session_id, 16) {
+            Ok(blocks) => blocks,
+            Err(error) => return Err(format!("failed to produce symbols: {:?}", error)),
+        };
+        let blocks = channel.transmit(blocks);
+
+        let decode_start = Instant::now();
+        let decoded = match decoder.consume(blocks) {
+            Ok(decoded) => decoded,
+            Err(error) => return Err(format!("failed to decode: {:?}", error)),
+        };
+        decode_time += decode_start.elapsed();
+
+        if decoded {
+            break;
+        }
+    }
+
+    return Ok(BenchResult {
+        encode_time,
+        decode_time,
+        latency: start.elapsed(),
+        symbols_received: channel.get_delivered(),
+        symbol_count,
+    });
+}
+
+/// Bench as bench_once does, with NativeEncoder and NativeDecoder in place of the block encoder and decoder.
+fn bench_native_once(data: &[u8], packet_size: u16, loss: u32) -> Result<BenchResult, String> {
+    let start = Instant::now();
+    let encoder = match NativeEncoder::new(packet_size, data) {
+        Ok(encoder) => encoder,
+        Err(error) => return Err(format!("failed to create encoder: {:?}", error)),
+    };
+    let encode_time = start.elapsed();
+
+    let mut decoder = NativeDecoder::new(encoder.get_config());
+    let mut channel = LossyChannel::new(loss as f64 / 100.0);
+    let source_packets = encoder.get_source_packets();
+    let symbol_count = source_packets.len();
+
+    // source symbols first, then repair symbols of every source block in rounds, as the block path sends them
+    let start = Instant::now();
+    let mut decode_time = Duration::ZERO;
+    let mut packets = source_packets;
+    let mut repair_start: u32 = 0;
+    loop {
+        // the channel takes EncodedBlocks, the block id is unused
+        let blocks = packets.into_iter().map(|data| EncodedBlock { block_id: 0, data }).collect();
+        let packets_received = channel.transmit(blocks).into_iter().map(|x| x.data).collect();
+
+        let decode_start = Instant::now();
+        let decoded = decoder.consume(packets_received);
+        decode_time += decode_start.elapsed();
+
+        if decoded {
+            break;
+        }
+        packets = encoder.get_repair_packets(repair_start, 16);
+        repair_start += 16;
+    }
+
+    return Ok(BenchResult {
+        encode_time,
+        decode_time,
+        latency: start.elapsed(),
+        symbols_received: channel.get_delivered(),
+        symbol_count,
+    });
+}
+
+/// Bench as bench_once does, with SmallEncoder and SmallDecoder in place of the block encoder and decoder, for
+/// objects of up to SMALL_OBJECT_LIMIT bytes.
+fn bench_small_once(data: &[u8], packet_size: u16, loss: u32) -> Result<BenchResult, String> {
+    let start = Instant::now();
+    let encoder = match SmallEncoder::new(EncoderConfig::new(packet_size), data) {
+        Ok(encoder) => encoder,
+        Err(error) => return Err(format!("failed to create encoder: {:?}", error)),
+    };
+    let encode_time = start.elapsed();
+
+    let mut decoder = match SmallDecoder::new(encoder.get_block_info().clone()) {
+        Ok(decoder) => decoder,
+        Err(error) => return Err(format!("failed to create decoder: {:?}", error)),
+    };
+    let mut channel = LossyChannel::new(loss as f64 / 100.0);
+
+    // source symbols first, then repair symbols in rounds, as the block path sends them
+    let start = Instant::now();
+    let mut decode_time = Duration::ZERO;
+    let mut blocks = encoder.generate_source_blocks();
+    let mut repair_start: u32 = 0;
+    loop {
+        let blocks_received = channel.transmit(blocks);
+
+        let decode_start = Instant::now();
+        let decoded = match decoder.consume(blocks_received) {
+            Ok(decoded) => decoded,
+            Err(error) => return Err(format!("failed to decode: {:?}", error)),
+        };
+        decode_time += decode_start.elapsed();
+
+        if decoded {
+            break;
+        }
+        blocks = encoder.generate_repair_blocks(repair_start, 16);
+        repair_start += 16;
+    }
+
+    return Ok(BenchResult {
+        encode_time,
+        decode_time,
+        latency: start.elapsed(),
+        symbols_received: channel.get_delivered(),
+        symbol_count: encoder.get_symbol_count(),
+    });
+}
+
+/// Padding each tail strategy adds to an object, as a percentage of its size.
+#[derive(Serialize)]
+struct PaddingRow {
+    size: usize,
+    packet_size: u16,
+    pad_percent: f64,
+    shrink_percent: f64,
+}
+
+fn padding_rows(sizes: &[usize], packet_sizes: &[u16]) -> Vec<PaddingRow> {
+    let mut rows: Vec<PaddingRow> = Vec::new();
+    for size in sizes.iter() {
+        for packet_size in packet_sizes.iter() {
+            let mut config = EncoderConfig::new(*packet_size);
+            let padded = config.get_padded_size(*size as u64);
+            config.tail_strategy = TailStrategy::ShrinkSymbols;
+            let shrunk = config.get_padded_size(*size as u64);
+            rows.push(PaddingRow {
+                size: *size,
+                packet_size: *packet_size,
+                pad_percent: (padded - *size as u64) as f64 * 100.0 / *size as f64,
+                shrink_percent: (shrunk - *size as u64) as f64 * 100.0 / *size as f64,
+            });
+        }
+    }
+    return rows;
+}
+
+fn print_padding(rows: &[PaddingRow]) {
+    println!("{:>12} {:>8} {:>12} {:>12}", "size", "packet", "pad%", "shrink%");
+    for row in rows.iter() {
+        println!("{:>12} {:>8} {:>12.3} {:>12.3}", row.size, row.packet_size, row.pad_percent, row.shrink_percent);
+    }
+}
+
+/// Encoding an object with a placement, against encoding it on one thread.
+#[derive(Serialize)]
+struct PlacementRow {
+    size: usize,
+    packet_size: u16,
+    placement: String,
+    threads: usize,
+    sequential_mbps: f64,
+    encode_mbps: f64,
+    speedup: f64,
+}
+
+fn placement_row(data: &[u8], packet_size: u16, placement: &EncodePlacement) -> Result<PlacementRow, String> {
+    let config = EncoderConfig::new(packet_size);
+    let start = Instant::now();
+    let encoder = match RaptorQEncoder::with_config(config, &[IoSlice::new(data)]) {
+        Ok(encoder) => encoder,
+        Err(error) => return Err(format!("failed to create encoder: {:?}", error)),
+    };
+    let sequential_time = start.elapsed();
+    // not to hold two copies at once
+    drop(encoder);
+
+    let start = Instant::now();
+    let encoder = match RaptorQEncoder::with_placement(config, &[IoSlice::new(data)], placement, None) {
+        Ok(encoder) => encoder,
+        Err(error) => return Err(format!("failed to create encoder: {:?}", error)),
+    };
+    let encode_time = start.elapsed();
+
+    return Ok(PlacementRow {
+        size: data.len(),
+        packet_size,
+        placement: placement.get_policy().to_string(),
+        threads: placement.assign(encoder.get_block_encoders().len()).iter().map(|x| x.threads).sum(),
+        sequential_mbps: throughput_mbps(data.len(), sequential_time),
+        encode_mbps: throughput_mbps(data.len(), encode_time),
+        speedup: sequential_time.as_secs_f64() / encode_time.as_secs_f64(),
+    });
+}
+
+fn print_placement(rows: &[PlacementRow]) {
+    println!("{:>12} {:>8} {:>16} {:>8} {:>12} {:>12} {:>8}", "size", "packet", "placement", "threads", "seq_mbps", "enc_mbps", "speedup");
+    for row in rows.iter() {
+        println!(
+            "{:>12} {:>8} {:>16} {:>8} {:>12.1} {:>12.1} {:>8.2}",
+            row.size, row.packet_size, row.placement, row.threads, row.sequential_mbps, row.encode_mbps, row.speedup,
+        );
+    }
+}
+
+fn throughput_mbps(size: usize, time: Duration) -> f64 {
+    return size as f64 * 8.0 / 1e6 / time.as_secs_f64();
+}
+
+/// One line of the bench's results.
+#[derive(Serialize)]
+struct BenchRow {
+    mode: &'static str,
+    size: usize,
+    packet_size: u16,
+    loss_percent: u32,
+    encode_mbps: f64,
+    decode_mbps: f64,
+    latency_ms: f64,
+    symbols_received: u64,
+    symbol_count: usize,
+    overhead_percent: f64,
+}
+
+impl BenchRow {
+    fn new(mode: &'static str, size: usize, packet_size: u16, loss: u32, result: &BenchResult) -> BenchRow {
+        return BenchRow {
+            mode,
+            size,
+            packet_size,
+            loss_percent: loss,
+            encode_mbps: throughput_mbps(size, result.encode_time),
+            decode_mbps: throughput_mbps(size, result.decode_time),
+            latency_ms: result.latency.as_secs_f64() * 1000.0,
+            symbols_received: result.symbols_received,
+            symbol_count: result.symbol_count,
+            overhead_percent: (result.symbols_received as f64 / result.symbol_count as f64 - 1.0) * 100.0,
+        };
+    }
+
+    fn print(&self) {
+        println!(
+            "{:>8} {:>12} {:>8} {:>6} {:>12.1} {:>12.1} {:>12.1} {:>10.2}",
+            self.mode,
+            self.size,
+            self.packet_size,
+            self.loss_percent,
+            self.encode_mbps,
+            self.decode_mbps,
+            self.latency_ms,
+            self.overhead_percent,
+        );
+    }
+}
+
+#[derive(Serialize)]
+struct BenchReport {
+    results: Vec<BenchRow>,
+    padding: Vec<PaddingRow>,
+    placement: Vec<PlacementRow>,
+}
+
+pub fn run(args: BenchArgs) -> Result<(), String> {
+    if let Some(loss) = args.loss.iter().find(|x| **x >= 100) {
+        return Err(format!("loss {}% leaves nothing to decode", loss));
+    }
+    let mut placements: Vec<EncodePlacement> = Vec::new();
+    for policy in args.placement.iter() {
+        match EncodePlacement::new(policy.clone(), args.threads_per_node) {
+            Ok(placement) => placements.push(placement),
+            Err(error) => return Err(format!("failed to place encoding: {}", error)),
+        }
+    }
+
+    if !args.json {
+        println!(
+            "{:>8} {:>12} {:>8} {:>6} {:>12} {:>12} {:>12} {:>10}",
+            "mode", "size", "packet", "loss%", "enc_mbps", "dec_mbps", "latency_ms", "overhead%",
+        );
+    }
+    let mut rows: Vec<BenchRow> = Vec::new();
+    let mut placement_rows: Vec<PlacementRow> = Vec::new();
+    for size in args.sizes.iter() {
+        let mut rng = thread_rng();
+        let data: Vec<u8> = (0..*size).map(|_| rng.gen()).collect();
+
+        for packet_size in args.packet_sizes.iter() {
+            for loss in args.loss.iter() {
+                let mut results = vec![("block", bench_once(&data, *packet_size, *loss)?)];
+                if args.native {
+                    results.push(("native", bench_native_once(&data, *packet_size, *loss)?));
+                }
+                // small objects also get the fast path, to see what it saves over the block path
+                if *size <= SMALL_OBJECT_LIMIT {
+                    results.push(("small", bench_small_once(&data, *packet_size, *loss)?));
+                }
+                for (mode, result) in results.iter() {
+                    let row = BenchRow::new(mode, *size, *packet_size, *loss, result);
+                    // rows are printed as they come, a large matrix takes a while
+                    if !args.json {
+                        row.print();
+                    }
+                    rows.push(row);
+                }
+            }
+            for placement in placements.iter() {
+                placement_rows.push(placement_row(&data, *packet_size, placement)?);
+            }
+        }
+    }
+
+    let padding = padding_rows(&args.sizes, &args.packet_sizes);
+    if args.json {
+        return print_json(&BenchReport { results: rows, padding, placement: placement_rows }, false);
+    }
+    println!();
+    print_padding(&padding);
+    if !placement_rows.is_empty() {
+        println!();
+        print_placement(&placement_rows);
+    }
+    return Ok(());
+}
@@ -0,0 +1,111 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand};
+
+use raptor_cdn::codec::bundle::{BundleExtractor, Bundler};
+
+#[derive(Args)]
+pub struct BundleArgs {
+    #[command(subcommand)]
+    command: BundleCommand,
+}
+
+#[derive(Subcommand)]
+enum BundleCommand {
+    /// Pack small files into one bundle, named by their file names.
+    Pack {
+        /// Where to write the bundle.
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Files to pack.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Extract the files of a bundle into a directory, e.g. of one decoded from a server's bundled directory.
+    Unpack {
+        /// Bundle to extract.
+        input: PathBuf,
+        /// Directory to extract into.
+        #[arg(short = 'C', long, default_value = ".")]
+        directory: PathBuf,
+        /// Names of the files to extract, all of them if left out.
+        #[arg(long, value_delimiter = ',')]
+        entries: Vec<String>,
+    },
+}
+
+fn pack(output: &Path, files: &[PathBuf]) -> Result<(), String> {
+    let mut contents: Vec<(String, Vec<u8>)> = Vec::with_capacity(files.len());
+    for file in files.iter() {
+        let name = match file.file_name().and_then(|x| x.to_str()) {
+            None => return Err(format!("{} has no usable file name", file.display())),
+            Some(name) => name.to_string(),
+        };
+        match fs::read(file) {
+            Ok(data) => contents.push((name, data)),
+            Err(error) => return Err(format!("failed to read {}: {}", file.display(), error)),
+        }
+    }
+
+    let mut bundler = Bundler::new();
+    for (name, data) in contents.iter() {
+        if let Err(error) = bundler.add(name, data) {
+            return Err(format!("failed to add {}: {:?}", name, error));
+        }
+    }
+
+    let writer = match File::create(output) {
+        Ok(file) => BufWriter::new(file),
+        Err(error) => return Err(format!("failed to create {}: {}", output.display(), error)),
+    };
+    if let Err(error) = bundler.write_to(writer) {
+        return Err(format!("failed to write {}: {}", output.display(), error));
+    }
+
+    println!("packed {} files into {}", contents.len(), output.display());
+    return Ok(());
+}
+
+fn unpack(input: &Path, directory: &Path, entries: &[String]) -> Result<(), String> {
+    let data = match fs::read(input) {
+        Ok(data) => data,
+        Err(error) => return Err(format!("failed to read {}: {}", input.display(), error)),
+    };
+    let extractor = match BundleExtractor::new(&data) {
+        Ok(extractor) => extractor,
+        Err(error) => return Err(format!("failed to parse {}: {:?}", input.display(), error)),
+    };
+
+    if let Some(name) = entries.iter().find(|x| extractor.get(x).is_none()) {
+        return Err(format!("{} has no file named {}", input.display(), name));
+    }
+
+    let mut extracted: usize = 0;
+    for (name, contents) in extractor.entries().iter() {
+        if !entries.is_empty() && !entries.iter().any(|x| x == name) {
+            continue;
+        }
+        // bundles may come from untrusted peers, never write outside the target directory
+        if name.is_empty() || name.contains(['/', '\\']) || *name == "." || *name == ".." {
+            return Err(format!("refusing to extract entry named {:?}", name));
+        }
+
+        let path = directory.join(name);
+        if let Err(error) = fs::write(&path, contents) {
+            return Err(format!("failed to write {}: {}", path.display(), error));
+        }
+        extracted += 1;
+    }
+
+    println!("unpacked {} files into {}", extracted, directory.display());
+    return Ok(());
+}
+
+pub fn run(args: BundleArgs) -> Result<(), String> {
+    match args.command {
+        BundleCommand::Pack { output, files } => return pack(&output, &files),
+        BundleCommand::Unpack { input, directory, entries } => return unpack(&input, &directory, &entries),
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use clap::Args;
+use serde::Serialize;
+
+use raptor_cdn::client::conformance::{check_dir, check_endpoint, ConformanceCase, Verdict};
+use super::print_json;
+
+#[derive(Args)]
+pub struct ConformanceArgs {
+    /// Implementation to check: a directory of its results for the golden vectors, see client::conformance, or a
+    /// server as host:port or http://host:port/prefix.
+    #[arg(long)]
+    against: String,
+    /// Most objects of a server to check, in the order it lists them.
+    #[arg(long, default_value_t = 16)]
+    max_objects: usize,
+    /// Print the results as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct CaseReport {
+    name: String,
+    manifest: String,
+    symbols: String,
+    theirs_to_ours: String,
+    ours_to_theirs: String,
+}
+
+fn format_verdict(verdict: &Verdict) -> String {
+    return match verdict {
+        Verdict::Pass => "pass".to_string(),
+        Verdict::Fail(reason) => format!("fail: {}", reason),
+        Verdict::Skipped => "skipped".to_string(),
+    };
+}
+
+fn short_verdict(verdict: &Verdict) -> &'static str {
+    return match verdict {
+        Verdict::Pass => "pass",
+        Verdict::Fail(_) => "FAIL",
+        Verdict::Skipped => "-",
+    };
+}
+
+pub fn run(args: ConformanceArgs) -> Result<(), String> {
+    let cases: Vec<ConformanceCase> = if Path::new(&args.against).is_dir() {
+        check_dir(Path::new(&args.against)).map_err(|error| format!("failed to check {}: {}", args.against, error))?
+    } else {
+        let endpoint = args.against.strip_prefix("http://").unwrap_or(&args.against);
+        let (addr, prefix) = match endpoint.find('/') {
+            Some(index) => endpoint.split_at(index),
+            None => (endpoint, ""),
+        };
+        check_endpoint(addr, prefix.trim_end_matches('/'), args.max_objects).map_err(|error| format!("failed to check {}: {}", args.against, error))?
+    };
+
+    let failed = cases.iter().filter(|x| !x.is_compliant()).count();
+    if args.json {
+        let reports: Vec<CaseReport> = cases.iter().map(|case| CaseReport {
+            name: case.name.clone(),
+            manifest: format_verdict(&case.manifest),
+            symbols: format_verdict(&case.symbols),
+            theirs_to_ours: format_verdict(&case.theirs_to_ours),
+            ours_to_theirs: format_verdict(&case.ours_to_theirs),
+        }).collect();
+        print_json(&reports, false)?;
+    } else {
+        let width = cases.iter().map(|x| x.name.len()).max().unwrap_or(0).max("case".len());
+        println!("{:width$}  {:8}  {:8}  {:14}  ours->theirs", "case", "manifest", "symbols", "theirs->ours", width = width);
+        for case in cases.iter() {
+            println!(
+                "{:width$}  {:8}  {:8}  {:14}  {}",
+                case.name,
+                short_verdict(&case.manifest),
+                short_verdict(&case.symbols),
+                short_verdict(&case.theirs_to_ours),
+                short_verdict(&case.ours_to_theirs),
+                width = width,
+            );
+        }
+        for case in cases.iter() {
+            let checks = [("manifest", &case.manifest), ("symbols", &case.symbols), ("theirs->ours", &case.theirs_to_ours), ("ours->theirs", &case.ours_to_theirs)];
+            for (check, verdict) in checks.iter() {
+                if let Verdict::Fail(reason) = verdict {
+                    println!("{} {}: {}", case.name, check, reason);
+                }
+            }
+        }
+    }
+
+    if cases.is_empty() {
+        return Err(format!("{} gave nothing to check", args.against));
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} cases are not compliant", failed, cases.len()));
+    }
+    return Ok(());
+}
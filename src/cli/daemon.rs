@@ -0,0 +1,671 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, IoSlice, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use raptor_cdn::client::fetch::{Fetch, PexSettings};
+use raptor_cdn::client::preflight::preflight;
+use raptor_cdn::client::reconcile::{fetch_reconciled_manifest, ReconcilePolicy, Reconciled};
+use raptor_cdn::client::schedule::{BandwidthProportional, LatencyWeighted, RarestFirst, RoundRobin, SchedulePolicy};
+use raptor_cdn::client::telemetry::{parse_traceparent, OtlpExporter, Telemetry};
+use raptor_cdn::client::transfers::{TransferId, TransferLimits, TransferQueue, TransferState};
+use raptor_cdn::codec::consts::RAPTORQ_MAX_SYMBOLS_IN_BLOCK;
+use raptor_cdn::codec::encoder::{EncoderConfig, RaptorQEncoder, TailStrategy};
+use raptor_cdn::codec::farm::{encode_job, EncodeJob, EncodeJobResult};
+use raptor_cdn::codec::manifest::{parse_object_id, to_hex, DecoderLimits, Manifest};
+use raptor_cdn::codec::plan_cache::PlanCache;
+use raptor_cdn::codec::shard::write_shard;
+use raptor_cdn::server::manifest_key::ManifestKey;
+use raptor_cdn::server::pex::PexKey;
+use super::bench::parse_size;
+use super::plan_cache::load_block_limit;
+
+/// JSON-RPC error codes, see https://www.jsonrpc.org/specification#error_object.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The request was understood but failed, e.g. a file could not be read.
+const REQUEST_FAILED: i64 = -32000;
+
+/// Longest request line taken, newline included, so a client can't make the daemon buffer without bound.
+const MAX_REQUEST_SIZE: u64 = 1 << 20;
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    /// UNIX socket to take requests on. A stale socket left by a daemon that died is replaced.
+    #[arg(long, default_value = "raptor-cdn.sock")]
+    socket: PathBuf,
+    /// Directory encoding plans are loaded from at startup, to start with a warm plan cache. Objects are encoded in
+    /// blocks of the limit saved there by plan-cache experiment, if any.
+    #[arg(long)]
+    plan_cache_dir: Option<PathBuf>,
+    /// OpenTelemetry collector to send a trace of each fetch and the fetch metrics to over OTLP/HTTP, e.g.
+    /// localhost:4318.
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+    /// How often to send the metrics to the collector, in seconds.
+    #[arg(long, default_value_t = 60)]
+    otlp_interval_secs: u64,
+    /// File holding the key peer lists must be signed with for fetches exchanging peers, see server::pex. Without
+    /// it unsigned lists are taken.
+    #[arg(long)]
+    pex_key_file: Option<PathBuf>,
+    /// File holding the key manifests of origins are signed with, for fetches preferring signed manifests, see
+    /// server::manifest_key.
+    #[arg(long)]
+    manifest_key_file: Option<PathBuf>,
+    /// Most fetches running at once; more are queued by priority, see the transfers method.
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    max_transfers: u32,
+    /// Most symbol bytes per second over all fetches, with an optional K, M or G suffix.
+    #[arg(long, value_parser = parse_size)]
+    max_download_rate: Option<usize>,
+}
+
+/// State shared by the connections of a daemon, kept warm between requests.
+struct Daemon {
+    started: Instant,
+    plan_cache: PlanCache,
+    /// EncoderConfig::max_block_symbols of encode requests.
+    max_block_symbols: u16,
+    requests: AtomicU64,
+    failed_requests: AtomicU64,
+    encoded: AtomicU64,
+    fetched: Arc<AtomicU64>,
+    /// Encode jobs run for a farm coordinator, see the farm command.
+    jobs: AtomicU64,
+    /// Requests being handled right now.
+    active: AtomicU64,
+    telemetry: Option<Arc<Telemetry>>,
+    pex_key: Option<PexKey>,
+    manifest_key: Option<ManifestKey>,
+    /// Fetches, queued under the daemon's limits.
+    transfers: Arc<TransferQueue>,
+}
+
+#[derive(Deserialize)]
+struct EncodeParams {
+    /// File to encode.
+    path: PathBuf,
+    shard: PathBuf,
+    manifest: PathBuf,
+    #[serde(default = "default_packet_size")]
+    packet_size: u16,
+    #[serde(default)]
+    shrink_tail: bool,
+}
+
+fn default_packet_size() -> u16 {
+    return 1280;
+}
+
+#[derive(Serialize)]
+struct EncodeResult {
+    object_id: String,
+    data_size: u64,
+    blocks: usize,
+    symbols: usize,
+    elapsed_secs: f64,
+}
+
+#[derive(Deserialize)]
+struct FetchParams {
+    object_id: String,
+    /// Peers to fetch from, e.g. "host:port". Every peer that has the object is asked for its manifest, and only
+    /// those giving the one manifest_policy chooses are fetched from.
+    peers: Vec<String>,
+    /// Where to write the object.
+    out: PathBuf,
+    #[serde(default)]
+    prefix: String,
+    token: Option<String>,
+    /// round-robin, rarest-first, latency or bandwidth, see client::schedule.
+    policy: Option<String>,
+    #[serde(default)]
+    priority_blocks: u32,
+    /// W3C traceparent of the caller, so the fetch's trace joins its own.
+    traceparent: Option<String>,
+    /// Ask the peers for more peers of the object as the fetch goes.
+    #[serde(default)]
+    peer_exchange: bool,
+    /// Address to announce to the peers when exchanging peers, e.g. of a server that will serve the object.
+    announce: Option<String>,
+    /// strict-equal, prefer-signed or newest-wins, see client::reconcile.
+    manifest_policy: Option<String>,
+    /// Higher is fetched first when more fetches are asked for than the daemon runs at once.
+    #[serde(default)]
+    priority: i32,
+    /// Answer once the fetch is queued, with its transfer_id, instead of once the object is written.
+    #[serde(default)]
+    detach: bool,
+}
+
+#[derive(Deserialize)]
+struct TransferParams {
+    transfer_id: TransferId,
+    /// New priority, for set_priority.
+    priority: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct LimitsParams {
+    /// Most fetches running at once, unchanged if not given.
+    max_concurrent: Option<usize>,
+    /// Most symbol bytes per second over all fetches, no limit if not given.
+    max_bytes_per_sec: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct TransferReport {
+    transfer_id: TransferId,
+    object_id: String,
+    data_size: u64,
+    priority: i32,
+    /// queued, running, paused, completed, failed or cancelled.
+    state: &'static str,
+    error: Option<String>,
+    block_count: usize,
+    blocks_decoded: usize,
+    bytes_received: u64,
+    /// Symbol bytes received from each peer, by address.
+    peers: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+struct FetchResult {
+    transfer_id: TransferId,
+    object_id: String,
+    data_size: u64,
+    symbols_received: u64,
+    bytes_received: u64,
+    duplicate_ratio: f64,
+    effective_overhead: f64,
+    wall_secs: f64,
+    first_byte_secs: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct StatusResult {
+    uptime_secs: f64,
+    requests: u64,
+    failed_requests: u64,
+    active_requests: u64,
+    objects_encoded: u64,
+    objects_fetched: u64,
+    jobs_encoded: u64,
+    /// Symbol counts the plan cache holds plans for.
+    cached_plans: usize,
+    /// Plan lookups served from the cache, and plans generated on a miss.
+    plan_hits: u64,
+    plan_generations: u64,
+}
+
+/// An error to answer a request with.
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+fn request_failed(message: String) -> RpcError {
+    return RpcError { code: REQUEST_FAILED, message };
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    return serde_json::from_value(params).map_err(|error| RpcError { code: INVALID_PARAMS, message: error.to_string() });
+}
+
+/// Waits for a fetch the daemon queued, writing the object to out once it completes.
+fn finish_fetch(transfers: &TransferQueue, fetched: &AtomicU64, transfer_id: TransferId, out: &Path) -> Result<FetchResult, RpcError> {
+    let status = transfers.wait(transfer_id).ok_or_else(|| request_failed(format!("transfer {} is gone", transfer_id)))?;
+    let data = transfers.take_result(transfer_id);
+    let data = match (status.state, data) {
+        (TransferState::Completed, Some(data)) => data,
+        (TransferState::Failed(error), _) => return Err(request_failed(format!("fetch failed: {}", error))),
+        _ => return Err(request_failed("fetch was cancelled".to_string())),
+    };
+    if let Err(error) = fs::write(out, &data) {
+        return Err(request_failed(format!("failed to write {}: {}", out.display(), error)));
+    }
+
+    fetched.fetch_add(1, Ordering::Relaxed);
+    let stats = status.stats;
+    return Ok(FetchResult {
+        transfer_id,
+        object_id: to_hex(&status.object_id),
+        data_size: data.len() as u64,
+        symbols_received: stats.symbols_received,
+        bytes_received: stats.bytes_received,
+        duplicate_ratio: stats.duplicate_ratio,
+        effective_overhead: stats.effective_overhead,
+        wall_secs: stats.wall_time.as_secs_f64(),
+        first_byte_secs: stats.first_byte_time.map(|x| x.as_secs_f64()),
+    });
+}
+
+fn parse_policy(name: &str) -> Result<Box<dyn SchedulePolicy>, RpcError> {
+    match name {
+        "round-robin" => return Ok(Box::new(RoundRobin::default())),
+        "rarest-first" => return Ok(Box::new(RarestFirst::default())),
+        "latency" => return Ok(Box::new(LatencyWeighted::default())),
+        "bandwidth" => return Ok(Box::new(BandwidthProportional::default())),
+        _ => return Err(RpcError { code: INVALID_PARAMS, message: format!("unknown policy {}", name) }),
+    }
+}
+
+impl Daemon {
+    fn encode(&self, params: EncodeParams) -> Result<EncodeResult, RpcError> {
+        let start = Instant::now();
+        let mut config = EncoderConfig::new(params.packet_size);
+        config.max_block_symbols = self.max_block_symbols;
+        if params.shrink_tail {
+            config.tail_strategy = TailStrategy::ShrinkSymbols;
+        }
+        let data = fs::read(&params.path).map_err(|error| request_failed(format!("failed to read {}: {}", params.path.display(), error)))?;
+        let encoder = match RaptorQEncoder::with_plan_cache(config, &[IoSlice::new(&data)], &self.plan_cache) {
+            Ok(encoder) => encoder,
+            Err(error) => return Err(RpcError { code: INVALID_PARAMS, message: format!("failed to create encoder: {:?}", error) }),
+        };
+
+        let manifest = Manifest::new(&encoder);
+        let blocks = encoder.generate_encoded_blocks();
+        let written = File::create(&params.shard).and_then(|file| write_shard(BufWriter::new(file), &blocks));
+        if let Err(error) = written {
+            return Err(request_failed(format!("failed to write {}: {}", params.shard.display(), error)));
+        }
+        let written = File::create(&params.manifest).and_then(|file| manifest.write_to(BufWriter::new(file)));
+        if let Err(error) = written {
+            return Err(request_failed(format!("failed to write {}: {}", params.manifest.display(), error)));
+        }
+
+        self.encoded.fetch_add(1, Ordering::Relaxed);
+        return Ok(EncodeResult {
+            object_id: to_hex(&manifest.object_id),
+            data_size: manifest.data_size,
+            blocks: manifest.get_block_count(),
+            symbols: blocks.len(),
+            elapsed_secs: start.elapsed().as_secs_f64(),
+        });
+    }
+
+    fn fetch(&self, params: FetchParams) -> Result<Value, RpcError> {
+        let object_id = match parse_object_id(&params.object_id) {
+            Some(object_id) => object_id,
+            None => return Err(RpcError { code: INVALID_PARAMS, message: format!("{} is not an object id", params.object_id) }),
+        };
+        let policy = parse_policy(params.policy.as_deref().unwrap_or("round-robin"))?;
+        let token = params.token.as_deref();
+        let manifest_policy: ReconcilePolicy = match params.manifest_policy.as_deref().unwrap_or("strict-equal").parse() {
+            Ok(manifest_policy) => manifest_policy,
+            Err(message) => return Err(RpcError { code: INVALID_PARAMS, message }),
+        };
+        let reconciled = fetch_reconciled_manifest(&params.peers, &params.prefix, &object_id, token, self.manifest_key.as_ref(), manifest_policy);
+        let Reconciled { manifest, sources, .. } = reconciled.map_err(|error| request_failed(error.to_string()))?;
+        let limits = DecoderLimits::default();
+        if let Err(error) = preflight(&manifest, &limits, None) {
+            return Err(request_failed(format!("object can't be fetched here: {:?}", error)));
+        }
+
+        let mut fetch = Fetch::new(manifest, sources, &limits).map_err(|error| request_failed(error.to_string()))?;
+        fetch.set_location(&params.prefix, token);
+        fetch.set_policy(policy);
+        fetch.set_priority_blocks(params.priority_blocks);
+        if let Some(telemetry) = self.telemetry.as_ref() {
+            fetch.set_telemetry(telemetry.clone());
+        }
+        if let Some((trace_id, span_id)) = params.traceparent.as_deref().and_then(parse_traceparent) {
+            fetch.set_trace_parent(trace_id, span_id);
+        }
+        if params.peer_exchange {
+            fetch.set_peer_exchange(PexSettings { key: self.pex_key.clone(), announce: params.announce, ..PexSettings::default() });
+        }
+        let transfer_id = self.transfers.submit(fetch, params.priority);
+        if params.detach {
+            let (transfers, fetched, out) = (self.transfers.clone(), self.fetched.clone(), params.out);
+            std::thread::spawn(move || {
+                if let Err(error) = finish_fetch(&transfers, &fetched, transfer_id, &out) {
+                    eprintln!("transfer {}: {}", transfer_id, error.message);
+                }
+            });
+            return Ok(json!({ "transfer_id": transfer_id }));
+        }
+        let result = finish_fetch(&self.transfers, &self.fetched, transfer_id, &params.out)?;
+        return serde_json::to_value(result).map_err(|error| request_failed(error.to_string()));
+    }
+
+    fn transfers(&self) -> Vec<TransferReport> {
+        return self.transfers.list().into_iter().map(|status| {
+            let (state, error) = match status.state {
+                TransferState::Queued => ("queued", None),
+                TransferState::Running => ("running", None),
+                TransferState::Paused => ("paused", None),
+                TransferState::Completed => ("completed", None),
+                TransferState::Failed(error) => ("failed", Some(error)),
+                TransferState::Cancelled => ("cancelled", None),
+            };
+            TransferReport {
+                transfer_id: status.id,
+                object_id: to_hex(&status.object_id),
+                data_size: status.data_size,
+                priority: status.priority,
+                state,
+                error,
+                block_count: status.block_count,
+                blocks_decoded: status.blocks_decoded,
+                bytes_received: status.stats.bytes_received,
+                peers: status.stats.peers.iter().map(|(addr, contribution)| (addr.clone(), contribution.bytes)).collect(),
+            }
+        }).collect();
+    }
+
+    /// Pauses, resumes, cancels or reprioritizes a transfer, as method says.
+    fn control_transfer(&self, method: &str, params: TransferParams) -> Result<Value, RpcError> {
+        let done = match method {
+            "pause" => self.transfers.pause(params.transfer_id),
+            "resume" => self.transfers.resume(params.transfer_id),
+            "cancel" => self.transfers.cancel(params.transfer_id),
+            _ => match params.priority {
+                Some(priority) => self.transfers.set_priority(params.transfer_id, priority),
+                None => return Err(RpcError { code: INVALID_PARAMS, message: "no priority given".to_string() }),
+            },
+        };
+        if !done {
+            return Err(request_failed(format!("transfer {} can't take {}, it's finished or unknown", params.transfer_id, method)));
+        }
+        return Ok(json!({ "transfer_id": params.transfer_id }));
+    }
+
+    fn set_limits(&self, params: LimitsParams) -> TransferLimits {
+        let mut limits = self.transfers.get_limits();
+        limits.max_concurrent = params.max_concurrent.unwrap_or(limits.max_concurrent);
+        limits.max_bytes_per_sec = params.max_bytes_per_sec;
+        self.transfers.set_limits(limits);
+        return limits;
+    }
+
+    fn encode_job(&self, job: EncodeJob) -> Result<EncodeJobResult, RpcError> {
+        let result = encode_job(&job, &self.plan_cache).map_err(|error| request_failed(format!("blocks {:?} failed: {}", job.block_ids, error)))?;
+        self.jobs.fetch_add(1, Ordering::Relaxed);
+        return Ok(result);
+    }
+
+    fn status(&self) -> StatusResult {
+        let plan_stats = self.plan_cache.get_stats();
+        return StatusResult {
+            uptime_secs: self.started.elapsed().as_secs_f64(),
+            requests: self.requests.load(Ordering::Relaxed),
+            failed_requests: self.failed_requests.load(Ordering::Relaxed),
+            active_requests: self.active.load(Ordering::Relaxed),
+            objects_encoded: self.encoded.load(Ordering::Relaxed),
+            objects_fetched: self.fetched.load(Ordering::Relaxed),
+            jobs_encoded: self.jobs.load(Ordering::Relaxed),
+            cached_plans: plan_stats.len(),
+            plan_hits: plan_stats.iter().map(|x| x.1.hits).sum(),
+            plan_generations: plan_stats.iter().map(|x| x.1.generations).sum(),
+        };
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let result = match method {
+            "encode" => serde_json::to_value(self.encode(parse_params(params)?)?),
+            "fetch" => Ok(self.fetch(parse_params(params)?)?),
+            "transfers" => serde_json::to_value(self.transfers()),
+            "pause" | "resume" | "cancel" | "set_priority" => Ok(self.control_transfer(method, parse_params(params)?)?),
+            "set_limits" => {
+                let limits = self.set_limits(parse_params(params)?);
+                Ok(json!({ "max_concurrent": limits.max_concurrent, "max_bytes_per_sec": limits.max_bytes_per_sec }))
+            },
+            "encode_job" => serde_json::to_value(self.encode_job(parse_params(params)?)?),
+            "status" => serde_json::to_value(self.status()),
+            _ => return Err(RpcError { code: METHOD_NOT_FOUND, message: format!("no method {}", method) }),
+        };
+        return result.map_err(|error| request_failed(error.to_string()));
+    }
+
+    /// Answers one JSON-RPC request. Notifications, requests without an id, are handled but not answered.
+    fn handle(&self, line: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(error) => return Some(json!({ "jsonrpc": "2.0", "id": null, "error": { "code": PARSE_ERROR, "message": error.to_string() } })),
+        };
+        let id = request.get("id").cloned();
+        let method = match request.get("method").and_then(|x| x.as_str()) {
+            Some(method) => method,
+            None => {
+                let error = json!({ "code": INVALID_REQUEST, "message": "not a request object with a method" });
+                return Some(json!({ "jsonrpc": "2.0", "id": id.unwrap_or(Value::Null), "error": error }));
+            },
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.active.fetch_add(1, Ordering::Relaxed);
+        let result = self.call(method, params);
+        self.active.fetch_sub(1, Ordering::Relaxed);
+
+        let id = id?;
+        match result {
+            Ok(result) => return Some(json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+            Err(error) => {
+                self.failed_requests.fetch_add(1, Ordering::Relaxed);
+                return Some(json!({ "jsonrpc": "2.0", "id": id, "error": { "code": error.code, "message": error.message } }));
+            },
+        }
+    }
+
+    /// Answers the requests of a connection, one JSON object per line each way, until it closes. A request longer
+    /// than MAX_REQUEST_SIZE is answered with an error and closes the connection, as where it ends is not known.
+    fn serve(&self, stream: UnixStream) {
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => return,
+        };
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            match (&mut reader).take(MAX_REQUEST_SIZE + 1).read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => (),
+            }
+            if line.len() as u64 > MAX_REQUEST_SIZE {
+                let error = json!({ "code": INVALID_REQUEST, "message": format!("request longer than {} bytes", MAX_REQUEST_SIZE) });
+                let _ = writeln!(writer, "{}", json!({ "jsonrpc": "2.0", "id": null, "error": error }));
+                return;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle(&line) {
+                if writeln!(writer, "{}", response).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Binds the socket, replacing a stale one no daemon listens on anymore.
+fn bind(path: &Path) -> Result<UnixListener, String> {
+    if path.exists() && UnixStream::connect(path).is_err() {
+        if let Err(error) = fs::remove_file(path) {
+            return Err(format!("failed to remove stale socket {}: {}", path.display(), error));
+        }
+    }
+    match UnixListener::bind(path) {
+        Ok(listener) => return Ok(listener),
+        Err(error) => return Err(format!("failed to listen on {}: {}", path.display(), error)),
+    }
+}
+
+pub fn run(args: DaemonArgs) -> Result<(), String> {
+    let plan_cache = match args.plan_cache_dir.as_ref() {
+        None => PlanCache::new(),
+        Some(dir) => {
+            let (plan_cache, error) = PlanCache::open_dir(dir);
+            if let Some(error) = error {
+                eprintln!("warning: plan cache {} is unusable, starting cold: {}", dir.display(), error);
+            }
+            plan_cache
+        },
+    };
+    let max_block_symbols = args.plan_cache_dir.as_deref().and_then(load_block_limit).unwrap_or(RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u16);
+    let pex_key = match args.pex_key_file.as_ref() {
+        Some(path) => Some(PexKey::new(&super::token::read_key_file(path, "peer exchange")?)),
+        None => None,
+    };
+    let manifest_key = match args.manifest_key_file.as_ref() {
+        Some(path) => Some(ManifestKey::new(&super::token::read_key_file(path, "manifest")?)),
+        None => None,
+    };
+    let daemon = Arc::new(Daemon {
+        started: Instant::now(),
+        plan_cache,
+        max_block_symbols,
+        requests: AtomicU64::new(0),
+        failed_requests: AtomicU64::new(0),
+        encoded: AtomicU64::new(0),
+        fetched: Arc::new(AtomicU64::new(0)),
+        jobs: AtomicU64::new(0),
+        active: AtomicU64::new(0),
+        telemetry: args.otlp_endpoint.as_ref().map(|x| Arc::new(Telemetry::new(Arc::new(OtlpExporter::new(x, "raptor-cdn"))))),
+        pex_key,
+        manifest_key,
+        transfers: Arc::new(TransferQueue::new(TransferLimits {
+            max_concurrent: args.max_transfers as usize,
+            max_bytes_per_sec: args.max_download_rate.map(|x| x as u64),
+        })),
+    });
+    if let Some(telemetry) = daemon.telemetry.clone() {
+        let interval = Duration::from_secs(args.otlp_interval_secs.max(1));
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            if let Err(error) = telemetry.export_metrics() {
+                eprintln!("failed to export metrics: {}", error);
+            }
+        });
+    }
+
+    let listener = bind(&args.socket)?;
+    println!("taking requests on {}", args.socket.display());
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let daemon = daemon.clone();
+                std::thread::spawn(move || daemon.serve(stream));
+            },
+            Err(error) => eprintln!("failed to accept a connection: {}", error),
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_daemon() -> Arc<Daemon> {
+        return Arc::new(Daemon {
+            started: Instant::now(),
+            plan_cache: PlanCache::new(),
+            max_block_symbols: RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u16,
+            requests: AtomicU64::new(0),
+            failed_requests: AtomicU64::new(0),
+            encoded: AtomicU64::new(0),
+            fetched: Arc::new(AtomicU64::new(0)),
+            jobs: AtomicU64::new(0),
+            active: AtomicU64::new(0),
+            telemetry: None,
+            pex_key: None,
+            manifest_key: None,
+            transfers: Arc::new(TransferQueue::new(TransferLimits { max_concurrent: 1, max_bytes_per_sec: None })),
+        });
+    }
+
+    /// Connects to a daemon serving the other end of a socket pair.
+    fn connect(daemon: &Arc<Daemon>) -> (UnixStream, BufReader<UnixStream>) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let daemon = daemon.clone();
+        std::thread::spawn(move || daemon.serve(server));
+        let reader = BufReader::new(client.try_clone().unwrap());
+        return (client, reader);
+    }
+
+    fn read_response(reader: &mut BufReader<UnixStream>) -> Value {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        return serde_json::from_str(&line).unwrap();
+    }
+
+    #[test]
+    fn test_daemon_rpc() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-daemon-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("object"), vec![7; 10 * 1000]).unwrap();
+        let daemon = new_daemon();
+        let (mut client, mut reader) = connect(&daemon);
+
+        let params = json!({ "path": root.join("object"), "shard": root.join("shard"), "manifest": root.join("manifest") });
+        writeln!(client, "{}", json!({ "jsonrpc": "2.0", "id": 1, "method": "encode", "params": params })).unwrap();
+        let response = read_response(&mut reader);
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["data_size"], 10 * 1000);
+        let manifest = Manifest::read_from(File::open(root.join("manifest")).unwrap()).unwrap();
+        assert_eq!(response["result"]["object_id"], to_hex(&manifest.object_id));
+
+        // errors are answered with the id of the request, and notifications not at all
+        let requests = [
+            (json!({ "jsonrpc": "2.0", "id": 2, "method": "nope" }), METHOD_NOT_FOUND),
+            (json!({ "jsonrpc": "2.0", "id": 3, "method": "encode", "params": { "path": 5 } }), INVALID_PARAMS),
+            (json!({ "jsonrpc": "2.0", "id": 4, "method": "encode", "params": { "path": root.join("missing"), "shard": "s", "manifest": "m" } }), REQUEST_FAILED),
+            (json!({ "jsonrpc": "2.0", "id": 5 }), INVALID_REQUEST),
+        ];
+        for (request, code) in requests.iter() {
+            writeln!(client, "{}", request).unwrap();
+            let response = read_response(&mut reader);
+            assert_eq!((&response["id"], &response["error"]["code"]), (&request["id"], &json!(code)));
+        }
+        writeln!(client, "{}", json!({ "jsonrpc": "2.0", "method": "nope" })).unwrap();
+        writeln!(client, "\n{{\"jsonrpc\": \"2.0\", \"id\": 6, \"method\": \"status\"").unwrap();
+        let response = read_response(&mut reader);
+        assert_eq!((&response["id"], &response["error"]["code"]), (&Value::Null, &json!(PARSE_ERROR)));
+        writeln!(client, "[1, 2]").unwrap();
+        assert_eq!(read_response(&mut reader)["error"]["code"], INVALID_REQUEST);
+
+        writeln!(client, "{}", json!({ "jsonrpc": "2.0", "id": "last", "method": "status" })).unwrap();
+        let response = read_response(&mut reader);
+        assert_eq!(response["id"], "last");
+        assert_eq!(response["result"]["objects_encoded"], 1);
+        assert_eq!(response["result"]["requests"], 6);
+        assert_eq!(response["result"]["failed_requests"], 3);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_daemon_oversize_request() {
+        let daemon = new_daemon();
+        let (mut client, mut reader) = connect(&daemon);
+
+        // the connection is answered and closed before the daemon holds more than MAX_REQUEST_SIZE of it
+        client.write_all(&vec![b' '; MAX_REQUEST_SIZE as usize + 1]).unwrap();
+        let response = read_response(&mut reader);
+        assert_eq!((&response["id"], &response["error"]["code"]), (&Value::Null, &json!(INVALID_REQUEST)));
+        let mut rest = String::new();
+        assert_eq!(reader.read_line(&mut rest).unwrap(), 0);
+
+        // a request of just the size is taken
+        let (mut client, mut reader) = connect(&daemon);
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "status" }).to_string();
+        let padding = " ".repeat(MAX_REQUEST_SIZE as usize - request.len() - 1);
+        writeln!(client, "{}{}", request, padding).unwrap();
+        assert_eq!(read_response(&mut reader)["id"], 1);
+    }
+}
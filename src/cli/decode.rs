@@ -0,0 +1,287 @@
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use clap::Args;
+use serde::Serialize;
+
+use raptor_cdn::codec::decoder::RaptorQDecoder;
+use raptor_cdn::codec::encoder::EncodedBlock;
+use raptor_cdn::codec::manifest::{to_hex, validate_manifest, DecoderLimits, Manifest};
+use raptor_cdn::codec::shard::ShardReader;
+use super::print_json;
+
+/// Symbols fed to the decoder at a time, so blocks come out while a shard is still being read.
+const BATCH_SIZE: usize = 256;
+
+#[derive(Args)]
+pub struct DecodeArgs {
+    /// Manifest of the object.
+    #[arg(long)]
+    manifest: PathBuf,
+    /// Shards to decode from, read in order until the object is decoded, or - to read one from stdin.
+    #[arg(required = true)]
+    shards: Vec<PathBuf>,
+    /// Where to write the object, or - for stdout. Blocks are written in order as soon as they are decoded, so a
+    /// pipeline can start on the object before the last shard is read.
+    #[arg(long)]
+    out: PathBuf,
+    /// Keep decoder scratch space in huge pages where the system has them, falling back to regular pages otherwise.
+    #[arg(long)]
+    huge_pages: bool,
+    /// Print the results as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct DecodeReport {
+    object_id: String,
+    data_size: u64,
+    blocks: usize,
+    /// Symbols read from shards, including those of blocks already decoded.
+    symbols_read: u64,
+    /// Blocks that did not match their hash and were decoded again.
+    rejected_blocks: u64,
+    /// Symbols decoding took beyond the source symbols, as a percentage of them.
+    overhead_percent: f64,
+    elapsed_secs: f64,
+}
+
+/// Writes decoded blocks in order, checking each against its hash in the manifest.
+struct OrderedOutput<'a> {
+    manifest: &'a Manifest,
+    writer: Box<dyn Write>,
+    /// First block not written yet.
+    next_block: u32,
+    symbols_read: u64,
+    rejected_blocks: u64,
+}
+
+impl<'a> OrderedOutput<'a> {
+    /// Writes the blocks from next_block on that are decoded. A block that does not match its hash is decoded again
+    /// from the symbols that follow.
+    fn write_decoded(&mut self, decoder: &mut RaptorQDecoder) -> Result<(), String> {
+        while let Some(data) = decoder.get_block_result(self.next_block) {
+            if Sha256::digest(data)[..] != self.manifest.block_hashes[self.next_block as usize][..] {
+                eprintln!("block {} does not match its hash, decoding it again", self.next_block);
+                if let Err(error) = decoder.reset_block(self.next_block) {
+                    return Err(format!("failed to decode block {} again: {:?}", self.next_block, error));
+                }
+                self.rejected_blocks += 1;
+                return Ok(());
+            }
+            if let Err(error) = self.writer.write_all(data).and_then(|_| self.writer.flush()) {
+                return Err(format!("failed to write block {}: {}", self.next_block, error));
+            }
+            self.next_block += 1;
+        }
+        return Ok(());
+    }
+
+    fn is_done(&self) -> bool {
+        return self.next_block as usize == self.manifest.get_block_count();
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest, String> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+    };
+    let manifest = match Manifest::read_from(&data[..]) {
+        Ok(manifest) => manifest,
+        Err(error) => return Err(format!("failed to parse {}: {}", path.display(), error)),
+    };
+    if let Err(issues) = validate_manifest(&manifest, &DecoderLimits::default()) {
+        return Err(format!("bad manifest {}: {:?}", path.display(), issues));
+    }
+    return Ok(manifest);
+}
+
+/// Feeds the symbols of a shard to the decoder a batch at a time, writing blocks out as they are decoded. Stops early
+/// once every block is written.
+fn decode_shard(path: &Path, decoder: &mut RaptorQDecoder, output: &mut OrderedOutput) -> Result<(), String> {
+    let reader: Box<dyn Read> = if path.as_os_str() == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(error) => return Err(format!("failed to open {}: {}", path.display(), error)),
+        }
+    };
+    let shard = match ShardReader::new(reader) {
+        Ok(shard) => shard,
+        Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+    };
+
+    let block_count = output.manifest.get_block_count();
+    let mut batch: Vec<EncodedBlock> = Vec::with_capacity(BATCH_SIZE);
+    let mut symbols = shard.peekable();
+    while let Some(block) = symbols.next() {
+        output.symbols_read += 1;
+        match block {
+            // symbols of other objects or manifests are not ours to decode
+            Ok(block) if (block.block_id as usize) < block_count => batch.push(block),
+            Ok(_) => (),
+            Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+        }
+        if batch.len() == BATCH_SIZE || symbols.peek().is_none() {
+            if let Err(error) = decoder.consume(std::mem::take(&mut batch)) {
+                return Err(format!("failed to decode {}: {:?}", path.display(), error));
+            }
+            output.write_decoded(decoder)?;
+            if output.is_done() {
+                return Ok(());
+            }
+        }
+    }
+    return Ok(());
+}
+
+pub fn run(args: DecodeArgs) -> Result<(), String> {
+    let manifest = read_manifest(&args.manifest)?;
+    let mut decoder = match RaptorQDecoder::from_manifest(&manifest) {
+        Ok(decoder) => decoder,
+        Err(error) => return Err(format!("failed to create decoder: {:?}", error)),
+    };
+    decoder.set_huge_pages(args.huge_pages);
+
+    let writer: Box<dyn Write> = if args.out.as_os_str() == "-" {
+        Box::new(BufWriter::new(io::stdout()))
+    } else {
+        match File::create(&args.out) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(error) => return Err(format!("failed to create {}: {}", args.out.display(), error)),
+        }
+    };
+    let start = Instant::now();
+    let mut output = OrderedOutput { manifest: &manifest, writer, next_block: 0, symbols_read: 0, rejected_blocks: 0 };
+
+    for shard in args.shards.iter() {
+        if output.is_done() {
+            break;
+        }
+        decode_shard(shard, &mut decoder, &mut output)?;
+    }
+    if !output.is_done() {
+        let needed = decoder.get_repair_plan().get_symbols_needed();
+        return Err(format!("shards hold too few symbols, {} blocks written, at least {} more symbols needed", output.next_block, needed));
+    }
+
+    if args.json {
+        let mut source_symbols: u64 = 0;
+        let mut used_symbols: u64 = 0;
+        for (block_info, stats) in manifest.block_info_vec.iter().zip(decoder.get_decode_stats()) {
+            source_symbols += (block_info.padded_size / block_info.config.symbol_size() as usize) as u64;
+            used_symbols += (stats.source_symbols + stats.repair_symbols) as u64;
+        }
+        let report = DecodeReport {
+            object_id: to_hex(&manifest.object_id),
+            data_size: manifest.data_size,
+            blocks: manifest.get_block_count(),
+            symbols_read: output.symbols_read,
+            rejected_blocks: output.rejected_blocks,
+            overhead_percent: match source_symbols {
+                0 => 0.0,
+                _ => (used_symbols as f64 / source_symbols as f64 - 1.0) * 100.0,
+            },
+            elapsed_secs: start.elapsed().as_secs_f64(),
+        };
+        return print_json(&report, args.out.as_os_str() == "-");
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use raptor_cdn::codec::encoder::{EncoderConfig, RaptorQEncoder};
+    use raptor_cdn::codec::shard::write_shard;
+    use raptorq::EncodingPacket;
+
+    fn gen_data(len: usize) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(len);
+        for _ in 0..len {
+            data.push(rand::thread_rng().gen());
+        }
+        return data;
+    }
+
+    /// Encodes data in blocks of 10 symbols of 1280 bytes.
+    fn encode(data: &[u8]) -> (RaptorQEncoder, Manifest) {
+        let config = EncoderConfig { max_block_symbols: 10, ..EncoderConfig::new(1280) };
+        let encoder = match RaptorQEncoder::with_config(config, &[io::IoSlice::new(data)]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let manifest = Manifest::new(&encoder);
+        return (encoder, manifest);
+    }
+
+    fn new_output<'a>(manifest: &'a Manifest, path: &Path) -> OrderedOutput<'a> {
+        let writer = Box::new(BufWriter::new(File::create(path).unwrap()));
+        return OrderedOutput { manifest, writer, next_block: 0, symbols_read: 0, rejected_blocks: 0 };
+    }
+
+    #[test]
+    fn test_decode_out_of_order() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-decode-order-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let data = gen_data(40 * 1000);
+        let (encoder, manifest) = encode(&data);
+        assert_eq!(manifest.get_block_count(), 4);
+        let mut decoder = RaptorQDecoder::from_manifest(&manifest).unwrap();
+        let mut output = new_output(&manifest, &root.join("out"));
+
+        // blocks decoded before the ones ahead of them wait for those
+        for block_id in [2, 1, 3, 0] {
+            let path = root.join(format!("shard-{}", block_id));
+            let blocks = encoder.get_block_encoders()[block_id].generate_repair_blocks(0, 12);
+            write_shard(File::create(&path).unwrap(), &blocks).unwrap();
+            decode_shard(&path, &mut decoder, &mut output).unwrap();
+            assert_eq!(output.next_block, if block_id == 0 { 4 } else { 0 });
+        }
+        assert!(output.is_done());
+        assert_eq!((output.symbols_read, output.rejected_blocks), (48, 0));
+        drop(output);
+        assert_eq!(fs::read(root.join("out")).unwrap(), data);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_decode_corrupted_symbol() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-decode-corrupt-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let data = gen_data(20 * 1000);
+        let (encoder, manifest) = encode(&data);
+        let block_encoders = encoder.get_block_encoders();
+        let mut decoder = RaptorQDecoder::from_manifest(&manifest).unwrap();
+        let mut output = new_output(&manifest, &root.join("out"));
+
+        // a corrupted source symbol decodes block 0 to the wrong payload, which is dropped
+        let mut blocks = block_encoders[0].generate_source_blocks();
+        let mut corrupted = blocks[3].data.data().to_vec();
+        corrupted[0] ^= 1;
+        blocks[3].data = EncodingPacket::new(blocks[3].data.payload_id().clone(), corrupted);
+        write_shard(File::create(root.join("corrupted")).unwrap(), &blocks).unwrap();
+        decode_shard(&root.join("corrupted"), &mut decoder, &mut output).unwrap();
+        assert_eq!((output.next_block, output.rejected_blocks), (0, 1));
+
+        // and decoded again from the symbols that follow
+        let mut blocks = block_encoders[0].generate_repair_blocks(0, 12);
+        blocks.extend(block_encoders[1].generate_source_blocks());
+        write_shard(File::create(root.join("repair")).unwrap(), &blocks).unwrap();
+        decode_shard(&root.join("repair"), &mut decoder, &mut output).unwrap();
+        assert!(output.is_done());
+        assert_eq!(output.rejected_blocks, 1);
+        drop(output);
+        assert_eq!(fs::read(root.join("out")).unwrap(), data);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use clap::Args;
+use serde::Serialize;
+
+use raptor_cdn::codec::encoder::{EncoderConfig, TailStrategy};
+use raptor_cdn::codec::incremental::IncrementalEncoder;
+use raptor_cdn::codec::manifest::to_hex;
+use raptor_cdn::codec::shard::{write_shard_records, SHARD_MAGIC};
+use super::plan_cache::load_block_limit;
+use super::print_json;
+
+#[derive(Args)]
+pub struct EncodeArgs {
+    /// File to encode, or - to read it from stdin, e.g. at the end of a pipeline. Only a block is held at a time.
+    input: PathBuf,
+    /// Where to write the shard, or - for stdout. Each block's symbols are written as soon as the block is read.
+    #[arg(long)]
+    shard: PathBuf,
+    /// Where to write the manifest, once the input ends.
+    #[arg(long)]
+    manifest: PathBuf,
+    #[arg(long, default_value_t = 1280)]
+    packet_size: u16,
+    /// Shrink the symbols of the tail block instead of padding it.
+    #[arg(long)]
+    shrink_tail: bool,
+    /// Keep block data in huge pages where the system has them, falling back to regular pages otherwise.
+    #[arg(long)]
+    huge_pages: bool,
+    /// Plan cache directory to take the block symbol limit saved by plan-cache experiment from, see the daemon
+    /// command. Without it, or a limit saved there, blocks hold as many symbols as they may.
+    #[arg(long)]
+    plan_cache_dir: Option<PathBuf>,
+    /// Print the results as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct EncodeReport {
+    object_id: String,
+    data_size: u64,
+    padded_size: u64,
+    blocks: usize,
+    symbols: usize,
+    /// Symbol bytes written to the shard, without record headers.
+    symbol_bytes: u64,
+    elapsed_secs: f64,
+}
+
+fn is_stdio(path: &Path) -> bool {
+    return path.as_os_str() == "-";
+}
+
+fn open_output(path: &Path) -> Result<Box<dyn Write>, String> {
+    if is_stdio(path) {
+        return Ok(Box::new(BufWriter::new(io::stdout())));
+    }
+    match File::create(path) {
+        Ok(file) => return Ok(Box::new(BufWriter::new(file))),
+        Err(error) => return Err(format!("failed to create {}: {}", path.display(), error)),
+    }
+}
+
+pub fn run(args: EncodeArgs) -> Result<(), String> {
+    let mut config = EncoderConfig::new(args.packet_size);
+    if args.shrink_tail {
+        config.tail_strategy = TailStrategy::ShrinkSymbols;
+    }
+    config.huge_pages = args.huge_pages;
+    if let Some(max_block_symbols) = args.plan_cache_dir.as_deref().and_then(load_block_limit) {
+        config.max_block_symbols = max_block_symbols;
+    }
+
+    let input: Box<dyn Read> = if is_stdio(&args.input) {
+        Box::new(io::stdin().lock())
+    } else {
+        match File::open(&args.input) {
+            Ok(file) => Box::new(file),
+            Err(error) => return Err(format!("failed to open {}: {}", args.input.display(), error)),
+        }
+    };
+    let mut encoder = match IncrementalEncoder::new(input, config) {
+        Ok(encoder) => encoder,
+        Err(error) => return Err(format!("failed to create encoder: {}", error)),
+    };
+
+    let mut shard = open_output(&args.shard)?;
+    let shard_error = |error: io::Error| format!("failed to write {}: {}", args.shard.display(), error);
+    shard.write_all(SHARD_MAGIC).map_err(shard_error)?;
+    let start = Instant::now();
+    let mut symbols: usize = 0;
+    let mut symbol_bytes: u64 = 0;
+    for block_encoder in encoder.by_ref() {
+        let block_encoder = match block_encoder {
+            Ok(block_encoder) => block_encoder,
+            Err(error) => return Err(format!("failed to read {}: {}", args.input.display(), error)),
+        };
+        let blocks = block_encoder.generate_encoded_blocks();
+        symbols += blocks.len();
+        symbol_bytes += blocks.iter().map(|x| x.data.data().len() as u64).sum::<u64>();
+        write_shard_records(&mut shard, &blocks).map_err(shard_error)?;
+        // downstream readers get each block's symbols as soon as they are out
+        shard.flush().map_err(shard_error)?;
+    }
+
+    let manifest = encoder.get_manifest().unwrap();
+    let written = File::create(&args.manifest).and_then(|file| manifest.write_to(BufWriter::new(file)));
+    if let Err(error) = written {
+        return Err(format!("failed to write {}: {}", args.manifest.display(), error));
+    }
+
+    if args.json {
+        let report = EncodeReport {
+            object_id: to_hex(&manifest.object_id),
+            data_size: manifest.data_size,
+            padded_size: manifest.get_padded_size(),
+            blocks: manifest.get_block_count(),
+            symbols,
+            symbol_bytes,
+            elapsed_secs: start.elapsed().as_secs_f64(),
+        };
+        return print_json(&report, is_stdio(&args.shard));
+    }
+    // stdout may be the shard
+    eprintln!(
+        "encoded {} bytes in {} blocks into {} symbols, object id {}",
+        manifest.data_size,
+        manifest.get_block_count(),
+        symbols,
+        to_hex(&manifest.object_id),
+    );
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raptor_cdn::codec::manifest::Manifest;
+    use raptor_cdn::codec::plan_cache::save_max_block_symbols;
+    use std::fs;
+
+    #[test]
+    fn test_encode_block_limit() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-encode-cli-test-{}", std::process::id()));
+        let plan_cache_dir = root.join("plans");
+        fs::create_dir_all(&plan_cache_dir).unwrap();
+        fs::write(root.join("object"), vec![3; 30 * 1000]).unwrap();
+        let args = |plan_cache_dir: Option<PathBuf>| EncodeArgs {
+            input: root.join("object"),
+            shard: root.join("shard"),
+            manifest: root.join("manifest"),
+            packet_size: 1280,
+            shrink_tail: false,
+            huge_pages: false,
+            plan_cache_dir,
+            json: false,
+        };
+        let read_manifest = || Manifest::read_from(File::open(root.join("manifest")).unwrap()).unwrap();
+
+        // nothing saved yet, the object fits a block
+        run(args(Some(plan_cache_dir.clone()))).unwrap();
+        assert_eq!(read_manifest().get_block_count(), 1);
+
+        save_max_block_symbols(&plan_cache_dir, 10).unwrap();
+        run(args(Some(plan_cache_dir.clone()))).unwrap();
+        let manifest = read_manifest();
+        assert_eq!(manifest.config.max_block_symbols, 10);
+        assert_eq!(manifest.get_block_count(), 3);
+        run(args(None)).unwrap();
+        assert_eq!(read_manifest().get_block_count(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use clap::Args;
+use serde::Serialize;
+
+use raptor_cdn::codec::archive::ArchiveWriter;
+use raptor_cdn::codec::encoder::{EncoderConfig, TailStrategy};
+use raptor_cdn::codec::incremental::IncrementalEncoder;
+use raptor_cdn::codec::manifest::to_hex;
+use super::print_json;
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// File to archive, or - to read it from stdin. Only a block is held at a time.
+    input: PathBuf,
+    /// Where to write the archive, or - for stdout, e.g. to pipe it to tape or object storage.
+    #[arg(long)]
+    out: PathBuf,
+    /// Repair symbols kept on top of each block's source symbols, in percent of them. A block survives as many of
+    /// its symbols rotting.
+    #[arg(long, default_value_t = 10)]
+    overhead: u16,
+    #[arg(long, default_value_t = 1280)]
+    packet_size: u16,
+    /// Shrink the symbols of the tail block instead of padding it.
+    #[arg(long)]
+    shrink_tail: bool,
+    /// Store the last source symbol of each padded block without its padding. Imports before this option was added
+    /// reject such archives.
+    #[arg(long)]
+    elide_padding: bool,
+    /// Print the results as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct ExportReport {
+    object_id: String,
+    data_size: u64,
+    blocks: usize,
+    symbols: u64,
+    overhead_percent: u16,
+    elapsed_secs: f64,
+}
+
+fn is_stdio(path: &Path) -> bool {
+    return path.as_os_str() == "-";
+}
+
+pub fn run(args: ExportArgs) -> Result<(), String> {
+    let mut config = EncoderConfig::new(args.packet_size);
+    if args.shrink_tail {
+        config.tail_strategy = TailStrategy::ShrinkSymbols;
+    }
+    config.elide_padding = args.elide_padding;
+
+    let input: Box<dyn Read> = if is_stdio(&args.input) {
+        Box::new(io::stdin().lock())
+    } else {
+        match File::open(&args.input) {
+            Ok(file) => Box::new(file),
+            Err(error) => return Err(format!("failed to open {}: {}", args.input.display(), error)),
+        }
+    };
+    let mut encoder = match IncrementalEncoder::new(input, config) {
+        Ok(encoder) => encoder,
+        Err(error) => return Err(format!("failed to create encoder: {}", error)),
+    };
+    let output: Box<dyn Write> = if is_stdio(&args.out) {
+        Box::new(BufWriter::new(io::stdout()))
+    } else {
+        match File::create(&args.out) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(error) => return Err(format!("failed to create {}: {}", args.out.display(), error)),
+        }
+    };
+
+    let start = Instant::now();
+    let out_error = |error: io::Error| format!("failed to write {}: {}", args.out.display(), error);
+    let mut writer = ArchiveWriter::new(output, args.overhead);
+    for block_encoder in encoder.by_ref() {
+        let block_encoder = match block_encoder {
+            Ok(block_encoder) => block_encoder,
+            Err(error) => return Err(format!("failed to read {}: {}", args.input.display(), error)),
+        };
+        writer.add_block(&block_encoder).map_err(out_error)?;
+    }
+    let symbols = writer.get_symbols();
+    let manifest = encoder.get_manifest().unwrap();
+    writer.finish(&manifest).map_err(out_error)?;
+
+    if args.json {
+        let report = ExportReport {
+            object_id: to_hex(&manifest.object_id),
+            data_size: manifest.data_size,
+            blocks: manifest.get_block_count(),
+            symbols,
+            overhead_percent: args.overhead,
+            elapsed_secs: start.elapsed().as_secs_f64(),
+        };
+        return print_json(&report, is_stdio(&args.out));
+    }
+    // stdout may be the archive
+    eprintln!(
+        "archived {} bytes in {} blocks into {} symbols, object id {}",
+        manifest.data_size,
+        manifest.get_block_count(),
+        symbols,
+        to_hex(&manifest.object_id),
+    );
+    return Ok(());
+}
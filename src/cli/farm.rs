@@ -0,0 +1,135 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use clap::Args;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use raptor_cdn::codec::encoder::{EncoderConfig, TailStrategy};
+use raptor_cdn::codec::farm::{Coordinator, EncodeJob, EncodeJobResult, EncodeWorker};
+use raptor_cdn::codec::manifest::to_hex;
+use super::print_json;
+
+#[derive(Args)]
+pub struct FarmArgs {
+    /// File to encode. Workers read it at the same path, so it has to be on storage they all reach.
+    input: PathBuf,
+    /// Socket of a daemon to send encode jobs to, see the daemon command. Repeat for more workers.
+    #[arg(long = "worker", required = true)]
+    workers: Vec<PathBuf>,
+    /// Directory workers write the shards of their jobs to, one per job.
+    #[arg(long)]
+    shard_dir: PathBuf,
+    /// Where to write the manifest of the whole object.
+    #[arg(long)]
+    manifest: PathBuf,
+    #[arg(long, default_value_t = 1280)]
+    packet_size: u16,
+    /// Shrink the symbols of the tail block instead of padding it.
+    #[arg(long)]
+    shrink_tail: bool,
+    /// Most blocks per job.
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    blocks_per_job: u32,
+    /// Print the results as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct FarmShard {
+    first_block: u32,
+    blocks: u32,
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct FarmReport {
+    object_id: String,
+    data_size: u64,
+    blocks: usize,
+    shards: Vec<FarmShard>,
+    elapsed_secs: f64,
+}
+
+/// A daemon taking encode jobs over its socket, a connection per job.
+struct DaemonWorker {
+    socket: PathBuf,
+    next_id: AtomicU64,
+}
+
+impl EncodeWorker for DaemonWorker {
+    fn run_job(&self, job: &EncodeJob) -> io::Result<EncodeJobResult> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut stream = UnixStream::connect(&self.socket)?;
+        writeln!(stream, "{}", json!({ "jsonrpc": "2.0", "id": id, "method": "encode_job", "params": job }))?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        let mut response: Value = serde_json::from_str(&line)?;
+        if let Some(error) = response.get("error") {
+            let message = error.get("message").and_then(|x| x.as_str()).unwrap_or("unknown error");
+            return Err(io::Error::other(format!("{}: {}", self.socket.display(), message)));
+        }
+        return Ok(serde_json::from_value(response["result"].take())?);
+    }
+}
+
+pub fn run(args: FarmArgs) -> Result<(), String> {
+    let mut config = EncoderConfig::new(args.packet_size);
+    if args.shrink_tail {
+        config.tail_strategy = TailStrategy::ShrinkSymbols;
+    }
+
+    // workers resolve paths against their own working directory
+    if let Err(error) = fs::create_dir_all(&args.shard_dir) {
+        return Err(format!("failed to create {}: {}", args.shard_dir.display(), error));
+    }
+    let input = fs::canonicalize(&args.input).map_err(|error| format!("failed to open {}: {}", args.input.display(), error))?;
+    let shard_dir = fs::canonicalize(&args.shard_dir).map_err(|error| format!("failed to open {}: {}", args.shard_dir.display(), error))?;
+
+    let workers: Vec<Arc<dyn EncodeWorker>> = args.workers.iter().map(|socket| {
+        Arc::new(DaemonWorker { socket: socket.clone(), next_id: AtomicU64::new(0) }) as Arc<dyn EncodeWorker>
+    }).collect();
+    let mut coordinator = Coordinator::new(workers, config);
+    coordinator.set_blocks_per_job(args.blocks_per_job);
+    let start = Instant::now();
+    let result = coordinator.run(&input, &shard_dir).map_err(|error| format!("failed to encode {}: {}", input.display(), error))?;
+
+    let manifest = &result.manifest;
+    let written = File::create(&args.manifest).and_then(|file| manifest.write_to(BufWriter::new(file)));
+    if let Err(error) = written {
+        return Err(format!("failed to write {}: {}", args.manifest.display(), error));
+    }
+
+    if args.json {
+        let report = FarmReport {
+            object_id: to_hex(&manifest.object_id),
+            data_size: manifest.data_size,
+            blocks: manifest.get_block_count(),
+            shards: result.shards.iter().map(|(block_ids, path)| FarmShard {
+                first_block: block_ids.start,
+                blocks: block_ids.len() as u32,
+                path: path.clone(),
+            }).collect(),
+            elapsed_secs: start.elapsed().as_secs_f64(),
+        };
+        return print_json(&report, false);
+    }
+    println!(
+        "encoded {} bytes in {} blocks on {} workers, object id {}",
+        manifest.data_size,
+        manifest.get_block_count(),
+        args.workers.len(),
+        to_hex(&manifest.object_id),
+    );
+    for (block_ids, path) in result.shards.iter() {
+        println!("blocks {}..{} in {}", block_ids.start, block_ids.end, path.display());
+    }
+    return Ok(());
+}
@@ -0,0 +1,111 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use clap::Args;
+use serde::Serialize;
+
+use raptor_cdn::codec::archive::ArchiveReader;
+use raptor_cdn::codec::manifest::{to_hex, validate_manifest, DecoderLimits};
+use super::print_json;
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// Archive written by the export command. It has to be a file, as its manifest comes last.
+    archive: PathBuf,
+    /// Where to write the object, or - for stdout. Without it, the archive is only verified.
+    #[arg(long)]
+    out: Option<PathBuf>,
+    /// Where to write the manifest of the object, e.g. to serve or decode it elsewhere.
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// Print the results as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct ImportReport {
+    object_id: String,
+    data_size: u64,
+    blocks: usize,
+    /// Blocks missing from the archive or not decoding to their hash, only listed when verifying.
+    bad_blocks: Vec<u32>,
+    restored: bool,
+    elapsed_secs: f64,
+}
+
+fn is_stdio(path: &Path) -> bool {
+    return path.as_os_str() == "-";
+}
+
+pub fn run(args: ImportArgs) -> Result<(), String> {
+    let file = match File::open(&args.archive) {
+        Ok(file) => file,
+        Err(error) => return Err(format!("failed to open {}: {}", args.archive.display(), error)),
+    };
+    let mut reader = match ArchiveReader::open(BufReader::new(file)) {
+        Ok(reader) => reader,
+        Err(error) => return Err(format!("failed to read {}: {}", args.archive.display(), error)),
+    };
+    let manifest = reader.get_manifest().clone();
+    if let Err(issues) = validate_manifest(&manifest, &DecoderLimits::default()) {
+        return Err(format!("bad manifest in {}: {:?}", args.archive.display(), issues));
+    }
+
+    let start = Instant::now();
+    let mut bad_blocks: Vec<u32> = Vec::new();
+    match args.out.as_ref() {
+        None => {
+            bad_blocks = reader.verify().map_err(|error| format!("failed to verify {}: {}", args.archive.display(), error))?;
+        },
+        Some(out) => {
+            let output: Box<dyn Write> = if is_stdio(out) {
+                Box::new(BufWriter::new(io::stdout()))
+            } else {
+                match File::create(out) {
+                    Ok(file) => Box::new(BufWriter::new(file)),
+                    Err(error) => return Err(format!("failed to create {}: {}", out.display(), error)),
+                }
+            };
+            if let Err(error) = reader.restore(output) {
+                // a partial object is worse than none
+                if !is_stdio(out) {
+                    let _ = fs::remove_file(out);
+                }
+                return Err(format!("failed to restore {}: {}", args.archive.display(), error));
+            }
+        },
+    }
+
+    if let Some(path) = args.manifest.as_ref() {
+        let written = File::create(path).and_then(|file| manifest.write_to(BufWriter::new(file)));
+        if let Err(error) = written {
+            return Err(format!("failed to write {}: {}", path.display(), error));
+        }
+    }
+
+    let to_stderr = args.out.as_deref().is_some_and(is_stdio);
+    if args.json {
+        let report = ImportReport {
+            object_id: to_hex(&manifest.object_id),
+            data_size: manifest.data_size,
+            blocks: manifest.get_block_count(),
+            bad_blocks: bad_blocks.clone(),
+            restored: args.out.is_some(),
+            elapsed_secs: start.elapsed().as_secs_f64(),
+        };
+        print_json(&report, to_stderr)?;
+    } else if args.out.is_some() {
+        eprintln!("restored {} bytes in {} blocks, object id {}", manifest.data_size, manifest.get_block_count(), to_hex(&manifest.object_id));
+    } else if bad_blocks.is_empty() {
+        println!("verified {} bytes in {} blocks, object id {}", manifest.data_size, manifest.get_block_count(), to_hex(&manifest.object_id));
+    }
+
+    if !bad_blocks.is_empty() {
+        let ids: Vec<String> = bad_blocks.iter().map(|x| x.to_string()).collect();
+        return Err(format!("{} of {} blocks can not be restored: {}", bad_blocks.len(), manifest.get_block_count(), ids.join(", ")));
+    }
+    return Ok(());
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use raptorq::extended_source_block_symbols;
+use serde::Serialize;
+
+use raptor_cdn::codec::encoder::EncodedBlock;
+use raptor_cdn::codec::manifest::{get_manifest_version, to_hex, validate_manifest, DecoderLimits, Manifest};
+use raptor_cdn::codec::shard::{read_shard, SHARD_MAGIC};
+use super::print_json;
+
+#[derive(Args)]
+pub struct InspectArgs {
+    /// Manifest or shard file to inspect.
+    path: PathBuf,
+    /// Manifest of the object a shard belongs to, to report coverage against each block's symbol count.
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// Print the contents as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest, String> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+    };
+    match Manifest::read_from(&data[..]) {
+        Ok(manifest) => return Ok(manifest),
+        Err(error) => return Err(format!("failed to parse {}: {}", path.display(), error)),
+    }
+}
+
+#[derive(Serialize)]
+struct BlockReport {
+    block_id: u32,
+    payload_size: usize,
+    symbols: usize,
+    symbol_size: u16,
+    overhead_percent: u16,
+    hash: String,
+}
+
+#[derive(Serialize)]
+struct ManifestReport {
+    object_id: String,
+    data_size: u64,
+    padded_size: u64,
+    padding_percent: f64,
+    packet_size: u16,
+    alignment: u8,
+    tail: String,
+    blocks: Vec<BlockReport>,
+    /// Issues validate_manifest found, formatted for people.
+    issues: Vec<String>,
+}
+
+impl ManifestReport {
+    fn new(manifest: &Manifest) -> ManifestReport {
+        let padded_size = manifest.get_padded_size();
+        let blocks = manifest.block_info_vec.iter().zip(manifest.block_hashes.iter()).zip(manifest.block_overheads.iter()).map(|((block_info, block_hash), overhead)| {
+            BlockReport {
+                block_id: block_info.block_id,
+                payload_size: block_info.payload_size,
+                symbols: block_info.padded_size / block_info.config.symbol_size() as usize,
+                symbol_size: block_info.config.symbol_size(),
+                overhead_percent: *overhead,
+                hash: to_hex(block_hash),
+            }
+        }).collect();
+
+        return ManifestReport {
+            object_id: to_hex(&manifest.object_id),
+            data_size: manifest.data_size,
+            padded_size,
+            padding_percent: match manifest.data_size {
+                0 => 0.0,
+                _ => (padded_size - manifest.data_size) as f64 * 100.0 / manifest.data_size as f64,
+            },
+            packet_size: manifest.config.packet_size,
+            alignment: manifest.config.alignment,
+            tail: format!("{:?}", manifest.config.tail_strategy),
+            blocks,
+            issues: validate_manifest(manifest, &DecoderLimits::default()).err().unwrap_or_default().iter().map(|x| format!("{:?}", x)).collect(),
+        };
+    }
+
+    fn print(&self) {
+        println!("manifest");
+        println!("  object id    {}", self.object_id);
+        println!("  data size    {}", self.data_size);
+        println!("  padded size  {} ({:.2}% padding overhead)", self.padded_size, self.padding_percent);
+        println!("  packet size  {}", self.packet_size);
+        println!("  alignment    {}", self.alignment);
+        println!("  tail         {}", self.tail);
+        println!("  blocks       {}", self.blocks.len());
+        for block in self.blocks.iter() {
+            println!(
+                "  block {:>6} size {:>10} symbols {:>6} x {:>5} overhead {:>3}% hash {}",
+                block.block_id,
+                block.payload_size,
+                block.symbols,
+                block.symbol_size,
+                block.overhead_percent,
+                block.hash,
+            );
+        }
+        for issue in self.issues.iter() {
+            println!("  issue        {}", issue);
+        }
+    }
+}
+
+/// Groups sorted encoding symbol ids into [start, end) ranges, e.g. (0, 3), (7, 8).
+fn esi_ranges(esis: &[u32]) -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for esi in esis.iter() {
+        match ranges.last_mut() {
+            Some((_, end)) if *end == *esi => *end += 1,
+            _ => ranges.push((*esi, *esi + 1)),
+        }
+    }
+    return ranges;
+}
+
+#[derive(Serialize)]
+struct ShardBlockReport {
+    block_id: u32,
+    unique_symbols: usize,
+    /// Encoding symbol ids held, as [start, end) ranges.
+    esi_ranges: Vec<(u32, u32)>,
+    /// Source and repair symbols held and the symbols needed, if the manifest was given.
+    source: Option<usize>,
+    repair: Option<usize>,
+    needed: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct ShardReport {
+    symbols: usize,
+    blocks: Vec<ShardBlockReport>,
+}
+
+impl ShardReport {
+    fn new(blocks: &[EncodedBlock], manifest: Option<&Manifest>) -> ShardReport {
+        let mut esis: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        for block in blocks.iter() {
+            esis.entry(block.block_id).or_default().push(block.data.payload_id().encoding_symbol_id());
+        }
+
+        let mut block_reports: Vec<ShardBlockReport> = Vec::with_capacity(esis.len());
+        for (block_id, mut block_esis) in esis.into_iter() {
+            block_esis.sort_unstable();
+            block_esis.dedup();
+
+            let mut report = ShardBlockReport {
+                block_id,
+                unique_symbols: block_esis.len(),
+                esi_ranges: esi_ranges(&block_esis),
+                source: None,
+                repair: None,
+                needed: None,
+            };
+            if let Some(block_info) = manifest.and_then(|x| x.block_info_vec.get(block_id as usize)) {
+                let symbol_count = (block_info.padded_size / block_info.config.symbol_size() as usize) as u32;
+                let extended_symbol_count = extended_source_block_symbols(symbol_count);
+                report.source = Some(block_esis.iter().filter(|x| **x < symbol_count).count());
+                report.repair = Some(block_esis.iter().filter(|x| **x >= extended_symbol_count).count());
+                report.needed = Some(symbol_count);
+            }
+            block_reports.push(report);
+        }
+
+        return ShardReport { symbols: blocks.len(), blocks: block_reports };
+    }
+
+    fn print(&self) {
+        println!("shard");
+        println!("  symbols      {}", self.symbols);
+        println!("  blocks       {}", self.blocks.len());
+        for block in self.blocks.iter() {
+            let coverage = match (block.source, block.repair, block.needed) {
+                (Some(source), Some(repair), Some(needed)) => format!(" ({} source + {} repair of {} needed)", source, repair, needed),
+                _ => String::new(),
+            };
+            let ranges: Vec<String> = block.esi_ranges.iter().map(|(start, end)| format!("{}..{}", start, end)).collect();
+            println!("  block {:>6} unique symbols {:>6}{}", block.block_id, block.unique_symbols, coverage);
+            println!("    esi {}", ranges.join(", "));
+        }
+    }
+}
+
+pub fn run(args: InspectArgs) -> Result<(), String> {
+    let data = match fs::read(&args.path) {
+        Ok(data) => data,
+        Err(error) => return Err(format!("failed to read {}: {}", args.path.display(), error)),
+    };
+
+    if get_manifest_version(&data).is_some() {
+        let report = ManifestReport::new(&read_manifest(&args.path)?);
+        if args.json {
+            return print_json(&report, false);
+        }
+        report.print();
+        return Ok(());
+    }
+
+    if data.starts_with(SHARD_MAGIC) {
+        let manifest = match args.manifest {
+            None => None,
+            Some(path) => Some(read_manifest(&path)?),
+        };
+        let report = match read_shard(&data[..]) {
+            Ok(blocks) => ShardReport::new(&blocks, manifest.as_ref()),
+            Err(error) => return Err(format!("failed to parse {}: {}", args.path.display(), error)),
+        };
+        if args.json {
+            return print_json(&report, false);
+        }
+        report.print();
+        return Ok(());
+    }
+
+    return Err(format!("{} is neither a manifest nor a shard", args.path.display()));
+}
@@ -0,0 +1,37 @@
+use serde::Serialize;
+
+pub mod soak;
+pub mod bundle;
+pub mod plan_cache;
+pub mod inspect;
+pub mod bench;
+pub mod serve;
+pub mod token;
+pub mod purge;
+pub mod encode;
+pub mod decode;
+pub mod audit;
+pub mod export;
+pub mod import;
+pub mod vectors;
+pub mod conformance;
+#[cfg(unix)]
+pub mod daemon;
+#[cfg(unix)]
+pub mod farm;
+#[cfg(all(unix, feature = "top"))]
+pub mod top;
+
+/// Prints a command's results as one line of JSON for --json, to stderr when stdout carries data, e.g. a shard.
+pub fn print_json<T: Serialize>(value: &T, to_stderr: bool) -> Result<(), String> {
+    let json = match serde_json::to_string(value) {
+        Ok(json) => json,
+        Err(error) => return Err(format!("failed to format results: {}", error)),
+    };
+    if to_stderr {
+        eprintln!("{}", json);
+    } else {
+        println!("{}", json);
+    }
+    return Ok(());
+}
@@ -0,0 +1,387 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand};
+use serde::Serialize;
+
+use raptor_cdn::codec::consts::*;
+use raptor_cdn::codec::encoder::EncoderConfig;
+use raptor_cdn::codec::plan_cache::{gc_dir, list_dir, load_max_block_symbols, measure_plan_benefit, migrate_dir, pick_max_block_symbols, plan_symbol_counts, save_max_block_symbols, PlanBenefit, PlanCache, PlanFile};
+use super::bench::parse_size;
+use super::print_json;
+
+#[derive(Args)]
+pub struct PlanCacheArgs {
+    /// Directory plans are saved in.
+    #[arg(long, default_value = ".encoding_plan_cache")]
+    dir: PathBuf,
+    /// Print the results as JSON.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: PlanCacheCommand,
+}
+
+#[derive(Subcommand)]
+enum PlanCacheCommand {
+    /// Generate plans for the given symbol counts and report how long each took, to decide what to prewarm.
+    /// Nothing is saved.
+    Stats {
+        /// Symbol counts (K) to generate plans for.
+        #[arg(required = true)]
+        symbol_counts: Vec<u16>,
+    },
+    /// Generate plans for the given symbol counts, or for the blocks of objects of the given sizes, and save the
+    /// ones not already in the cache directory. Objects are split into blocks of the limit saved by experiment, if
+    /// any.
+    Prewarm {
+        /// Symbol counts (K) to generate plans for.
+        #[arg(long, value_delimiter = ',', required_unless_present = "sizes")]
+        symbols: Vec<u16>,
+        /// Expected object sizes, as SIZE or SIZE:COUNT with an optional K, M or G suffix on the size.
+        #[arg(long, value_delimiter = ',', value_parser = parse_size_count)]
+        sizes: Vec<(u64, u64)>,
+        /// Packet size objects are encoded with, for --sizes.
+        #[arg(long, default_value_t = 1280)]
+        packet_size: u16,
+    },
+    /// Measure block encode time with and without a cached plan across symbol counts, and pick the block symbol
+    /// limit encoders should default to from it, see plan_cache::pick_max_block_symbols.
+    Experiment {
+        /// Symbol counts (K) to measure, by default a spread from 10 to the most a block may have.
+        #[arg(long, value_delimiter = ',')]
+        symbols: Vec<u16>,
+        /// Packet size blocks are encoded with.
+        #[arg(long, default_value_t = 1280)]
+        packet_size: u16,
+        /// Encodes of each symbol count each way, the fastest of which is kept.
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+        rounds: u32,
+        /// Save the picked limit in the cache directory, where daemon, encode, serve and prewarm --sizes pick it up.
+        #[arg(long)]
+        save: bool,
+    },
+    /// List the plans in the cache directory.
+    Ls,
+    /// Remove the least recently written plans until the cache directory fits in a size.
+    Gc {
+        /// Size to shrink the saved plans to, in bytes.
+        #[arg(long)]
+        max_size: u64,
+    },
+    /// Rewrite the plans in the cache directory saved by older versions in the current format, in place.
+    Migrate,
+}
+
+/// Gets the block symbol limit experiment saved in dir, if any, for the commands encoding objects to split them
+/// into blocks of. An unusable limit is warned about and ignored.
+pub fn load_block_limit(dir: &Path) -> Option<u16> {
+    match load_max_block_symbols(dir) {
+        Ok(max_block_symbols) => return max_block_symbols,
+        Err(error) => {
+            eprintln!("warning: ignoring the block symbol limit of {}: {}", dir.display(), error);
+            return None;
+        },
+    }
+}
+
+/// Parses a histogram entry like 64K or 1M:20.
+fn parse_size_count(value: &str) -> Result<(u64, u64), String> {
+    let (size, count) = match value.split_once(':') {
+        None => (value, "1"),
+        Some(split) => split,
+    };
+    match count.parse::<u64>() {
+        Ok(count) => return Ok((parse_size(size)? as u64, count)),
+        Err(_) => return Err(format!("{} is not a valid count", count)),
+    }
+}
+
+fn validate_symbol_counts(symbol_counts: &[u16]) -> Result<(), String> {
+    if let Some(symbol_count) = symbol_counts.iter().find(|x| **x == 0 || **x as usize > RAPTORQ_MAX_SYMBOLS_IN_BLOCK) {
+        return Err(format!("symbol count {} is not in 1..={}", symbol_count, RAPTORQ_MAX_SYMBOLS_IN_BLOCK));
+    }
+
+    return Ok(());
+}
+
+/// Generation stats of the plans of one symbol count.
+#[derive(Serialize)]
+struct PlanStatsRow {
+    symbol_count: u16,
+    plans: u64,
+    total_ms: f64,
+    hits: u64,
+    max_ms: f64,
+}
+
+fn stats_rows(cache: &PlanCache) -> Vec<PlanStatsRow> {
+    return cache.get_stats().into_iter().map(|(symbol_count, stats)| PlanStatsRow {
+        symbol_count,
+        plans: stats.generations,
+        total_ms: stats.generation_time.as_secs_f64() * 1000.0,
+        hits: stats.hits,
+        max_ms: stats.max_generation_time.as_secs_f64() * 1000.0,
+    }).collect();
+}
+
+fn print_stats(rows: &[PlanStatsRow]) {
+    println!("{:>8} {:>8} {:>12} {:>8} {:>12}", "K", "plans", "total_ms", "hits", "max_ms");
+    for row in rows.iter() {
+        println!("{:>8} {:>8} {:>12.3} {:>8} {:>12.3}", row.symbol_count, row.plans, row.total_ms, row.hits, row.max_ms);
+    }
+}
+
+#[derive(Serialize)]
+struct StatsReport {
+    stats: Vec<PlanStatsRow>,
+}
+
+fn stats(symbol_counts: &[u16], json: bool) -> Result<(), String> {
+    validate_symbol_counts(symbol_counts)?;
+
+    let cache = PlanCache::new();
+    cache.prewarm(symbol_counts);
+    let rows = stats_rows(&cache);
+    if json {
+        return print_json(&StatsReport { stats: rows }, false);
+    }
+    print_stats(&rows);
+    return Ok(());
+}
+
+#[derive(Serialize)]
+struct ExpectedBlocks {
+    symbol_count: u16,
+    blocks: u64,
+}
+
+#[derive(Serialize)]
+struct PrewarmReport {
+    /// Blocks of each symbol count expected of objects of the sizes given.
+    expected: Vec<ExpectedBlocks>,
+    stats: Vec<PlanStatsRow>,
+    /// Plans saved, None if the cache directory was unusable.
+    saved: Option<usize>,
+}
+
+fn prewarm(dir: &Path, symbol_counts: &[u16], sizes: &[(u64, u64)], packet_size: u16, json: bool) -> Result<(), String> {
+    validate_symbol_counts(symbol_counts)?;
+    if !sizes.is_empty() && packet_size < MIN_PACKET_SIZE {
+        return Err(format!("packet size must be at least {}", MIN_PACKET_SIZE));
+    }
+    let mut config = EncoderConfig::new(packet_size);
+    if let Some(max_block_symbols) = load_block_limit(dir) {
+        config.max_block_symbols = max_block_symbols;
+    }
+    let mut symbol_counts = symbol_counts.to_vec();
+    let mut expected: Vec<ExpectedBlocks> = Vec::new();
+    for (symbol_count, blocks) in plan_symbol_counts(sizes, config) {
+        if !json {
+            println!("K {:>8} expected blocks {}", symbol_count, blocks);
+        }
+        symbol_counts.push(symbol_count);
+        expected.push(ExpectedBlocks { symbol_count, blocks });
+    }
+
+    let (cache, error) = PlanCache::open_dir(dir);
+    if let Some(error) = error.as_ref() {
+        eprintln!("warning: plan cache {} is unusable, plans will not be saved: {}", dir.display(), error);
+    }
+    cache.prewarm(&symbol_counts);
+    let rows = stats_rows(&cache);
+    if !json {
+        print_stats(&rows);
+    }
+
+    let mut saved: Option<usize> = None;
+    if error.is_none() {
+        match cache.save_dir(dir) {
+            Ok(count) => saved = Some(count),
+            Err(error) => return Err(format!("failed to save plans to {}: {}", dir.display(), error)),
+        }
+    }
+    if json {
+        return print_json(&PrewarmReport { expected, stats: rows, saved }, false);
+    }
+    if let Some(saved) = saved {
+        println!("saved {} plans to {}", saved, dir.display());
+    }
+    return Ok(());
+}
+
+/// Symbol counts experiment measures by default: about three per decade from 10 up, and the most a block may have.
+const EXPERIMENT_SYMBOL_COUNTS: &[u16] = &[10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000, 20000, 40000, RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u16];
+
+/// Encode times of one symbol count with and without a cached plan.
+#[derive(Serialize)]
+struct ExperimentRow {
+    symbol_count: u16,
+    plan_ms: f64,
+    uncached_ms: f64,
+    cached_ms: f64,
+    /// Share of the uncached encode time the plan saves, in percent.
+    saved_percent: f64,
+    cached_us_per_symbol: f64,
+}
+
+impl ExperimentRow {
+    fn new(benefit: &PlanBenefit) -> ExperimentRow {
+        let uncached_ms = benefit.uncached_time.as_secs_f64() * 1000.0;
+        let cached_ms = benefit.cached_time.as_secs_f64() * 1000.0;
+        return ExperimentRow {
+            symbol_count: benefit.symbol_count,
+            plan_ms: benefit.plan_time.as_secs_f64() * 1000.0,
+            uncached_ms,
+            cached_ms,
+            saved_percent: if uncached_ms > 0.0 { (1.0 - cached_ms / uncached_ms) * 100.0 } else { 0.0 },
+            cached_us_per_symbol: benefit.get_cached_secs_per_symbol() * 1_000_000.0,
+        };
+    }
+}
+
+#[derive(Serialize)]
+struct ExperimentReport {
+    packet_size: u16,
+    rounds: u32,
+    results: Vec<ExperimentRow>,
+    max_block_symbols: u16,
+    /// Whether max_block_symbols was saved in the cache directory.
+    saved: bool,
+}
+
+fn experiment(dir: &Path, symbol_counts: &[u16], packet_size: u16, rounds: u32, save: bool, json: bool) -> Result<(), String> {
+    validate_symbol_counts(symbol_counts)?;
+    if packet_size < MIN_PACKET_SIZE {
+        return Err(format!("packet size must be at least {}", MIN_PACKET_SIZE));
+    }
+    let symbol_counts = match symbol_counts.is_empty() {
+        true => EXPERIMENT_SYMBOL_COUNTS,
+        false => symbol_counts,
+    };
+
+    if !json {
+        println!("{:>8} {:>12} {:>12} {:>12} {:>8} {:>12}", "K", "plan_ms", "uncached_ms", "cached_ms", "saved%", "us/symbol");
+    }
+    let mut benefits: Vec<PlanBenefit> = Vec::with_capacity(symbol_counts.len());
+    for symbol_count in symbol_counts.iter() {
+        let benefit = match measure_plan_benefit(*symbol_count, packet_size, rounds as usize) {
+            Ok(benefit) => benefit,
+            Err(error) => return Err(format!("failed to encode {} symbols: {:?}", symbol_count, error)),
+        };
+        if !json {
+            let row = ExperimentRow::new(&benefit);
+            println!("{:>8} {:>12.3} {:>12.3} {:>12.3} {:>8.1} {:>12.3}", row.symbol_count, row.plan_ms, row.uncached_ms, row.cached_ms, row.saved_percent, row.cached_us_per_symbol);
+        }
+        benefits.push(benefit);
+    }
+
+    let max_block_symbols = pick_max_block_symbols(&benefits);
+    if save {
+        if let Err(error) = save_max_block_symbols(dir, max_block_symbols) {
+            return Err(format!("failed to save the block symbol limit to {}: {}", dir.display(), error));
+        }
+    }
+    if json {
+        let results = benefits.iter().map(ExperimentRow::new).collect();
+        return print_json(&ExperimentReport { packet_size, rounds, results, max_block_symbols, saved: save }, false);
+    }
+    println!("max block symbols {}", max_block_symbols);
+    if save {
+        println!("saved to {}", dir.display());
+    }
+    return Ok(());
+}
+
+#[derive(Serialize)]
+struct PlanFileRow {
+    symbol_count: u16,
+    path: String,
+    size: u64,
+}
+
+impl PlanFileRow {
+    fn new(plan_file: &PlanFile) -> PlanFileRow {
+        return PlanFileRow {
+            symbol_count: plan_file.symbol_count,
+            path: plan_file.path.display().to_string(),
+            size: plan_file.size,
+        };
+    }
+}
+
+#[derive(Serialize)]
+struct PlanFilesReport {
+    plans: Vec<PlanFileRow>,
+    total_size: u64,
+}
+
+impl PlanFilesReport {
+    fn new(plan_files: &[PlanFile]) -> PlanFilesReport {
+        return PlanFilesReport {
+            plans: plan_files.iter().map(PlanFileRow::new).collect(),
+            total_size: plan_files.iter().map(|x| x.size).sum(),
+        };
+    }
+}
+
+fn ls(dir: &Path, json: bool) -> Result<(), String> {
+    let plan_files = match list_dir(dir) {
+        Ok(plan_files) => plan_files,
+        Err(error) => return Err(format!("failed to list {}: {}", dir.display(), error)),
+    };
+    if json {
+        return print_json(&PlanFilesReport::new(&plan_files), false);
+    }
+
+    println!("{:>8} {:>12}", "K", "bytes");
+    for plan_file in plan_files.iter() {
+        println!("{:>8} {:>12}", plan_file.symbol_count, plan_file.size);
+    }
+    println!("{} plans, {} bytes", plan_files.len(), plan_files.iter().map(|x| x.size).sum::<u64>());
+    return Ok(());
+}
+
+fn gc(dir: &Path, max_size: u64, json: bool) -> Result<(), String> {
+    let removed = match gc_dir(dir, max_size) {
+        Ok(removed) => removed,
+        Err(error) => return Err(format!("failed to collect {}: {}", dir.display(), error)),
+    };
+    // the plans listed are the ones removed
+    if json {
+        return print_json(&PlanFilesReport::new(&removed), false);
+    }
+
+    for plan_file in removed.iter() {
+        println!("removed {}", plan_file.path.display());
+    }
+    println!("removed {} plans, {} bytes", removed.len(), removed.iter().map(|x| x.size).sum::<u64>());
+    return Ok(());
+}
+
+fn migrate(dir: &Path, json: bool) -> Result<(), String> {
+    let migrated = match migrate_dir(dir) {
+        Ok(migrated) => migrated,
+        Err(error) => return Err(format!("failed to migrate {}: {}", dir.display(), error)),
+    };
+    // the plans listed are the ones rewritten
+    if json {
+        return print_json(&PlanFilesReport::new(&migrated), false);
+    }
+
+    for plan_file in migrated.iter() {
+        println!("migrated {}", plan_file.path.display());
+    }
+    println!("migrated {} plans", migrated.len());
+    return Ok(());
+}
+
+pub fn run(args: PlanCacheArgs) -> Result<(), String> {
+    match args.command {
+        PlanCacheCommand::Stats { symbol_counts } => return stats(&symbol_counts, args.json),
+        PlanCacheCommand::Prewarm { symbols, sizes, packet_size } => return prewarm(&args.dir, &symbols, &sizes, packet_size, args.json),
+        PlanCacheCommand::Experiment { symbols, packet_size, rounds, save } => return experiment(&args.dir, &symbols, packet_size, rounds, save, args.json),
+        PlanCacheCommand::Ls => return ls(&args.dir, args.json),
+        PlanCacheCommand::Gc { max_size } => return gc(&args.dir, max_size, args.json),
+        PlanCacheCommand::Migrate => return migrate(&args.dir, args.json),
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use raptor_cdn::client::http::send_purge;
+use raptor_cdn::codec::manifest::parse_object_id;
+use raptor_cdn::server::purge::{PurgeKey, PurgeNotice};
+use super::token::read_key_file;
+
+#[derive(Args)]
+pub struct PurgeArgs {
+    /// File holding the key the servers check purge notices with.
+    #[arg(long)]
+    key_file: PathBuf,
+    /// Id of the object to purge.
+    #[arg(long)]
+    object: String,
+    /// Why the object is purged, kept in the notice for logs.
+    #[arg(long, default_value = "")]
+    reason: String,
+    /// Address of a server to send the notice to, e.g. 127.0.0.1:8080. Without any the notice is only printed, to
+    /// be sent later.
+    #[arg(long)]
+    server: Vec<String>,
+}
+
+pub fn read_key(path: &Path) -> Result<PurgeKey, String> {
+    return Ok(PurgeKey::new(&read_key_file(path, "purge")?));
+}
+
+pub fn run(args: PurgeArgs) -> Result<(), String> {
+    let object_id = match parse_object_id(&args.object) {
+        Some(object_id) => object_id,
+        None => return Err(format!("{} is not an object id", args.object)),
+    };
+
+    let notice = read_key(&args.key_file)?.sign(&PurgeNotice::new(object_id, &args.reason));
+    println!("{}", notice);
+
+    // keep going past failures, so one server being down doesn't leave the others serving the object
+    let mut failed: usize = 0;
+    for server in args.server.iter() {
+        match send_purge(&server[..], &notice) {
+            Ok(names) => names.iter().for_each(|x| println!("{} deleted {}", server, x)),
+            Err(error) => {
+                eprintln!("{} failed: {}", server, error);
+                failed += 1;
+            },
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} servers did not take the notice", failed, args.server.len()));
+    }
+    return Ok(());
+}
@@ -0,0 +1,663 @@
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use raptor_cdn::codec::encoder::{EncoderConfig, TailStrategy};
+use raptor_cdn::codec::manifest::Protection;
+use raptor_cdn::codec::numa::{EncodePlacement, NumaPolicy};
+use raptor_cdn::server::broadcast::CatalogBroadcast;
+use raptor_cdn::server::catalog::{Catalog, CatalogChanges, CatalogLimits};
+use raptor_cdn::server::health::{get_watchdog_interval, notify_systemd};
+use raptor_cdn::server::http::HttpServer;
+use raptor_cdn::server::manifest_key::ManifestKey;
+use raptor_cdn::server::pex::PeerExchange;
+use raptor_cdn::server::pool::{PoolSettings, PoolStats};
+use raptor_cdn::server::tenant::Tenant;
+use raptor_cdn::store::file::FileStore;
+use raptor_cdn::transport::net::{bind_tcp, bind_udp, AddressFamily};
+use raptor_cdn::transport::queue::SendQueue;
+use raptor_cdn::transport::udp::Integrity;
+use super::bench::parse_size;
+use super::plan_cache::load_block_limit;
+
+/// How soon a SIGHUP is acted on.
+const SIGHUP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the UDP carousel queues the datagrams due at its rate.
+const BROADCAST_TICK: Duration = Duration::from_millis(10);
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Directory of files to serve. Hidden files are skipped.
+    #[arg(long)]
+    root: PathBuf,
+    /// Address to serve HTTP on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+    /// Addresses to take clients on: any for the system's default, ipv4, ipv6, or dual for IPv6 and IPv4 clients
+    /// on an IPv6 address such as [::]:8080.
+    #[arg(long, default_value = "any")]
+    address_family: AddressFamily,
+    /// Encoded packet size.
+    #[arg(long, default_value_t = 1280)]
+    packet_size: u16,
+    /// Shrink the symbols of each file's last block instead of padding it to a whole packet.
+    #[arg(long)]
+    shrink_tail: bool,
+    /// Keep block data in huge pages where the system has them, falling back to regular pages otherwise.
+    #[arg(long)]
+    huge_pages: bool,
+    /// Plan cache directory to take the block symbol limit saved by plan-cache experiment from, see the daemon
+    /// command. Without it, or a limit saved there, blocks hold as many symbols as they may.
+    #[arg(long)]
+    plan_cache_dir: Option<PathBuf>,
+    /// How often to rescan the root directory in full, in seconds, on top of rescanning when it is notified of
+    /// changes. Catches changes the file system doesn't notify of, e.g. on network file systems.
+    #[arg(long, default_value_t = 30)]
+    reload_secs: u64,
+    /// How long changes under the root directory must settle before it is rescanned, in milliseconds, so a file
+    /// being written is encoded once it is done rather than at every write.
+    #[arg(long, default_value_t = 500)]
+    watch_debounce_ms: u64,
+    /// Also broadcast every object over UDP to this address, e.g. a multicast group like 239.1.2.3:5000, as a
+    /// carousel receivers can join at any time, see server::broadcast.
+    #[arg(long)]
+    udp_carousel: Option<String>,
+    /// Datagrams per second the UDP carousel sends.
+    #[arg(long, default_value_t = 1000, requires = "udp_carousel", value_parser = clap::value_parser!(u64).range(1..))]
+    udp_carousel_rate: u64,
+    /// Close symbol sessions that requested nothing for this many seconds. Their clients have to open a new one.
+    #[arg(long, default_value_t = 600)]
+    session_ttl_secs: u64,
+    /// Drop the encoder state of objects without open sessions at each rescan, recomputing it when they are asked
+    /// for symbols again. Bounds memory when serving many objects, at the cost of encoding them again.
+    #[arg(long)]
+    evict_idle: bool,
+    /// Generate the symbols of concurrent requests for the same object in shared passes.
+    #[arg(long)]
+    coalesce: bool,
+    /// Keep this many symbols of each block of hot objects generated ahead of requests, trading memory for request
+    /// latency, see server::pool. Without it symbols are generated on request.
+    #[arg(long)]
+    symbol_pool_size: Option<usize>,
+    /// Symbol requests per second that make an object hot.
+    #[arg(long, default_value_t = 10, requires = "symbol_pool_size")]
+    symbol_pool_hot_rate: u64,
+    /// How often symbol pools are refilled, in milliseconds.
+    #[arg(long, default_value_t = 100, requires = "symbol_pool_size", value_parser = clap::value_parser!(u64).range(1..))]
+    symbol_pool_refill_ms: u64,
+    /// Alternate the symbols of this many adjacent blocks in each response, to spread burst losses over blocks.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    interleave_depth: u64,
+    /// Repair overhead for a byte range of every file, as START-END:PERCENT with an optional K, M or G suffix on the
+    /// sizes and END left out for the rest of the file, e.g. 0-64K:30,64K-:5. Blocks holding part of a range get
+    /// its share of the symbols sent.
+    #[arg(long, value_delimiter = ',', value_parser = parse_protection)]
+    protect: Vec<Protection>,
+    /// Serve each directory whose name ends with .bundle as one object, a bundle of the files directly in it, to save
+    /// the per object overhead of many small files. Clients take files out of the decoded bundle by name, see the
+    /// bundle command.
+    #[arg(long)]
+    bundle_dirs: bool,
+    /// Encode the blocks of each file at once, on threads pinned to NUMA nodes: off for threads on any CPU,
+    /// interleave to deal blocks out to every node, or nodes:<ids> for some, e.g. nodes:0,1. Pays off for files of
+    /// gigabytes. Without it each file is encoded on the thread rescanning.
+    #[arg(long)]
+    encode_placement: Option<NumaPolicy>,
+    /// Encode threads per NUMA node, or in all with --encode-placement off. 0 for one per CPU.
+    #[arg(long, default_value_t = 0, requires = "encode_placement")]
+    encode_threads_per_node: usize,
+    /// File holding the key object requests must carry a token signed with, see the token command.
+    /// Without it objects are served to anyone.
+    #[arg(long)]
+    token_key_file: Option<PathBuf>,
+    /// File holding the key purge notices must be signed with, see the purge command. Without it GET /purge is not
+    /// served.
+    #[arg(long)]
+    purge_key_file: Option<PathBuf>,
+    /// File holding the key to sign manifests with, so clients can prefer them over those of caches, see
+    /// server::manifest_key.
+    #[arg(long)]
+    manifest_key_file: Option<PathBuf>,
+    /// Tell clients asking for other peers of an object about the peers that announced themselves in the last this
+    /// many seconds, see server::pex. Without it peers are not exchanged.
+    #[arg(long)]
+    peer_exchange_ttl_secs: Option<u64>,
+    /// Peers to tell clients of for every object, e.g. the other nodes of a cluster, as host:port socket addresses.
+    #[arg(long, value_delimiter = ',', requires = "peer_exchange_ttl_secs", value_parser = parse_peer)]
+    peers: Vec<String>,
+    /// Directory of a file object store to serve ranges of objects from, at /objects/<object id>/range, including
+    /// objects only partially fetched into it, see HttpServer.
+    #[arg(long)]
+    store_dir: Option<PathBuf>,
+    /// Serve each directory directly under root as a tenant, under /tenants/<directory>/. Tenants are found at
+    /// startup.
+    #[arg(long)]
+    tenants: bool,
+    /// Most files served per tenant.
+    #[arg(long, requires = "tenants")]
+    tenant_max_objects: Option<usize>,
+    /// Most bytes of files served per tenant, with an optional K, M or G suffix.
+    #[arg(long, requires = "tenants", value_parser = parse_size)]
+    tenant_max_storage: Option<usize>,
+    /// Most symbol bytes per second served per tenant, with an optional K, M or G suffix.
+    #[arg(long, requires = "tenants", value_parser = parse_size)]
+    tenant_max_bandwidth: Option<usize>,
+    /// File of "key = value" lines overriding session_ttl_secs, tenant_max_objects, tenant_max_storage,
+    /// tenant_max_bandwidth, protect and peers, with "none" for no limit, ranges or peers. Re-read on SIGHUP without
+    /// dropping open sessions.
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+/// Parses a range's repair overhead like 0-64K:30 or 64K-:5.
+fn parse_protection(value: &str) -> Result<Protection, String> {
+    let bad_value = || format!("{} is not START-END:PERCENT", value);
+    let (range, percent) = value.split_once(':').ok_or_else(bad_value)?;
+    let (start, end) = range.split_once('-').ok_or_else(bad_value)?;
+    let start = if start == "0" { 0 } else { parse_size(start)? as u64 };
+    let end = if end.is_empty() { u64::MAX } else { parse_size(end)? as u64 };
+    let overhead_percent = percent.parse::<u16>().map_err(|_| bad_value())?;
+    return Ok(Protection { range: start..end, overhead_percent });
+}
+
+/// Parses a peer's socket address, which is all a peer exchange takes.
+fn parse_peer(value: &str) -> Result<String, String> {
+    match value.parse::<SocketAddr>() {
+        Ok(_) => return Ok(value.to_string()),
+        Err(_) => return Err(format!("{} is not a host:port socket address", value)),
+    }
+}
+
+/// Creates a catalog of a directory, bundling and placing its files' encoding as the flags say.
+fn new_catalog(args: &ServeArgs, root: PathBuf, config: EncoderConfig) -> Result<Catalog, String> {
+    let mut catalog = Catalog::new(root, config);
+    catalog.set_bundling(args.bundle_dirs);
+    if let Some(policy) = args.encode_placement.clone() {
+        match EncodePlacement::new(policy, args.encode_threads_per_node) {
+            Ok(placement) => catalog.set_placement(placement),
+            Err(error) => return Err(format!("failed to place encoding: {}", error)),
+        }
+    }
+    return Ok(catalog);
+}
+
+/// What can be changed while serving.
+#[derive(Clone, Debug, PartialEq)]
+struct Settings {
+    session_ttl: Duration,
+    limits: CatalogLimits,
+    bandwidth: Option<u64>,
+    protection: Vec<Protection>,
+    peers: Vec<String>,
+}
+
+/// Gets the settings from the flags, overridden by the config file if any.
+fn load_settings(args: &ServeArgs) -> Result<Settings, String> {
+    let mut settings = Settings {
+        session_ttl: Duration::from_secs(args.session_ttl_secs),
+        limits: CatalogLimits {
+            max_objects: args.tenant_max_objects,
+            max_storage: args.tenant_max_storage.map(|x| x as u64),
+        },
+        bandwidth: args.tenant_max_bandwidth.map(|x| x as u64),
+        protection: args.protect.clone(),
+        peers: args.peers.clone(),
+    };
+    let path = match args.config.as_ref() {
+        None => return Ok(settings),
+        Some(path) => path,
+    };
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+    };
+
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad_line = || format!("{}:{}: expected key = value, got {}", path.display(), line_number + 1, line);
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return Err(bad_line()),
+        };
+        let size = || if value == "none" { Ok(None) } else { parse_size(value).map(|x| Some(x as u64)) };
+        let list = || if value == "none" { Vec::new() } else { value.split(',').map(|x| x.trim()).collect() };
+        match key {
+            "session_ttl_secs" => match value.parse() {
+                Ok(secs) => settings.session_ttl = Duration::from_secs(secs),
+                Err(_) => return Err(bad_line()),
+            },
+            "tenant_max_objects" => settings.limits.max_objects = size()?.map(|x| x as usize),
+            "tenant_max_storage" => settings.limits.max_storage = size()?,
+            "tenant_max_bandwidth" => settings.bandwidth = size()?,
+            "protect" => settings.protection = list().into_iter().map(parse_protection).collect::<Result<_, _>>()?,
+            "peers" => settings.peers = list().into_iter().map(parse_peer).collect::<Result<_, _>>()?,
+            _ => return Err(format!("{}:{}: unknown setting {}", path.display(), line_number + 1, key)),
+        }
+    }
+
+    if !settings.peers.is_empty() && args.peer_exchange_ttl_secs.is_none() {
+        return Err("peers need --peer-exchange-ttl-secs".to_string());
+    }
+    return Ok(settings);
+}
+
+/// Applies settings to the catalogs and tenants being served and to the peer exchange if any. Overheads take effect
+/// at the next refresh of each catalog.
+fn apply_settings(settings: &Settings, catalogs: &[(String, Arc<Catalog>)], tenants: &[Arc<Tenant>], exchange: Option<&PeerExchange>) {
+    for (_, catalog) in catalogs.iter() {
+        catalog.set_protection(settings.protection.clone());
+    }
+    for tenant in tenants.iter() {
+        tenant.get_catalog().set_limits(settings.limits);
+        tenant.set_bandwidth_limit(settings.bandwidth);
+    }
+    if let Some(exchange) = exchange {
+        for addr in exchange.set_fixed_peers(&settings.peers) {
+            eprintln!("not telling of peer {}, too many peers", addr);
+        }
+    }
+}
+
+/// Sets a flag whenever the process gets a SIGHUP.
+#[cfg(unix)]
+fn watch_sighup() -> Result<Arc<AtomicBool>, String> {
+    let hangup = Arc::new(AtomicBool::new(false));
+    if let Err(error) = signal_hook::flag::register(signal_hook::consts::SIGHUP, hangup.clone()) {
+        return Err(format!("failed to handle SIGHUP: {}", error));
+    }
+    return Ok(hangup);
+}
+
+#[cfg(not(unix))]
+fn watch_sighup() -> Result<Arc<AtomicBool>, String> {
+    return Ok(Arc::new(AtomicBool::new(false)));
+}
+
+/// Watches everything under root, sending on the returned channel whenever something changes. The watcher stops
+/// once dropped.
+fn watch_root(root: &Path) -> Result<(RecommendedWatcher, Receiver<()>), String> {
+    let (changed, receiver) = mpsc::channel();
+    let handler = move |event: notify::Result<notify::Event>| {
+        // reading files while encoding them is an access, which would rescan forever; errors, e.g. events lost to
+        // an overflowing queue, may hide changes so they rescan too
+        if !event.is_ok_and(|x| x.kind.is_access()) {
+            let _ = changed.send(());
+        }
+    };
+    let mut watcher = match notify::recommended_watcher(handler) {
+        Ok(watcher) => watcher,
+        Err(error) => return Err(format!("failed to watch {}: {}", root.display(), error)),
+    };
+    if let Err(error) = watcher.watch(root, RecursiveMode::Recursive) {
+        return Err(format!("failed to watch {}: {}", root.display(), error));
+    }
+    return Ok((watcher, receiver));
+}
+
+/// Sends the datagrams of broadcast to addr at rate datagrams per second, on a thread of its own.
+fn start_broadcast(addr: &str, rate: u64) -> Result<Arc<Mutex<CatalogBroadcast>>, String> {
+    let addr: SocketAddr = match addr.to_socket_addrs().map(|mut x| x.next()) {
+        Ok(Some(addr)) => addr,
+        Ok(None) => return Err(format!("{} has no address", addr)),
+        Err(error) => return Err(format!("failed to resolve {}: {}", addr, error)),
+    };
+    let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = match bind_udp(local, AddressFamily::Any) {
+        Ok(socket) => socket,
+        Err(error) => return Err(format!("failed to bind a socket to broadcast to {}: {}", addr, error)),
+    };
+
+    let broadcast = Arc::new(Mutex::new(CatalogBroadcast::new(Integrity::Crc32c)));
+    let sending = broadcast.clone();
+    std::thread::spawn(move || {
+        let started = Instant::now();
+        let mut last_due: u64 = 0;
+        let mut queue = SendQueue::new();
+        loop {
+            std::thread::sleep(BROADCAST_TICK);
+            // an empty carousel queues nothing, and doesn't make up for it with a burst later
+            let due = (rate as u128 * started.elapsed().as_millis() / 1000) as u64;
+            sending.lock().unwrap().fill(&mut queue, addr, (due - last_due) as usize);
+            last_due = due;
+            if let Err(error) = queue.send_to(&socket) {
+                eprintln!("failed to broadcast to {}: {}", addr, error);
+            }
+        }
+    });
+    return Ok(broadcast);
+}
+
+/// Makes broadcast cycle the objects of catalogs, printing what changed.
+fn sync_broadcast(broadcast: &Mutex<CatalogBroadcast>, catalogs: &[(String, Arc<Catalog>)]) {
+    let entries: Vec<_> = catalogs.iter().flat_map(|(_, catalog)| catalog.list()).collect();
+    let changes = broadcast.lock().unwrap().sync(&entries);
+    if !changes.added.is_empty() || !changes.removed.is_empty() {
+        println!(
+            "broadcasting {} objects over udp, {} added, {} removed",
+            broadcast.lock().unwrap().get_carousel().get_flows().len(), changes.added.len(), changes.removed.len(),
+        );
+    }
+}
+
+/// Prints catalog changes, names prefixed with the tenant they belong to if any.
+fn print_changes(prefix: &str, changes: &CatalogChanges) {
+    for name in changes.added.iter() {
+        println!("added {}{}", prefix, name);
+    }
+    for name in changes.updated.iter() {
+        println!("updated {}{}", prefix, name);
+    }
+    for name in changes.removed.iter() {
+        println!("removed {}{}", prefix, name);
+    }
+    for name in changes.over_limit.iter() {
+        println!("over limit, not served {}{}", prefix, name);
+    }
+    for name in changes.reprotected.iter() {
+        println!("reprotected {}{}", prefix, name);
+    }
+}
+
+/// Names the readiness check of the catalog whose changes are printed with prefix.
+fn readiness_check(prefix: &str) -> String {
+    match prefix.strip_suffix('/') {
+        None => return "catalog".to_string(),
+        Some(tenant) => return format!("catalog of tenant {}", tenant),
+    }
+}
+
+/// Tells systemd about state, if it started us. Failing to is not worth stopping for.
+fn notify(state: &str) {
+    if let Err(error) = notify_systemd(state) {
+        eprintln!("failed to notify systemd: {}", error);
+    }
+}
+
+/// Creates a tenant for each directory directly under root.
+fn load_tenants(args: &ServeArgs, config: EncoderConfig) -> Result<Vec<Arc<Tenant>>, String> {
+    let dir_entries = match fs::read_dir(&args.root) {
+        Ok(dir_entries) => dir_entries,
+        Err(error) => return Err(format!("failed to list {}: {}", args.root.display(), error)),
+    };
+
+    let mut tenants: Vec<Arc<Tenant>> = Vec::new();
+    for dir_entry in dir_entries.flatten() {
+        let name = match dir_entry.file_name().into_string() {
+            Ok(name) if !name.starts_with('.') && dir_entry.path().is_dir() => name,
+            _ => continue,
+        };
+        tenants.push(Arc::new(Tenant::new(&name, Arc::new(new_catalog(args, dir_entry.path(), config)?))));
+    }
+
+    return Ok(tenants);
+}
+
+/// Refills the symbol pools of catalogs on a thread of its own, printing how they did every report_interval while
+/// they serve requests.
+fn refill_pools(catalogs: Vec<(String, Arc<Catalog>)>, settings: PoolSettings, report_interval: Duration) {
+    std::thread::spawn(move || {
+        let mut last_report = Instant::now();
+        let mut reported: Vec<PoolStats> = vec![PoolStats::default(); catalogs.len()];
+        loop {
+            std::thread::sleep(settings.refill_interval);
+            for (_, catalog) in catalogs.iter() {
+                catalog.refill_pools(&settings);
+            }
+            if last_report.elapsed() < report_interval {
+                continue;
+            }
+            last_report = Instant::now();
+            for ((prefix, catalog), reported) in catalogs.iter().zip(reported.iter_mut()) {
+                let stats = catalog.get_pool_stats();
+                if stats.hits + stats.misses != reported.hits + reported.misses {
+                    println!(
+                        "symbol pools of /{}: {:.1}% of {} requests served from pools, {} symbols ({} bytes) pooled",
+                        prefix, stats.get_hit_rate() * 100.0, stats.hits + stats.misses, stats.pooled, stats.pooled_bytes,
+                    );
+                }
+                *reported = stats;
+            }
+        }
+    });
+}
+
+pub fn run(args: ServeArgs) -> Result<(), String> {
+    let mut config = EncoderConfig::new(args.packet_size);
+    if args.shrink_tail {
+        config.tail_strategy = TailStrategy::ShrinkSymbols;
+    }
+    config.huge_pages = args.huge_pages;
+    if let Some(max_block_symbols) = args.plan_cache_dir.as_deref().and_then(load_block_limit) {
+        config.max_block_symbols = max_block_symbols;
+    }
+
+    let mut settings = load_settings(&args)?;
+    let hangup = watch_sighup()?;
+
+    // catalogs to refresh, with the prefix their changes are printed with
+    let mut catalogs: Vec<(String, Arc<Catalog>)> = Vec::new();
+    let mut tenants: Vec<Arc<Tenant>> = Vec::new();
+    let listener = match bind_tcp(&args.listen[..], args.address_family) {
+        Ok(listener) => listener,
+        Err(error) => return Err(format!("failed to listen on {}: {}", args.listen, error)),
+    };
+    let exchange = args.peer_exchange_ttl_secs.map(|x| Arc::new(PeerExchange::new(Duration::from_secs(x))));
+    let bound = if args.tenants {
+        tenants = load_tenants(&args, config)?;
+        for tenant in tenants.iter() {
+            catalogs.push((format!("{}/", tenant.get_name()), tenant.get_catalog().clone()));
+        }
+        HttpServer::from_listener_tenants(listener, tenants.clone())
+    } else {
+        catalogs.push((String::new(), Arc::new(new_catalog(&args, args.root.clone(), config)?)));
+        HttpServer::from_listener(listener, catalogs[0].1.clone())
+    };
+    let mut server = match bound {
+        Ok(server) => server,
+        Err(error) => return Err(format!("failed to listen on {}: {}", args.listen, error)),
+    };
+    apply_settings(&settings, &catalogs, &tenants, exchange.as_deref());
+
+    let readiness = server.get_readiness();
+    for (prefix, _) in catalogs.iter() {
+        readiness.set(&readiness_check(prefix), false);
+    }
+    for (prefix, catalog) in catalogs.iter() {
+        match catalog.refresh() {
+            Ok(changes) => print_changes(prefix, &changes),
+            Err(error) => return Err(format!("failed to load {}/{}: {}", args.root.display(), prefix, error)),
+        }
+        readiness.set(&readiness_check(prefix), true);
+    }
+    let broadcast = match args.udp_carousel.as_ref() {
+        None => None,
+        Some(addr) => {
+            let broadcast = start_broadcast(addr, args.udp_carousel_rate)?;
+            sync_broadcast(&broadcast, &catalogs);
+            println!("broadcasting {} over udp to {}", args.root.display(), addr);
+            Some(broadcast)
+        },
+    };
+    let (watcher, changed) = watch_root(&args.root)?;
+
+    if let Some(path) = args.token_key_file.as_ref() {
+        server.require_tokens(super::token::read_key(path)?);
+    }
+    if let Some(path) = args.purge_key_file.as_ref() {
+        server.accept_purges(super::purge::read_key(path)?);
+    }
+    if let Some(path) = args.manifest_key_file.as_ref() {
+        server.sign_manifests(ManifestKey::new(&super::token::read_key_file(path, "manifest")?));
+    }
+    if let Some(exchange) = exchange.as_ref() {
+        server.exchange_peers(exchange.clone());
+    }
+    if let Some(path) = args.store_dir.as_ref() {
+        match FileStore::new(path) {
+            Ok(store) => server.serve_object_store(Arc::new(store)),
+            Err(error) => return Err(format!("failed to open store {}: {}", path.display(), error)),
+        }
+    }
+    if args.coalesce {
+        server.coalesce_symbols();
+    }
+    server.interleave_symbols(args.interleave_depth as usize);
+    if let Some(symbols_per_block) = args.symbol_pool_size {
+        server.pool_symbols();
+        let settings = PoolSettings {
+            symbols_per_block,
+            hot_requests_per_sec: args.symbol_pool_hot_rate,
+            refill_interval: Duration::from_millis(args.symbol_pool_refill_ms),
+        };
+        refill_pools(catalogs.clone(), settings, Duration::from_secs(args.reload_secs));
+    }
+    match server.local_addr() {
+        Ok(addr) => {
+            println!("serving {} on http://{}", args.root.display(), addr);
+            notify(&format!("READY=1\nSTATUS=serving {} on http://{}", args.root.display(), addr));
+        },
+        Err(error) => return Err(format!("failed to listen on {}: {}", args.listen, error)),
+    }
+
+    // notifications for changes, and a full rescan now and then for what file systems don't notify of
+    let reload_interval = Duration::from_secs(args.reload_secs);
+    let debounce = Duration::from_millis(args.watch_debounce_ms);
+    // systemd recommends pinging at half the interval it gives up after
+    let watchdog_interval = get_watchdog_interval().map(|x| x / 2);
+    std::thread::spawn(move || {
+        let _watcher = watcher;
+        let mut last_reload = Instant::now();
+        let mut last_ping = Instant::now();
+        // when the latest change not rescanned yet was notified
+        let mut last_change: Option<Instant> = None;
+        loop {
+            if changed.recv_timeout(SIGHUP_POLL_INTERVAL).is_ok() {
+                while changed.try_recv().is_ok() {}
+                last_change = Some(Instant::now());
+            }
+            let settled = last_change.is_some_and(|x| x.elapsed() >= debounce);
+            if watchdog_interval.is_some_and(|x| last_ping.elapsed() >= x) {
+                notify("WATCHDOG=1");
+                last_ping = Instant::now();
+            }
+            if hangup.swap(false, Ordering::Relaxed) {
+                match load_settings(&args) {
+                    Ok(reloaded) => {
+                        settings = reloaded;
+                        apply_settings(&settings, &catalogs, &tenants, exchange.as_deref());
+                        println!("reloaded settings {:?}", settings);
+                    },
+                    Err(error) => eprintln!("failed to reload settings, keeping the old ones: {}", error),
+                }
+            } else if !settled && last_reload.elapsed() < reload_interval {
+                continue;
+            }
+            last_reload = Instant::now();
+            last_change = None;
+
+            for (prefix, catalog) in catalogs.iter() {
+                // a catalog that can't be reloaded may be serving files that are gone
+                match catalog.refresh() {
+                    Ok(changes) => {
+                        print_changes(prefix, &changes);
+                        readiness.set(&readiness_check(prefix), true);
+                    },
+                    Err(error) => {
+                        eprintln!("failed to reload {}/{}: {}", args.root.display(), prefix, error);
+                        readiness.set(&readiness_check(prefix), false);
+                    },
+                }
+
+                let reaped = catalog.expire_idle_sessions(settings.session_ttl);
+                if reaped > 0 {
+                    let (open_sessions, stats) = catalog.get_session_stats();
+                    println!(
+                        "reaped {} idle sessions of /{}, {} open, {} opened, {} closed, {} reaped in total",
+                        reaped, prefix, open_sessions, stats.opened, stats.closed, stats.reaped,
+                    );
+                }
+                if args.evict_idle {
+                    let evicted = catalog.evict_idle_encoders();
+                    if evicted > 0 {
+                        println!("evicted {} idle encoders of /{}, {} bytes held", evicted, prefix, catalog.get_memory_usage());
+                    }
+                }
+            }
+            if let Some(broadcast) = broadcast.as_ref() {
+                sync_broadcast(broadcast, &catalogs);
+            }
+        }
+    });
+
+    if let Err(error) = server.run() {
+        return Err(format!("server failed: {}", error));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: ServeArgs,
+    }
+
+    #[test]
+    fn test_reload_settings() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-serve-reload-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/object"), vec![1; 5000]).unwrap();
+        let config_path = root.join(".config");
+        fs::write(&config_path, "session_ttl_secs = 60\n").unwrap();
+
+        let args = Cli::parse_from([
+            "serve", "--root", root.to_str().unwrap(), "--tenants", "--tenant-max-bandwidth", "1M", "--protect", "0-:5",
+            "--peer-exchange-ttl-secs", "60", "--peers", "10.0.0.1:80", "--config", config_path.to_str().unwrap(),
+        ]).args;
+        let tenants = load_tenants(&args, EncoderConfig::new(1280)).unwrap();
+        let catalogs: Vec<(String, Arc<Catalog>)> = tenants.iter().map(|x| (x.get_name().to_string(), x.get_catalog().clone())).collect();
+        let exchange = PeerExchange::new(Duration::from_secs(60));
+        let settings = load_settings(&args).unwrap();
+        assert_eq!(settings.session_ttl, Duration::from_secs(60));
+        apply_settings(&settings, &catalogs, &tenants, Some(&exchange));
+        catalogs[0].1.refresh().unwrap();
+        let entry = catalogs[0].1.list().remove(0);
+        let session_id = entry.producer.lock().unwrap().open_session();
+        assert_eq!(entry.manifest.block_overheads, vec![5]);
+        assert_eq!(tenants[0].get_bandwidth_limit().get_rate(), Some(1 << 20));
+        assert_eq!(exchange.get_fixed_peers(), vec!["10.0.0.1:80".to_string()]);
+
+        // a reload changes limits, overheads and peers, keeping open sessions
+        fs::write(&config_path, "tenant_max_bandwidth = none\nprotect = 0-1K:30, 1K-:10\npeers = 10.0.0.2:80,[::1]:80\n").unwrap();
+        apply_settings(&load_settings(&args).unwrap(), &catalogs, &tenants, Some(&exchange));
+        assert_eq!(catalogs[0].1.refresh().unwrap().reprotected, vec!["object".to_string()]);
+        let entry = catalogs[0].1.list().remove(0);
+        assert_eq!(entry.manifest.block_overheads, vec![30]);
+        assert!(entry.producer.lock().unwrap().next_symbols(session_id, 1).is_ok());
+        assert_eq!(tenants[0].get_bandwidth_limit().get_rate(), None);
+        assert_eq!(exchange.get_fixed_peers(), vec!["10.0.0.2:80".to_string(), "[::1]:80".to_string()]);
+
+        // a bad file is refused as a whole
+        fs::write(&config_path, "peers = cache.example:80\n").unwrap();
+        assert!(load_settings(&args).is_err());
+        fs::write(&config_path, "protect = 0-1K\n").unwrap();
+        assert!(load_settings(&args).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
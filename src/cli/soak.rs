@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use rand::{thread_rng, Rng};
+
+use raptor_cdn::codec::consts::*;
+use raptor_cdn::codec::decoder::RaptorQDecoder;
+use raptor_cdn::codec::encoder::RaptorQEncoder;
+use raptor_cdn::codec::producer::SymbolProducer;
+use raptor_cdn::sim::channel::LossyChannel;
+
+#[derive(Args)]
+pub struct SoakArgs {
+    /// How long to run for, in seconds.
+    #[arg(long, default_value_t = 3600)]
+    duration_secs: u64,
+    /// Largest object to encode, in bytes.
+    #[arg(long, default_value_t = 4 * 1024 * 1024)]
+    max_size: usize,
+    /// Largest packet loss to simulate, in percent.
+    #[arg(long, default_value_t = 20)]
+    max_loss: u32,
+    /// How often to report resource usage, in seconds.
+    #[arg(long, default_value_t = 10)]
+    report_secs: u64,
+    /// Fail if resident memory grows by more than this many MiB over the first report.
+    #[arg(long)]
+    max_rss_growth_mib: Option<u64>,
+}
+
+/// Resource usage of this process, where the platform exposes it.
+struct Usage {
+    rss_kib: Option<u64>,
+    open_fds: Option<usize>,
+}
+
+impl Usage {
+    fn sample() -> Usage {
+        let rss_kib = std::fs::read_to_string("/proc/self/status").ok().and_then(|status| {
+            let line = status.lines().find(|x| x.starts_with("VmRSS:"))?;
+            return line.split_whitespace().nth(1)?.parse().ok();
+        });
+        let open_fds = std::fs::read_dir("/proc/self/fd").ok().map(|x| x.count());
+
+        return Usage { rss_kib, open_fds };
+    }
+}
+
+fn format_option<T: std::fmt::Display>(value: Option<T>) -> String {
+    match value {
+        None => return "n/a".to_string(),
+        Some(value) => return value.to_string(),
+    }
+}
+
+/// Encodes and decodes one random object through a SymbolProducer session with random loss.
+fn soak_once(args: &SoakArgs) -> Result<usize, String> {
+    let mut rng = thread_rng();
+    let data_size = rng.gen_range(1..=args.max_size);
+    let packet_size = rng.gen_range((MIN_PACKET_SIZE / ALIGNMENT as u16)..=(1500 / ALIGNMENT as u16)) * ALIGNMENT as u16;
+    let mut channel = LossyChannel::new(rng.gen_range(0..=args.max_loss) as f64 / 100.0);
+
+    let data: Vec<u8> = (0..data_size).map(|_| rng.gen()).collect();
+    let encoder = match RaptorQEncoder::new(packet_size, &data) {
+        Ok(encoder) => encoder,
+        Err(error) => return Err(format!("failed to create encoder: {:?}", error)),
+    };
+    let mut decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+        Ok(decoder) => decoder,
+        Err(error) => return Err(format!("failed to create decoder: {:?}", error)),
+    };
+
+    let symbol_count = data_size.div_ceil(packet_size as usize);
+    let mut producer = SymbolProducer::new(encoder);
+    let session_id = producer.open_session();
+
+    let mut symbols_sent: usize = 0;
+    loop {
+        let blocks = match producer.next_symbols(session_id, 64) {
+            Ok(blocks) => blocks,
+            Err(error) => return Err(format!("failed to produce symbols: {:?}", error)),
+        };
+        symbols_sent += blocks.len();
+
+        match decoder.consume(channel.transmit(blocks)) {
+            Ok(true) => break,
+            Ok(false) => (),
+            Err(error) => return Err(format!("failed to decode: {:?}", error)),
+        }
+
+        if symbols_sent > 4 * symbol_count + 1024 {
+            return Err(format!("no decode after {} symbols for {} source symbols", symbols_sent, symbol_count));
+        }
+    }
+
+    if let Err(error) = producer.close_session(session_id) {
+        return Err(format!("failed to close session: {:?}", error));
+    }
+    if decoder.get_result().as_deref() != Some(&data[..]) {
+        return Err(format!("recovered data mismatch, size {} packet size {}", data_size, packet_size));
+    }
+
+    return Ok(data_size);
+}
+
+pub fn run(args: SoakArgs) -> Result<(), String> {
+    if args.max_size == 0 {
+        return Err("max size must be at least 1 byte".to_string());
+    }
+    if args.max_loss >= 100 {
+        return Err("max loss must be below 100%".to_string());
+    }
+
+    let start = Instant::now();
+    let duration = Duration::from_secs(args.duration_secs);
+    let report_interval = Duration::from_secs(args.report_secs);
+    let mut next_report = start + report_interval;
+
+    let mut baseline_rss_kib: Option<u64> = None;
+    let mut objects: u64 = 0;
+    let mut bytes: u64 = 0;
+
+    while start.elapsed() < duration {
+        bytes += soak_once(&args)? as u64;
+        objects += 1;
+
+        if Instant::now() >= next_report {
+            next_report += report_interval;
+            let usage = Usage::sample();
+            println!(
+                "{:>6}s objects {} bytes {} rss_kib {} open_fds {}",
+                start.elapsed().as_secs(),
+                objects,
+                bytes,
+                format_option(usage.rss_kib),
+                format_option(usage.open_fds),
+            );
+
+            if let (Some(max_growth_mib), Some(rss_kib)) = (args.max_rss_growth_mib, usage.rss_kib) {
+                let baseline = *baseline_rss_kib.get_or_insert(rss_kib);
+                if rss_kib > baseline + max_growth_mib * 1024 {
+                    return Err(format!("resident memory grew from {} KiB to {} KiB", baseline, rss_kib));
+                }
+            }
+        }
+    }
+
+    println!("soak passed: {} objects, {} bytes in {}s", objects, bytes, start.elapsed().as_secs());
+    return Ok(());
+}
@@ -0,0 +1,48 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Args;
+
+use raptor_cdn::codec::manifest::parse_object_id;
+use raptor_cdn::server::token::TokenKey;
+
+#[derive(Args)]
+pub struct TokenArgs {
+    /// File holding the key the server checks tokens with.
+    #[arg(long)]
+    key_file: PathBuf,
+    /// Id of the object the token grants access to.
+    #[arg(long)]
+    object: String,
+    /// Tenant whose copy of the object the token grants access to, on a server started with --tenants.
+    #[arg(long)]
+    tenant: Option<String>,
+    /// How long the token is valid for, in seconds.
+    #[arg(long, default_value_t = 3600)]
+    ttl_secs: u64,
+}
+
+pub fn read_key(path: &Path) -> Result<TokenKey, String> {
+    return Ok(TokenKey::new(&read_key_file(path, "token")?));
+}
+
+/// Reads a key file of any kind, e.g. "token", refusing empty ones.
+pub fn read_key_file(path: &Path, kind: &str) -> Result<Vec<u8>, String> {
+    match fs::read(path) {
+        Ok(key) if key.is_empty() => return Err(format!("{} key file {} is empty", kind, path.display())),
+        Ok(key) => return Ok(key),
+        Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+    }
+}
+
+pub fn run(args: TokenArgs) -> Result<(), String> {
+    let object_id = match parse_object_id(&args.object) {
+        Some(object_id) => object_id,
+        None => return Err(format!("{} is not an object id", args.object)),
+    };
+
+    let key = read_key(&args.key_file)?;
+    println!("{}", key.sign(args.tenant.as_deref(), &object_id, Duration::from_secs(args.ttl_secs)));
+    return Ok(());
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Args)]
+pub struct TopArgs {
+    /// Socket of the daemon to watch, see the daemon command.
+    #[arg(long, default_value = "raptor-cdn.sock")]
+    socket: PathBuf,
+    /// How often to ask the daemon, in milliseconds.
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(100..))]
+    interval_ms: u64,
+}
+
+/// The parts of the daemon's status shown.
+#[derive(Deserialize)]
+struct DaemonStatus {
+    uptime_secs: f64,
+    requests: u64,
+    failed_requests: u64,
+    objects_fetched: u64,
+    cached_plans: usize,
+    plan_hits: u64,
+    plan_generations: u64,
+}
+
+/// A transfer as the daemon's transfers method reports it.
+#[derive(Deserialize)]
+struct TransferView {
+    transfer_id: u64,
+    object_id: String,
+    data_size: u64,
+    priority: i32,
+    state: String,
+    error: Option<String>,
+    block_count: usize,
+    blocks_decoded: usize,
+    bytes_received: u64,
+    peers: BTreeMap<String, u64>,
+}
+
+/// What the daemon said at one refresh.
+struct Sample {
+    at: Instant,
+    status: DaemonStatus,
+    transfers: Vec<TransferView>,
+}
+
+impl Sample {
+    /// Gets the bytes received from each peer over all transfers.
+    fn get_peer_bytes(&self) -> BTreeMap<&str, u64> {
+        let mut bytes: BTreeMap<&str, u64> = BTreeMap::new();
+        for (addr, received) in self.transfers.iter().flat_map(|x| x.peers.iter()) {
+            *bytes.entry(&addr[..]).or_default() += received;
+        }
+        return bytes;
+    }
+}
+
+/// The last two samples, to tell rates from, and the last error asking for one.
+struct Top {
+    socket: PathBuf,
+    next_id: u64,
+    current: Option<Sample>,
+    previous: Option<Sample>,
+    error: Option<String>,
+}
+
+impl Top {
+    fn new(socket: PathBuf) -> Top {
+        return Top { socket, next_id: 0, current: None, previous: None, error: None };
+    }
+
+    fn call(&mut self, method: &str) -> io::Result<Value> {
+        self.next_id += 1;
+        return call(&self.socket, self.next_id, method);
+    }
+
+    /// Takes a new sample, keeping the last one if the daemon doesn't answer.
+    fn refresh(&mut self) {
+        let sample = self.call("status").and_then(|status| {
+            let transfers = self.call("transfers")?;
+            return Ok(Sample { at: Instant::now(), status: serde_json::from_value(status)?, transfers: serde_json::from_value(transfers)? });
+        });
+        match sample {
+            Ok(sample) => {
+                self.previous = self.current.replace(sample);
+                self.error = None;
+            },
+            Err(error) => self.error = Some(error.to_string()),
+        }
+    }
+
+    /// Gets bytes per second from a count in the current sample and the same count in the previous one, if any.
+    fn get_rate(&self, current: u64, previous: Option<u64>) -> Option<f64> {
+        let elapsed = self.current.as_ref()?.at.duration_since(self.previous.as_ref()?.at);
+        return previous.map(|x| current.saturating_sub(x) as f64 / elapsed.as_secs_f64().max(1e-3));
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let sample = match self.current.as_ref() {
+            Some(sample) => sample,
+            None => return,
+        };
+        let [header, transfers, peers] = Layout::vertical([Constraint::Length(5), Constraint::Fill(2), Constraint::Fill(1)]).areas(frame.area());
+        let status = &sample.status;
+        let running = sample.transfers.iter().filter(|x| x.state == "running").collect::<Vec<&TransferView>>();
+        let queued = sample.transfers.iter().filter(|x| x.state == "queued").count();
+        let decoder_queue: usize = running.iter().map(|x| x.block_count - x.blocks_decoded).sum();
+        let lookups = status.plan_hits + status.plan_generations;
+        let lines = vec![
+            Line::from(format!(
+                "uptime {}   requests {} ({} failed)   objects fetched {}",
+                format_duration(status.uptime_secs), status.requests, status.failed_requests, status.objects_fetched,
+            )),
+            Line::from(format!(
+                "transfers {} running, {} queued   decoder queue {} blocks   plan cache {} hit of {} lookups, {} plans",
+                running.len(), queued, decoder_queue, format_percent(status.plan_hits, lookups), lookups, status.cached_plans,
+            )),
+            match self.error.as_ref() {
+                Some(error) => Line::styled(format!("{}: {}", self.socket.display(), error), Style::new().add_modifier(Modifier::REVERSED)),
+                None => Line::from("q to quit"),
+            },
+        ];
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" raptor-cdn top ")), header);
+
+        let previous = |id: u64| self.previous.as_ref().and_then(|x| x.transfers.iter().find(|x| x.transfer_id == id)).map(|x| x.bytes_received);
+        let rows: Vec<Row> = sample.transfers.iter().map(|x| Row::new(vec![
+            x.transfer_id.to_string(),
+            x.object_id.chars().take(16).collect(),
+            x.error.as_ref().map_or(x.state.clone(), |error| format!("{}: {}", x.state, error)),
+            x.priority.to_string(),
+            format!("{}/{} blocks", x.blocks_decoded, x.block_count),
+            format!("{} of {}", format_bytes(x.bytes_received), format_bytes(x.data_size)),
+            format_rate(self.get_rate(x.bytes_received, previous(x.transfer_id))),
+        ])).collect();
+        let widths = [Constraint::Length(6), Constraint::Length(17), Constraint::Fill(1), Constraint::Length(8), Constraint::Length(16), Constraint::Length(22), Constraint::Length(12)];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["id", "object", "state", "priority", "decoded", "received", "rate"]).style(Style::new().add_modifier(Modifier::BOLD)))
+            .block(Block::bordered().title(" transfers "));
+        frame.render_widget(table, transfers);
+
+        let previous_bytes = self.previous.as_ref().map(|x| x.get_peer_bytes()).unwrap_or_default();
+        let rows: Vec<Row> = sample.get_peer_bytes().into_iter().map(|(addr, bytes)| Row::new(vec![
+            addr.to_string(),
+            sample.transfers.iter().filter(|x| x.peers.contains_key(addr)).count().to_string(),
+            format_bytes(bytes),
+            format_rate(self.get_rate(bytes, previous_bytes.get(addr).copied())),
+        ])).collect();
+        let widths = [Constraint::Fill(1), Constraint::Length(10), Constraint::Length(12), Constraint::Length(12)];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["peer", "transfers", "received", "rate"]).style(Style::new().add_modifier(Modifier::BOLD)))
+            .block(Block::bordered().title(" peers "));
+        frame.render_widget(table, peers);
+    }
+}
+
+/// Sends a request without params to the daemon on socket, returning its result.
+fn call(socket: &Path, id: u64, method: &str) -> io::Result<Value> {
+    let mut stream = UnixStream::connect(socket)?;
+    writeln!(stream, "{}", json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": {} }))?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let mut response: Value = serde_json::from_str(&line)?;
+    if let Some(error) = response.get("error") {
+        let message = error.get("message").and_then(|x| x.as_str()).unwrap_or("unknown error");
+        return Err(io::Error::other(format!("{} failed: {}", method, message)));
+    }
+    return Ok(response["result"].take());
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < units.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    return match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, units[unit]),
+    };
+}
+
+fn format_rate(bytes_per_sec: Option<f64>) -> String {
+    return bytes_per_sec.map_or("-".to_string(), |x| format!("{}/s", format_bytes(x as u64)));
+}
+
+fn format_percent(part: u64, whole: u64) -> String {
+    if whole == 0 {
+        return "-".to_string();
+    }
+    return format!("{:.1}%", part as f64 * 100.0 / whole as f64);
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs as u64;
+    return format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+}
+
+/// Draws and refreshes until q, Esc or Ctrl-C.
+fn watch(terminal: &mut DefaultTerminal, top: &mut Top, interval: Duration) -> io::Result<()> {
+    let mut refreshed = Instant::now();
+    loop {
+        terminal.draw(|frame| top.draw(frame))?;
+        if event::poll(interval.saturating_sub(refreshed.elapsed()))? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)) {
+                    return Ok(());
+                }
+            }
+        }
+        if refreshed.elapsed() >= interval {
+            top.refresh();
+            refreshed = Instant::now();
+        }
+    }
+}
+
+pub fn run(args: TopArgs) -> Result<(), String> {
+    // fail before taking over the terminal if there is no daemon to watch
+    let mut top = Top::new(args.socket);
+    top.refresh();
+    if let Some(error) = top.error.take() {
+        return Err(format!("failed to reach the daemon at {}: {}", top.socket.display(), error));
+    }
+
+    let mut terminal = ratatui::init();
+    let result = watch(&mut terminal, &mut top, Duration::from_millis(args.interval_ms));
+    ratatui::restore();
+    return result.map_err(|error| format!("terminal failed: {}", error));
+}
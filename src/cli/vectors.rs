@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use serde::Serialize;
+
+use raptor_cdn::codec::vectors::{get_default_specs, run_vectors, write_vectors};
+use super::print_json;
+
+#[derive(Args)]
+pub struct VectorsArgs {
+    /// Directory of test vectors, e.g. spec/vectors/v1.
+    dir: PathBuf,
+    /// Generate the vectors into the directory instead of checking them, e.g. after a deliberate format change.
+    #[arg(long)]
+    generate: bool,
+    /// Print the results as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct VectorReport {
+    name: String,
+    failures: Vec<String>,
+}
+
+pub fn run(args: VectorsArgs) -> Result<(), String> {
+    if args.generate {
+        let specs = get_default_specs();
+        if let Err(error) = write_vectors(&args.dir, &specs) {
+            return Err(format!("failed to generate vectors into {}: {}", args.dir.display(), error));
+        }
+        println!("generated {} vectors into {}", specs.len(), args.dir.display());
+        return Ok(());
+    }
+
+    let results = match run_vectors(&args.dir) {
+        Ok(results) => results,
+        Err(error) => return Err(format!("failed to read vectors from {}: {}", args.dir.display(), error)),
+    };
+    let failed = results.iter().filter(|x| !x.1.is_empty()).count();
+    if args.json {
+        let reports: Vec<VectorReport> = results.iter().map(|(name, failures)| VectorReport {
+            name: name.clone(),
+            failures: failures.iter().map(|x| format!("{:?}", x)).collect(),
+        }).collect();
+        print_json(&reports, false)?;
+    } else {
+        for (name, failures) in results.iter() {
+            if failures.is_empty() {
+                println!("{}: ok", name);
+            } else {
+                let failures: Vec<String> = failures.iter().map(|x| format!("{:?}", x)).collect();
+                println!("{}: {}", name, failures.join(", "));
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} of {} vectors fail", failed, results.len()));
+    }
+    return Ok(());
+}
@@ -0,0 +1,346 @@
+//! Checking another implementation of the formats and HTTP endpoints against this one, both ways: what it encodes
+//! has to decode here, and what is encoded here, e.g. the golden vectors of codec::vectors, has to decode there.
+//!
+//! An implementation is reached either through a directory of its results or through a server's endpoints. The
+//! directory has a subdirectory for each golden vector it took part in, named after it, holding any of:
+//! - manifest and packets: what it encoded from the vector's input and spec, in the formats of the vector's files.
+//! - decoded: what it decoded from the vector's manifest and packets.
+//!
+//! A server is asked for the objects it lists, which are decoded here and encoded again to compare. Servers only
+//! serve, so what is encoded here can't be checked against them.
+
+use raptorq::extended_source_block_symbols;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, IoSlice};
+use std::path::Path;
+
+use crate::codec::decoder::RaptorQDecoder;
+use crate::codec::encoder::{EncodedBlock, RaptorQEncoder};
+use crate::codec::manifest::{parse_object_id, validate_manifest, DecoderLimits, Manifest};
+use crate::codec::shard::read_shard;
+use crate::codec::vectors::{get_default_specs, TestVector};
+use super::http::{fetch_manifest, fetch_symbols, get};
+
+/// Symbols asked for per request to a server.
+const SYMBOLS_PER_REQUEST: usize = 256;
+
+/// Outcome of a check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// Failed, and why.
+    Fail(String),
+    /// Not run, as the implementation gave nothing to check.
+    Skipped,
+}
+
+impl Verdict {
+    fn from_result(result: Result<(), String>) -> Verdict {
+        return match result {
+            Ok(()) => Verdict::Pass,
+            Err(reason) => Verdict::Fail(reason),
+        };
+    }
+
+    pub fn is_fail(&self) -> bool {
+        return matches!(self, Verdict::Fail(_));
+    }
+}
+
+/// The checks of a vector or object, a row of the compliance matrix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConformanceCase {
+    /// Name of the vector, or id of the object.
+    pub name: String,
+    /// The implementation's manifest is the one encoding the object here gives.
+    pub manifest: Verdict,
+    /// Each of the implementation's symbols is the one with its encoding symbol id here.
+    pub symbols: Verdict,
+    /// What the implementation encoded decodes here.
+    pub theirs_to_ours: Verdict,
+    /// What was encoded here decodes there.
+    pub ours_to_theirs: Verdict,
+}
+
+impl ConformanceCase {
+    fn new(name: String) -> ConformanceCase {
+        return ConformanceCase {
+            name,
+            manifest: Verdict::Skipped,
+            symbols: Verdict::Skipped,
+            theirs_to_ours: Verdict::Skipped,
+            ours_to_theirs: Verdict::Skipped,
+        };
+    }
+
+    /// Whether no check failed. Skipped checks don't count against it.
+    pub fn is_compliant(&self) -> bool {
+        return ![&self.manifest, &self.symbols, &self.theirs_to_ours, &self.ours_to_theirs].iter().any(|x| x.is_fail());
+    }
+}
+
+/// Checks manifest is the one encoding data with its config gives here. Overheads are chosen per object, so they
+/// are taken from manifest.
+fn check_manifest(manifest: &Manifest, encoder: &RaptorQEncoder) -> Result<(), String> {
+    let mut ours = Manifest::new(encoder);
+    ours.block_overheads = manifest.block_overheads.clone();
+    if ours.object_id != manifest.object_id {
+        return Err("object id differs".to_string());
+    }
+    if ours.block_info_vec != manifest.block_info_vec {
+        return Err("block layout differs".to_string());
+    }
+    if ours.block_hashes != manifest.block_hashes {
+        return Err("block hashes differ".to_string());
+    }
+    if ours != *manifest {
+        return Err("manifest differs".to_string());
+    }
+    return Ok(());
+}
+
+/// Checks every symbol is the one with its block and encoding symbol id encoder generates.
+fn check_symbols(symbols: &[EncodedBlock], encoder: &RaptorQEncoder) -> Result<(), String> {
+    if let Some(symbol) = symbols.iter().find(|x| x.block_id as usize >= encoder.get_block_encoders().len()) {
+        return Err(format!("symbol of block {}, which the object does not have", symbol.block_id));
+    }
+    for block_encoder in encoder.get_block_encoders().iter() {
+        let block_id = block_encoder.get_block_info().block_id;
+        let symbol_count = block_encoder.get_symbol_count() as u32;
+        // repair symbols are numbered after the extended source symbols, the ids between are never sent
+        let repair_base = extended_source_block_symbols(symbol_count);
+        let mut source: Option<Vec<EncodedBlock>> = None;
+        for symbol in symbols.iter().filter(|x| x.block_id == block_id) {
+            let esi = symbol.data.payload_id().encoding_symbol_id();
+            let ours = if esi < symbol_count {
+                let source = source.get_or_insert_with(|| block_encoder.generate_source_blocks());
+                source.iter().find(|x| x.data.payload_id().encoding_symbol_id() == esi).cloned()
+            } else if esi >= repair_base {
+                block_encoder.generate_repair_blocks(esi - repair_base, 1).pop()
+            } else {
+                None
+            };
+            if ours.as_ref() != Some(symbol) {
+                return Err(format!("symbol {} of block {} differs", esi, block_id));
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// Decodes symbols with manifest, checking the result against the object id.
+fn decode(manifest: &Manifest, symbols: Vec<EncodedBlock>) -> Result<Vec<u8>, String> {
+    let mut decoder = RaptorQDecoder::from_manifest(manifest).map_err(|error| format!("{:?}", error))?;
+    decoder.consume(symbols).map_err(|error| format!("{:?}", error))?;
+    let data = match decoder.get_result() {
+        Some(data) => data,
+        None => return Err("too few symbols to decode".to_string()),
+    };
+    if Sha256::digest(&data)[..] != manifest.object_id[..] {
+        return Err("decoded object does not match its id".to_string());
+    }
+    return Ok(data);
+}
+
+/// Reads a file of a vector's directory, None if it is not there.
+fn read_optional(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => return Ok(Some(data)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    }
+}
+
+/// Checks the results of an implementation in dir against the golden vectors, see the module documentation.
+/// Returns a case for every golden vector, in order.
+pub fn check_dir(dir: &Path) -> io::Result<Vec<ConformanceCase>> {
+    let mut cases: Vec<ConformanceCase> = Vec::new();
+    for spec in get_default_specs().iter() {
+        let vector = TestVector::generate(spec)?;
+        let vector_dir = dir.join(&spec.name);
+        let mut case = ConformanceCase::new(spec.name.clone());
+
+        if let Some(decoded) = read_optional(&vector_dir.join("decoded"))? {
+            case.ours_to_theirs = Verdict::from_result(match decoded == vector.input {
+                true => Ok(()),
+                false => Err("decoded object differs from the input".to_string()),
+            });
+        }
+
+        let theirs = (read_optional(&vector_dir.join("manifest"))?, read_optional(&vector_dir.join("packets"))?);
+        if let (Some(manifest), Some(packets)) = theirs {
+            let manifest = Manifest::read_from(&manifest[..]).map_err(|error| error.to_string());
+            let symbols = read_shard(&packets[..]).map_err(|error| error.to_string());
+            let config = manifest.as_ref().map(|x| x.config).unwrap_or(spec.config);
+            let encoder = match RaptorQEncoder::with_config(config, &[IoSlice::new(&vector.input)]) {
+                Ok(encoder) => encoder,
+                Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad config in {}: {:?}", vector_dir.display(), error))),
+            };
+            case.manifest = Verdict::from_result(manifest.as_ref().map_err(|x| x.clone()).and_then(|x| check_manifest(x, &encoder)));
+            case.symbols = Verdict::from_result(symbols.as_ref().map_err(|x| x.clone()).and_then(|x| check_symbols(x, &encoder)));
+            case.theirs_to_ours = Verdict::from_result(match (manifest, symbols) {
+                (Ok(manifest), Ok(symbols)) => decode(&manifest, symbols).map(|_| ()),
+                (Err(error), _) | (_, Err(error)) => Err(error),
+            });
+        }
+        cases.push(case);
+    }
+    return Ok(cases);
+}
+
+/// Checks an object served at addr under prefix, e.g. "" or "/tenants/<name>".
+fn check_object(addr: &str, prefix: &str, name: String) -> ConformanceCase {
+    let mut case = ConformanceCase::new(name);
+    let object_id = match parse_object_id(&case.name) {
+        Some(object_id) => object_id,
+        None => {
+            case.theirs_to_ours = Verdict::Fail("listed object id is not hex".to_string());
+            return case;
+        },
+    };
+    let manifest = match fetch_manifest(addr, prefix, &object_id, None) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            case.manifest = Verdict::Fail(error.to_string());
+            return case;
+        },
+    };
+    if let Err(issues) = validate_manifest(&manifest, &DecoderLimits::default()) {
+        case.manifest = Verdict::Fail(format!("{:?}", issues));
+        return case;
+    }
+
+    // as much as every block's source symbols twice, as a well behaved server sends far fewer
+    let symbol_limit: usize = manifest.block_info_vec.iter().map(|x| 2 * x.padded_size / x.config.symbol_size() as usize + 2).sum();
+    let mut decoder = match RaptorQDecoder::from_manifest(&manifest) {
+        Ok(decoder) => decoder,
+        Err(error) => {
+            case.manifest = Verdict::Fail(format!("{:?}", error));
+            return case;
+        },
+    };
+    let mut received: Vec<EncodedBlock> = Vec::new();
+    let mut session_id = None;
+    while !decoder.is_decoded() && received.len() < symbol_limit {
+        let symbols = match fetch_symbols(addr, prefix, &object_id, None, session_id, None, SYMBOLS_PER_REQUEST) {
+            Ok((id, symbols)) => {
+                session_id = Some(id);
+                symbols
+            },
+            Err(error) => {
+                case.theirs_to_ours = Verdict::Fail(error.to_string());
+                return case;
+            },
+        };
+        if symbols.is_empty() {
+            break;
+        }
+        received.extend(symbols.iter().cloned());
+        if let Err(error) = decoder.consume(symbols) {
+            case.theirs_to_ours = Verdict::Fail(format!("{:?}", error));
+            return case;
+        }
+    }
+
+    let data = match decoder.get_result() {
+        Some(data) if Sha256::digest(&data)[..] == object_id[..] => data,
+        Some(_) => {
+            case.theirs_to_ours = Verdict::Fail("decoded object does not match its id".to_string());
+            return case;
+        },
+        None => {
+            case.theirs_to_ours = Verdict::Fail(format!("not decoded from {} symbols", received.len()));
+            return case;
+        },
+    };
+    case.theirs_to_ours = Verdict::Pass;
+    match RaptorQEncoder::with_config(manifest.config, &[IoSlice::new(&data)]) {
+        Ok(encoder) => {
+            case.manifest = Verdict::from_result(check_manifest(&manifest, &encoder));
+            case.symbols = Verdict::from_result(check_symbols(&received, &encoder));
+        },
+        Err(error) => case.manifest = Verdict::Fail(format!("{:?}", error)),
+    }
+    return case;
+}
+
+/// Checks the objects served at addr under prefix, e.g. "" or "/tenants/<name>", by a server with the endpoints of
+/// server::http::HttpServer, up to max_objects of them in the order listed. Returns a case for each object, named
+/// after its id. Fails if the objects can't be listed.
+pub fn check_endpoint(addr: &str, prefix: &str, max_objects: usize) -> io::Result<Vec<ConformanceCase>> {
+    let response = get(addr, &format!("{}/objects", prefix))?;
+    if response.status != 200 {
+        return Err(io::Error::other(format!("listing objects answered {}", response.status)));
+    }
+    let listing = match String::from_utf8(response.body) {
+        Ok(listing) => listing,
+        Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "object listing is not text")),
+    };
+
+    let names = listing.lines().filter_map(|x| x.split_whitespace().next()).take(max_objects);
+    return Ok(names.map(|name| check_object(addr, prefix, name.to_string())).collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoder::EncoderConfig;
+    use crate::codec::shard::write_shard;
+    use crate::server::catalog::Catalog;
+    use crate::server::http::HttpServer;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_conformance() {
+        let dir = std::env::temp_dir().join(format!("raptorcdn-conformance-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // an implementation that encodes and decodes like this one passes, with what it left out skipped
+        let specs = get_default_specs();
+        for spec in specs.iter().skip(1) {
+            let vector = TestVector::generate(spec).unwrap();
+            vector.write_to_dir(&dir).unwrap();
+            fs::write(dir.join(&spec.name).join("decoded"), &vector.input).unwrap();
+        }
+        let cases = check_dir(&dir).unwrap();
+        assert_eq!(cases.len(), specs.len());
+        assert_eq!(cases[0], ConformanceCase::new(specs[0].name.clone()));
+        for case in cases.iter().skip(1) {
+            assert_eq!(vec![&case.manifest, &case.symbols, &case.theirs_to_ours, &case.ours_to_theirs], vec![&Verdict::Pass; 4], "{}", case.name);
+        }
+
+        // one that gets a symbol or the decoding wrong does not
+        let vector_dir = dir.join(&specs[1].name);
+        let mut symbols = read_shard(&fs::read(vector_dir.join("packets")).unwrap()[..]).unwrap();
+        let mut tampered = symbols[0].data.data().to_vec();
+        tampered[0] ^= 1;
+        symbols[0].data = raptorq::EncodingPacket::new(symbols[0].data.payload_id().clone(), tampered);
+        let mut packets: Vec<u8> = Vec::new();
+        write_shard(&mut packets, &symbols).unwrap();
+        fs::write(vector_dir.join("packets"), packets).unwrap();
+        fs::write(vector_dir.join("decoded"), b"not the input").unwrap();
+        let case = &check_dir(&dir).unwrap()[1];
+        assert!(!case.is_compliant());
+        assert_eq!(case.manifest, Verdict::Pass);
+        assert!(case.symbols.is_fail() && case.theirs_to_ours.is_fail() && case.ours_to_theirs.is_fail());
+
+        // a server is checked through the objects it lists
+        let root = dir.join("objects");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("object"), TestVector::generate(&specs[2]).unwrap().input).unwrap();
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.run());
+        let cases = check_endpoint(&addr, "", 16).unwrap();
+        assert_eq!(cases.len(), 1);
+        assert!(cases[0].is_compliant(), "{:?}", cases[0]);
+        assert_eq!(cases[0].theirs_to_ours, Verdict::Pass);
+        assert_eq!(cases[0].ours_to_theirs, Verdict::Skipped);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
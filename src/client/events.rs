@@ -0,0 +1,149 @@
+//! Progress of fetches as it happens, for applications drawing it, e.g. progress bars in a GUI or TUI, without
+//! polling. A Fetch given an EventHub sends it a TransferEvent at each milestone, with a snapshot of its throughput,
+//! and the hub passes each on to every channel subscribed and every callback added. Fetches of a TransferQueue send
+//! theirs to the queue's hub.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::codec::manifest::ObjectId;
+use super::transfers::TransferId;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransferEventKind {
+    /// The fetch is sending its first request.
+    Started,
+    /// The fetch has the manifest, which says how much there is to fetch. Sent right after Started, a Fetch being
+    /// made from a manifest fetched before.
+    ManifestReceived { data_size: u64, block_count: usize },
+    /// A block was decoded and matched its hash.
+    BlockDecoded { block_id: u32 },
+    /// No symbols came for as long as given, e.g. peers failing or slow. Sent once until symbols come again.
+    Stalled { since: Duration },
+    Completed,
+    /// Failed, and why. Cancelled transfers of a TransferQueue fail as "cancelled".
+    Failed(String),
+}
+
+/// How far a fetch got, as of an event.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Throughput {
+    /// Time since the fetch started.
+    pub elapsed: Duration,
+    /// Symbol bytes received, without transport headers.
+    pub bytes_received: u64,
+    pub blocks_decoded: usize,
+    pub block_count: usize,
+    /// Average of bytes_received over elapsed.
+    pub bytes_per_sec: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferEvent {
+    pub object_id: ObjectId,
+    /// The transfer, for fetches of a TransferQueue.
+    pub transfer_id: Option<TransferId>,
+    pub kind: TransferEventKind,
+    pub throughput: Throughput,
+}
+
+/// Called with each event, on the thread of the fetch sending it, so it should return quickly.
+pub type EventCallback = Box<dyn Fn(&TransferEvent) + Send + Sync>;
+
+/// Passes events of fetches on to subscribers, see the module documentation.
+#[derive(Default)]
+pub struct EventHub {
+    senders: Mutex<Vec<Sender<TransferEvent>>>,
+    callbacks: Mutex<Vec<EventCallback>>,
+}
+
+impl EventHub {
+    pub fn new() -> EventHub {
+        return EventHub::default();
+    }
+
+    /// Gets a channel receiving every event sent from now on. Dropping the receiver unsubscribes it.
+    pub fn subscribe(&self) -> Receiver<TransferEvent> {
+        let (sender, receiver) = channel();
+        self.senders.lock().unwrap().push(sender);
+        return receiver;
+    }
+
+    /// Calls callback with every event sent from now on.
+    pub fn add_callback(&self, callback: EventCallback) {
+        self.callbacks.lock().unwrap().push(callback);
+    }
+
+    /// Passes an event on to every subscriber.
+    pub fn send(&self, event: TransferEvent) {
+        for callback in self.callbacks.lock().unwrap().iter() {
+            callback(&event);
+        }
+        self.senders.lock().unwrap().retain(|x| x.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::client::fetch::Fetch;
+    use crate::client::http::fetch_manifest;
+    use crate::codec::encoder::EncoderConfig;
+    use crate::codec::manifest::DecoderLimits;
+    use crate::server::catalog::Catalog;
+    use crate::server::http::HttpServer;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_transfer_events() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-events-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(200 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let object_id = catalog.list()[0].manifest.object_id;
+        let server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.run());
+        // nothing listens on the first peer once its socket is dropped, so asking it first stalls the fetch
+        let dead = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+
+        let hub = Arc::new(EventHub::new());
+        let events = hub.subscribe();
+        let called = Arc::new(AtomicUsize::new(0));
+        let counter = called.clone();
+        hub.add_callback(Box::new(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }));
+        drop(hub.subscribe());
+
+        let manifest = fetch_manifest(&addr[..], "", &object_id, None).unwrap();
+        let block_count = manifest.get_block_count();
+        let mut fetch = Fetch::new(manifest, vec![dead, addr], &DecoderLimits::default()).unwrap();
+        fetch.set_events(hub.clone());
+        fetch.set_stall_timeout(Duration::ZERO);
+        assert_eq!(fetch.run().unwrap(), &data[..]);
+
+        let events: Vec<TransferEvent> = events.try_iter().collect();
+        assert_eq!(called.load(Ordering::Relaxed), events.len());
+        assert_eq!(hub.senders.lock().unwrap().len(), 1);
+        let kinds: Vec<&TransferEventKind> = events.iter().map(|x| &x.kind).collect();
+        assert_eq!(kinds[..2], [&TransferEventKind::Started, &TransferEventKind::ManifestReceived { data_size: data.len() as u64, block_count }]);
+        assert!(kinds.iter().any(|x| matches!(x, TransferEventKind::Stalled { .. })));
+        assert_eq!(kinds.iter().filter(|x| matches!(x, TransferEventKind::BlockDecoded { .. })).count(), block_count);
+        assert_eq!(kinds.last(), Some(&&TransferEventKind::Completed));
+        assert!(events.iter().all(|x| x.object_id == object_id && x.transfer_id.is_none()));
+
+        // progress only grows, and the last snapshot has the whole object
+        assert!(events.windows(2).all(|x| x[0].throughput.bytes_received <= x[1].throughput.bytes_received));
+        let last = events.last().unwrap().throughput;
+        assert_eq!((last.blocks_decoded, last.block_count), (block_count, block_count));
+        assert!(last.bytes_received >= data.len() as u64 && last.bytes_per_sec > 0.0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,719 @@
+//! Fetching an object from several HTTP peers at once, e.g. an origin and edge caches. A SchedulePolicy picks which
+//! peer to ask for symbols of which block next; peers that fail are dropped and the others make up for them. As in
+//! MixedFetch, each decoded block is checked against its hash in the manifest, so only the manifest needs to come
+//! from someone trusted. With peer exchange on, peers are asked for more peers of the object as the fetch goes, see
+//! server::pex. Progress can be followed as it happens through an EventHub, see client::events.
+
+use sha2::{Digest, Sha256};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::codec::bundle::BundleExtractor;
+use crate::codec::decoder::{BlockNeeds, RaptorQDecoder};
+use crate::codec::manifest::{to_hex, validate_manifest, DecoderLimits, Manifest};
+use crate::codec::producer::SessionId;
+use crate::server::pex::{read_peer_list, PexKey};
+use crate::store::object_store::{put_decoded_blocks, ObjectStore};
+use crate::store::resume::ResumeToken;
+use super::events::{EventHub, Throughput, TransferEvent, TransferEventKind};
+use super::http::{fetch_peers, fetch_symbols};
+use super::schedule::{PeerState, RoundRobin, SchedulePolicy};
+use super::stats::{TransferRecorder, TransferStats};
+use super::telemetry::{gen_span_id, gen_trace_id, AttributeValue, Span, SpanId, Telemetry, TraceId};
+use super::transfers::TransferId;
+
+/// Most symbols asked of a peer in one request by default.
+const DEFAULT_SYMBOLS_PER_REQUEST: usize = 64;
+
+/// Weight of a new measurement in a peer's smoothed latency and bandwidth.
+const SMOOTHING: f64 = 0.25;
+
+/// How long without symbols before a fetch sends TransferEventKind::Stalled, by default.
+const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a Fetch exchanges peers, see Fetch::set_peer_exchange.
+#[derive(Clone)]
+pub struct PexSettings {
+    /// Key peer lists must be signed with, None to take unsigned lists.
+    pub key: Option<PexKey>,
+    /// Least time between asking the same peer for peers.
+    pub interval: Duration,
+    /// Most peers the fetch grows to, counting those it started with.
+    pub max_peers: usize,
+    /// Address to announce to the peers asked, for a client serving what it fetches.
+    pub announce: Option<String>,
+}
+
+impl Default for PexSettings {
+    fn default() -> PexSettings {
+        return PexSettings { key: None, interval: Duration::from_secs(30), max_peers: 16, announce: None };
+    }
+}
+
+/// Decodes an object from symbols fetched from several peers over HTTP, one request at a time.
+pub struct Fetch {
+    manifest: Manifest,
+    /// Prefix and token of the object's resources, see fetch_manifest.
+    prefix: String,
+    token: Option<String>,
+    peers: Vec<PeerState>,
+    /// Session of each peer, once opened.
+    sessions: Vec<Option<SessionId>>,
+    policy: Box<dyn SchedulePolicy>,
+    symbols_per_request: usize,
+    /// Blocks with ids below this are asked for before any other.
+    priority_blocks: u32,
+    decoder: RaptorQDecoder,
+    /// Whether each block was decoded and matched its hash.
+    verified: Vec<bool>,
+    result: Option<Vec<u8>>,
+    recorder: TransferRecorder,
+    telemetry: Option<Arc<Telemetry>>,
+    /// Trace and span the fetch's span is part of.
+    trace_parent: Option<(TraceId, SpanId)>,
+    /// When the first request was sent.
+    started_at: Option<SystemTime>,
+    /// When each peer was first asked, and when it last answered or failed.
+    peer_times: Vec<Option<(SystemTime, SystemTime)>>,
+    pex: Option<PexSettings>,
+    /// When each peer was last asked for peers.
+    pex_times: Vec<Option<Instant>>,
+    events: Option<Arc<EventHub>>,
+    /// Transfer of the TransferQueue running the fetch, named in its events.
+    transfer_id: Option<TransferId>,
+    stall_timeout: Duration,
+    /// When the first step began.
+    first_step_at: Option<Instant>,
+    /// When symbols last came, and whether Stalled was sent since.
+    progress_at: Option<Instant>,
+    stalled: bool,
+}
+
+impl Fetch {
+    /// Gets ready to fetch the object described by manifest from peers, e.g. "host:port". Fails with
+    /// ErrorKind::InvalidData if the manifest does not pass validate_manifest with limits.
+    pub fn new(manifest: Manifest, peers: Vec<String>, limits: &DecoderLimits) -> io::Result<Fetch> {
+        if let Err(issues) = validate_manifest(&manifest, limits) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad manifest: {:?}", issues)));
+        }
+        let decoder = match RaptorQDecoder::from_manifest(&manifest) {
+            Ok(decoder) => decoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad manifest: {:?}", error))),
+        };
+
+        let verified = vec![false; manifest.get_block_count()];
+        return Ok(Fetch {
+            manifest,
+            prefix: String::new(),
+            token: None,
+            sessions: vec![None; peers.len()],
+            peer_times: vec![None; peers.len()],
+            pex_times: vec![None; peers.len()],
+            peers: peers.into_iter().map(|addr| PeerState { addr, ..PeerState::default() }).collect(),
+            policy: Box::new(RoundRobin::default()),
+            symbols_per_request: DEFAULT_SYMBOLS_PER_REQUEST,
+            priority_blocks: 0,
+            decoder,
+            verified,
+            result: None,
+            recorder: TransferRecorder::new(),
+            telemetry: None,
+            trace_parent: None,
+            started_at: None,
+            pex: None,
+            events: None,
+            transfer_id: None,
+            stall_timeout: DEFAULT_STALL_TIMEOUT,
+            first_step_at: None,
+            progress_at: None,
+            stalled: false,
+        });
+    }
+
+    /// Sets where the object's resources are on every peer, e.g. "/tenants/<name>", and the token to send.
+    pub fn set_location(&mut self, prefix: &str, token: Option<&str>) {
+        self.prefix = prefix.to_string();
+        self.token = token.map(|x| x.to_string());
+    }
+
+    /// Replaces the policy picking requests, RoundRobin by default.
+    pub fn set_policy(&mut self, policy: Box<dyn SchedulePolicy>) {
+        self.policy = policy;
+    }
+
+    /// Declares which blocks a peer holds, e.g. a cache with part of the object, by block id. Peers hold every
+    /// block by default. Returns false if there is no such peer.
+    pub fn set_peer_blocks(&mut self, peer: usize, blocks: Vec<bool>) -> bool {
+        match self.peers.get_mut(peer) {
+            None => return false,
+            Some(state) => state.blocks = Some(blocks),
+        }
+        return true;
+    }
+
+    /// Sets the most symbols asked of a peer in one request. Panics if count is zero.
+    pub fn set_symbols_per_request(&mut self, count: usize) {
+        assert!(count > 0, "symbols per request must be positive");
+        self.symbols_per_request = count;
+    }
+
+    /// Asks for the first count blocks before any other, so applications reading the object from the start, e.g.
+    /// video players or installers reading headers, can begin sooner. The policy only sees the other blocks once the
+    /// first ones are decoded or no peer left holds them. 0, the default, leaves the order to the policy. See
+    /// TransferStats::first_byte_time for the effect.
+    pub fn set_priority_blocks(&mut self, count: u32) {
+        self.priority_blocks = count;
+    }
+
+    /// Reports the fetch to telemetry once run finishes, as a span with a child span for each peer asked.
+    pub fn set_telemetry(&mut self, telemetry: Arc<Telemetry>) {
+        self.telemetry = Some(telemetry);
+    }
+
+    /// Makes the fetch's span part of a trace, as a child of span_id, e.g. from parse_traceparent. Without it the
+    /// span starts a trace of its own.
+    pub fn set_trace_parent(&mut self, trace_id: TraceId, span_id: SpanId) {
+        self.trace_parent = Some((trace_id, span_id));
+    }
+
+    /// Asks each peer that answers for more peers of the object, at most once per interval, adding those not known
+    /// yet to the end of the peers. Peers that don't exchange peers are asked for symbols all the same.
+    pub fn set_peer_exchange(&mut self, settings: PexSettings) {
+        self.pex = Some(settings);
+    }
+
+    /// Sends events of the fetch to hub as it goes. Completed and Failed are sent once run finishes.
+    pub fn set_events(&mut self, hub: Arc<EventHub>) {
+        self.events = Some(hub);
+    }
+
+    /// Decodes into huge pages where the system has them, see RaptorQDecoder::set_huge_pages.
+    pub fn set_huge_pages(&mut self, huge_pages: bool) {
+        self.decoder.set_huge_pages(huge_pages);
+    }
+
+    /// Sets how long without symbols before TransferEventKind::Stalled is sent, 10 seconds by default.
+    pub fn set_stall_timeout(&mut self, timeout: Duration) {
+        self.stall_timeout = timeout;
+    }
+
+    /// Picks up a fetch interrupted after save_progress: restores the blocks token records as decoded from store,
+    /// see ResumeToken::restore, and adds the peers it names that are not known yet. Call before the first step.
+    /// Returns how many blocks were restored.
+    pub fn resume(&mut self, token: &ResumeToken, store: &dyn ObjectStore) -> io::Result<usize> {
+        let restored = token.restore(&self.manifest, &mut self.decoder, store)?;
+        for addr in token.peers.iter() {
+            if !self.peers.iter().any(|x| x.addr == *addr) {
+                self.peers.push(PeerState { addr: addr.clone(), ..PeerState::default() });
+                self.sessions.push(None);
+                self.peer_times.push(None);
+                self.pex_times.push(None);
+            }
+        }
+        for block_id in restored.iter() {
+            self.verify_block(*block_id);
+        }
+        return Ok(restored.len());
+    }
+
+    /// Puts the blocks decoded so far in store, where the object must be open, see ObjectStore::open, and records
+    /// how far the fetch got, to persist and resume later, e.g. when it is paused or the process exits.
+    pub fn save_progress(&self, store: &dyn ObjectStore) -> io::Result<ResumeToken> {
+        put_decoded_blocks(store, &self.manifest.object_id, &self.decoder)?;
+        return Ok(ResumeToken::new(&self.manifest, &self.decoder, self.peers.iter().map(|x| x.addr.clone()).collect()));
+    }
+
+    pub(crate) fn set_transfer_id(&mut self, id: TransferId) {
+        self.transfer_id = Some(id);
+    }
+
+    /// Sends the request the policy picks, returning whether the object is decoded and verified. A peer that fails
+    /// is marked failed and not asked again. Fails with ErrorKind::NotFound if no peer left can serve the blocks
+    /// that are missing.
+    pub fn step(&mut self) -> io::Result<bool> {
+        if self.result.is_some() {
+            return Ok(true);
+        }
+        if self.events.is_none() {
+            return self.request();
+        }
+
+        let now = Instant::now();
+        if self.first_step_at.is_none() {
+            self.first_step_at = Some(now);
+            self.progress_at = Some(now);
+            self.send_event(TransferEventKind::Started);
+            let (data_size, block_count) = (self.manifest.data_size, self.manifest.get_block_count());
+            self.send_event(TransferEventKind::ManifestReceived { data_size, block_count });
+        }
+        let (bytes_before, verified_before) = (self.get_bytes_received(), self.verified.clone());
+        let result = self.request();
+
+        if self.get_bytes_received() > bytes_before {
+            self.progress_at = Some(Instant::now());
+            self.stalled = false;
+        } else if let Some(since) = self.progress_at.map(|x| x.elapsed()).filter(|x| *x >= self.stall_timeout && !self.stalled) {
+            self.stalled = true;
+            self.send_event(TransferEventKind::Stalled { since });
+        }
+        for block_id in (0..self.verified.len()).filter(|x| self.verified[*x] && !verified_before[*x]) {
+            self.send_event(TransferEventKind::BlockDecoded { block_id: block_id as u32 });
+        }
+        return result;
+    }
+
+    fn get_bytes_received(&self) -> u64 {
+        return self.peers.iter().map(|x| x.contribution.bytes).sum();
+    }
+
+    /// Sends an event to the hub set, if any, with the throughput so far.
+    fn send_event(&self, kind: TransferEventKind) {
+        let hub = match self.events.as_ref() {
+            Some(hub) => hub,
+            None => return,
+        };
+        let elapsed = self.first_step_at.map_or(Duration::ZERO, |x| x.elapsed());
+        let bytes_received = self.get_bytes_received();
+        let throughput = Throughput {
+            elapsed,
+            bytes_received,
+            blocks_decoded: self.get_blocks_decoded(),
+            block_count: self.verified.len(),
+            bytes_per_sec: bytes_received as f64 / elapsed.max(Duration::from_micros(1)).as_secs_f64(),
+        };
+        hub.send(TransferEvent { object_id: self.manifest.object_id, transfer_id: self.transfer_id, kind, throughput });
+    }
+
+    /// Step without the events.
+    fn request(&mut self) -> io::Result<bool> {
+        let mut needs = self.decoder.get_block_needs();
+        let priority = |x: &BlockNeeds| x.block_id < self.priority_blocks && self.peers.iter().any(|peer| peer.can_serve(x.block_id));
+        if needs.iter().any(priority) {
+            needs.retain(priority);
+        }
+        let request = match self.policy.next_request(&needs, &self.peers) {
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no peer left can serve the missing blocks")),
+            Some(request) => request,
+        };
+        let count = needs.iter()
+            .find(|x| x.block_id == request.block_id)
+            .map_or(1, |x| x.symbols_needed as usize)
+            .clamp(1, self.symbols_per_request);
+
+        let peer = &mut self.peers[request.peer];
+        let start = Instant::now();
+        let sent_at = SystemTime::now();
+        self.started_at.get_or_insert(sent_at);
+        peer.requests += 1;
+        let token = self.token.as_deref();
+        let blocks = fetch_symbols(&peer.addr[..], &self.prefix, &self.manifest.object_id, token, self.sessions[request.peer], Some(request.block_id), count);
+        let peer_times = &mut self.peer_times[request.peer];
+        *peer_times = Some((peer_times.map_or(sent_at, |x| x.0), SystemTime::now()));
+        let (session_id, blocks) = match blocks {
+            Ok(fetched) => fetched,
+            Err(_) => {
+                peer.failed = true;
+                return Ok(false);
+            },
+        };
+        let elapsed = start.elapsed().max(Duration::from_micros(1));
+        self.sessions[request.peer] = Some(session_id);
+
+        let bytes: usize = blocks.iter().map(|x| x.data.data().len()).sum();
+        let bytes_per_sec = bytes as f64 / elapsed.as_secs_f64();
+        peer.latency = Some(peer.latency.map_or(elapsed, |x| x.mul_f64(1.0 - SMOOTHING) + elapsed.mul_f64(SMOOTHING)));
+        peer.bytes_per_sec = Some(peer.bytes_per_sec.map_or(bytes_per_sec, |x| x * (1.0 - SMOOTHING) + bytes_per_sec * SMOOTHING));
+
+        if blocks.iter().any(|x| x.block_id != request.block_id) || self.recorder.consume(&mut self.decoder, &peer.addr, blocks).is_err() {
+            // not what was asked for, so not to be trusted with anything else
+            peer.failed = true;
+            return Ok(false);
+        }
+        peer.contribution = self.recorder.get_contribution(&peer.addr).unwrap_or_default();
+
+        self.exchange_peers(request.peer);
+        return Ok(self.verify_block(request.block_id));
+    }
+
+    /// Asks a peer for more peers if peer exchange is on, there is room for more, and the peer was not asked within
+    /// the interval. A list that fails read_peer_list, or is of another object, is dropped.
+    fn exchange_peers(&mut self, peer: usize) {
+        let settings = match self.pex.as_ref() {
+            Some(settings) if self.peers.len() < settings.max_peers => settings,
+            _ => return,
+        };
+        if self.pex_times[peer].is_some_and(|x| x.elapsed() < settings.interval) {
+            return;
+        }
+        self.pex_times[peer] = Some(Instant::now());
+        let message = fetch_peers(&self.peers[peer].addr[..], &self.prefix, &self.manifest.object_id, self.token.as_deref(), settings.announce.as_deref());
+        let list = match message.map(|x| read_peer_list(&x, settings.key.as_ref())) {
+            Ok(Ok(list)) if list.object_id == self.manifest.object_id => list,
+            _ => return,
+        };
+
+        let (max_peers, announce) = (settings.max_peers, settings.announce.clone());
+        for addr in list.peers.into_iter() {
+            if self.peers.len() >= max_peers {
+                break;
+            }
+            if Some(&addr) == announce.as_ref() || self.peers.iter().any(|x| x.addr == addr) {
+                continue;
+            }
+            self.peers.push(PeerState { addr, ..PeerState::default() });
+            self.sessions.push(None);
+            self.peer_times.push(None);
+            self.pex_times.push(None);
+        }
+    }
+
+    /// Checks a block against its hash once decoded, decoding it again if it does not match. Returns whether the
+    /// object is decoded and verified.
+    fn verify_block(&mut self, block_id: u32) -> bool {
+        let verified = match self.decoder.get_block_result(block_id) {
+            None => return false,
+            Some(data) => Sha256::digest(data)[..] == self.manifest.block_hashes[block_id as usize][..],
+        };
+        if !verified {
+            self.decoder.reset_block(block_id).unwrap();
+            return false;
+        }
+        self.verified[block_id as usize] = true;
+
+        if self.verified.iter().all(|x| *x) {
+            let data = self.decoder.get_result().unwrap();
+            if Sha256::digest(&data)[..] == self.manifest.object_id[..] {
+                self.result = Some(data);
+            }
+        }
+        return self.result.is_some();
+    }
+
+    /// Steps until the object is decoded and verified, returning it. Fails as step does, and with
+    /// ErrorKind::InvalidData if every block matched its hash but the object did not match its id.
+    /// Reports the fetch to the telemetry set, if any, whether it succeeded or failed.
+    pub fn run(&mut self) -> io::Result<&[u8]> {
+        let mut result = Ok(false);
+        while let Ok(false) = result {
+            result = self.step_checked();
+        }
+        self.report(result.as_ref().err());
+        result?;
+        return Ok(self.result.as_deref().unwrap());
+    }
+
+    /// Steps as step does, and fails as run does once every block matched its hash but the object did not match its
+    /// id, for callers stepping fetches themselves, e.g. a TransferQueue.
+    pub(crate) fn step_checked(&mut self) -> io::Result<bool> {
+        if self.step()? {
+            return Ok(true);
+        }
+        if self.verified.iter().all(|x| *x) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "manifest is inconsistent with its object id"));
+        }
+        return Ok(false);
+    }
+
+    /// Reports the finished fetch to the telemetry and events set, if any, as failed with error if given.
+    pub(crate) fn report(&self, error: Option<&io::Error>) {
+        let error = error.map(|x| x.to_string());
+        if let Some(telemetry) = self.telemetry.as_ref() {
+            telemetry.record_fetch(&self.get_transfer_stats(), error.is_some(), &self.get_spans(error.clone()));
+        }
+        self.send_event(match error {
+            Some(error) => TransferEventKind::Failed(error),
+            None => TransferEventKind::Completed,
+        });
+    }
+
+    /// Sends Failed to the events set, if any, for a fetch cancelled before it finished.
+    pub(crate) fn report_cancelled(&self) {
+        self.send_event(TransferEventKind::Failed("cancelled".to_string()));
+    }
+
+    /// Gets the spans of the fetch so far: one for the fetch, failed with error if given, and a child of it for
+    /// each peer asked.
+    fn get_spans(&self, error: Option<String>) -> Vec<Span> {
+        let end = SystemTime::now();
+        let start = self.started_at.unwrap_or(end);
+        let (trace_id, parent_span_id) = match self.trace_parent {
+            Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+            None => (gen_trace_id(), None),
+        };
+        let stats = self.get_transfer_stats();
+        let fetch_span = Span {
+            trace_id,
+            span_id: gen_span_id(),
+            parent_span_id,
+            name: "raptorcdn.fetch".to_string(),
+            start,
+            end,
+            attributes: vec![
+                ("raptorcdn.object_id".to_string(), AttributeValue::Str(to_hex(&self.manifest.object_id))),
+                ("raptorcdn.data_size".to_string(), AttributeValue::Int(self.manifest.data_size as i64)),
+                ("raptorcdn.blocks".to_string(), AttributeValue::Int(self.manifest.get_block_count() as i64)),
+                ("raptorcdn.peers".to_string(), AttributeValue::Int(self.peers.len() as i64)),
+                ("raptorcdn.symbols_received".to_string(), AttributeValue::Int(stats.symbols_received as i64)),
+                ("raptorcdn.bytes_received".to_string(), AttributeValue::Int(stats.bytes_received as i64)),
+                ("raptorcdn.duplicate_ratio".to_string(), AttributeValue::Float(stats.duplicate_ratio)),
+                ("raptorcdn.effective_overhead".to_string(), AttributeValue::Float(stats.effective_overhead)),
+            ],
+            error,
+        };
+
+        let mut spans: Vec<Span> = Vec::with_capacity(self.peers.len() + 1);
+        for (peer, times) in self.peers.iter().zip(self.peer_times.iter()) {
+            let (peer_start, peer_end) = match times {
+                Some(times) => *times,
+                None => continue,
+            };
+            spans.push(Span {
+                trace_id,
+                span_id: gen_span_id(),
+                parent_span_id: Some(fetch_span.span_id),
+                name: "raptorcdn.fetch.peer".to_string(),
+                start: peer_start,
+                end: peer_end,
+                attributes: vec![
+                    ("server.address".to_string(), AttributeValue::Str(peer.addr.clone())),
+                    ("raptorcdn.requests".to_string(), AttributeValue::Int(peer.requests as i64)),
+                    ("raptorcdn.symbols_received".to_string(), AttributeValue::Int(peer.contribution.symbols as i64)),
+                    ("raptorcdn.bytes_received".to_string(), AttributeValue::Int(peer.contribution.bytes as i64)),
+                    ("raptorcdn.symbols_innovative".to_string(), AttributeValue::Int(peer.contribution.innovative as i64)),
+                ],
+                error: if peer.failed { Some("peer failed".to_string()) } else { None },
+            });
+        }
+        spans.insert(0, fetch_span);
+        return spans;
+    }
+
+    /// Gets the object, once step returned true.
+    pub fn get_result(&self) -> Option<&[u8]> {
+        return self.result.as_deref();
+    }
+
+    /// Gets the contents of the file named name in the object, a bundle of files, see Catalog::set_bundling, once
+    /// step returned true. Fails with ErrorKind::InvalidInput before then, InvalidData if the object is not a bundle,
+    /// and NotFound if the bundle has no such file.
+    pub fn get_bundle_entry(&self, name: &str) -> io::Result<&[u8]> {
+        let result = match self.result.as_deref() {
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "object is not decoded yet")),
+            Some(result) => result,
+        };
+        let extractor = match BundleExtractor::new(result) {
+            Ok(extractor) => extractor,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("object is not a bundle: {:?}", error))),
+        };
+        match extractor.get(name) {
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("bundle has no file named {}", name))),
+            Some(data) => return Ok(data),
+        }
+    }
+
+    /// Gets the number of blocks decoded and verified so far.
+    pub fn get_blocks_decoded(&self) -> usize {
+        return self.verified.iter().filter(|x| **x).count();
+    }
+
+    pub fn get_manifest(&self) -> &Manifest {
+        return &self.manifest;
+    }
+
+    /// Gets what is known of each peer, in the order given to new, then those learned through peer exchange.
+    pub fn get_peers(&self) -> &[PeerState] {
+        return &self.peers;
+    }
+
+    /// Summarizes the fetch so far, peers named by address.
+    pub fn get_transfer_stats(&self) -> TransferStats {
+        return self.recorder.get_stats(&self.decoder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::http::fetch_manifest;
+    use super::super::schedule::{RarestFirst, ScheduledRequest};
+    use crate::codec::encoder::EncoderConfig;
+    use crate::server::catalog::Catalog;
+    use crate::server::http::HttpServer;
+    use crate::store::memory::MemoryStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_fetch_peers() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-fetch-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(100 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let object_id = catalog.list()[0].manifest.object_id;
+        let mut addrs: Vec<String> = Vec::new();
+        for _ in 0..2 {
+            let server = HttpServer::bind("127.0.0.1:0", catalog.clone()).unwrap();
+            addrs.push(server.local_addr().unwrap().to_string());
+            std::thread::spawn(move || server.run());
+        }
+        // nothing listens on the third peer once its socket is dropped
+        addrs.push(std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string());
+
+        let manifest = fetch_manifest(&addrs[0][..], "", &object_id, None).unwrap();
+        let mut fetch = Fetch::new(manifest, addrs.clone(), &DecoderLimits::default()).unwrap();
+        fetch.set_policy(Box::new(RarestFirst::default()));
+        fetch.set_symbols_per_request(8);
+        fetch.set_priority_blocks(1);
+        // the second peer declares the only block, peers take turns at it
+        assert!(fetch.set_peer_blocks(1, vec![true]));
+        assert!(!fetch.set_peer_blocks(3, vec![true]));
+        assert_eq!(fetch.run().unwrap(), &data[..]);
+
+        let peers = fetch.get_peers();
+        assert!(peers[2].failed && !peers[0].failed && !peers[1].failed);
+        assert!(peers[1].contribution.symbols > 0 && peers[0].contribution.symbols > 0);
+        assert!(peers[0].latency.is_some() && peers[0].bytes_per_sec.is_some());
+        let stats = fetch.get_transfer_stats();
+        assert_eq!(stats.symbols_received, peers[0].contribution.symbols + peers[1].contribution.symbols);
+        assert!(stats.first_byte_time.is_some_and(|x| x <= stats.wall_time));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_bundle() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-fetch-bundle-test-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("small.bundle")).unwrap();
+        let files: Vec<Vec<u8>> = (0..20).map(|x| gen_data(100 + x * 50)).collect();
+        for (i, data) in files.iter().enumerate() {
+            std::fs::write(root.join("small.bundle").join(format!("file-{}", i)), data).unwrap();
+        }
+
+        let mut catalog = Catalog::new(&root, EncoderConfig::new(1280));
+        catalog.set_bundling(true);
+        catalog.refresh().unwrap();
+        let object_id = catalog.list()[0].manifest.object_id;
+        let server = HttpServer::bind("127.0.0.1:0", Arc::new(catalog)).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.run());
+
+        let manifest = fetch_manifest(&addr[..], "", &object_id, None).unwrap();
+        let mut fetch = Fetch::new(manifest, vec![addr], &DecoderLimits::default()).unwrap();
+        assert_eq!(fetch.get_bundle_entry("file-0").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        fetch.run().unwrap();
+        for (i, data) in files.iter().enumerate() {
+            assert_eq!(fetch.get_bundle_entry(&format!("file-{}", i)).unwrap(), &data[..]);
+        }
+        assert_eq!(fetch.get_bundle_entry("file-20").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Asks the only peer for the last block needed, recording the blocks asked for.
+    struct LastFirst {
+        requested: Arc<std::sync::Mutex<Vec<u32>>>,
+    }
+
+    impl SchedulePolicy for LastFirst {
+        fn next_request(&mut self, needs: &[BlockNeeds], _peers: &[PeerState]) -> Option<ScheduledRequest> {
+            let block_id = needs.iter().map(|x| x.block_id).max()?;
+            self.requested.lock().unwrap().push(block_id);
+            return Some(ScheduledRequest { peer: 0, block_id });
+        }
+    }
+
+    #[test]
+    fn test_fetch_priority_blocks() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-fetch-priority-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(60 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+
+        let config = EncoderConfig { max_block_symbols: 10, ..EncoderConfig::new(1280) };
+        let catalog = Arc::new(Catalog::new(&root, config));
+        catalog.refresh().unwrap();
+        let object_id = catalog.list()[0].manifest.object_id;
+        let server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.run());
+
+        let manifest = fetch_manifest(&addr[..], "", &object_id, None).unwrap();
+        assert_eq!(manifest.get_block_count(), 5);
+        let requested = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut fetch = Fetch::new(manifest, vec![addr], &DecoderLimits::default()).unwrap();
+        fetch.set_policy(Box::new(LastFirst { requested: requested.clone() }));
+        fetch.set_symbols_per_request(4);
+        fetch.set_priority_blocks(2);
+
+        // the policy only gets to pick among the other blocks once the first two are decoded
+        let mut decoded: Vec<usize> = Vec::new();
+        while !fetch.step().unwrap() {
+            for block_id in 0..fetch.verified.len() {
+                if fetch.verified[block_id] && !decoded.contains(&block_id) {
+                    decoded.push(block_id);
+                }
+            }
+        }
+        assert_eq!(fetch.get_result(), Some(&data[..]));
+        assert_eq!(decoded[..2], [1, 0]);
+        let requested = requested.lock().unwrap();
+        let first_other = requested.iter().position(|x| *x >= 2).unwrap();
+        assert!(requested[..first_other].contains(&0) && requested[..first_other].contains(&1));
+        assert!(requested[first_other..].iter().all(|x| *x >= 2));
+        assert_eq!(requested[first_other], 4);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_resume() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-fetch-resume-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(60 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+
+        let config = EncoderConfig { max_block_symbols: 10, ..EncoderConfig::new(1280) };
+        let catalog = Arc::new(Catalog::new(&root, config));
+        catalog.refresh().unwrap();
+        let object_id = catalog.list()[0].manifest.object_id;
+        let server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.run());
+
+        // the fetch is interrupted once two blocks are decoded
+        let manifest = fetch_manifest(&addr[..], "", &object_id, None).unwrap();
+        let mut fetch = Fetch::new(manifest.clone(), vec![addr.clone()], &DecoderLimits::default()).unwrap();
+        fetch.set_symbols_per_request(4);
+        fetch.set_priority_blocks(2);
+        while fetch.verified.iter().filter(|x| **x).count() < 2 {
+            assert!(!fetch.step().unwrap());
+        }
+        let store = MemoryStore::new();
+        store.open(&manifest).unwrap();
+        let mut persisted: Vec<u8> = Vec::new();
+        fetch.save_progress(&store).unwrap().write_to(&mut persisted).unwrap();
+
+        // a new fetch knowing no peers picks up from the token and the store
+        let token = ResumeToken::read_from(&persisted[..]).unwrap();
+        let mut resumed = Fetch::new(manifest.clone(), Vec::new(), &DecoderLimits::default()).unwrap();
+        assert_eq!(resumed.resume(&token, &store).unwrap(), token.get_decoded_count());
+        assert!(resumed.verified[0] && resumed.verified[1]);
+        assert_eq!(resumed.get_peers().len(), 1);
+        assert_eq!(resumed.run().unwrap(), &data[..]);
+        // only the blocks that were not restored are fetched
+        assert!(resumed.get_transfer_stats().symbols_received < (data.len() / 1280) as u64);
+
+        // a token of another object is refused
+        let mut other = ResumeToken { object_id: [0; 32], ..token };
+        assert_eq!(resumed.resume(&other, &store).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        other.object_id = object_id;
+        other.blocks.pop();
+        assert_eq!(resumed.resume(&other, &store).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
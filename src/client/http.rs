@@ -0,0 +1,472 @@
+//! Minimal HTTP/1.1 client for the endpoints of server::http::HttpServer, one request per connection. There is no
+//! TLS, so reach an HTTPS origin through a TLS-terminating proxy; manifests are checked against the object id they
+//! were requested for either way, see fetch_manifest.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::codec::decoder::RaptorQDecoder;
+use crate::codec::encoder::EncodedBlock;
+use crate::codec::manifest::{to_hex, Manifest, ObjectId};
+use crate::codec::producer::SessionId;
+use crate::codec::shard::{read_shard, ShardReader};
+use crate::transport::net::connect_tcp;
+use super::stats::{TransferRecorder, TransferStats};
+
+/// Most bytes read of a response's status line and headers.
+const MAX_RESPONSE_HEAD_SIZE: u64 = 16 * 1024;
+
+/// Most bytes read of a response's body, above what the largest symbol request takes at the default packet size.
+/// Larger requests go through open_symbol_stream instead.
+const MAX_RESPONSE_BODY_SIZE: u64 = 256 * 1024 * 1024;
+
+/// How long a request may wait on the server before failing with ErrorKind::WouldBlock or TimedOut.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A response to a GET request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// Headers in the order received, names as sent.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Gets the value of the first header named name, ignoring case.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        return self.headers.iter().find(|(x, _)| x.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str());
+    }
+}
+
+/// Sends a GET request for target, e.g. "/objects", reading the whole response. Fails with ErrorKind::InvalidData
+/// if the response is not HTTP or its body is larger than MAX_RESPONSE_BODY_SIZE.
+pub fn get<A: ToSocketAddrs>(addr: A, target: &str) -> io::Result<HttpResponse> {
+    let (mut response, reader) = send_request(addr, target, None)?;
+    response.body = read_body(&response, reader, MAX_RESPONSE_BODY_SIZE)?;
+    return Ok(response);
+}
+
+/// Sends a POST request with a body of content_type to target, reading the whole response. Fails as get does.
+pub fn post<A: ToSocketAddrs>(addr: A, target: &str, content_type: &str, body: &[u8]) -> io::Result<HttpResponse> {
+    let (mut response, reader) = send_request(addr, target, Some((content_type, body)))?;
+    response.body = read_body(&response, reader, MAX_RESPONSE_BODY_SIZE)?;
+    return Ok(response);
+}
+
+/// Reads the body of response, chunked, of its Content-Length, or up to the end of the connection otherwise. Fails
+/// with ErrorKind::InvalidData if it is larger than max_size, without reading past max_size, and with
+/// ErrorKind::UnexpectedEof if it is shorter than its Content-Length.
+fn read_body<R: BufRead>(response: &HttpResponse, reader: R, max_size: u64) -> io::Result<Vec<u8>> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidData, "response body too large");
+    let mut body: Vec<u8> = Vec::new();
+    if response.get_header("Transfer-Encoding").is_some_and(|x| x.eq_ignore_ascii_case("chunked")) {
+        ChunkedReader::new(reader).take(max_size + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > max_size {
+            return Err(too_large());
+        }
+        return Ok(body);
+    }
+
+    let content_length = match response.get_header("Content-Length").map(|x| x.parse::<u64>()) {
+        None => None,
+        Some(Ok(content_length)) => Some(content_length),
+        Some(Err(_)) => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad Content-Length")),
+    };
+    if content_length.is_some_and(|x| x > max_size) {
+        return Err(too_large());
+    }
+    // without a length, the server closes the connection after the body
+    reader.take(content_length.unwrap_or(max_size + 1)).read_to_end(&mut body)?;
+    match content_length {
+        Some(content_length) if (body.len() as u64) < content_length => return Err(io::ErrorKind::UnexpectedEof.into()),
+        None if body.len() as u64 > max_size => return Err(too_large()),
+        _ => return Ok(body),
+    }
+}
+
+/// Sends a GET request, or a POST request with a content type and body if given, reading the response head.
+/// Returns the response without a body and the reader positioned at the body.
+fn send_request<A: ToSocketAddrs>(addr: A, target: &str, body: Option<(&str, &[u8])>) -> io::Result<(HttpResponse, BufReader<TcpStream>)> {
+    let stream = connect_tcp(addr, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    match body {
+        None => write!(&stream, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", target, stream.peer_addr()?)?,
+        Some((content_type, body)) => {
+            write!(
+                &stream,
+                "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                target,
+                stream.peer_addr()?,
+                content_type,
+                body.len(),
+            )?;
+            (&stream).write_all(body)?;
+        },
+    }
+
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad HTTP response: {}", reason));
+    let mut reader = BufReader::new(stream);
+    let mut head = (&mut reader).take(MAX_RESPONSE_HEAD_SIZE);
+
+    let mut status_line = String::new();
+    head.read_line(&mut status_line)?;
+    let status = match status_line.split_whitespace().collect::<Vec<&str>>()[..] {
+        [version, status, ..] if version.starts_with("HTTP/1.") => status.parse().map_err(|_| invalid("bad status"))?,
+        _ => return Err(invalid("bad status line")),
+    };
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    loop {
+        let mut header = String::new();
+        if head.read_line(&mut header)? == 0 {
+            return Err(invalid("truncated headers"));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        match header.split_once(':') {
+            None => return Err(invalid("bad header")),
+            Some((name, value)) => headers.push((name.to_string(), value.trim().to_string())),
+        }
+    }
+
+    return Ok((HttpResponse { status, headers, body: Vec::new() }, reader));
+}
+
+/// Reads the body of a response sent with Transfer-Encoding: chunked, ending at the last chunk. Fails with
+/// ErrorKind::InvalidData on a malformed chunk, and ErrorKind::UnexpectedEof if the connection ends before the last
+/// chunk.
+pub struct ChunkedReader<R: BufRead> {
+    reader: R,
+    /// Bytes left in the current chunk.
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(reader: R) -> ChunkedReader<R> {
+        return ChunkedReader { reader, remaining: 0, done: false };
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if (&mut self.reader).take(MAX_RESPONSE_HEAD_SIZE).read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended before its last chunk"));
+        }
+        return Ok(line);
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !self.done {
+            let line = self.read_line()?;
+            // chunk extensions follow a ;
+            let size = line.trim_end().split(';').next().unwrap_or("");
+            self.remaining = match usize::from_str_radix(size, 16) {
+                Ok(remaining) => remaining,
+                Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad chunk size")),
+            };
+            if self.remaining == 0 {
+                // skip trailers up to the empty line
+                while !self.read_line()?.trim_end().is_empty() {}
+                self.done = true;
+            }
+        }
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(self.remaining);
+        let read = self.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended in a chunk"));
+        }
+        self.remaining -= read;
+        if self.remaining == 0 && self.read_line()?.trim_end() != "" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "chunk longer than its size"));
+        }
+        return Ok(read);
+    }
+}
+
+/// Symbols of an object streamed by a server, see open_symbol_stream.
+pub struct SymbolStream {
+    session_id: SessionId,
+    reader: ShardReader<ChunkedReader<BufReader<TcpStream>>>,
+    /// The server's address, naming it in transfer stats.
+    peer: String,
+    recorder: TransferRecorder,
+}
+
+impl SymbolStream {
+    /// Gets the server session the symbols come from, to continue it with another request.
+    pub fn get_session_id(&self) -> SessionId {
+        return self.session_id;
+    }
+
+    /// Feeds symbols to decoder until it is decoded, returning true, or the stream ends, returning false.
+    pub fn decode_into(&mut self, decoder: &mut RaptorQDecoder) -> io::Result<bool> {
+        for block in &mut self.reader {
+            match self.recorder.consume(decoder, &self.peer, vec![block?]) {
+                Ok(true) => return Ok(true),
+                Ok(false) => (),
+                Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad symbol: {:?}", error))),
+            }
+        }
+        return Ok(false);
+    }
+
+    /// Summarizes what decode_into fed to decoder since the stream was opened, the server named by its address.
+    pub fn get_transfer_stats(&self, decoder: &RaptorQDecoder) -> TransferStats {
+        return self.recorder.get_stats(decoder);
+    }
+}
+
+impl Iterator for SymbolStream {
+    type Item = io::Result<EncodedBlock>;
+
+    fn next(&mut self) -> Option<io::Result<EncodedBlock>> {
+        return self.reader.next();
+    }
+}
+
+/// Opens a stream of symbols of an object at bytes_per_sec, for count symbols or until dropped, continuing a
+/// session if one is given. Takes the same prefix and token as fetch_manifest, and fails the same way.
+pub fn open_symbol_stream<A: ToSocketAddrs>(addr: A, prefix: &str, object_id: &ObjectId, token: Option<&str>, session_id: Option<SessionId>, bytes_per_sec: u64, count: Option<usize>) -> io::Result<SymbolStream> {
+    let mut target = format!("{}/objects/{}/stream?rate={}", prefix, to_hex(object_id), bytes_per_sec);
+    if let Some(session_id) = session_id {
+        target.push_str(&format!("&session={}", session_id));
+    }
+    if let Some(count) = count {
+        target.push_str(&format!("&count={}", count));
+    }
+    if let Some(token) = token {
+        target.push_str(&format!("&token={}", token));
+    }
+
+    let recorder = TransferRecorder::new();
+    let (response, reader) = send_request(addr, &target, None)?;
+    check_status(response.status)?;
+    let peer = reader.get_ref().peer_addr()?.to_string();
+    let session_id = match response.get_header("X-Session-Id").map(|x| x.parse()) {
+        Some(Ok(session_id)) => session_id,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "stream without a session id")),
+    };
+    if !response.get_header("Transfer-Encoding").is_some_and(|x| x.eq_ignore_ascii_case("chunked")) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "stream is not chunked"));
+    }
+    return Ok(SymbolStream { session_id, reader: ShardReader::new(ChunkedReader::new(reader))?, peer, recorder });
+}
+
+/// Fetches the next count symbols of a session, only of block_id if given, opening a session if none is given.
+/// Returns the session, to continue it with the next request, and the symbols. Takes the same prefix and token as
+/// fetch_manifest, and fails the same way.
+pub fn fetch_symbols<A: ToSocketAddrs>(addr: A, prefix: &str, object_id: &ObjectId, token: Option<&str>, session_id: Option<SessionId>, block_id: Option<u32>, count: usize) -> io::Result<(SessionId, Vec<EncodedBlock>)> {
+    let mut target = format!("{}/objects/{}/symbols?count={}", prefix, to_hex(object_id), count);
+    if let Some(session_id) = session_id {
+        target.push_str(&format!("&session={}", session_id));
+    }
+    if let Some(block_id) = block_id {
+        target.push_str(&format!("&block={}", block_id));
+    }
+    if let Some(token) = token {
+        target.push_str(&format!("&token={}", token));
+    }
+
+    let response = get(addr, &target)?;
+    check_status(response.status)?;
+    let session_id = match response.get_header("X-Session-Id").map(|x| x.parse()) {
+        Some(Ok(session_id)) => session_id,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "symbols without a session id")),
+    };
+    return Ok((session_id, read_shard(&response.body[..])?));
+}
+
+/// Maps an error status of an object resource to an error.
+fn check_status(status: u16) -> io::Result<()> {
+    match status {
+        200 => return Ok(()),
+        404 => return Err(io::Error::new(io::ErrorKind::NotFound, "origin does not have the object")),
+        403 => return Err(io::Error::new(io::ErrorKind::PermissionDenied, "origin refused the token")),
+        status => return Err(io::Error::other(format!("origin answered {}", status))),
+    }
+}
+
+/// Fetches the manifest of an object from an origin, under prefix, e.g. "" or "/tenants/<name>", with a token if
+/// the origin requires them. Fails with ErrorKind::NotFound if the origin does not have the object, and with
+/// ErrorKind::InvalidData if the manifest it returns is for another object.
+pub fn fetch_manifest<A: ToSocketAddrs>(addr: A, prefix: &str, object_id: &ObjectId, token: Option<&str>) -> io::Result<Manifest> {
+    return fetch_manifest_response(addr, prefix, object_id, token).map(|x| x.0);
+}
+
+/// Fetches the manifest of an object as fetch_manifest does, with the response it came in, for its headers, e.g.
+/// X-Manifest-Issued. The response's body is left empty.
+pub fn fetch_manifest_response<A: ToSocketAddrs>(addr: A, prefix: &str, object_id: &ObjectId, token: Option<&str>) -> io::Result<(Manifest, HttpResponse)> {
+    let mut target = format!("{}/objects/{}/manifest", prefix, to_hex(object_id));
+    if let Some(token) = token {
+        target.push_str(&format!("?token={}", token));
+    }
+
+    let mut response = get(addr, &target)?;
+    check_status(response.status)?;
+
+    let manifest = Manifest::read_from(&response.body[..])?;
+    if manifest.object_id != *object_id {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "origin returned the manifest of another object"));
+    }
+    response.body.clear();
+    return Ok((manifest, response));
+}
+
+/// Asks a peer for other peers of an object, see server::pex, announcing the caller at announce, e.g. the address
+/// it serves the object at, if given. Returns the peer list as answered, to be checked with read_peer_list. Takes the
+/// same prefix and token as fetch_manifest, and fails the same way, with ErrorKind::NotFound too if the peer knows of
+/// no other peers or does not exchange them.
+pub fn fetch_peers<A: ToSocketAddrs>(addr: A, prefix: &str, object_id: &ObjectId, token: Option<&str>, announce: Option<&str>) -> io::Result<String> {
+    let mut query: Vec<String> = Vec::new();
+    if let Some(announce) = announce {
+        query.push(format!("announce={}", announce));
+    }
+    if let Some(token) = token {
+        query.push(format!("token={}", token));
+    }
+    let mut target = format!("{}/objects/{}/peers", prefix, to_hex(object_id));
+    if !query.is_empty() {
+        target.push_str(&format!("?{}", query.join("&")));
+    }
+
+    let response = get(addr, &target)?;
+    check_status(response.status)?;
+    return match String::from_utf8(response.body) {
+        Ok(body) => Ok(body),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "peer list is not text")),
+    };
+}
+
+/// Sends a signed purge notice, see server::purge, to a server started with accept_purges, returning the names of
+/// the files it deleted. Fails with ErrorKind::PermissionDenied if the server does not accept the notice's
+/// signature, and ErrorKind::NotFound if it does not take purges at all. A control plane sends a notice to every
+/// server holding the object.
+pub fn send_purge<A: ToSocketAddrs>(addr: A, notice: &str) -> io::Result<Vec<String>> {
+    let response = get(addr, &format!("/purge?notice={}", notice))?;
+    match response.status {
+        200 => (),
+        404 => return Err(io::Error::new(io::ErrorKind::NotFound, "server does not take purges")),
+        403 => return Err(io::Error::new(io::ErrorKind::PermissionDenied, "server refused the notice's signature")),
+        status => return Err(io::Error::other(format!("server answered {}", status))),
+    }
+    return match String::from_utf8(response.body) {
+        Ok(body) => Ok(body.lines().map(|x| x.to_string()).collect()),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "purge response is not text")),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::EncoderConfig;
+    use crate::server::catalog::Catalog;
+    use crate::server::http::HttpServer;
+    use crate::server::purge::{PurgeKey, PurgeNotice};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_read_body() {
+        let response = |headers: &[(&str, &str)]| HttpResponse {
+            status: 200,
+            headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: Vec::new(),
+        };
+
+        let sized = response(&[("Content-Length", "5")]);
+        assert_eq!(read_body(&sized, &b"hello, and more"[..], 10).unwrap(), b"hello");
+        assert_eq!(read_body(&sized, &b"hell"[..], 10).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(read_body(&sized, &b"hello"[..], 4).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let unknown_length = response(&[]);
+        assert_eq!(read_body(&unknown_length, &b"hello"[..], 5).unwrap(), b"hello");
+        assert_eq!(read_body(&unknown_length, &b"hello!"[..], 5).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // chunked bodies are capped on their running total, whatever the size of each chunk
+        let chunked = response(&[("Transfer-Encoding", "chunked")]);
+        assert_eq!(read_body(&chunked, &b"3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n"[..], 5).unwrap(), b"hello");
+        assert_eq!(read_body(&chunked, &b"3\r\nhel\r\n3\r\nlo!\r\n0\r\n\r\n"[..], 5).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_symbol_stream() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-stream-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(100 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let object_id = catalog.list()[0].manifest.object_id;
+        let server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+
+        let manifest = fetch_manifest(addr, "", &object_id, None).unwrap();
+        let mut decoder = RaptorQDecoder::from_manifest(&manifest).unwrap();
+
+        // 40 symbols at 100KB/s take about half a second, paced after the first chunk
+        let start = Instant::now();
+        let mut stream = open_symbol_stream(addr, "", &object_id, None, None, 100 * 1000, Some(40)).unwrap();
+        assert!(!stream.decode_into(&mut decoder).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        // the rest comes from the same session, stopping as soon as the object is decoded
+        let session_id = stream.get_session_id();
+        let mut stream = open_symbol_stream(addr, "", &object_id, None, Some(session_id), 10 * 1000 * 1000, None).unwrap();
+        assert!(stream.decode_into(&mut decoder).unwrap());
+        assert_eq!(decoder.get_result(), Some(data));
+        let stats = stream.get_transfer_stats(&decoder);
+        assert_eq!(stats.peers[&addr.to_string()].symbols, stats.symbols_received);
+        assert!(stats.symbols_received > 0 && stats.duplicate_ratio == 0.0);
+
+        let error = open_symbol_stream(addr, "", &object_id, None, Some(session_id + 1), 1000, None).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_send_purge() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-purge-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("object"), gen_data(100 * 1000)).unwrap();
+
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let object_id = catalog.list()[0].manifest.object_id;
+        let key = PurgeKey::new(b"0123456789abcdef0123456789abcdef");
+        let mut server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        server.accept_purges(key.clone());
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+
+        let notice = key.sign(&PurgeNotice::new(object_id, "takedown"));
+        let forged = PurgeKey::new(b"another key").sign(&PurgeNotice::new(object_id, "takedown"));
+        assert_eq!(send_purge(addr, &forged).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+
+        // a stream open at the time of the purge ends early
+        let mut stream = open_symbol_stream(addr, "", &object_id, None, None, 100 * 1000, None).unwrap();
+        assert!(stream.next().unwrap().is_ok());
+        assert_eq!(send_purge(addr, &notice).unwrap(), vec!["object".to_string()]);
+        assert!(stream.all(|x| x.is_ok()));
+        assert!(!root.join("object").exists());
+        assert_eq!(fetch_manifest(addr, "", &object_id, None).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        // purging again is harmless
+        assert!(send_purge(addr, &notice).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! Fetching an object over two paths: the manifest from an origin over HTTP, and symbols over UDP, e.g. multicast
+//! from local peers or a carousel. Only the manifest needs to come from someone trusted. Symbols from the UDP side
+//! are not, so each decoded block is checked against its hash in the manifest and decoded again from later symbols
+//! if it does not match, and the object is checked against its id before it is handed out.
+
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Seek};
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use crate::codec::decoder::RaptorQDecoder;
+use crate::codec::manifest::{validate_manifest, DecoderLimits, Manifest, ObjectId};
+use crate::store::object_store::{put_decoded_blocks, ObjectStore};
+use crate::store::resume::ResumeToken;
+use crate::store::warm::warm_start;
+use crate::transport::net::canonical_addr;
+use crate::transport::udp::{decode_datagram, FlowId};
+use super::http::fetch_manifest;
+use super::stats::{TransferRecorder, TransferStats};
+
+/// Largest datagram recv_from accepts.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// Counts of what a MixedFetch did with the datagrams it got.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MixedFetchStats {
+    /// Symbols of the object fed to the decoder.
+    pub symbols: u64,
+    /// Datagrams of other flows, malformed or rejected by the decoder.
+    pub ignored: u64,
+    /// Blocks that decoded to something other than their hash in the manifest and were decoded again.
+    pub rejected_blocks: u64,
+}
+
+/// Decodes an object from symbols received over UDP, as flow flow_id, against a manifest fetched over HTTP.
+pub struct MixedFetch {
+    manifest: Manifest,
+    flow_id: FlowId,
+    decoder: RaptorQDecoder,
+    /// Whether each block was decoded and matched its hash.
+    verified: Vec<bool>,
+    /// The object, once every block is verified and it matches its id.
+    result: Option<Vec<u8>>,
+    stats: MixedFetchStats,
+    recorder: TransferRecorder,
+}
+
+impl MixedFetch {
+    /// Fetches the manifest of object_id from an origin, see fetch_manifest, and gets ready for its symbols.
+    pub fn start<A: ToSocketAddrs>(origin: A, prefix: &str, object_id: &ObjectId, token: Option<&str>, flow_id: FlowId) -> io::Result<MixedFetch> {
+        let manifest = fetch_manifest(origin, prefix, object_id, token)?;
+        return MixedFetch::new(manifest, flow_id, &DecoderLimits::default());
+    }
+
+    /// Gets ready for the symbols of the object described by a manifest obtained some other way. Fails with
+    /// ErrorKind::InvalidData if the manifest does not pass validate_manifest with limits.
+    pub fn new(manifest: Manifest, flow_id: FlowId, limits: &DecoderLimits) -> io::Result<MixedFetch> {
+        if let Err(issues) = validate_manifest(&manifest, limits) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad manifest: {:?}", issues)));
+        }
+        let decoder = match RaptorQDecoder::from_manifest(&manifest) {
+            Ok(decoder) => decoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad manifest: {:?}", error))),
+        };
+
+        let verified = vec![false; manifest.get_block_count()];
+        return Ok(MixedFetch {
+            manifest,
+            flow_id,
+            decoder,
+            verified,
+            result: None,
+            stats: MixedFetchStats::default(),
+            recorder: TransferRecorder::new(),
+        });
+    }
+
+    /// Feeds a datagram, returning whether the object is decoded and verified. Datagrams of other flows are ignored.
+    /// Its symbols count toward peer "" in transfer stats, see receive_from.
+    pub fn receive(&mut self, datagram: &[u8]) -> bool {
+        return self.receive_from("", datagram);
+    }
+
+    /// Feeds a datagram as receive does, its symbols counting toward peer in transfer stats.
+    pub fn receive_from(&mut self, peer: &str, datagram: &[u8]) -> bool {
+        if self.result.is_some() {
+            return true;
+        }
+        let block = match decode_datagram(datagram) {
+            Ok((flow_id, _, block)) if flow_id == self.flow_id => block,
+            _ => {
+                self.stats.ignored += 1;
+                return false;
+            },
+        };
+
+        let block_id = block.block_id as usize;
+        if self.verified.get(block_id).is_none_or(|x| *x) {
+            // already verified blocks need nothing more, and unknown blocks are rejected as the decoder would
+            self.stats.ignored += (block_id >= self.verified.len()) as u64;
+            return false;
+        }
+        if self.recorder.consume(&mut self.decoder, peer, vec![block]).is_err() {
+            self.stats.ignored += 1;
+            return false;
+        }
+        self.stats.symbols += 1;
+
+        let verified = match self.decoder.get_block_result(block_id as u32) {
+            None => return false,
+            Some(data) => Sha256::digest(data)[..] == self.manifest.block_hashes[block_id][..],
+        };
+        if !verified {
+            // some symbol was bad, hope the next ones are not
+            self.decoder.reset_block(block_id as u32).unwrap();
+            self.stats.rejected_blocks += 1;
+            return false;
+        }
+        self.verified[block_id] = true;
+        return self.check_complete();
+    }
+
+    /// Restores the blocks a local copy of the object already holds, see warm_start, returning how many were
+    /// restored and whether the object is now complete. Call before receiving, so only the other blocks need symbols.
+    pub fn warm_start<R: Read + Seek>(&mut self, local: R) -> io::Result<(usize, bool)> {
+        let restored = warm_start(&self.manifest, &mut self.decoder, local)?;
+        for block_id in restored.iter() {
+            self.verified[*block_id as usize] = true;
+        }
+        return Ok((restored.len(), self.check_complete()));
+    }
+
+    /// Picks up a fetch interrupted after save_progress, restoring the blocks token records as decoded from store,
+    /// see ResumeToken::restore. Returns how many blocks were restored and whether the object is now complete.
+    /// Call before receiving, so only the other blocks need symbols.
+    pub fn resume(&mut self, token: &ResumeToken, store: &dyn ObjectStore) -> io::Result<(usize, bool)> {
+        let restored = token.restore(&self.manifest, &mut self.decoder, store)?;
+        for block_id in restored.iter() {
+            self.verified[*block_id as usize] = true;
+        }
+        return Ok((restored.len(), self.check_complete()));
+    }
+
+    /// Puts the blocks decoded so far in store, where the object must be open, see ObjectStore::open, and records
+    /// how far the fetch got, to persist and resume later. Only verified blocks are decoded, others being reset.
+    pub fn save_progress(&self, store: &dyn ObjectStore) -> io::Result<ResumeToken> {
+        put_decoded_blocks(store, &self.manifest.object_id, &self.decoder)?;
+        return Ok(ResumeToken::new(&self.manifest, &self.decoder, Vec::new()));
+    }
+
+    /// Sets the result once every block is verified, returning whether it is set.
+    fn check_complete(&mut self) -> bool {
+        if self.result.is_none() && self.verified.iter().all(|x| *x) {
+            // the blocks match the manifest, this only catches a manifest inconsistent with its own object id
+            let data = self.decoder.get_result().unwrap();
+            if Sha256::digest(&data)[..] == self.manifest.object_id[..] {
+                self.result = Some(data);
+            }
+        }
+        return self.result.is_some();
+    }
+
+    /// Receives one datagram from socket and feeds it as receive_from does, the sender named by its address, IPv4
+    /// senders the same whether the socket is dual-stack or not.
+    pub fn recv_from(&mut self, socket: &UdpSocket) -> io::Result<bool> {
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+        let (len, addr) = socket.recv_from(&mut buf)?;
+        return Ok(self.receive_from(&canonical_addr(addr).to_string(), &buf[..len]));
+    }
+
+    /// Whether every block was decoded and matched its hash, but the object did not match its id, so the manifest
+    /// itself is bad and no symbols will help.
+    pub fn is_manifest_inconsistent(&self) -> bool {
+        return self.result.is_none() && self.verified.iter().all(|x| *x);
+    }
+
+    /// Gets the object, once receive returned true.
+    pub fn get_result(&self) -> Option<&[u8]> {
+        return self.result.as_deref();
+    }
+
+    pub fn get_manifest(&self) -> &Manifest {
+        return &self.manifest;
+    }
+
+    pub fn get_stats(&self) -> MixedFetchStats {
+        return self.stats;
+    }
+
+    /// Summarizes the fetch so far, its wall time counted from start or new.
+    pub fn get_transfer_stats(&self) -> TransferStats {
+        return self.recorder.get_stats(&self.decoder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::{EncodedBlock, EncoderConfig, RaptorQEncoder};
+    use crate::server::catalog::Catalog;
+    use crate::server::http::HttpServer;
+    use crate::store::memory::MemoryStore;
+    use crate::transport::udp::{encode_datagram, send_flow, Integrity};
+    use raptorq::EncodingPacket;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mixed_fetch() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-mixed-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(50 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let entry = catalog.list().remove(0);
+        let object_id = entry.manifest.object_id;
+        let server = HttpServer::bind("127.0.0.1:0", catalog.clone()).unwrap();
+        let origin = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+
+        let mut fetch = MixedFetch::start(origin, "", &object_id, None, 9).unwrap();
+        assert_eq!(MixedFetch::start(origin, "", &[0; 32], None, 9).err().map(|x| x.kind()), Some(io::ErrorKind::NotFound));
+
+        // a peer sends corrupt symbols first, the block is decoded again from the good ones that follow
+        let encoder = entry.producer.lock().unwrap().get_encoder().clone();
+        let blocks = encoder.generate_encoded_blocks();
+        let corrupt: Vec<EncodedBlock> = encoder.get_block_encoders()[0].generate_source_blocks().iter().map(|x| EncodedBlock {
+            block_id: x.block_id,
+            data: EncodingPacket::new(x.data.payload_id().clone(), vec![0x5a; x.data.data().len()]),
+        }).collect();
+        for block in corrupt.iter() {
+            assert!(!fetch.receive(&encode_datagram(9, block, Integrity::None)));
+        }
+        assert!(!fetch.receive(&encode_datagram(8, &blocks[0], Integrity::None)));
+        assert_eq!(fetch.get_stats().rejected_blocks, 1);
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        send_flow(&sender, receiver.local_addr().unwrap(), 9, &blocks, Integrity::Crc32c).unwrap();
+        while !fetch.recv_from(&receiver).unwrap() {}
+        assert_eq!(fetch.get_result(), Some(&data[..]));
+        assert_eq!(fetch.get_stats().ignored, 1);
+        let stats = fetch.get_transfer_stats();
+        assert_eq!(stats.peers[""].symbols, corrupt.len() as u64);
+        assert_eq!(stats.peers[&sender.local_addr().unwrap().to_string()].symbols, stats.symbols_received - corrupt.len() as u64);
+
+        // a complete local copy needs no symbols at all
+        let mut fetch = MixedFetch::new(fetch.get_manifest().clone(), 9, &DecoderLimits::default()).unwrap();
+        assert_eq!(fetch.warm_start(std::io::Cursor::new(&data)).unwrap(), (entry.manifest.get_block_count(), true));
+        assert_eq!(fetch.get_result(), Some(&data[..]));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn test_mixed_fetch_resume() {
+        let data = gen_data(30 * 1000);
+        let config = EncoderConfig { max_block_symbols: 10, ..EncoderConfig::new(1280) };
+        let encoder = match RaptorQEncoder::with_config(config, &[std::io::IoSlice::new(&data)]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let manifest = Manifest::new(&encoder);
+        assert_eq!(manifest.get_block_count(), 3);
+
+        // the fetch is interrupted once the first block is decoded
+        let mut fetch = MixedFetch::new(manifest.clone(), 9, &DecoderLimits::default()).unwrap();
+        for block in encoder.get_block_encoders()[0].generate_source_blocks().iter() {
+            assert!(!fetch.receive(&encode_datagram(9, block, Integrity::None)));
+        }
+        let store = MemoryStore::new();
+        store.open(&manifest).unwrap();
+        let token = fetch.save_progress(&store).unwrap();
+        assert_eq!(token.get_decoded_count(), 1);
+
+        // the other blocks are all a new fetch needs
+        let mut fetch = MixedFetch::new(manifest.clone(), 9, &DecoderLimits::default()).unwrap();
+        assert_eq!(fetch.resume(&token, &store).unwrap(), (1, false));
+        let mut done = false;
+        for block_encoder in encoder.get_block_encoders()[1..].iter() {
+            for block in block_encoder.generate_source_blocks().iter() {
+                done = fetch.receive(&encode_datagram(9, block, Integrity::None));
+            }
+        }
+        assert!(done);
+        assert_eq!(fetch.get_result(), Some(&data[..]));
+        assert_eq!(fetch.get_stats().symbols, (data.len() as u64 - 10 * 1280).div_ceil(1280));
+    }
+}
@@ -0,0 +1,11 @@
+pub mod conformance;
+pub mod events;
+pub mod fetch;
+pub mod http;
+pub mod mixed;
+pub mod preflight;
+pub mod reconcile;
+pub mod schedule;
+pub mod stats;
+pub mod telemetry;
+pub mod transfers;
@@ -0,0 +1,104 @@
+//! Checking that an object fits before fetching it, so a transfer that can't finish fails before any symbol is
+//! requested rather than when memory or disk runs out halfway.
+
+use std::io;
+
+use crate::codec::manifest::{validate_manifest, DecoderLimits, Manifest, ManifestIssue};
+use crate::store::object_store::ObjectStore;
+
+/// Why an object can't be fetched here, see preflight.
+#[derive(Debug)]
+pub enum PreflightError {
+    /// The manifest does not pass validate_manifest, e.g. as the object is larger than the limits allow.
+    Manifest(Vec<ManifestIssue>),
+    /// The store has less space left than the object takes.
+    DiskSpace { needed: u64, available: u64 },
+    /// The store could not tell how much space it has left.
+    Store(io::Error),
+}
+
+/// Checks the object described by manifest against limits, which cover its size and the memory decoding takes, and
+/// against the space left in store, if the object is to be put there. An object the store already holds part of
+/// counts in full.
+pub fn preflight(manifest: &Manifest, limits: &DecoderLimits, store: Option<&dyn ObjectStore>) -> Result<(), PreflightError> {
+    validate_manifest(manifest, limits).map_err(PreflightError::Manifest)?;
+
+    let available = match store.map(|x| x.get_available_space()) {
+        None | Some(Ok(None)) => return Ok(()),
+        Some(Ok(Some(available))) => available,
+        Some(Err(error)) => return Err(PreflightError::Store(error)),
+    };
+    if manifest.data_size > available {
+        return Err(PreflightError::DiskSpace { needed: manifest.data_size, available });
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoder::RaptorQEncoder;
+    use crate::codec::manifest::ObjectId;
+    use crate::store::file::FileStore;
+    use crate::store::memory::MemoryStore;
+    use crate::store::object_store::unknown_object;
+
+    /// A store that is nearly full, so nothing put in it fits and it holds nothing.
+    struct FullStore;
+
+    impl ObjectStore for FullStore {
+        fn open(&self, _manifest: &Manifest) -> io::Result<()> {
+            return Err(io::Error::other("store is full"));
+        }
+
+        fn put_block(&self, _object_id: &ObjectId, _block_id: u32, _data: &[u8]) -> io::Result<()> {
+            return Err(unknown_object());
+        }
+
+        fn get_range(&self, _object_id: &ObjectId, _offset: u64, _len: usize) -> io::Result<Vec<u8>> {
+            return Err(unknown_object());
+        }
+
+        fn finalize(&self, _object_id: &ObjectId) -> io::Result<()> {
+            return Err(unknown_object());
+        }
+
+        fn delete(&self, _object_id: &ObjectId) -> io::Result<bool> {
+            return Ok(false);
+        }
+
+        fn get_available_space(&self) -> io::Result<Option<u64>> {
+            return Ok(Some(1000));
+        }
+    }
+
+    #[test]
+    fn test_preflight() {
+        let data = vec![7; 50 * 1000];
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let manifest = Manifest::new(&encoder);
+        let limits = DecoderLimits::default();
+
+        assert!(preflight(&manifest, &limits, None).is_ok());
+        assert!(preflight(&manifest, &limits, Some(&MemoryStore::new())).is_ok());
+        let root = std::env::temp_dir().join(format!("raptorcdn-preflight-test-{}", std::process::id()));
+        let store = FileStore::new(&root).unwrap();
+        assert!(preflight(&manifest, &limits, Some(&store)).is_ok());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        match preflight(&manifest, &limits, Some(&FullStore)) {
+            Err(PreflightError::DiskSpace { needed, available }) => assert_eq!((needed, available), (50 * 1000, 1000)),
+            other => panic!("expected a disk space error, got {:?}", other),
+        }
+        assert!(FullStore.open(&manifest).is_err());
+        assert_eq!(FullStore.get_range(&manifest.object_id, 0, 1).unwrap_err().kind(), io::ErrorKind::NotFound);
+        let limits = DecoderLimits { max_memory: 64 * 1000, ..DecoderLimits::default() };
+        match preflight(&manifest, &limits, None) {
+            Err(PreflightError::Manifest(issues)) => assert!(matches!(issues[..], [ManifestIssue::Memory { max: 64000, .. }])),
+            other => panic!("expected a manifest error, got {:?}", other),
+        }
+    }
+}
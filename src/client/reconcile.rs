@@ -0,0 +1,190 @@
+//! Choosing one manifest when several sources give manifests of the same object that differ, e.g. caches that
+//! encoded it with other packet sizes or overheads, or an origin that re-encoded it. Any of them decodes to the
+//! object if honest, but symbols only fit the manifest they were encoded for, so a fetch has to settle on one. A
+//! ReconcilePolicy says which, or that differing manifests are an error.
+
+use std::io;
+use std::str::FromStr;
+
+use crate::codec::manifest::{Manifest, ObjectId};
+use crate::server::manifest_key::ManifestKey;
+use super::http::fetch_manifest_response;
+
+/// How to choose between differing manifests of an object.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReconcilePolicy {
+    /// Every source has to give the same manifest.
+    #[default]
+    StrictEqual,
+    /// Take the manifest of the sources with a valid signature, which have to agree. Without any, every source has
+    /// to give the same manifest.
+    PreferSigned,
+    /// Take the manifest issued last. Sources without an issue time count as older than any; differing manifests
+    /// issued at the same time are a conflict.
+    NewestWins,
+}
+
+impl FromStr for ReconcilePolicy {
+    type Err = String;
+
+    /// Parses strict-equal, prefer-signed or newest-wins.
+    fn from_str(policy: &str) -> Result<ReconcilePolicy, String> {
+        match policy {
+            "strict-equal" => return Ok(ReconcilePolicy::StrictEqual),
+            "prefer-signed" => return Ok(ReconcilePolicy::PreferSigned),
+            "newest-wins" => return Ok(ReconcilePolicy::NewestWins),
+            _ => return Err(format!("unknown manifest policy {}, expected strict-equal, prefer-signed or newest-wins", policy)),
+        }
+    }
+}
+
+/// A manifest as one source gave it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestCandidate {
+    /// Where it came from, e.g. "host:port".
+    pub source: String,
+    pub manifest: Manifest,
+    /// Whether it came with a valid signature, see server::manifest_key.
+    pub signed: bool,
+    /// When it was issued, in seconds since the unix epoch, if the source said.
+    pub issued: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReconcileError {
+    /// No source gave a manifest of the object.
+    NoManifest,
+    /// Sources gave differing manifests the policy can't choose between, named by source.
+    Conflict(Vec<String>),
+}
+
+/// The manifest chosen, and who agreed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reconciled {
+    pub manifest: Manifest,
+    /// Sources that gave the manifest chosen, in the order given, e.g. the peers to fetch from.
+    pub sources: Vec<String>,
+    /// Sources that gave another manifest, or one of another object.
+    pub rejected: Vec<String>,
+}
+
+/// Chooses a manifest of object_id among candidates as policy says. Candidates of other objects are rejected
+/// whatever the policy.
+pub fn reconcile(object_id: &ObjectId, candidates: Vec<ManifestCandidate>, policy: ReconcilePolicy) -> Result<Reconciled, ReconcileError> {
+    let (candidates, mut rejected): (Vec<ManifestCandidate>, Vec<ManifestCandidate>) = candidates.into_iter().partition(|x| x.manifest.object_id == *object_id);
+    if candidates.is_empty() {
+        return Err(ReconcileError::NoManifest);
+    }
+
+    let newest = candidates.iter().map(|x| x.issued).max().unwrap();
+    let pool: Vec<&ManifestCandidate> = match policy {
+        ReconcilePolicy::StrictEqual => candidates.iter().collect(),
+        ReconcilePolicy::PreferSigned if candidates.iter().any(|x| x.signed) => candidates.iter().filter(|x| x.signed).collect(),
+        ReconcilePolicy::PreferSigned => candidates.iter().collect(),
+        ReconcilePolicy::NewestWins => candidates.iter().filter(|x| x.issued == newest).collect(),
+    };
+    if pool.iter().any(|x| x.manifest != pool[0].manifest) {
+        return Err(ReconcileError::Conflict(pool.iter().map(|x| x.source.clone()).collect()));
+    }
+
+    let manifest = pool[0].manifest.clone();
+    let (agreed, differing): (Vec<ManifestCandidate>, Vec<ManifestCandidate>) = candidates.into_iter().partition(|x| x.manifest == manifest);
+    rejected.extend(differing);
+    return Ok(Reconciled {
+        manifest,
+        sources: agreed.into_iter().map(|x| x.source).collect(),
+        rejected: rejected.into_iter().map(|x| x.source).collect(),
+    });
+}
+
+/// Fetches the manifest of an object from a server, see fetch_manifest, checking its signature with key if given.
+pub fn fetch_candidate(addr: &str, prefix: &str, object_id: &ObjectId, token: Option<&str>, key: Option<&ManifestKey>) -> io::Result<ManifestCandidate> {
+    let (manifest, response) = fetch_manifest_response(addr, prefix, object_id, token)?;
+    let issued: Option<u64> = response.get_header("X-Manifest-Issued").and_then(|x| x.parse().ok());
+    let signature = response.get_header("X-Manifest-Signature");
+    let signed = match (key, issued, signature) {
+        (Some(key), Some(issued), Some(signature)) => key.verify(&manifest, issued, signature),
+        _ => false,
+    };
+    return Ok(ManifestCandidate { source: addr.to_string(), manifest, signed, issued });
+}
+
+/// Fetches the manifest of an object from every peer that has it, e.g. "host:port", and chooses one as policy says.
+/// Fails with ErrorKind::NotFound if none has it, and with ErrorKind::InvalidData on a conflict.
+pub fn fetch_reconciled_manifest(peers: &[String], prefix: &str, object_id: &ObjectId, token: Option<&str>, key: Option<&ManifestKey>, policy: ReconcilePolicy) -> io::Result<Reconciled> {
+    let candidates: Vec<ManifestCandidate> = peers.iter().filter_map(|x| fetch_candidate(x, prefix, object_id, token, key).ok()).collect();
+    match reconcile(object_id, candidates, policy) {
+        Ok(reconciled) => return Ok(reconciled),
+        Err(ReconcileError::NoManifest) => return Err(io::Error::new(io::ErrorKind::NotFound, "no peer has the object")),
+        Err(ReconcileError::Conflict(sources)) => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("peers gave differing manifests: {}", sources.join(", "))));
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::EncoderConfig;
+    use crate::server::catalog::Catalog;
+    use crate::server::http::HttpServer;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reconcile() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-reconcile-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(20 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+
+        // two servers encoding the object alike and one with another packet size, which signs its manifests
+        let key = ManifestKey::new(b"0123456789abcdef0123456789abcdef");
+        let mut addrs: Vec<String> = Vec::new();
+        let mut object_id: ObjectId = [0; 32];
+        for (packet_size, signs) in [(1280, false), (1280, false), (640, true)] {
+            let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(packet_size)));
+            catalog.refresh().unwrap();
+            object_id = catalog.list()[0].manifest.object_id;
+            let mut server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+            if signs {
+                server.sign_manifests(key.clone());
+            }
+            addrs.push(server.local_addr().unwrap().to_string());
+            std::thread::spawn(move || server.run());
+        }
+
+        let candidates: Vec<ManifestCandidate> = addrs.iter().map(|x| fetch_candidate(x, "", &object_id, None, Some(&key)).unwrap()).collect();
+        assert_eq!(candidates.iter().map(|x| x.signed).collect::<Vec<bool>>(), vec![false, false, true]);
+        assert!(candidates.iter().all(|x| x.issued.is_some()));
+        let conflict = reconcile(&object_id, candidates.clone(), ReconcilePolicy::StrictEqual);
+        assert_eq!(conflict, Err(ReconcileError::Conflict(addrs.clone())));
+        let signed = reconcile(&object_id, candidates.clone(), ReconcilePolicy::PreferSigned).unwrap();
+        assert_eq!((signed.manifest.config.packet_size, signed.sources.clone(), signed.rejected.clone()), (640, vec![addrs[2].clone()], addrs[..2].to_vec()));
+        let strict = reconcile(&object_id, candidates[..2].to_vec(), ReconcilePolicy::StrictEqual).unwrap();
+        assert_eq!(strict.sources, addrs[..2].to_vec());
+        assert_eq!(fetch_reconciled_manifest(&addrs, "", &object_id, None, None, ReconcilePolicy::PreferSigned).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // the newest issued wins, a tie between differing manifests is a conflict
+        let mut dated = candidates.clone();
+        dated[0].issued = Some(200);
+        dated[1].issued = None;
+        dated[2].issued = Some(100);
+        let newest = reconcile(&object_id, dated.clone(), ReconcilePolicy::NewestWins).unwrap();
+        assert_eq!((newest.sources, newest.rejected), (addrs[..2].to_vec(), vec![addrs[2].clone()]));
+        dated[2].issued = Some(200);
+        assert!(matches!(reconcile(&object_id, dated, ReconcilePolicy::NewestWins), Err(ReconcileError::Conflict(_))));
+
+        // a manifest of another object or a forged signature doesn't count
+        let mut other = candidates[2].clone();
+        other.manifest.object_id = [9; 32];
+        other.source = "other".to_string();
+        let reconciled = reconcile(&object_id, vec![candidates[0].clone(), other], ReconcilePolicy::StrictEqual).unwrap();
+        assert_eq!(reconciled.rejected, vec!["other".to_string()]);
+        assert!(!key.verify(&candidates[0].manifest, candidates[2].issued.unwrap(), &key.sign(&candidates[2].manifest, candidates[2].issued.unwrap()).unwrap()));
+        assert_eq!(reconcile(&object_id, Vec::new(), ReconcilePolicy::NewestWins), Err(ReconcileError::NoManifest));
+        assert_eq!("newest-wins".parse(), Ok(ReconcilePolicy::NewestWins));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
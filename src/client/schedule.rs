@@ -0,0 +1,189 @@
+//! Policies deciding which peer a Fetch asks for symbols of which block next. Implement SchedulePolicy to try
+//! another one; RoundRobin is the default.
+
+use std::time::Duration;
+
+use crate::codec::decoder::BlockNeeds;
+use super::stats::PeerContribution;
+
+/// What a Fetch knows about one of its peers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeerState {
+    /// Address the peer is reached at, e.g. "host:port".
+    pub addr: String,
+    /// Which blocks the peer holds, by block id, or None if it holds every block.
+    pub blocks: Option<Vec<bool>>,
+    pub requests: u64,
+    pub contribution: PeerContribution,
+    /// Smoothed time a request took, None before the first answer.
+    pub latency: Option<Duration>,
+    /// Smoothed symbol bytes per second of answers, None before the first.
+    pub bytes_per_sec: Option<f64>,
+    /// Whether a request failed, after which the peer is not asked again.
+    pub failed: bool,
+}
+
+impl PeerState {
+    /// Whether the peer can be asked for symbols of a block.
+    pub fn can_serve(&self, block_id: u32) -> bool {
+        return !self.failed && self.blocks.as_ref().is_none_or(|x| x.get(block_id as usize).is_some_and(|x| *x));
+    }
+
+    /// Gets the first block of needs the peer can serve.
+    fn first_servable(&self, needs: &[BlockNeeds]) -> Option<u32> {
+        return needs.iter().map(|x| x.block_id).find(|x| self.can_serve(*x));
+    }
+}
+
+/// Ask peers[peer] for symbols of block_id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScheduledRequest {
+    pub peer: usize,
+    pub block_id: u32,
+}
+
+/// Picks the next request of a Fetch from the blocks not decoded yet, see RaptorQDecoder::get_block_needs, and what
+/// is known of the peers. Returns None if no peer can serve any of the blocks.
+pub trait SchedulePolicy: Send {
+    fn next_request(&mut self, needs: &[BlockNeeds], peers: &[PeerState]) -> Option<ScheduledRequest>;
+}
+
+/// Takes turns among the peers, asking each for the first block it can serve.
+#[derive(Clone, Debug, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl SchedulePolicy for RoundRobin {
+    fn next_request(&mut self, needs: &[BlockNeeds], peers: &[PeerState]) -> Option<ScheduledRequest> {
+        for i in 0..peers.len() {
+            let peer = (self.next + i) % peers.len();
+            if let Some(block_id) = peers[peer].first_servable(needs) {
+                self.next = peer + 1;
+                return Some(ScheduledRequest { peer, block_id });
+            }
+        }
+        return None;
+    }
+}
+
+/// Asks for the block the fewest peers hold first, most needed first among those, so blocks that could become
+/// unavailable are fetched while they can be. Takes turns among the peers holding it.
+#[derive(Clone, Debug, Default)]
+pub struct RarestFirst {
+    next: usize,
+}
+
+impl SchedulePolicy for RarestFirst {
+    fn next_request(&mut self, needs: &[BlockNeeds], peers: &[PeerState]) -> Option<ScheduledRequest> {
+        let holders = |block_id: u32| peers.iter().filter(|x| x.can_serve(block_id)).count();
+        let rarest = needs.iter()
+            .filter(|x| holders(x.block_id) > 0)
+            .min_by_key(|x| (holders(x.block_id), std::cmp::Reverse(x.symbols_needed), x.block_id))?;
+
+        let peer = (0..peers.len()).map(|x| (self.next + x) % peers.len()).find(|x| peers[*x].can_serve(rarest.block_id))?;
+        self.next = peer + 1;
+        return Some(ScheduledRequest { peer, block_id: rarest.block_id });
+    }
+}
+
+/// Smooth weighted round robin over the peers that can serve some block, asking each for the first block it can
+/// serve. Peers without a weight yet are asked first, to measure them.
+#[derive(Clone, Debug, Default)]
+struct WeightedRoundRobin {
+    current: Vec<f64>,
+}
+
+impl WeightedRoundRobin {
+    fn next_request<F: Fn(&PeerState) -> Option<f64>>(&mut self, needs: &[BlockNeeds], peers: &[PeerState], weight: F) -> Option<ScheduledRequest> {
+        self.current.resize(peers.len(), 0.0);
+        let servable: Vec<(usize, u32)> = peers.iter()
+            .enumerate()
+            .filter_map(|(peer, state)| state.first_servable(needs).map(|block_id| (peer, block_id)))
+            .collect();
+        if let Some((peer, block_id)) = servable.iter().find(|(peer, _)| weight(&peers[*peer]).is_none()) {
+            return Some(ScheduledRequest { peer: *peer, block_id: *block_id });
+        }
+
+        let mut total: f64 = 0.0;
+        for (peer, _) in servable.iter() {
+            let weight = weight(&peers[*peer]).unwrap();
+            self.current[*peer] += weight;
+            total += weight;
+        }
+        let (peer, block_id) = *servable.iter().max_by(|x, y| self.current[x.0].total_cmp(&self.current[y.0]).then(y.0.cmp(&x.0)))?;
+        self.current[peer] -= total;
+        return Some(ScheduledRequest { peer, block_id });
+    }
+}
+
+/// Asks peers in proportion to how quickly they answer, as the inverse of their latency.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyWeighted {
+    round_robin: WeightedRoundRobin,
+}
+
+impl SchedulePolicy for LatencyWeighted {
+    fn next_request(&mut self, needs: &[BlockNeeds], peers: &[PeerState]) -> Option<ScheduledRequest> {
+        // a microsecond floor keeps a peer on the same host from taking every request
+        return self.round_robin.next_request(needs, peers, |x| x.latency.map(|x| 1.0 / x.as_secs_f64().max(1e-6)));
+    }
+}
+
+/// Asks peers in proportion to the bandwidth they delivered.
+#[derive(Clone, Debug, Default)]
+pub struct BandwidthProportional {
+    round_robin: WeightedRoundRobin,
+}
+
+impl SchedulePolicy for BandwidthProportional {
+    fn next_request(&mut self, needs: &[BlockNeeds], peers: &[PeerState]) -> Option<ScheduledRequest> {
+        return self.round_robin.next_request(needs, peers, |x| x.bytes_per_sec);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn needs(block_ids: &[u32]) -> Vec<BlockNeeds> {
+        return block_ids.iter().map(|x| BlockNeeds { block_id: *x, symbols_needed: 10, received: Vec::new() }).collect();
+    }
+
+    fn peer(blocks: Option<Vec<bool>>) -> PeerState {
+        return PeerState { blocks, ..PeerState::default() };
+    }
+
+    fn schedule<P: SchedulePolicy>(policy: &mut P, needs: &[BlockNeeds], peers: &[PeerState], count: usize) -> Vec<(usize, u32)> {
+        return (0..count).map(|_| policy.next_request(needs, peers).map(|x| (x.peer, x.block_id)).unwrap()).collect();
+    }
+
+    #[test]
+    fn test_schedule_policies() {
+        // peer 0 holds every block, peer 1 only block 1, peer 2 failed
+        let mut peers = vec![peer(None), peer(Some(vec![false, true])), peer(None)];
+        peers[2].failed = true;
+        let needs = needs(&[0, 1]);
+
+        assert_eq!(schedule(&mut RoundRobin::default(), &needs, &peers, 3), vec![(0, 0), (1, 1), (0, 0)]);
+        // block 0 is held by fewer peers
+        assert_eq!(schedule(&mut RarestFirst::default(), &needs, &peers, 2), vec![(0, 0), (0, 0)]);
+        assert_eq!(RoundRobin::default().next_request(&needs[..0], &peers), None);
+        assert_eq!(RarestFirst::default().next_request(&needs[1..], &[peers[2].clone()]), None);
+
+        // unmeasured peers are asked first, then peer 0 three times as often as peer 1
+        let mut policy = BandwidthProportional::default();
+        assert_eq!(schedule(&mut policy, &needs, &peers, 1), vec![(0, 0)]);
+        peers[0].bytes_per_sec = Some(3000.0);
+        assert_eq!(schedule(&mut policy, &needs, &peers, 1), vec![(1, 1)]);
+        peers[1].bytes_per_sec = Some(1000.0);
+        let picks = schedule(&mut policy, &needs, &peers, 8);
+        assert_eq!(picks.iter().filter(|x| x.0 == 0).count(), 6);
+
+        // a peer answering in a third of the time is asked three times as often
+        peers[0].latency = Some(Duration::from_millis(10));
+        peers[1].latency = Some(Duration::from_millis(30));
+        let picks = schedule(&mut LatencyWeighted::default(), &needs, &peers, 8);
+        assert_eq!(picks.iter().filter(|x| x.0 == 0).count(), 6);
+    }
+}
@@ -0,0 +1,175 @@
+//! Summaries of finished transfers, for applications to log and to compare transports and policies with.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::codec::decoder::{RaptorQDecoder, RaptorQDecoderError};
+use crate::codec::encoder::EncodedBlock;
+
+/// What one peer sent during a transfer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerContribution {
+    pub symbols: u64,
+    /// Symbol bytes, without transport headers.
+    pub bytes: u64,
+    /// Symbols that helped decoding, see InnovationStats.
+    pub innovative: u64,
+}
+
+/// Summary of a transfer, see TransferRecorder.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransferStats {
+    pub symbols_received: u64,
+    /// Symbol bytes received, without transport headers.
+    pub bytes_received: u64,
+    /// Fraction of the symbols received that did not help decoding: duplicates, symbols of blocks already decoded
+    /// and malformed symbols.
+    pub duplicate_ratio: f64,
+    /// Distinct symbols the decoded blocks took beyond their source symbols, as a fraction of the source symbols.
+    pub effective_overhead: f64,
+    /// Contribution of each peer, by the name it was recorded under.
+    pub peers: BTreeMap<String, PeerContribution>,
+    /// Time from the recorder's creation until the object was decoded, or until now if it is not.
+    pub wall_time: Duration,
+    /// Time from the recorder's creation until block 0 was decoded, the time to the first usable byte of the
+    /// object. None while it is not.
+    pub first_byte_time: Option<Duration>,
+    /// Time spent in the decoder. Decoding runs on the caller's thread, so this is its decode CPU time.
+    pub decode_time: Duration,
+}
+
+/// Feeds symbols to a decoder on behalf of a fetch, keeping count of what each peer sent and how long decoding took.
+pub struct TransferRecorder {
+    start: Instant,
+    decoded_at: Option<Instant>,
+    first_byte_at: Option<Instant>,
+    decode_time: Duration,
+    /// Sender ids handed to RaptorQDecoder::consume_from, by peer name.
+    sender_ids: HashMap<String, u64>,
+    peers: BTreeMap<String, PeerContribution>,
+}
+
+impl TransferRecorder {
+    /// Creates a recorder, the transfer's wall time starting now.
+    pub fn new() -> TransferRecorder {
+        return TransferRecorder {
+            start: Instant::now(),
+            decoded_at: None,
+            first_byte_at: None,
+            decode_time: Duration::ZERO,
+            sender_ids: HashMap::new(),
+            peers: BTreeMap::new(),
+        };
+    }
+
+    /// Feeds symbols a peer sent to decoder, as RaptorQDecoder::consume does, returning whether it is decoded.
+    /// Peers are named by the caller, e.g. by address.
+    pub fn consume(&mut self, decoder: &mut RaptorQDecoder, peer: &str, blocks: Vec<EncodedBlock>) -> Result<bool, RaptorQDecoderError> {
+        let next_sender_id = self.sender_ids.len() as u64;
+        let sender_id = *self.sender_ids.entry(peer.to_string()).or_insert(next_sender_id);
+        let contribution = self.peers.entry(peer.to_string()).or_default();
+        contribution.symbols += blocks.len() as u64;
+        contribution.bytes += blocks.iter().map(|x| x.data.data().len() as u64).sum::<u64>();
+
+        // the decoder may have seen this sender id from another recorder, so count only what this call adds
+        let innovative = |decoder: &RaptorQDecoder| decoder.get_innovation_stats(sender_id).map_or(0, |x| x.innovative);
+        let innovative_before = innovative(decoder);
+        let before = Instant::now();
+        let result = decoder.consume_from(sender_id, blocks);
+        self.decode_time += before.elapsed();
+
+        contribution.innovative += innovative(decoder) - innovative_before;
+        let now = Instant::now();
+        if result == Ok(true) && self.decoded_at.is_none() {
+            self.decoded_at = Some(now);
+        }
+        // blocks restored from a local copy count once the first symbols arrive
+        if self.first_byte_at.is_none() && decoder.get_block_result(0).is_some() {
+            self.first_byte_at = Some(now);
+        }
+        return result;
+    }
+
+    /// Gets what a peer sent so far, or None if it sent nothing.
+    pub fn get_contribution(&self, peer: &str) -> Option<PeerContribution> {
+        return self.peers.get(peer).copied();
+    }
+
+    /// Summarizes the transfer so far. decoder is the one symbols were fed to.
+    pub fn get_stats(&self, decoder: &RaptorQDecoder) -> TransferStats {
+        let symbols_received: u64 = self.peers.values().map(|x| x.symbols).sum();
+        let innovative: u64 = self.peers.values().map(|x| x.innovative).sum();
+
+        let mut source_symbols: u64 = 0;
+        let mut used_symbols: u64 = 0;
+        for (block_info, stats) in decoder.get_block_info_vec().iter().zip(decoder.get_decode_stats()) {
+            if stats.systematic.is_some() {
+                source_symbols += (block_info.padded_size / block_info.config.symbol_size() as usize) as u64;
+                used_symbols += (stats.source_symbols + stats.repair_symbols) as u64;
+            }
+        }
+
+        return TransferStats {
+            symbols_received,
+            bytes_received: self.peers.values().map(|x| x.bytes).sum(),
+            duplicate_ratio: match symbols_received {
+                0 => 0.0,
+                _ => (symbols_received - innovative) as f64 / symbols_received as f64,
+            },
+            effective_overhead: match source_symbols {
+                0 => 0.0,
+                _ => used_symbols as f64 / source_symbols as f64 - 1.0,
+            },
+            peers: self.peers.clone(),
+            wall_time: self.decoded_at.unwrap_or_else(Instant::now).duration_since(self.start),
+            first_byte_time: self.first_byte_at.map(|x| x.duration_since(self.start)),
+            decode_time: self.decode_time,
+        };
+    }
+}
+
+impl Default for TransferRecorder {
+    fn default() -> TransferRecorder {
+        return TransferRecorder::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::RaptorQEncoder;
+
+    #[test]
+    fn test_transfer_stats() {
+        let data = gen_data(50 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let block_encoder = &encoder.get_block_encoders()[0];
+        let source = block_encoder.generate_source_blocks();
+        let mut decoder = RaptorQDecoder::new(encoder.get_block_info_vec()).unwrap();
+        let mut recorder = TransferRecorder::new();
+
+        // peer a sends the first half of the source symbols twice, peer b repair symbols until decoded
+        recorder.consume(&mut decoder, "a", source[..20].to_vec()).unwrap();
+        recorder.consume(&mut decoder, "a", source[..20].to_vec()).unwrap();
+        let mut start = 0;
+        while !recorder.consume(&mut decoder, "b", block_encoder.generate_repair_blocks(start, 1)).unwrap() {
+            start += 1;
+        }
+        recorder.consume(&mut decoder, "b", block_encoder.generate_repair_blocks(start + 1, 1)).unwrap();
+
+        let stats = recorder.get_stats(&decoder);
+        let b = stats.peers["b"];
+        assert_eq!(stats.peers["a"], PeerContribution { symbols: 40, bytes: 40 * 1280, innovative: 20 });
+        assert_eq!((b.symbols, b.innovative), (start as u64 + 2, start as u64 + 1));
+        assert_eq!(stats.symbols_received, 42 + start as u64);
+        assert_eq!(stats.duplicate_ratio, 21.0 / stats.symbols_received as f64);
+        assert_eq!(stats.effective_overhead, (start as f64 + 21.0) / 40.0 - 1.0);
+        assert!(stats.decode_time <= stats.wall_time);
+        // the only block is the first
+        assert_eq!(stats.first_byte_time, Some(stats.wall_time));
+    }
+}
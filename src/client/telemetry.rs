@@ -0,0 +1,462 @@
+//! Exporting traces and metrics of transfers to an observability stack. A Fetch given a Telemetry reports a span
+//! for the object fetch when it finishes, with a child span for each peer it asked, and adds to the Telemetry's
+//! counters, which are exported as metrics when the application asks. Where they go is up to a TelemetryExporter;
+//! with the otel feature, OtlpExporter sends them to an OpenTelemetry collector over OTLP/HTTP.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use super::stats::TransferStats;
+
+pub type TraceId = [u8; 16];
+pub type SpanId = [u8; 8];
+
+/// Value of an attribute of a span or metric.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeValue {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+/// A finished operation, as in OpenTelemetry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+    /// Span this one is part of, None for the root of a trace.
+    pub parent_span_id: Option<SpanId>,
+    pub name: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(String, AttributeValue)>,
+    /// Why the operation failed, None if it succeeded.
+    pub error: Option<String>,
+}
+
+/// Value of a metric at the time it is exported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetricValue {
+    /// A monotonic count since the Telemetry was created.
+    Sum(u64),
+    /// A value as it is now.
+    Gauge(f64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    /// Unit in UCUM, e.g. "By" or "1".
+    pub unit: String,
+    pub description: String,
+    pub value: MetricValue,
+}
+
+/// Where spans and metrics go, e.g. OtlpExporter, or a log.
+pub trait TelemetryExporter: Send + Sync {
+    fn export_spans(&self, spans: &[Span]) -> io::Result<()>;
+
+    /// Exports metrics sampled at time, which started counting at start.
+    fn export_metrics(&self, metrics: &[Metric], start: SystemTime, time: SystemTime) -> io::Result<()>;
+}
+
+/// Draws a random trace id.
+pub fn gen_trace_id() -> TraceId {
+    return rand::random();
+}
+
+/// Draws a random span id.
+pub fn gen_span_id() -> SpanId {
+    return rand::random();
+}
+
+/// Parses a W3C traceparent header, e.g. 00-<trace id>-<span id>-01, into the trace and span it names, so spans of a
+/// fetch can join the trace of whatever asked for it. None if it is malformed.
+pub fn parse_traceparent(traceparent: &str) -> Option<(TraceId, SpanId)> {
+    let fields: Vec<&str> = traceparent.trim().split('-').collect();
+    let (version, trace_id, span_id) = match fields[..] {
+        [version, trace_id, span_id, flags] if version.len() == 2 && flags.len() == 2 => (version, trace_id, span_id),
+        _ => return None,
+    };
+    if version == "ff" {
+        return None;
+    }
+    let mut trace = [0u8; 16];
+    let mut span = [0u8; 8];
+    if !parse_hex(trace_id, &mut trace) || !parse_hex(span_id, &mut span) {
+        return None;
+    }
+    // all zeros is invalid for both
+    if trace == [0u8; 16] || span == [0u8; 8] {
+        return None;
+    }
+    return Some((trace, span));
+}
+
+/// Parses hex of exactly out's length into out.
+fn parse_hex(hex: &str, out: &mut [u8]) -> bool {
+    if hex.len() != out.len() * 2 || !hex.is_ascii() {
+        return false;
+    }
+    for (i, byte) in out.iter_mut().enumerate() {
+        match u8::from_str_radix(&hex[2 * i..2 * i + 2], 16) {
+            Ok(value) => *byte = value,
+            Err(_) => return false,
+        }
+    }
+    return true;
+}
+
+/// Counters of the transfers of an application, and the exporter spans and metrics go to. Shared by every Fetch of
+/// the application.
+pub struct Telemetry {
+    exporter: Arc<dyn TelemetryExporter>,
+    started: SystemTime,
+    fetches: AtomicU64,
+    failed_fetches: AtomicU64,
+    symbols_received: AtomicU64,
+    bytes_received: AtomicU64,
+    /// Exports that failed, which are dropped rather than failing the transfers.
+    export_errors: AtomicU64,
+}
+
+impl Telemetry {
+    pub fn new(exporter: Arc<dyn TelemetryExporter>) -> Telemetry {
+        return Telemetry {
+            exporter,
+            started: SystemTime::now(),
+            fetches: AtomicU64::new(0),
+            failed_fetches: AtomicU64::new(0),
+            symbols_received: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            export_errors: AtomicU64::new(0),
+        };
+    }
+
+    /// Counts a finished fetch and exports its spans. A failed export is counted, see get_export_errors.
+    pub fn record_fetch(&self, stats: &TransferStats, failed: bool, spans: &[Span]) {
+        self.fetches.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            self.failed_fetches.fetch_add(1, Ordering::Relaxed);
+        }
+        self.symbols_received.fetch_add(stats.symbols_received, Ordering::Relaxed);
+        self.bytes_received.fetch_add(stats.bytes_received, Ordering::Relaxed);
+        if self.exporter.export_spans(spans).is_err() {
+            self.export_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Gets the counters as metrics.
+    pub fn get_metrics(&self) -> Vec<Metric> {
+        let metric = |name: &str, unit: &str, description: &str, value: MetricValue| Metric {
+            name: name.to_string(),
+            unit: unit.to_string(),
+            description: description.to_string(),
+            value,
+        };
+        return vec![
+            metric("raptorcdn.fetch.count", "1", "Object fetches finished", MetricValue::Sum(self.fetches.load(Ordering::Relaxed))),
+            metric("raptorcdn.fetch.failed", "1", "Object fetches that failed", MetricValue::Sum(self.failed_fetches.load(Ordering::Relaxed))),
+            metric("raptorcdn.fetch.symbols", "1", "Symbols received by fetches", MetricValue::Sum(self.symbols_received.load(Ordering::Relaxed))),
+            metric("raptorcdn.fetch.bytes", "By", "Symbol bytes received by fetches", MetricValue::Sum(self.bytes_received.load(Ordering::Relaxed))),
+        ];
+    }
+
+    /// Exports the metrics, e.g. every minute.
+    pub fn export_metrics(&self) -> io::Result<()> {
+        let result = self.exporter.export_metrics(&self.get_metrics(), self.started, SystemTime::now());
+        if result.is_err() {
+            self.export_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        return result;
+    }
+
+    pub fn get_export_errors(&self) -> u64 {
+        return self.export_errors.load(Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "otel")]
+pub use otlp::OtlpExporter;
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use serde_json::{json, Value};
+    use std::io;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use crate::codec::manifest::to_hex;
+    use super::super::http::post;
+    use super::{AttributeValue, Metric, MetricValue, Span, TelemetryExporter};
+
+    /// Span kind of a client, per the OTLP protobuf definitions.
+    const SPAN_KIND_CLIENT: u32 = 3;
+
+    /// Status codes, per the OTLP protobuf definitions.
+    const STATUS_CODE_OK: u32 = 1;
+    const STATUS_CODE_ERROR: u32 = 2;
+
+    /// Aggregation temporality of counts since a fixed start, per the OTLP protobuf definitions.
+    const AGGREGATION_TEMPORALITY_CUMULATIVE: u32 = 2;
+
+    /// Sends spans and metrics to an OpenTelemetry collector over OTLP/HTTP, in its JSON encoding, one request per
+    /// export. Each export waits on the collector, so a slow collector holds up the Fetch reporting to it.
+    pub struct OtlpExporter {
+        /// Address of the collector, e.g. "localhost:4318".
+        addr: String,
+        service_name: String,
+    }
+
+    fn unix_nanos(time: SystemTime) -> String {
+        // int64 fields are strings in the JSON encoding
+        return time.duration_since(UNIX_EPOCH).map_or(0, |x| x.as_nanos()).to_string();
+    }
+
+    fn attribute(key: &str, value: &AttributeValue) -> Value {
+        let value = match value {
+            AttributeValue::Str(value) => json!({ "stringValue": value }),
+            AttributeValue::Int(value) => json!({ "intValue": value.to_string() }),
+            AttributeValue::Float(value) => json!({ "doubleValue": value }),
+            AttributeValue::Bool(value) => json!({ "boolValue": value }),
+        };
+        return json!({ "key": key, "value": value });
+    }
+
+    impl OtlpExporter {
+        /// Creates an exporter sending to the collector at addr, e.g. "localhost:4318", as the service named
+        /// service_name.
+        pub fn new(addr: &str, service_name: &str) -> OtlpExporter {
+            return OtlpExporter { addr: addr.to_string(), service_name: service_name.to_string() };
+        }
+
+        fn get_resource(&self) -> Value {
+            return json!({ "attributes": [attribute("service.name", &AttributeValue::Str(self.service_name.clone()))] });
+        }
+
+        fn get_scope(&self) -> Value {
+            return json!({ "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") });
+        }
+
+        /// Gets the body of an export of spans.
+        pub(crate) fn encode_spans(&self, spans: &[Span]) -> Value {
+            let spans: Vec<Value> = spans.iter().map(|span| {
+                let mut value = json!({
+                    "traceId": to_hex(&span.trace_id),
+                    "spanId": to_hex(&span.span_id),
+                    "name": span.name,
+                    "kind": SPAN_KIND_CLIENT,
+                    "startTimeUnixNano": unix_nanos(span.start),
+                    "endTimeUnixNano": unix_nanos(span.end),
+                    "attributes": span.attributes.iter().map(|(key, value)| attribute(key, value)).collect::<Vec<Value>>(),
+                    "status": match span.error.as_ref() {
+                        None => json!({ "code": STATUS_CODE_OK }),
+                        Some(error) => json!({ "code": STATUS_CODE_ERROR, "message": error }),
+                    },
+                });
+                if let Some(parent_span_id) = span.parent_span_id {
+                    value["parentSpanId"] = json!(to_hex(&parent_span_id));
+                }
+                value
+            }).collect();
+            return json!({
+                "resourceSpans": [{
+                    "resource": self.get_resource(),
+                    "scopeSpans": [{ "scope": self.get_scope(), "spans": spans }],
+                }],
+            });
+        }
+
+        /// Gets the body of an export of metrics.
+        pub(crate) fn encode_metrics(&self, metrics: &[Metric], start: SystemTime, time: SystemTime) -> Value {
+            let metrics: Vec<Value> = metrics.iter().map(|metric| {
+                let mut value = json!({ "name": metric.name, "unit": metric.unit, "description": metric.description });
+                match metric.value {
+                    MetricValue::Sum(count) => value["sum"] = json!({
+                        "aggregationTemporality": AGGREGATION_TEMPORALITY_CUMULATIVE,
+                        "isMonotonic": true,
+                        "dataPoints": [{ "asInt": count.to_string(), "startTimeUnixNano": unix_nanos(start), "timeUnixNano": unix_nanos(time) }],
+                    }),
+                    MetricValue::Gauge(gauge) => value["gauge"] = json!({
+                        "dataPoints": [{ "asDouble": gauge, "timeUnixNano": unix_nanos(time) }],
+                    }),
+                }
+                value
+            }).collect();
+            return json!({
+                "resourceMetrics": [{
+                    "resource": self.get_resource(),
+                    "scopeMetrics": [{ "scope": self.get_scope(), "metrics": metrics }],
+                }],
+            });
+        }
+
+        fn send(&self, target: &str, body: &Value) -> io::Result<()> {
+            let response = post(&self.addr[..], target, "application/json", body.to_string().as_bytes())?;
+            if response.status != 200 {
+                return Err(io::Error::other(format!("collector answered {}", response.status)));
+            }
+            return Ok(());
+        }
+    }
+
+    impl TelemetryExporter for OtlpExporter {
+        fn export_spans(&self, spans: &[Span]) -> io::Result<()> {
+            if spans.is_empty() {
+                return Ok(());
+            }
+            return self.send("/v1/traces", &self.encode_spans(spans));
+        }
+
+        fn export_metrics(&self, metrics: &[Metric], start: SystemTime, time: SystemTime) -> io::Result<()> {
+            return self.send("/v1/metrics", &self.encode_metrics(metrics, start, time));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::client::fetch::Fetch;
+    use crate::client::http::fetch_manifest;
+    use crate::codec::encoder::EncoderConfig;
+    use crate::codec::manifest::DecoderLimits;
+    use crate::server::catalog::Catalog;
+    use crate::server::http::HttpServer;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingExporter {
+        spans: Mutex<Vec<Span>>,
+        metrics: Mutex<Vec<Metric>>,
+    }
+
+    impl TelemetryExporter for RecordingExporter {
+        fn export_spans(&self, spans: &[Span]) -> io::Result<()> {
+            self.spans.lock().unwrap().extend_from_slice(spans);
+            return Ok(());
+        }
+
+        fn export_metrics(&self, metrics: &[Metric], _start: SystemTime, _time: SystemTime) -> io::Result<()> {
+            *self.metrics.lock().unwrap() = metrics.to_vec();
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_fetch_telemetry() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-telemetry-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(50 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let object_id = catalog.list()[0].manifest.object_id;
+        let server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.run());
+        let dead = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+
+        let exporter = Arc::new(RecordingExporter::default());
+        let telemetry = Arc::new(Telemetry::new(exporter.clone()));
+        let manifest = fetch_manifest(&addr[..], "", &object_id, None).unwrap();
+        let mut fetch = Fetch::new(manifest, vec![dead.clone(), addr.clone()], &DecoderLimits::default()).unwrap();
+        fetch.set_telemetry(telemetry.clone());
+        let (trace_id, parent) = parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        fetch.set_trace_parent(trace_id, parent);
+        assert_eq!(fetch.run().unwrap(), &data[..]);
+
+        // a span for the fetch in the caller's trace, and one for each peer under it
+        let spans = exporter.spans.lock().unwrap().clone();
+        assert_eq!(spans.len(), 3);
+        assert!(spans.iter().all(|x| x.trace_id == trace_id && x.start <= x.end));
+        assert_eq!((spans[0].name.as_str(), spans[0].parent_span_id, spans[0].error.as_ref()), ("raptorcdn.fetch", Some(parent), None));
+        let address = |span: &Span| span.attributes.iter().find(|x| x.0 == "server.address").map(|x| x.1.clone());
+        assert_eq!(address(&spans[1]), Some(AttributeValue::Str(dead)));
+        assert_eq!(spans[1].error.as_deref(), Some("peer failed"));
+        assert_eq!(address(&spans[2]), Some(AttributeValue::Str(addr)));
+        assert!(spans[1..].iter().all(|x| x.parent_span_id == Some(spans[0].span_id) && x.start >= spans[0].start));
+
+        telemetry.export_metrics().unwrap();
+        let metrics = exporter.metrics.lock().unwrap().clone();
+        assert_eq!(metrics[0].value, MetricValue::Sum(1));
+        assert_eq!(metrics[1].value, MetricValue::Sum(0));
+        assert_eq!(metrics[3].value, MetricValue::Sum(fetch.get_transfer_stats().bytes_received));
+        assert_eq!(telemetry.get_export_errors(), 0);
+
+        assert_eq!(parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"), None);
+        assert_eq!(parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7"), None);
+        assert_eq!(parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e47zz-00f067aa0ba902b7-01"), None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "otel")]
+    #[test]
+    fn test_otlp_export() {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        // a collector taking two requests, answering the first with 200 and the second with 503
+        let collector = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = collector.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let mut requests: Vec<(String, serde_json::Value)> = Vec::new();
+            for status in ["200 OK", "503 Service Unavailable"].iter() {
+                let (stream, _) = collector.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                requests.push((request_line, serde_json::from_slice(&body).unwrap()));
+                write!(&stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            }
+            requests
+        });
+
+        let exporter = Arc::new(OtlpExporter::new(&addr, "edge-1"));
+        let start = SystemTime::now();
+        let span = Span {
+            trace_id: gen_trace_id(),
+            span_id: gen_span_id(),
+            parent_span_id: None,
+            name: "raptorcdn.fetch".to_string(),
+            start,
+            end: start,
+            attributes: vec![("raptorcdn.data_size".to_string(), AttributeValue::Int(7))],
+            error: Some("no peer left".to_string()),
+        };
+        exporter.export_spans(std::slice::from_ref(&span)).unwrap();
+        let telemetry = Telemetry::new(exporter);
+        assert!(telemetry.export_metrics().is_err());
+        assert_eq!(telemetry.get_export_errors(), 1);
+
+        let requests = handle.join().unwrap();
+        assert!(requests[0].0.starts_with("POST /v1/traces "));
+        let resource_spans = &requests[0].1["resourceSpans"][0];
+        assert_eq!(resource_spans["resource"]["attributes"][0]["value"]["stringValue"], "edge-1");
+        let exported = &resource_spans["scopeSpans"][0]["spans"][0];
+        assert_eq!(exported["traceId"], crate::codec::manifest::to_hex(&span.trace_id));
+        assert_eq!(exported["attributes"][0]["value"]["intValue"], "7");
+        assert_eq!(exported["status"]["code"], 2);
+        assert!(exported.get("parentSpanId").is_none());
+        assert!(requests[1].0.starts_with("POST /v1/metrics "));
+        let metric = &requests[1].1["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][0];
+        assert_eq!(metric["name"], "raptorcdn.fetch.count");
+        assert_eq!(metric["sum"]["dataPoints"][0]["asInt"], "0");
+    }
+}
@@ -0,0 +1,412 @@
+//! Running many fetches under global limits, for applications fetching more objects than they want in flight at
+//! once. A TransferQueue runs up to a number of fetches at a time, each on its own thread, starting the queued one
+//! of highest priority whenever one finishes or is paused, and paces them all to an aggregate download rate.
+//! Transfers can be paused, which frees their place for the next one, and resumed later where they left off.
+//! Their progress can be followed through the queue's EventHub, see client::events.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::codec::manifest::ObjectId;
+use crate::server::tenant::RateLimiter;
+use super::events::{EventCallback, EventHub, TransferEvent};
+use super::fetch::Fetch;
+use super::stats::TransferStats;
+
+pub type TransferId = u64;
+
+/// How long a transfer waits for the aggregate rate before trying again.
+const BANDWIDTH_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Limits over all transfers of a TransferQueue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransferLimits {
+    /// Most transfers running at once.
+    pub max_concurrent: usize,
+    /// Most symbol bytes received per second over all transfers, None for no limit.
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+impl Default for TransferLimits {
+    fn default() -> TransferLimits {
+        return TransferLimits { max_concurrent: 4, max_bytes_per_sec: None };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransferState {
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    /// Failed, and why.
+    Failed(String),
+    Cancelled,
+}
+
+impl TransferState {
+    /// Whether the transfer is over, one way or another.
+    pub fn is_finished(&self) -> bool {
+        return matches!(self, TransferState::Completed | TransferState::Failed(_) | TransferState::Cancelled);
+    }
+}
+
+/// What a TransferQueue knows of a transfer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferStatus {
+    pub id: TransferId,
+    pub object_id: ObjectId,
+    pub data_size: u64,
+    /// Higher runs first.
+    pub priority: i32,
+    pub state: TransferState,
+    pub block_count: usize,
+    /// Blocks decoded and verified as of the fetch's last request.
+    pub blocks_decoded: usize,
+    /// The fetch's stats as of its last request.
+    pub stats: TransferStats,
+}
+
+struct Transfer {
+    status: TransferStatus,
+    /// The fetch, while it is not running.
+    fetch: Option<Fetch>,
+    /// Asked of a running transfer, which acts on it before its next request.
+    pause_requested: bool,
+    cancel_requested: bool,
+    result: Option<Vec<u8>>,
+}
+
+struct QueueState {
+    transfers: BTreeMap<TransferId, Transfer>,
+    next_id: TransferId,
+    limits: TransferLimits,
+    running: usize,
+}
+
+struct Shared {
+    state: Mutex<QueueState>,
+    /// Signalled whenever a transfer finishes.
+    finished: Condvar,
+    bandwidth: RateLimiter,
+    events: Arc<EventHub>,
+}
+
+/// Fetches queued under TransferLimits, see the module documentation.
+pub struct TransferQueue {
+    shared: Arc<Shared>,
+}
+
+impl TransferQueue {
+    pub fn new(limits: TransferLimits) -> TransferQueue {
+        let bandwidth = RateLimiter::unlimited();
+        bandwidth.set_rate(limits.max_bytes_per_sec);
+        return TransferQueue {
+            shared: Arc::new(Shared {
+                state: Mutex::new(QueueState { transfers: BTreeMap::new(), next_id: 0, limits, running: 0 }),
+                finished: Condvar::new(),
+                bandwidth,
+                events: Arc::new(EventHub::new()),
+            }),
+        };
+    }
+
+    /// Queues a fetch, to run once no queued transfer of higher priority, or of the same priority queued earlier,
+    /// is waiting and there is room under the limits. Its events go to the queue's subscribers.
+    pub fn submit(&self, mut fetch: Fetch, priority: i32) -> TransferId {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        fetch.set_events(self.shared.events.clone());
+        fetch.set_transfer_id(id);
+        let status = TransferStatus {
+            id,
+            object_id: fetch.get_manifest().object_id,
+            data_size: fetch.get_manifest().data_size,
+            priority,
+            state: TransferState::Queued,
+            block_count: fetch.get_manifest().get_block_count(),
+            blocks_decoded: fetch.get_blocks_decoded(),
+            stats: fetch.get_transfer_stats(),
+        };
+        state.transfers.insert(id, Transfer { status, fetch: Some(fetch), pause_requested: false, cancel_requested: false, result: None });
+        dispatch(&self.shared, &mut state);
+        return id;
+    }
+
+    /// Changes the limits. Running transfers over a lowered max_concurrent finish, no new ones start meanwhile.
+    pub fn set_limits(&self, limits: TransferLimits) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.limits = limits;
+        self.shared.bandwidth.set_rate(limits.max_bytes_per_sec);
+        dispatch(&self.shared, &mut state);
+    }
+
+    pub fn get_limits(&self) -> TransferLimits {
+        return self.shared.state.lock().unwrap().limits;
+    }
+
+    /// Changes the priority of a transfer, which matters only while it is queued. Returns false if there is no
+    /// such transfer.
+    pub fn set_priority(&self, id: TransferId, priority: i32) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.transfers.get_mut(&id) {
+            None => return false,
+            Some(transfer) => transfer.status.priority = priority,
+        }
+        return true;
+    }
+
+    /// Pauses a queued or running transfer, a running one before its next request. Returns false if there is no
+    /// such transfer or it is finished.
+    pub fn pause(&self, id: TransferId) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let transfer = match state.transfers.get_mut(&id) {
+            Some(transfer) if !transfer.status.state.is_finished() => transfer,
+            _ => return false,
+        };
+        match transfer.status.state {
+            TransferState::Running => transfer.pause_requested = true,
+            _ => transfer.status.state = TransferState::Paused,
+        }
+        return true;
+    }
+
+    /// Queues a paused transfer again, in its place by priority and submission. Returns false if there is no such
+    /// transfer or it is not paused.
+    pub fn resume(&self, id: TransferId) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let transfer = match state.transfers.get_mut(&id) {
+            Some(transfer) => transfer,
+            None => return false,
+        };
+        match transfer.status.state {
+            TransferState::Paused => transfer.status.state = TransferState::Queued,
+            TransferState::Running if transfer.pause_requested => transfer.pause_requested = false,
+            _ => return false,
+        }
+        dispatch(&self.shared, &mut state);
+        return true;
+    }
+
+    /// Cancels a transfer that is not finished, a running one before its next request. Returns false if there is
+    /// no such transfer or it is finished.
+    pub fn cancel(&self, id: TransferId) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let transfer = match state.transfers.get_mut(&id) {
+            Some(transfer) if !transfer.status.state.is_finished() => transfer,
+            _ => return false,
+        };
+        match transfer.status.state {
+            TransferState::Running => transfer.cancel_requested = true,
+            _ => {
+                transfer.status.state = TransferState::Cancelled;
+                if let Some(fetch) = transfer.fetch.take() {
+                    fetch.report_cancelled();
+                }
+                self.shared.finished.notify_all();
+            },
+        }
+        return true;
+    }
+
+    /// Gets a channel receiving the events of every transfer from now on, see EventHub::subscribe.
+    pub fn subscribe(&self) -> Receiver<TransferEvent> {
+        return self.shared.events.subscribe();
+    }
+
+    /// Calls callback with the events of every transfer from now on, see EventHub::add_callback.
+    pub fn add_callback(&self, callback: EventCallback) {
+        self.shared.events.add_callback(callback);
+    }
+
+    pub fn get_status(&self, id: TransferId) -> Option<TransferStatus> {
+        return self.shared.state.lock().unwrap().transfers.get(&id).map(|x| x.status.clone());
+    }
+
+    /// Gets the status of every transfer not removed, in the order submitted.
+    pub fn list(&self) -> Vec<TransferStatus> {
+        return self.shared.state.lock().unwrap().transfers.values().map(|x| x.status.clone()).collect();
+    }
+
+    /// Waits until a transfer is finished, returning its status. None if there is no such transfer.
+    pub fn wait(&self, id: TransferId) -> Option<TransferStatus> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            match state.transfers.get(&id) {
+                None => return None,
+                Some(transfer) if transfer.status.state.is_finished() => return Some(transfer.status.clone()),
+                Some(_) => state = self.shared.finished.wait(state).unwrap(),
+            }
+        }
+    }
+
+    /// Removes a finished transfer, returning the object if it completed. None if there is no such transfer or it
+    /// is not finished, in which case it is left as it is.
+    pub fn take_result(&self, id: TransferId) -> Option<Vec<u8>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.transfers.get(&id).is_some_and(|x| x.status.state.is_finished()) {
+            return None;
+        }
+        return state.transfers.remove(&id).and_then(|x| x.result);
+    }
+}
+
+/// Starts queued transfers, highest priority first, while there is room.
+fn dispatch(shared: &Arc<Shared>, state: &mut MutexGuard<QueueState>) {
+    while state.running < state.limits.max_concurrent {
+        let next = state.transfers.values()
+            .filter(|x| x.status.state == TransferState::Queued)
+            // the earliest submitted of the highest priority, ids being handed out in order
+            .max_by_key(|x| (x.status.priority, std::cmp::Reverse(x.status.id)))
+            .map(|x| x.status.id);
+        let id = match next {
+            None => return,
+            Some(id) => id,
+        };
+        let transfer = state.transfers.get_mut(&id).unwrap();
+        transfer.status.state = TransferState::Running;
+        let fetch = transfer.fetch.take().unwrap();
+        state.running += 1;
+        let shared = shared.clone();
+        std::thread::spawn(move || run_transfer(shared, id, fetch));
+    }
+}
+
+/// Steps a fetch until it finishes or is paused or cancelled, pacing it to the aggregate rate.
+fn run_transfer(shared: Arc<Shared>, id: TransferId, mut fetch: Fetch) {
+    let mut bytes_received = fetch.get_transfer_stats().bytes_received;
+    loop {
+        {
+            let mut state = shared.state.lock().unwrap();
+            // running transfers are not finished, so can't have been removed
+            let transfer = state.transfers.get_mut(&id).unwrap();
+            if transfer.cancel_requested || transfer.pause_requested {
+                if transfer.cancel_requested {
+                    fetch.report_cancelled();
+                    transfer.status.state = TransferState::Cancelled;
+                    shared.finished.notify_all();
+                } else {
+                    transfer.pause_requested = false;
+                    transfer.status.state = TransferState::Paused;
+                    transfer.fetch = Some(fetch);
+                }
+                state.running -= 1;
+                dispatch(&shared, &mut state);
+                return;
+            }
+        }
+
+        let result = fetch.step_checked();
+        let stats = fetch.get_transfer_stats();
+        // take what was received from the bucket in pieces it can hold, so a low rate still lets requests through
+        let mut owed = stats.bytes_received - bytes_received;
+        bytes_received = stats.bytes_received;
+        while owed > 0 {
+            let piece = owed.min(shared.bandwidth.get_rate().unwrap_or(u64::MAX).max(1));
+            while !shared.bandwidth.try_take(piece) {
+                std::thread::sleep(BANDWIDTH_RETRY_INTERVAL);
+            }
+            owed -= piece;
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        let transfer = state.transfers.get_mut(&id).unwrap();
+        transfer.status.stats = stats;
+        transfer.status.blocks_decoded = fetch.get_blocks_decoded();
+        let finished = match result {
+            Ok(false) => continue,
+            Ok(true) => {
+                fetch.report(None);
+                transfer.result = fetch.get_result().map(|x| x.to_vec());
+                TransferState::Completed
+            },
+            Err(error) => {
+                fetch.report(Some(&error));
+                TransferState::Failed(error.to_string())
+            },
+        };
+        transfer.status.state = finished;
+        state.running -= 1;
+        dispatch(&shared, &mut state);
+        shared.finished.notify_all();
+        return;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::client::events::TransferEventKind;
+    use crate::client::http::fetch_manifest;
+    use crate::codec::encoder::EncoderConfig;
+    use crate::codec::manifest::DecoderLimits;
+    use crate::server::catalog::Catalog;
+    use crate::server::http::HttpServer;
+    use std::time::Instant;
+
+    #[test]
+    fn test_transfer_queue() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-transfers-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let objects: Vec<Vec<u8>> = (0..4).map(|_| gen_data(100 * 1000)).collect();
+        for (i, data) in objects.iter().enumerate() {
+            std::fs::write(root.join(format!("object-{}", i)), data).unwrap();
+        }
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let server = HttpServer::bind("127.0.0.1:0", catalog.clone()).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.run());
+        let new_fetch = |i: usize| {
+            let name = format!("object-{}", i);
+            let object_id = catalog.list().iter().find(|x| x.name == name).unwrap().manifest.object_id;
+            let manifest = fetch_manifest(&addr[..], "", &object_id, None).unwrap();
+            Fetch::new(manifest, vec![addr.clone()], &DecoderLimits::default()).unwrap()
+        };
+
+        // one at a time, at 100 KB/s after a second's burst
+        let queue = TransferQueue::new(TransferLimits { max_concurrent: 1, max_bytes_per_sec: Some(100 * 1000) });
+        let events = queue.subscribe();
+        let start = Instant::now();
+        let first = queue.submit(new_fetch(0), 0);
+        let low = queue.submit(new_fetch(1), 0);
+        let high = queue.submit(new_fetch(2), 5);
+        assert!(queue.pause(low));
+        assert_eq!(queue.get_status(high).unwrap().state, TransferState::Queued);
+
+        assert_eq!(queue.wait(first).unwrap().state, TransferState::Completed);
+        assert_eq!(queue.wait(high).unwrap().state, TransferState::Completed);
+        assert_eq!(queue.get_status(low).unwrap().state, TransferState::Paused);
+        assert!(queue.resume(low));
+        let status = queue.wait(low).unwrap();
+        assert_eq!(status.state, TransferState::Completed);
+        assert!(status.stats.bytes_received >= objects[1].len() as u64);
+        assert_eq!(status.blocks_decoded, status.block_count);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(queue.take_result(high).unwrap(), objects[2]);
+        assert_eq!(queue.list().iter().map(|x| x.id).collect::<Vec<TransferId>>(), vec![first, low]);
+
+        // a cancelled transfer ends without its object, and finished ones can't be paused
+        queue.set_limits(TransferLimits { max_concurrent: 2, max_bytes_per_sec: None });
+        let cancelled = queue.submit(new_fetch(3), 0);
+        assert!(queue.cancel(cancelled));
+        assert_eq!(queue.wait(cancelled).unwrap().state, TransferState::Cancelled);
+        assert_eq!(queue.take_result(cancelled), None);
+        let events: Vec<TransferEvent> = events.try_iter().collect();
+        let ends: Vec<(Option<TransferId>, &TransferEventKind)> = events.iter()
+            .filter(|x| matches!(x.kind, TransferEventKind::Completed | TransferEventKind::Failed(_)))
+            .map(|x| (x.transfer_id, &x.kind))
+            .collect();
+        let failed = TransferEventKind::Failed("cancelled".to_string());
+        assert_eq!(ends, vec![(Some(first), &TransferEventKind::Completed), (Some(high), &TransferEventKind::Completed), (Some(low), &TransferEventKind::Completed), (Some(cancelled), &failed)]);
+        assert!(!queue.pause(first));
+        assert!(!queue.resume(first));
+        assert_eq!(queue.take_result(first).unwrap(), objects[0]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+/// A zero-initialized byte buffer whose start is aligned to a caller-chosen power of two, for consumers
+/// that reinterpret the recovered payload in place (mmap-backed readers, Arrow buffers, SIMD).
+pub struct AlignedBuffer {
+    /// Start of the allocation, or a dangling aligned pointer if len is 0.
+    ptr: NonNull<u8>,
+    /// Layout used for the allocation.
+    layout: Layout,
+}
+
+// AlignedBuffer owns its allocation exclusively, like Vec<u8>.
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    /// Allocates len zeroed bytes aligned to alignment. Returns None if alignment is not a power of two.
+    pub fn new(len: usize, alignment: usize) -> Option<AlignedBuffer> {
+        let layout = Layout::from_size_align(len, alignment).ok()?;
+
+        // Zero-sized allocations are not allowed, use a dangling pointer that still honors the alignment.
+        if len == 0 {
+            let ptr = NonNull::new(alignment as *mut u8)?;
+            return Some(AlignedBuffer { ptr, layout });
+        }
+
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        match NonNull::new(ptr) {
+            None => alloc::handle_alloc_error(layout),
+            Some(ptr) => return Some(AlignedBuffer { ptr, layout }),
+        }
+    }
+
+    /// Gets the alignment of the start of the buffer.
+    pub fn alignment(&self) -> usize {
+        return self.layout.align();
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        return unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) };
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        return unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) };
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        if self.layout.size() > 0 {
+            unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+        }
+    }
+}
+
+impl std::fmt::Debug for AlignedBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return f.debug_struct("AlignedBuffer")
+            .field("len", &self.len())
+            .field("alignment", &self.alignment())
+            .finish();
+    }
+}
@@ -0,0 +1,336 @@
+//! Cold storage archives of objects, e.g. for tape or S3 Glacier: a tar file holding the shard of each block,
+//! shards/<block id>.shard, with the CRC32C of each of its symbols in shards/<block id>.crc, then the manifest,
+//! manifest. Each shard has the block's source symbols and a chosen overhead of repair symbols, so a block survives
+//! that many of its symbols rotting: symbols not matching their CRC are left out, and the rest still decode. The
+//! manifest comes last, as it is only complete once the whole object was read, so an archive can be written from a
+//! stream but has to be read back from a file.
+//!
+//! Archives are plain ustar, so standard tools can list and split them. Restoring checks every block against its
+//! hash and the whole object against its id.
+
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::decoder::BlockDecoder;
+use super::encoder::{BlockEncoder, EncodedBlock};
+use super::manifest::Manifest;
+use super::shard::{read_shard, write_shard};
+
+/// Name of the manifest in an archive.
+pub const ARCHIVE_MANIFEST_NAME: &str = "manifest";
+
+/// Size of tar headers, and what entries are padded to.
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Name of the shard of a block in an archive.
+pub fn archive_shard_name(block_id: u32) -> String {
+    return format!("shards/{}.shard", block_id);
+}
+
+/// Name of the CRCs of the symbols of a block's shard in an archive, a u32 per symbol in the order of the shard,
+/// little endian.
+pub fn archive_crc_name(block_id: u32) -> String {
+    return format!("shards/{}.crc", block_id);
+}
+
+/// Gets the CRC32C of a symbol, covering its payload id as well as its data.
+fn symbol_crc(symbol: &EncodedBlock) -> u32 {
+    let crc = crc32c::crc32c(&symbol.data.payload_id().serialize());
+    return crc32c::crc32c_append(crc, symbol.data.data());
+}
+
+/// Writes an octal tar header field, zero padded and NUL terminated.
+fn write_octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    field[..width].copy_from_slice(format!("{:0width$o}", value, width = width).as_bytes());
+}
+
+/// Reads an octal tar header field, which may be padded with spaces and NULs.
+fn read_octal(field: &[u8]) -> io::Result<u64> {
+    let digits = std::str::from_utf8(field).map(|x| x.trim_matches(|c| c == ' ' || c == '\0'));
+    return match digits {
+        Ok("") => Ok(0),
+        Ok(digits) => u64::from_str_radix(digits, 8).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad archive: bad number in tar header")),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "bad archive: bad number in tar header")),
+    };
+}
+
+/// Sums a tar header with its checksum field taken as spaces.
+fn header_checksum(header: &[u8; TAR_BLOCK_SIZE]) -> u64 {
+    return header.iter().enumerate().map(|(i, x)| if (148..156).contains(&i) { b' ' as u64 } else { *x as u64 }).sum();
+}
+
+/// Builds the ustar header of a regular file.
+fn tar_header(name: &str, size: u64, mtime: u64) -> io::Result<[u8; TAR_BLOCK_SIZE]> {
+    // sizes past 11 octal digits need extensions, and a shard never gets there
+    if name.len() > 100 || size >= 1 << 33 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} does not fit a tar header", name)));
+    }
+    let mut header = [0u8; TAR_BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let checksum = header_checksum(&header);
+    write_octal(&mut header[148..155], checksum);
+    header[155] = b' ';
+    return Ok(header);
+}
+
+/// Writes an archive, see the module documentation.
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+    overhead_percent: u16,
+    mtime: u64,
+    /// Symbols written so far.
+    symbols: u64,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Creates an ArchiveWriter putting overhead_percent repair symbols in each shard on top of its source symbols.
+    pub fn new(writer: W, overhead_percent: u16) -> ArchiveWriter<W> {
+        let mtime = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+        return ArchiveWriter { writer, overhead_percent, mtime, symbols: 0 };
+    }
+
+    fn write_entry(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&tar_header(name, data.len() as u64, self.mtime)?)?;
+        self.writer.write_all(data)?;
+        let padding = (TAR_BLOCK_SIZE - data.len() % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+        return self.writer.write_all(&[0; TAR_BLOCK_SIZE][..padding]);
+    }
+
+    /// Writes the shard of a block. Blocks go in in order, as IncrementalEncoder yields them.
+    pub fn add_block(&mut self, block_encoder: &BlockEncoder) -> io::Result<()> {
+        let mut symbols = block_encoder.generate_source_blocks();
+        let repair_count = (symbols.len() * self.overhead_percent as usize).div_ceil(100);
+        symbols.append(&mut block_encoder.generate_repair_blocks(0, repair_count));
+        self.symbols += symbols.len() as u64;
+
+        let block_id = block_encoder.get_block_info().block_id;
+        let mut shard: Vec<u8> = Vec::new();
+        write_shard(&mut shard, &symbols)?;
+        self.write_entry(&archive_shard_name(block_id), &shard)?;
+        let crcs: Vec<u8> = symbols.iter().flat_map(|x| symbol_crc(x).to_le_bytes()).collect();
+        return self.write_entry(&archive_crc_name(block_id), &crcs);
+    }
+
+    pub fn get_symbols(&self) -> u64 {
+        return self.symbols;
+    }
+
+    /// Writes the manifest and the end of the archive, returning the writer.
+    pub fn finish(mut self, manifest: &Manifest) -> io::Result<W> {
+        let mut data: Vec<u8> = Vec::new();
+        manifest.write_to(&mut data)?;
+        self.write_entry(ARCHIVE_MANIFEST_NAME, &data)?;
+        self.writer.write_all(&[0; 2 * TAR_BLOCK_SIZE])?;
+        self.writer.flush()?;
+        return Ok(self.writer);
+    }
+}
+
+/// Reads an archive back, see the module documentation.
+pub struct ArchiveReader<R: Read + Seek> {
+    reader: R,
+    manifest: Manifest,
+    /// Offset and size of the shard of each block, None for blocks the archive lacks.
+    shards: Vec<Option<(u64, u64)>>,
+    /// Offset and size of the CRCs of the symbols of each block's shard.
+    crcs: Vec<Option<(u64, u64)>>,
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Indexes the entries of an archive and reads its manifest. Fails with ErrorKind::InvalidData if it is not an
+    /// archive, a tar header is damaged or the manifest is missing. Entries other than shards and the manifest are
+    /// skipped.
+    pub fn open(mut reader: R) -> io::Result<ArchiveReader<R>> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad archive: {}", reason));
+        let mut entries: Vec<(String, u64, u64)> = Vec::new();
+        loop {
+            let mut header = [0u8; TAR_BLOCK_SIZE];
+            reader.read_exact(&mut header)?;
+            if header.iter().all(|x| *x == 0) {
+                break;
+            }
+            if read_octal(&header[148..156])? != header_checksum(&header) {
+                return Err(invalid("tar header checksum mismatch"));
+            }
+
+            let size = read_octal(&header[124..136])?;
+            let offset = reader.stream_position()?;
+            // regular files only, which leaves out directories and pax headers
+            if header[156] == b'0' || header[156] == 0 {
+                let name_len = header[..100].iter().position(|x| *x == 0).unwrap_or(100);
+                let name = String::from_utf8_lossy(&header[..name_len]).into_owned();
+                entries.push((name, offset, size));
+            }
+            let padded_size = size.div_ceil(TAR_BLOCK_SIZE as u64) * TAR_BLOCK_SIZE as u64;
+            reader.seek(SeekFrom::Start(offset + padded_size))?;
+        }
+
+        let (offset, size) = match entries.iter().find(|x| x.0 == ARCHIVE_MANIFEST_NAME) {
+            Some((_, offset, size)) => (*offset, *size),
+            None => return Err(invalid("no manifest")),
+        };
+        reader.seek(SeekFrom::Start(offset))?;
+        let manifest = Manifest::read_from((&mut reader).take(size))?;
+
+        let find = |name: String| entries.iter().find(|x| x.0 == name).map(|x| (x.1, x.2));
+        let block_ids = 0..(manifest.get_block_count() as u32);
+        let shards = block_ids.clone().map(|x| find(archive_shard_name(x))).collect();
+        let crcs = block_ids.map(|x| find(archive_crc_name(x))).collect();
+        return Ok(ArchiveReader { reader, manifest, shards, crcs });
+    }
+
+    pub fn get_manifest(&self) -> &Manifest {
+        return &self.manifest;
+    }
+
+    /// Gets the ids of the blocks the archive has no shard of.
+    pub fn get_missing_blocks(&self) -> Vec<u32> {
+        return self.shards.iter().enumerate().filter(|(_, x)| x.is_none()).map(|(i, _)| i as u32).collect();
+    }
+
+    /// Reads the symbols of a block's shard, leaving out those that do not match their CRC. Fails with
+    /// ErrorKind::NotFound if the archive has no shard of the block.
+    pub fn read_shard(&mut self, block_id: u32) -> io::Result<Vec<EncodedBlock>> {
+        let (offset, size) = match self.shards.get(block_id as usize).copied().flatten() {
+            Some(shard) => shard,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("archive has no shard of block {}", block_id))),
+        };
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let symbols = read_shard((&mut self.reader).take(size))?;
+
+        // without CRCs, or with CRCs not matching the shard, every symbol is taken and the block hash has the last say
+        let (offset, size) = match self.crcs[block_id as usize] {
+            Some((offset, size)) if size == symbols.len() as u64 * 4 => (offset, size),
+            _ => return Ok(symbols),
+        };
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut crcs = vec![0u8; size as usize];
+        self.reader.read_exact(&mut crcs)?;
+        let intact = symbols.into_iter().zip(crcs.chunks(4)).filter(|(symbol, crc)| symbol_crc(symbol).to_le_bytes()[..] == crc[..]);
+        return Ok(intact.map(|(symbol, _)| symbol).collect());
+    }
+
+    /// Decodes a block from its shard, failing with ErrorKind::InvalidData if it does not decode to its hash.
+    fn decode_block(&mut self, block_id: u32) -> io::Result<Vec<u8>> {
+        let symbols = self.read_shard(block_id)?;
+        let block_info = self.manifest.block_info_vec[block_id as usize].clone();
+        let mut decoder = match BlockDecoder::new(block_info) {
+            Ok(decoder) => decoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad manifest: {:?}", error))),
+        };
+        decoder.set_symbol_slack(symbols.len());
+        let _ = decoder.consume(symbols.into_iter().filter(|x| x.block_id == block_id).collect());
+        return match decoder.get_result() {
+            Some(data) if Sha256::digest(data)[..] == self.manifest.block_hashes[block_id as usize][..] => Ok(data.to_vec()),
+            Some(_) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("block {} does not match its hash", block_id))),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("block {} has too few symbols to decode", block_id))),
+        };
+    }
+
+    /// Decodes every block without writing anything, returning the ids of the blocks that are missing or do not
+    /// decode to their hash.
+    pub fn verify(&mut self) -> io::Result<Vec<u32>> {
+        let mut bad_blocks: Vec<u32> = Vec::new();
+        let mut object_hasher = Sha256::new();
+        for block_id in 0..(self.manifest.get_block_count() as u32) {
+            match self.decode_block(block_id) {
+                Ok(data) => object_hasher.update(&data),
+                // a shard cut short is as bad as a missing one
+                Err(error) if matches!(error.kind(), io::ErrorKind::InvalidData | io::ErrorKind::NotFound | io::ErrorKind::UnexpectedEof) => {
+                    bad_blocks.push(block_id);
+                },
+                Err(error) => return Err(error),
+            }
+        }
+        // every block matching its hash but the object not, the manifest itself is bad
+        if bad_blocks.is_empty() && object_hasher.finalize()[..] != self.manifest.object_id[..] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad archive: object does not match its id"));
+        }
+        return Ok(bad_blocks);
+    }
+
+    /// Decodes the object into writer block by block. Fails with ErrorKind::InvalidData at the first block that is
+    /// missing or does not decode to its hash, or at the end if the object does not match its id, having written
+    /// what came before.
+    pub fn restore<W: Write>(&mut self, mut writer: W) -> io::Result<()> {
+        let mut object_hasher = Sha256::new();
+        for block_id in 0..(self.manifest.get_block_count() as u32) {
+            let data = match self.decode_block(block_id) {
+                Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+                result => result?,
+            };
+            object_hasher.update(&data);
+            writer.write_all(&data)?;
+        }
+        if object_hasher.finalize()[..] != self.manifest.object_id[..] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad archive: object does not match its id"));
+        }
+        return writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::encoder::EncoderConfig;
+    use super::super::incremental::IncrementalEncoder;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_archive_round_trip() {
+        let data = gen_data(200 * 1000);
+        let mut encoder = IncrementalEncoder::new(&data[..], EncoderConfig::new(1280)).unwrap();
+        let mut writer = ArchiveWriter::new(Vec::new(), 10);
+        for block_encoder in encoder.by_ref() {
+            writer.add_block(&block_encoder.unwrap()).unwrap();
+        }
+        let manifest = encoder.get_manifest().unwrap();
+        // 157 source symbols and 16 repair symbols
+        assert_eq!(writer.get_symbols(), 173);
+        let archive = writer.finish(&manifest).unwrap();
+        assert_eq!(archive.len() % TAR_BLOCK_SIZE, 0);
+
+        let mut reader = ArchiveReader::open(Cursor::new(&archive[..])).unwrap();
+        assert_eq!(reader.get_manifest(), &manifest);
+        assert_eq!(reader.get_missing_blocks(), Vec::<u32>::new());
+        assert_eq!(reader.verify().unwrap(), Vec::<u32>::new());
+        let mut restored: Vec<u8> = Vec::new();
+        reader.restore(&mut restored).unwrap();
+        assert_eq!(restored, data);
+
+        // symbols rotting are covered by the repair symbols, up to the overhead; the shard starts after its tar header
+        // and magic, and each symbol after its 10 byte record header
+        let rot = |symbols: usize| {
+            let mut damaged = archive.clone();
+            for symbol in 0..symbols {
+                damaged[TAR_BLOCK_SIZE + 8 + symbol * (10 + 1280) + 10] ^= 1;
+            }
+            return ArchiveReader::open(Cursor::new(damaged)).unwrap();
+        };
+        let mut reader = rot(10);
+        assert_eq!(reader.verify().unwrap(), Vec::<u32>::new());
+        let mut restored: Vec<u8> = Vec::new();
+        reader.restore(&mut restored).unwrap();
+        assert_eq!(restored, data);
+        assert_eq!(rot(20).verify().unwrap(), vec![0]);
+
+        // a damaged tar header is caught before anything is decoded
+        let mut damaged = archive.clone();
+        damaged[0] ^= 1;
+        match ArchiveReader::open(Cursor::new(&damaged[..])) {
+            Ok(_) => panic!("Should have failed to open a damaged archive"),
+            Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidData),
+        }
+    }
+}
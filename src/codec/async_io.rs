@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::task::{Context, Poll, Waker};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::encoder::{BlockEncoder, BlockInfo, EncodedBlock, EncoderConfig};
+use super::reader::DecodeReader;
+
+/// Reads never block: if the block at the current position is not decoded yet, the task is woken up once a
+/// DecodeFeeder makes progress.
+impl AsyncRead for DecodeReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        let shared = this.shared.clone();
+        let mut state = shared.state.lock().unwrap();
+        if let Some(copied) = this.read_decoded(&state, buf.initialize_unfilled()) {
+            buf.advance(copied);
+            return Poll::Ready(Ok(()));
+        }
+
+        if shared.feeders.load(Ordering::Acquire) == 0 {
+            return Poll::Ready(Err(DecodeReader::no_feeders_error()));
+        }
+
+        if !state.wakers.iter().any(|x| x.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        return Poll::Pending;
+    }
+}
+
+/// AsyncWrite sink encoding the bytes written to it. Encoded blocks come out of its Stream implementation as soon
+/// as each block fills up, and for the tail block once the sink is shut down. Writes wait while more packets than
+/// the sink's queue limit are waiting to be taken from the stream.
+/// Encoding runs inline in poll_write / poll_shutdown, so large blocks will hold up the calling task.
+pub struct EncoderSink {
+    /// Blocks are encoded with this config, and split at its block size.
+    config: EncoderConfig,
+    /// Bytes of the block currently being filled.
+    block: Vec<u8>,
+    /// Infos of the blocks encoded so far.
+    block_info_vec: Vec<BlockInfo>,
+    /// Encoded blocks not yet taken from the stream.
+    encoded_blocks: VecDeque<EncodedBlock>,
+    /// Writes wait while encoded_blocks holds this many packets or more.
+    max_queued_packets: usize,
+    /// Set once the sink is shut down, after which no more blocks are produced.
+    shut_down: bool,
+    /// Task waiting on the stream.
+    waker: Option<Waker>,
+    /// Task waiting for the queue to drain to write.
+    write_waker: Option<Waker>,
+}
+
+impl EncoderSink {
+    /// Creates an EncoderSink encoding with config, whose writes wait while max_queued_packets packets or more are
+    /// queued. Fails with ErrorKind::InvalidInput if the config is invalid.
+    pub fn new(config: EncoderConfig, max_queued_packets: usize) -> io::Result<EncoderSink> {
+        if let Err(error) = config.validate() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid config: {:?}", error)));
+        }
+
+        return Ok(EncoderSink {
+            config,
+            block: Vec::new(),
+            block_info_vec: Vec::new(),
+            encoded_blocks: VecDeque::new(),
+            max_queued_packets,
+            shut_down: false,
+            waker: None,
+            write_waker: None,
+        });
+    }
+
+    /// Gets the infos of all blocks, once the sink is shut down.
+    pub fn get_block_info_vec(&self) -> Option<Vec<BlockInfo>> {
+        if !self.shut_down {
+            return None;
+        }
+
+        return Some(self.block_info_vec.clone());
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        let block_id = self.block_info_vec.len() as u32;
+        let block = std::mem::take(&mut self.block);
+        let encoder = match BlockEncoder::with_config(block_id, self.config, block) {
+            Ok(encoder) => encoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("failed to create encoder: {:?}", error))),
+        };
+
+        self.block_info_vec.push(encoder.get_block_info());
+        self.encoded_blocks.extend(encoder.generate_encoded_blocks());
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+
+        return Ok(());
+    }
+}
+
+impl AsyncWrite for EncoderSink {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.shut_down {
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::BrokenPipe, "sink is shut down")));
+        }
+
+        if this.encoded_blocks.len() >= this.max_queued_packets {
+            this.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let block_size = this.config.get_block_size();
+        let written = std::cmp::min(block_size - this.block.len(), buf.len());
+        this.block.extend_from_slice(&buf[..written]);
+        if this.block.len() == block_size {
+            this.encode_block()?;
+        }
+
+        return Poll::Ready(Ok(written));
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        return Poll::Ready(Ok(()));
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.shut_down {
+            return Poll::Ready(Ok(()));
+        }
+
+        if !this.block.is_empty() {
+            this.encode_block()?;
+        }
+
+        this.shut_down = true;
+        if let Some(waker) = this.waker.take() {
+            waker.wake();
+        }
+        return Poll::Ready(Ok(()));
+    }
+}
+
+impl Stream for EncoderSink {
+    type Item = EncodedBlock;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<EncodedBlock>> {
+        let this = self.get_mut();
+        if let Some(block) = this.encoded_blocks.pop_front() {
+            if this.encoded_blocks.len() < this.max_queued_packets {
+                if let Some(waker) = this.write_waker.take() {
+                    waker.wake();
+                }
+            }
+            return Poll::Ready(Some(block));
+        }
+
+        if this.shut_down {
+            return Poll::Ready(None);
+        }
+
+        this.waker = Some(cx.waker().clone());
+        return Poll::Pending;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::decoder::RaptorQDecoder;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_async_encode_decode() {
+        let data = gen_data(100 * 1000);
+
+        let mut sink = EncoderSink::new(EncoderConfig::new(1280), usize::MAX).unwrap();
+        sink.write_all(&data).await.unwrap();
+        sink.shutdown().await.unwrap();
+
+        let block_info_vec = sink.get_block_info_vec().unwrap();
+        let decoder = match RaptorQDecoder::new(block_info_vec) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let (mut reader, feeder) = DecodeReader::new(decoder, false);
+
+        // read concurrently with feeding, the reader should wait for the feeder
+        let read_task = tokio::spawn(async move {
+            let mut recovered_data: Vec<u8> = Vec::new();
+            reader.read_to_end(&mut recovered_data).await.unwrap();
+            recovered_data
+        });
+        tokio::task::yield_now().await;
+
+        let mut blocks: Vec<EncodedBlock> = Vec::new();
+        while let Some(block) = std::future::poll_fn(|cx| Pin::new(&mut sink).poll_next(cx)).await {
+            blocks.push(block);
+        }
+
+        // a second pass over the same data, so decoding does not depend on luck
+        let mut sink = EncoderSink::new(EncoderConfig::new(1280), usize::MAX).unwrap();
+        sink.write_all(&data).await.unwrap();
+        sink.shutdown().await.unwrap();
+        while let Some(block) = std::future::poll_fn(|cx| Pin::new(&mut sink).poll_next(cx)).await {
+            blocks.push(block);
+        }
+
+        match feeder.consume(blocks) {
+            Ok(decoded) => assert!(decoded),
+            Err(error) => panic!("Failed to decode data, err {:?}", error),
+        }
+
+        assert_eq!(read_task.await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_async_encode_backpressure() {
+        let data = gen_data(25 * 1280);
+        let mut config = EncoderConfig::new(1280);
+        config.max_block_symbols = 10;
+        let mut sink = EncoderSink::new(config, 1).unwrap();
+
+        // the first block fills up and is encoded, then writes wait until its packets are taken
+        async fn poll_write(sink: &mut EncoderSink, buf: &[u8]) -> Poll<io::Result<usize>> {
+            return std::future::poll_fn(|cx| Poll::Ready(Pin::new(&mut *sink).poll_write(cx, buf))).await;
+        }
+        match poll_write(&mut sink, &data).await {
+            Poll::Ready(Ok(written)) => assert_eq!(written, 10 * 1280),
+            _ => panic!("Should have taken the first block"),
+        }
+        assert!(poll_write(&mut sink, &data[(10 * 1280)..]).await.is_pending());
+
+        let mut blocks: Vec<EncodedBlock> = Vec::new();
+        while !sink.encoded_blocks.is_empty() {
+            blocks.push(std::future::poll_fn(|cx| Pin::new(&mut sink).poll_next(cx)).await.unwrap());
+        }
+        assert_eq!(blocks.len(), 10);
+        sink.write_all(&data[(10 * 1280)..(20 * 1280)]).await.unwrap();
+        assert!(poll_write(&mut sink, &data[(20 * 1280)..]).await.is_pending());
+
+        // blocks are split at the config's block size, whatever RaptorQ allows
+        while !sink.encoded_blocks.is_empty() {
+            blocks.push(std::future::poll_fn(|cx| Pin::new(&mut sink).poll_next(cx)).await.unwrap());
+        }
+        sink.write_all(&data[(20 * 1280)..]).await.unwrap();
+        sink.shutdown().await.unwrap();
+        let block_info_vec = sink.get_block_info_vec().unwrap();
+        assert_eq!(block_info_vec.iter().map(|x| x.payload_size).collect::<Vec<usize>>(), vec![10 * 1280, 10 * 1280, 5 * 1280]);
+        while let Some(block) = std::future::poll_fn(|cx| Pin::new(&mut sink).poll_next(cx)).await {
+            blocks.push(block);
+        }
+        assert_eq!(blocks.len(), 25);
+    }
+}
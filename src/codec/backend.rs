@@ -0,0 +1,201 @@
+//! Pluggable implementations of the per-block RaptorQ math, so alternatives, e.g. GPU accelerated GF(256) matrix
+//! ops or SIMD optimized forks of raptorq, can be tried without touching the rest of the codec. BlockEncoder and
+//! BlockDecoder keep the framing, padding, symbol accounting and systematic fast path, and hand a backend only the
+//! packets to encode or decode. Backends must stay wire compatible with RFC 6330, as peers may use other backends;
+//! see backend_bench for comparing one against the raptorq crate.
+
+use std::sync::Arc;
+
+use raptorq::{EncodingPacket, ObjectTransmissionInformation, SourceBlockDecoder, SourceBlockEncoder};
+
+use super::plan_cache::PlanCache;
+
+/// Creates the encoders and decoders of single blocks.
+pub trait BlockCodecBackend: Send + Sync {
+    /// Name of the backend, for benches and logs.
+    fn name(&self) -> &str;
+
+    /// Creates an encoder of a block of data, a multiple of config's symbol size long, with a single source block
+    /// and sub-block. plan_cache has raptorq encoding plans by symbol count; backends without plans ignore it.
+    fn new_encoder(&self, config: &ObjectTransmissionInformation, data: &[u8], plan_cache: Option<&PlanCache>) -> Box<dyn BlockSymbolEncoder>;
+
+    /// Creates a decoder of a block of padded_size bytes encoded with config.
+    fn new_decoder(&self, config: &ObjectTransmissionInformation, padded_size: u64) -> Box<dyn BlockSymbolDecoder>;
+}
+
+/// Encoder of one block. Shared between threads generating symbols at once.
+pub trait BlockSymbolEncoder: Send + Sync {
+    /// Creates the source packets, i.e. the data itself split into packets.
+    fn source_packets(&self) -> Vec<EncodingPacket>;
+
+    /// Creates count repair packets, numbered from repair symbol start.
+    fn repair_packets(&self, start: u32, count: u32) -> Vec<EncodingPacket>;
+}
+
+/// Decoder of one block, retaining packets between calls to decode.
+pub trait BlockSymbolDecoder: Send + Sync {
+    /// Adds packets, returning the padded block once enough were added.
+    fn decode(&mut self, packets: Vec<EncodingPacket>) -> Option<Vec<u8>>;
+
+    /// Adds packets, writing the first out.len() bytes of the padded block into out once enough were added and
+    /// returning whether they were. Backends able to decode in place override it; by default the block from decode
+    /// is copied in.
+    fn decode_into(&mut self, packets: Vec<EncodingPacket>, out: &mut [u8]) -> bool {
+        match self.decode(packets) {
+            None => return false,
+            Some(data) => {
+                out.copy_from_slice(&data[..out.len()]);
+                return true;
+            },
+        }
+    }
+
+    /// Copies the decoder with the packets added so far, as BlockDecoder clones do.
+    fn box_clone(&self) -> Box<dyn BlockSymbolDecoder>;
+}
+
+impl Clone for Box<dyn BlockSymbolDecoder> {
+    fn clone(&self) -> Box<dyn BlockSymbolDecoder> {
+        return self.box_clone();
+    }
+}
+
+/// The raptorq crate, used unless another backend is given.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RaptorqBackend;
+
+impl RaptorqBackend {
+    /// Gets the backend as the codec takes backends.
+    pub fn shared() -> Arc<dyn BlockCodecBackend> {
+        return Arc::new(RaptorqBackend);
+    }
+}
+
+impl BlockCodecBackend for RaptorqBackend {
+    fn name(&self) -> &str {
+        return "raptorq";
+    }
+
+    fn new_encoder(&self, config: &ObjectTransmissionInformation, data: &[u8], plan_cache: Option<&PlanCache>) -> Box<dyn BlockSymbolEncoder> {
+        match plan_cache {
+            None => return Box::new(SourceBlockEncoder::new2(0, config, data)),
+            Some(plan_cache) => {
+                let plan = plan_cache.get((data.len() / config.symbol_size() as usize) as u16);
+                return Box::new(SourceBlockEncoder::with_encoding_plan2(0, config, data, &plan));
+            },
+        }
+    }
+
+    fn new_decoder(&self, config: &ObjectTransmissionInformation, padded_size: u64) -> Box<dyn BlockSymbolDecoder> {
+        return Box::new(SourceBlockDecoder::new2(0, config, padded_size));
+    }
+}
+
+impl BlockSymbolEncoder for SourceBlockEncoder {
+    fn source_packets(&self) -> Vec<EncodingPacket> {
+        return SourceBlockEncoder::source_packets(self);
+    }
+
+    fn repair_packets(&self, start: u32, count: u32) -> Vec<EncodingPacket> {
+        return SourceBlockEncoder::repair_packets(self, start, count);
+    }
+}
+
+impl BlockSymbolDecoder for SourceBlockDecoder {
+    fn decode(&mut self, packets: Vec<EncodingPacket>) -> Option<Vec<u8>> {
+        return SourceBlockDecoder::decode(self, packets);
+    }
+
+    fn box_clone(&self) -> Box<dyn BlockSymbolDecoder> {
+        return Box::new(self.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::decoder::{BlockDecoder, RaptorQDecoder};
+    use crate::codec::encoder::{EncoderConfig, RaptorQEncoder};
+    use std::io::IoSlice;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts what goes through raptorq, as a stand-in for another backend.
+    #[derive(Default)]
+    struct CountingBackend {
+        encoders: AtomicUsize,
+        decoders: AtomicUsize,
+        decoded_in_place: Arc<AtomicUsize>,
+    }
+
+    /// Counts the blocks decoded into a caller's buffer.
+    struct CountingDecoder {
+        decoder: Box<dyn BlockSymbolDecoder>,
+        decoded_in_place: Arc<AtomicUsize>,
+    }
+
+    impl BlockSymbolDecoder for CountingDecoder {
+        fn decode(&mut self, packets: Vec<EncodingPacket>) -> Option<Vec<u8>> {
+            return self.decoder.decode(packets);
+        }
+
+        fn decode_into(&mut self, packets: Vec<EncodingPacket>, out: &mut [u8]) -> bool {
+            self.decoded_in_place.fetch_add(1, Ordering::Relaxed);
+            return self.decoder.decode_into(packets, out);
+        }
+
+        fn box_clone(&self) -> Box<dyn BlockSymbolDecoder> {
+            return Box::new(CountingDecoder { decoder: self.decoder.clone(), decoded_in_place: self.decoded_in_place.clone() });
+        }
+    }
+
+    impl BlockCodecBackend for CountingBackend {
+        fn name(&self) -> &str {
+            return "counting";
+        }
+
+        fn new_encoder(&self, config: &ObjectTransmissionInformation, data: &[u8], plan_cache: Option<&PlanCache>) -> Box<dyn BlockSymbolEncoder> {
+            self.encoders.fetch_add(1, Ordering::Relaxed);
+            return RaptorqBackend.new_encoder(config, data, plan_cache);
+        }
+
+        fn new_decoder(&self, config: &ObjectTransmissionInformation, padded_size: u64) -> Box<dyn BlockSymbolDecoder> {
+            self.decoders.fetch_add(1, Ordering::Relaxed);
+            return Box::new(CountingDecoder { decoder: RaptorqBackend.new_decoder(config, padded_size), decoded_in_place: self.decoded_in_place.clone() });
+        }
+    }
+
+    #[test]
+    fn test_block_codec_backend() {
+        let data = gen_data(300 * 1000);
+        let backend = Arc::new(CountingBackend::default());
+        let encoder = RaptorQEncoder::with_backend(EncoderConfig::new(1280), &[IoSlice::new(&data)], backend.clone()).unwrap();
+        let block_count = encoder.get_block_encoders().len();
+        assert_eq!(backend.encoders.load(Ordering::Relaxed), block_count);
+
+        // evicted blocks are encoded again with the same backend
+        encoder.evict();
+        let blocks = encoder.generate_encoded_blocks();
+        assert_eq!(backend.encoders.load(Ordering::Relaxed), 2 * block_count);
+
+        // repair symbols only, so every block goes through the backend's decoder
+        let mut decoder = RaptorQDecoder::with_backend(encoder.get_block_info_vec(), backend.clone()).unwrap();
+        assert!(decoder.consume(blocks.clone()).unwrap());
+        assert_eq!(decoder.get_result().unwrap(), data);
+        assert_eq!(backend.decoders.load(Ordering::Relaxed), block_count);
+
+        // symbols of one backend decode with another
+        let mut decoder = RaptorQDecoder::new(encoder.get_block_info_vec()).unwrap();
+        assert!(decoder.consume(blocks).unwrap());
+        assert_eq!(decoder.get_result().unwrap(), data);
+        assert_eq!(backend.decoders.load(Ordering::Relaxed), block_count);
+
+        // decoding into a buffer goes through the backend's decode_into
+        let block_encoder = &encoder.get_block_encoders()[0];
+        let block_decoder = BlockDecoder::with_backend(block_encoder.get_block_info(), backend.clone()).unwrap();
+        let mut out = vec![0; block_decoder.get_block_info().payload_size];
+        assert_eq!(block_decoder.decode_into(block_encoder.generate_repair_blocks(0, block_encoder.get_symbol_count() + 2), &mut out), Ok(out.len()));
+        assert_eq!(out[..], data[..out.len()]);
+        assert_eq!(backend.decoded_in_place.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,177 @@
+//! Harness comparing block codec backends, see backend. Each backend encodes an object and decodes the symbols that
+//! make it through a lossy channel, as the bench command does with the raptorq crate, and is checked against the
+//! raptorq crate both ways: its symbols must decode with raptorq, and raptorq's symbols with it. Backends live in
+//! crates of their own, which call compare_backends from their benches.
+
+use std::io::IoSlice;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::sim::channel::LossyChannel;
+use super::backend::{BlockCodecBackend, RaptorqBackend};
+use super::decoder::{RaptorQDecoder, RaptorQDecoderError};
+use super::encoder::{EncodedBlock, EncoderConfig, RaptorQEncoder, RaptorQEncoderError};
+use super::producer::{SymbolProducer, SymbolProducerError};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendBenchError {
+    Encoder(RaptorQEncoderError),
+    Decoder(RaptorQDecoderError),
+    Producer(SymbolProducerError),
+}
+
+/// How one backend did on one object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackendBenchResult {
+    /// Name of the backend.
+    pub backend: String,
+    /// Time to encode every block, i.e. computing the intermediate symbols.
+    pub encode_time: Duration,
+    /// Time spent generating the symbols sent.
+    pub repair_time: Duration,
+    /// Time spent decoding the symbols received.
+    pub decode_time: Duration,
+    /// Symbols received until the object was decoded, and source symbols in the object.
+    pub symbols_received: u64,
+    pub symbol_count: usize,
+    /// Whether the object decoded to the data encoded.
+    pub decoded: bool,
+    /// Whether the backend's symbols decode with the raptorq crate and the other way round.
+    pub interoperable: bool,
+}
+
+impl BackendBenchResult {
+    /// Gets throughput of size bytes over time, in megabits per second.
+    pub fn get_mbps(size: usize, time: Duration) -> f64 {
+        return size as f64 * 8.0 / 1e6 / time.as_secs_f64();
+    }
+}
+
+/// Symbols sent until an object decoded, and what the receiving end got.
+struct Transfer {
+    repair_time: Duration,
+    decode_time: Duration,
+    received: Vec<EncodedBlock>,
+    data: Option<Vec<u8>>,
+}
+
+/// Sends symbols of encoder through channel to decoder, 16 at a time, until the object decodes or symbol_limit
+/// symbols were sent.
+fn transfer(encoder: RaptorQEncoder, mut decoder: RaptorQDecoder, channel: &mut LossyChannel, symbol_limit: u64) -> Result<Transfer, BackendBenchError> {
+    let mut producer = SymbolProducer::new(encoder);
+    let session_id = producer.open_session();
+    let mut transfer = Transfer { repair_time: Duration::ZERO, decode_time: Duration::ZERO, received: Vec::new(), data: None };
+    while channel.get_sent() < symbol_limit {
+        let start = Instant::now();
+        let blocks = producer.next_symbols(session_id, 16).map_err(BackendBenchError::Producer)?;
+        transfer.repair_time += start.elapsed();
+        let blocks = channel.transmit(blocks);
+        transfer.received.extend(blocks.iter().cloned());
+
+        let start = Instant::now();
+        let decoded = decoder.consume(blocks).map_err(BackendBenchError::Decoder)?;
+        transfer.decode_time += start.elapsed();
+        if decoded {
+            transfer.data = decoder.get_result();
+            break;
+        }
+    }
+    return Ok(transfer);
+}
+
+/// Decodes blocks all at once with backend, returning whether they decode to data.
+fn decodes_to(backend: Arc<dyn BlockCodecBackend>, encoder: &RaptorQEncoder, blocks: Vec<EncodedBlock>, data: &[u8]) -> Result<bool, BackendBenchError> {
+    let mut decoder = RaptorQDecoder::with_backend(encoder.get_block_info_vec(), backend).map_err(BackendBenchError::Decoder)?;
+    let decoded = decoder.consume(blocks).map_err(BackendBenchError::Decoder)?;
+    return Ok(decoded && decoder.get_result().as_deref() == Some(data));
+}
+
+/// Benches backend on data, losing loss of the packets sent, in 0.0..=1.0. A backend failing to decode gives up
+/// after sending four times the object's symbols.
+pub fn bench_backend(backend: Arc<dyn BlockCodecBackend>, data: &[u8], config: EncoderConfig, loss: f64) -> Result<BackendBenchResult, BackendBenchError> {
+    let start = Instant::now();
+    let encoder = RaptorQEncoder::with_backend(config, &[IoSlice::new(data)], backend.clone()).map_err(BackendBenchError::Encoder)?;
+    let encode_time = start.elapsed();
+
+    let symbol_count: usize = encoder.get_block_encoders().iter().map(|x| x.get_symbol_count()).sum();
+    let symbol_limit = 4 * symbol_count as u64;
+    let decoder = RaptorQDecoder::with_backend(encoder.get_block_info_vec(), backend.clone()).map_err(BackendBenchError::Decoder)?;
+    let mut channel = LossyChannel::new(loss);
+    let sent = transfer(encoder.clone(), decoder, &mut channel, symbol_limit)?;
+
+    // the same symbols with the raptorq crate's decoder, and symbols of its encoder with the backend's decoder
+    let reference = RaptorQEncoder::with_config(config, &[IoSlice::new(data)]).map_err(BackendBenchError::Encoder)?;
+    let reference_blocks = reference.generate_encoded_blocks();
+    let interoperable = decodes_to(RaptorqBackend::shared(), &encoder, sent.received, data)?
+        && decodes_to(backend.clone(), &reference, reference_blocks, data)?;
+
+    return Ok(BackendBenchResult {
+        backend: backend.name().to_string(),
+        encode_time,
+        repair_time: sent.repair_time,
+        decode_time: sent.decode_time,
+        symbols_received: channel.get_delivered(),
+        symbol_count,
+        decoded: sent.data.as_deref() == Some(data),
+        interoperable,
+    });
+}
+
+/// Benches each backend on the same data, see bench_backend.
+pub fn compare_backends(backends: &[Arc<dyn BlockCodecBackend>], data: &[u8], config: EncoderConfig, loss: f64) -> Result<Vec<BackendBenchResult>, BackendBenchError> {
+    return backends.iter().map(|x| bench_backend(x.clone(), data, config, loss)).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::backend::{BlockSymbolDecoder, BlockSymbolEncoder};
+    use super::super::plan_cache::PlanCache;
+    use raptorq::ObjectTransmissionInformation;
+
+    /// Decodes nothing, as a broken backend would.
+    struct NeverDecodes;
+
+    struct Undecodable;
+
+    impl BlockSymbolDecoder for Undecodable {
+        fn decode(&mut self, _packets: Vec<raptorq::EncodingPacket>) -> Option<Vec<u8>> {
+            return None;
+        }
+
+        fn box_clone(&self) -> Box<dyn BlockSymbolDecoder> {
+            return Box::new(Undecodable);
+        }
+    }
+
+    impl BlockCodecBackend for NeverDecodes {
+        fn name(&self) -> &str {
+            return "never";
+        }
+
+        fn new_encoder(&self, config: &ObjectTransmissionInformation, data: &[u8], plan_cache: Option<&PlanCache>) -> Box<dyn BlockSymbolEncoder> {
+            return RaptorqBackend.new_encoder(config, data, plan_cache);
+        }
+
+        fn new_decoder(&self, _config: &ObjectTransmissionInformation, _padded_size: u64) -> Box<dyn BlockSymbolDecoder> {
+            return Box::new(Undecodable);
+        }
+    }
+
+    #[test]
+    fn test_compare_backends() {
+        let data = gen_data(200 * 1000);
+        let backends: Vec<Arc<dyn BlockCodecBackend>> = vec![RaptorqBackend::shared(), Arc::new(NeverDecodes)];
+        let results = compare_backends(&backends, &data, EncoderConfig::new(1280), 0.1).unwrap();
+
+        assert_eq!(results[0].backend, "raptorq");
+        assert!(results[0].decoded && results[0].interoperable);
+        assert!(results[0].symbols_received >= results[0].symbol_count as u64);
+        assert!(BackendBenchResult::get_mbps(data.len(), results[0].encode_time) > 0.0);
+
+        // gives up rather than sending forever
+        assert_eq!(results[1].backend, "never");
+        assert!(!results[1].decoded && !results[1].interoperable);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, IoSlice, Write};
+
+use super::encoder::{EncoderConfig, RaptorQEncoder, RaptorQEncoderError};
+
+/// First bytes of every bundle, followed by the index.
+const BUNDLE_MAGIC: &[u8; 8] = b"RCDNBDL1";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BundleError {
+    /// Two entries share a name.
+    DuplicateName,
+    /// Entry name does not fit the index, or is empty.
+    InvalidName,
+    /// Decoded payload is not a bundle, or is truncated.
+    Malformed,
+}
+
+/// Gets the size of a bundle of entries with the given names and sizes, as Bundler::write_to writes it, e.g. to tell
+/// whether the files of a bundle changed without reading them.
+pub fn get_bundle_size<'b, I: IntoIterator<Item = (&'b str, u64)>>(entries: I) -> u64 {
+    let mut size = BUNDLE_MAGIC.len() as u64 + 4;
+    for (name, len) in entries {
+        size += 2 + name.len() as u64 + 8 + len;
+    }
+    return size;
+}
+
+/// Packs many small files into a single object, so they are encoded and transmitted together.
+/// The object starts with an index of entry names and sizes, followed by the entries' contents back to back.
+/// All integers are little endian.
+pub struct Bundler<'a> {
+    entries: Vec<(String, &'a [u8])>,
+    names: HashSet<String>,
+}
+
+impl<'a> Bundler<'a> {
+    pub fn new() -> Bundler<'a> {
+        return Bundler {
+            entries: Vec::new(),
+            names: HashSet::new(),
+        };
+    }
+
+    /// Adds an entry. Entries are extracted in the order they were added.
+    pub fn add(&mut self, name: &str, data: &'a [u8]) -> Result<(), BundleError> {
+        if name.is_empty() || name.len() > u16::MAX as usize {
+            return Err(BundleError::InvalidName);
+        }
+        if !self.names.insert(name.to_string()) {
+            return Err(BundleError::DuplicateName);
+        }
+
+        self.entries.push((name.to_string(), data));
+        return Ok(());
+    }
+
+    /// Serializes the index: magic, entry count (u32), then for each entry its name length (u16), name and size (u64).
+    fn index(&self) -> Vec<u8> {
+        let mut index: Vec<u8> = Vec::new();
+        index.extend_from_slice(BUNDLE_MAGIC);
+        index.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for (name, data) in self.entries.iter() {
+            index.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            index.extend_from_slice(name.as_bytes());
+            index.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        }
+        return index;
+    }
+
+    /// Encodes the bundle as one object, without copying entries into an intermediate buffer.
+    pub fn encode(&self, config: EncoderConfig) -> Result<RaptorQEncoder, RaptorQEncoderError> {
+        let index = self.index();
+        let mut bufs: Vec<IoSlice> = vec![IoSlice::new(&index)];
+        bufs.extend(self.entries.iter().map(|(_, data)| IoSlice::new(data)));
+        return RaptorQEncoder::with_config(config, &bufs);
+    }
+
+    /// Writes the bundle unencoded, e.g. to be encoded later.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.index())?;
+        for (_, data) in self.entries.iter() {
+            writer.write_all(data)?;
+        }
+        return writer.flush();
+    }
+}
+
+impl<'a> Default for Bundler<'a> {
+    fn default() -> Bundler<'a> {
+        return Bundler::new();
+    }
+}
+
+/// Extracts entries from a decoded bundle.
+pub struct BundleExtractor<'a> {
+    /// Entry names and contents, in bundle order.
+    entries: Vec<(&'a str, &'a [u8])>,
+}
+
+impl<'a> BundleExtractor<'a> {
+    /// Parses the index of a decoded bundle. Fails if the index is malformed or the entries are truncated.
+    pub fn new(data: &'a [u8]) -> Result<BundleExtractor<'a>, BundleError> {
+        let mut rest = match data.strip_prefix(&BUNDLE_MAGIC[..]) {
+            None => return Err(BundleError::Malformed),
+            Some(rest) => rest,
+        };
+        let mut take = |len: usize| -> Result<&'a [u8], BundleError> {
+            if rest.len() < len {
+                return Err(BundleError::Malformed);
+            }
+            let (taken, remaining) = rest.split_at(len);
+            rest = remaining;
+            return Ok(taken);
+        };
+
+        let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let mut index: Vec<(&'a str, u64)> = Vec::new();
+        for _ in 0..count {
+            let name_len = u16::from_le_bytes(take(2)?.try_into().unwrap());
+            let name = match std::str::from_utf8(take(name_len as usize)?) {
+                Ok(name) => name,
+                Err(_) => return Err(BundleError::Malformed),
+            };
+            let len = u64::from_le_bytes(take(8)?.try_into().unwrap());
+            index.push((name, len));
+        }
+
+        let mut entries: Vec<(&'a str, &'a [u8])> = Vec::with_capacity(index.len());
+        for (name, len) in index {
+            let len = match usize::try_from(len) {
+                Ok(len) => len,
+                Err(_) => return Err(BundleError::Malformed),
+            };
+            entries.push((name, take(len)?));
+        }
+
+        return Ok(BundleExtractor { entries });
+    }
+
+    /// Gets the contents of the entry with the given name.
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        return self.entries.iter().find(|(x, _)| *x == name).map(|(_, data)| *data);
+    }
+
+    /// Gets every entry's name and contents, in the order they were bundled.
+    pub fn entries(&self) -> &[(&'a str, &'a [u8])] {
+        return &self.entries;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::decoder::*;
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let files: Vec<(String, Vec<u8>)> = (0..100).map(|x| (format!("file-{}.txt", x), gen_data(x * 37))).collect();
+
+        let mut bundler = Bundler::new();
+        for (name, data) in files.iter() {
+            bundler.add(name, data).unwrap();
+        }
+        assert_eq!(bundler.add("file-0.txt", &[]), Err(BundleError::DuplicateName));
+
+        let encoder = match bundler.encode(EncoderConfig::new(1280)) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        assert_eq!(decoder.consume(blocks), Ok(true));
+
+        let decoded = decoder.get_result().unwrap();
+        let mut written: Vec<u8> = Vec::new();
+        bundler.write_to(&mut written).unwrap();
+        assert_eq!(decoded, written);
+        assert_eq!(get_bundle_size(files.iter().map(|(name, data)| (&name[..], data.len() as u64))), written.len() as u64);
+
+        let extractor = match BundleExtractor::new(&decoded) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to parse bundle, error {}", error as u32),
+        };
+        assert_eq!(extractor.entries().len(), files.len());
+        for (name, data) in files.iter() {
+            assert_eq!(extractor.get(name), Some(&data[..]));
+        }
+
+        match BundleExtractor::new(&decoded[..(decoded.len() - 1)]) {
+            Ok(_) => panic!("Should have failed to parse a truncated bundle"),
+            Err(error) => assert_eq!(error, BundleError::Malformed),
+        }
+    }
+}
@@ -1,7 +1,3 @@
-const fn const_min(a: usize, b: usize) -> usize {
-    [a, b][(a > b) as usize]
-}
-
 /*
  * Constants defined in the RPC spec are prefixed with RAPTORQ_
  * Other constants are defined by the encoder implementation. 
@@ -12,8 +8,13 @@ pub const RAPTORQ_ENCODING_SYMBOL_ID_MAX: usize = 1 << 24;
 /// Maximum symbols allowed to be in a block in the raptorq spec. 
 pub const RAPTORQ_MAX_SYMBOLS_IN_BLOCK: usize = 56403;
 
-/// Alignment of symbols in memory in bytes.
+/// Default alignment of symbols in memory in bytes, see EncoderConfig.
 pub const ALIGNMENT: u8 = 8;
 
 // We enforce a minimum packet size for our encoder - not specified in RFC, but it makes code easier. 
-pub const MIN_PACKET_SIZE: u16 = 512;
\ No newline at end of file
+pub const MIN_PACKET_SIZE: u16 = 512;
+
+/// Distinct symbols a block decoder keeps beyond the block's source symbols by default, see
+/// BlockDecoder::set_symbol_slack. RFC 6330 puts the odds of failing to decode from two extra symbols below one in a
+/// million, each further symbol making it rarer still.
+pub const DEFAULT_SYMBOL_SLACK: usize = 32;
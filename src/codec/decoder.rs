@@ -1,30 +1,627 @@
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
 use raptorq::{
-    EncodingPacket, ObjectTransmissionInformation, SourceBlockDecoder,
+    extended_source_block_symbols, EncodingPacket, PayloadId,
 };
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use super::aligned::AlignedBuffer;
+use super::backend::{BlockCodecBackend, BlockSymbolDecoder, RaptorqBackend};
+use super::consts::*;
+use super::hugepage::PageBuffer;
 use super::encoder::{
     BlockInfo,
+    BlockRegion,
     EncodedBlock,
 };
+use super::manifest::Manifest;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum RaptorQDecoderError {
     /// TODO: make errors more useful. 
     BadBlockId,
     RaptorQDecodeFailed,
+    /// A block info is invalid, or two block infos of the same block differ.
+    InvalidBlockInfo,
+    /// Block infos do not cover blocks 0..n, listing the ids of the blocks missing.
+    MissingBlocks(Vec<u32>),
+    /// Result was requested before every block was decoded.
+    BlockNotDecoded,
+    /// Output buffer is smaller than the payload.
+    BufferTooSmall,
+    /// Requested alignment is not a power of two.
+    InvalidAlignment,
 }
 
-/// A representation of a BlockDecoder
+/// A representation of a RaptorQDecoder. Holds one BlockDecoder per block of the payload. Clones carry on
+/// independently from the symbols received so far.
+#[derive(Clone)]
+pub struct RaptorQDecoder {
+    block_decoders: Vec<BlockDecoder>,
+    /// Innovation of the symbols fed through consume_from, per sender.
+    senders: HashMap<u64, InnovationStats>,
+}
+
+/// How useful the symbols received from one sender were. A symbol is innovative if it is the first copy of its
+/// encoding symbol id for a block that was not decoded yet; anything else can't help decoding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InnovationStats {
+    /// Symbols received.
+    pub received: u64,
+    /// Innovative symbols received.
+    pub innovative: u64,
+    /// Symbols received since the last innovative one.
+    pub redundant_streak: u64,
+}
+
+impl InnovationStats {
+    /// Gets the fraction of received symbols that were innovative, 1 if nothing was received.
+    pub fn get_innovation_rate(&self) -> f64 {
+        if self.received == 0 {
+            return 1.0;
+        }
+        return self.innovative as f64 / self.received as f64;
+    }
+}
+
+impl RaptorQDecoder {
+    /// Creates a RaptorQDecoder from the block infos produced by RaptorQEncoder::get_block_info_vec, in any order.
+    /// Repeated block infos are fine as long as they are the same, e.g. when several copies of a manifest were
+    /// merged. Fails with MissingBlocks if any block below the highest block id is missing.
+    pub fn new(block_info_vec: Vec<BlockInfo>) -> Result<RaptorQDecoder, RaptorQDecoderError> {
+        let block_count = block_info_vec.iter().map(|x| x.block_id as usize + 1).max().unwrap_or(0);
+        return RaptorQDecoder::with_block_count(block_info_vec, block_count, RaptorqBackend::shared());
+    }
+
+    /// Creates a RaptorQDecoder as new does, decoding every block with backend instead of the raptorq crate.
+    pub fn with_backend(block_info_vec: Vec<BlockInfo>, backend: Arc<dyn BlockCodecBackend>) -> Result<RaptorQDecoder, RaptorQDecoderError> {
+        let block_count = block_info_vec.iter().map(|x| x.block_id as usize + 1).max().unwrap_or(0);
+        return RaptorQDecoder::with_block_count(block_info_vec, block_count, backend);
+    }
+
+    /// Creates a RaptorQDecoder for the object described by manifest. Unlike new, this also notices blocks missing
+    /// at the end, as the manifest hashes every block.
+    pub fn from_manifest(manifest: &Manifest) -> Result<RaptorQDecoder, RaptorQDecoderError> {
+        return RaptorQDecoder::with_block_count(manifest.block_info_vec.clone(), manifest.block_hashes.len(), RaptorqBackend::shared());
+    }
+
+    fn with_block_count(block_info_vec: Vec<BlockInfo>, block_count: usize, backend: Arc<dyn BlockCodecBackend>) -> Result<RaptorQDecoder, RaptorQDecoderError> {
+        let block_info_vec = RaptorQDecoder::order_block_infos(block_info_vec, block_count)?;
+        let mut block_decoders: Vec<BlockDecoder> = Vec::with_capacity(block_count);
+        for block_info in block_info_vec.into_iter() {
+            block_decoders.push(BlockDecoder::with_backend(block_info, backend.clone())?);
+        }
+        return Ok(RaptorQDecoder { block_decoders, senders: HashMap::new() });
+    }
+
+    /// Orders block infos by block id, dropping repeats, and checks they cover blocks 0..block_count.
+    fn order_block_infos(mut block_info_vec: Vec<BlockInfo>, block_count: usize) -> Result<Vec<BlockInfo>, RaptorQDecoderError> {
+        if block_info_vec.iter().any(|x| x.block_id as usize >= block_count) {
+            return Err(RaptorQDecoderError::InvalidBlockInfo);
+        }
+        block_info_vec.sort_by_key(|x| x.block_id);
+        block_info_vec.dedup();
+        // repeats that did not dedup differ from each other
+        if block_info_vec.windows(2).any(|x| x[0].block_id == x[1].block_id) {
+            return Err(RaptorQDecoderError::InvalidBlockInfo);
+        }
+
+        if block_info_vec.len() < block_count {
+            let mut missing: Vec<u32> = Vec::with_capacity(block_count - block_info_vec.len());
+            let mut infos = block_info_vec.iter().peekable();
+            for block_id in 0..(block_count as u32) {
+                match infos.peek() {
+                    Some(block_info) if block_info.block_id == block_id => {
+                        infos.next();
+                    },
+                    _ => missing.push(block_id),
+                }
+            }
+            return Err(RaptorQDecoderError::MissingBlocks(missing));
+        }
+
+        return Ok(block_info_vec);
+    }
+
+    /// Takes an updated manifest of a growing object, see Manifest::append, adding decoders for the blocks it appends
+    /// while keeping everything received for the blocks before. Updates may come in any order: one with no more
+    /// blocks than the decoder has is stale and changes nothing. Returns true if blocks were added, which then take
+    /// symbols and share the slack and huge pages setting of the last block. Fails with InvalidBlockInfo if the
+    /// manifest's blocks differ from the decoder's, and MissingBlocks if it lacks some. Block hashes are not checked,
+    /// see Manifest::is_prefix_of.
+    pub fn update_manifest(&mut self, manifest: &Manifest) -> Result<bool, RaptorQDecoderError> {
+        let block_info_vec = RaptorQDecoder::order_block_infos(manifest.block_info_vec.clone(), manifest.block_hashes.len())?;
+        let known = std::cmp::min(self.block_decoders.len(), block_info_vec.len());
+        if self.block_decoders.iter().zip(block_info_vec.iter()).any(|(x, y)| x.block_info != *y) {
+            return Err(RaptorQDecoderError::InvalidBlockInfo);
+        }
+        if block_info_vec.len() == known {
+            return Ok(false);
+        }
+
+        let (slack, huge_pages, backend) = match self.block_decoders.last() {
+            Some(last) => (last.symbol_slack, last.huge_pages, last.backend.clone()),
+            None => (DEFAULT_SYMBOL_SLACK, false, RaptorqBackend::shared()),
+        };
+        let mut added: Vec<BlockDecoder> = Vec::with_capacity(block_info_vec.len() - known);
+        for block_info in block_info_vec.into_iter().skip(known) {
+            let mut block_decoder = BlockDecoder::with_backend(block_info, backend.clone())?;
+            block_decoder.set_symbol_slack(slack);
+            block_decoder.set_huge_pages(huge_pages);
+            added.push(block_decoder);
+        }
+        self.block_decoders.append(&mut added);
+        return Ok(true);
+    }
+
+    /// Feeds encoded blocks to the block decoders they belong to. Returns true once every block is decoded.
+    pub fn consume(&mut self, blocks: Vec<EncodedBlock>) -> Result<bool, RaptorQDecoderError> {
+        self.consume_counting(blocks)?;
+        return Ok(self.is_decoded());
+    }
+
+    /// Like consume, also tracking how many of the symbols were innovative for the sender they came from, e.g. a
+    /// peer or server session id. Once a sender's symbols stop being innovative, it is only using up bandwidth.
+    pub fn consume_from(&mut self, sender: u64, blocks: Vec<EncodedBlock>) -> Result<bool, RaptorQDecoderError> {
+        let received = blocks.len() as u64;
+        let innovative = self.consume_counting(blocks)? as u64;
+
+        let stats = self.senders.entry(sender).or_default();
+        stats.received += received;
+        stats.innovative += innovative;
+        if innovative > 0 {
+            // the innovative symbols may not have been last in the batch, but close enough
+            stats.redundant_streak = 0;
+        } else {
+            stats.redundant_streak += received;
+        }
+
+        return Ok(self.is_decoded());
+    }
+
+    /// Gets how innovative a sender's symbols were, or None if nothing was consumed from it.
+    pub fn get_innovation_stats(&self, sender: u64) -> Option<InnovationStats> {
+        return self.senders.get(&sender).copied();
+    }
+
+    /// Routes blocks to their block decoders, returning how many were innovative.
+    fn consume_counting(&mut self, blocks: Vec<EncodedBlock>) -> Result<u32, RaptorQDecoderError> {
+        let mut routed: Vec<Vec<EncodedBlock>> = vec![Vec::new(); self.block_decoders.len()];
+        for block in blocks {
+            match routed.get_mut(block.block_id as usize) {
+                None => return Err(RaptorQDecoderError::BadBlockId),
+                Some(bucket) => bucket.push(block),
+            }
+        }
+
+        let mut innovative: u32 = 0;
+        for (block_decoder, bucket) in self.block_decoders.iter_mut().zip(routed) {
+            if !bucket.is_empty() {
+                innovative += block_decoder.consume_counting(bucket)?;
+            }
+        }
+
+        return Ok(innovative);
+    }
+
+    /// Routes encoded blocks to their block decoders without decoding them yet, see decode_for. Blocks beyond what
+    /// a block decoder would keep are dropped right away, see BlockDecoder::set_symbol_slack.
+    pub fn queue(&mut self, blocks: Vec<EncodedBlock>) -> Result<(), RaptorQDecoderError> {
+        if blocks.iter().any(|x| x.block_id as usize >= self.block_decoders.len()) {
+            return Err(RaptorQDecoderError::BadBlockId);
+        }
+        for block in blocks {
+            let block_decoder = &mut self.block_decoders[block.block_id as usize];
+            if block_decoder.is_decoded() || block_decoder.received_esi.len() + block_decoder.queued.len() >= block_decoder.get_max_symbols() {
+                block_decoder.stats.dropped_symbols += 1;
+            } else {
+                block_decoder.queued.push(block);
+            }
+        }
+        return Ok(());
+    }
+
+    /// Sets the slack of every block decoder, see BlockDecoder::set_symbol_slack.
+    pub fn set_symbol_slack(&mut self, slack: usize) {
+        for block_decoder in self.block_decoders.iter_mut() {
+            block_decoder.set_symbol_slack(slack);
+        }
+    }
+
+    /// Sets whether every block decoder keeps its scratch space in huge pages, see BlockDecoder::set_huge_pages.
+    pub fn set_huge_pages(&mut self, huge_pages: bool) {
+        for block_decoder in self.block_decoders.iter_mut() {
+            block_decoder.set_huge_pages(huge_pages);
+        }
+    }
+
+    /// Feeds queued blocks to their block decoders, one block at a time, until budget is spent. Returns true once
+    /// every block is decoded. Decoding a block can't be interrupted, so a call may overrun budget by one block's
+    /// decode, but it never stalls on all blocks at once as consume can when the last symbols of many blocks
+    /// arrive together. Hosts that interleave decoding with other work call this until it returns true or nothing
+    /// is queued, yielding in between.
+    pub fn decode_for(&mut self, budget: Duration) -> Result<bool, RaptorQDecoderError> {
+        let start = Instant::now();
+        for block_decoder in self.block_decoders.iter_mut().filter(|x| !x.queued.is_empty()) {
+            block_decoder.decode_queued()?;
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        return Ok(self.is_decoded());
+    }
+
+    /// Returns true if blocks passed to queue are still waiting for decode_for.
+    pub fn has_queued(&self) -> bool {
+        return self.block_decoders.iter().any(|x| !x.queued.is_empty());
+    }
+
+    /// Returns true once every block is decoded.
+    pub fn is_decoded(&self) -> bool {
+        return self.block_decoders.iter().all(|x| x.is_decoded());
+    }
+
+    pub fn get_block_info_vec(&self) -> Vec<BlockInfo> {
+        return self.block_decoders.iter().map(|x| x.block_info.clone()).collect();
+    }
+
+    /// Gets the region of the original payload covered by each block.
+    pub fn block_map(&self) -> Vec<BlockRegion> {
+        return BlockRegion::map(&self.get_block_info_vec());
+    }
+
+    /// Gets what each block that is not decoded yet still needs, for asking peers for symbols precisely.
+    pub fn get_block_needs(&self) -> Vec<BlockNeeds> {
+        return self.block_decoders.iter()
+            .filter(|x| !x.is_decoded())
+            .map(|x| BlockNeeds {
+                block_id: x.block_info.block_id,
+                symbols_needed: x.get_symbols_needed() as u32,
+                received: x.get_received_ranges(),
+            })
+            .collect();
+    }
+
+    /// Gets what the blocks that are not decoded yet still need, see RepairPlan. A block that is not decoded with
+    /// enough symbols on hand needs at least one more.
+    pub fn get_repair_plan(&self) -> RepairPlan {
+        let mut blocks = self.get_block_needs();
+        blocks.iter_mut().for_each(|x| x.symbols_needed = x.symbols_needed.max(1));
+
+        let mut senders: Vec<(&u64, &InnovationStats)> = self.senders.iter().collect();
+        senders.sort_by(|x, y| {
+            y.1.get_innovation_rate().total_cmp(&x.1.get_innovation_rate()).then(y.1.innovative.cmp(&x.1.innovative)).then(x.0.cmp(y.0))
+        });
+        return RepairPlan { blocks, peers: senders.into_iter().map(|(sender, _)| *sender).collect() };
+    }
+
+    /// Gets statistics about the symbols received, per block.
+    pub fn get_decode_stats(&self) -> Vec<DecodeStats> {
+        return self.block_decoders.iter().map(|x| x.get_decode_stats()).collect();
+    }
+
+    /// Returns the recovered payload, or None if some block is not decoded yet.
+    pub fn get_result(&self) -> Option<Vec<u8>> {
+        let mut result: Vec<u8> = Vec::new();
+        for block_decoder in self.block_decoders.iter() {
+            result.extend_from_slice(block_decoder.get_result()?);
+        }
+
+        return Some(result);
+    }
+
+    /// Returns the recovered payload of a single block, or None if it is unknown or not decoded yet.
+    pub fn get_block_result(&self, block_id: u32) -> Option<&[u8]> {
+        return self.block_decoders.get(block_id as usize)?.get_result();
+    }
+
+    /// Gets the size of the payload, without padding.
+    pub fn get_data_size(&self) -> usize {
+        return self.block_decoders.iter().map(|x| x.block_info.payload_size).sum();
+    }
+
+    /// Copies the recovered payload into a caller-provided buffer. Returns the number of bytes written.
+    pub fn get_result_into(&self, out: &mut [u8]) -> Result<usize, RaptorQDecoderError> {
+        let data_size = self.get_data_size();
+        if out.len() < data_size {
+            return Err(RaptorQDecoderError::BufferTooSmall);
+        }
+
+        for (block_decoder, region) in self.block_decoders.iter().zip(self.block_map()) {
+            match block_decoder.get_result() {
+                None => return Err(RaptorQDecoderError::BlockNotDecoded),
+                Some(data) => out[region.byte_offset..(region.byte_offset + region.len)].copy_from_slice(data),
+            }
+        }
+
+        return Ok(data_size);
+    }
+
+    /// Returns the recovered payload in a buffer aligned to alignment bytes.
+    pub fn get_result_aligned(&self, alignment: usize) -> Result<AlignedBuffer, RaptorQDecoderError> {
+        let mut buffer = match AlignedBuffer::new(self.get_data_size(), alignment) {
+            None => return Err(RaptorQDecoderError::InvalidAlignment),
+            Some(buffer) => buffer,
+        };
+
+        self.get_result_into(&mut buffer)?;
+        return Ok(buffer);
+    }
+
+    /// Marks a block decoded with a payload recovered earlier, e.g. read back from a store when resuming a fetch.
+    /// The payload is not checked against anything but its size, so verify it against the block's hash first.
+    pub fn restore_block(&mut self, block_id: u32, data: Vec<u8>) -> Result<(), RaptorQDecoderError> {
+        match self.block_decoders.get_mut(block_id as usize) {
+            None => return Err(RaptorQDecoderError::BadBlockId),
+            Some(block_decoder) => return block_decoder.restore(data),
+        }
+    }
+
+    /// Forgets everything received for a block, e.g. once its payload failed to match its hash, so it is decoded again
+    /// from the symbols that come after. Keeps the block's slack and huge pages setting.
+    pub fn reset_block(&mut self, block_id: u32) -> Result<(), RaptorQDecoderError> {
+        match self.block_decoders.get_mut(block_id as usize) {
+            None => return Err(RaptorQDecoderError::BadBlockId),
+            Some(block_decoder) => {
+                let (slack, huge_pages) = (block_decoder.symbol_slack, block_decoder.huge_pages);
+                *block_decoder = BlockDecoder::with_backend(block_decoder.block_info.clone(), block_decoder.backend.clone())?;
+                block_decoder.set_symbol_slack(slack);
+                block_decoder.set_huge_pages(huge_pages);
+                return Ok(());
+            },
+        }
+    }
+
+    /// Splits the decoder into independent per-block handles, which can be fed from different threads, and the
+    /// innovation stats of its senders, to hand back to merge.
+    pub fn split(self) -> (Vec<BlockDecoder>, HashMap<u64, InnovationStats>) {
+        return (self.block_decoders, self.senders);
+    }
+
+    /// Reassembles a decoder from handles and sender stats produced by split. Handles may be passed in any order.
+    pub fn merge(mut block_decoders: Vec<BlockDecoder>, senders: HashMap<u64, InnovationStats>) -> Result<RaptorQDecoder, RaptorQDecoderError> {
+        block_decoders.sort_by_key(|x| x.block_info.block_id);
+        for (i, block_decoder) in block_decoders.iter().enumerate() {
+            if block_decoder.block_info.block_id as usize != i {
+                return Err(RaptorQDecoderError::InvalidBlockInfo);
+            }
+        }
+
+        return Ok(RaptorQDecoder { block_decoders, senders });
+    }
+}
+
+/// What a block that is not decoded yet still needs. A sender can skip the encoding symbol ids already received, and
+/// should send at least symbols_needed more.
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockNeeds {
+    pub block_id: u32,
+    /// Distinct symbols still needed before decoding can succeed. Decoding from exactly this many more may still
+    /// fail, so senders should add some overhead.
+    pub symbols_needed: u32,
+    /// Encoding symbol ids received so far, as sorted, disjoint ranges.
+    pub received: Vec<Range<u32>>,
+}
+
+impl BlockNeeds {
+    /// Returns true if the encoding symbol id was already received.
+    pub fn is_received(&self, esi: u32) -> bool {
+        let index = self.received.partition_point(|x| x.end <= esi);
+        return self.received.get(index).is_some_and(|x| x.contains(&esi));
+    }
+}
+
+/// What an undecoded object is short of, for asking senders for exactly that, e.g. in repair requests, rather than
+/// starting over.
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepairPlan {
+    /// Blocks that are not decoded, with what each still needs.
+    pub blocks: Vec<BlockNeeds>,
+    /// Senders to ask first, as passed to RaptorQDecoder::consume_from, most innovative first. Empty if no symbols
+    /// came through consume_from.
+    pub peers: Vec<u64>,
+}
+
+impl RepairPlan {
+    /// Gets the distinct symbols needed over every block.
+    pub fn get_symbols_needed(&self) -> u64 {
+        return self.blocks.iter().map(|x| x.symbols_needed as u64).sum();
+    }
+}
+
+/// A decode that failed, with what it would take to succeed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeFailure {
+    pub error: RaptorQDecoderError,
+    /// What to ask for, or None if more symbols would not help, e.g. on BadBlockId.
+    pub plan: Option<RepairPlan>,
+}
+
+/// Statistics about the symbols a BlockDecoder received. Counting stops once the block is decoded, but for
+/// dropped_symbols.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeStats {
+    /// Distinct source symbols received.
+    pub source_symbols: u32,
+    /// Distinct repair symbols received, all of which are used by inactivation decoding.
+    pub repair_symbols: u32,
+    /// Symbols received more than once.
+    pub duplicate_symbols: u32,
+    /// Malformed symbols, which were dropped.
+    pub invalid_symbols: u32,
+    /// New symbols dropped because the block already had its source symbols plus slack, or was decoded, see
+    /// BlockDecoder::set_symbol_slack.
+    pub dropped_symbols: u32,
+    /// Whether decode took the systematic fast path (every source symbol received) rather than inactivation
+    /// decoding. None if the block is not decoded yet.
+    pub systematic: Option<bool>,
+}
+
+/// Source symbols copied straight into place. As long as no source symbol is lost, as is typical on a LAN, the
+/// block is reassembled from these without RaptorQ decoding.
+#[derive(Clone)]
+struct SystematicBuffer {
+    /// The payload, allocated on the first source symbol. Padding is left out, so the payload needs no copying
+    /// once every source symbol arrived.
+    data: PageBuffer,
+    received: Vec<bool>,
+    /// Source symbols not received yet, once data is allocated.
+    missing: usize,
+}
+
+impl SystematicBuffer {
+    fn new() -> SystematicBuffer {
+        return SystematicBuffer {
+            data: PageBuffer::from(Vec::new()),
+            received: Vec::new(),
+            missing: 0,
+        };
+    }
+
+    /// Copies the payload bytes of a source symbol into place.
+    fn insert(&mut self, packet: &EncodingPacket, block_info: &BlockInfo, huge_pages: bool) {
+        let symbol_size = block_info.config.symbol_size() as usize;
+        if self.received.is_empty() {
+            let symbol_count = block_info.padded_size / symbol_size;
+            self.data = PageBuffer::zeroed(block_info.payload_size, huge_pages);
+            self.received = vec![false; symbol_count];
+            self.missing = symbol_count;
+        }
+
+        let esi = packet.payload_id().encoding_symbol_id() as usize;
+        if !self.received[esi] {
+            self.received[esi] = true;
+            self.missing -= 1;
+            let len = block_info.get_source_symbol_len(esi as u32);
+            self.data[(esi * symbol_size)..(esi * symbol_size + len)].copy_from_slice(&packet.data()[..len]);
+        }
+    }
+
+    /// Turns the received source symbols back into packets, padding included.
+    fn into_packets(self, block_info: &BlockInfo) -> Vec<EncodingPacket> {
+        let symbol_size = block_info.config.symbol_size() as usize;
+        return self.received.iter()
+            .enumerate()
+            .filter(|(_, received)| **received)
+            .map(|(esi, _)| {
+                let len = block_info.get_source_symbol_len(esi as u32);
+                let mut symbol = self.data[(esi * symbol_size)..(esi * symbol_size + len)].to_vec();
+                symbol.resize(symbol_size, 0);
+                return EncodingPacket::new(PayloadId::new(0, esi as u32), symbol);
+            })
+            .collect();
+    }
+}
+
+/// A representation of a BlockDecoder. Clones carry on independently from the symbols received so far, e.g. to
+/// attempt decoding speculatively.
+#[derive(Clone)]
 pub struct BlockDecoder {
     /// Block metadata
     block_info: BlockInfo,
+    /// Encoding symbol ids received so far.
+    received_esi: HashSet<u32>,
+    /// Distinct symbols kept beyond the source symbols, see set_symbol_slack.
+    symbol_slack: usize,
+    /// Whether scratch space is allocated in huge pages, see set_huge_pages.
+    huge_pages: bool,
+    /// Symbol statistics.
+    stats: DecodeStats,
+    /// Source symbols received before any repair symbol, see SystematicBuffer. None once a repair symbol arrived.
+    systematic: Option<SystematicBuffer>,
+    /// RaptorQ decoder, retains packets between calls to consume.
+    decoder: Box<dyn BlockSymbolDecoder>,
+    /// Backend creating the decoder, and those of decode_blocks and decode_into.
+    backend: Arc<dyn BlockCodecBackend>,
+    /// Blocks queued by RaptorQDecoder::queue, not counted or decoded yet.
+    queued: Vec<EncodedBlock>,
+    /// Recovered payload (without padding), once decoded.
+    data: Option<PageBuffer>,
 }
 
 impl BlockDecoder {
     pub fn new(block_info: BlockInfo) -> Result<BlockDecoder, RaptorQDecoderError> {
-        return Ok(BlockDecoder{block_info: block_info});
+        return BlockDecoder::with_backend(block_info, RaptorqBackend::shared());
+    }
+
+    /// Creates a BlockDecoder as new does, decoding with backend instead of the raptorq crate.
+    pub fn with_backend(block_info: BlockInfo, backend: Arc<dyn BlockCodecBackend>) -> Result<BlockDecoder, RaptorQDecoderError> {
+        if BlockDecoder::check_block_info(&block_info).is_err() {
+            return Err(RaptorQDecoderError::InvalidBlockInfo);
+        }
+
+        let decoder = backend.new_decoder(&block_info.config, block_info.padded_size as u64);
+        return Ok(BlockDecoder {
+            block_info,
+            received_esi: HashSet::new(),
+            symbol_slack: DEFAULT_SYMBOL_SLACK,
+            huge_pages: false,
+            stats: DecodeStats::default(),
+            systematic: Some(SystematicBuffer::new()),
+            decoder,
+            backend,
+            queued: Vec::new(),
+            data: None,
+        });
+    }
+
+    /// Checks block info is consistent and within RaptorQ limits, as it may come from an untrusted peer, returning
+    /// what is wrong with it if not.
+    pub(crate) fn check_block_info(block_info: &BlockInfo) -> Result<(), &'static str> {
+        let config = &block_info.config;
+        let symbol_size = config.symbol_size() as usize;
+        if config.symbol_alignment() == 0 || symbol_size == 0 {
+            return Err("zero symbol size or alignment");
+        }
+        if !symbol_size.is_multiple_of(config.symbol_alignment() as usize) {
+            return Err("symbol size is not a multiple of the alignment");
+        }
+
+        // BlockEncoder always uses a single sub-block.
+        if config.sub_blocks() != 1 {
+            return Err("more than one sub-block");
+        }
+
+        if block_info.padded_size == 0 || !block_info.padded_size.is_multiple_of(symbol_size) {
+            return Err("padded size is not a positive multiple of the symbol size");
+        }
+        if block_info.padded_size / symbol_size > RAPTORQ_MAX_SYMBOLS_IN_BLOCK {
+            return Err("more symbols than RaptorQ allows in a block");
+        }
+        if block_info.payload_size > block_info.padded_size {
+            return Err("payload larger than its padded size");
+        }
+        return Ok(());
+    }
+
+    /// Checks a packet can be fed to the RaptorQ decoder, which asserts on malformed packets.
+    fn is_valid_packet(&self, packet: &EncodingPacket) -> bool {
+        let symbol_count = self.get_symbol_count() as u32;
+        let esi = packet.payload_id().encoding_symbol_id();
+
+        // Source symbols are numbered 0..K, repair symbols from K' on, and padding symbols in between are never sent.
+        // Source symbols may come without their padding, see EncoderConfig::elide_padding.
+        let len = packet.data().len();
+        return packet.payload_id().source_block_number() == 0
+            && (len == self.block_info.config.symbol_size() as usize || (esi < symbol_count && len == self.block_info.get_source_symbol_len(esi)))
+            && (esi < symbol_count || esi >= extended_source_block_symbols(symbol_count));
+    }
+
+    /// Fills in the padding of a source symbol sent without it, as the RaptorQ decoder takes whole symbols only.
+    /// Other packets are returned as they are.
+    fn pad_packet(&self, packet: EncodingPacket) -> EncodingPacket {
+        let symbol_size = self.block_info.config.symbol_size() as usize;
+        if packet.data().len() >= symbol_size || !self.is_valid_packet(&packet) {
+            return packet;
+        }
+        let (payload_id, mut data) = packet.split();
+        data.resize(symbol_size, 0);
+        return EncodingPacket::new(payload_id, data);
     }
 
     fn extract_packets(mut blocks: Vec<EncodedBlock>, packets:&mut Vec<EncodingPacket>, block_id: u32) -> Option<RaptorQDecoderError> {
@@ -42,14 +639,13 @@ impl BlockDecoder {
         return None;
     }
 
-    /// static method for encoding data
-    pub(crate) fn decode_data(block_info: &BlockInfo, mut blocks: Vec<EncodedBlock>) -> Result<Vec<u8>, RaptorQDecoderError> {
-        let mut decoder = SourceBlockDecoder::new2(0, &block_info.config, block_info.padded_size as u64);
+    /// static method for decoding data with backend
+    pub(crate) fn decode_data(backend: &dyn BlockCodecBackend, block_info: &BlockInfo, blocks: Vec<EncodedBlock>) -> Result<Vec<u8>, RaptorQDecoderError> {
+        let mut decoder = backend.new_decoder(&block_info.config, block_info.padded_size as u64);
         let mut packets: Vec<EncodingPacket> = Vec::new();
 
-        match BlockDecoder::extract_packets(blocks, &mut packets, block_info.block_id) {
-            Some(error) => return Err(error),
-            None => (),
+        if let Some(error) = BlockDecoder::extract_packets(blocks, &mut packets, block_info.block_id) {
+            return Err(error);
         }
 
         match decoder.decode(packets) {
@@ -58,25 +654,274 @@ impl BlockDecoder {
         }
     }
 
-    pub fn decode_blocks(&self, mut blocks: Vec<EncodedBlock>) -> Result<Vec<u8>, RaptorQDecoderError> {
-        return BlockDecoder::decode_data(&self.block_info, blocks);
+    /// Decodes the block from blocks alone, ignoring what this decoder received. If there were too few symbols, the
+    /// failure carries a plan asking for the rest.
+    pub fn decode_blocks(&self, blocks: Vec<EncodedBlock>) -> Result<Vec<u8>, DecodeFailure> {
+        let esis: HashSet<u32> = blocks.iter()
+            .filter(|x| x.block_id == self.block_info.block_id && self.is_valid_packet(&x.data))
+            .map(|x| x.data.payload_id().encoding_symbol_id())
+            .collect();
+
+        let blocks: Vec<EncodedBlock> = blocks.into_iter().map(|x| EncodedBlock { block_id: x.block_id, data: self.pad_packet(x.data) }).collect();
+        match BlockDecoder::decode_data(&*self.backend, &self.block_info, blocks) {
+            Ok(data) => return Ok(data),
+            Err(error) if error != RaptorQDecoderError::RaptorQDecodeFailed => return Err(DecodeFailure { error, plan: None }),
+            Err(error) => {
+                let needs = BlockNeeds {
+                    block_id: self.block_info.block_id,
+                    // decoding just failed, so at least one more
+                    symbols_needed: self.get_symbol_count().saturating_sub(esis.len()).max(1) as u32,
+                    received: esi_ranges(esis),
+                };
+                return Err(DecodeFailure { error, plan: Some(RepairPlan { blocks: vec![needs], peers: Vec::new() }) });
+            },
+        }
+    }
+
+    /// Decodes encoded blocks into a caller-provided buffer of at least the block's payload size, returning the
+    /// number of bytes written. When every source symbol is among the blocks they are copied straight into out
+    /// without allocating; otherwise the backend decodes into out, see BlockSymbolDecoder::decode_into. The raptorq
+    /// backend can't decode in place, so with it only the former path avoids allocating the block.
+    pub fn decode_into(&self, mut blocks: Vec<EncodedBlock>, out: &mut [u8]) -> Result<usize, RaptorQDecoderError> {
+        let payload_size = self.block_info.payload_size;
+        if out.len() < payload_size {
+            return Err(RaptorQDecoderError::BufferTooSmall);
+        }
+        if blocks.iter().any(|x| x.block_id != self.block_info.block_id) {
+            return Err(RaptorQDecoderError::BadBlockId);
+        }
+
+        blocks.retain(|x| self.is_valid_packet(&x.data));
+        blocks.sort_unstable_by_key(|x| x.data.payload_id().encoding_symbol_id());
+        blocks.dedup_by_key(|x| x.data.payload_id().encoding_symbol_id());
+
+        // distinct and sorted, so the first K are the source symbols if the Kth has ESI K - 1
+        let symbol_count = self.get_symbol_count();
+        if blocks.get(symbol_count - 1).is_some_and(|x| x.data.payload_id().encoding_symbol_id() as usize == symbol_count - 1) {
+            let symbol_size = self.block_info.config.symbol_size() as usize;
+            for (block, chunk) in blocks.iter().zip(out[..payload_size].chunks_mut(symbol_size)) {
+                chunk.copy_from_slice(&block.data.data()[..chunk.len()]);
+            }
+            return Ok(payload_size);
+        }
+
+        let mut decoder = self.backend.new_decoder(&self.block_info.config, self.block_info.padded_size as u64);
+        if !decoder.decode_into(blocks.into_iter().map(|x| self.pad_packet(x.data)).collect(), &mut out[..payload_size]) {
+            return Err(RaptorQDecoderError::RaptorQDecodeFailed);
+        }
+        return Ok(payload_size);
+    }
+
+    /// Feeds the blocks queued by RaptorQDecoder::queue to the retained decoder. Returns true once the block is
+    /// decoded.
+    pub fn decode_queued(&mut self) -> Result<bool, RaptorQDecoderError> {
+        let queued = std::mem::take(&mut self.queued);
+        return self.consume(queued);
+    }
+
+    /// Feeds encoded blocks to the retained decoder. Returns true once the block is decoded.
+    pub fn consume(&mut self, blocks: Vec<EncodedBlock>) -> Result<bool, RaptorQDecoderError> {
+        self.consume_counting(blocks)?;
+        return Ok(self.is_decoded());
+    }
+
+    /// Feeds encoded blocks to the retained decoder, returning how many were innovative.
+    fn consume_counting(&mut self, blocks: Vec<EncodedBlock>) -> Result<u32, RaptorQDecoderError> {
+        let mut packets: Vec<EncodingPacket> = Vec::new();
+        if let Some(error) = BlockDecoder::extract_packets(blocks, &mut packets, self.block_info.block_id) {
+            return Err(error);
+        }
+
+        let mut innovative: u32 = 0;
+        if self.data.is_some() {
+            self.stats.dropped_symbols += packets.len() as u32;
+        } else {
+            let received = packets.len();
+            packets.retain(|x| self.is_valid_packet(x));
+            self.stats.invalid_symbols += (received - packets.len()) as u32;
+
+            innovative = self.count_symbols(&mut packets);
+            let symbol_count = self.get_symbol_count();
+            if let Some(mut systematic) = self.systematic.take() {
+                if packets.iter().all(|x| (x.payload_id().encoding_symbol_id() as usize) < symbol_count) {
+                    for packet in packets.iter() {
+                        systematic.insert(packet, &self.block_info, self.huge_pages);
+                    }
+                    // nothing is missing before the first source symbol either
+                    if systematic.missing == 0 && !systematic.received.is_empty() {
+                        self.data = Some(systematic.data);
+                        self.stats.systematic = Some(true);
+                    } else {
+                        self.systematic = Some(systematic);
+                    }
+                    return Ok(innovative);
+                }
+
+                // the sender is repairing losses, hand everything to the RaptorQ decoder from now on
+                let mut buffered = systematic.into_packets(&self.block_info);
+                buffered.append(&mut packets);
+                packets = buffered;
+            }
+
+            let packets: Vec<EncodingPacket> = packets.into_iter().map(|x| self.pad_packet(x)).collect();
+            if let Some(mut data) = self.decoder.decode(packets) {
+                data.truncate(self.block_info.payload_size);
+                self.data = Some(PageBuffer::from(data));
+                self.stats.systematic = Some(self.stats.source_symbols as usize == self.get_symbol_count());
+            }
+        }
+
+        return Ok(innovative);
+    }
+
+    /// Counts the symbols received, keeping only new ones up to the limit of set_symbol_slack, and returning how
+    /// many were kept.
+    fn count_symbols(&mut self, packets: &mut Vec<EncodingPacket>) -> u32 {
+        let symbol_count = self.get_symbol_count() as u32;
+        let max_symbols = self.get_max_symbols();
+        let received_esi = &mut self.received_esi;
+        let stats = &mut self.stats;
+        packets.retain(|packet| {
+            let esi = packet.payload_id().encoding_symbol_id();
+            if received_esi.contains(&esi) {
+                stats.duplicate_symbols += 1;
+                return false;
+            }
+            if received_esi.len() >= max_symbols {
+                stats.dropped_symbols += 1;
+                return false;
+            }
+
+            received_esi.insert(esi);
+            if esi < symbol_count {
+                stats.source_symbols += 1;
+            } else {
+                stats.repair_symbols += 1;
+            }
+            return true;
+        });
+
+        return packets.len() as u32;
+    }
+
+    /// Returns true once the block is decoded.
+    pub fn is_decoded(&self) -> bool {
+        return self.data.is_some();
+    }
+
+    /// Attempts to decode with the symbols received so far plus blocks, without changing this decoder, returning
+    /// the payload if that is enough. Checks symbol counts before copying the decoder, so asking "can I decode
+    /// yet?" is cheap while too few symbols are on hand; if the attempt fails, keep consuming as before.
+    pub fn try_decode_with(&self, blocks: Vec<EncodedBlock>) -> Result<Option<Vec<u8>>, RaptorQDecoderError> {
+        if let Some(data) = self.data.as_ref() {
+            return Ok(Some(data.to_vec()));
+        }
+        if blocks.iter().any(|x| x.block_id != self.block_info.block_id) {
+            return Err(RaptorQDecoderError::BadBlockId);
+        }
+
+        let new_esis: HashSet<u32> = blocks.iter()
+            .filter(|x| self.is_valid_packet(&x.data))
+            .map(|x| x.data.payload_id().encoding_symbol_id())
+            .filter(|x| !self.received_esi.contains(x))
+            .collect();
+        if self.received_esi.len() + new_esis.len() < self.get_symbol_count() {
+            return Ok(None);
+        }
+
+        let mut fork = self.clone();
+        fork.consume(blocks)?;
+        return Ok(fork.data.map(|x| x.into_vec()));
+    }
+
+    /// Marks the block decoded with a payload recovered earlier, see RaptorQDecoder::restore_block. Fails with
+    /// InvalidBlockInfo if the payload is not the size of the block's.
+    pub fn restore(&mut self, data: Vec<u8>) -> Result<(), RaptorQDecoderError> {
+        if data.len() != self.block_info.payload_size {
+            return Err(RaptorQDecoderError::InvalidBlockInfo);
+        }
+        self.systematic = None;
+        self.queued.clear();
+        self.data = Some(PageBuffer::from(data));
+        return Ok(());
+    }
+
+    /// Gets the number of distinct symbols still needed before decoding can succeed, 0 once decoded.
+    pub fn get_symbols_needed(&self) -> usize {
+        if self.is_decoded() {
+            return 0;
+        }
+        return self.get_symbol_count().saturating_sub(self.received_esi.len());
+    }
+
+    /// Gets the encoding symbol ids received so far, as sorted, disjoint ranges. Ids received after the block was
+    /// decoded are not recorded.
+    pub fn get_received_ranges(&self) -> Vec<Range<u32>> {
+        return esi_ranges(self.received_esi.iter().copied());
+    }
+
+    /// Gets the number of source symbols in the block.
+    pub fn get_symbol_count(&self) -> usize {
+        return self.block_info.padded_size / self.block_info.config.symbol_size() as usize;
+    }
+
+    /// Sets how many distinct symbols beyond the block's source symbols are kept while it is not decoded, bounding
+    /// the memory a flood of distinct symbols can take. Further symbols are dropped and counted in DecodeStats. If
+    /// the kept symbols still do not decode, as when some were corrupt, the block is stuck until
+    /// RaptorQDecoder::reset_block. Defaults to DEFAULT_SYMBOL_SLACK.
+    pub fn set_symbol_slack(&mut self, slack: usize) {
+        self.symbol_slack = slack;
+    }
+
+    /// Sets whether the buffer source symbols are copied into, which becomes the payload once every one arrives, is
+    /// allocated in huge pages where the system has them, see hugepage. Takes effect from the first source symbol.
+    /// The RaptorQ decoder's own matrices are allocated by the backend. Defaults to false.
+    pub fn set_huge_pages(&mut self, huge_pages: bool) {
+        self.huge_pages = huge_pages;
+    }
+
+    /// Gets the most distinct symbols kept while the block is not decoded.
+    pub fn get_max_symbols(&self) -> usize {
+        return self.get_symbol_count() + self.symbol_slack;
+    }
+
+    /// Gets statistics about the symbols received.
+    pub fn get_decode_stats(&self) -> DecodeStats {
+        return self.stats;
+    }
+
+    /// Returns the recovered payload of this block, or None if it is not decoded yet.
+    pub fn get_result(&self) -> Option<&[u8]> {
+        return self.data.as_deref();
+    }
+
+    /// Gets the metadata of the block this decoder recovers.
+    pub fn get_block_info(&self) -> &BlockInfo {
+        return &self.block_info;
+    }
+}
+
+/// Collapses distinct encoding symbol ids into sorted, disjoint ranges.
+pub(crate) fn esi_ranges<I: IntoIterator<Item = u32>>(esis: I) -> Vec<Range<u32>> {
+    let mut esis: Vec<u32> = esis.into_iter().collect();
+    esis.sort_unstable();
+
+    let mut ranges: Vec<Range<u32>> = Vec::new();
+    for esi in esis {
+        match ranges.last_mut() {
+            Some(range) if range.end == esi => range.end += 1,
+            _ => ranges.push(esi..(esi + 1)),
+        }
     }
+    return ranges;
 }
 
 #[cfg(test)]
-use super::encoder::*;
 mod tests {
     use super::*;
-    use rand::Rng;
+    use crate::test_util::gen_data;
+    use super::super::encoder::*;
 
-    fn gen_data(len: usize) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::with_capacity(len);
-        for _ in 0..len {
-            data.push(rand::thread_rng().gen());
-        }
-        return data;
-    }
-    
     fn arr_eq(data1: &[u8], data2: &[u8]) -> bool {
         return data1.iter().zip(data2.iter()).all(|(a,b)| a == b);
     }
@@ -96,12 +941,594 @@ mod tests {
         
         let decoder = match BlockDecoder::new(encoder.get_block_info()) {
             Ok(succ) => succ,
-            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
         };
 
         match decoder.decode_blocks(blocks) {
-            Ok(recovered_data) => assert_eq!(arr_eq(&recovered_data, &data), true),
-            Err(error) => panic!("Failed to decode data, err {}", error as u32),
+            Ok(recovered_data) => assert!(arr_eq(&recovered_data, &data)),
+            Err(failure) => panic!("Failed to decode data, err {:?}", failure.error),
+        }
+    }
+
+    #[test]
+    fn test_decode_repair_plan() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(64 * 1024);
+
+        let encoder = match BlockEncoder::new(0, packet_size, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let symbol_count = encoder.get_symbol_count() as u32;
+        let block_decoder = match BlockDecoder::new(encoder.get_block_info()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        // ten source symbols short, the plan says which ones arrived
+        let mut source = encoder.generate_source_blocks();
+        source.sort_by_key(|x| x.data.payload_id().encoding_symbol_id());
+        let failure = block_decoder.decode_blocks(source[10..].to_vec()).unwrap_err();
+        assert_eq!(failure.error, RaptorQDecoderError::RaptorQDecodeFailed);
+        let plan = failure.plan.unwrap();
+        assert_eq!(plan.blocks.len(), 1);
+        assert_eq!((plan.blocks[0].block_id, plan.blocks[0].symbols_needed), (0, 10));
+        assert!(plan.blocks[0].is_received(10) && !plan.blocks[0].is_received(9) && plan.blocks[0].is_received(symbol_count - 1));
+        assert_eq!(plan.get_symbols_needed(), 10);
+
+        let mut wrong_block = source[0].clone();
+        wrong_block.block_id = 1;
+        assert_eq!(block_decoder.decode_blocks(vec![wrong_block]), Err(DecodeFailure { error: RaptorQDecoderError::BadBlockId, plan: None }));
+
+        // senders are suggested by how useful their symbols were
+        let mut decoder = match RaptorQDecoder::new(vec![encoder.get_block_info()]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        decoder.consume_from(1, source[..10].to_vec()).unwrap();
+        decoder.consume_from(2, source[..20].to_vec()).unwrap();
+        decoder.consume_from(3, source[20..40].to_vec()).unwrap();
+        let plan = decoder.get_repair_plan();
+        assert_eq!(plan.peers, vec![3, 1, 2]);
+        assert_eq!(plan.get_symbols_needed(), symbol_count as u64 - 40);
+
+        decoder.consume(source).unwrap();
+        assert!(decoder.get_repair_plan().blocks.is_empty());
+    }
+
+    #[test]
+    fn test_decoder_split_merge_threads() {
+        let packet_size: u16 = 1280;
+        let data_size: usize = 32 * 1024;
+        let num_blocks: u32 = 3;
+        let data = gen_data(data_size * num_blocks as usize);
+
+        let encoders: Vec<BlockEncoder> = data.chunks(data_size).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, packet_size, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+
+        let mut decoder = match RaptorQDecoder::new(encoders.iter().map(|x| x.get_block_info()).collect()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        assert!(!decoder.consume_from(7, encoders[0].generate_source_blocks()[..1].to_vec()).unwrap());
+        let sender_stats = decoder.get_innovation_stats(7);
+
+        let block_map = decoder.block_map();
+        for (i, region) in block_map.iter().enumerate() {
+            assert_eq!(region.block_id, i as u32);
+            assert_eq!(region.byte_offset, i * data_size);
+            assert_eq!(region.len, data_size);
+        }
+
+        // feed each block from its own thread
+        let (block_decoders, senders) = decoder.split();
+        let handles: Vec<std::thread::JoinHandle<BlockDecoder>> = block_decoders.into_iter().zip(encoders.iter()).map(|(mut block_decoder, encoder)| {
+            let mut blocks = encoder.generate_encoded_blocks();
+            blocks.append(&mut encoder.generate_encoded_blocks());
+            std::thread::spawn(move || {
+                match block_decoder.consume(blocks) {
+                    Ok(decoded) => assert!(decoded),
+                    Err(error) => panic!("Failed to decode data, err {:?}", error),
+                }
+                block_decoder
+            })
+        }).collect();
+
+        // merge in reverse order, merge should not care
+        let block_decoders: Vec<BlockDecoder> = handles.into_iter().rev().map(|x| x.join().unwrap()).collect();
+        let decoder = match RaptorQDecoder::merge(block_decoders, senders) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to merge decoders, error {:?}", error),
+        };
+
+        assert_eq!(decoder.get_result(), Some(data));
+        // sender stats survive the round trip
+        assert_eq!(decoder.get_innovation_stats(7), sender_stats);
+        assert_eq!(decoder.get_innovation_stats(7).unwrap().innovative, 1);
+        assert_eq!(decoder.get_repair_plan().peers, vec![7]);
+    }
+
+    #[test]
+    fn test_decoder_time_sliced() {
+        let packet_size: u16 = 1280;
+        let data_size: usize = 32 * 1024;
+        let data = gen_data(data_size * 3);
+
+        let encoders: Vec<BlockEncoder> = data.chunks(data_size).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, packet_size, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let mut decoder = match RaptorQDecoder::new(encoders.iter().map(|x| x.get_block_info()).collect()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        for encoder in encoders.iter() {
+            decoder.queue(encoder.generate_encoded_blocks()).unwrap();
+            decoder.queue(encoder.generate_encoded_blocks()).unwrap();
+        }
+        assert!(!decoder.is_decoded());
+
+        // without budget, each call decodes a single block
+        let mut calls = 0;
+        while decoder.has_queued() {
+            decoder.decode_for(Duration::ZERO).unwrap();
+            calls += 1;
+        }
+        assert_eq!(calls, 3);
+        assert_eq!(decoder.get_result(), Some(data));
+        assert_eq!(decoder.queue(encoders[0].generate_source_blocks().into_iter().map(|mut x| {
+            x.block_id = 3;
+            x
+        }).collect()), Err(RaptorQDecoderError::BadBlockId));
+    }
+
+    #[test]
+    fn test_decoder_invalid_block_info() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(16 * 1024);
+
+        let encoder = match BlockEncoder::new(1, packet_size, data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+
+        match RaptorQDecoder::new(vec![encoder.get_block_info()]) {
+            Ok(_) => panic!("Should have failed to create decoder without block 0"),
+            Err(error) => assert_eq!(error, RaptorQDecoderError::MissingBlocks(vec![0])),
+        };
+        let mut conflicting = encoder.get_block_info();
+        conflicting.payload_size -= 1;
+        match RaptorQDecoder::new(vec![encoder.get_block_info(), conflicting]) {
+            Ok(_) => panic!("Should have failed to create decoder from differing infos of block 1"),
+            Err(error) => assert_eq!(error, RaptorQDecoderError::InvalidBlockInfo),
+        };
+    }
+
+    #[test]
+    fn test_decoder_block_info_order() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(16 * 1024);
+        let block_info_vec: Vec<BlockInfo> = (0..4).map(|block_id| {
+            match BlockEncoder::new(block_id, packet_size, data.clone()) {
+                Ok(succ) => succ.get_block_info(),
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+
+        // shuffled and repeated, as when merging copies of a manifest
+        let shuffled = vec![3, 1, 0, 1, 2, 3].into_iter().map(|x| block_info_vec[x].clone()).collect();
+        let decoder = match RaptorQDecoder::new(shuffled) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let block_ids: Vec<u32> = decoder.split().0.iter().map(|x| x.block_info.block_id).collect();
+        assert_eq!(block_ids, vec![0, 1, 2, 3]);
+        match RaptorQDecoder::new(vec![block_info_vec[3].clone(), block_info_vec[1].clone()]) {
+            Ok(_) => panic!("Should have failed to create decoder without blocks 0 and 2"),
+            Err(error) => assert_eq!(error, RaptorQDecoderError::MissingBlocks(vec![0, 2])),
+        };
+
+        let encoder = match RaptorQEncoder::new(packet_size, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut manifest = Manifest::new(&encoder);
+        assert!(RaptorQDecoder::from_manifest(&manifest).is_ok());
+        manifest.block_hashes.push([0; 32]);
+        match RaptorQDecoder::from_manifest(&manifest) {
+            Ok(_) => panic!("Should have failed to create decoder without the last block"),
+            Err(error) => assert_eq!(error, RaptorQDecoderError::MissingBlocks(vec![1])),
+        };
+    }
+
+    #[test]
+    fn test_decoder_aligned_result() {
+        let packet_size: u16 = 1280;
+        let data_size: usize = 100 * 1000;
+        let data = gen_data(data_size);
+
+        let encoder = match RaptorQEncoder::new(packet_size, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+
+        let mut decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        match decoder.get_result_aligned(64) {
+            Ok(_) => panic!("Should have failed to get result before decoding"),
+            Err(error) => assert_eq!(error, RaptorQDecoderError::BlockNotDecoded),
+        }
+
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        match decoder.consume(blocks) {
+            Ok(decoded) => assert!(decoded),
+            Err(error) => panic!("Failed to decode data, err {:?}", error),
+        }
+
+        let buffer = match decoder.get_result_aligned(64) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to get aligned result, err {:?}", error),
+        };
+        assert_eq!(buffer.as_ptr() as usize % 64, 0);
+        assert_eq!(&buffer[..], &data[..]);
+
+        let mut out: Vec<u8> = vec![0; data_size - 1];
+        match decoder.get_result_into(&mut out) {
+            Ok(_) => panic!("Should have failed to write into a short buffer"),
+            Err(error) => assert_eq!(error, RaptorQDecoderError::BufferTooSmall),
+        }
+
+        match decoder.get_result_aligned(3) {
+            Ok(_) => panic!("Should have failed to align to 3 bytes"),
+            Err(error) => assert_eq!(error, RaptorQDecoderError::InvalidAlignment),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_block_decode_stats() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(64 * 1024);
+
+        let encoder = match BlockEncoder::new(0, packet_size, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let symbol_count = encoder.get_symbol_count() as u32;
+
+        // all source symbols, plus a duplicate, takes the systematic path
+        let mut decoder = match BlockDecoder::new(encoder.get_block_info()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let mut blocks = encoder.generate_source_blocks();
+        blocks.push(blocks[0].clone());
+        assert_eq!(decoder.consume(blocks), Ok(true));
+        assert_eq!(decoder.get_decode_stats(), DecodeStats {
+            source_symbols: symbol_count,
+            repair_symbols: 0,
+            duplicate_symbols: 1,
+            invalid_symbols: 0,
+            dropped_symbols: 0,
+            systematic: Some(true),
+        });
+
+        // repair symbols only needs inactivation decoding
+        let mut decoder = match BlockDecoder::new(encoder.get_block_info()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        assert_eq!(decoder.consume(blocks), Ok(true));
+        let stats = decoder.get_decode_stats();
+        assert_eq!(stats.source_symbols, 0);
+        assert!(stats.repair_symbols >= symbol_count);
+        assert_eq!(stats.systematic, Some(false));
+        assert_eq!(decoder.get_result(), Some(&data[..]));
+    }
+
+    #[test]
+    fn test_decoder_innovation_stats() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(64 * 1024);
+
+        let encoder = match BlockEncoder::new(0, packet_size, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut decoder = match RaptorQDecoder::new(vec![encoder.get_block_info()]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        // the second sender only repeats what the first already sent
+        let blocks = encoder.generate_source_blocks();
+        let half = blocks.len() / 2;
+        assert_eq!(decoder.consume_from(1, blocks[..half].to_vec()), Ok(false));
+        assert_eq!(decoder.consume_from(2, blocks[..half].to_vec()), Ok(false));
+        assert_eq!(decoder.get_innovation_stats(1), Some(InnovationStats { received: half as u64, innovative: half as u64, redundant_streak: 0 }));
+        assert_eq!(decoder.get_innovation_stats(2), Some(InnovationStats { received: half as u64, innovative: 0, redundant_streak: half as u64 }));
+        assert_eq!(decoder.get_innovation_stats(2).unwrap().get_innovation_rate(), 0.0);
+        assert_eq!(decoder.get_innovation_stats(3), None);
+
+        assert_eq!(decoder.consume_from(1, blocks[half..].to_vec()), Ok(true));
+        assert_eq!(decoder.get_innovation_stats(1).unwrap().get_innovation_rate(), 1.0);
+
+        // nothing is innovative once decoded
+        assert_eq!(decoder.consume_from(2, encoder.generate_encoded_blocks()), Ok(true));
+        assert_eq!(decoder.get_innovation_stats(2).unwrap().innovative, 0);
+        assert_eq!(decoder.get_result(), Some(data));
+    }
+
+    #[test]
+    fn test_block_decode_symbol_slack() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(64 * 1024);
+
+        let encoder = match BlockEncoder::new(0, packet_size, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let symbol_count = encoder.get_symbol_count();
+
+        // with no slack, a batch keeps only as many symbols as the block has, the source symbols being fed first
+        let mut decoder = match BlockDecoder::new(encoder.get_block_info()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        decoder.set_symbol_slack(0);
+        assert_eq!(decoder.get_max_symbols(), symbol_count);
+        let mut blocks = encoder.generate_repair_blocks(0, 100);
+        blocks.append(&mut encoder.generate_source_blocks());
+        assert_eq!(decoder.consume(blocks), Ok(true));
+        assert_eq!(decoder.get_decode_stats().dropped_symbols, 100);
+        assert_eq!(decoder.get_result(), Some(&data[..]));
+
+        // anything after decoding is dropped too
+        assert_eq!(decoder.consume(encoder.generate_repair_blocks(100, 10)), Ok(true));
+        assert_eq!(decoder.get_decode_stats().dropped_symbols, 110);
+
+        // queueing stops at the limit as well, and the limit survives a reset
+        let mut decoder = match RaptorQDecoder::new(vec![encoder.get_block_info()]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        decoder.set_symbol_slack(10);
+        decoder.reset_block(0).unwrap();
+        decoder.queue(encoder.generate_repair_blocks(0, symbol_count + 50)).unwrap();
+        assert_eq!(decoder.get_decode_stats()[0].dropped_symbols, 40);
+        assert_eq!(decoder.decode_for(Duration::from_secs(60)), Ok(true));
+        assert_eq!(decoder.get_result(), Some(data));
+    }
+
+    #[test]
+    fn test_block_decode_systematic_fallback() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(64 * 1024);
+
+        let encoder = match BlockEncoder::new(0, packet_size, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut decoder = match BlockDecoder::new(encoder.get_block_info()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        // nothing received yet is not a decoded block
+        assert_eq!(decoder.consume(Vec::new()), Ok(false));
+        assert_eq!(decoder.get_result(), None);
+
+        // one source symbol lost, so the buffered source symbols are handed to the RaptorQ decoder with the repair ones
+        let mut blocks = encoder.generate_source_blocks();
+        blocks.remove(3);
+        assert_eq!(decoder.consume(blocks), Ok(false));
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        assert_eq!(decoder.consume(blocks), Ok(true));
+        assert_eq!(decoder.get_decode_stats().systematic, Some(false));
+        assert_eq!(decoder.get_result(), Some(&data[..]));
+    }
+
+    #[test]
+    fn test_block_decode_elided_padding() {
+        let mut config = EncoderConfig::new(1280);
+        config.elide_padding = true;
+        let data = gen_data(20 * 1280 + 100);
+        let encoder = match BlockEncoder::with_config(0, config, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let block_info = encoder.get_block_info();
+        assert_eq!((block_info.get_pad_len(), block_info.get_source_symbol_len(20), block_info.get_source_symbol_len(19)), (1180, 100, 1280));
+
+        // the tail symbol is sent without its padding, repair symbols are whole
+        let mut source = encoder.generate_source_blocks();
+        source.sort();
+        assert_eq!(source.iter().map(|x| x.data.data().len()).sum::<usize>(), data.len());
+        assert!(encoder.generate_encoded_blocks().iter().all(|x| x.data.data().len() == 1280));
+
+        let mut decoder = BlockDecoder::new(block_info.clone()).unwrap();
+        assert_eq!(decoder.consume(source.clone()), Ok(true));
+        assert_eq!(decoder.get_decode_stats().systematic, Some(true));
+        assert_eq!(decoder.get_result(), Some(&data[..]));
+
+        // the RaptorQ decoder gets it padded again, whichever way the block is decoded
+        let mut decoder = BlockDecoder::new(block_info.clone()).unwrap();
+        let tail = source.pop().unwrap();
+        assert_eq!(decoder.consume(vec![tail.clone()]), Ok(false));
+        assert_eq!(decoder.consume(encoder.generate_encoded_blocks()), Ok(true));
+        assert_eq!(decoder.get_result(), Some(&data[..]));
+
+        let mut blocks = encoder.generate_repair_blocks(0, 20);
+        blocks.push(tail.clone());
+        // decode_blocks returns the padded block
+        assert_eq!(&decoder.decode_blocks(blocks.clone()).unwrap()[..data.len()], &data[..]);
+        let mut out: Vec<u8> = vec![0; data.len()];
+        assert_eq!(decoder.decode_into(blocks, &mut out), Ok(data.len()));
+        assert_eq!(out, data);
+
+        // a tail symbol of any other length is invalid
+        let mut decoder = BlockDecoder::new(block_info).unwrap();
+        let bogus = EncodedBlock { block_id: 0, data: EncodingPacket::new(tail.data.payload_id().clone(), vec![0; 99]) };
+        assert_eq!(decoder.consume(vec![bogus]), Ok(false));
+        assert_eq!(decoder.get_decode_stats().invalid_symbols, 1);
+    }
+
+    #[test]
+    fn test_block_decode_into() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(64 * 1024 + 100);
+
+        let encoder = match BlockEncoder::new(0, packet_size, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let decoder = match BlockDecoder::new(encoder.get_block_info()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        // every source symbol, in any order and with duplicates
+        let mut out: Vec<u8> = vec![0; data.len()];
+        let mut blocks = encoder.generate_source_blocks();
+        blocks.push(blocks[5].clone());
+        assert_eq!(decoder.decode_into(blocks, &mut out), Ok(data.len()));
+        assert_eq!(out, data);
+
+        // a lost source symbol needs the RaptorQ decoder
+        let mut out: Vec<u8> = vec![0; data.len() + 10];
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        blocks.retain(|x| x.data.payload_id().encoding_symbol_id() != 3);
+        assert_eq!(decoder.decode_into(blocks, &mut out), Ok(data.len()));
+        assert_eq!(&out[..data.len()], &data[..]);
+
+        let mut out: Vec<u8> = vec![0; data.len() - 1];
+        assert_eq!(decoder.decode_into(encoder.generate_source_blocks(), &mut out), Err(RaptorQDecoderError::BufferTooSmall));
+    }
+
+    #[test]
+    fn test_block_decode_speculative() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(64 * 1024);
+
+        let encoder = match BlockEncoder::new(0, packet_size, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut decoder = match BlockDecoder::new(encoder.get_block_info()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let symbol_count = decoder.get_symbol_count();
+
+        let repair = encoder.generate_repair_blocks(0, 2 * symbol_count);
+        assert_eq!(decoder.consume(repair[..(symbol_count / 2)].to_vec()), Ok(false));
+
+        // too few symbols is answered without decoding
+        assert_eq!(decoder.try_decode_with(repair[..(symbol_count / 2)].to_vec()), Ok(None));
+        // enough symbols decode in a fork, leaving the decoder as it was
+        let attempt = decoder.try_decode_with(repair[(symbol_count / 2)..].to_vec());
+        assert_eq!(attempt, Ok(Some(data.clone())));
+        assert!(!decoder.is_decoded());
+        assert_eq!(decoder.get_symbols_needed(), symbol_count - symbol_count / 2);
+
+        let mut fork = decoder.clone();
+        assert_eq!(fork.consume(repair[(symbol_count / 2)..].to_vec()), Ok(true));
+        assert!(!decoder.is_decoded());
+        assert_eq!(decoder.consume(repair[(symbol_count / 2)..].to_vec()), Ok(true));
+        assert_eq!(decoder.get_result(), Some(&data[..]));
+    }
+
+    #[test]
+    fn test_decoder_block_needs() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(64 * 1024);
+
+        let encoder = match BlockEncoder::new(0, packet_size, data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let symbol_count = encoder.get_symbol_count() as u32;
+        let mut decoder = match RaptorQDecoder::new(vec![encoder.get_block_info()]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        assert_eq!(decoder.get_block_needs(), vec![BlockNeeds { block_id: 0, symbols_needed: symbol_count, received: Vec::new() }]);
+
+        let mut blocks = encoder.generate_source_blocks();
+        blocks.retain(|x| x.data.payload_id().encoding_symbol_id() < 10 && x.data.payload_id().encoding_symbol_id() != 4);
+        blocks.append(&mut encoder.generate_repair_blocks(100, 5));
+        assert_eq!(decoder.consume(blocks), Ok(false));
+
+        let needs = decoder.get_block_needs().remove(0);
+        let repair_start = extended_source_block_symbols(symbol_count) + 100;
+        assert_eq!(needs.symbols_needed, symbol_count - 14);
+        assert_eq!(needs.received, vec![0..4, 5..10, repair_start..(repair_start + 5)]);
+        assert!(needs.is_received(3));
+        assert!(!needs.is_received(4));
+        assert!(needs.is_received(repair_start + 4));
+        assert!(!needs.is_received(repair_start + 5));
+
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        assert_eq!(decoder.consume(blocks), Ok(true));
+        assert!(decoder.get_block_needs().is_empty());
+    }
+
+    #[test]
+    fn test_update_manifest() {
+        let mut config = EncoderConfig::new(512);
+        config.max_block_symbols = 20;
+        let data = gen_data(50 * 512);
+        let encoder = match RaptorQEncoder::with_config(config, &[std::io::IoSlice::new(&data[..25 * 512])]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let v1 = Manifest::new(&encoder);
+        let (v2, v2_encoders) = v1.append(&data[..35 * 512]).unwrap();
+        let (v3, v3_encoders) = v2.append(&data).unwrap();
+
+        // half of block 0 arrives before any update
+        let mut decoder = RaptorQDecoder::from_manifest(&v1).unwrap();
+        decoder.set_symbol_slack(4);
+        let mut blocks = encoder.get_block_encoders()[0].generate_source_blocks();
+        blocks.truncate(10);
+        assert_eq!(decoder.consume(blocks), Ok(false));
+
+        // v3 overtakes v2, which is then stale
+        assert_eq!(decoder.update_manifest(&v3), Ok(true));
+        assert_eq!(decoder.update_manifest(&v2), Ok(false));
+        assert_eq!(decoder.update_manifest(&v1), Ok(false));
+        assert_eq!(decoder.get_block_info_vec(), v3.block_info_vec);
+        assert_eq!(decoder.get_block_needs()[0].symbols_needed, 10);
+        assert_eq!(decoder.get_block_needs().last().unwrap().block_id, 3);
+
+        let mut blocks = encoder.generate_encoded_blocks();
+        for block_encoder in v2_encoders.iter().chain(v3_encoders.iter()) {
+            blocks.append(&mut block_encoder.generate_encoded_blocks());
+        }
+        assert_eq!(decoder.consume(blocks), Ok(true));
+        assert_eq!(decoder.get_result(), Some(data.clone()));
+
+        // an update disagreeing on a block is refused
+        let mut conflicting = v3.clone();
+        conflicting.block_info_vec[1].payload_size -= 1;
+        assert_eq!(decoder.update_manifest(&conflicting), Err(RaptorQDecoderError::InvalidBlockInfo));
+        let mut truncated = v3.clone();
+        truncated.block_info_vec.remove(2);
+        assert_eq!(decoder.update_manifest(&truncated), Err(RaptorQDecoderError::MissingBlocks(vec![2])));
+    }
+}
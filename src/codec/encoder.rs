@@ -1,50 +1,340 @@
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
-use raptorq::{EncodingPacket, ObjectTransmissionInformation, SourceBlockEncoder};
+use raptorq::{extended_source_block_symbols, EncodingPacket, ObjectTransmissionInformation};
 use std::cmp;
+use std::io::IoSlice;
+use std::sync::{Arc, Mutex, RwLock};
+use super::backend::{BlockCodecBackend, BlockSymbolEncoder, RaptorqBackend};
 use super::consts::*;
-use rand::{thread_rng, Rng};
+use super::hugepage::{PageBacking, PageBuffer};
+use super::numa::{pin_current_thread, EncodePlacement};
+use super::plan_cache::PlanCache;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+
+/// How a block whose payload is not a multiple of packet_size, i.e. usually the last block of an object, is fit to
+/// whole symbols. The choice is recorded in each block's BlockInfo, so decoders need not know it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum TailStrategy {
+    /// Zero pad the block up to a multiple of packet_size. Up to a packet of padding is encoded, and sent as part
+    /// of the symbols.
+    Pad,
+    /// Keep the block's symbol count, but shrink its symbol size to the smallest multiple of alignment that fits the
+    /// payload, so padding stays under alignment bytes per symbol. The block's packets are smaller than packet_size.
+    ShrinkSymbols,
+}
+
+/// Parameters shared by every block of an encoded object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct EncoderConfig {
+    /// Encoded packet size. Also the symbol size, so it must be a multiple of alignment.
+    pub packet_size: u16,
+    /// Symbol alignment (Al in RFC 6330), in bytes. Must be a power of two.
+    pub alignment: u8,
+    pub tail_strategy: TailStrategy,
+    /// Seed for the random repair symbol ids encoders start at, for reproducible runs. None draws them from the
+    /// thread's generator. Not part of the manifest, as decoders don't care.
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub seed: Option<u64>,
+    /// Keep block data in huge pages where the system has them, see hugepage. Not part of the manifest either.
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub huge_pages: bool,
+    /// Send the last source symbol of a padded block without its zero padding, see
+    /// BlockEncoder::generate_source_blocks. Not part of the manifest, decoders take the symbol either way, though
+    /// decoders predating it reject the shortened symbol.
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub elide_padding: bool,
+    /// Most source symbols in a block, in 1..=RAPTORQ_MAX_SYMBOLS_IN_BLOCK. Objects are split into blocks of this
+    /// many symbols plus a tail block. Smaller blocks encode faster per symbol but cost more blocks, see
+    /// plan_cache::pick_max_block_symbols. Not part of the manifest, which lists the layout of every block.
+    #[cfg_attr(feature = "serde_support", serde(default = "default_max_block_symbols"))]
+    pub max_block_symbols: u16,
+}
+
+#[cfg(feature = "serde_support")]
+fn default_max_block_symbols() -> u16 {
+    return RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u16;
+}
+
+impl EncoderConfig {
+    /// Creates a config with the default alignment, padding tail blocks.
+    pub fn new(packet_size: u16) -> EncoderConfig {
+        return EncoderConfig {
+            packet_size,
+            alignment: ALIGNMENT,
+            tail_strategy: TailStrategy::Pad,
+            seed: None,
+            huge_pages: false,
+            elide_padding: false,
+            max_block_symbols: RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u16,
+        };
+    }
+
+    /// Gets the payload size of full blocks, i.e. every block of an object but the last.
+    pub fn get_block_size(&self) -> usize {
+        return self.max_block_symbols as usize * self.packet_size as usize;
+    }
+
+    /// Gets the symbol size and symbol count of a block with payload_size bytes of payload.
+    pub fn get_block_layout(&self, payload_size: usize) -> (u16, usize) {
+        let symbol_count = payload_size.div_ceil(self.packet_size as usize);
+        if self.tail_strategy == TailStrategy::Pad || symbol_count == 0 {
+            return (self.packet_size, symbol_count);
+        }
 
+        let alignment = self.alignment as usize;
+        let symbol_size = payload_size.div_ceil(symbol_count).div_ceil(alignment) * alignment;
+        // rounding up to the alignment may leave room to drop a symbol
+        return (symbol_size as u16, payload_size.div_ceil(symbol_size));
+    }
+
+    /// Gets the total size of an object of data_size bytes once its blocks are padded to whole symbols.
+    pub fn get_padded_size(&self, data_size: u64) -> u64 {
+        let block_size = self.get_block_size() as u64;
+        let (symbol_size, symbol_count) = self.get_block_layout((data_size % block_size) as usize);
+        return data_size / block_size * block_size + (symbol_size as usize * symbol_count) as u64;
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), RaptorQEncoderError> {
+        if !self.alignment.is_power_of_two() {
+            return Err(RaptorQEncoderError::InvalidAlignment);
+        }
+        if !self.packet_size.is_multiple_of(self.alignment as u16) || self.packet_size < MIN_PACKET_SIZE {
+            return Err(RaptorQEncoderError::InvalidPacketSize);
+        }
+        if self.max_block_symbols == 0 || self.max_block_symbols as usize > RAPTORQ_MAX_SYMBOLS_IN_BLOCK {
+            return Err(RaptorQEncoderError::InvalidBlockSymbols);
+        }
+
+        return Ok(());
+    }
+}
+
+/// Encodes an object split into blocks. Immutable once built and Send + Sync, so many threads can generate symbols
+/// of the same object at once; clones are cheap and share the blocks.
+#[derive(Clone)]
 pub struct RaptorQEncoder {
     data_size: usize,
-    packet_size: u16,
-    block_encoders: Vec<BlockEncoder>,
+    config: EncoderConfig,
+    block_encoders: Arc<[BlockEncoder]>,
+    /// Whether generate_encoded_blocks evicts each block once its symbols are generated.
+    evict_after_generate: bool,
 }
 
+// sharing encoders between server threads relies on this
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RaptorQEncoder>();
+};
+
 impl RaptorQEncoder {
     pub fn new(packet_size: u16, data: &[u8]) -> Result<RaptorQEncoder, RaptorQEncoderError> {
-        let block_size = RAPTORQ_MAX_SYMBOLS_IN_BLOCK * packet_size as usize;
+        return RaptorQEncoder::with_config(EncoderConfig::new(packet_size), &[IoSlice::new(data)]);
+    }
 
-        let data_chunks: Vec<Vec<u8>> = data.chunks(block_size).map(|x| x.to_vec()).collect();
+    /// Creates a RaptorQEncoder from a sequence of buffers, as if they were concatenated.
+    /// Block boundaries may fall anywhere within a buffer; each byte is copied once, into its block.
+    pub fn new_vectored(packet_size: u16, bufs: &[IoSlice]) -> Result<RaptorQEncoder, RaptorQEncoderError> {
+        return RaptorQEncoder::with_config(EncoderConfig::new(packet_size), bufs);
+    }
+
+    /// Creates a RaptorQEncoder from a sequence of buffers, as new_vectored does, with explicit parameters.
+    pub fn with_config(config: EncoderConfig, bufs: &[IoSlice]) -> Result<RaptorQEncoder, RaptorQEncoderError> {
+        return RaptorQEncoder::build(config, bufs, None, RaptorqBackend::shared());
+    }
+
+    /// Creates a RaptorQEncoder as with_config does, taking encoding plans from a cache shared with other encoders.
+    pub fn with_plan_cache(config: EncoderConfig, bufs: &[IoSlice], plan_cache: &PlanCache) -> Result<RaptorQEncoder, RaptorQEncoderError> {
+        return RaptorQEncoder::build(config, bufs, Some(plan_cache), RaptorqBackend::shared());
+    }
+
+    /// Creates a RaptorQEncoder as with_config does, encoding every block with backend instead of the raptorq crate.
+    pub fn with_backend(config: EncoderConfig, bufs: &[IoSlice], backend: Arc<dyn BlockCodecBackend>) -> Result<RaptorQEncoder, RaptorQEncoderError> {
+        return RaptorQEncoder::build(config, bufs, None, backend);
+    }
+
+    /// Creates a RaptorQEncoder as with_config does, encoding blocks at once on the threads and NUMA nodes of
+    /// placement, with encoding plans from plan_cache if given. The blocks are the same as with_config makes.
+    pub fn with_placement(config: EncoderConfig, bufs: &[IoSlice], placement: &EncodePlacement, plan_cache: Option<&PlanCache>) -> Result<RaptorQEncoder, RaptorQEncoderError> {
+        config.validate()?;
+
+        let block_size = config.get_block_size();
+        let data_size: usize = bufs.iter().map(|x| x.len()).sum();
+        let groups = placement.assign(data_size.div_ceil(block_size));
+
+        let built: Mutex<Vec<Result<BlockEncoder, RaptorQEncoderError>>> = Mutex::new(Vec::new());
+        std::thread::scope(|scope| {
+            let built = &built;
+            for group in groups.iter() {
+                for _ in 0..group.threads {
+                    scope.spawn(move || {
+                        if !group.cpus.is_empty() {
+                            // encoding still works unpinned, e.g. in a container restricting affinity
+                            let _ = pin_current_thread(&group.cpus);
+                        }
+                        loop {
+                            let block_id = match group.blocks.lock().unwrap().pop_front() {
+                                Some(block_id) => block_id,
+                                None => return,
+                            };
+                            // copied on the thread encoding it, so the block's pages are on the thread's node
+                            let start = block_id as usize * block_size;
+                            let block = RaptorQEncoder::gather(bufs, start, cmp::min(block_size, data_size - start), &config);
+                            let block_encoder = BlockEncoder::build(block_id, config, block, plan_cache, RaptorqBackend::shared());
+                            built.lock().unwrap().push(block_encoder);
+                        }
+                    });
+                }
+            }
+        });
+
+        let mut block_encoders = built.into_inner().unwrap().into_iter().collect::<Result<Vec<BlockEncoder>, RaptorQEncoderError>>()?;
+        block_encoders.sort_by_key(|x| x.block_id);
+        return Ok(RaptorQEncoder {
+            data_size,
+            config,
+            block_encoders: block_encoders.into(),
+            evict_after_generate: false,
+        });
+    }
+
+    /// Copies len bytes at offset start of bufs, as if they were concatenated, into a block allocated as alloc_block
+    /// does.
+    fn gather(bufs: &[IoSlice], mut start: usize, len: usize, config: &EncoderConfig) -> Vec<u8> {
+        let mut block: Vec<u8> = RaptorQEncoder::alloc_block(len, config);
+        for buf in bufs.iter() {
+            if block.len() == len {
+                break;
+            }
+            if start >= buf.len() {
+                start -= buf.len();
+                continue;
+            }
+            let copied = cmp::min(buf.len() - start, len - block.len());
+            block.extend_from_slice(&buf[start..start + copied]);
+            start = 0;
+        }
+        return block;
+    }
+
+    fn build(config: EncoderConfig, bufs: &[IoSlice], plan_cache: Option<&PlanCache>, backend: Arc<dyn BlockCodecBackend>) -> Result<RaptorQEncoder, RaptorQEncoderError> {
+        config.validate()?;
+
+        let block_size = config.get_block_size();
+        let data_size: usize = bufs.iter().map(|x| x.len()).sum();
 
         // create block encoders
         let mut block_encoders: Vec<BlockEncoder> = Vec::new();
-        for (i, data_chunk) in data_chunks.iter().enumerate() {
-            match BlockEncoder::new(i as u32, packet_size, data_chunk.to_vec()) {
-                Ok(block_encoder) => block_encoders.push(block_encoder),
-                Err(error) => return Err(error),
+        let mut block: Vec<u8> = RaptorQEncoder::alloc_block(data_size, &config);
+        for buf in bufs.iter() {
+            let mut buf: &[u8] = buf;
+            while !buf.is_empty() {
+                let copied = cmp::min(block_size - block.len(), buf.len());
+                block.extend_from_slice(&buf[..copied]);
+                buf = &buf[copied..];
+
+                if block.len() == block_size {
+                    let remaining = data_size - (block_encoders.len() + 1) * block_size;
+                    let full_block = std::mem::replace(&mut block, RaptorQEncoder::alloc_block(remaining, &config));
+                    block_encoders.push(BlockEncoder::build(block_encoders.len() as u32, config, full_block, plan_cache, backend.clone())?);
+                }
             }
         }
+
+        if !block.is_empty() {
+            block_encoders.push(BlockEncoder::build(block_encoders.len() as u32, config, block, plan_cache, backend)?);
+        }
+
         return Ok(RaptorQEncoder {
-            data_size: data.len(),
-            packet_size: packet_size,
-            block_encoders: block_encoders,
+            data_size,
+            config,
+            block_encoders: block_encoders.into(),
+            evict_after_generate: false,
         });
     }
 
+    /// Allocates room for the next block, including padding, so BlockEncoder::new does not reallocate.
+    fn alloc_block(remaining: usize, config: &EncoderConfig) -> Vec<u8> {
+        let packet_size = config.packet_size as usize;
+        let padded_size = remaining.div_ceil(packet_size) * packet_size;
+        return Vec::with_capacity(cmp::min(config.get_block_size(), padded_size));
+    }
+
     pub fn generate_encoded_blocks(&self) -> Vec<EncodedBlock> {
         let mut blocks: Vec<EncodedBlock> = Vec::new();
 
         for block_encoder in self.block_encoders.iter() {
             blocks.append(&mut block_encoder.generate_encoded_blocks());
+            if self.evict_after_generate {
+                block_encoder.evict();
+            }
         }
 
         return blocks;
     }
 
+    /// Creates packets to transmit as generate_encoded_blocks does, drawing each block's start from rng.
+    pub fn generate_encoded_blocks_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<EncodedBlock> {
+        let mut blocks: Vec<EncodedBlock> = Vec::new();
+
+        for block_encoder in self.block_encoders.iter() {
+            blocks.append(&mut block_encoder.generate_encoded_blocks_with(rng));
+            if self.evict_after_generate {
+                block_encoder.evict();
+            }
+        }
+
+        return blocks;
+    }
+
+    /// Makes generate_encoded_blocks evict each block once its symbols are generated, so encoding a large object
+    /// in one pass holds the encoder state of a single block at a time. Clones made later start with the same setting.
+    pub fn set_evict_after_generate(&mut self, evict_after_generate: bool) {
+        self.evict_after_generate = evict_after_generate;
+    }
+
+    /// Evicts the encoder state of every block, see BlockEncoder::evict.
+    pub fn evict(&self) {
+        for block_encoder in self.block_encoders.iter() {
+            block_encoder.evict();
+        }
+    }
+
+    /// Estimates the bytes held by the encoder, see BlockEncoder::memory_usage.
+    pub fn memory_usage(&self) -> usize {
+        return self.block_encoders.iter().map(|x| x.memory_usage()).sum();
+    }
+
     pub fn get_block_info_vec(&self) -> Vec<BlockInfo> {
         return self.block_encoders.iter().map(|x| x.get_block_info()).collect();
     }
+
+    /// Gets the region of the original payload covered by each block.
+    pub fn block_map(&self) -> Vec<BlockRegion> {
+        return BlockRegion::map(&self.get_block_info_vec());
+    }
+
+    /// Gets the encoders of each block, ordered by block id.
+    pub fn get_block_encoders(&self) -> &[BlockEncoder] {
+        return &self.block_encoders;
+    }
+
+    /// Gets the size of the payload, without padding.
+    pub fn get_data_size(&self) -> usize {
+        return self.data_size;
+    }
+
+    /// Gets the encoded packet size.
+    pub fn get_packet_size(&self) -> u16 {
+        return self.config.packet_size;
+    }
+
+    /// Gets the parameters the object was encoded with.
+    pub fn get_config(&self) -> EncoderConfig {
+        return self.config;
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -53,6 +343,14 @@ pub enum RaptorQEncoderError {
     /// TODO: make errors more useful. 
     InvalidPacketSize,
     DataSizeTooLarge,
+    /// Alignment is not a power of two.
+    InvalidAlignment,
+    /// Block symbol limit is not in 1..=RAPTORQ_MAX_SYMBOLS_IN_BLOCK.
+    InvalidBlockSymbols,
+    /// Data appended to an object does not start with its payload, see Manifest::append.
+    PayloadMismatch,
+    /// The object is encrypted as a whole, so data can't be appended to it, see Manifest::append.
+    Enveloped,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash)]
@@ -76,45 +374,112 @@ pub struct BlockInfo {
     pub block_id: u32,
 }
 
+impl BlockInfo {
+    /// Gets the bytes of zero padding after the payload.
+    pub fn get_pad_len(&self) -> usize {
+        return self.padded_size - self.payload_size;
+    }
+
+    /// Gets the payload bytes in source symbol esi, the rest of the symbol being padding. Blocks are laid out so only
+    /// the last source symbol has padding, but peers may describe blocks with symbols of padding alone, which have
+    /// none.
+    pub fn get_source_symbol_len(&self, esi: u32) -> usize {
+        let symbol_size = self.config.symbol_size() as usize;
+        return cmp::min(symbol_size, self.payload_size.saturating_sub(esi as usize * symbol_size));
+    }
+}
+
+/// Region of the original payload covered by a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct BlockRegion {
+    /// Index of the block in overall payload.
+    pub block_id: u32,
+    /// Offset of the block's first byte in overall payload.
+    pub byte_offset: usize,
+    /// Size of the block's payload, without padding.
+    pub len: usize,
+}
+
+impl BlockRegion {
+    /// Computes block regions from block infos ordered by block id.
+    pub fn map(block_info_vec: &[BlockInfo]) -> Vec<BlockRegion> {
+        let mut byte_offset: usize = 0;
+        return block_info_vec.iter().map(|block_info| {
+            let region = BlockRegion {
+                block_id: block_info.block_id,
+                byte_offset,
+                len: block_info.payload_size,
+            };
+            byte_offset += block_info.payload_size;
+            region
+        }).collect();
+    }
+}
+
 /// A representation of a BlockEncoder
 pub struct BlockEncoder {
     /// RaptorQ configuration object
     config: ObjectTransmissionInformation,
+    /// RaptorQ encoder, retained so packets can be generated repeatedly without recomputing intermediate symbols.
+    /// None once evicted, until symbols are generated again.
+    encoder: RwLock<Option<Arc<dyn BlockSymbolEncoder>>>,
+    /// Backend creating the encoder, again after an eviction.
+    backend: Arc<dyn BlockCodecBackend>,
     /// Data to be encoded with the RaptorQ scheme (padded to a multiple of packet_size)
-    data: Vec<u8>,
+    data: PageBuffer,
     /// Original size of data before padding.
     payload_size: usize,
     /// Index of this block in overall payload.
     block_id: u32,
-    /// Encoded packet size. Also the symbol size used for BlockEncoder.
+    /// Encoded packet size. Also the symbol size used for BlockEncoder, which is below the config's packet size for a
+    /// tail block shrunk with TailStrategy::ShrinkSymbols.
     packet_size: u16,
+    /// Generator for random repair symbol ids if the config has a seed, otherwise the thread's generator is used.
+    rng: Option<Mutex<StdRng>>,
+    /// EncoderConfig::elide_padding.
+    elide_padding: bool,
 }
 
 impl BlockEncoder {
     /// Creates a BlockEncoder with a given data payload and packet size
     /// We use packet size == symbol size. 
-    pub fn new(block_id: u32, packet_size: u16, mut data: Vec<u8>) -> Result<BlockEncoder, RaptorQEncoderError> {
-        if packet_size % ALIGNMENT as u16 != 0 || packet_size < MIN_PACKET_SIZE {
-            return Err(RaptorQEncoderError::InvalidPacketSize);
-        }
+    pub fn new(block_id: u32, packet_size: u16, data: Vec<u8>) -> Result<BlockEncoder, RaptorQEncoderError> {
+        return BlockEncoder::with_config(block_id, EncoderConfig::new(packet_size), data);
+    }
 
-        let payload_size = data.len();
+    /// Creates a BlockEncoder with explicit parameters. The alignment is carried to decoders in the BlockInfo.
+    pub fn with_config(block_id: u32, config: EncoderConfig, data: Vec<u8>) -> Result<BlockEncoder, RaptorQEncoderError> {
+        return BlockEncoder::build(block_id, config, data, None, RaptorqBackend::shared());
+    }
 
-        // The rust RaptorQ library asserts data length to be a multiple of packet size, pad with zeros.
-        if data.len() % packet_size as usize > 0 {
-            data.resize(
-                data.len() + (packet_size as usize - (data.len() % packet_size as usize)),
-                0,
-            );
-        }
+    /// Creates a BlockEncoder as with_config does, taking the encoding plan from a cache.
+    pub fn with_plan_cache(block_id: u32, config: EncoderConfig, data: Vec<u8>, plan_cache: &PlanCache) -> Result<BlockEncoder, RaptorQEncoderError> {
+        return BlockEncoder::build(block_id, config, data, Some(plan_cache), RaptorqBackend::shared());
+    }
 
-        let source_block_size_limit = RAPTORQ_MAX_SYMBOLS_IN_BLOCK * packet_size as usize;
+    /// Creates a BlockEncoder as with_config does, encoding with backend instead of the raptorq crate.
+    pub fn with_backend(block_id: u32, config: EncoderConfig, data: Vec<u8>, backend: Arc<dyn BlockCodecBackend>) -> Result<BlockEncoder, RaptorQEncoderError> {
+        return BlockEncoder::build(block_id, config, data, None, backend);
+    }
 
-        let max_data_size = source_block_size_limit;
-        if data.len() > max_data_size as usize {
+    fn build(block_id: u32, config: EncoderConfig, mut data: Vec<u8>, plan_cache: Option<&PlanCache>, backend: Arc<dyn BlockCodecBackend>) -> Result<BlockEncoder, RaptorQEncoderError> {
+        config.validate()?;
+
+        if data.len() > config.get_block_size() {
             return Err(RaptorQEncoderError::DataSizeTooLarge);
         }
 
+        let (packet_size, symbol_count) = config.get_block_layout(data.len());
+        let payload_size = data.len();
+
+        // The rust RaptorQ library asserts data length to be a multiple of packet size, pad with zeros.
+        data.resize(symbol_count * packet_size as usize, 0);
+        let data = match config.huge_pages {
+            true => PageBuffer::copy_from(&data, true),
+            false => PageBuffer::from(data),
+        };
+
         /*
          * ObjectTransmissionInformation is described roughly by the RFC spec:
          * RFC 4.4.1.2:
@@ -130,18 +495,25 @@ impl BlockEncoder {
          * Notes:
          * Consider tweaking the sub-block argument.
          */
+        let oti = ObjectTransmissionInformation::new(
+            data.len() as u64,
+            packet_size,
+            1,
+            1,
+            config.alignment,
+        );
+        let encoder = backend.new_encoder(&oti, &data, plan_cache);
         return Ok(BlockEncoder {
-            config: ObjectTransmissionInformation::new(
-                data.len() as u64,
-                packet_size,
-                1,
-                1,
-                ALIGNMENT,
-            ),
-            data: data,
-            payload_size: payload_size,
-            packet_size: packet_size,
-            block_id: block_id,
+            config: oti,
+            encoder: RwLock::new(Some(Arc::from(encoder))),
+            backend,
+            data,
+            payload_size,
+            packet_size,
+            block_id,
+            // blocks of a seeded object get distinct streams
+            rng: config.seed.map(|x| Mutex::new(StdRng::seed_from_u64(x ^ ((block_id as u64) << 32)))),
+            elide_padding: config.elide_padding,
         });
     }
 
@@ -149,21 +521,18 @@ impl BlockEncoder {
         while match packets.pop() {
             None => false,
             Some(packet) => {
-                blocks.push(EncodedBlock{block_id: block_id, data: packet});
+                blocks.push(EncodedBlock{block_id, data: packet});
                 true
             },
         } {}
     }
 
-    /// static method for encoding data
-    pub(crate) fn encode_data(config: &ObjectTransmissionInformation, data: &[u8], packet_size: u16, block_id: u32) -> Vec<EncodedBlock> {
-        let encoder = SourceBlockEncoder::new2(0, config, data);
-        let packets_to_send = data.len() / packet_size as usize;
+    /// static method for encoding data, creates packets_to_send repair packets starting at repair symbol start_index.
+    /// Repair symbol ids wrap around to 0 at repair_symbol_id_limit.
+    pub(crate) fn encode_data(encoder: &dyn BlockSymbolEncoder, repair_symbol_id_limit: usize, start_index: usize, packets_to_send: usize, block_id: u32) -> Vec<EncodedBlock> {
         let mut blocks :Vec<EncodedBlock> = Vec::new();
 
-        let start_index = thread_rng().gen_range(0..RAPTORQ_ENCODING_SYMBOL_ID_MAX);
-        
-        let packets_created = cmp::min(RAPTORQ_ENCODING_SYMBOL_ID_MAX - start_index, packets_to_send);
+        let packets_created = cmp::min(repair_symbol_id_limit - start_index, packets_to_send);
 
         BlockEncoder::add_packets(&mut blocks, encoder.repair_packets(start_index as u32, packets_created as u32), block_id);
 
@@ -174,9 +543,106 @@ impl BlockEncoder {
         return blocks;
     }
 
-    /// Creates packets to transmit.
+    /// Creates packets to transmit, starting at a random repair symbol drawn from the seeded generator if the config
+    /// has a seed.
     pub fn generate_encoded_blocks(&self) -> Vec<EncodedBlock> {
-        return BlockEncoder::encode_data(&self.config, &self.data, self.packet_size, self.block_id);
+        let start_index = self.gen_repair_index();
+        return BlockEncoder::encode_data(&*self.get_source_encoder(), self.get_repair_symbol_id_limit(), start_index, self.get_symbol_count(), self.block_id);
+    }
+
+    /// Creates packets to transmit, starting at a repair symbol drawn from rng.
+    pub fn generate_encoded_blocks_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<EncodedBlock> {
+        let repair_symbol_id_limit = self.get_repair_symbol_id_limit();
+        let start_index = rng.gen_range(0..repair_symbol_id_limit);
+        return BlockEncoder::encode_data(&*self.get_source_encoder(), repair_symbol_id_limit, start_index, self.get_symbol_count(), self.block_id);
+    }
+
+    /// Draws a random repair symbol index, from the seeded generator if the config has a seed.
+    pub(crate) fn gen_repair_index(&self) -> usize {
+        let repair_symbol_id_limit = self.get_repair_symbol_id_limit();
+        match self.rng.as_ref() {
+            None => return thread_rng().gen_range(0..repair_symbol_id_limit),
+            Some(rng) => return rng.lock().unwrap().gen_range(0..repair_symbol_id_limit),
+        }
+    }
+
+    /// Creates the source packets of the block, i.e. the data itself split into packets. With
+    /// EncoderConfig::elide_padding, the last packet of a padded block ends with the payload, decoders filling in
+    /// the zeros.
+    pub fn generate_source_blocks(&self) -> Vec<EncodedBlock> {
+        let mut blocks :Vec<EncodedBlock> = Vec::new();
+        BlockEncoder::add_packets(&mut blocks, self.get_source_encoder().source_packets(), self.block_id);
+        if self.elide_padding {
+            let block_info = self.get_block_info();
+            for block in blocks.iter_mut() {
+                let len = block_info.get_source_symbol_len(block.data.payload_id().encoding_symbol_id());
+                if len < block.data.data().len() {
+                    block.data = EncodingPacket::new(block.data.payload_id().clone(), block.data.data()[..len].to_vec());
+                }
+            }
+        }
+        return blocks;
+    }
+
+    /// Creates count packets starting at repair symbol start_index, wrapping around at the end of the ESI space.
+    pub fn generate_repair_blocks(&self, start_index: u32, count: usize) -> Vec<EncodedBlock> {
+        let repair_symbol_id_limit = self.get_repair_symbol_id_limit();
+        let start_index = start_index as usize % repair_symbol_id_limit;
+        return BlockEncoder::encode_data(&*self.get_source_encoder(), repair_symbol_id_limit, start_index, count, self.block_id);
+    }
+
+    /// Gets the number of repair symbol ids available. Repair symbols are numbered after the extended source
+    /// symbols, and encoding symbol ids must fit in 24 bits.
+    pub fn get_repair_symbol_id_limit(&self) -> usize {
+        let extended_symbol_count = extended_source_block_symbols(self.get_symbol_count() as u32) as usize;
+        return RAPTORQ_ENCODING_SYMBOL_ID_MAX - extended_symbol_count;
+    }
+
+    /// Gets the RaptorQ encoder, recomputing its intermediate symbols from the data if it was evicted.
+    fn get_source_encoder(&self) -> Arc<dyn BlockSymbolEncoder> {
+        if let Some(encoder) = self.encoder.read().unwrap().as_ref() {
+            return encoder.clone();
+        }
+
+        let mut encoder = self.encoder.write().unwrap();
+        return encoder.get_or_insert_with(|| Arc::from(self.backend.new_encoder(&self.config, &self.data, None))).clone();
+    }
+
+    /// Drops the RaptorQ encoder's state, which is recomputed from the data the next time symbols are generated.
+    /// Saves about twice the block's size, at the cost of encoding the block again.
+    pub fn evict(&self) {
+        *self.encoder.write().unwrap() = None;
+    }
+
+    /// Returns true unless the encoder's state was evicted and not recomputed since.
+    pub fn is_resident(&self) -> bool {
+        return self.encoder.read().unwrap().is_some();
+    }
+
+    /// Estimates the bytes held by the block: its padded data, plus the RaptorQ encoder's copy of the source symbols
+    /// and its intermediate symbols unless evicted. Intermediate symbols are counted as K', leaving out the few
+    /// percent more the LDPC and HDPC symbols add.
+    pub fn memory_usage(&self) -> usize {
+        if !self.is_resident() {
+            return self.data.capacity();
+        }
+        let intermediate_symbols = extended_source_block_symbols(self.get_symbol_count() as u32) as usize;
+        return self.data.capacity() + self.data.len() + intermediate_symbols * self.packet_size as usize;
+    }
+
+    /// Gets the number of source symbols in the block.
+    pub fn get_symbol_count(&self) -> usize {
+        return self.data.len() / self.packet_size as usize;
+    }
+
+    /// Gets what the block's data is kept in, see EncoderConfig::huge_pages.
+    pub fn get_page_backing(&self) -> PageBacking {
+        return self.data.get_backing();
+    }
+
+    /// Gets the payload of the block, without padding.
+    pub fn get_payload(&self) -> &[u8] {
+        return &self.data[..self.payload_size];
     }
 
     /// Gets information about payload required for decoding.
@@ -191,19 +657,11 @@ impl BlockEncoder {
 }
 
 #[cfg(test)]
-use super::decoder::*;
 mod tests {
     use super::*;
-    use rand::Rng;
+    use crate::test_util::gen_data;
+    use super::super::decoder::*;
 
-    fn gen_data(len: usize) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::with_capacity(len);
-        for _ in 0..len {
-            data.push(rand::thread_rng().gen());
-        }
-        return data;
-    }
-    
     fn arr_eq(data1: &[u8], data2: &[u8]) -> bool {
         return data1.iter().zip(data2.iter()).all(|(a,b)| a == b);
     }
@@ -232,9 +690,9 @@ mod tests {
         };
         let blocks = encoder.generate_encoded_blocks();
         
-        match BlockDecoder::decode_data(&encoder.get_block_info(), blocks) {
-            Ok(recovered_data) => assert_eq!(arr_eq(&recovered_data, &data), true),
-            Err(error) => panic!("Failed to decode data, err {}", error as u32),
+        match BlockDecoder::decode_data(&RaptorqBackend, &encoder.get_block_info(), blocks) {
+            Ok(recovered_data) => assert!(arr_eq(&recovered_data, &data)),
+            Err(error) => panic!("Failed to decode data, err {:?}", error),
         }
     }
     
@@ -264,12 +722,118 @@ mod tests {
         blocks.append(&mut blocks_3);
         
         // recover data
-        match BlockDecoder::decode_data(&encoder.get_block_info(), blocks) {
-            Ok(recovered_data) => assert_eq!(arr_eq(&recovered_data, &data), true),
-            Err(error) => panic!("Failed to decode data, err {}", error as u32),
+        match BlockDecoder::decode_data(&RaptorqBackend, &encoder.get_block_info(), blocks) {
+            Ok(recovered_data) => assert!(arr_eq(&recovered_data, &data)),
+            Err(error) => panic!("Failed to decode data, err {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_encoder_block_map() {
+        let packet_size: u16 = 1280;
+        let data_size: usize = 100 * 1000;
+        let data = gen_data(data_size);
+
+        let encoder = match RaptorQEncoder::new(packet_size, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+
+        assert_eq!(encoder.block_map(), vec![BlockRegion { block_id: 0, byte_offset: 0, len: data_size }]);
+    }
+
+    #[test]
+    fn test_encoder_vectored() {
+        let packet_size: u16 = 1280;
+        let data_size: usize = 100 * 1000;
+        let data = gen_data(data_size);
+
+        // split into uneven buffers, including an empty one
+        let bufs: Vec<IoSlice> = vec![
+            IoSlice::new(&data[..1]),
+            IoSlice::new(&data[1..4099]),
+            IoSlice::new(&data[4099..4099]),
+            IoSlice::new(&data[4099..]),
+        ];
+
+        let encoder = match RaptorQEncoder::new_vectored(packet_size, &bufs) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        assert_eq!(encoder.get_data_size(), data_size);
+
+        let block_info_vec = encoder.get_block_info_vec();
+        assert_eq!(block_info_vec.len(), 1);
+
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        match BlockDecoder::decode_data(&RaptorqBackend, &block_info_vec[0], blocks) {
+            Ok(recovered_data) => assert_eq!(&recovered_data[..data_size], &data[..]),
+            Err(error) => panic!("Failed to decode data, err {:?}", error),
         }
     }
 
+    #[test]
+    fn test_encoder_config_alignment() {
+        let data_size: usize = 100 * 1000;
+        let data = gen_data(data_size);
+
+        let config = EncoderConfig { alignment: 64, ..EncoderConfig::new(1280) };
+        let encoder = match RaptorQEncoder::with_config(config, &[IoSlice::new(&data)]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        assert_eq!(encoder.get_config(), config);
+
+        // decoders take the alignment from the block info
+        let block_info_vec = encoder.get_block_info_vec();
+        assert_eq!(block_info_vec[0].config.symbol_alignment(), 64);
+        let mut decoder = match RaptorQDecoder::new(block_info_vec) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        assert_eq!(decoder.consume(blocks), Ok(true));
+        assert_eq!(decoder.get_result(), Some(data.clone()));
+
+        match RaptorQEncoder::with_config(EncoderConfig { alignment: 24, ..EncoderConfig::new(1280) }, &[IoSlice::new(&data)]) {
+            Ok(_) => panic!("Should have failed to use alignment 24"),
+            Err(error) => assert_eq!(error, RaptorQEncoderError::InvalidAlignment),
+        }
+        match RaptorQEncoder::with_config(EncoderConfig { alignment: 16, ..EncoderConfig::new(1288) }, &[IoSlice::new(&data)]) {
+            Ok(_) => panic!("Should have failed to use packet_size 1288 with alignment 16"),
+            Err(error) => assert_eq!(error, RaptorQEncoderError::InvalidPacketSize),
+        }
+    }
+
+    #[test]
+    fn test_max_block_symbols() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(250 * packet_size as usize + 100);
+        let mut config = EncoderConfig::new(packet_size);
+        config.max_block_symbols = 100;
+        let encoder = match RaptorQEncoder::with_config(config, &[IoSlice::new(&data)]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let symbol_counts: Vec<usize> = encoder.get_block_encoders().iter().map(|x| x.get_symbol_count()).collect();
+        assert_eq!(symbol_counts, vec![100, 100, 51]);
+        assert_eq!(config.get_padded_size(data.len() as u64), 251 * packet_size as u64);
+
+        let mut decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        assert_eq!(decoder.consume(blocks), Ok(true));
+        assert_eq!(decoder.get_result(), Some(data.clone()));
+
+        config.max_block_symbols = 0;
+        assert_eq!(RaptorQEncoder::with_config(config, &[IoSlice::new(&data)]).err(), Some(RaptorQEncoderError::InvalidBlockSymbols));
+    }
+
     // this test should be run with --release, due to raptorq performance. 
     #[cfg(not(debug_assertions))]
     #[test]
@@ -297,9 +861,9 @@ mod tests {
             let (drained, rest): (Vec<EncodedBlock>, Vec<EncodedBlock>) = blocks_total.into_iter().partition(|x| x.block_id == block_info.block_id);
             blocks_total = rest;
 
-            match BlockDecoder::decode_data(&block_info, drained) {
-                Ok(recovered_data) => assert_eq!(arr_eq(&recovered_data, &data[start_index..(start_index + block_info.padded_size)]), true),
-                Err(error) => panic!("Failed to decode data, err {}", error as u32),
+            match BlockDecoder::decode_data(&RaptorqBackend, block_info, drained) {
+                Ok(recovered_data) => assert!(arr_eq(&recovered_data, &data[start_index..(start_index + block_info.padded_size)])),
+                Err(error) => panic!("Failed to decode data, err {:?}", error),
             }
 
             start_index += block_info.padded_size;
@@ -307,4 +871,115 @@ mod tests {
         
         assert_eq!(blocks_total.len(), 0);
     }
+
+    #[test]
+    fn test_encoder_tail_strategy() {
+        let mut config = EncoderConfig::new(1280);
+        // one byte into the last symbol, the worst case for padding
+        let data = gen_data(10 * 1280 + 1);
+
+        let padded = match BlockEncoder::with_config(0, config, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        assert_eq!(padded.get_block_info().padded_size, 11 * 1280);
+        assert_eq!(config.get_padded_size(data.len() as u64), 11 * 1280);
+
+        config.tail_strategy = TailStrategy::ShrinkSymbols;
+        let shrunk = match BlockEncoder::with_config(0, config, data.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let block_info = shrunk.get_block_info();
+        assert_eq!(shrunk.get_symbol_count(), 11);
+        assert_eq!(block_info.config.symbol_size(), 1168);
+        assert_eq!(block_info.padded_size, 11 * 1168);
+        assert_eq!(config.get_padded_size(data.len() as u64), 11 * 1168);
+        assert!(block_info.padded_size - data.len() < 11 * ALIGNMENT as usize);
+
+        // decoders only need the block info
+        let mut decoder = match BlockDecoder::new(block_info) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let mut blocks = shrunk.generate_encoded_blocks();
+        blocks.append(&mut shrunk.generate_encoded_blocks());
+        assert!(blocks.iter().all(|x| x.data.data().len() == 1168));
+        assert_eq!(decoder.consume(blocks), Ok(true));
+        assert_eq!(decoder.get_result(), Some(&data[..]));
+    }
+
+    #[test]
+    fn test_encoder_shared_between_threads() {
+        let data = gen_data(100 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let expected = encoder.get_block_encoders()[0].generate_repair_blocks(0, 16);
+
+        // clones share the block encoders rather than copying them
+        let clone = encoder.clone();
+        assert!(std::ptr::eq(clone.get_block_encoders(), encoder.get_block_encoders()));
+
+        let threads: Vec<std::thread::JoinHandle<Vec<EncodedBlock>>> = (0..4).map(|_| {
+            let encoder = encoder.clone();
+            std::thread::spawn(move || encoder.get_block_encoders()[0].generate_repair_blocks(0, 16))
+        }).collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_encoder_seeded() {
+        let data = gen_data(100 * 1000);
+        let mut config = EncoderConfig::new(1280);
+        config.seed = Some(42);
+        let encode = || match RaptorQEncoder::with_config(config, &[IoSlice::new(&data)]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+
+        // the same seed gives the same sequence of symbols, each round still starting somewhere new
+        let (first, second) = (encode(), encode());
+        let round = first.generate_encoded_blocks();
+        assert_eq!(round, second.generate_encoded_blocks());
+        assert_ne!(first.generate_encoded_blocks(), round);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let blocks = first.generate_encoded_blocks_with(&mut rng);
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(second.generate_encoded_blocks_with(&mut rng), blocks);
+    }
+
+    #[test]
+    fn test_encoder_evict() {
+        let data = gen_data(100 * 1000);
+        let mut encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let resident = encoder.memory_usage();
+        assert!(resident > 2 * data.len());
+
+        // evicted state is recomputed on demand, generating the same symbols
+        let repair = encoder.get_block_encoders()[0].generate_repair_blocks(5, 10);
+        encoder.evict();
+        assert!(encoder.memory_usage() < resident / 2);
+        assert!(!encoder.get_block_encoders()[0].is_resident());
+        assert_eq!(encoder.get_block_encoders()[0].generate_repair_blocks(5, 10), repair);
+        assert_eq!(encoder.memory_usage(), resident);
+
+        encoder.set_evict_after_generate(true);
+        let mut blocks = encoder.generate_encoded_blocks();
+        assert!(!encoder.get_block_encoders()[0].is_resident());
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        let mut decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        assert!(decoder.consume(blocks).unwrap());
+        assert_eq!(decoder.get_result(), Some(data));
+    }
 }
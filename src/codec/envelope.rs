@@ -0,0 +1,239 @@
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "envelope")]
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+#[cfg(feature = "envelope")]
+use chacha20poly1305::{AeadCore, ChaCha20Poly1305, Key, Nonce};
+#[cfg(feature = "envelope")]
+use hkdf::Hkdf;
+#[cfg(feature = "envelope")]
+use sha2::Sha256;
+#[cfg(feature = "envelope")]
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+/// Tag of the envelope section of a manifest, see Manifest::write_to.
+pub const ENVELOPE_SECTION: u16 = 1;
+
+/// Version of the envelope section layout, ChaCha20-Poly1305 data keys wrapped with X25519 and HKDF-SHA256.
+pub const ENVELOPE_VERSION: u16 = 1;
+
+/// Size of an X25519 public key.
+pub const RECIPIENT_KEY_SIZE: usize = 32;
+
+/// Size of a wrapped data key: the key and its Poly1305 tag.
+pub const WRAPPED_KEY_SIZE: usize = 32 + 16;
+
+/// Most recipients an envelope holds, as the section counts them in a u16.
+pub const MAX_RECIPIENTS: usize = u16::MAX as usize;
+
+/// Size of the nonce the object is encrypted with.
+pub const NONCE_SIZE: usize = 12;
+
+/// HKDF info of the keys wrapping data keys, so they can't be mistaken for keys derived for anything else.
+#[cfg(feature = "envelope")]
+const WRAP_INFO: &[u8] = b"raptorcdn envelope v1 key wrap";
+
+/// The data key of an object, wrapped for one recipient: encrypted with a key derived from an ephemeral key pair
+/// and the recipient's public key, so only the holder of the recipient's secret key can unwrap it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct WrappedKey {
+    /// X25519 public key of the recipient.
+    pub recipient: [u8; RECIPIENT_KEY_SIZE],
+    /// X25519 public key of the ephemeral key pair the data key was wrapped with.
+    pub ephemeral: [u8; RECIPIENT_KEY_SIZE],
+    /// The encrypted data key, WRAPPED_KEY_SIZE bytes.
+    pub wrapped_key: Vec<u8>,
+}
+
+/// How an object's payload is encrypted: once, with a data key wrapped for each recipient. Symbols of the object
+/// are then symbols of ciphertext, so caches and relays serve them to anyone without being able to read them. The
+/// manifest's object id and block hashes are those of the ciphertext.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Envelope {
+    pub nonce: [u8; NONCE_SIZE],
+    pub wrapped_keys: Vec<WrappedKey>,
+}
+
+/// Why an encrypted object could not be opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// No data key is wrapped for the public key of the secret key given.
+    NotARecipient,
+    /// The data key wrapped for the recipient did not unwrap, e.g. the entry was tampered with.
+    Unwrap,
+    /// The payload did not decrypt with the data key, e.g. it is not the payload the envelope was sealed with.
+    Decrypt,
+    /// The payload did not encrypt, e.g. it is too large for the cipher.
+    Encrypt,
+}
+
+impl Envelope {
+    /// Writes the envelope section: nonce, recipient count (u16), then for each recipient its public key, ephemeral
+    /// public key and wrapped key. Fails with ErrorKind::InvalidInput above MAX_RECIPIENTS recipients.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let count = match u16::try_from(self.wrapped_keys.len()) {
+            Ok(count) => count,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("more than {} recipients", MAX_RECIPIENTS))),
+        };
+        writer.write_all(&self.nonce)?;
+        writer.write_all(&count.to_le_bytes())?;
+        for wrapped_key in self.wrapped_keys.iter() {
+            if wrapped_key.wrapped_key.len() != WRAPPED_KEY_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "wrapped key of the wrong size"));
+            }
+            writer.write_all(&wrapped_key.recipient)?;
+            writer.write_all(&wrapped_key.ephemeral)?;
+            writer.write_all(&wrapped_key.wrapped_key)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Reads an envelope section written by write_to.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Envelope> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        reader.read_exact(&mut nonce)?;
+        let mut count = [0u8; 2];
+        reader.read_exact(&mut count)?;
+
+        let mut wrapped_keys: Vec<WrappedKey> = Vec::new();
+        for _ in 0..u16::from_le_bytes(count) {
+            let mut recipient = [0u8; RECIPIENT_KEY_SIZE];
+            reader.read_exact(&mut recipient)?;
+            let mut ephemeral = [0u8; RECIPIENT_KEY_SIZE];
+            reader.read_exact(&mut ephemeral)?;
+            let mut wrapped_key = vec![0u8; WRAPPED_KEY_SIZE];
+            reader.read_exact(&mut wrapped_key)?;
+            wrapped_keys.push(WrappedKey { recipient, ephemeral, wrapped_key });
+        }
+
+        return Ok(Envelope { nonce, wrapped_keys });
+    }
+}
+
+/// Encrypts data with a fresh data key wrapped for each recipient, returning the ciphertext to encode in its place
+/// and the envelope to set in its manifest. The ciphertext is 16 bytes longer than data. Envelopes of more than
+/// MAX_RECIPIENTS recipients can't be written. Fails with Encrypt if the cipher refuses data.
+#[cfg(feature = "envelope")]
+pub fn seal(data: &[u8], recipients: &[PublicKey]) -> Result<(Vec<u8>, Envelope), EnvelopeError> {
+    let data_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = match ChaCha20Poly1305::new(&data_key).encrypt(&nonce, data) {
+        Ok(ciphertext) => ciphertext,
+        Err(_) => return Err(EnvelopeError::Encrypt),
+    };
+
+    let wrapped_keys: Result<Vec<WrappedKey>, EnvelopeError> = recipients.iter().map(|recipient| {
+        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral = PublicKey::from(&ephemeral_secret);
+        let wrapping_key = derive_wrapping_key(ephemeral_secret.diffie_hellman(recipient).as_bytes(), &ephemeral, recipient);
+        return Ok(WrappedKey {
+            recipient: recipient.to_bytes(),
+            ephemeral: ephemeral.to_bytes(),
+            wrapped_key: ChaCha20Poly1305::new(&wrapping_key).encrypt(&Nonce::default(), data_key.as_slice()).map_err(|_| EnvelopeError::Encrypt)?,
+        });
+    }).collect();
+
+    let mut envelope_nonce = [0u8; NONCE_SIZE];
+    envelope_nonce.copy_from_slice(&nonce);
+    return Ok((ciphertext, Envelope { nonce: envelope_nonce, wrapped_keys: wrapped_keys? }));
+}
+
+/// Decrypts the ciphertext sealed with envelope, as the recipient of secret.
+#[cfg(feature = "envelope")]
+pub fn open(envelope: &Envelope, ciphertext: &[u8], secret: &StaticSecret) -> Result<Vec<u8>, EnvelopeError> {
+    let recipient = PublicKey::from(secret);
+    let wrapped_key = match envelope.wrapped_keys.iter().find(|x| x.recipient == recipient.to_bytes()) {
+        Some(wrapped_key) => wrapped_key,
+        None => return Err(EnvelopeError::NotARecipient),
+    };
+
+    let ephemeral = PublicKey::from(wrapped_key.ephemeral);
+    let wrapping_key = derive_wrapping_key(secret.diffie_hellman(&ephemeral).as_bytes(), &ephemeral, &recipient);
+    let data_key = match ChaCha20Poly1305::new(&wrapping_key).decrypt(&Nonce::default(), wrapped_key.wrapped_key.as_slice()) {
+        Ok(data_key) if data_key.len() == 32 => data_key,
+        _ => return Err(EnvelopeError::Unwrap),
+    };
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&data_key));
+    return cipher.decrypt(Nonce::from_slice(&envelope.nonce), ciphertext).map_err(|_| EnvelopeError::Decrypt);
+}
+
+/// Derives the key wrapping a data key from the shared secret of the ephemeral and recipient key pairs, salted with
+/// both public keys so a wrapped key can't be replayed under another recipient's entry. Every wrapping key comes
+/// from a fresh ephemeral key pair and wraps a single data key, so wrapping with the all-zero nonce never reuses a
+/// nonce under a key.
+#[cfg(feature = "envelope")]
+fn derive_wrapping_key(shared_secret: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> Key {
+    let mut salt = [0u8; 2 * RECIPIENT_KEY_SIZE];
+    salt[..RECIPIENT_KEY_SIZE].copy_from_slice(ephemeral.as_bytes());
+    salt[RECIPIENT_KEY_SIZE..].copy_from_slice(recipient.as_bytes());
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret).expand(WRAP_INFO, &mut key).unwrap();
+    return key;
+}
+
+#[cfg(all(test, feature = "envelope"))]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::decoder::RaptorQDecoder;
+    use crate::codec::encoder::RaptorQEncoder;
+    use crate::codec::manifest::Manifest;
+
+    #[test]
+    fn test_envelope() {
+        let data = gen_data(100 * 1000);
+        let alice = StaticSecret::random_from_rng(OsRng);
+        let bob = StaticSecret::random_from_rng(OsRng);
+        let mallory = StaticSecret::random_from_rng(OsRng);
+
+        // encrypted once, the ciphertext is what gets encoded and the envelope travels in the manifest
+        let (ciphertext, envelope) = seal(&data, &[PublicKey::from(&alice), PublicKey::from(&bob)]).unwrap();
+        assert_eq!(ciphertext.len(), data.len() + 16);
+        let encoder = match RaptorQEncoder::new(1280, &ciphertext) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let mut manifest = Manifest::new(&encoder);
+        manifest.envelope = Some(envelope);
+        let mut written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut written).unwrap();
+        let manifest = Manifest::read_from(&written[..]).unwrap();
+        let envelope = manifest.envelope.as_ref().unwrap();
+
+        let mut decoder = RaptorQDecoder::from_manifest(&manifest).unwrap();
+        for block_encoder in encoder.get_block_encoders().iter() {
+            decoder.consume(block_encoder.generate_source_blocks()).unwrap();
+        }
+        let decoded = decoder.get_result().unwrap();
+        assert_eq!(open(envelope, &decoded, &alice), Ok(data.clone()));
+        assert_eq!(open(envelope, &decoded, &bob), Ok(data));
+
+        // whoever isn't a recipient gets nothing, even by claiming a recipient's entry as their own
+        assert_eq!(open(envelope, &decoded, &mallory), Err(EnvelopeError::NotARecipient));
+        let mut forged = envelope.clone();
+        forged.wrapped_keys[0].recipient = PublicKey::from(&mallory).to_bytes();
+        assert_eq!(open(&forged, &decoded, &mallory), Err(EnvelopeError::Unwrap));
+
+        let mut tampered = decoded.clone();
+        tampered[0] ^= 1;
+        assert_eq!(open(envelope, &tampered, &alice), Err(EnvelopeError::Decrypt));
+
+        // the recipient count must fit the section, rather than wrap around
+        let mut crowded = envelope.clone();
+        crowded.wrapped_keys = vec![envelope.wrapped_keys[0].clone(); MAX_RECIPIENTS];
+        let mut written: Vec<u8> = Vec::new();
+        crowded.write_to(&mut written).unwrap();
+        assert_eq!(Envelope::read_from(&written[..]).unwrap(), crowded);
+        crowded.wrapped_keys.push(envelope.wrapped_keys[1].clone());
+        let mut written: Vec<u8> = Vec::new();
+        assert_eq!(crowded.write_to(&mut written).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(written.is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+//! Encoding an object on several workers, e.g. to pre-encode a catalog too large for one machine. A Coordinator
+//! splits the object into ranges of blocks and hands each range to a worker as an EncodeJob; the worker reads the
+//! range from the object's file, which all workers reach at the same path, e.g. on a network file system, and writes
+//! the range's symbols to a shard of its own. The coordinator puts the results together into one manifest, the same
+//! RaptorQEncoder makes of the whole object, along with where each range's shard is.
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::encoder::{BlockEncoder, BlockInfo, EncoderConfig};
+use super::manifest::{BlockHash, Manifest, MANIFEST_BLOCK_ID_BASE};
+use super::plan_cache::PlanCache;
+use super::shard::{write_shard_records, SHARD_MAGIC};
+
+/// Blocks per job unless set otherwise.
+const DEFAULT_BLOCKS_PER_JOB: u32 = 16;
+
+/// Times a job is tried, on any worker, before the whole run fails.
+const MAX_JOB_ATTEMPTS: u32 = 3;
+
+/// Encoding a range of blocks of an object, see the module documentation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct EncodeJob {
+    /// File holding the whole object.
+    pub source: PathBuf,
+    pub block_ids: Range<u32>,
+    pub config: EncoderConfig,
+    /// Where to write the symbols of the job's blocks, as a shard.
+    pub shard: PathBuf,
+    /// Symbol counts of the job's blocks, for the worker to take encoding plans for from its plan cache, or to
+    /// compute once and keep there.
+    pub plan_hints: Vec<u16>,
+}
+
+/// What a worker made of an EncodeJob.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct EncodeJobResult {
+    pub block_ids: Range<u32>,
+    /// Block infos of the job's blocks, ordered by block id.
+    pub block_info_vec: Vec<BlockInfo>,
+    /// Block hashes of the job's blocks, ordered by block id.
+    pub block_hashes: Vec<BlockHash>,
+    pub shard: PathBuf,
+    /// Symbols written to the shard.
+    pub symbols: u64,
+}
+
+/// Runs encode jobs, e.g. in another process reached over the control plane, or right here, see LocalWorker.
+pub trait EncodeWorker: Send + Sync {
+    fn run_job(&self, job: &EncodeJob) -> io::Result<EncodeJobResult>;
+}
+
+/// A worker encoding in this process, with plans from a cache kept across jobs.
+pub struct LocalWorker {
+    plan_cache: Arc<PlanCache>,
+}
+
+impl LocalWorker {
+    pub fn new(plan_cache: Arc<PlanCache>) -> LocalWorker {
+        return LocalWorker { plan_cache };
+    }
+}
+
+impl EncodeWorker for LocalWorker {
+    fn run_job(&self, job: &EncodeJob) -> io::Result<EncodeJobResult> {
+        return encode_job(job, &self.plan_cache);
+    }
+}
+
+/// Runs an encode job, taking encoding plans from plan_cache: reads the job's blocks from its source and writes their
+/// symbols to its shard. Fails with ErrorKind::InvalidInput if the job's config is invalid, and
+/// ErrorKind::UnexpectedEof if the source ends before the job's blocks.
+pub fn encode_job(job: &EncodeJob, plan_cache: &PlanCache) -> io::Result<EncodeJobResult> {
+    if let Err(error) = job.config.validate() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid config: {:?}", error)));
+    }
+    plan_cache.prewarm(&job.plan_hints);
+
+    let block_size = job.config.get_block_size();
+    let mut source = File::open(&job.source)?;
+    source.seek(SeekFrom::Start(job.block_ids.start as u64 * block_size as u64))?;
+    let mut shard = BufWriter::new(File::create(&job.shard)?);
+    io::Write::write_all(&mut shard, SHARD_MAGIC)?;
+
+    let mut result = EncodeJobResult {
+        block_ids: job.block_ids.clone(),
+        block_info_vec: Vec::with_capacity(job.block_ids.len()),
+        block_hashes: Vec::with_capacity(job.block_ids.len()),
+        shard: job.shard.clone(),
+        symbols: 0,
+    };
+    for block_id in job.block_ids.clone() {
+        let mut block: Vec<u8> = Vec::with_capacity(block_size);
+        (&mut source).take(block_size as u64).read_to_end(&mut block)?;
+        // only the object's last block may be short
+        if block.is_empty() || (block.len() < block_size && block_id + 1 < job.block_ids.end) {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} ends before block {}", job.source.display(), block_id)));
+        }
+
+        result.block_hashes.push(Sha256::digest(&block).into());
+        let block_encoder = match BlockEncoder::with_plan_cache(block_id, job.config, block, plan_cache) {
+            Ok(block_encoder) => block_encoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("failed to create encoder: {:?}", error))),
+        };
+        let blocks = block_encoder.generate_encoded_blocks();
+        write_shard_records(&mut shard, &blocks)?;
+        result.symbols += blocks.len() as u64;
+        result.block_info_vec.push(block_encoder.get_block_info());
+    }
+    io::Write::flush(&mut shard)?;
+
+    return Ok(result);
+}
+
+/// The manifest of an object encoded by a Coordinator, with the shard of each range of blocks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FarmResult {
+    pub manifest: Manifest,
+    /// Ranges of blocks with the shard holding their symbols, ordered by block id.
+    pub shards: Vec<(Range<u32>, PathBuf)>,
+}
+
+/// A job waiting for a worker, with the attempts made at it so far.
+struct QueuedJob {
+    job: EncodeJob,
+    attempts: u32,
+}
+
+/// Splits objects into encode jobs and runs them on workers, see the module documentation.
+pub struct Coordinator {
+    workers: Vec<Arc<dyn EncodeWorker>>,
+    config: EncoderConfig,
+    blocks_per_job: u32,
+}
+
+impl Coordinator {
+    /// Creates a Coordinator running jobs on workers, one job per worker at a time. Panics if there are no workers.
+    pub fn new(workers: Vec<Arc<dyn EncodeWorker>>, config: EncoderConfig) -> Coordinator {
+        assert!(!workers.is_empty(), "workers must not be empty");
+        return Coordinator { workers, config, blocks_per_job: DEFAULT_BLOCKS_PER_JOB };
+    }
+
+    /// Sets how many blocks each job covers, at most. Fewer make smaller shards and spread a small object over more
+    /// workers. Panics if blocks is zero.
+    pub fn set_blocks_per_job(&mut self, blocks: u32) {
+        assert!(blocks > 0, "blocks per job must be positive");
+        self.blocks_per_job = blocks;
+    }
+
+    /// Splits an object of data_size bytes at source into jobs, with their shards named after the first block in
+    /// shard_dir.
+    pub fn plan_jobs(&self, source: &Path, data_size: u64, shard_dir: &Path) -> Vec<EncodeJob> {
+        let block_size = self.config.get_block_size() as u64;
+        let block_count = data_size.div_ceil(block_size) as u32;
+
+        let mut jobs: Vec<EncodeJob> = Vec::new();
+        let mut start: u32 = 0;
+        while start < block_count {
+            let end = block_count.min(start.saturating_add(self.blocks_per_job));
+            let plan_hints: BTreeSet<u16> = (start..end).map(|block_id| {
+                let len = block_size.min(data_size - block_id as u64 * block_size);
+                self.config.get_block_layout(len as usize).1 as u16
+            }).collect();
+            jobs.push(EncodeJob {
+                source: source.to_path_buf(),
+                block_ids: start..end,
+                config: self.config,
+                shard: shard_dir.join(format!("blocks-{:08}.shard", start)),
+                plan_hints: plan_hints.into_iter().collect(),
+            });
+            start = end;
+        }
+        return jobs;
+    }
+
+    /// Encodes the object at source on the workers, writing shards to shard_dir. A job that fails is tried again, on
+    /// whichever worker is free next, up to a few times before the run fails with the job's last error. Meanwhile
+    /// the object is hashed here, which takes a pass over it but far less time than encoding it. Fails with
+    /// ErrorKind::InvalidInput if the config is invalid.
+    pub fn run(&self, source: &Path, shard_dir: &Path) -> io::Result<FarmResult> {
+        if let Err(error) = self.config.validate() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid config: {:?}", error)));
+        }
+        let data_size = std::fs::metadata(source)?.len();
+        let jobs = self.plan_jobs(source, data_size, shard_dir);
+        if jobs.last().is_some_and(|x| x.block_ids.end > MANIFEST_BLOCK_ID_BASE) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "object has too many blocks"));
+        }
+
+        let queue: Mutex<VecDeque<QueuedJob>> = Mutex::new(jobs.into_iter().map(|job| QueuedJob { job, attempts: 0 }).collect());
+        let results: Mutex<Vec<EncodeJobResult>> = Mutex::new(Vec::new());
+        let failure: Mutex<Option<io::Error>> = Mutex::new(None);
+        let object_id = std::thread::scope(|scope| {
+            let (queue, results, failure) = (&queue, &results, &failure);
+            for worker in self.workers.iter() {
+                scope.spawn(move || {
+                    while failure.lock().unwrap().is_none() {
+                        let mut queued = match queue.lock().unwrap().pop_front() {
+                            None => return,
+                            Some(queued) => queued,
+                        };
+                        match worker.run_job(&queued.job) {
+                            Ok(result) => results.lock().unwrap().push(result),
+                            Err(error) => {
+                                queued.attempts += 1;
+                                if queued.attempts >= MAX_JOB_ATTEMPTS {
+                                    let message = format!("blocks {:?} failed {} times: {}", queued.job.block_ids, queued.attempts, error);
+                                    *failure.lock().unwrap() = Some(io::Error::new(error.kind(), message));
+                                    return;
+                                }
+                                queue.lock().unwrap().push_back(queued);
+                            },
+                        }
+                    }
+                });
+            }
+
+            let mut object_hasher = Sha256::new();
+            io::copy(&mut File::open(source)?, &mut object_hasher)?;
+            return Ok::<BlockHash, io::Error>(object_hasher.finalize().into());
+        })?;
+        if let Some(error) = failure.into_inner().unwrap() {
+            return Err(error);
+        }
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|x| x.block_ids.start);
+        let mut manifest = Manifest {
+            object_id,
+            data_size,
+            config: self.config,
+            block_info_vec: Vec::new(),
+            block_hashes: Vec::new(),
+            block_overheads: Vec::new(),
+            envelope: None,
+        };
+        let mut shards: Vec<(Range<u32>, PathBuf)> = Vec::with_capacity(results.len());
+        for result in results {
+            if result.block_ids.start as usize != manifest.get_block_count() || result.block_info_vec.len() != result.block_ids.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("worker returned the wrong blocks for {:?}", result.block_ids)));
+            }
+            manifest.block_info_vec.extend(result.block_info_vec);
+            manifest.block_hashes.extend(result.block_hashes);
+            shards.push((result.block_ids, result.shard));
+        }
+        manifest.block_overheads = vec![0; manifest.get_block_count()];
+        return Ok(FarmResult { manifest, shards });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::RaptorQEncoder;
+    use crate::codec::shard::read_shard;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// A worker that fails its first few jobs, as one losing its connection would.
+    struct FlakyWorker {
+        failures_left: AtomicU32,
+    }
+
+    impl EncodeWorker for FlakyWorker {
+        fn run_job(&self, job: &EncodeJob) -> io::Result<EncodeJobResult> {
+            if self.failures_left.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| x.checked_sub(1)).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "worker went away"));
+            }
+            return encode_job(job, &PlanCache::new());
+        }
+    }
+
+    #[test]
+    fn test_farm_encode() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-farm-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(100 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+        let config = EncoderConfig::new(1280);
+
+        let plan_cache = Arc::new(PlanCache::new());
+        let workers: Vec<Arc<dyn EncodeWorker>> = vec![
+            Arc::new(FlakyWorker { failures_left: AtomicU32::new(1) }),
+            Arc::new(LocalWorker::new(plan_cache.clone())),
+        ];
+        let coordinator = Coordinator::new(workers, config);
+        let jobs = coordinator.plan_jobs(&root.join("object"), data.len() as u64, &root);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].plan_hints, vec![config.get_block_layout(data.len()).1 as u16]);
+
+        let result = coordinator.run(&root.join("object"), &root).unwrap();
+        let whole = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        assert_eq!(result.manifest, Manifest::new(&whole));
+        assert_eq!(result.shards, vec![(0..1, root.join("blocks-00000000.shard"))]);
+        let symbols = read_shard(File::open(&result.shards[0].1).unwrap()).unwrap();
+        assert_eq!(symbols.len(), whole.generate_encoded_blocks().len());
+
+        let workers: Vec<Arc<dyn EncodeWorker>> = vec![Arc::new(FlakyWorker { failures_left: AtomicU32::new(MAX_JOB_ATTEMPTS) })];
+        assert_eq!(Coordinator::new(workers, config).run(&root.join("object"), &root).unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
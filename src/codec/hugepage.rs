@@ -0,0 +1,222 @@
+//! Byte buffers backed by huge pages, for block data and decoder scratch space. RaptorQ works over whole blocks of
+//! up to tens of megabytes at scattered offsets, and with 4K pages that takes thousands of TLB entries per block.
+//! A PageBuffer asks for explicit huge pages (MAP_HUGETLB) first, which need pages reserved by the administrator,
+//! then for transparent huge pages (madvise MADV_HUGEPAGE), and falls back to the heap when neither is available,
+//! e.g. off Linux, without the huge_pages feature, or for buffers smaller than a huge page.
+
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+/// Size of the huge pages asked for, the default on x86-64 and aarch64.
+pub const HUGE_PAGE_SIZE: usize = 2 << 20;
+
+/// What a PageBuffer's memory came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PageBacking {
+    /// Explicit huge pages, from the pool reserved through vm.nr_hugepages.
+    HugeTlb,
+    /// Anonymous memory the kernel was advised to back with transparent huge pages, which it does as it can.
+    Transparent,
+    /// The heap, in regular pages.
+    Heap,
+}
+
+enum Memory {
+    Heap(Vec<u8>),
+    /// An anonymous mapping of mapped_len bytes, a whole number of huge pages.
+    #[cfg_attr(not(all(target_os = "linux", feature = "huge_pages")), allow(dead_code))]
+    Mapped { ptr: NonNull<u8>, mapped_len: usize, backing: PageBacking },
+}
+
+/// A byte buffer in huge pages where available, see the module documentation. Derefs to its bytes like a Vec.
+pub struct PageBuffer {
+    memory: Memory,
+    len: usize,
+}
+
+// PageBuffer owns its mapping exclusively, like Vec<u8>.
+unsafe impl Send for PageBuffer {}
+unsafe impl Sync for PageBuffer {}
+
+impl PageBuffer {
+    /// Allocates len zeroed bytes, in huge pages if huge_pages is set and the system has them, otherwise on the heap.
+    pub fn zeroed(len: usize, huge_pages: bool) -> PageBuffer {
+        if huge_pages && len >= HUGE_PAGE_SIZE {
+            if let Some(memory) = PageBuffer::map(len) {
+                return PageBuffer { memory, len };
+            }
+        }
+        return PageBuffer { memory: Memory::Heap(vec![0; len]), len };
+    }
+
+    /// Copies data into a new buffer, allocated as zeroed does.
+    pub fn copy_from(data: &[u8], huge_pages: bool) -> PageBuffer {
+        if !huge_pages {
+            return PageBuffer::from(data.to_vec());
+        }
+        let mut buffer = PageBuffer::zeroed(data.len(), true);
+        buffer.copy_from_slice(data);
+        return buffer;
+    }
+
+    #[cfg(all(target_os = "linux", feature = "huge_pages"))]
+    fn map(len: usize) -> Option<Memory> {
+        let mapped_len = len.div_ceil(HUGE_PAGE_SIZE) * HUGE_PAGE_SIZE;
+        let protection = libc::PROT_READ | libc::PROT_WRITE;
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), mapped_len, protection, flags | libc::MAP_HUGETLB, -1, 0) };
+        if ptr != libc::MAP_FAILED {
+            return Some(Memory::Mapped { ptr: NonNull::new(ptr as *mut u8)?, mapped_len, backing: PageBacking::HugeTlb });
+        }
+
+        // no huge pages reserved, try transparent ones
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), mapped_len, protection, flags, -1, 0) };
+        if ptr == libc::MAP_FAILED {
+            return None;
+        }
+        if unsafe { libc::madvise(ptr, mapped_len, libc::MADV_HUGEPAGE) } != 0 {
+            // transparent huge pages are disabled, the heap does as well
+            unsafe { libc::munmap(ptr, mapped_len) };
+            return None;
+        }
+        return Some(Memory::Mapped { ptr: NonNull::new(ptr as *mut u8)?, mapped_len, backing: PageBacking::Transparent });
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "huge_pages")))]
+    fn map(_len: usize) -> Option<Memory> {
+        return None;
+    }
+
+    /// Gets what the buffer's memory came from.
+    pub fn get_backing(&self) -> PageBacking {
+        match &self.memory {
+            Memory::Heap(_) => return PageBacking::Heap,
+            Memory::Mapped { backing, .. } => return *backing,
+        }
+    }
+
+    /// Shortens the buffer to len bytes, keeping its memory. Does nothing if it is not longer.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = std::cmp::min(self.len, len);
+        if let Memory::Heap(data) = &mut self.memory {
+            data.truncate(len);
+        }
+    }
+
+    /// Gets the bytes held, including those past the end.
+    pub fn capacity(&self) -> usize {
+        match &self.memory {
+            Memory::Heap(data) => return data.capacity(),
+            Memory::Mapped { mapped_len, .. } => return *mapped_len,
+        }
+    }
+
+    /// Turns the buffer into a Vec, copying it unless it is on the heap.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        if let Memory::Heap(data) = &mut self.memory {
+            return std::mem::take(data);
+        }
+        return self.to_vec();
+    }
+}
+
+impl From<Vec<u8>> for PageBuffer {
+    /// Takes data as it is, on the heap.
+    fn from(data: Vec<u8>) -> PageBuffer {
+        let len = data.len();
+        return PageBuffer { memory: Memory::Heap(data), len };
+    }
+}
+
+impl Clone for PageBuffer {
+    /// Copies into the same kind of memory, as far as the system still has it.
+    fn clone(&self) -> PageBuffer {
+        return PageBuffer::copy_from(self, self.get_backing() != PageBacking::Heap);
+    }
+}
+
+impl Deref for PageBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.memory {
+            Memory::Heap(data) => return data,
+            Memory::Mapped { ptr, .. } => return unsafe { std::slice::from_raw_parts(ptr.as_ptr(), self.len) },
+        }
+    }
+}
+
+impl DerefMut for PageBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.memory {
+            Memory::Heap(data) => return data,
+            Memory::Mapped { ptr, .. } => return unsafe { std::slice::from_raw_parts_mut(ptr.as_ptr(), self.len) },
+        }
+    }
+}
+
+impl Drop for PageBuffer {
+    fn drop(&mut self) {
+        #[cfg(all(target_os = "linux", feature = "huge_pages"))]
+        if let Memory::Mapped { ptr, mapped_len, .. } = &self.memory {
+            unsafe { libc::munmap(ptr.as_ptr() as *mut libc::c_void, *mapped_len) };
+        }
+    }
+}
+
+impl std::fmt::Debug for PageBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return f.debug_struct("PageBuffer")
+            .field("len", &self.len)
+            .field("backing", &self.get_backing())
+            .finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decoder::RaptorQDecoder;
+    use crate::codec::encoder::{BlockInfo, EncodedBlock};
+    use raptorq::{EncodingPacket, ObjectTransmissionInformation, PayloadId};
+
+    #[test]
+    fn test_page_buffer() {
+        // too small for huge pages
+        let buffer = PageBuffer::zeroed(4096, true);
+        assert_eq!((buffer.len(), buffer.get_backing()), (4096, PageBacking::Heap));
+
+        // whatever the system has, the buffer behaves the same
+        let len = HUGE_PAGE_SIZE + 1000;
+        let mut buffer = PageBuffer::zeroed(len, true);
+        assert_eq!(buffer.len(), len);
+        assert!(buffer.capacity() >= len && buffer.iter().all(|x| *x == 0));
+        for (index, byte) in buffer.iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        let copy = buffer.clone();
+        assert_eq!(copy.get_backing() == PageBacking::Heap, buffer.get_backing() == PageBacking::Heap);
+        buffer.truncate(10);
+        assert_eq!(&buffer[..], &(0..10).collect::<Vec<u8>>()[..]);
+        assert_eq!(copy.len(), len);
+        assert_eq!(copy.into_vec().last(), Some(&((len - 1) as u8)));
+
+        let buffer = PageBuffer::zeroed(len, false);
+        assert_eq!(buffer.get_backing(), PageBacking::Heap);
+        let buffer = PageBuffer::from(vec![1, 2, 3]);
+        assert_eq!((buffer.get_backing(), buffer.into_vec()), (PageBacking::Heap, vec![1, 2, 3]));
+
+        // source symbols reassembled in huge pages, as decoders do before any repair symbol arrives
+        let data: Vec<u8> = (0..(1280 * 1700)).map(|x| (x * 7) as u8).collect();
+        let config = ObjectTransmissionInformation::new(data.len() as u64, 1280, 1, 1, 8);
+        let block_info = BlockInfo { payload_size: data.len(), padded_size: data.len(), config, block_id: 0 };
+        let blocks: Vec<EncodedBlock> = data.chunks(1280).enumerate()
+            .map(|(esi, x)| EncodedBlock { block_id: 0, data: EncodingPacket::new(PayloadId::new(0, esi as u32), x.to_vec()) })
+            .collect();
+        let mut decoder = RaptorQDecoder::new(vec![block_info]).unwrap();
+        decoder.set_huge_pages(true);
+        assert!(decoder.consume(blocks).unwrap());
+        assert_eq!(decoder.get_result().unwrap(), data);
+    }
+}
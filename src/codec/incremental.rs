@@ -0,0 +1,136 @@
+//! Encoding an object read from a stream, e.g. stdin at the end of a pipeline, one block at a time. Blocks are the
+//! same as RaptorQEncoder makes of the whole object, so only a block is held at a time and its symbols can go out
+//! before the rest of the object is read. The manifest is complete once the stream ends.
+
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+
+use super::encoder::{BlockEncoder, BlockInfo, EncoderConfig};
+use super::manifest::{BlockHash, Manifest, MANIFEST_BLOCK_ID_BASE};
+
+/// Reads an object from reader and yields an encoder for each block as it fills up, see the module documentation.
+pub struct IncrementalEncoder<R: Read> {
+    reader: R,
+    config: EncoderConfig,
+    object_hasher: Sha256,
+    data_size: u64,
+    block_info_vec: Vec<BlockInfo>,
+    block_hashes: Vec<BlockHash>,
+    /// Set once reader reached its end or failed.
+    done: bool,
+    failed: bool,
+}
+
+impl<R: Read> IncrementalEncoder<R> {
+    /// Creates an IncrementalEncoder, failing with ErrorKind::InvalidInput if the config is invalid.
+    pub fn new(reader: R, config: EncoderConfig) -> io::Result<IncrementalEncoder<R>> {
+        if let Err(error) = config.validate() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid config: {:?}", error)));
+        }
+
+        return Ok(IncrementalEncoder {
+            reader,
+            config,
+            object_hasher: Sha256::new(),
+            data_size: 0,
+            block_info_vec: Vec::new(),
+            block_hashes: Vec::new(),
+            done: false,
+            failed: false,
+        });
+    }
+
+    /// Reads the next block, returning None at the end of the object.
+    fn next_block(&mut self) -> io::Result<Option<BlockEncoder>> {
+        let block_size = self.config.get_block_size();
+        let mut block: Vec<u8> = Vec::new();
+        (&mut self.reader).take(block_size as u64).read_to_end(&mut block)?;
+        if block.len() < block_size {
+            self.done = true;
+        }
+        if block.is_empty() {
+            return Ok(None);
+        }
+
+        let block_id = self.block_info_vec.len() as u32;
+        if block_id >= MANIFEST_BLOCK_ID_BASE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "object has too many blocks"));
+        }
+        self.object_hasher.update(&block);
+        self.data_size += block.len() as u64;
+        self.block_hashes.push(Sha256::digest(&block).into());
+        let block_encoder = match BlockEncoder::with_config(block_id, self.config, block) {
+            Ok(block_encoder) => block_encoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("failed to create encoder: {:?}", error))),
+        };
+        self.block_info_vec.push(block_encoder.get_block_info());
+        return Ok(Some(block_encoder));
+    }
+
+    /// Gets the manifest of the object once every block was yielded, or None before.
+    pub fn get_manifest(&self) -> Option<Manifest> {
+        if !self.done || self.failed {
+            return None;
+        }
+
+        return Some(Manifest {
+            object_id: self.object_hasher.clone().finalize().into(),
+            data_size: self.data_size,
+            config: self.config,
+            block_info_vec: self.block_info_vec.clone(),
+            block_hashes: self.block_hashes.clone(),
+            block_overheads: vec![0; self.block_hashes.len()],
+            envelope: None,
+        });
+    }
+}
+
+impl<R: Read> Iterator for IncrementalEncoder<R> {
+    type Item = io::Result<BlockEncoder>;
+
+    /// Yields the encoder of the next block. Stops after an error, and get_manifest stays None then.
+    fn next(&mut self) -> Option<io::Result<BlockEncoder>> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_block() {
+            Ok(block_encoder) => return block_encoder.map(Ok),
+            Err(error) => {
+                self.done = true;
+                self.failed = true;
+                return Some(Err(error));
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::RaptorQEncoder;
+    use std::io::IoSlice;
+
+    #[test]
+    fn test_incremental_encoder() {
+        let data = gen_data(100 * 1000);
+        let config = EncoderConfig::new(1280);
+        let mut encoder = IncrementalEncoder::new(&data[..], config).unwrap();
+        assert_eq!(encoder.get_manifest(), None);
+
+        let block_encoders: Vec<BlockEncoder> = encoder.by_ref().collect::<io::Result<Vec<BlockEncoder>>>().unwrap();
+        assert_eq!(block_encoders.len(), 1);
+        assert_eq!(block_encoders[0].get_payload(), &data[..]);
+        let whole = match RaptorQEncoder::with_config(config, &[IoSlice::new(&data)]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        assert_eq!(encoder.get_manifest(), Some(Manifest::new(&whole)));
+
+        let mut empty = IncrementalEncoder::new(&[][..], config).unwrap();
+        assert!(empty.next().is_none());
+        assert_eq!(empty.get_manifest().map(|x| (x.data_size, x.get_block_count())), Some((0, 0)));
+        assert!(IncrementalEncoder::new(&data[..], EncoderConfig::new(100)).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use super::decoder::{RaptorQDecoder, RaptorQDecoderError};
+use super::encoder::EncodedBlock;
+
+/// A RaptorQDecoder fed by an internal ingestion thread. Any number of threads can push encoded blocks
+/// through cloned senders while decoding proceeds.
+pub struct IngestingDecoder {
+    /// Sender handed out to receive threads.
+    sender: Sender<EncodedBlock>,
+    /// Set by the ingestion thread once every block is decoded.
+    decoded: Arc<AtomicBool>,
+    /// Ingestion thread, returns the decoder once all senders are dropped.
+    handle: JoinHandle<Result<RaptorQDecoder, RaptorQDecoderError>>,
+}
+
+impl IngestingDecoder {
+    /// Moves the decoder onto an ingestion thread.
+    pub fn new(decoder: RaptorQDecoder) -> IngestingDecoder {
+        let (sender, receiver) = channel();
+        let decoded = Arc::new(AtomicBool::new(decoder.is_decoded()));
+        let thread_decoded = decoded.clone();
+
+        let handle = std::thread::spawn(move || {
+            return IngestingDecoder::ingest(decoder, receiver, thread_decoded);
+        });
+
+        return IngestingDecoder {
+            sender,
+            decoded,
+            handle,
+        };
+    }
+
+    fn ingest(mut decoder: RaptorQDecoder, receiver: Receiver<EncodedBlock>, decoded: Arc<AtomicBool>) -> Result<RaptorQDecoder, RaptorQDecoderError> {
+        let num_blocks = decoder.get_block_info_vec().len();
+
+        // Block until a packet arrives, then drain whatever else is queued so decode attempts are batched.
+        while let Ok(block) = receiver.recv() {
+            let mut blocks: Vec<EncodedBlock> = vec![block];
+            blocks.extend(receiver.try_iter());
+
+            // Packets for blocks we do not know about are dropped rather than failing the whole transfer.
+            blocks.retain(|x| (x.block_id as usize) < num_blocks);
+
+            if decoder.consume(blocks)? {
+                decoded.store(true, Ordering::Release);
+            }
+        }
+
+        return Ok(decoder);
+    }
+
+    /// Gets a sender for pushing encoded blocks to the decoder.
+    pub fn sender(&self) -> Sender<EncodedBlock> {
+        return self.sender.clone();
+    }
+
+    /// Returns true once every block is decoded.
+    pub fn is_decoded(&self) -> bool {
+        return self.decoded.load(Ordering::Acquire);
+    }
+
+    /// Stops ingestion and returns the decoder. Blocks until every sender handed out is dropped.
+    pub fn finish(self) -> Result<RaptorQDecoder, RaptorQDecoderError> {
+        drop(self.sender);
+        match self.handle.join() {
+            Ok(result) => return result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::encoder::*;
+
+    #[test]
+    fn test_ingesting_decoder_multiple_senders() {
+        let packet_size: u16 = 1280;
+        let data_size: usize = 128 * 1024;
+        let data = gen_data(data_size);
+
+        let encoder = match RaptorQEncoder::new(packet_size, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+
+        let decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        let ingesting = IngestingDecoder::new(decoder);
+
+        // pretend we have three sockets, each receiving a different stream
+        let handles: Vec<std::thread::JoinHandle<()>> = (0..3).map(|_| {
+            let blocks = encoder.generate_encoded_blocks();
+            let sender = ingesting.sender();
+            std::thread::spawn(move || {
+                for block in blocks {
+                    sender.send(block).unwrap();
+                }
+            })
+        }).collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let decoder = match ingesting.finish() {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to decode data, err {:?}", error),
+        };
+
+        assert_eq!(decoder.get_result(), Some(data));
+    }
+}
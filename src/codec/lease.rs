@@ -0,0 +1,282 @@
+//! Leases of repair symbols for partial seeding. A coordinator plans what each peer of a swarm seeds by granting it
+//! leases, each over a range of repair symbol ids of a block and until an expiry. Ranges granted at the same time
+//! never overlap, so peers seeding in parallel never send the same symbol. Leases can be renewed, and revoked to take
+//! capacity back; expired leases free their symbols for new grants. Peers generate symbols with a LeasedProducer,
+//! which only generates what their unexpired leases cover.
+//!
+//! Expiries are wall clock times, as leases travel between machines, so peers should keep their clocks in sync and
+//! coordinators grant with some margin.
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use raptorq::extended_source_block_symbols;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::{Duration, SystemTime};
+
+use super::consts::RAPTORQ_ENCODING_SYMBOL_ID_MAX;
+use super::encoder::{EncodedBlock, RaptorQEncoder};
+use super::manifest::Manifest;
+use super::producer::SymbolRange;
+
+/// Identifies a lease of a LeaseCoordinator.
+pub type LeaseId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseError {
+    /// Lease was never granted, already revoked, or expired and dropped.
+    UnknownLease,
+    BadBlockId,
+    /// The lease expired, and its symbols may have been granted to another peer since.
+    Expired,
+    /// No range of the requested size is free in the block.
+    EsiSpaceExhausted,
+    /// No unexpired lease of the block has symbols left to generate.
+    NoLease,
+}
+
+/// Repair symbols of a block a peer may seed until expires_at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct SymbolLease {
+    pub lease_id: LeaseId,
+    pub peer: String,
+    pub block_id: u32,
+    /// Repair symbol ids leased, counted from the block's first repair symbol as in SymbolRange.
+    pub esis: Range<u32>,
+    pub expires_at: SystemTime,
+}
+
+impl SymbolLease {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        return now >= self.expires_at;
+    }
+}
+
+/// Grants, renews and revokes the leases of the symbols of an object, see the module documentation.
+pub struct LeaseCoordinator {
+    /// Repair symbol ids available, per block.
+    repair_symbol_id_limits: Vec<u32>,
+    leases: BTreeMap<LeaseId, SymbolLease>,
+    next_lease_id: LeaseId,
+}
+
+impl LeaseCoordinator {
+    /// Creates a LeaseCoordinator for the object described by manifest, without leases.
+    pub fn new(manifest: &Manifest) -> LeaseCoordinator {
+        let repair_symbol_id_limits = manifest.block_info_vec.iter().map(|block_info| {
+            let symbol_count = (block_info.padded_size / block_info.config.symbol_size() as usize) as u32;
+            return (RAPTORQ_ENCODING_SYMBOL_ID_MAX - extended_source_block_symbols(symbol_count) as usize) as u32;
+        }).collect();
+        return LeaseCoordinator { repair_symbol_id_limits, leases: BTreeMap::new(), next_lease_id: 0 };
+    }
+
+    /// Grants peer the lowest count repair symbol ids of a block not leased to anyone, until ttl from now. Expired
+    /// leases are dropped first, freeing their symbols.
+    pub fn grant(&mut self, peer: &str, block_id: u32, count: u32, ttl: Duration, now: SystemTime) -> Result<SymbolLease, LeaseError> {
+        let limit = match self.repair_symbol_id_limits.get(block_id as usize) {
+            Some(limit) => *limit,
+            None => return Err(LeaseError::BadBlockId),
+        };
+        self.expire(now);
+
+        let mut taken: Vec<Range<u32>> = self.leases.values().filter(|x| x.block_id == block_id).map(|x| x.esis.clone()).collect();
+        taken.sort_by_key(|x| x.start);
+        let mut start: u32 = 0;
+        for range in taken.iter() {
+            if range.start >= start && range.start - start >= count {
+                break;
+            }
+            start = start.max(range.end);
+        }
+        if limit - start < count {
+            return Err(LeaseError::EsiSpaceExhausted);
+        }
+
+        let lease = SymbolLease {
+            lease_id: self.next_lease_id,
+            peer: peer.to_string(),
+            block_id,
+            esis: start..(start + count),
+            expires_at: now + ttl,
+        };
+        self.next_lease_id += 1;
+        self.leases.insert(lease.lease_id, lease.clone());
+        return Ok(lease);
+    }
+
+    /// Extends a lease to ttl from now. Fails with Expired if it expired already, as its symbols may have been
+    /// granted again; grant a new lease instead.
+    pub fn renew(&mut self, lease_id: LeaseId, ttl: Duration, now: SystemTime) -> Result<SymbolLease, LeaseError> {
+        let lease = match self.leases.get_mut(&lease_id) {
+            Some(lease) => lease,
+            None => return Err(LeaseError::UnknownLease),
+        };
+        if lease.is_expired(now) {
+            return Err(LeaseError::Expired);
+        }
+        lease.expires_at = now + ttl;
+        return Ok(lease.clone());
+    }
+
+    /// Revokes a lease, freeing its symbols for new grants. The peer has to be told to stop, see
+    /// LeasedProducer::revoke.
+    pub fn revoke(&mut self, lease_id: LeaseId) -> Result<SymbolLease, LeaseError> {
+        return self.leases.remove(&lease_id).ok_or(LeaseError::UnknownLease);
+    }
+
+    /// Revokes every lease of a peer, e.g. once it left the swarm, returning them.
+    pub fn revoke_peer(&mut self, peer: &str) -> Vec<SymbolLease> {
+        let lease_ids: Vec<LeaseId> = self.leases.values().filter(|x| x.peer == peer).map(|x| x.lease_id).collect();
+        return lease_ids.iter().filter_map(|x| self.leases.remove(x)).collect();
+    }
+
+    /// Drops the leases expired at now, returning them.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<SymbolLease> {
+        let lease_ids: Vec<LeaseId> = self.leases.values().filter(|x| x.is_expired(now)).map(|x| x.lease_id).collect();
+        return lease_ids.iter().filter_map(|x| self.leases.remove(x)).collect();
+    }
+
+    /// Gets the leases not revoked or dropped, by lease id.
+    pub fn get_leases(&self) -> Vec<SymbolLease> {
+        return self.leases.values().cloned().collect();
+    }
+
+    /// Gets how many symbols each peer is leased that are not expired at now, to plan seeding capacity.
+    pub fn get_peer_capacity(&self, now: SystemTime) -> BTreeMap<String, u64> {
+        let mut capacity: BTreeMap<String, u64> = BTreeMap::new();
+        for lease in self.leases.values().filter(|x| !x.is_expired(now)) {
+            *capacity.entry(lease.peer.clone()).or_default() += lease.esis.len() as u64;
+        }
+        return capacity;
+    }
+}
+
+/// Generates a peer's symbols within its leases, see the module documentation.
+pub struct LeasedProducer {
+    encoder: RaptorQEncoder,
+    /// Leases with how many of their symbols were generated, by lease id.
+    leases: BTreeMap<LeaseId, (SymbolLease, u32)>,
+}
+
+impl LeasedProducer {
+    pub fn new(encoder: RaptorQEncoder) -> LeasedProducer {
+        return LeasedProducer { encoder, leases: BTreeMap::new() };
+    }
+
+    /// Adds a lease granted to the peer. Adding a renewed lease again extends it, keeping what was generated of it.
+    /// Fails with BadBlockId if the encoder has no such block.
+    pub fn add_lease(&mut self, lease: SymbolLease) -> Result<(), LeaseError> {
+        if lease.block_id as usize >= self.encoder.get_block_encoders().len() {
+            return Err(LeaseError::BadBlockId);
+        }
+        let generated = self.leases.get(&lease.lease_id).map(|x| x.1).unwrap_or(0);
+        self.leases.insert(lease.lease_id, (lease, generated));
+        return Ok(());
+    }
+
+    /// Stops generating the symbols of a lease the coordinator revoked.
+    pub fn revoke(&mut self, lease_id: LeaseId) -> Result<(), LeaseError> {
+        return self.leases.remove(&lease_id).map(|_| ()).ok_or(LeaseError::UnknownLease);
+    }
+
+    /// Gets how many symbols of a block the unexpired leases still cover.
+    pub fn get_remaining_symbols(&self, block_id: u32, now: SystemTime) -> u64 {
+        return self.leases.values()
+            .filter(|(lease, _)| lease.block_id == block_id && !lease.is_expired(now))
+            .map(|(lease, generated)| (lease.esis.len() as u32 - generated) as u64)
+            .sum();
+    }
+
+    /// Generates up to count symbols of a block from the unexpired leases of it, oldest lease first, dropping
+    /// expired and used up leases. Fails with NoLease if there is nothing left to generate.
+    pub fn next_block_symbols(&mut self, block_id: u32, count: usize, now: SystemTime) -> Result<Vec<EncodedBlock>, LeaseError> {
+        if block_id as usize >= self.encoder.get_block_encoders().len() {
+            return Err(LeaseError::BadBlockId);
+        }
+        self.leases.retain(|_, (lease, generated)| !lease.is_expired(now) && *generated < lease.esis.len() as u32);
+
+        let mut symbols: Vec<EncodedBlock> = Vec::with_capacity(count);
+        for (lease, generated) in self.leases.values_mut().filter(|(lease, _)| lease.block_id == block_id) {
+            let take = (count - symbols.len()).min((lease.esis.len() as u32 - *generated) as usize);
+            let range = SymbolRange { block_id, start: lease.esis.start + *generated, count: take };
+            symbols.append(&mut range.generate(&self.encoder));
+            *generated += take as u32;
+            if symbols.len() == count {
+                break;
+            }
+        }
+        if symbols.is_empty() && count > 0 {
+            return Err(LeaseError::NoLease);
+        }
+        return Ok(symbols);
+    }
+
+    /// Gets the encoder symbols are generated with.
+    pub fn get_encoder(&self) -> &RaptorQEncoder {
+        return &self.encoder;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::decoder::RaptorQDecoder;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_symbol_leases() {
+        let data = gen_data(100 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let manifest = Manifest::new(&encoder);
+        let symbol_count = encoder.get_block_encoders()[0].get_symbol_count() as u32;
+        let mut coordinator = LeaseCoordinator::new(&manifest);
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(60);
+
+        // two peers split the block's symbols between them
+        let a = coordinator.grant("a", 0, symbol_count / 2 + 2, ttl, now).unwrap();
+        let b = coordinator.grant("b", 0, symbol_count / 2 + 2, ttl, now).unwrap();
+        assert_eq!(b.esis.start, a.esis.end);
+        assert_eq!(coordinator.grant("a", 1, 1, ttl, now), Err(LeaseError::BadBlockId));
+        assert_eq!(coordinator.get_peer_capacity(now)["b"], symbol_count as u64 / 2 + 2);
+
+        let mut decoder = RaptorQDecoder::from_manifest(&manifest).unwrap();
+        let mut seen: HashSet<u32> = HashSet::new();
+        for lease in [a.clone(), b.clone()] {
+            let mut producer = LeasedProducer::new(encoder.clone());
+            producer.add_lease(lease.clone()).unwrap();
+            let symbols = producer.next_block_symbols(0, symbol_count as usize, now).unwrap();
+            assert_eq!(symbols.len(), lease.esis.len());
+            assert!(symbols.iter().all(|x| seen.insert(x.data.payload_id().encoding_symbol_id())));
+            assert_eq!(producer.next_block_symbols(0, 1, now), Err(LeaseError::NoLease));
+            let _ = decoder.consume(symbols);
+        }
+        assert_eq!(decoder.get_result(), Some(data));
+
+        // an expired lease generates nothing and its symbols go to the next grant, a renewed one carries on
+        let mut producer = LeasedProducer::new(encoder.clone());
+        producer.add_lease(a.clone()).unwrap();
+        let later = now + Duration::from_secs(30);
+        let renewed = coordinator.renew(b.lease_id, ttl, later).unwrap();
+        producer.add_lease(renewed).unwrap();
+        let expired = now + ttl;
+        assert_eq!(producer.get_remaining_symbols(0, expired), b.esis.len() as u64);
+        assert_eq!(coordinator.renew(a.lease_id, ttl, expired), Err(LeaseError::Expired));
+        let c = coordinator.grant("c", 0, 4, ttl, expired).unwrap();
+        assert_eq!(c.esis, 0..4);
+        let range = SymbolRange { block_id: 0, start: b.esis.start, count: 4 };
+        assert_eq!(producer.next_block_symbols(0, 4, expired).unwrap(), range.generate(&encoder));
+
+        // revoking takes the capacity back
+        assert_eq!(coordinator.revoke_peer("b").len(), 1);
+        assert_eq!(coordinator.revoke(b.lease_id), Err(LeaseError::UnknownLease));
+        producer.revoke(b.lease_id).unwrap();
+        assert_eq!(producer.next_block_symbols(0, 1, expired), Err(LeaseError::NoLease));
+        assert_eq!(coordinator.get_leases(), vec![c]);
+    }
+}
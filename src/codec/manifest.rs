@@ -0,0 +1,907 @@
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use raptorq::ObjectTransmissionInformation;
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+use super::consts::*;
+use super::decoder::BlockDecoder;
+use super::envelope::{Envelope, ENVELOPE_SECTION, ENVELOPE_VERSION};
+use super::encoder::{BlockEncoder, BlockInfo, BlockRegion, EncodedBlock, EncoderConfig, RaptorQEncoder, RaptorQEncoderError, TailStrategy};
+
+/// First bytes of a manifest file without sections, which readers of version 4 still take.
+pub const MANIFEST_MAGIC: &[u8; 8] = b"RCDNMAN4";
+
+/// First bytes of a manifest file with sections after its block entries, see Manifest::write_to.
+pub const MANIFEST_SECTIONS_MAGIC: &[u8; 8] = b"RCDNMAN5";
+
+/// Oldest manifest version read_from still reads. Version 1 had no tail strategy, versions up to 2 a u32 block count
+/// and versions up to 3 no block overheads.
+const OLDEST_MANIFEST_VERSION: u8 = 1;
+
+/// Tag of the section of a manifest carrying the most source symbols in a block the object was encoded with, as a
+/// u16, see Manifest::write_to. Left out for RAPTORQ_MAX_SYMBOLS_IN_BLOCK.
+pub const BLOCK_SYMBOLS_SECTION: u16 = 2;
+
+pub const BLOCK_SYMBOLS_VERSION: u16 = 1;
+
+/// Block ids from this one up are reserved for manifests sent as blocks, see Manifest::encode_block. The low bits
+/// of the id carry the block's symbol count, so a receiver can decode the manifest from its symbols alone.
+pub const MANIFEST_BLOCK_ID_BASE: u32 = 1 << 31;
+
+/// Offset of the block count in a manifest, after the magic, object id, data size, packet size, alignment and tail
+/// strategy.
+#[cfg(test)]
+const BLOCK_COUNT_OFFSET: usize = 8 + 32 + 8 + 2 + 1 + 1;
+
+/// Size of each block's entry in a manifest: payload size, padded size, serialized OTI, hash and overhead.
+#[cfg(test)]
+const BLOCK_ENTRY_SIZE: usize = 8 + 8 + 12 + 32 + 2;
+
+/// SHA-256 of an object's payload, identifying it independently of how it was encoded.
+pub type ObjectId = [u8; 32];
+
+/// SHA-256 of a block's payload, without padding.
+pub type BlockHash = [u8; 32];
+
+/// Repair overhead for a range of an object's payload, see Manifest::protect.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Protection {
+    /// Byte range of the payload, the end past the payload's end to cover the rest of it.
+    pub range: Range<u64>,
+    pub overhead_percent: u16,
+}
+
+/// Everything a receiver needs to know about an encoded object before it can decode it and verify the result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Manifest {
+    pub object_id: ObjectId,
+    /// Size of the payload, without padding.
+    pub data_size: u64,
+    pub config: EncoderConfig,
+    /// Block infos, ordered by block id.
+    pub block_info_vec: Vec<BlockInfo>,
+    /// Block hashes, ordered by block id.
+    pub block_hashes: Vec<BlockHash>,
+    /// Repair overhead of each block in percent of its source symbols, ordered by block id. Blocks with more
+    /// overhead get a larger share of the repair symbols sent, see SymbolProducer::set_block_overheads.
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub block_overheads: Vec<u16>,
+    /// How the payload is encrypted, if it is, see codec::envelope.
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub envelope: Option<Envelope>,
+}
+
+impl Manifest {
+    /// Describes the object encoded by encoder, hashing its payload.
+    pub fn new(encoder: &RaptorQEncoder) -> Manifest {
+        let mut object_hasher = Sha256::new();
+        let mut block_hashes: Vec<BlockHash> = Vec::with_capacity(encoder.get_block_encoders().len());
+        for block_encoder in encoder.get_block_encoders().iter() {
+            object_hasher.update(block_encoder.get_payload());
+            block_hashes.push(Sha256::digest(block_encoder.get_payload()).into());
+        }
+
+        return Manifest {
+            object_id: object_hasher.finalize().into(),
+            data_size: encoder.get_data_size() as u64,
+            config: encoder.get_config(),
+            block_info_vec: encoder.get_block_info_vec(),
+            block_overheads: vec![0; block_hashes.len()],
+            envelope: None,
+            block_hashes,
+        };
+    }
+
+    /// Grows the object with the payload data has past the data_size bytes the manifest describes, for log-like
+    /// objects, returning the manifest of the grown object and the encoders of the blocks appended. data must start
+    /// with the payload described so far. Appended data starts a new block, so the blocks described so far stay as
+    /// they are and receivers keep what they have of them, see RaptorQDecoder::update_manifest. Appended blocks
+    /// are as large as the config's, which read_from keeps. Fails with PayloadMismatch if data doesn't start with
+    /// the payload, and with Enveloped if the manifest has an envelope, as the payload is then a single ciphertext
+    /// that appended bytes would break.
+    pub fn append(&self, data: &[u8]) -> Result<(Manifest, Vec<BlockEncoder>), RaptorQEncoderError> {
+        if self.envelope.is_some() {
+            return Err(RaptorQEncoderError::Enveloped);
+        }
+        if (data.len() as u64) < self.data_size || <ObjectId>::from(Sha256::digest(&data[..self.data_size as usize])) != self.object_id {
+            return Err(RaptorQEncoderError::PayloadMismatch);
+        }
+        let mut manifest = self.clone();
+        let mut block_encoders: Vec<BlockEncoder> = Vec::new();
+        for block in data[self.data_size as usize..].chunks(self.config.get_block_size()) {
+            let block_id = manifest.block_info_vec.len() as u32;
+            if block_id >= MANIFEST_BLOCK_ID_BASE {
+                return Err(RaptorQEncoderError::DataSizeTooLarge);
+            }
+            let block_encoder = BlockEncoder::with_config(block_id, self.config, block.to_vec())?;
+            manifest.block_info_vec.push(block_encoder.get_block_info());
+            manifest.block_hashes.push(Sha256::digest(block).into());
+            manifest.block_overheads.push(0);
+            block_encoders.push(block_encoder);
+        }
+
+        manifest.object_id = Sha256::digest(data).into();
+        manifest.data_size = data.len() as u64;
+        return Ok((manifest, block_encoders));
+    }
+
+    /// Returns true if later describes the same object grown by zero or more blocks, i.e. with the same encoding and
+    /// every block of this manifest as its first blocks, as Manifest::append makes.
+    pub fn is_prefix_of(&self, later: &Manifest) -> bool {
+        let block_count = self.get_block_count();
+        return self.config.packet_size == later.config.packet_size
+            && self.config.alignment == later.config.alignment
+            && self.config.tail_strategy == later.config.tail_strategy
+            && later.get_block_count() >= block_count
+            && later.block_info_vec[..block_count] == self.block_info_vec[..]
+            && later.block_hashes[..block_count] == self.block_hashes[..];
+    }
+
+    /// Encodes the manifest as a block of packet_size symbols with a reserved id, see MANIFEST_BLOCK_ID_BASE, so it
+    /// can be sent FEC-protected among the object's symbols over links without a reliable channel, and decoded with
+    /// a ManifestDecoder. Fails with DataSizeTooLarge if the manifest does not fit in a block or can't be written,
+    /// see write_to.
+    pub fn encode_block(&self, packet_size: u16) -> Result<BlockEncoder, RaptorQEncoderError> {
+        let mut data: Vec<u8> = Vec::new();
+        if self.write_to(&mut data).is_err() {
+            return Err(RaptorQEncoderError::DataSizeTooLarge);
+        }
+        let symbol_count = data.len().div_ceil(packet_size as usize);
+        if symbol_count > RAPTORQ_MAX_SYMBOLS_IN_BLOCK {
+            return Err(RaptorQEncoderError::DataSizeTooLarge);
+        }
+
+        // receivers only learn the symbol count, so the padding is part of the payload, and read_from ignores it
+        data.resize(symbol_count * packet_size as usize, 0);
+        return BlockEncoder::new(MANIFEST_BLOCK_ID_BASE | symbol_count as u32, packet_size, data);
+    }
+
+    /// Raises the overhead of every block holding part of a range to at least the range's overhead, e.g. 30% for
+    /// a file's header and 5% for the rest, so the header survives worse loss. Blocks are the unit of protection,
+    /// so the whole block holding the end of the header gets the header's overhead.
+    pub fn protect(&mut self, protection: &Protection) {
+        let regions = BlockRegion::map(&self.block_info_vec);
+        for (region, overhead) in regions.iter().zip(self.block_overheads.iter_mut()) {
+            let (start, end) = (region.byte_offset as u64, (region.byte_offset + region.len) as u64);
+            if start < protection.range.end && protection.range.start < end {
+                *overhead = std::cmp::max(*overhead, protection.overhead_percent);
+            }
+        }
+    }
+
+    /// Gets the number of blocks of the object.
+    pub fn get_block_count(&self) -> usize {
+        return self.block_info_vec.len();
+    }
+
+    /// Gets the size of the payload including padding, i.e. the total size of all source symbols.
+    pub fn get_padded_size(&self) -> u64 {
+        return self.block_info_vec.iter().map(|x| x.padded_size as u64).sum();
+    }
+
+    /// Gets the memory decoding the object in memory takes at the end: every decoded block with its padding, plus
+    /// the object assembled from them.
+    pub fn get_decode_memory(&self) -> u64 {
+        return self.get_padded_size().saturating_add(self.data_size);
+    }
+
+    /// Writes the manifest: magic, object id, data size (u64), packet size (u16), alignment (u8), tail strategy (u8,
+    /// 0 for Pad and 1 for ShrinkSymbols), block count (u64, below MANIFEST_BLOCK_ID_BASE),
+    /// then for each block its payload size (u64), padded size (u64), serialized OTI, hash and overhead (u16). Integers are little endian
+    /// and sizes are u64 whatever the pointer width, so 32 and 64-bit nodes read the same manifest.
+    ///
+    /// A manifest with an envelope, or of an object encoded with fewer source symbols per block than RaptorQ
+    /// allows, is written as version 5, followed by a section count (u16) and each section's tag (u16), version
+    /// (u16), length (u32) and bytes. Others are written as version 4, without sections.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut sections: Vec<(u16, u16, Vec<u8>)> = Vec::new();
+        if self.config.max_block_symbols as usize != RAPTORQ_MAX_SYMBOLS_IN_BLOCK {
+            sections.push((BLOCK_SYMBOLS_SECTION, BLOCK_SYMBOLS_VERSION, self.config.max_block_symbols.to_le_bytes().to_vec()));
+        }
+        if let Some(envelope) = &self.envelope {
+            let mut bytes: Vec<u8> = Vec::new();
+            envelope.write_to(&mut bytes)?;
+            sections.push((ENVELOPE_SECTION, ENVELOPE_VERSION, bytes));
+        }
+
+        writer.write_all(if sections.is_empty() { MANIFEST_MAGIC } else { MANIFEST_SECTIONS_MAGIC })?;
+        writer.write_all(&self.object_id)?;
+        writer.write_all(&self.data_size.to_le_bytes())?;
+        writer.write_all(&self.config.packet_size.to_le_bytes())?;
+        writer.write_all(&[self.config.alignment])?;
+        writer.write_all(&[match self.config.tail_strategy {
+            TailStrategy::Pad => 0,
+            TailStrategy::ShrinkSymbols => 1,
+        }])?;
+        writer.write_all(&(self.block_info_vec.len() as u64).to_le_bytes())?;
+        for (block_id, (block_info, block_hash)) in self.block_info_vec.iter().zip(self.block_hashes.iter()).enumerate() {
+            writer.write_all(&(block_info.payload_size as u64).to_le_bytes())?;
+            writer.write_all(&(block_info.padded_size as u64).to_le_bytes())?;
+            writer.write_all(&block_info.config.serialize())?;
+            writer.write_all(block_hash)?;
+            writer.write_all(&self.block_overheads.get(block_id).copied().unwrap_or(0).to_le_bytes())?;
+        }
+        if !sections.is_empty() {
+            writer.write_all(&(sections.len() as u16).to_le_bytes())?;
+            for (tag, version, bytes) in sections.iter() {
+                writer.write_all(&tag.to_le_bytes())?;
+                writer.write_all(&version.to_le_bytes())?;
+                writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+                writer.write_all(bytes)?;
+            }
+        }
+
+        return writer.flush();
+    }
+
+    /// Reads a manifest written by write_to, or by an older version of it, see get_manifest_version. Fails with
+    /// ErrorKind::InvalidData if it is not a manifest, if its block sizes don't add up, or if it describes blocks
+    /// larger than this platform can address or MANIFEST_BLOCK_ID_BASE blocks or more, the block ids from there up
+    /// being reserved for manifests. The block count is written as u64 all the same. Sections of unknown tags are
+    /// skipped, but an envelope of an unknown version fails, as the payload could not be decrypted.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Manifest> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad manifest: {}", reason));
+
+        let magic: [u8; 8] = read_array(&mut reader)?;
+        let version = match get_manifest_version(&magic) {
+            Some(version) => version,
+            None => return Err(invalid("bad magic")),
+        };
+
+        let mut object_id: ObjectId = [0; 32];
+        reader.read_exact(&mut object_id)?;
+        let data_size = u64::from_le_bytes(read_array(&mut reader)?);
+        let packet_size = u16::from_le_bytes(read_array(&mut reader)?);
+        let [alignment] = read_array(&mut reader)?;
+        let tail_strategy = match version {
+            1 => TailStrategy::Pad,
+            _ => match read_array(&mut reader)? {
+                [0u8] => TailStrategy::Pad,
+                [1u8] => TailStrategy::ShrinkSymbols,
+                _ => return Err(invalid("unknown tail strategy")),
+            },
+        };
+        let block_count = match version {
+            1 | 2 => u32::from_le_bytes(read_array(&mut reader)?) as u64,
+            _ => u64::from_le_bytes(read_array(&mut reader)?),
+        };
+        if block_count >= MANIFEST_BLOCK_ID_BASE as u64 {
+            return Err(invalid("more blocks than block ids"));
+        }
+
+        let mut block_info_vec: Vec<BlockInfo> = Vec::new();
+        let mut block_hashes: Vec<BlockHash> = Vec::new();
+        let mut block_overheads: Vec<u16> = Vec::new();
+        for block_id in 0..block_count {
+            let payload_size = u64::from_le_bytes(read_array(&mut reader)?);
+            let padded_size = u64::from_le_bytes(read_array(&mut reader)?);
+            let (payload_size, padded_size) = match (usize::try_from(payload_size), usize::try_from(padded_size)) {
+                (Ok(payload_size), Ok(padded_size)) if payload_size <= padded_size => (payload_size, padded_size),
+                (Ok(_), Ok(_)) => return Err(invalid("block payload larger than its padded size")),
+                _ => return Err(invalid("block too large for this platform")),
+            };
+            let config = ObjectTransmissionInformation::deserialize(&read_array(&mut reader)?);
+            block_hashes.push(read_array(&mut reader)?);
+            block_overheads.push(match version {
+                1..=3 => 0,
+                _ => u16::from_le_bytes(read_array(&mut reader)?),
+            });
+            block_info_vec.push(BlockInfo {
+                payload_size,
+                padded_size,
+                config,
+                block_id: block_id as u32,
+            });
+        }
+
+        let mut envelope: Option<Envelope> = None;
+        let mut max_block_symbols = RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u16;
+        if version >= 5 {
+            for _ in 0..u16::from_le_bytes(read_array(&mut reader)?) {
+                let tag = u16::from_le_bytes(read_array(&mut reader)?);
+                let section_version = u16::from_le_bytes(read_array(&mut reader)?);
+                let len = u32::from_le_bytes(read_array(&mut reader)?) as u64;
+                let mut section = (&mut reader).take(len);
+                match (tag, section_version) {
+                    (ENVELOPE_SECTION, ENVELOPE_VERSION) => envelope = Some(Envelope::read_from(&mut section)?),
+                    (ENVELOPE_SECTION, _) => return Err(invalid("unknown envelope version")),
+                    (BLOCK_SYMBOLS_SECTION, BLOCK_SYMBOLS_VERSION) => {
+                        max_block_symbols = u16::from_le_bytes(read_array(&mut section)?);
+                        if max_block_symbols == 0 || max_block_symbols as usize > RAPTORQ_MAX_SYMBOLS_IN_BLOCK {
+                            return Err(invalid("bad block symbol limit"));
+                        }
+                    },
+                    _ => (),
+                }
+                io::copy(&mut section, &mut io::sink())?;
+                if section.limit() > 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+
+        let total_size = block_info_vec.iter().try_fold(0u64, |total, x| total.checked_add(x.payload_size as u64));
+        if total_size != Some(data_size) {
+            return Err(invalid("block sizes don't add up to data size"));
+        }
+
+        return Ok(Manifest {
+            object_id,
+            data_size,
+            config: EncoderConfig { packet_size, alignment, tail_strategy, seed: None, huge_pages: false, elide_padding: false, max_block_symbols },
+            block_info_vec,
+            block_hashes,
+            block_overheads,
+            envelope,
+        });
+    }
+}
+
+/// What a receiver is prepared to decode, see validate_manifest. The default allows anything RaptorQ and the
+/// manifest format do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecoderLimits {
+    pub max_data_size: u64,
+    pub max_block_count: u64,
+    pub max_packet_size: u16,
+    /// Most source symbols in a block, which bounds the memory a block decoder takes.
+    pub max_block_symbols: usize,
+    /// Most memory decoding the object may take, see Manifest::get_decode_memory.
+    pub max_memory: u64,
+}
+
+impl Default for DecoderLimits {
+    fn default() -> DecoderLimits {
+        return DecoderLimits {
+            max_data_size: u64::MAX,
+            max_block_count: MANIFEST_BLOCK_ID_BASE as u64,
+            max_packet_size: u16::MAX,
+            max_block_symbols: RAPTORQ_MAX_SYMBOLS_IN_BLOCK,
+            max_memory: u64::MAX,
+        };
+    }
+}
+
+/// Something wrong with a manifest, found by validate_manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestIssue {
+    /// Packet size is below MIN_PACKET_SIZE or above the local limit.
+    PacketSize { packet_size: u16, min: u16, max: u16 },
+    /// Alignment is not a power of two, or the packet size is not a multiple of it.
+    Alignment { alignment: u8, packet_size: u16 },
+    DataSize { data_size: u64, max: u64 },
+    BlockCount { block_count: u64, max: u64 },
+    /// Block hashes or overheads are not one per block info.
+    BlockListLength { block_infos: usize, block_hashes: usize, block_overheads: usize },
+    /// Block infos are not ordered by block id from 0.
+    BlockId { index: usize, block_id: u32 },
+    /// A block info is inconsistent or out of RaptorQ limits.
+    BlockInfo { block_id: u32, reason: &'static str },
+    /// A block has more source symbols than the local limit.
+    BlockSymbols { block_id: u32, symbols: usize, max: usize },
+    /// Block payloads don't add up to the data size.
+    TotalSize { total: u64, data_size: u64 },
+    /// Decoding the object takes more memory than the local limit.
+    Memory { needed: u64, max: u64 },
+}
+
+/// Checks that a manifest describes an object this receiver can decode, before any decoder is allocated or peers
+/// are contacted, returning every issue found rather than the first. Mismatches caught here would otherwise only
+/// surface as decode failures. The format version is checked by Manifest::read_from, which refuses other versions.
+pub fn validate_manifest(manifest: &Manifest, limits: &DecoderLimits) -> Result<(), Vec<ManifestIssue>> {
+    let mut issues: Vec<ManifestIssue> = Vec::new();
+    let config = &manifest.config;
+
+    if config.packet_size < MIN_PACKET_SIZE || config.packet_size > limits.max_packet_size {
+        issues.push(ManifestIssue::PacketSize { packet_size: config.packet_size, min: MIN_PACKET_SIZE, max: limits.max_packet_size });
+    }
+    if !config.alignment.is_power_of_two() || !config.packet_size.is_multiple_of(config.alignment as u16) {
+        issues.push(ManifestIssue::Alignment { alignment: config.alignment, packet_size: config.packet_size });
+    }
+    if manifest.data_size > limits.max_data_size {
+        issues.push(ManifestIssue::DataSize { data_size: manifest.data_size, max: limits.max_data_size });
+    }
+
+    let block_count = manifest.get_block_count();
+    if block_count as u64 > limits.max_block_count {
+        issues.push(ManifestIssue::BlockCount { block_count: block_count as u64, max: limits.max_block_count });
+    }
+    if manifest.block_hashes.len() != block_count || manifest.block_overheads.len() != block_count {
+        issues.push(ManifestIssue::BlockListLength {
+            block_infos: block_count,
+            block_hashes: manifest.block_hashes.len(),
+            block_overheads: manifest.block_overheads.len(),
+        });
+    }
+
+    for (index, block_info) in manifest.block_info_vec.iter().enumerate() {
+        let block_id = block_info.block_id;
+        if block_id as usize != index {
+            issues.push(ManifestIssue::BlockId { index, block_id });
+        }
+        if let Err(reason) = BlockDecoder::check_block_info(block_info) {
+            issues.push(ManifestIssue::BlockInfo { block_id, reason });
+            continue;
+        }
+        if block_info.config.symbol_size() > config.packet_size {
+            issues.push(ManifestIssue::BlockInfo { block_id, reason: "symbols larger than the packet size" });
+        }
+        if block_info.config.symbol_alignment() != config.alignment {
+            issues.push(ManifestIssue::BlockInfo { block_id, reason: "alignment differs from the manifest's" });
+        }
+        let symbols = block_info.padded_size / block_info.config.symbol_size() as usize;
+        if symbols > limits.max_block_symbols {
+            issues.push(ManifestIssue::BlockSymbols { block_id, symbols, max: limits.max_block_symbols });
+        }
+    }
+
+    let total = manifest.block_info_vec.iter().fold(0u64, |total, x| total.saturating_add(x.payload_size as u64));
+    if total != manifest.data_size {
+        issues.push(ManifestIssue::TotalSize { total, data_size: manifest.data_size });
+    }
+    let needed = manifest.get_decode_memory();
+    if needed > limits.max_memory {
+        issues.push(ManifestIssue::Memory { needed, max: limits.max_memory });
+    }
+
+    if issues.is_empty() {
+        return Ok(());
+    }
+    return Err(issues);
+}
+
+/// Whether a block id is reserved for a manifest sent as a block.
+pub fn is_manifest_block(block_id: u32) -> bool {
+    return block_id >= MANIFEST_BLOCK_ID_BASE;
+}
+
+/// Symbols of another manifest block a ManifestDecoder takes in a row before dropping the block it is decoding for
+/// that one, so a stale or forged symbol arriving first doesn't hold off the manifest being sent.
+pub const MANIFEST_SWITCH_SYMBOLS: usize = 4;
+
+/// Decodes a manifest sent as a block by Manifest::encode_block, from whichever of its symbols arrive.
+#[derive(Default)]
+pub struct ManifestDecoder {
+    decoder: Option<BlockDecoder>,
+    /// Symbols of another manifest block received in a row since the last symbol of the block being decoded.
+    pending: Vec<EncodedBlock>,
+    /// Symbols taken for the blocks decoded, see get_accepted.
+    accepted: u64,
+}
+
+impl ManifestDecoder {
+    pub fn new() -> ManifestDecoder {
+        return ManifestDecoder { decoder: None, pending: Vec::new(), accepted: 0 };
+    }
+
+    /// Feeds symbols, returning the manifest once decoded. Symbols of object blocks are ignored. Symbols of manifest
+    /// blocks other than the one being decoded, e.g. of an updated manifest, are set aside, and once
+    /// MANIFEST_SWITCH_SYMBOLS of one block arrive in a row the decoder starts over on that block. Fails with
+    /// ErrorKind::InvalidData if the symbols don't make a valid block or the block is not a manifest.
+    pub fn consume(&mut self, blocks: Vec<EncodedBlock>) -> io::Result<Option<Manifest>> {
+        for block in blocks.into_iter().filter(|x| is_manifest_block(x.block_id)) {
+            let block_id = self.decoder.as_ref().map(|x| x.get_block_info().block_id);
+            let symbols = match block_id {
+                Some(block_id) if block_id != block.block_id => {
+                    if self.pending.first().is_some_and(|x| x.block_id != block.block_id) {
+                        self.pending.clear();
+                    }
+                    self.pending.push(block);
+                    if self.pending.len() < MANIFEST_SWITCH_SYMBOLS {
+                        continue;
+                    }
+                    self.decoder = None;
+                    std::mem::take(&mut self.pending)
+                },
+                _ => {
+                    self.pending.clear();
+                    vec![block]
+                },
+            };
+            if let Some(manifest) = self.feed(symbols)? {
+                return Ok(Some(manifest));
+            }
+        }
+        return Ok(None);
+    }
+
+    /// Gets how many symbols were taken for decoding so far, which doesn't grow while symbols of another manifest
+    /// block are set aside, e.g. to tell a join making progress from one fed stale symbols.
+    pub fn get_accepted(&self) -> u64 {
+        return self.accepted;
+    }
+
+    /// Feeds symbols of one manifest block, starting a decoder for the block if there is none.
+    fn feed(&mut self, symbols: Vec<EncodedBlock>) -> io::Result<Option<Manifest>> {
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, format!("bad manifest block: {}", reason));
+
+        if self.decoder.is_none() {
+            let first = match symbols.first() {
+                None => return Ok(None),
+                Some(first) => first,
+            };
+            let symbol_size = first.data.data().len();
+            if symbol_size == 0 || symbol_size > u16::MAX as usize || symbol_size % ALIGNMENT as usize != 0 {
+                return Err(invalid(format!("bad symbol size {}", symbol_size)));
+            }
+            // checked before building the transmission information, whose constructor asserts on bad sizes
+            let symbol_count = (first.block_id - MANIFEST_BLOCK_ID_BASE) as usize;
+            if !(1..=RAPTORQ_MAX_SYMBOLS_IN_BLOCK).contains(&symbol_count) {
+                return Err(invalid(format!("bad symbol count {}", symbol_count)));
+            }
+            let padded_size = match symbol_count.checked_mul(symbol_size) {
+                None => return Err(invalid("block too large".to_string())),
+                Some(padded_size) => padded_size,
+            };
+            let block_info = BlockInfo {
+                payload_size: padded_size,
+                padded_size,
+                config: ObjectTransmissionInformation::new(padded_size as u64, symbol_size as u16, 1, 1, ALIGNMENT),
+                block_id: first.block_id,
+            };
+            if let Err(reason) = BlockDecoder::check_block_info(&block_info) {
+                return Err(invalid(reason.to_string()));
+            }
+            self.decoder = Some(BlockDecoder::new(block_info).unwrap());
+        }
+
+        let decoder = self.decoder.as_mut().unwrap();
+        self.accepted += symbols.len() as u64;
+        if let Err(error) = decoder.consume(symbols) {
+            return Err(invalid(format!("{:?}", error)));
+        }
+        match decoder.get_result() {
+            None => return Ok(None),
+            Some(data) => return Manifest::read_from(data).map(Some),
+        }
+    }
+}
+
+/// Formats bytes, e.g. an object id, as lowercase hex.
+pub fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|x| format!("{:02x}", x)).collect();
+}
+
+/// Parses an object id formatted by to_hex.
+pub fn parse_object_id(hex: &str) -> Option<ObjectId> {
+    if hex.len() != 64 || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut object_id: ObjectId = [0; 32];
+    for (byte, digits) in object_id.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    return Some(object_id);
+}
+
+/// Gets the version of the manifest starting with magic, or None if it is not a manifest this version reads.
+pub fn get_manifest_version(magic: &[u8]) -> Option<u8> {
+    if magic.len() < MANIFEST_MAGIC.len() || magic[..7] != MANIFEST_MAGIC[..7] {
+        return None;
+    }
+    let version = magic[7].wrapping_sub(b'0');
+    if version < OLDEST_MANIFEST_VERSION || version > MANIFEST_SECTIONS_MAGIC[7] - b'0' {
+        return None;
+    }
+    return Some(version);
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut array = [0u8; N];
+    reader.read_exact(&mut array)?;
+    return Ok(array);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::BlockEncoder;
+    use crate::codec::envelope::{WrappedKey, NONCE_SIZE, WRAPPED_KEY_SIZE};
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let data = gen_data(100 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+
+        let manifest = Manifest::new(&encoder);
+        assert_eq!(manifest.object_id, <ObjectId>::from(Sha256::digest(&data)));
+        assert_eq!(manifest.block_hashes, vec![manifest.object_id]);
+        assert_eq!(manifest.get_padded_size(), 79 * 1280);
+
+        let mut written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut written).unwrap();
+        assert_eq!(Manifest::read_from(&written[..]).unwrap(), manifest);
+
+        assert_eq!(parse_object_id(&to_hex(&manifest.object_id)), Some(manifest.object_id));
+        assert_eq!(parse_object_id("00"), None);
+
+        match Manifest::read_from(&written[1..]) {
+            Ok(_) => panic!("Should have failed to read a manifest without magic"),
+            Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidData),
+        }
+    }
+
+    #[test]
+    fn test_manifest_append() {
+        let mut config = EncoderConfig::new(512);
+        config.max_block_symbols = 20;
+        let data = gen_data(40 * 512 + 100);
+        let encoder = match RaptorQEncoder::with_config(config, &[io::IoSlice::new(&data[..15 * 512])]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let manifest = Manifest::new(&encoder);
+
+        // the partial block stays, and the appended data starts a block of its own
+        let (grown, block_encoders) = manifest.append(&data).unwrap();
+        assert_eq!(block_encoders.iter().map(|x| (x.get_block_info().block_id, x.get_symbol_count())).collect::<Vec<(u32, usize)>>(), vec![(1, 20), (2, 6)]);
+        assert_eq!(grown.object_id, <ObjectId>::from(Sha256::digest(&data)));
+        assert_eq!(grown.data_size, data.len() as u64);
+        assert_eq!(grown.block_overheads.len(), 3);
+        assert!(manifest.is_prefix_of(&grown));
+        assert!(grown.is_prefix_of(&grown));
+        assert!(!grown.is_prefix_of(&manifest));
+
+        // appending nothing changes nothing
+        let (same, block_encoders) = grown.append(&data).unwrap();
+        assert!(block_encoders.is_empty());
+        assert_eq!(same, grown);
+
+        let mut other = grown.clone();
+        other.block_hashes[0][0] ^= 1;
+        assert!(!manifest.is_prefix_of(&other));
+
+        // data not starting with the payload is refused
+        assert_eq!(grown.append(&data[..100]).err(), Some(RaptorQEncoderError::PayloadMismatch));
+        let mut changed = data.clone();
+        changed[0] ^= 1;
+        assert_eq!(manifest.append(&changed).err(), Some(RaptorQEncoderError::PayloadMismatch));
+
+        // a manifest read back appends blocks of the size it was encoded with
+        let mut written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut written).unwrap();
+        let read = Manifest::read_from(&written[..]).unwrap();
+        assert_eq!(read, manifest);
+        assert_eq!(read.append(&data).unwrap().0, grown);
+
+        // an encrypted object would no longer open once grown
+        let mut enveloped = manifest.clone();
+        enveloped.envelope = Some(Envelope { nonce: [0; NONCE_SIZE], wrapped_keys: Vec::new() });
+        assert_eq!(enveloped.append(&data).err(), Some(RaptorQEncoderError::Enveloped));
+    }
+
+    #[test]
+    fn test_manifest_old_versions() {
+        let data = gen_data(100 * 1000);
+        let encoder = match RaptorQEncoder::with_config(EncoderConfig::new(1280), &[io::IoSlice::new(&data)]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let manifest = Manifest::new(&encoder);
+        let mut written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut written).unwrap();
+
+        // rewrite the current layout into the layouts of the earlier versions
+        let entries = &written[(BLOCK_COUNT_OFFSET + 8)..];
+        let block_count = manifest.get_block_count();
+        for version in 1..=3u8 {
+            let mut old: Vec<u8> = format!("RCDNMAN{}", version).into_bytes();
+            old.extend_from_slice(&written[8..(BLOCK_COUNT_OFFSET - 1)]);
+            if version > 1 {
+                old.push(written[BLOCK_COUNT_OFFSET - 1]);
+            }
+            match version {
+                1 | 2 => old.extend_from_slice(&(block_count as u32).to_le_bytes()),
+                _ => old.extend_from_slice(&(block_count as u64).to_le_bytes()),
+            }
+            for entry in entries.chunks(BLOCK_ENTRY_SIZE) {
+                old.extend_from_slice(&entry[..(BLOCK_ENTRY_SIZE - 2)]);
+            }
+
+            assert_eq!(get_manifest_version(&old), Some(version));
+            assert_eq!(Manifest::read_from(&old[..]).unwrap(), manifest);
+        }
+
+        assert_eq!(get_manifest_version(&written), Some(4));
+        assert_eq!(get_manifest_version(b"RCDNMAN0"), None);
+        assert_eq!(get_manifest_version(MANIFEST_SECTIONS_MAGIC), Some(5));
+        assert_eq!(get_manifest_version(b"RCDNMAN6"), None);
+        assert_eq!(get_manifest_version(b"RCDN"), None);
+    }
+
+    #[test]
+    fn test_manifest_sections() {
+        let data = gen_data(100 * 1000);
+        let encoder = match RaptorQEncoder::with_config(EncoderConfig::new(1280), &[io::IoSlice::new(&data)]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let mut manifest = Manifest::new(&encoder);
+        manifest.envelope = Some(Envelope {
+            nonce: [7; 12],
+            wrapped_keys: vec![WrappedKey { recipient: [1; 32], ephemeral: [2; 32], wrapped_key: vec![3; WRAPPED_KEY_SIZE] }],
+        });
+        let mut written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut written).unwrap();
+        assert_eq!(&written[..8], MANIFEST_SECTIONS_MAGIC);
+        assert_eq!(Manifest::read_from(&written[..]).unwrap(), manifest);
+
+        // sections of later tags are skipped, envelopes of later versions are not
+        let sections = BLOCK_COUNT_OFFSET + 8 + manifest.get_block_count() * BLOCK_ENTRY_SIZE;
+        let mut later = written.clone();
+        later[sections..(sections + 2)].copy_from_slice(&2u16.to_le_bytes());
+        later.extend_from_slice(&[9, 0, 1, 0, 3, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(Manifest::read_from(&later[..]).unwrap(), manifest);
+        later[sections + 4] = 2;
+        match Manifest::read_from(&later[..]) {
+            Ok(_) => panic!("Should have failed to read an envelope of an unknown version"),
+            Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidData),
+        }
+
+        // without sections, version 4 readers still take it
+        manifest.envelope = None;
+        let mut written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut written).unwrap();
+        assert_eq!(&written[..8], MANIFEST_MAGIC);
+    }
+
+    #[test]
+    fn test_manifest_fixed_width() {
+        let data = gen_data(100 * 1000);
+        let encoder = match RaptorQEncoder::with_config(EncoderConfig::new(1280), &[io::IoSlice::new(&data[..50 * 1000]), io::IoSlice::new(&data[50 * 1000..])]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let manifest = Manifest::new(&encoder);
+
+        // the layout doesn't depend on the pointer width of the writer
+        let mut written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut written).unwrap();
+        let block_count = manifest.get_block_count();
+        assert_eq!(written.len(), BLOCK_COUNT_OFFSET + 8 + block_count * BLOCK_ENTRY_SIZE);
+        assert_eq!(written[BLOCK_COUNT_OFFSET..(BLOCK_COUNT_OFFSET + 8)], (block_count as u64).to_le_bytes());
+        let payload_size = &written[(BLOCK_COUNT_OFFSET + 8)..(BLOCK_COUNT_OFFSET + 16)];
+        assert_eq!(payload_size, (manifest.block_info_vec[0].payload_size as u64).to_le_bytes());
+
+        // counts past u32 and into the ids reserved for manifests are refused before reading any block
+        for too_many in [1u64 << 33, u32::MAX as u64 + 1, MANIFEST_BLOCK_ID_BASE as u64] {
+            let mut too_many_blocks = written.clone();
+            too_many_blocks[BLOCK_COUNT_OFFSET..(BLOCK_COUNT_OFFSET + 8)].copy_from_slice(&too_many.to_le_bytes());
+            match Manifest::read_from(&too_many_blocks[..]) {
+                Ok(_) => panic!("Should have failed to read a manifest with more blocks than block ids"),
+                Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidData),
+            }
+        }
+
+        // sizes past 4GiB, written by a 64-bit node, are refused on 32-bit nodes and read back exactly on 64-bit ones
+        let mut huge = manifest.clone();
+        let huge_size: u64 = 5 << 30;
+        huge.data_size = huge.data_size - huge.block_info_vec[0].payload_size as u64 + huge_size;
+        let mut written: Vec<u8> = Vec::new();
+        huge.write_to(&mut written).unwrap();
+        let offset = BLOCK_COUNT_OFFSET + 8;
+        written[offset..(offset + 8)].copy_from_slice(&huge_size.to_le_bytes());
+        written[(offset + 8)..(offset + 16)].copy_from_slice(&huge_size.to_le_bytes());
+        match Manifest::read_from(&written[..]) {
+            #[cfg(target_pointer_width = "64")]
+            Ok(read) => {
+                assert_eq!(read.block_info_vec[0].payload_size as u64, huge_size);
+                assert_eq!(read.data_size, huge.data_size);
+            },
+            #[cfg(not(target_pointer_width = "64"))]
+            Ok(_) => panic!("Should have failed to read a block larger than the address space"),
+            #[cfg(target_pointer_width = "64")]
+            Err(error) => panic!("Failed to read manifest, error {}", error),
+            #[cfg(not(target_pointer_width = "64"))]
+            Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidData),
+        }
+    }
+
+    #[test]
+    fn test_manifest_protect() {
+        let data = gen_data(100 * 1000);
+        let block_info_vec: Vec<BlockInfo> = data.chunks(40 * 1000).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, 1280, chunk.to_vec()) {
+                Ok(succ) => succ.get_block_info(),
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let mut manifest = Manifest {
+            object_id: [1; 32],
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(1280),
+            block_hashes: vec![[0; 32]; block_info_vec.len()],
+            block_overheads: vec![0; block_info_vec.len()],
+            envelope: None,
+            block_info_vec,
+        };
+
+        // a header ending inside the second block protects it whole, and the bulk overhead doesn't lower it
+        manifest.protect(&Protection { range: 0..50 * 1000, overhead_percent: 30 });
+        manifest.protect(&Protection { range: 50 * 1000..u64::MAX, overhead_percent: 5 });
+        assert_eq!(manifest.block_overheads, vec![30, 30, 5]);
+
+        let mut written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut written).unwrap();
+        assert_eq!(Manifest::read_from(&written[..]).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_validate_manifest() {
+        let data = gen_data(100 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let manifest = Manifest::new(&encoder);
+        assert_eq!(validate_manifest(&manifest, &DecoderLimits::default()), Ok(()));
+
+        // every issue is reported, not just the first
+        let limits = DecoderLimits { max_data_size: 50 * 1000, max_block_symbols: 64, max_memory: 200 * 1000, ..DecoderLimits::default() };
+        let mut bad = manifest.clone();
+        bad.config.alignment = 3;
+        bad.block_hashes.clear();
+        bad.block_info_vec[0].block_id = 1;
+        let issues = validate_manifest(&bad, &limits).unwrap_err();
+        assert_eq!(issues, vec![
+            ManifestIssue::Alignment { alignment: 3, packet_size: 1280 },
+            ManifestIssue::DataSize { data_size: 100 * 1000, max: 50 * 1000 },
+            ManifestIssue::BlockListLength { block_infos: 1, block_hashes: 0, block_overheads: 1 },
+            ManifestIssue::BlockId { index: 0, block_id: 1 },
+            ManifestIssue::BlockInfo { block_id: 1, reason: "alignment differs from the manifest's" },
+            ManifestIssue::BlockSymbols { block_id: 1, symbols: 79, max: 64 },
+            // 79 padded symbols, then the object again
+            ManifestIssue::Memory { needed: 79 * 1280 + 100 * 1000, max: 200 * 1000 },
+        ]);
+
+        let mut bad = manifest.clone();
+        bad.block_info_vec[0].payload_size = bad.block_info_vec[0].padded_size + 1;
+        let issues = validate_manifest(&bad, &DecoderLimits::default()).unwrap_err();
+        assert_eq!(issues[0], ManifestIssue::BlockInfo { block_id: 0, reason: "payload larger than its padded size" });
+        assert!(matches!(issues[1], ManifestIssue::TotalSize { .. }));
+    }
+
+    #[test]
+    fn test_manifest_block() {
+        let data = gen_data(90 * 1000);
+        let encoders: Vec<BlockEncoder> = data.chunks(1000).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, 1280, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let manifest = Manifest {
+            object_id: Sha256::digest(&data).into(),
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(1280),
+            block_info_vec: encoders.iter().map(|x| x.get_block_info()).collect(),
+            block_hashes: encoders.iter().map(|x| Sha256::digest(x.get_payload()).into()).collect(),
+            block_overheads: vec![0; encoders.len()],
+            envelope: None,
+        };
+
+        // 90 blocks take 5 symbols, lose the source symbols and decode from repair symbols mixed with object symbols
+        let block_encoder = manifest.encode_block(1280).unwrap();
+        assert_eq!(block_encoder.get_block_info().block_id, MANIFEST_BLOCK_ID_BASE | 5);
+        let mut decoder = ManifestDecoder::new();
+        assert_eq!(decoder.consume(encoders[0].generate_source_blocks()).unwrap(), None);
+        assert_eq!(decoder.consume(block_encoder.generate_repair_blocks(0, 3)).unwrap(), None);
+        let mut blocks = block_encoder.generate_repair_blocks(3, 4);
+        blocks.append(&mut encoders[1].generate_source_blocks());
+        assert_eq!(decoder.consume(blocks).unwrap(), Some(manifest.clone()));
+
+        // a manifest's block id can't collide with an object's
+        assert_eq!(validate_manifest(&manifest, &DecoderLimits::default()), Ok(()));
+        assert!(!is_manifest_block(encoders[89].get_block_info().block_id));
+        assert_eq!(manifest.encode_block(64).err(), Some(RaptorQEncoderError::InvalidPacketSize));
+    }
+}
@@ -1,3 +1,31 @@
 pub mod encoder;
+pub mod backend;
+pub mod numa;
 pub mod decoder;
-pub mod consts;
\ No newline at end of file
+pub mod consts;
+pub mod ingest;
+pub mod aligned;
+pub mod hugepage;
+pub mod reader;
+pub mod producer;
+pub mod bundle;
+pub mod plan_cache;
+pub mod manifest;
+pub mod envelope;
+pub mod shard;
+pub mod native;
+pub mod small;
+pub mod stream;
+pub mod incremental;
+pub mod farm;
+pub mod partial;
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod lease;
+pub mod vectors;
+#[cfg(feature = "tokio_support")]
+pub mod async_io;
+#[cfg(feature = "proptest_support")]
+pub mod strategies;
+#[cfg(feature = "backend_bench")]
+pub mod backend_bench;
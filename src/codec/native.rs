@@ -0,0 +1,151 @@
+//! Encoding with raptorq's own multi-source-block Encoder and Decoder, as an alternative to RaptorQEncoder's block
+//! splitting. The object is described by a single ObjectTransmissionInformation (OTI) that carries the source block
+//! and sub-block counts, so any RFC 6330 decoder can decode it. The OTI allows at most 255 source blocks and sizes
+//! blocks by decoder memory rather than the symbol limit, so objects are usually split into more, smaller blocks.
+
+use raptorq::{Decoder, Encoder, EncodingPacket, ObjectTransmissionInformation};
+
+use crate::codec::consts::*;
+use crate::codec::encoder::{EncoderConfig, RaptorQEncoderError};
+
+/// Memory a decoder is expected to have for a source block, raptorq's default.
+pub const DEFAULT_DECODER_MEMORY: u64 = 10 * 1024 * 1024;
+
+/// Source blocks an OTI can describe.
+const MAX_SOURCE_BLOCKS: usize = u8::MAX as usize;
+
+/// Gets the most source symbols raptorq puts in a source block, as it partitions an object for a decoder with
+/// decoder_memory bytes (RFC 6330 section 4.3), or 0 if even the smallest block does not fit.
+fn get_max_block_symbols(packet_size: u16, decoder_memory: u64) -> usize {
+    let alignment = ALIGNMENT as u64;
+    // raptorq tries blocks without sub-blocks first and panics if not even those fit
+    if decoder_memory / (packet_size as u64) < raptorq::extended_source_block_symbols(1) as u64 {
+        return 0;
+    }
+    let max_sub_blocks = packet_size as u64 / (8 * alignment);
+    let sub_symbol_size = (packet_size as u64).div_ceil(alignment * max_sub_blocks);
+    let limit = std::cmp::min(decoder_memory / (alignment * sub_symbol_size), RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u64) as u32;
+
+    // the largest extended block size K' not over the limit
+    let mut symbols = limit;
+    while symbols > 0 && raptorq::extended_source_block_symbols(symbols) > limit {
+        symbols -= 1;
+    }
+    return symbols as usize;
+}
+
+/// Encodes an object with raptorq's Encoder, see the module documentation.
+pub struct NativeEncoder {
+    encoder: Encoder,
+}
+
+impl NativeEncoder {
+    /// Creates a NativeEncoder for decoders with DEFAULT_DECODER_MEMORY, see with_decoder_memory.
+    pub fn new(packet_size: u16, data: &[u8]) -> Result<NativeEncoder, RaptorQEncoderError> {
+        return NativeEncoder::with_decoder_memory(packet_size, DEFAULT_DECODER_MEMORY, data);
+    }
+
+    /// Creates a NativeEncoder whose source blocks a decoder with decoder_memory bytes can decode, more memory
+    /// meaning fewer source blocks. Panics if data is empty, which raptorq cannot partition.
+    pub fn with_decoder_memory(packet_size: u16, decoder_memory: u64, data: &[u8]) -> Result<NativeEncoder, RaptorQEncoderError> {
+        assert!(!data.is_empty(), "cannot encode an empty object");
+        EncoderConfig::new(packet_size).validate()?;
+        // raptorq truncates the source block count to 8 bits, so catch objects needing more before it does
+        let max_block_symbols = get_max_block_symbols(packet_size, decoder_memory);
+        let symbol_count = data.len().div_ceil(packet_size as usize);
+        if max_block_symbols == 0 || symbol_count.div_ceil(max_block_symbols) > MAX_SOURCE_BLOCKS {
+            return Err(RaptorQEncoderError::DataSizeTooLarge);
+        }
+
+        let mut builder = raptorq::EncoderBuilder::new();
+        builder.set_max_packet_size(packet_size);
+        builder.set_decoder_memory_requirement(decoder_memory);
+        return Ok(NativeEncoder { encoder: builder.build(data) });
+    }
+
+    /// Gets the OTI decoders need, see ObjectTransmissionInformation::serialize.
+    pub fn get_config(&self) -> ObjectTransmissionInformation {
+        return self.encoder.get_config();
+    }
+
+    pub fn get_source_block_count(&self) -> usize {
+        return self.encoder.get_block_encoders().len();
+    }
+
+    /// Gets the source symbols of every source block.
+    pub fn get_source_packets(&self) -> Vec<EncodingPacket> {
+        return self.encoder.get_block_encoders().iter().flat_map(|x| x.source_packets()).collect();
+    }
+
+    /// Gets count repair symbols of every source block, starting at repair symbol start.
+    pub fn get_repair_packets(&self, start: u32, count: u32) -> Vec<EncodingPacket> {
+        return self.encoder.get_block_encoders().iter().flat_map(|x| x.repair_packets(start, count)).collect();
+    }
+
+    /// Gets the source symbols and repair_count repair symbols of every source block.
+    pub fn generate_packets(&self, repair_count: u32) -> Vec<EncodingPacket> {
+        return self.encoder.get_encoded_packets(repair_count);
+    }
+}
+
+/// Decodes an object encoded by a NativeEncoder, or any RFC 6330 encoder.
+pub struct NativeDecoder {
+    decoder: Decoder,
+    result: Option<Vec<u8>>,
+}
+
+impl NativeDecoder {
+    pub fn new(config: ObjectTransmissionInformation) -> NativeDecoder {
+        return NativeDecoder {
+            decoder: Decoder::new(config),
+            result: None,
+        };
+    }
+
+    /// Adds packets, returning whether the object is decoded.
+    pub fn consume(&mut self, packets: Vec<EncodingPacket>) -> bool {
+        for packet in packets {
+            if self.result.is_some() {
+                break;
+            }
+            self.result = self.decoder.decode(packet);
+        }
+        return self.result.is_some();
+    }
+
+    /// Gets the object, once consume returned true.
+    pub fn get_result(&self) -> Option<&[u8]> {
+        return self.result.as_deref();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_native_multi_block() {
+        let data: Vec<u8> = (0..(1000 * 1000)).map(|x| (x * 7) as u8).collect();
+        let encoder = match NativeEncoder::with_decoder_memory(1280, 16 * 1024, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let config = encoder.get_config();
+        assert!(config.source_blocks() > 1);
+        assert_eq!(config.source_blocks() as usize, encoder.get_source_block_count());
+
+        // the OTI is all a decoder needs, drop every other source symbol and make it up with repair symbols
+        let mut decoder = NativeDecoder::new(ObjectTransmissionInformation::deserialize(&config.serialize()));
+        let packets: Vec<EncodingPacket> = encoder.get_source_packets().into_iter().step_by(2).collect();
+        assert!(!decoder.consume(packets));
+        let mut start = 0;
+        while !decoder.consume(encoder.get_repair_packets(start, 16)) {
+            start += 16;
+            assert!(start < 1000);
+        }
+        assert_eq!(decoder.get_result().unwrap(), &data[..]);
+
+        assert_eq!(NativeEncoder::new(256, &data).err(), Some(RaptorQEncoderError::InvalidPacketSize));
+        assert_eq!(NativeEncoder::with_decoder_memory(1280, 8 * 1024, &data).err(), Some(RaptorQEncoderError::DataSizeTooLarge));
+    }
+}
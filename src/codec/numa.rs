@@ -0,0 +1,257 @@
+//! Encoding the blocks of a large object on every core of a multi-socket server without memory crossing sockets.
+//! An EncodePlacement spreads blocks over the server's NUMA nodes and encodes each on threads pinned to its node's
+//! CPUs. The pinned thread copies the block in and computes its intermediate symbols, so with Linux's first-touch
+//! policy the block's memory ends up on the node encoding it. Symbols generated later, e.g. by server threads, may
+//! come from any node.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Where the encode threads run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NumaPolicy {
+    /// Threads on any CPU, as the OS schedules them.
+    Off,
+    /// Blocks dealt out to every node in turn, each encoded on its node.
+    Interleave,
+    /// As Interleave, over these nodes only, e.g. to leave the others to serving.
+    Nodes(Vec<usize>),
+}
+
+impl FromStr for NumaPolicy {
+    type Err = String;
+
+    /// Parses off, interleave or nodes:<ids>, e.g. nodes:0,2.
+    fn from_str(policy: &str) -> Result<NumaPolicy, String> {
+        match policy {
+            "off" => return Ok(NumaPolicy::Off),
+            "interleave" => return Ok(NumaPolicy::Interleave),
+            _ => (),
+        }
+        let nodes = policy.strip_prefix("nodes:")
+            .and_then(|x| x.split(',').map(|x| x.parse::<usize>().ok()).collect::<Option<Vec<usize>>>())
+            .filter(|x| !x.is_empty());
+        match nodes {
+            Some(nodes) => return Ok(NumaPolicy::Nodes(nodes)),
+            None => return Err(format!("unknown placement {}, expected off, interleave or nodes:<ids>", policy)),
+        }
+    }
+}
+
+impl fmt::Display for NumaPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NumaPolicy::Off => return write!(f, "off"),
+            NumaPolicy::Interleave => return write!(f, "interleave"),
+            NumaPolicy::Nodes(nodes) => {
+                let nodes: Vec<String> = nodes.iter().map(|x| x.to_string()).collect();
+                return write!(f, "nodes:{}", nodes.join(","));
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumaNode {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// The NUMA nodes of the machine and their CPUs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumaTopology {
+    pub nodes: Vec<NumaNode>,
+}
+
+impl NumaTopology {
+    /// Reads the topology from sysfs on Linux. Elsewhere, or if sysfs has no nodes, the machine counts as a single
+    /// node of every CPU.
+    pub fn detect() -> NumaTopology {
+        let mut nodes: Vec<NumaNode> = Vec::new();
+        if let Ok(dir) = fs::read_dir("/sys/devices/system/node") {
+            for dir_entry in dir.flatten() {
+                let id = match dir_entry.file_name().to_str().and_then(|x| x.strip_prefix("node")).and_then(|x| x.parse::<usize>().ok()) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let cpus = fs::read_to_string(dir_entry.path().join("cpulist")).ok().and_then(|x| parse_cpu_list(&x));
+                // nodes of memory only have no CPUs to encode on
+                if let Some(cpus) = cpus.filter(|x| !x.is_empty()) {
+                    nodes.push(NumaNode { id, cpus });
+                }
+            }
+        }
+        if nodes.is_empty() {
+            let cpu_count = std::thread::available_parallelism().map_or(1, |x| x.get());
+            return NumaTopology { nodes: vec![NumaNode { id: 0, cpus: (0..cpu_count).collect() }] };
+        }
+        nodes.sort_by_key(|x| x.id);
+        return NumaTopology { nodes };
+    }
+
+    pub fn get_node(&self, id: usize) -> Option<&NumaNode> {
+        return self.nodes.iter().find(|x| x.id == id);
+    }
+}
+
+/// Parses a sysfs CPU list, e.g. 0-3,8-11.
+pub fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus: Vec<usize> = Vec::new();
+    for range in list.trim().split(',').filter(|x| !x.is_empty()) {
+        match range.split_once('-') {
+            None => cpus.push(range.parse().ok()?),
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse::<usize>().ok()?),
+        }
+    }
+    return Some(cpus);
+}
+
+/// Pins the calling thread to cpus. Only supported on Linux, with the numa feature.
+pub fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+    #[cfg(all(target_os = "linux", feature = "numa"))]
+    {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for cpu in cpus.iter().filter(|x| **x < libc::CPU_SETSIZE as usize) {
+            unsafe { libc::CPU_SET(*cpu, &mut set) };
+        }
+        if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(());
+    }
+    #[cfg(not(all(target_os = "linux", feature = "numa")))]
+    {
+        let _ = cpus;
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "pinning threads is only supported on Linux, with the numa feature"));
+    }
+}
+
+/// Threads sharing a queue of blocks to encode.
+#[derive(Debug)]
+pub struct EncodeGroup {
+    /// The node the group's threads are pinned to, None if they aren't.
+    pub node: Option<usize>,
+    /// CPUs the group's threads are pinned to, empty if they aren't.
+    pub cpus: Vec<usize>,
+    pub threads: usize,
+    /// Ids of the blocks left to encode.
+    pub blocks: Mutex<VecDeque<u32>>,
+}
+
+/// How the blocks of an object are spread over threads and nodes, see the module documentation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodePlacement {
+    policy: NumaPolicy,
+    /// Threads per node, or in all with NumaPolicy::Off. 0 for one per CPU.
+    threads_per_node: usize,
+    topology: NumaTopology,
+}
+
+impl EncodePlacement {
+    /// Creates a placement on the machine's topology, see NumaTopology::detect.
+    pub fn new(policy: NumaPolicy, threads_per_node: usize) -> io::Result<EncodePlacement> {
+        return EncodePlacement::with_topology(policy, threads_per_node, NumaTopology::detect());
+    }
+
+    /// Creates a placement on topology. Fails if the policy names nodes topology doesn't have.
+    pub fn with_topology(policy: NumaPolicy, threads_per_node: usize, topology: NumaTopology) -> io::Result<EncodePlacement> {
+        if let NumaPolicy::Nodes(nodes) = &policy {
+            if let Some(node) = nodes.iter().find(|x| topology.get_node(**x).is_none()) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no NUMA node {} with CPUs", node)));
+            }
+        }
+        return Ok(EncodePlacement { policy, threads_per_node, topology });
+    }
+
+    pub fn get_policy(&self) -> &NumaPolicy {
+        return &self.policy;
+    }
+
+    pub fn get_topology(&self) -> &NumaTopology {
+        return &self.topology;
+    }
+
+    /// Deals block_count blocks out to groups of threads: one unpinned group with NumaPolicy::Off, otherwise a group
+    /// per node, block b going to the (b mod nodes)th node. Groups have no more threads than blocks.
+    pub fn assign(&self, block_count: usize) -> Vec<EncodeGroup> {
+        let nodes: Vec<&NumaNode> = match &self.policy {
+            NumaPolicy::Off => Vec::new(),
+            NumaPolicy::Interleave => self.topology.nodes.iter().collect(),
+            NumaPolicy::Nodes(ids) => ids.iter().filter_map(|x| self.topology.get_node(*x)).collect(),
+        };
+        let threads = |cpus: usize, blocks: usize| (if self.threads_per_node == 0 { cpus } else { self.threads_per_node }).clamp(1, blocks.max(1));
+
+        if nodes.is_empty() {
+            let cpus: usize = self.topology.nodes.iter().map(|x| x.cpus.len()).sum();
+            return vec![EncodeGroup {
+                node: None,
+                cpus: Vec::new(),
+                threads: threads(cpus, block_count),
+                blocks: Mutex::new((0..block_count as u32).collect()),
+            }];
+        }
+        return nodes.iter().enumerate().map(|(index, node)| {
+            let blocks: VecDeque<u32> = (index..block_count).step_by(nodes.len()).map(|x| x as u32).collect();
+            return EncodeGroup { node: Some(node.id), cpus: node.cpus.clone(), threads: threads(node.cpus.len(), blocks.len()), blocks: Mutex::new(blocks) };
+        }).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::{EncoderConfig, RaptorQEncoder};
+    use std::io::IoSlice;
+
+    #[test]
+    fn test_encode_placement() {
+        assert_eq!(parse_cpu_list("0-3,8-9,12\n"), Some(vec![0, 1, 2, 3, 8, 9, 12]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+        assert_eq!("interleave".parse::<NumaPolicy>(), Ok(NumaPolicy::Interleave));
+        assert_eq!("nodes:0,2".parse::<NumaPolicy>(), Ok(NumaPolicy::Nodes(vec![0, 2])));
+        assert!("nodes:".parse::<NumaPolicy>().is_err());
+        assert_eq!(NumaPolicy::Nodes(vec![0, 2]).to_string(), "nodes:0,2");
+
+        let topology = NumaTopology { nodes: vec![
+            NumaNode { id: 0, cpus: vec![0, 1, 2, 3] },
+            NumaNode { id: 1, cpus: vec![4, 5] },
+            NumaNode { id: 2, cpus: vec![6, 7] },
+        ] };
+        assert!(EncodePlacement::with_topology(NumaPolicy::Nodes(vec![3]), 0, topology.clone()).is_err());
+
+        // blocks dealt out in turn, threads capped by the CPUs of the node and its blocks
+        let placement = EncodePlacement::with_topology(NumaPolicy::Interleave, 0, topology.clone()).unwrap();
+        let groups = placement.assign(7);
+        let layout: Vec<(Option<usize>, usize, Vec<u32>)> = groups.iter()
+            .map(|x| (x.node, x.threads, x.blocks.lock().unwrap().iter().copied().collect()))
+            .collect();
+        assert_eq!(layout, vec![(Some(0), 3, vec![0, 3, 6]), (Some(1), 2, vec![1, 4]), (Some(2), 2, vec![2, 5])]);
+        assert_eq!(groups[1].cpus, vec![4, 5]);
+
+        let placement = EncodePlacement::with_topology(NumaPolicy::Nodes(vec![2]), 1, topology.clone()).unwrap();
+        let groups = placement.assign(2);
+        assert_eq!((groups.len(), groups[0].node, groups[0].threads), (1, Some(2), 1));
+
+        let placement = EncodePlacement::with_topology(NumaPolicy::Off, 0, topology).unwrap();
+        let groups = placement.assign(100);
+        assert_eq!((groups.len(), groups[0].node, groups[0].threads), (1, None, 8));
+        assert!(groups[0].cpus.is_empty());
+
+        // the same blocks as encoding on one thread
+        let data = gen_data(300 * 1000);
+        let placement = EncodePlacement::new(NumaPolicy::Interleave, 0).unwrap();
+        let config = EncoderConfig::new(1280);
+        let encoder = RaptorQEncoder::with_placement(config, &[IoSlice::new(&data)], &placement, None).unwrap();
+        let reference = RaptorQEncoder::with_config(config, &[IoSlice::new(&data)]).unwrap();
+        assert_eq!(encoder.get_block_info_vec(), reference.get_block_info_vec());
+        for (block, reference) in encoder.get_block_encoders().iter().zip(reference.get_block_encoders().iter()) {
+            assert_eq!(block.get_payload(), reference.get_payload());
+            assert_eq!(block.generate_repair_blocks(7, 10), reference.generate_repair_blocks(7, 10));
+        }
+    }
+}
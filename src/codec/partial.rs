@@ -0,0 +1,195 @@
+//! Decoding part of an object, so an object too large for one machine can be decoded by a cluster: each node decodes
+//! a range of the blocks, e.g. blocks 0..100 on one node and 100..200 on another, from the same symbols, and writes
+//! what it decoded at its offset in the object.
+
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+use super::decoder::{BlockDecoder, BlockNeeds, RaptorQDecoderError};
+use super::encoder::{BlockRegion, EncodedBlock};
+use super::manifest::{BlockHash, Manifest};
+
+/// Payload of a range of blocks, to be written at byte_offset of the object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialOutput {
+    pub block_ids: Range<u32>,
+    /// Offset of the first byte of data in the object.
+    pub byte_offset: u64,
+    pub data: Vec<u8>,
+}
+
+/// Decodes the blocks of a range of block ids of an object, see the module documentation. Symbols of other blocks are
+/// skipped, so every node of a cluster can be fed the same symbols.
+pub struct BlockRangeDecoder {
+    block_ids: Range<u32>,
+    block_decoders: Vec<BlockDecoder>,
+    block_hashes: Vec<BlockHash>,
+    /// Offset of the range's first byte in the object.
+    byte_offset: u64,
+    /// Blocks that were decoded but did not match their hash, and were decoded again.
+    rejected_blocks: u64,
+}
+
+impl BlockRangeDecoder {
+    /// Creates a BlockRangeDecoder for the blocks of manifest's object in block_ids, failing with BadBlockId if the
+    /// range is empty or goes beyond the object's blocks.
+    pub fn new(manifest: &Manifest, block_ids: Range<u32>) -> Result<BlockRangeDecoder, RaptorQDecoderError> {
+        if block_ids.is_empty() || block_ids.end as usize > manifest.get_block_count() {
+            return Err(RaptorQDecoderError::BadBlockId);
+        }
+        if manifest.block_info_vec.iter().enumerate().any(|(i, x)| x.block_id as usize != i) {
+            return Err(RaptorQDecoderError::InvalidBlockInfo);
+        }
+
+        let range = (block_ids.start as usize)..(block_ids.end as usize);
+        let mut block_decoders: Vec<BlockDecoder> = Vec::with_capacity(range.len());
+        for block_info in manifest.block_info_vec[range.clone()].iter() {
+            block_decoders.push(BlockDecoder::new(block_info.clone())?);
+        }
+        let byte_offset = BlockRegion::map(&manifest.block_info_vec[..range.end])[range.start].byte_offset as u64;
+
+        return Ok(BlockRangeDecoder {
+            block_ids,
+            block_decoders,
+            block_hashes: manifest.block_hashes.get(range).ok_or(RaptorQDecoderError::InvalidBlockInfo)?.to_vec(),
+            byte_offset,
+            rejected_blocks: 0,
+        });
+    }
+
+    /// Feeds encoded blocks to the block decoders of the range, skipping those of other blocks. A block whose
+    /// payload does not match its hash is decoded again from the symbols that come after. Returns true once every
+    /// block of the range is decoded.
+    pub fn consume(&mut self, blocks: Vec<EncodedBlock>) -> Result<bool, RaptorQDecoderError> {
+        let mut routed: Vec<Vec<EncodedBlock>> = vec![Vec::new(); self.block_decoders.len()];
+        for block in blocks {
+            if self.block_ids.contains(&block.block_id) {
+                routed[(block.block_id - self.block_ids.start) as usize].push(block);
+            }
+        }
+
+        for (i, bucket) in routed.into_iter().enumerate() {
+            let block_decoder = &mut self.block_decoders[i];
+            if bucket.is_empty() || !block_decoder.consume(bucket)? {
+                continue;
+            }
+            if Sha256::digest(block_decoder.get_result().unwrap())[..] != self.block_hashes[i][..] {
+                *block_decoder = BlockDecoder::new(block_decoder.get_block_info().clone())?;
+                self.rejected_blocks += 1;
+            }
+        }
+
+        return Ok(self.is_decoded());
+    }
+
+    /// Returns true once every block of the range is decoded.
+    pub fn is_decoded(&self) -> bool {
+        return self.block_decoders.iter().all(|x| x.is_decoded());
+    }
+
+    pub fn get_block_ids(&self) -> Range<u32> {
+        return self.block_ids.clone();
+    }
+
+    /// Gets the bytes of the object the range covers.
+    pub fn get_byte_range(&self) -> Range<u64> {
+        let len: u64 = self.block_decoders.iter().map(|x| x.get_block_info().payload_size as u64).sum();
+        return self.byte_offset..(self.byte_offset + len);
+    }
+
+    /// Gets what each block of the range that is not decoded yet still needs, for asking peers for symbols of just
+    /// this range.
+    pub fn get_block_needs(&self) -> Vec<BlockNeeds> {
+        return self.block_decoders.iter()
+            .filter(|x| !x.is_decoded())
+            .map(|x| BlockNeeds {
+                block_id: x.get_block_info().block_id,
+                symbols_needed: x.get_symbols_needed() as u32,
+                received: x.get_received_ranges(),
+            })
+            .collect();
+    }
+
+    /// Gets how many decoded blocks did not match their hash.
+    pub fn get_rejected_blocks(&self) -> u64 {
+        return self.rejected_blocks;
+    }
+
+    /// Returns the payload of the range with its offset in the object, or None if some block is not decoded yet.
+    pub fn get_result(&self) -> Option<PartialOutput> {
+        let mut data: Vec<u8> = Vec::with_capacity((self.get_byte_range().end - self.byte_offset) as usize);
+        for block_decoder in self.block_decoders.iter() {
+            data.extend_from_slice(block_decoder.get_result()?);
+        }
+
+        return Some(PartialOutput { block_ids: self.block_ids.clone(), byte_offset: self.byte_offset, data });
+    }
+}
+
+/// Splits block_count blocks into at most parts ranges of nearly the same number of blocks, e.g. one per node of a
+/// cluster. Panics if parts is zero.
+pub fn partition_blocks(block_count: usize, parts: usize) -> Vec<Range<u32>> {
+    assert!(parts > 0, "parts must be positive");
+    let parts = parts.min(block_count);
+    let mut ranges: Vec<Range<u32>> = Vec::with_capacity(parts);
+    let mut start: usize = 0;
+    for part in 0..parts {
+        // the first block_count % parts ranges get a block more
+        let len = block_count / parts + usize::from(part < block_count % parts);
+        ranges.push((start as u32)..((start + len) as u32));
+        start += len;
+    }
+    return ranges;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::{BlockEncoder, EncoderConfig};
+
+    #[test]
+    fn test_block_range_decoder() {
+        let data = gen_data(50 * 1000);
+        // blocks this small only come out of BlockEncoder, RaptorQEncoder makes blocks of tens of megabytes
+        let block_encoders: Vec<BlockEncoder> = data.chunks(12 * 1000).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, 1280, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let mut manifest = Manifest {
+            object_id: Sha256::digest(&data).into(),
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(1280),
+            block_info_vec: block_encoders.iter().map(|x| x.get_block_info()).collect(),
+            block_hashes: block_encoders.iter().map(|x| Sha256::digest(x.get_payload()).into()).collect(),
+            block_overheads: vec![0; block_encoders.len()],
+            envelope: None,
+        };
+        assert_eq!(partition_blocks(manifest.get_block_count(), 2), vec![0..3, 3..5]);
+        assert_eq!(partition_blocks(2, 3), vec![0..1, 1..2]);
+
+        let symbols: Vec<EncodedBlock> = block_encoders.iter().flat_map(|x| x.generate_encoded_blocks()).collect();
+        let mut output = vec![0; data.len()];
+        for block_ids in partition_blocks(manifest.get_block_count(), 2) {
+            let mut decoder = BlockRangeDecoder::new(&manifest, block_ids.clone()).unwrap();
+            assert_eq!(decoder.get_block_needs().len(), block_ids.len());
+            assert!(decoder.consume(symbols.clone()).unwrap());
+
+            let partial = decoder.get_result().unwrap();
+            assert_eq!(partial.byte_offset, decoder.get_byte_range().start);
+            assert_eq!(partial.byte_offset, block_ids.start as u64 * 12 * 1000);
+            let start = partial.byte_offset as usize;
+            output[start..(start + partial.data.len())].copy_from_slice(&partial.data);
+        }
+        assert_eq!(output, data);
+
+        manifest.block_hashes[4] = [0; 32];
+        let mut decoder = BlockRangeDecoder::new(&manifest, 4..5).unwrap();
+        assert!(!decoder.consume(symbols).unwrap());
+        assert_eq!(decoder.get_rejected_blocks(), 1);
+        assert!(BlockRangeDecoder::new(&manifest, 4..6).is_err());
+        assert!(BlockRangeDecoder::new(&manifest, 2..2).is_err());
+    }
+}
@@ -0,0 +1,615 @@
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "plan_cache_persistence")]
+use std::fs::{self, File};
+#[cfg(feature = "plan_cache_persistence")]
+use std::io::{self, Write};
+#[cfg(feature = "plan_cache_persistence")]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "plan_cache_persistence")]
+use std::time::SystemTime;
+use std::time::{Duration, Instant};
+
+use raptorq::SourceBlockEncodingPlan;
+use rand::{thread_rng, Rng};
+
+use super::consts::*;
+use super::encoder::{BlockEncoder, EncoderConfig, RaptorQEncoderError};
+
+/// Plan usage and generation latency for one symbol count.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlanStats {
+    /// Lookups served from the cache.
+    pub hits: u64,
+    /// Plans generated, i.e. lookups that missed.
+    pub generations: u64,
+    /// Time spent generating plans.
+    pub generation_time: Duration,
+    /// Slowest single generation.
+    pub max_generation_time: Duration,
+}
+
+/// Cache of encoding plans by symbol count. Generating a plan costs about as much as encoding a block, and
+/// varies wildly with the symbol count, so encoders of blocks with the same symbol count should share one.
+/// Thread safe; plans are generated without holding the lock, so a slow K does not hold up the others.
+pub struct PlanCache {
+    plans: Mutex<HashMap<u16, Arc<SourceBlockEncodingPlan>>>,
+    stats: Mutex<HashMap<u16, PlanStats>>,
+}
+
+impl PlanCache {
+    pub fn new() -> PlanCache {
+        return PlanCache {
+            plans: Mutex::new(HashMap::new()),
+            stats: Mutex::new(HashMap::new()),
+        };
+    }
+
+    /// Gets the plan for blocks of symbol_count symbols, generating it on a miss.
+    pub fn get(&self, symbol_count: u16) -> Arc<SourceBlockEncodingPlan> {
+        if let Some(plan) = self.plans.lock().unwrap().get(&symbol_count) {
+            self.stats.lock().unwrap().entry(symbol_count).or_default().hits += 1;
+            return plan.clone();
+        }
+
+        let start = Instant::now();
+        let plan = Arc::new(SourceBlockEncodingPlan::generate(symbol_count));
+        let elapsed = start.elapsed();
+
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats.entry(symbol_count).or_default();
+        stats.generations += 1;
+        stats.generation_time += elapsed;
+        stats.max_generation_time = std::cmp::max(stats.max_generation_time, elapsed);
+
+        // another thread may have raced us to it, keep the first plan so callers share it
+        return self.plans.lock().unwrap().entry(symbol_count).or_insert(plan).clone();
+    }
+
+    /// Generates plans ahead of time, so the first encoders of these symbol counts don't pay for them.
+    pub fn prewarm(&self, symbol_counts: &[u16]) {
+        for symbol_count in symbol_counts.iter() {
+            self.get(*symbol_count);
+        }
+    }
+
+    /// Generates the plans objects of the given sizes will need, see plan_symbol_counts, returning what it found.
+    pub fn prewarm_for_sizes(&self, size_histogram: &[(u64, u64)], config: EncoderConfig) -> Vec<(u16, u64)> {
+        let symbol_counts = plan_symbol_counts(size_histogram, config);
+        for (symbol_count, _) in symbol_counts.iter() {
+            self.get(*symbol_count);
+        }
+        return symbol_counts;
+    }
+
+    /// Gets the number of cached plans.
+    pub fn len(&self) -> usize {
+        return self.plans.lock().unwrap().len();
+    }
+
+    /// Returns true if no plan is cached.
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Gets usage and generation latency of every symbol count looked up so far, ordered by symbol count.
+    pub fn get_stats(&self) -> Vec<(u16, PlanStats)> {
+        let mut stats: Vec<(u16, PlanStats)> = self.stats.lock().unwrap().iter().map(|(x, y)| (*x, *y)).collect();
+        stats.sort_by_key(|(symbol_count, _)| *symbol_count);
+        return stats;
+    }
+}
+
+/// Works out the symbol counts of the blocks objects of the given sizes are encoded into with config: full blocks of
+/// config.max_block_symbols symbols, plus a tail block for whatever is left. The histogram pairs object sizes
+/// with how many objects of that size are expected; the result pairs symbol counts with how many blocks of that
+/// symbol count to expect, ordered by symbol count.
+pub fn plan_symbol_counts(size_histogram: &[(u64, u64)], config: EncoderConfig) -> Vec<(u16, u64)> {
+    let block_size = config.get_block_size() as u64;
+    let mut blocks: BTreeMap<u16, u64> = BTreeMap::new();
+    for (size, count) in size_histogram.iter().filter(|(size, count)| *size > 0 && *count > 0) {
+        if size / block_size > 0 {
+            *blocks.entry(config.max_block_symbols).or_default() += size / block_size * count;
+        }
+        let tail = size % block_size;
+        if tail > 0 {
+            let (_, symbol_count) = config.get_block_layout(tail as usize);
+            *blocks.entry(symbol_count as u16).or_default() += count;
+        }
+    }
+
+    return blocks.into_iter().collect();
+}
+
+/// How much slower per symbol than the fastest symbol count measured blocks may encode for pick_max_block_symbols.
+pub const MAX_BLOCK_SLOWDOWN: f64 = 2.0;
+
+/// Encode time of a block of one symbol count with and without a cached plan, see measure_plan_benefit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlanBenefit {
+    pub symbol_count: u16,
+    /// Time generating the plan took.
+    pub plan_time: Duration,
+    /// Fastest encode of the block without a plan, raptorq working one out on the way.
+    pub uncached_time: Duration,
+    /// Fastest encode of the block with the plan cached.
+    pub cached_time: Duration,
+}
+
+impl PlanBenefit {
+    /// Gets the encode time per source symbol with the plan cached, in seconds.
+    pub fn get_cached_secs_per_symbol(&self) -> f64 {
+        return self.cached_time.as_secs_f64() / self.symbol_count as f64;
+    }
+}
+
+/// Measures what a cached plan saves encoding a block of symbol_count random symbols of packet_size bytes, keeping
+/// the fastest of rounds encodes each way. Fails as BlockEncoder::with_config does for a bad packet size or more
+/// than RAPTORQ_MAX_SYMBOLS_IN_BLOCK symbols.
+pub fn measure_plan_benefit(symbol_count: u16, packet_size: u16, rounds: usize) -> Result<PlanBenefit, RaptorQEncoderError> {
+    assert!(symbol_count > 0, "symbol count must be positive");
+    assert!(rounds > 0, "rounds must be positive");
+    let config = EncoderConfig::new(packet_size);
+    config.validate()?;
+    if symbol_count as usize > RAPTORQ_MAX_SYMBOLS_IN_BLOCK {
+        return Err(RaptorQEncoderError::DataSizeTooLarge);
+    }
+
+    let mut data: Vec<u8> = vec![0; symbol_count as usize * packet_size as usize];
+    thread_rng().fill(&mut data[..]);
+    let cache = PlanCache::new();
+    cache.get(symbol_count);
+
+    let mut uncached_time = Duration::MAX;
+    let mut cached_time = Duration::MAX;
+    for _ in 0..rounds {
+        let block = data.clone();
+        let start = Instant::now();
+        BlockEncoder::with_config(0, config, block)?;
+        uncached_time = std::cmp::min(uncached_time, start.elapsed());
+
+        let block = data.clone();
+        let start = Instant::now();
+        BlockEncoder::with_plan_cache(0, config, block, &cache)?;
+        cached_time = std::cmp::min(cached_time, start.elapsed());
+    }
+
+    return Ok(PlanBenefit {
+        symbol_count,
+        plan_time: cache.get_stats()[0].1.generation_time,
+        uncached_time,
+        cached_time,
+    });
+}
+
+/// Picks a block symbol limit for EncoderConfig::max_block_symbols from measurements: the largest symbol count
+/// measured whose blocks encode with a cached plan in at most MAX_BLOCK_SLOWDOWN times the time per symbol of the
+/// fastest. Larger blocks spread loss over more symbols, so they are worth some encode time, but past a point raptorq
+/// slows down faster than they help. Without measurements, keeps the limit of the spec.
+pub fn pick_max_block_symbols(benefits: &[PlanBenefit]) -> u16 {
+    let fastest = benefits.iter().map(|x| x.get_cached_secs_per_symbol()).fold(f64::INFINITY, f64::min);
+    return benefits.iter()
+        .filter(|x| x.get_cached_secs_per_symbol() <= fastest * MAX_BLOCK_SLOWDOWN)
+        .map(|x| x.symbol_count)
+        .max()
+        .unwrap_or(RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u16);
+}
+
+/// Extension of plan files in a cache directory. Files are named after the symbol count of their plan.
+#[cfg(feature = "plan_cache_persistence")]
+const PLAN_FILE_EXTENSION: &str = "plan";
+
+/// Version of the plan files save_dir writes. Version 1 files are a bare bincode plan; version 2 files start with
+/// PLAN_FILE_MAGIC, the version (u8), the symbol count (u16) and a CRC32C of the plan (u32), little endian, so
+/// truncated, corrupt or misnamed plans are caught on load. Older versions are still loaded, and migrate_dir
+/// rewrites them in this one.
+#[cfg(feature = "plan_cache_persistence")]
+pub const PLAN_FILE_VERSION: u8 = 2;
+
+#[cfg(feature = "plan_cache_persistence")]
+const PLAN_FILE_MAGIC: &[u8; 4] = b"RQPL";
+
+#[cfg(feature = "plan_cache_persistence")]
+const PLAN_FILE_HEADER_SIZE: usize = 4 + 1 + 2 + 4;
+
+/// A plan saved in a cache directory.
+#[cfg(feature = "plan_cache_persistence")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlanFile {
+    pub symbol_count: u16,
+    pub path: PathBuf,
+    /// Size of the file, in bytes.
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+#[cfg(feature = "plan_cache_persistence")]
+impl PlanCache {
+    /// Opens the plans saved in dir, creating it if needed and checking that plans can be written to it.
+    /// If dir is unusable, falls back to an empty in-memory cache and returns the reason alongside it, so callers can
+    /// warn and carry on without saving.
+    pub fn open_dir<P: AsRef<Path>>(dir: P) -> (PlanCache, Option<io::Error>) {
+        let cache = PlanCache::new();
+        match PlanCache::validate_dir(dir.as_ref()).and_then(|_| cache.load_dir(dir)) {
+            Ok(_) => return (cache, None),
+            Err(error) => return (PlanCache::new(), Some(error)),
+        }
+    }
+
+    /// Creates dir if needed, and checks we can write to it by creating and removing a file.
+    fn validate_dir(dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let probe_path = dir.join(".write-probe.tmp");
+        File::create(&probe_path)?;
+        return fs::remove_file(&probe_path);
+    }
+
+    /// Loads every plan saved in dir into the cache, of any version, returning how many were loaded.
+    /// Loading is not counted as generation in the stats.
+    pub fn load_dir<P: AsRef<Path>>(&self, dir: P) -> io::Result<usize> {
+        let plan_files = list_dir(dir)?;
+        for plan_file in plan_files.iter() {
+            let (_, plan) = read_plan_file(plan_file)?;
+            self.plans.lock().unwrap().insert(plan_file.symbol_count, Arc::new(plan));
+        }
+
+        return Ok(plan_files.len());
+    }
+
+    /// Saves cached plans that are not in dir yet, returning how many were saved. Creates dir if needed.
+    /// Plans are written to a temporary file first, so concurrent loaders never see a partial plan.
+    pub fn save_dir<P: AsRef<Path>>(&self, dir: P) -> io::Result<usize> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let saved: Vec<u16> = list_dir(dir)?.iter().map(|x| x.symbol_count).collect();
+        let plans: Vec<(u16, Arc<SourceBlockEncodingPlan>)> = self.plans.lock().unwrap().iter()
+            .filter(|(x, _)| !saved.contains(x))
+            .map(|(x, y)| (*x, y.clone()))
+            .collect();
+
+        for (symbol_count, plan) in plans.iter() {
+            write_plan_file(dir, *symbol_count, plan)?;
+        }
+
+        return Ok(plans.len());
+    }
+}
+
+/// Reads a plan file of any version, returning its version and plan. Fails with ErrorKind::InvalidData for a file
+/// that is not a valid plan of the symbol count it is named after.
+#[cfg(feature = "plan_cache_persistence")]
+fn read_plan_file(plan_file: &PlanFile) -> io::Result<(u8, SourceBlockEncodingPlan)> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, format!("bad plan {}: {}", plan_file.path.display(), reason));
+
+    let contents = fs::read(&plan_file.path)?;
+    let (version, body) = match contents.strip_prefix(&PLAN_FILE_MAGIC[..]) {
+        None => (1, &contents[..]),
+        Some(_) if contents.len() < PLAN_FILE_HEADER_SIZE => return Err(invalid("truncated header".to_string())),
+        Some(header) => {
+            if header[0] != PLAN_FILE_VERSION {
+                return Err(invalid(format!("unknown version {}", header[0])));
+            }
+            if u16::from_le_bytes([header[1], header[2]]) != plan_file.symbol_count {
+                return Err(invalid("plan of another symbol count".to_string()));
+            }
+            let body = &contents[PLAN_FILE_HEADER_SIZE..];
+            if u32::from_le_bytes([header[3], header[4], header[5], header[6]]) != crc32c::crc32c(body) {
+                return Err(invalid("checksum mismatch".to_string()));
+            }
+            (header[0], body)
+        },
+    };
+    match bincode::deserialize(body) {
+        Ok(plan) => return Ok((version, plan)),
+        Err(error) => return Err(invalid(error.to_string())),
+    }
+}
+
+/// Writes a plan file of the current version in dir, through a temporary file so concurrent loaders never see a
+/// partial plan.
+#[cfg(feature = "plan_cache_persistence")]
+fn write_plan_file(dir: &Path, symbol_count: u16, plan: &SourceBlockEncodingPlan) -> io::Result<()> {
+    let path = dir.join(format!("{}.{}", symbol_count, PLAN_FILE_EXTENSION));
+    let temp_path = dir.join(format!(".{}.{}.tmp", symbol_count, PLAN_FILE_EXTENSION));
+    let body = match bincode::serialize(plan) {
+        Ok(body) => body,
+        Err(error) => return Err(io::Error::other(format!("failed to write plan {}: {}", temp_path.display(), error))),
+    };
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(PLAN_FILE_MAGIC)?;
+    file.write_all(&[PLAN_FILE_VERSION])?;
+    file.write_all(&symbol_count.to_le_bytes())?;
+    file.write_all(&crc32c::crc32c(&body).to_le_bytes())?;
+    file.write_all(&body)?;
+    drop(file);
+    return fs::rename(&temp_path, &path);
+}
+
+/// Rewrites the plans in dir saved by older versions in the current one, see PLAN_FILE_VERSION, returning the
+/// plans rewritten. Plans that can't be read are left alone, and fail the migration.
+#[cfg(feature = "plan_cache_persistence")]
+pub fn migrate_dir<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PlanFile>> {
+    let dir = dir.as_ref();
+    let mut migrated: Vec<PlanFile> = Vec::new();
+    for plan_file in list_dir(dir)? {
+        let (version, plan) = read_plan_file(&plan_file)?;
+        if version < PLAN_FILE_VERSION {
+            write_plan_file(dir, plan_file.symbol_count, &plan)?;
+            migrated.push(plan_file);
+        }
+    }
+
+    return Ok(migrated);
+}
+
+/// File in a cache directory holding the block symbol limit saved by save_max_block_symbols.
+#[cfg(feature = "plan_cache_persistence")]
+const MAX_BLOCK_SYMBOLS_FILE: &str = "max_block_symbols";
+
+/// Saves a block symbol limit picked by pick_max_block_symbols in dir, for encoders using the cache directory to
+/// default to. Creates dir if needed.
+#[cfg(feature = "plan_cache_persistence")]
+pub fn save_max_block_symbols<P: AsRef<Path>>(dir: P, max_block_symbols: u16) -> io::Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let temp_path = dir.join(format!(".{}.tmp", MAX_BLOCK_SYMBOLS_FILE));
+    fs::write(&temp_path, format!("{}\n", max_block_symbols))?;
+    return fs::rename(&temp_path, dir.join(MAX_BLOCK_SYMBOLS_FILE));
+}
+
+/// Loads the block symbol limit saved in dir, None if none was saved. Fails with ErrorKind::InvalidData if the
+/// saved limit is not in 1..=RAPTORQ_MAX_SYMBOLS_IN_BLOCK.
+#[cfg(feature = "plan_cache_persistence")]
+pub fn load_max_block_symbols<P: AsRef<Path>>(dir: P) -> io::Result<Option<u16>> {
+    let path = dir.as_ref().join(MAX_BLOCK_SYMBOLS_FILE);
+    let contents = match fs::read_to_string(&path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        result => result?,
+    };
+    match contents.trim().parse::<u16>() {
+        Ok(max_block_symbols) if max_block_symbols > 0 && max_block_symbols as usize <= RAPTORQ_MAX_SYMBOLS_IN_BLOCK => return Ok(Some(max_block_symbols)),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad block symbol limit in {}", path.display()))),
+    }
+}
+
+/// Lists the plans saved in dir, ordered by symbol count. Other files are ignored, and a dir that does not exist
+/// yet has no plans.
+#[cfg(feature = "plan_cache_persistence")]
+pub fn list_dir<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PlanFile>> {
+    let mut plan_files: Vec<PlanFile> = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(plan_files),
+        result => result?,
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|x| x.to_str()) != Some(PLAN_FILE_EXTENSION) {
+            continue;
+        }
+        let symbol_count: u16 = match path.file_stem().and_then(|x| x.to_str()).map(|x| x.parse()) {
+            Some(Ok(symbol_count)) => symbol_count,
+            _ => continue,
+        };
+
+        let metadata = entry.metadata()?;
+        plan_files.push(PlanFile {
+            symbol_count,
+            path,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
+
+    plan_files.sort_by_key(|x| x.symbol_count);
+    return Ok(plan_files);
+}
+
+/// Removes the least recently written plans in dir until the plans take up at most max_size bytes.
+/// Returns the removed plans.
+#[cfg(feature = "plan_cache_persistence")]
+pub fn gc_dir<P: AsRef<Path>>(dir: P, max_size: u64) -> io::Result<Vec<PlanFile>> {
+    let mut plan_files = list_dir(dir)?;
+    plan_files.sort_by_key(|x| x.modified);
+
+    let mut total_size: u64 = plan_files.iter().map(|x| x.size).sum();
+    let mut removed: Vec<PlanFile> = Vec::new();
+    for plan_file in plan_files {
+        if total_size <= max_size {
+            break;
+        }
+        fs::remove_file(&plan_file.path)?;
+        total_size -= plan_file.size;
+        removed.push(plan_file);
+    }
+
+    return Ok(removed);
+}
+
+impl Default for PlanCache {
+    fn default() -> PlanCache {
+        return PlanCache::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::decoder::*;
+    use super::super::encoder::*;
+    use std::io::IoSlice;
+
+    #[test]
+    fn test_plan_symbol_counts() {
+        let packet_size: u16 = 1000;
+        let block_size = RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u64 * packet_size as u64;
+        let histogram: Vec<(u64, u64)> = vec![(0, 5), (1, 3), (999, 2), (10 * 1000 + 1, 4), (2 * block_size + 1000, 1)];
+        assert_eq!(plan_symbol_counts(&histogram, EncoderConfig::new(packet_size)), vec![
+            (1, 3 + 2 + 1),
+            (11, 4),
+            (RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u16, 2),
+        ]);
+
+        // full blocks follow the config's limit
+        let mut config = EncoderConfig::new(packet_size);
+        config.max_block_symbols = 8;
+        assert_eq!(plan_symbol_counts(&[(20 * 1000, 1)], config), vec![(4, 1), (8, 2)]);
+
+        let cache = PlanCache::new();
+        cache.prewarm_for_sizes(&[(20 * 1000, 1)], EncoderConfig::new(packet_size));
+        assert_eq!(cache.get_stats().iter().map(|(x, _)| *x).collect::<Vec<u16>>(), vec![20]);
+    }
+
+    #[test]
+    fn test_plan_benefit() {
+        let benefit = measure_plan_benefit(40, 512, 2).unwrap();
+        assert_eq!(benefit.symbol_count, 40);
+        assert!(benefit.cached_time < Duration::MAX && benefit.uncached_time < Duration::MAX);
+        assert_eq!(measure_plan_benefit(40, 100, 1).err(), Some(RaptorQEncoderError::InvalidPacketSize));
+        assert_eq!(measure_plan_benefit(60000, 512, 1).err(), Some(RaptorQEncoderError::DataSizeTooLarge));
+
+        // the largest symbol count within MAX_BLOCK_SLOWDOWN of the fastest per symbol
+        let benefit = |symbol_count: u16, cached_ms: u64| PlanBenefit {
+            symbol_count,
+            plan_time: Duration::ZERO,
+            uncached_time: Duration::ZERO,
+            cached_time: Duration::from_millis(cached_ms),
+        };
+        let benefits = vec![benefit(10, 20), benefit(100, 100), benefit(1000, 1500), benefit(10000, 25000)];
+        assert_eq!(pick_max_block_symbols(&benefits), 1000);
+        assert_eq!(pick_max_block_symbols(&[]), RAPTORQ_MAX_SYMBOLS_IN_BLOCK as u16);
+    }
+
+    #[test]
+    fn test_plan_cache_stats() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(100 * 1000);
+        let cache = PlanCache::new();
+
+        for _ in 0..2 {
+            let encoder = match RaptorQEncoder::with_plan_cache(EncoderConfig::new(packet_size), &[IoSlice::new(&data)], &cache) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            };
+
+            let mut decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create decoder, error {:?}", error),
+            };
+            let mut blocks = encoder.generate_encoded_blocks();
+            blocks.append(&mut encoder.generate_encoded_blocks());
+            assert_eq!(decoder.consume(blocks), Ok(true));
+            assert_eq!(decoder.get_result(), Some(data.clone()));
+        }
+
+        // 100000 bytes in 1280 byte symbols, generated for the first encoder and reused by the second
+        let stats = cache.get_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].0, 79);
+        assert_eq!(stats[0].1.generations, 1);
+        assert_eq!(stats[0].1.hits, 1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[cfg(feature = "plan_cache_persistence")]
+    #[test]
+    fn test_plan_cache_dir() {
+        // save_dir creates missing directories
+        let root = std::env::temp_dir().join(format!("raptorcdn-plan-cache-test-{}", std::process::id()));
+        let dir = root.join("nested").join("cache");
+
+        let cache = PlanCache::new();
+        cache.prewarm(&[10, 20, 30]);
+        assert_eq!(cache.save_dir(&dir).unwrap(), 3);
+        assert_eq!(cache.save_dir(&dir).unwrap(), 0);
+
+        let plan_files = list_dir(&dir).unwrap();
+        assert_eq!(plan_files.iter().map(|x| x.symbol_count).collect::<Vec<u16>>(), vec![10, 20, 30]);
+
+        // loaded plans are the generated ones, and are not generated again
+        let loaded = PlanCache::new();
+        assert_eq!(loaded.load_dir(&dir).unwrap(), 3);
+        assert_eq!(loaded.get(20), cache.get(20));
+        assert_eq!(loaded.get_stats()[0].1.generations, 0);
+
+        let total_size: u64 = plan_files.iter().map(|x| x.size).sum();
+        assert_eq!(gc_dir(&dir, total_size).unwrap().len(), 0);
+        // the block symbol limit is not a plan, and survives gc
+        assert_eq!(load_max_block_symbols(&dir).unwrap(), None);
+        save_max_block_symbols(&dir, 4096).unwrap();
+        assert_eq!(load_max_block_symbols(&dir).unwrap(), Some(4096));
+        assert_eq!(list_dir(&dir).unwrap().len(), 3);
+
+        assert_eq!(gc_dir(&dir, 0).unwrap().len(), 3);
+        assert!(list_dir(&dir).unwrap().is_empty());
+        assert_eq!(load_max_block_symbols(&dir).unwrap(), Some(4096));
+        fs::write(dir.join(MAX_BLOCK_SYMBOLS_FILE), "60000").unwrap();
+        assert_eq!(load_max_block_symbols(&dir).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "plan_cache_persistence")]
+    #[test]
+    fn test_plan_cache_migrate_dir() {
+        let dir = std::env::temp_dir().join(format!("raptorcdn-plan-cache-migrate-test-{}", std::process::id()));
+        let cache = PlanCache::new();
+        cache.prewarm(&[10, 20]);
+        assert_eq!(cache.save_dir(&dir).unwrap(), 2);
+
+        // a version 1 plan is a bare bincode plan, and still loads
+        let v1_path = dir.join("30.plan");
+        fs::write(&v1_path, bincode::serialize(&*cache.get(30)).unwrap()).unwrap();
+        let loaded = PlanCache::new();
+        assert_eq!(loaded.load_dir(&dir).unwrap(), 3);
+        assert_eq!(loaded.get(30), cache.get(30));
+
+        // only it is rewritten, and loads the same afterwards
+        let migrated = migrate_dir(&dir).unwrap();
+        assert_eq!(migrated.iter().map(|x| x.symbol_count).collect::<Vec<u16>>(), vec![30]);
+        assert!(fs::read(&v1_path).unwrap().starts_with(PLAN_FILE_MAGIC));
+        assert!(migrate_dir(&dir).unwrap().is_empty());
+        let loaded = PlanCache::new();
+        assert_eq!(loaded.load_dir(&dir).unwrap(), 3);
+        assert_eq!(loaded.get(30), cache.get(30));
+
+        // corrupt and misnamed plans are caught
+        let mut contents = fs::read(&v1_path).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        fs::write(&v1_path, &contents).unwrap();
+        assert_eq!(PlanCache::new().load_dir(&dir).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::copy(dir.join("10.plan"), &v1_path).unwrap();
+        assert_eq!(migrate_dir(&dir).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "plan_cache_persistence")]
+    #[test]
+    fn test_plan_cache_open_dir() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-plan-cache-open-test-{}", std::process::id()));
+        let dir = root.join("cache");
+
+        // first run, the directory gets created
+        assert!(list_dir(&dir).unwrap().is_empty());
+        let (cache, error) = PlanCache::open_dir(&dir);
+        assert!(error.is_none());
+        assert!(cache.is_empty());
+        cache.prewarm(&[10]);
+        cache.save_dir(&dir).unwrap();
+
+        let (cache, error) = PlanCache::open_dir(&dir);
+        assert!(error.is_none());
+        assert_eq!(cache.len(), 1);
+
+        // a file where the directory should be falls back to memory
+        let (cache, error) = PlanCache::open_dir(dir.join("10.plan"));
+        assert!(error.is_some());
+        assert!(cache.is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant};
+
+use super::encoder::{EncodedBlock, RaptorQEncoder};
+
+/// First line of files written by SymbolProducer::save_cursors, followed by the block count.
+const CURSORS_HEADER: &str = "raptorcdn-cursors v2";
+
+/// First line of cursor files written before the symbols handed out were saved, which restore_cursors still reads.
+const CURSORS_V1_HEADER: &str = "raptorcdn-cursors v1";
+
+/// Identifies a receiver session of a SymbolProducer.
+pub type SessionId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SymbolProducerError {
+    /// Session was never opened, or already closed.
+    UnknownSession,
+    BadBlockId,
+    /// The session was handed every repair symbol id of a block, and the producer refuses to reuse them.
+    EsiSpaceExhausted,
+}
+
+/// What a SymbolProducer does once a session was handed every repair symbol id of a block. Reused symbols are
+/// duplicates the receiver gains nothing from, but a receiver that lost everything may still want them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReusePolicy {
+    /// Wrap around and hand out the session's symbols again, counting them in SessionStats::reused_symbols.
+    Wrap,
+    /// Fail with SymbolProducerError::EsiSpaceExhausted.
+    Refuse,
+}
+
+/// Counts of the sessions a SymbolProducer opened and how they ended.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub opened: u64,
+    pub closed: u64,
+    /// Sessions dropped by expire_idle_sessions.
+    pub reaped: u64,
+    /// Symbols handed out again to a session after it was handed every repair symbol id of their block.
+    pub reused_symbols: u64,
+}
+
+/// Repair symbols of one block handed to a session, see SymbolProducer::reserve_symbols.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SymbolRange {
+    pub block_id: u32,
+    /// Encoding symbol id of the first symbol, counted from the block's first repair symbol.
+    pub start: u32,
+    pub count: usize,
+}
+
+impl SymbolRange {
+    /// Generates the symbols of the range with the encoder of the producer that reserved it.
+    pub fn generate(&self, encoder: &RaptorQEncoder) -> Vec<EncodedBlock> {
+        return encoder.get_block_encoders()[self.block_id as usize].generate_repair_blocks(self.start, self.count);
+    }
+}
+
+/// Reorders symbols for sending so that a burst of losses is spread over several blocks. Blocks are taken in groups
+/// of depth adjacent blocks, and the symbols of a group's blocks are alternated, one symbol of each block in turn.
+/// Symbols of a block keep their order. A depth of 1 leaves the symbols block after block.
+pub fn interleave_blocks(blocks: Vec<EncodedBlock>, depth: usize) -> Vec<EncodedBlock> {
+    if depth <= 1 {
+        return blocks;
+    }
+
+    let mut by_block: BTreeMap<u32, VecDeque<EncodedBlock>> = BTreeMap::new();
+    let len = blocks.len();
+    for block in blocks {
+        by_block.entry(block.block_id).or_default().push_back(block);
+    }
+
+    let mut interleaved: Vec<EncodedBlock> = Vec::with_capacity(len);
+    let mut queues: Vec<VecDeque<EncodedBlock>> = by_block.into_values().collect();
+    for group in queues.chunks_mut(depth) {
+        while group.iter().any(|x| !x.is_empty()) {
+            interleaved.extend(group.iter_mut().filter_map(|x| x.pop_front()));
+        }
+    }
+    return interleaved;
+}
+
+struct Session {
+    /// Next repair symbol id, per block.
+    cursors: Vec<u32>,
+    /// Repair symbols handed out, per block, to tell when ids start being reused.
+    issued: Vec<u64>,
+    last_active: Instant,
+}
+
+/// Pull-based symbol generation: the transport asks for the next N symbols of a session, and exactly that many are
+/// generated. Each session tracks its own repair symbol cursor per block, so a session never receives the same
+/// symbol twice and receivers that finish early cost nothing more.
+pub struct SymbolProducer {
+    encoder: RaptorQEncoder,
+    sessions: HashMap<SessionId, Session>,
+    next_session_id: SessionId,
+    session_stats: SessionStats,
+    reuse_policy: ReusePolicy,
+    interleave_depth: usize,
+    /// Repair overhead of each block in percent, see set_block_overheads.
+    block_overheads: Vec<u16>,
+}
+
+impl SymbolProducer {
+    pub fn new(encoder: RaptorQEncoder) -> SymbolProducer {
+        let block_overheads = vec![0; encoder.get_block_encoders().len()];
+        return SymbolProducer {
+            encoder,
+            sessions: HashMap::new(),
+            next_session_id: 0,
+            session_stats: SessionStats::default(),
+            reuse_policy: ReusePolicy::Wrap,
+            interleave_depth: 1,
+            block_overheads,
+        };
+    }
+
+    /// Sets the repair overhead of each block, ordered by block id, as carried in Manifest::block_overheads. A block
+    /// with overhead p gets a share of next_symbols in proportion to its symbol count times 100 + p, so with 30% for
+    /// the first block and 5% for the rest, the first block gets 130 / 105 times its plain share. Defaults to 0 for
+    /// every block. Fails with BadBlockId if overheads has the wrong length.
+    pub fn set_block_overheads(&mut self, overheads: &[u16]) -> Result<(), SymbolProducerError> {
+        if overheads.len() != self.block_overheads.len() {
+            return Err(SymbolProducerError::BadBlockId);
+        }
+        self.block_overheads = overheads.to_vec();
+        return Ok(());
+    }
+
+    /// Sets what happens once a session was handed every repair symbol id of a block. Defaults to Wrap.
+    pub fn set_reuse_policy(&mut self, reuse_policy: ReusePolicy) {
+        self.reuse_policy = reuse_policy;
+    }
+
+    /// Sets how many adjacent blocks next_symbols alternates symbols between, see interleave_blocks. Defaults to 1,
+    /// no interleaving. Panics if depth is zero.
+    pub fn set_interleave_depth(&mut self, depth: usize) {
+        assert!(depth > 0, "interleave depth must be positive");
+        self.interleave_depth = depth;
+    }
+
+    pub fn get_interleave_depth(&self) -> usize {
+        return self.interleave_depth;
+    }
+
+    /// Opens a session. Cursors start at random repair symbol ids, so independent producers of the same object
+    /// are unlikely to overlap. They are drawn from the encoder's seeded generator if its config has a seed.
+    pub fn open_session(&mut self) -> SessionId {
+        let cursors: Vec<u32> = self.encoder.get_block_encoders().iter().map(|x| x.gen_repair_index() as u32).collect();
+
+        let session_id = self.next_session_id;
+        self.next_session_id += 1;
+        let issued = vec![0; cursors.len()];
+        self.sessions.insert(session_id, Session { cursors, issued, last_active: Instant::now() });
+        self.session_stats.opened += 1;
+        return session_id;
+    }
+
+    /// Closes a session, dropping its cursors.
+    pub fn close_session(&mut self, session_id: SessionId) -> Result<(), SymbolProducerError> {
+        match self.sessions.remove(&session_id) {
+            None => return Err(SymbolProducerError::UnknownSession),
+            Some(_) => {
+                self.session_stats.closed += 1;
+                return Ok(());
+            },
+        }
+    }
+
+    /// Closes the sessions that produced nothing for ttl, returning their ids. Servers can't tell a receiver that
+    /// finished or went away from one that is just slow, so call this periodically to bound the sessions kept.
+    pub fn expire_idle_sessions(&mut self, ttl: Duration, now: Instant) -> Vec<SessionId> {
+        let expired: Vec<SessionId> = self.sessions.iter()
+            .filter(|(_, session)| now.saturating_duration_since(session.last_active) >= ttl)
+            .map(|(session_id, _)| *session_id)
+            .collect();
+        for session_id in expired.iter() {
+            self.sessions.remove(session_id);
+        }
+
+        self.session_stats.reaped += expired.len() as u64;
+        return expired;
+    }
+
+    /// Marks a session active, for requests it was served elsewhere, e.g. from a SymbolPool, so it isn't expired.
+    pub fn touch_session(&mut self, session_id: SessionId) -> Result<(), SymbolProducerError> {
+        match self.sessions.get_mut(&session_id) {
+            None => return Err(SymbolProducerError::UnknownSession),
+            Some(session) => session.last_active = Instant::now(),
+        }
+        return Ok(());
+    }
+
+    pub fn get_open_sessions(&self) -> usize {
+        return self.sessions.len();
+    }
+
+    pub fn get_session_stats(&self) -> SessionStats {
+        return self.session_stats;
+    }
+
+    /// Gets the number of repair symbols of a block a session can still be handed before ids are reused.
+    pub fn get_fresh_symbols(&self, session_id: SessionId, block_id: u32) -> Result<u64, SymbolProducerError> {
+        let session = match self.sessions.get(&session_id) {
+            None => return Err(SymbolProducerError::UnknownSession),
+            Some(session) => session,
+        };
+        match session.issued.get(block_id as usize).zip(self.encoder.get_block_encoders().get(block_id as usize)) {
+            None => return Err(SymbolProducerError::BadBlockId),
+            Some((issued, block_encoder)) => return Ok((block_encoder.get_repair_symbol_id_limit() as u64).saturating_sub(*issued)),
+        }
+    }
+
+    /// Generates the next count symbols of one block for a session.
+    pub fn next_block_symbols(&mut self, session_id: SessionId, block_id: u32, count: usize) -> Result<Vec<EncodedBlock>, SymbolProducerError> {
+        return Ok(self.reserve_block_symbols(session_id, block_id, count)?.generate(&self.encoder));
+    }
+
+    /// Advances a session past the next count symbols of one block, returning them as a range to generate.
+    pub fn reserve_block_symbols(&mut self, session_id: SessionId, block_id: u32, count: usize) -> Result<SymbolRange, SymbolProducerError> {
+        let session = match self.sessions.get_mut(&session_id) {
+            None => return Err(SymbolProducerError::UnknownSession),
+            Some(session) => session,
+        };
+        session.last_active = Instant::now();
+        let (cursor, issued) = match session.cursors.get_mut(block_id as usize).zip(session.issued.get_mut(block_id as usize)) {
+            None => return Err(SymbolProducerError::BadBlockId),
+            Some(succ) => succ,
+        };
+        let block_encoder = &self.encoder.get_block_encoders()[block_id as usize];
+
+        let fresh = (block_encoder.get_repair_symbol_id_limit() as u64).saturating_sub(*issued);
+        if count as u64 > fresh {
+            if self.reuse_policy == ReusePolicy::Refuse {
+                return Err(SymbolProducerError::EsiSpaceExhausted);
+            }
+            self.session_stats.reused_symbols += count as u64 - fresh;
+        }
+        *issued += count as u64;
+
+        let range = SymbolRange { block_id, start: *cursor, count };
+        *cursor = ((*cursor as usize + count) % block_encoder.get_repair_symbol_id_limit()) as u32;
+        return Ok(range);
+    }
+
+    /// Generates the next count symbols of the object for a session, spread over blocks in proportion to their
+    /// symbol counts weighted by their overheads, and interleaved by the producer's interleave depth.
+    pub fn next_symbols(&mut self, session_id: SessionId, count: usize) -> Result<Vec<EncodedBlock>, SymbolProducerError> {
+        let ranges = self.reserve_symbols(session_id, count)?;
+        let blocks = ranges.iter().flat_map(|x| x.generate(&self.encoder)).collect();
+        return Ok(interleave_blocks(blocks, self.interleave_depth));
+    }
+
+    /// Advances a session as next_symbols does, returning the symbols as ranges to generate. Generating is the
+    /// expensive part, so a server sharing a producer between threads can generate with a clone of the encoder
+    /// after releasing the producer. The generated symbols are block after block, see interleave_blocks.
+    pub fn reserve_symbols(&mut self, session_id: SessionId, count: usize) -> Result<Vec<SymbolRange>, SymbolProducerError> {
+        let block_counts = self.get_block_counts(count);
+
+        // refuse before advancing any block, so a refused request leaves the session as it was
+        if self.reuse_policy == ReusePolicy::Refuse {
+            for (block_id, block_count) in block_counts.iter().enumerate() {
+                if *block_count as u64 > self.get_fresh_symbols(session_id, block_id as u32)? {
+                    return Err(SymbolProducerError::EsiSpaceExhausted);
+                }
+            }
+        }
+
+        let mut ranges: Vec<SymbolRange> = Vec::with_capacity(block_counts.len());
+        for (block_id, block_count) in block_counts.into_iter().enumerate() {
+            ranges.push(self.reserve_block_symbols(session_id, block_id as u32, block_count)?);
+        }
+        return Ok(ranges);
+    }
+
+    /// Splits count symbols over the blocks as next_symbols does, returning the symbols of each block by block id.
+    pub fn get_block_counts(&self, count: usize) -> Vec<usize> {
+        let weights: Vec<u64> = self.encoder.get_block_encoders().iter().zip(self.block_overheads.iter())
+            .map(|(block_encoder, overhead)| block_encoder.get_symbol_count() as u64 * (100 + *overhead as u64))
+            .collect();
+        let total_weight: u64 = weights.iter().sum();
+
+        let mut block_counts: Vec<usize> = Vec::with_capacity(weights.len());
+        for (block_id, weight) in weights.iter().enumerate() {
+            // Hand out rounding leftovers to the last block.
+            if block_id + 1 == weights.len() {
+                block_counts.push(count - block_counts.iter().sum::<usize>());
+            } else {
+                block_counts.push((count as u64 * weight / total_weight) as usize);
+            }
+        }
+        return block_counts;
+    }
+
+    /// Writes the cursors of every open session, so a restarted producer can continue where this one left off.
+    /// Format is a header line, then one line per session: the session id, its cursor for each block, then the
+    /// repair symbols it was handed of each block, so reuse keeps being told apart after the restart.
+    pub fn save_cursors<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{} {}", CURSORS_HEADER, self.encoder.get_block_encoders().len())?;
+
+        let mut session_ids: Vec<&SessionId> = self.sessions.keys().collect();
+        session_ids.sort();
+        for session_id in session_ids {
+            write!(writer, "{}", session_id)?;
+            let session = &self.sessions[session_id];
+            for cursor in session.cursors.iter() {
+                write!(writer, " {}", cursor)?;
+            }
+            for issued in session.issued.iter() {
+                write!(writer, " {}", issued)?;
+            }
+            writeln!(writer)?;
+        }
+
+        return writer.flush();
+    }
+
+    /// Restores sessions written by save_cursors. Restored sessions replace open sessions with the same id, and count
+    /// as active now. Files of the first version don't have the symbols handed out, so for their sessions reuse is
+    /// counted from the restart. Fails without restoring anything if the cursors were saved for an object with a
+    /// different block count.
+    pub fn restore_cursors<R: BufRead>(&mut self, reader: R) -> io::Result<()> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad cursor file: {}", reason));
+
+        let mut lines = reader.lines();
+        let header = match lines.next() {
+            None => return Err(invalid("missing header")),
+            Some(line) => line?,
+        };
+        let num_blocks = self.encoder.get_block_encoders().len();
+        let has_issued = if header == format!("{} {}", CURSORS_HEADER, num_blocks) {
+            true
+        } else if header == format!("{} {}", CURSORS_V1_HEADER, num_blocks) {
+            false
+        } else {
+            return Err(invalid("header does not match this object"));
+        };
+
+        let mut sessions: HashMap<SessionId, (Vec<u32>, Vec<u64>)> = HashMap::new();
+        for line in lines {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let session_id: SessionId = match fields.next().map(|x| x.parse()) {
+                Some(Ok(session_id)) => session_id,
+                _ => return Err(invalid("bad session id")),
+            };
+            let fields: Vec<&str> = fields.collect();
+            let (cursors, issued) = match (has_issued, fields.len()) {
+                (true, len) if len == 2 * num_blocks => fields.split_at(num_blocks),
+                (false, len) if len == num_blocks => (&fields[..], &[][..]),
+                _ => return Err(invalid("bad cursors")),
+            };
+            let cursors: Vec<u32> = match cursors.iter().map(|x| x.parse()).collect() {
+                Ok(cursors) => cursors,
+                Err(_) => return Err(invalid("bad cursor")),
+            };
+            let issued: Vec<u64> = match issued.iter().map(|x| x.parse()).collect() {
+                Ok(issued) if has_issued => issued,
+                Ok(_) => vec![0; num_blocks],
+                Err(_) => return Err(invalid("bad issued count")),
+            };
+            let block_encoders = self.encoder.get_block_encoders();
+            if cursors.iter().zip(block_encoders).any(|(x, y)| *x as usize >= y.get_repair_symbol_id_limit()) {
+                return Err(invalid("bad cursors"));
+            }
+            sessions.insert(session_id, (cursors, issued));
+        }
+
+        let now = Instant::now();
+        for (session_id, (cursors, issued)) in sessions {
+            self.next_session_id = std::cmp::max(self.next_session_id, session_id.saturating_add(1));
+            self.sessions.insert(session_id, Session { cursors, issued, last_active: now });
+        }
+
+        return Ok(());
+    }
+
+    /// Gets the encoder symbols are produced from.
+    pub fn get_encoder(&self) -> &RaptorQEncoder {
+        return &self.encoder;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::decoder::*;
+    use raptorq::{EncodingPacket, PayloadId};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_symbol_producer_pull() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(100 * 1000);
+
+        let encoder = match RaptorQEncoder::new(packet_size, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        let mut producer = SymbolProducer::new(encoder);
+        let session_id = producer.open_session();
+
+        // pull a few symbols at a time until decoded, a session should never repeat a symbol
+        let mut seen: HashSet<u32> = HashSet::new();
+        let mut decoded = false;
+        while !decoded {
+            let blocks = match producer.next_symbols(session_id, 10) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to produce symbols, error {}", error as u32),
+            };
+            assert_eq!(blocks.len(), 10);
+            for block in blocks.iter() {
+                assert!(seen.insert(block.data.payload_id().encoding_symbol_id()));
+            }
+
+            decoded = match decoder.consume(blocks) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to decode data, err {:?}", error),
+            };
+        }
+
+        assert_eq!(decoder.get_result(), Some(data));
+
+        assert_eq!(producer.close_session(session_id), Ok(()));
+        assert_eq!(producer.next_symbols(session_id, 1), Err(SymbolProducerError::UnknownSession));
+    }
+
+    #[test]
+    fn test_symbol_producer_restore_cursors() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(100 * 1000);
+
+        let encoder = match RaptorQEncoder::new(packet_size, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut producer = SymbolProducer::new(encoder);
+        let session_id = producer.open_session();
+        producer.next_symbols(session_id, 10).unwrap();
+
+        let mut saved: Vec<u8> = Vec::new();
+        producer.save_cursors(&mut saved).unwrap();
+
+        // pretend the origin restarted
+        let encoder = match RaptorQEncoder::new(packet_size, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut restarted = SymbolProducer::new(encoder);
+        restarted.restore_cursors(&saved[..]).unwrap();
+
+        // the restarted producer continues with the same symbols, and never reuses the restored session id
+        assert_eq!(restarted.next_symbols(session_id, 10).unwrap(), producer.next_symbols(session_id, 10).unwrap());
+        assert!(restarted.open_session() > session_id);
+
+        // nor forgets the symbols handed out before the restart
+        for block_id in 0..producer.get_encoder().get_block_encoders().len() as u32 {
+            assert_eq!(restarted.get_fresh_symbols(session_id, block_id), producer.get_fresh_symbols(session_id, block_id));
+        }
+
+        // files without them still restore, counting reuse from the restart
+        let cursors: Vec<String> = restarted.sessions[&session_id].cursors.iter().map(|x| x.to_string()).collect();
+        let v1 = format!("raptorcdn-cursors v1 {}\n{} {}\n", cursors.len(), session_id, cursors.join(" "));
+        restarted.restore_cursors(v1.as_bytes()).unwrap();
+        let limit = restarted.get_encoder().get_block_encoders()[0].get_repair_symbol_id_limit() as u64;
+        assert_eq!(restarted.get_fresh_symbols(session_id, 0), Ok(limit));
+
+        match restarted.restore_cursors(&b"raptorcdn-cursors v1 2\n"[..]) {
+            Ok(_) => panic!("Should have failed to restore cursors of an object with 2 blocks"),
+            Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidData),
+        }
+    }
+
+    #[test]
+    fn test_symbol_producer_expire_idle_sessions() {
+        let data = gen_data(10 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut producer = SymbolProducer::new(encoder);
+        let idle = producer.open_session();
+        let closed = producer.open_session();
+        let active = producer.open_session();
+        producer.close_session(closed).unwrap();
+
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(10));
+        producer.next_symbols(active, 1).unwrap();
+
+        let later = Instant::now();
+        assert_eq!(producer.expire_idle_sessions(later - start, later), vec![idle]);
+        assert_eq!(producer.get_open_sessions(), 1);
+        assert_eq!(producer.next_symbols(idle, 1), Err(SymbolProducerError::UnknownSession));
+        assert_eq!(producer.get_session_stats(), SessionStats { opened: 3, closed: 1, reaped: 1, reused_symbols: 0 });
+
+        assert_eq!(producer.expire_idle_sessions(Duration::ZERO, Instant::now()), vec![active]);
+        assert_eq!(producer.get_open_sessions(), 0);
+    }
+
+    #[test]
+    fn test_symbol_producer_esi_exhaustion() {
+        let data = gen_data(10 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let limit = encoder.get_block_encoders()[0].get_repair_symbol_id_limit();
+        let mut producer = SymbolProducer::new(encoder);
+        let session_id = producer.open_session();
+
+        // reserving doesn't generate, so the whole ESI space can be handed out cheaply
+        producer.reserve_symbols(session_id, limit - 10).unwrap();
+        assert_eq!(producer.get_fresh_symbols(session_id, 0), Ok(10));
+
+        producer.set_reuse_policy(ReusePolicy::Refuse);
+        assert_eq!(producer.reserve_symbols(session_id, 20), Err(SymbolProducerError::EsiSpaceExhausted));
+        assert_eq!(producer.get_fresh_symbols(session_id, 0), Ok(10));
+        assert_eq!(producer.next_symbols(session_id, 10).unwrap().len(), 10);
+
+        producer.set_reuse_policy(ReusePolicy::Wrap);
+        assert_eq!(producer.next_symbols(session_id, 5).unwrap().len(), 5);
+        assert_eq!(producer.get_fresh_symbols(session_id, 0), Ok(0));
+        assert_eq!(producer.get_session_stats().reused_symbols, 5);
+    }
+
+    #[test]
+    fn test_interleave_blocks() {
+        let blocks: Vec<EncodedBlock> = (0..5u32)
+            .flat_map(|block_id| (0..4u32).map(move |esi| EncodedBlock { block_id, data: EncodingPacket::new(PayloadId::new(0, esi), Vec::new()) }))
+            .collect();
+        assert_eq!(interleave_blocks(blocks.clone(), 1), blocks);
+
+        // blocks 0 to 2 alternate, then blocks 3 and 4, each block's symbols in order
+        let interleaved = interleave_blocks(blocks.clone(), 3);
+        let order: Vec<(u32, u32)> = interleaved.iter().map(|x| (x.block_id, x.data.payload_id().encoding_symbol_id())).collect();
+        assert_eq!(&order[..6], &[(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+        assert_eq!(&order[12..16], &[(3, 0), (4, 0), (3, 1), (4, 1)]);
+        assert_eq!(interleaved.len(), blocks.len());
+
+        // a burst of 6 lost symbols takes 2 of each of 3 blocks rather than all 4 of one block and 2 of the next
+        let mut lost = [0; 5];
+        for block in interleaved[3..9].iter() {
+            lost[block.block_id as usize] += 1;
+        }
+        assert_eq!(lost, [2, 2, 2, 0, 0]);
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Waker;
+
+use super::decoder::{RaptorQDecoder, RaptorQDecoderError};
+use super::encoder::{BlockRegion, EncodedBlock};
+
+/// State guarded by the SharedDecoder lock.
+pub(crate) struct DecodeState {
+    pub(crate) decoder: RaptorQDecoder,
+    /// Async readers waiting for progress.
+    pub(crate) wakers: Vec<Waker>,
+}
+
+/// Decoder shared between a DecodeReader and the DecodeFeeders pushing packets into it.
+pub(crate) struct SharedDecoder {
+    pub(crate) state: Mutex<DecodeState>,
+    /// Signalled whenever a feeder consumed packets or went away.
+    progress: Condvar,
+    /// Number of live feeders. Once 0, blocks that are not decoded never will be.
+    pub(crate) feeders: AtomicUsize,
+}
+
+impl SharedDecoder {
+    /// Wakes up blocked and async readers. Called with the lock held so no reader can miss the wakeup.
+    fn notify(&self, state: &mut DecodeState) {
+        self.progress.notify_all();
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Handle used to feed encoded blocks into the decoder behind a DecodeReader.
+pub struct DecodeFeeder {
+    shared: Arc<SharedDecoder>,
+}
+
+impl DecodeFeeder {
+    /// Feeds encoded blocks to the decoder and wakes up blocked readers.
+    pub fn consume(&self, blocks: Vec<EncodedBlock>) -> Result<bool, RaptorQDecoderError> {
+        let mut state = self.shared.state.lock().unwrap();
+        let result = state.decoder.consume(blocks);
+        self.shared.notify(&mut state);
+        return result;
+    }
+}
+
+impl Clone for DecodeFeeder {
+    fn clone(&self) -> DecodeFeeder {
+        self.shared.feeders.fetch_add(1, Ordering::AcqRel);
+        return DecodeFeeder { shared: self.shared.clone() };
+    }
+}
+
+impl Drop for DecodeFeeder {
+    fn drop(&mut self) {
+        // Take the lock so a reader can't miss the wakeup between checking feeders and waiting.
+        let mut state = self.shared.state.lock().unwrap();
+        self.shared.feeders.fetch_sub(1, Ordering::AcqRel);
+        self.shared.notify(&mut state);
+    }
+}
+
+/// std::io::Read and Seek over a decode in progress. Reads of bytes whose block is not decoded yet either block
+/// until a feeder recovers it, or fail with ErrorKind::WouldBlock.
+pub struct DecodeReader {
+    pub(crate) shared: Arc<SharedDecoder>,
+    /// Block regions, cached so reads don't need to recompute them.
+    block_map: Vec<BlockRegion>,
+    /// Size of the payload, without padding.
+    data_size: u64,
+    /// Current read position in the payload.
+    position: u64,
+    /// Whether reads wait for missing blocks instead of returning WouldBlock.
+    blocking: bool,
+}
+
+impl DecodeReader {
+    /// Wraps a decoder, returning the reader and a feeder for pushing encoded blocks into it.
+    pub fn new(decoder: RaptorQDecoder, blocking: bool) -> (DecodeReader, DecodeFeeder) {
+        let block_map = decoder.block_map();
+        let data_size = decoder.get_data_size() as u64;
+        let shared = Arc::new(SharedDecoder {
+            state: Mutex::new(DecodeState {
+                decoder,
+                wakers: Vec::new(),
+            }),
+            progress: Condvar::new(),
+            feeders: AtomicUsize::new(1),
+        });
+
+        let reader = DecodeReader {
+            shared: shared.clone(),
+            block_map,
+            data_size,
+            position: 0,
+            blocking,
+        };
+        return (reader, DecodeFeeder { shared });
+    }
+
+    /// Returns true once every block is decoded.
+    pub fn is_decoded(&self) -> bool {
+        return self.shared.state.lock().unwrap().decoder.is_decoded();
+    }
+
+    /// Gets the size of the payload, without padding.
+    pub fn get_data_size(&self) -> u64 {
+        return self.data_size;
+    }
+
+    /// Finds the block covering the current position.
+    fn current_region(&self) -> Option<BlockRegion> {
+        let index = self.block_map.partition_point(|x| (x.byte_offset + x.len) as u64 <= self.position);
+        return self.block_map.get(index).copied();
+    }
+
+    /// Copies bytes at the current position if their block is decoded. Returns None if it is not decoded yet.
+    pub(crate) fn read_decoded(&mut self, state: &DecodeState, buf: &mut [u8]) -> Option<usize> {
+        let region = match self.current_region() {
+            None => return Some(0),
+            Some(region) => region,
+        };
+
+        let data = state.decoder.get_block_result(region.block_id)?;
+        let start = (self.position - region.byte_offset as u64) as usize;
+        let copied = std::cmp::min(buf.len(), data.len() - start);
+        buf[..copied].copy_from_slice(&data[start..(start + copied)]);
+        self.position += copied as u64;
+        return Some(copied);
+    }
+
+    pub(crate) fn no_feeders_error() -> io::Error {
+        return io::Error::new(io::ErrorKind::UnexpectedEof, "no feeders left to decode block");
+    }
+}
+
+impl Read for DecodeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let shared = self.shared.clone();
+        let mut state = shared.state.lock().unwrap();
+        loop {
+            if let Some(copied) = self.read_decoded(&state, buf) {
+                return Ok(copied);
+            }
+
+            if !self.blocking {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "block not decoded yet"));
+            }
+            if shared.feeders.load(Ordering::Acquire) == 0 {
+                return Err(DecodeReader::no_feeders_error());
+            }
+
+            state = shared.progress.wait(state).unwrap();
+        }
+    }
+}
+
+impl Seek for DecodeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.data_size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        match position {
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position")),
+            Some(position) => {
+                self.position = position;
+                return Ok(position);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::encoder::*;
+
+    fn setup(data: &[u8], blocking: bool) -> (RaptorQEncoder, DecodeReader, DecodeFeeder) {
+        let encoder = match RaptorQEncoder::new(1280, data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+
+        let decoder = match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        let (reader, feeder) = DecodeReader::new(decoder, blocking);
+        return (encoder, reader, feeder);
+    }
+
+    #[test]
+    fn test_decode_reader_would_block() {
+        let data = gen_data(100 * 1000);
+        let (encoder, mut reader, feeder) = setup(&data, false);
+
+        let mut buf: Vec<u8> = vec![0; 16];
+        match reader.read(&mut buf) {
+            Ok(_) => panic!("Should not be able to read before decoding"),
+            Err(error) => assert_eq!(error.kind(), io::ErrorKind::WouldBlock),
+        }
+
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        match feeder.consume(blocks) {
+            Ok(decoded) => assert!(decoded),
+            Err(error) => panic!("Failed to decode data, err {:?}", error),
+        }
+
+        let mut recovered_data: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut recovered_data).unwrap();
+        assert_eq!(recovered_data, data);
+
+        assert_eq!(reader.seek(SeekFrom::End(-16)).unwrap(), data.len() as u64 - 16);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[(data.len() - 16)..]);
+    }
+
+    #[test]
+    fn test_decode_reader_blocking() {
+        let data = gen_data(100 * 1000);
+        let (encoder, mut reader, feeder) = setup(&data, true);
+
+        let mut blocks = encoder.generate_encoded_blocks();
+        blocks.append(&mut encoder.generate_encoded_blocks());
+        let handle = std::thread::spawn(move || {
+            match feeder.consume(blocks) {
+                Ok(decoded) => assert!(decoded),
+                Err(error) => panic!("Failed to decode data, err {:?}", error),
+            }
+        });
+
+        let mut recovered_data: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut recovered_data).unwrap();
+        assert_eq!(recovered_data, data);
+
+        handle.join().unwrap();
+    }
+}
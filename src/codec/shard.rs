@@ -0,0 +1,114 @@
+use raptorq::{EncodingPacket, PayloadId};
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+use super::encoder::EncodedBlock;
+
+/// First bytes of a shard file.
+pub const SHARD_MAGIC: &[u8; 8] = b"RCDNSHD1";
+
+/// Writes encoded blocks as a shard file: magic, then for each block its block id (u32), serialized payload id,
+/// symbol size (u16) and symbol. Integers are little endian.
+pub fn write_shard<W: Write>(mut writer: W, blocks: &[EncodedBlock]) -> io::Result<()> {
+    writer.write_all(SHARD_MAGIC)?;
+    write_shard_records(&mut writer, blocks)?;
+    return writer.flush();
+}
+
+/// Writes the records of encoded blocks as write_shard does, without the magic, e.g. to append to a shard being
+/// streamed.
+pub fn write_shard_records<W: Write>(mut writer: W, blocks: &[EncodedBlock]) -> io::Result<()> {
+    for block in blocks.iter() {
+        let symbol = block.data.data();
+        let symbol_size: u16 = match symbol.len().try_into() {
+            Ok(symbol_size) => symbol_size,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "symbol too large for a shard")),
+        };
+
+        writer.write_all(&block.block_id.to_le_bytes())?;
+        writer.write_all(&block.data.payload_id().serialize())?;
+        writer.write_all(&symbol_size.to_le_bytes())?;
+        writer.write_all(symbol)?;
+    }
+
+    return Ok(());
+}
+
+/// Reads the encoded blocks of a shard file written by write_shard. Fails with ErrorKind::InvalidData if it is not
+/// a shard, and ErrorKind::UnexpectedEof if it is truncated.
+pub fn read_shard<R: Read>(reader: R) -> io::Result<Vec<EncodedBlock>> {
+    return ShardReader::new(reader)?.collect();
+}
+
+/// Reads the encoded blocks of a shard one at a time, e.g. while it is still being streamed.
+pub struct ShardReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> ShardReader<R> {
+    /// Reads the magic. Fails with ErrorKind::InvalidData if reader does not hold a shard.
+    pub fn new(mut reader: R) -> io::Result<ShardReader<R>> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SHARD_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad shard: bad magic"));
+        }
+        return Ok(ShardReader { reader });
+    }
+
+    /// Reads the next block, or None at a clean end of the shard.
+    fn read_block(&mut self) -> io::Result<Option<EncodedBlock>> {
+        // a clean end of file can only fall between records
+        let mut block_id = [0u8; 4];
+        match self.reader.read(&mut block_id[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut block_id[1..])?,
+        }
+
+        let mut payload_id = [0u8; 4];
+        self.reader.read_exact(&mut payload_id)?;
+        let mut symbol_size = [0u8; 2];
+        self.reader.read_exact(&mut symbol_size)?;
+        let mut symbol = vec![0u8; u16::from_le_bytes(symbol_size) as usize];
+        self.reader.read_exact(&mut symbol)?;
+
+        return Ok(Some(EncodedBlock {
+            block_id: u32::from_le_bytes(block_id),
+            data: EncodingPacket::new(PayloadId::deserialize(&payload_id), symbol),
+        }));
+    }
+}
+
+impl<R: Read> Iterator for ShardReader<R> {
+    type Item = io::Result<EncodedBlock>;
+
+    fn next(&mut self) -> Option<io::Result<EncodedBlock>> {
+        return self.read_block().transpose();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::encoder::*;
+
+    #[test]
+    fn test_shard_round_trip() {
+        let data = gen_data(100 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let blocks = encoder.generate_encoded_blocks();
+
+        let mut written: Vec<u8> = Vec::new();
+        write_shard(&mut written, &blocks).unwrap();
+        assert_eq!(read_shard(&written[..]).unwrap(), blocks);
+
+        match read_shard(&written[..(written.len() - 1)]) {
+            Ok(_) => panic!("Should have failed to read a truncated shard"),
+            Err(error) => assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof),
+        }
+    }
+}
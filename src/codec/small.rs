@@ -0,0 +1,343 @@
+//! A fast path for objects of up to SMALL_OBJECT_LIMIT bytes, the bulk of what a CDN serves, where constant costs
+//! rather than throughput decide latency. Such an object always fits in a single block, so SmallEncoder and
+//! SmallDecoder drop what RaptorQEncoder and RaptorQDecoder keep per block for large objects: no shared, lockable
+//! encoder state, no backend or plan cache lookups, no per-sender or per-symbol accounting. Source symbols are cut
+//! straight from the data, the block's intermediate symbols are only computed once a repair symbol is asked for,
+//! and the decoder likewise copies source symbols into place, only creating a RaptorQ decoder once a repair symbol
+//! arrives. Over a lossless path, neither side does any RaptorQ math.
+//!
+//! The symbols and BlockInfo are exactly those RaptorQEncoder makes of the same object and config, so either side
+//! may take the fast path without the other.
+
+use raptorq::{extended_source_block_symbols, EncodingPacket, ObjectTransmissionInformation, PayloadId, SourceBlockDecoder, SourceBlockEncoder};
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use std::sync::{Mutex, OnceLock};
+
+use super::consts::*;
+use super::decoder::{BlockDecoder, RaptorQDecoderError};
+use super::encoder::{BlockInfo, EncodedBlock, EncoderConfig, RaptorQEncoderError};
+
+/// Largest object the fast path takes.
+pub const SMALL_OBJECT_LIMIT: usize = 64 * 1024;
+
+/// Encodes a small object as a single block, see the module documentation.
+pub struct SmallEncoder {
+    /// The padded block.
+    data: Vec<u8>,
+    /// EncoderConfig::elide_padding.
+    elide_padding: bool,
+    /// Created on the first repair symbol asked for.
+    encoder: OnceLock<SourceBlockEncoder>,
+    block_info: BlockInfo,
+    /// Generator for random repair symbol ids if the config has a seed, as BlockEncoder has.
+    rng: Option<Mutex<StdRng>>,
+}
+
+impl SmallEncoder {
+    /// Takes a copy of data to encode, failing with DataSizeTooLarge over SMALL_OBJECT_LIMIT. Panics if data is empty, which has no
+    /// symbols to encode.
+    pub fn new(config: EncoderConfig, data: &[u8]) -> Result<SmallEncoder, RaptorQEncoderError> {
+        assert!(!data.is_empty(), "cannot encode an empty object");
+        config.validate()?;
+        if data.len() > SMALL_OBJECT_LIMIT {
+            return Err(RaptorQEncoderError::DataSizeTooLarge);
+        }
+
+        let (symbol_size, symbol_count) = config.get_block_layout(data.len());
+        let padded_size = symbol_count * symbol_size as usize;
+        let oti = ObjectTransmissionInformation::new(padded_size as u64, symbol_size, 1, 1, config.alignment);
+        let mut padded = Vec::with_capacity(padded_size);
+        padded.extend_from_slice(data);
+        padded.resize(padded_size, 0);
+
+        return Ok(SmallEncoder {
+            data: padded,
+            elide_padding: config.elide_padding,
+            encoder: OnceLock::new(),
+            block_info: BlockInfo { payload_size: data.len(), padded_size, config: oti, block_id: 0 },
+            rng: config.seed.map(|x| Mutex::new(StdRng::seed_from_u64(x))),
+        });
+    }
+
+    /// Gets the block info decoders need, as RaptorQEncoder::get_block_info_vec would have it.
+    pub fn get_block_info(&self) -> &BlockInfo {
+        return &self.block_info;
+    }
+
+    pub fn get_symbol_count(&self) -> usize {
+        return self.block_info.padded_size / self.block_info.config.symbol_size() as usize;
+    }
+
+    /// Creates the source packets, i.e. the data itself split into packets, the last without its padding with
+    /// EncoderConfig::elide_padding.
+    pub fn generate_source_blocks(&self) -> Vec<EncodedBlock> {
+        let symbol_size = self.block_info.config.symbol_size() as usize;
+        let data = match self.elide_padding {
+            true => &self.data[..self.block_info.payload_size],
+            false => &self.data[..],
+        };
+        return data.chunks(symbol_size).enumerate()
+            .map(|(esi, x)| EncodedBlock { block_id: 0, data: EncodingPacket::new(PayloadId::new(0, esi as u32), x.to_vec()) })
+            .collect();
+    }
+
+    /// Creates count packets starting at repair symbol start_index, wrapping around at the end of the ESI space, as
+    /// BlockEncoder::generate_repair_blocks does.
+    pub fn generate_repair_blocks(&self, start_index: u32, count: usize) -> Vec<EncodedBlock> {
+        let repair_symbol_id_limit = self.get_repair_symbol_id_limit();
+        let start_index = start_index as usize % repair_symbol_id_limit;
+        let created = std::cmp::min(repair_symbol_id_limit - start_index, count);
+        let encoder = self.encoder.get_or_init(|| SourceBlockEncoder::new2(0, &self.block_info.config, &self.data));
+        let mut packets = encoder.repair_packets(start_index as u32, created as u32);
+        if created < count {
+            packets.append(&mut encoder.repair_packets(0, (count - created) as u32));
+        }
+        return packets.into_iter().map(|data| EncodedBlock { block_id: 0, data }).collect();
+    }
+
+    /// Creates as many repair packets as there are source symbols, starting at a random repair symbol drawn from the
+    /// seeded generator if the config has a seed.
+    pub fn generate_encoded_blocks(&self) -> Vec<EncodedBlock> {
+        let repair_symbol_id_limit = self.get_repair_symbol_id_limit();
+        let start_index = match self.rng.as_ref() {
+            None => thread_rng().gen_range(0..repair_symbol_id_limit),
+            Some(rng) => rng.lock().unwrap().gen_range(0..repair_symbol_id_limit),
+        };
+        return self.generate_repair_blocks(start_index as u32, self.get_symbol_count());
+    }
+
+    /// Returns true once the block's intermediate symbols were computed, i.e. a repair symbol was generated.
+    pub fn is_encoded(&self) -> bool {
+        return self.encoder.get().is_some();
+    }
+
+    /// Gets the number of repair symbol ids available, see BlockEncoder::get_repair_symbol_id_limit.
+    pub fn get_repair_symbol_id_limit(&self) -> usize {
+        return RAPTORQ_ENCODING_SYMBOL_ID_MAX - extended_source_block_symbols(self.get_symbol_count() as u32) as usize;
+    }
+}
+
+/// Decodes a small object, see the module documentation. Takes symbols from any encoder of a single block object.
+pub struct SmallDecoder {
+    block_info: BlockInfo,
+    symbol_count: usize,
+    /// The payload, source symbols copied in without their padding as they arrive.
+    data: Vec<u8>,
+    received: Vec<bool>,
+    /// Source symbols not received yet.
+    missing: usize,
+    /// Created on the first repair symbol, and fed every symbol from then on.
+    decoder: Option<SourceBlockDecoder>,
+    decoded: bool,
+}
+
+impl SmallDecoder {
+    /// Fails with InvalidBlockInfo unless block_info is valid, see BlockDecoder, and describes block 0 of an object
+    /// of up to SMALL_OBJECT_LIMIT bytes.
+    pub fn new(block_info: BlockInfo) -> Result<SmallDecoder, RaptorQDecoderError> {
+        if BlockDecoder::check_block_info(&block_info).is_err() || block_info.block_id != 0 || block_info.payload_size > SMALL_OBJECT_LIMIT {
+            return Err(RaptorQDecoderError::InvalidBlockInfo);
+        }
+        // a payload under the limit may still be padded to tiny symbols, keep scratch space within bounds too
+        if block_info.padded_size > SMALL_OBJECT_LIMIT + block_info.config.symbol_size() as usize {
+            return Err(RaptorQDecoderError::InvalidBlockInfo);
+        }
+
+        let symbol_count = block_info.padded_size / block_info.config.symbol_size() as usize;
+        return Ok(SmallDecoder {
+            data: vec![0; block_info.payload_size],
+            received: vec![false; symbol_count],
+            missing: symbol_count,
+            symbol_count,
+            block_info,
+            decoder: None,
+            decoded: false,
+        });
+    }
+
+    /// Feeds encoded blocks, returning true once the object is decoded. Fails with BadBlockId on a block other than
+    /// block 0; malformed symbols are skipped, as peers may send anything.
+    pub fn consume(&mut self, blocks: Vec<EncodedBlock>) -> Result<bool, RaptorQDecoderError> {
+        if blocks.iter().any(|x| x.block_id != 0) {
+            return Err(RaptorQDecoderError::BadBlockId);
+        }
+        if self.decoded {
+            return Ok(true);
+        }
+
+        let symbol_size = self.block_info.config.symbol_size() as usize;
+        // symbols for the RaptorQ decoder, if it is needed
+        let mut packets: Vec<EncodingPacket> = Vec::new();
+        for block in blocks {
+            let esi = block.data.payload_id().encoding_symbol_id() as usize;
+            if block.data.payload_id().source_block_number() != 0 {
+                continue;
+            }
+            if esi < self.symbol_count {
+                // with or without its padding, see EncoderConfig::elide_padding
+                let len = self.block_info.get_source_symbol_len(esi as u32);
+                if block.data.data().len() != symbol_size && block.data.data().len() != len {
+                    continue;
+                }
+                if !self.received[esi] {
+                    self.received[esi] = true;
+                    self.missing -= 1;
+                    self.data[(esi * symbol_size)..(esi * symbol_size + len)].copy_from_slice(&block.data.data()[..len]);
+                }
+                if self.decoder.is_some() {
+                    packets.push(self.get_source_packet(esi));
+                }
+            } else if block.data.data().len() != symbol_size {
+                continue;
+            } else if esi >= extended_source_block_symbols(self.symbol_count as u32) as usize {
+                packets.push(block.data);
+            }
+        }
+
+        if self.missing > 0 && !packets.is_empty() {
+            if self.decoder.is_none() {
+                self.decoder = Some(self.new_decoder());
+            }
+            let decoder = self.decoder.as_mut().unwrap();
+            if let Some(mut data) = decoder.decode(packets) {
+                data.truncate(self.block_info.payload_size);
+                self.data = data;
+                self.missing = 0;
+            }
+        }
+
+        if self.missing == 0 {
+            self.decoder = None;
+            self.decoded = true;
+        }
+        return Ok(self.decoded);
+    }
+
+    /// Creates the RaptorQ decoder, handing it the source symbols received so far.
+    fn new_decoder(&self) -> SourceBlockDecoder {
+        let mut decoder = SourceBlockDecoder::new2(0, &self.block_info.config, self.block_info.padded_size as u64);
+        let source: Vec<EncodingPacket> = self.received.iter().enumerate()
+            .filter(|(_, received)| **received)
+            .map(|(esi, _)| self.get_source_packet(esi))
+            .collect();
+        if !source.is_empty() {
+            // fewer than K source symbols never decode
+            let _ = decoder.decode(source);
+        }
+        return decoder;
+    }
+
+    /// Gets a received source symbol as a packet, padding included.
+    fn get_source_packet(&self, esi: usize) -> EncodingPacket {
+        let symbol_size = self.block_info.config.symbol_size() as usize;
+        let len = self.block_info.get_source_symbol_len(esi as u32);
+        let mut symbol = self.data[(esi * symbol_size)..(esi * symbol_size + len)].to_vec();
+        symbol.resize(symbol_size, 0);
+        return EncodingPacket::new(PayloadId::new(0, esi as u32), symbol);
+    }
+
+    pub fn is_decoded(&self) -> bool {
+        return self.decoded;
+    }
+
+    /// Gets the object, once consume returned true.
+    pub fn get_result(&self) -> Option<&[u8]> {
+        if !self.decoded {
+            return None;
+        }
+        return Some(&self.data);
+    }
+
+    /// Takes the object, once consume returned true.
+    pub fn into_result(self) -> Option<Vec<u8>> {
+        if !self.decoded {
+            return None;
+        }
+        return Some(self.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::decoder::RaptorQDecoder;
+    use crate::codec::encoder::{RaptorQEncoder, TailStrategy};
+    use std::io::IoSlice;
+
+    #[test]
+    fn test_small_object() {
+        let data = gen_data(50 * 1000 + 17);
+        let mut config = EncoderConfig::new(1280);
+        config.seed = Some(7);
+        let encoder = SmallEncoder::new(config, &data).unwrap();
+        let reference = RaptorQEncoder::with_config(config, &[IoSlice::new(&data)]).unwrap();
+
+        // the same object as the block path makes, symbols included
+        assert_eq!(reference.get_block_info_vec(), vec![encoder.get_block_info().clone()]);
+        let block = &reference.get_block_encoders()[0];
+        let mut source = block.generate_source_blocks();
+        source.sort();
+        assert_eq!(encoder.generate_source_blocks(), source);
+        assert!(!encoder.is_encoded());
+        let mut repair = block.generate_repair_blocks(3, 5);
+        repair.sort();
+        assert_eq!(encoder.generate_repair_blocks(3, 5), repair);
+        let mut encoded = block.generate_encoded_blocks();
+        encoded.sort();
+        let mut small_encoded = encoder.generate_encoded_blocks();
+        small_encoded.sort();
+        assert_eq!(small_encoded, encoded);
+
+        // source symbols only
+        let mut decoder = SmallDecoder::new(encoder.get_block_info().clone()).unwrap();
+        let mut source = encoder.generate_source_blocks();
+        let last = source.pop().unwrap();
+        assert!(!decoder.consume(source.clone()).unwrap());
+        assert!(decoder.get_result().is_none());
+        assert!(decoder.consume(vec![last]).unwrap());
+        assert_eq!(decoder.get_result().unwrap(), &data[..]);
+
+        // source symbols lost and made up with repair symbols, from either path
+        let mut decoder = SmallDecoder::new(encoder.get_block_info().clone()).unwrap();
+        assert!(!decoder.consume(source.into_iter().step_by(2).collect()).unwrap());
+        assert!(decoder.consume(block.generate_encoded_blocks()).unwrap());
+        assert_eq!(decoder.into_result().unwrap(), data);
+        let mut decoder = RaptorQDecoder::new(vec![encoder.get_block_info().clone()]).unwrap();
+        assert!(decoder.consume(encoder.generate_encoded_blocks()).unwrap());
+        assert_eq!(decoder.get_result().unwrap(), data);
+
+        // shrunk tail symbols, and malformed symbols skipped
+        let mut config = EncoderConfig::new(1280);
+        config.tail_strategy = TailStrategy::ShrinkSymbols;
+        let data = gen_data(3000);
+        let encoder = SmallEncoder::new(config, &data).unwrap();
+        let mut decoder = SmallDecoder::new(encoder.get_block_info().clone()).unwrap();
+        let bogus = EncodedBlock { block_id: 0, data: EncodingPacket::new(PayloadId::new(0, 0), vec![0; 3]) };
+        assert!(!decoder.consume(vec![bogus]).unwrap());
+        assert!(decoder.consume(encoder.generate_encoded_blocks()).unwrap());
+        assert_eq!(decoder.get_result().unwrap(), &data[..]);
+        assert_eq!(decoder.consume(vec![EncodedBlock { block_id: 1, data: encoder.generate_source_blocks()[0].data.clone() }]), Err(RaptorQDecoderError::BadBlockId));
+
+        // the tail symbol without its padding, on its own and handed to the RaptorQ decoder
+        let mut config = EncoderConfig::new(1280);
+        config.elide_padding = true;
+        let encoder = SmallEncoder::new(config, &data).unwrap();
+        let mut source = encoder.generate_source_blocks();
+        assert_eq!(source.last().unwrap().data.data().len(), 3000 - 2 * 1280);
+        let mut decoder = SmallDecoder::new(encoder.get_block_info().clone()).unwrap();
+        assert!(decoder.consume(source.clone()).unwrap());
+        assert_eq!(decoder.get_result().unwrap(), &data[..]);
+        let mut decoder = SmallDecoder::new(encoder.get_block_info().clone()).unwrap();
+        source.remove(0);
+        assert!(!decoder.consume(source).unwrap());
+        assert!(decoder.consume(encoder.generate_repair_blocks(0, 1)).unwrap());
+        assert_eq!(decoder.get_result().unwrap(), &data[..]);
+
+        assert_eq!(SmallEncoder::new(config, &gen_data(SMALL_OBJECT_LIMIT + 1)).err(), Some(RaptorQEncoderError::DataSizeTooLarge));
+        let mut block_info = encoder.get_block_info().clone();
+        block_info.block_id = 1;
+        assert!(SmallDecoder::new(block_info).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! proptest strategies for codec types, for property-testing pipelines built on them.
+//! Strategies named valid_* only produce instances an encoder could have produced; the Arbitrary impls produce
+//! any value, including adversarial ones a malicious peer could send.
+
+use proptest::arbitrary::Arbitrary;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use raptorq::{extended_source_block_symbols, EncodingPacket, ObjectTransmissionInformation, PayloadId};
+
+use super::consts::*;
+use super::encoder::{BlockInfo, EncodedBlock};
+
+/// Packet sizes accepted by the encoder, up to a typical MTU.
+pub fn valid_packet_size() -> impl Strategy<Value = u16> {
+    return ((MIN_PACKET_SIZE / ALIGNMENT as u16)..=(1500 / ALIGNMENT as u16)).prop_map(|x| x * ALIGNMENT as u16);
+}
+
+/// Block infos as produced by BlockEncoder, for blocks of at most max_symbols symbols.
+pub fn valid_block_info(max_symbols: usize) -> impl Strategy<Value = BlockInfo> {
+    return (valid_packet_size(), 1..=max_symbols, any::<u32>()).prop_flat_map(|(packet_size, symbols, block_id)| {
+        let padded_size = symbols * packet_size as usize;
+        let min_payload_size = padded_size - packet_size as usize + 1;
+        return (min_payload_size..=padded_size).prop_map(move |payload_size| BlockInfo {
+            payload_size,
+            padded_size,
+            config: ObjectTransmissionInformation::new(padded_size as u64, packet_size, 1, 1, ALIGNMENT),
+            block_id,
+        });
+    });
+}
+
+/// Encoded blocks a decoder for block_info would accept: well-sized source or repair symbols with random contents.
+pub fn valid_encoded_block(block_info: &BlockInfo) -> impl Strategy<Value = EncodedBlock> {
+    let symbol_size = block_info.config.symbol_size() as usize;
+    let symbol_count = (block_info.padded_size / symbol_size) as u32;
+    let extended_symbol_count = extended_source_block_symbols(symbol_count);
+    let block_id = block_info.block_id;
+
+    let esi = prop_oneof![
+        0..symbol_count,
+        extended_symbol_count..(RAPTORQ_ENCODING_SYMBOL_ID_MAX as u32),
+    ];
+    return (esi, vec(any::<u8>(), symbol_size)).prop_map(move |(esi, data)| EncodedBlock {
+        block_id,
+        data: EncodingPacket::new(PayloadId::new(0, esi), data),
+    });
+}
+
+impl Arbitrary for BlockInfo {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<BlockInfo>;
+
+    fn arbitrary_with(_: ()) -> BoxedStrategy<BlockInfo> {
+        // ObjectTransmissionInformation::deserialize skips the checks new() asserts.
+        return (any::<usize>(), any::<usize>(), any::<[u8; 12]>(), any::<u32>())
+            .prop_map(|(payload_size, padded_size, config, block_id)| BlockInfo {
+                payload_size,
+                padded_size,
+                config: ObjectTransmissionInformation::deserialize(&config),
+                block_id,
+            })
+            .boxed();
+    }
+}
+
+impl Arbitrary for EncodedBlock {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<EncodedBlock>;
+
+    fn arbitrary_with(_: ()) -> BoxedStrategy<EncodedBlock> {
+        return (any::<u32>(), any::<[u8; 4]>(), vec(any::<u8>(), 0..2048))
+            .prop_map(|(block_id, payload_id, data)| EncodedBlock {
+                block_id,
+                data: EncodingPacket::new(PayloadId::deserialize(&payload_id), data),
+            })
+            .boxed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::decoder::*;
+    use super::super::encoder::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn prop_encode_decode_round_trip(packet_size in valid_packet_size(), data in vec(any::<u8>(), 1..(32 * 1024))) {
+            let encoder = RaptorQEncoder::new(packet_size, &data).unwrap();
+            let mut decoder = RaptorQDecoder::new(encoder.get_block_info_vec()).unwrap();
+
+            let mut blocks = encoder.generate_encoded_blocks();
+            blocks.append(&mut encoder.generate_encoded_blocks());
+            prop_assert_eq!(decoder.consume(blocks), Ok(true));
+            prop_assert_eq!(decoder.get_result(), Some(data));
+        }
+
+        #[test]
+        fn prop_block_decoder_rejects_adversarial_block_info(block_info in any::<BlockInfo>()) {
+            // must not panic, whatever the peer sent
+            let _ = BlockDecoder::new(block_info);
+        }
+
+        #[test]
+        fn prop_block_decoder_survives_adversarial_packets(
+            (block_info, mut valid) in valid_block_info(64).prop_flat_map(|x| (Just(x.clone()), vec(valid_encoded_block(&x), 0..8))),
+            adversarial in vec(any::<EncodedBlock>(), 0..8),
+        ) {
+            let mut decoder = BlockDecoder::new(block_info.clone()).unwrap();
+
+            let block_id = block_info.block_id;
+            let mut blocks: Vec<EncodedBlock> = adversarial.into_iter().map(|x| EncodedBlock { block_id, data: x.data }).collect();
+            blocks.append(&mut valid);
+            prop_assert!(decoder.consume(blocks).is_ok());
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Unbounded streams, e.g. logs or telemetry feeds, carried as a sliding window of blocks. The sender pushes data a
+//! block at a time and sends symbols of every block in its window; the oldest block is retired as a new one is
+//! pushed, or earlier once receivers have it. The receiver decodes the blocks of its window and delivers them in
+//! order, giving up on a block once the window moves past it.
+//!
+//! Block ids count up from 0 and are u32 on the wire, so a stream carries at most 2^32 blocks.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+
+use super::decoder::{BlockDecoder, RaptorQDecoderError};
+use super::encoder::{BlockEncoder, BlockInfo, EncodedBlock, EncoderConfig, RaptorQEncoderError};
+
+/// Sending side of a stream, see the module documentation.
+pub struct StreamEncoder {
+    config: EncoderConfig,
+    window_size: usize,
+    /// Blocks in the window, oldest first, with the next repair symbol to send of each.
+    blocks: VecDeque<(BlockEncoder, u32)>,
+    next_block_id: u32,
+}
+
+impl StreamEncoder {
+    /// Creates a StreamEncoder keeping at most window_size blocks. Panics if window_size is zero.
+    pub fn new(config: EncoderConfig, window_size: usize) -> StreamEncoder {
+        assert!(window_size > 0, "window size must be positive");
+        return StreamEncoder {
+            config,
+            window_size,
+            blocks: VecDeque::new(),
+            next_block_id: 0,
+        };
+    }
+
+    /// Encodes data as the next block, retiring the oldest block if the window is full. Returns the block's info,
+    /// which receivers need before they can decode it. Panics if data is empty.
+    pub fn push(&mut self, data: Vec<u8>) -> Result<BlockInfo, RaptorQEncoderError> {
+        assert!(!data.is_empty(), "cannot push an empty block");
+        let block_encoder = BlockEncoder::with_config(self.next_block_id, self.config, data)?;
+        let block_info = block_encoder.get_block_info();
+
+        if self.blocks.len() == self.window_size {
+            self.blocks.pop_front();
+        }
+        let cursor = block_encoder.gen_repair_index() as u32;
+        self.blocks.push_back((block_encoder, cursor));
+        self.next_block_id += 1;
+        return Ok(block_info);
+    }
+
+    /// Retires the blocks before block_id, e.g. once every receiver has them, so no more symbols are spent on them.
+    pub fn retire_before(&mut self, block_id: u32) {
+        while self.blocks.front().is_some_and(|(x, _)| x.get_block_info().block_id < block_id) {
+            self.blocks.pop_front();
+        }
+    }
+
+    /// Gets the ids of the blocks in the window.
+    pub fn get_window(&self) -> Range<u32> {
+        return (self.next_block_id - self.blocks.len() as u32)..self.next_block_id;
+    }
+
+    /// Gets the encoder of a block in the window.
+    pub fn get_block_encoder(&self, block_id: u32) -> Option<&BlockEncoder> {
+        let index = block_id.checked_sub(self.get_window().start)?;
+        return self.blocks.get(index as usize).map(|(x, _)| x);
+    }
+
+    /// Generates count repair symbols of each block in the window, newest block first so fresh data gets out even if
+    /// the sender falls behind, never repeating a symbol of a block.
+    pub fn next_repair_symbols(&mut self, count: usize) -> Vec<EncodedBlock> {
+        let mut blocks: Vec<EncodedBlock> = Vec::with_capacity(count * self.blocks.len());
+        for (block_encoder, cursor) in self.blocks.iter_mut().rev() {
+            blocks.append(&mut block_encoder.generate_repair_blocks(*cursor, count));
+            *cursor = ((*cursor as usize + count) % block_encoder.get_repair_symbol_id_limit()) as u32;
+        }
+        return blocks;
+    }
+}
+
+/// Counts of what a StreamDecoder did with the blocks and symbols it got.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Blocks handed out by next_block.
+    pub delivered: u64,
+    /// Blocks the window moved past before they were decoded.
+    pub lost: u64,
+    /// Symbols of blocks behind the window or not opened yet, which were dropped.
+    pub dropped_symbols: u64,
+}
+
+/// Receiving side of a stream, see the module documentation.
+pub struct StreamDecoder {
+    window_size: usize,
+    /// Blocks opened in the window, by block id.
+    blocks: BTreeMap<u32, BlockDecoder>,
+    /// First block of the window; blocks before it were delivered or lost.
+    window_start: u32,
+    stats: StreamStats,
+}
+
+impl StreamDecoder {
+    /// Creates a StreamDecoder keeping at most window_size blocks, which should match the sender's. Panics if
+    /// window_size is zero.
+    pub fn new(window_size: usize) -> StreamDecoder {
+        assert!(window_size > 0, "window size must be positive");
+        return StreamDecoder {
+            window_size,
+            blocks: BTreeMap::new(),
+            window_start: 0,
+            stats: StreamStats::default(),
+        };
+    }
+
+    /// Starts decoding a block the sender pushed. A block past the end of the window advances the window, losing
+    /// the blocks that fall out of it undecoded. Blocks behind the window and blocks already open are ignored.
+    pub fn open_block(&mut self, block_info: BlockInfo) -> Result<(), RaptorQDecoderError> {
+        let block_id = block_info.block_id;
+        if block_id < self.window_start || self.blocks.contains_key(&block_id) {
+            return Ok(());
+        }
+
+        let block_decoder = BlockDecoder::new(block_info)?;
+        let window_end = self.window_start as u64 + self.window_size as u64;
+        if block_id as u64 >= window_end {
+            self.advance_to(block_id - (self.window_size as u32 - 1));
+        }
+        self.blocks.insert(block_id, block_decoder);
+        return Ok(());
+    }
+
+    /// Feeds symbols of the blocks in the window. Symbols of other blocks are dropped.
+    pub fn consume(&mut self, blocks: Vec<EncodedBlock>) -> Result<(), RaptorQDecoderError> {
+        let mut by_block: BTreeMap<u32, Vec<EncodedBlock>> = BTreeMap::new();
+        for block in blocks {
+            by_block.entry(block.block_id).or_default().push(block);
+        }
+
+        for (block_id, blocks) in by_block {
+            match self.blocks.get_mut(&block_id) {
+                None => self.stats.dropped_symbols += blocks.len() as u64,
+                Some(block_decoder) => {
+                    block_decoder.consume(blocks)?;
+                },
+            }
+        }
+        return Ok(());
+    }
+
+    /// Takes the first block of the window once it is decoded, returning its id and payload, so blocks come out in
+    /// order. Call until it returns None after each consume.
+    pub fn next_block(&mut self) -> Option<(u32, Vec<u8>)> {
+        let block_id = self.window_start;
+        let data = self.blocks.get(&block_id)?.get_result()?.to_vec();
+        self.blocks.remove(&block_id);
+        self.window_start += 1;
+        self.stats.delivered += 1;
+        return Some((block_id, data));
+    }
+
+    /// Moves the window to start at block_id, giving up on the blocks before it, e.g. when the sender retired them.
+    pub fn advance_to(&mut self, block_id: u32) {
+        if block_id <= self.window_start {
+            return;
+        }
+
+        let kept = self.blocks.split_off(&block_id);
+        // blocks never opened are lost too
+        self.stats.lost += (block_id - self.window_start) as u64;
+        self.blocks = kept;
+        self.window_start = block_id;
+    }
+
+    /// Gets the ids of the blocks the decoder accepts symbols for, once opened.
+    pub fn get_window(&self) -> Range<u32> {
+        return self.window_start..self.window_start.saturating_add(self.window_size as u32);
+    }
+
+    pub fn get_stats(&self) -> StreamStats {
+        return self.stats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+
+    #[test]
+    fn test_stream_window() {
+        let mut encoder = StreamEncoder::new(EncoderConfig::new(1280), 3);
+        let mut decoder = StreamDecoder::new(3);
+        let chunks: Vec<Vec<u8>> = (0..6).map(|_| gen_data(10 * 1000)).collect();
+
+        let mut delivered: Vec<(u32, Vec<u8>)> = Vec::new();
+        for (block_id, chunk) in chunks.iter().enumerate() {
+            let block_info = match encoder.push(chunk.clone()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            };
+            assert_eq!(encoder.get_window(), (block_id.saturating_sub(2) as u32)..(block_id as u32 + 1));
+
+            // everything of block 1 is lost on the way
+            decoder.open_block(block_info).unwrap();
+            let mut blocks = encoder.next_repair_symbols(20);
+            blocks.retain(|x| x.block_id != 1);
+            decoder.consume(blocks).unwrap();
+            while let Some(block) = decoder.next_block() {
+                delivered.push(block);
+            }
+            if block_id == 3 {
+                assert_eq!(delivered.len(), 1);
+            }
+        }
+
+        // blocks 2 and 3 waited on block 1 until block 4 moved the window past it
+        let delivered_ids: Vec<u32> = delivered.iter().map(|x| x.0).collect();
+        assert_eq!(delivered_ids, vec![0, 2, 3, 4, 5]);
+        assert_eq!(decoder.get_window(), 6..9);
+        assert!(delivered.iter().all(|(block_id, data)| *data == chunks[*block_id as usize]));
+        let stats = decoder.get_stats();
+        assert_eq!((stats.delivered, stats.lost), (5, 1));
+
+        // symbols of blocks behind the window are dropped, and retiring moves the sender's window
+        decoder.consume(encoder.get_block_encoder(3).unwrap().generate_repair_blocks(0, 2)).unwrap();
+        assert_eq!(decoder.get_stats().dropped_symbols, stats.dropped_symbols + 2);
+        encoder.retire_before(5);
+        assert_eq!(encoder.get_window(), 5..6);
+        assert!(encoder.get_block_encoder(4).is_none());
+    }
+}
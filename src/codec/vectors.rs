@@ -0,0 +1,327 @@
+//! Golden test vectors, so changes to the manifest or packet formats, or to the symbols an encoder produces, that
+//! would break interop with deployed nodes are caught, and other implementations have something to conform to.
+//!
+//! A vector is a directory holding:
+//! - spec: how the vector was generated, one key=value per line, see VectorSpec.
+//! - input: the object.
+//! - manifest: its manifest, as written by Manifest::write_to.
+//! - packets: a shard, as written by write_shard, of the packets a decoder receives: the source symbols surviving
+//!   loss_percent loss, as many repair symbols as were lost plus two, shuffled.
+//!
+//! Decoding packets with manifest has to give input back. Everything is drawn from the spec's seed, the encoders
+//! included, see EncoderConfig::seed, so generating a vector again from its spec gives the same bytes unless the
+//! encoder changed. Vectors of a format version live under one directory, e.g. spec/vectors/v1.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::fs;
+use std::io::{self, IoSlice};
+use std::path::Path;
+
+use super::decoder::RaptorQDecoder;
+use super::encoder::{EncodedBlock, EncoderConfig, RaptorQEncoder, TailStrategy};
+use super::manifest::Manifest;
+use super::shard::{read_shard, write_shard};
+
+/// Version of the vector format, and of the manifest and packet formats the vectors hold.
+pub const VECTORS_VERSION: u32 = 1;
+
+/// Repair symbols a vector's packets have beyond the lost source symbols.
+const VECTOR_EXTRA_SYMBOLS: usize = 2;
+
+/// How a vector is generated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VectorSpec {
+    /// Name of the vector's directory.
+    pub name: String,
+    pub data_size: usize,
+    /// The seed is ignored, seed is used instead.
+    pub config: EncoderConfig,
+    /// Seed for the object, the packets kept and the encoders.
+    pub seed: u64,
+    /// Share of the source symbols lost, in percent.
+    pub loss_percent: u8,
+}
+
+impl VectorSpec {
+    /// Writes the spec as key=value lines, without the name, which is the directory's.
+    fn to_text(&self) -> String {
+        let tail_strategy = match self.config.tail_strategy {
+            TailStrategy::Pad => "pad",
+            TailStrategy::ShrinkSymbols => "shrink_symbols",
+        };
+        return format!(
+            "version={}\ndata_size={}\npacket_size={}\nalignment={}\ntail_strategy={}\nseed={}\nloss_percent={}\n",
+            VECTORS_VERSION,
+            self.data_size,
+            self.config.packet_size,
+            self.config.alignment,
+            tail_strategy,
+            self.seed,
+            self.loss_percent,
+        );
+    }
+
+    /// Reads a spec written by to_text. Fails with ErrorKind::InvalidData if a key is missing or unknown, or the
+    /// version is not VECTORS_VERSION.
+    fn from_text(name: &str, text: &str) -> io::Result<VectorSpec> {
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, format!("bad vector spec: {}", reason));
+
+        let mut spec = VectorSpec { name: name.to_string(), data_size: 0, config: EncoderConfig::new(0), seed: 0, loss_percent: 0 };
+        let mut version: Option<u32> = None;
+        let mut seen: Vec<&str> = Vec::new();
+        for line in text.lines().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            let (key, value) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => return Err(invalid(format!("{} is not key=value", line))),
+            };
+            let bad_value = || invalid(format!("bad {}: {}", key, value));
+            match key {
+                "version" => version = Some(value.parse().map_err(|_| bad_value())?),
+                "data_size" => spec.data_size = value.parse().map_err(|_| bad_value())?,
+                "packet_size" => spec.config.packet_size = value.parse().map_err(|_| bad_value())?,
+                "alignment" => spec.config.alignment = value.parse().map_err(|_| bad_value())?,
+                "tail_strategy" => spec.config.tail_strategy = match value {
+                    "pad" => TailStrategy::Pad,
+                    "shrink_symbols" => TailStrategy::ShrinkSymbols,
+                    _ => return Err(bad_value()),
+                },
+                "seed" => spec.seed = value.parse().map_err(|_| bad_value())?,
+                "loss_percent" => spec.loss_percent = value.parse().ok().filter(|x| *x <= 100).ok_or_else(bad_value)?,
+                _ => return Err(invalid(format!("unknown key {}", key))),
+            }
+            seen.push(key);
+        }
+
+        for key in ["version", "data_size", "packet_size", "alignment", "tail_strategy", "seed", "loss_percent"].iter() {
+            if !seen.contains(key) {
+                return Err(invalid(format!("no {}", key)));
+            }
+        }
+        if version != Some(VECTORS_VERSION) {
+            return Err(invalid(format!("version {} is not {}", version.unwrap(), VECTORS_VERSION)));
+        }
+        return Ok(spec);
+    }
+}
+
+/// Gets the specs of the vectors shipped under spec/vectors.
+pub fn get_default_specs() -> Vec<VectorSpec> {
+    let spec = |name: &str, data_size: usize, packet_size: u16, tail_strategy: TailStrategy, seed: u64, loss_percent: u8| {
+        let mut config = EncoderConfig::new(packet_size);
+        config.tail_strategy = tail_strategy;
+        VectorSpec { name: name.to_string(), data_size, config, seed, loss_percent }
+    };
+    return vec![
+        spec("pad", 3000, 512, TailStrategy::Pad, 1, 30),
+        spec("shrink-symbols", 3000, 512, TailStrategy::ShrinkSymbols, 2, 30),
+        spec("repair-only", 9000, 1024, TailStrategy::Pad, 3, 100),
+        spec("lossless", 1000, 512, TailStrategy::Pad, 4, 0),
+    ];
+}
+
+/// What is wrong with a vector, see TestVector::check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VectorFailure {
+    /// The manifest can not be read.
+    BadManifest(String),
+    /// The packets can not be read.
+    BadPackets(String),
+    /// The manifest or packets read fine, but are not written back the same.
+    NotCanonical,
+    /// The packets don't decode.
+    DecodeFailed(String),
+    /// The packets decode to something other than the input.
+    WrongOutput,
+    /// Generating the vector again from its spec gives other bytes, i.e. the encoder changed.
+    Regenerated { manifest: bool, packets: bool },
+}
+
+/// A test vector, see the module documentation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestVector {
+    pub spec: VectorSpec,
+    pub input: Vec<u8>,
+    /// The serialized manifest.
+    pub manifest: Vec<u8>,
+    /// The serialized packets, as a shard.
+    pub packets: Vec<u8>,
+}
+
+impl TestVector {
+    /// Generates a vector from spec. Fails with ErrorKind::InvalidInput if the spec's config is not valid.
+    pub fn generate(spec: &VectorSpec) -> io::Result<TestVector> {
+        let mut rng = StdRng::seed_from_u64(spec.seed);
+        let mut input = vec![0u8; spec.data_size];
+        rng.fill(&mut input[..]);
+
+        let mut config = spec.config;
+        config.seed = Some(spec.seed);
+        let encoder = match RaptorQEncoder::with_config(config, &[IoSlice::new(&input)]) {
+            Ok(encoder) => encoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("bad vector spec: {:?}", error))),
+        };
+        let mut symbols: Vec<EncodedBlock> = Vec::new();
+        for block_encoder in encoder.get_block_encoders().iter() {
+            let source = block_encoder.generate_source_blocks();
+            let source_count = source.len();
+            symbols.extend(source.into_iter().filter(|_| rng.gen_range(0..100) >= spec.loss_percent));
+            let lost = source_count - (symbols.iter().filter(|x| x.block_id == block_encoder.get_block_info().block_id).count());
+            if lost > 0 {
+                symbols.extend(block_encoder.generate_encoded_blocks().into_iter().take(lost + VECTOR_EXTRA_SYMBOLS));
+            }
+        }
+        symbols.shuffle(&mut rng);
+
+        let mut manifest: Vec<u8> = Vec::new();
+        Manifest::new(&encoder).write_to(&mut manifest)?;
+        let mut packets: Vec<u8> = Vec::new();
+        write_shard(&mut packets, &symbols)?;
+        return Ok(TestVector { spec: spec.clone(), input, manifest, packets });
+    }
+
+    /// Reads the vector in dir, named after it.
+    pub fn read_from_dir(dir: &Path) -> io::Result<TestVector> {
+        let name = match dir.file_name().and_then(|x| x.to_str()) {
+            Some(name) => name,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a vector directory", dir.display()))),
+        };
+        return Ok(TestVector {
+            spec: VectorSpec::from_text(name, &fs::read_to_string(dir.join("spec"))?)?,
+            input: fs::read(dir.join("input"))?,
+            manifest: fs::read(dir.join("manifest"))?,
+            packets: fs::read(dir.join("packets"))?,
+        });
+    }
+
+    /// Writes the vector to a directory named after it under dir.
+    pub fn write_to_dir(&self, dir: &Path) -> io::Result<()> {
+        let dir = dir.join(&self.spec.name);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("spec"), self.spec.to_text())?;
+        fs::write(dir.join("input"), &self.input)?;
+        fs::write(dir.join("manifest"), &self.manifest)?;
+        fs::write(dir.join("packets"), &self.packets)?;
+        return Ok(());
+    }
+
+    /// Checks that the manifest and packets read and write back the same, that the packets decode to the input,
+    /// and that the vector generates the same again. Returns everything found wrong.
+    pub fn check(&self) -> Vec<VectorFailure> {
+        let mut failures: Vec<VectorFailure> = Vec::new();
+        let manifest = Manifest::read_from(&self.manifest[..]).map_err(|error| VectorFailure::BadManifest(error.to_string()));
+        let symbols = read_shard(&self.packets[..]).map_err(|error| VectorFailure::BadPackets(error.to_string()));
+        let (manifest, symbols) = match (manifest, symbols) {
+            (Ok(manifest), Ok(symbols)) => (manifest, symbols),
+            (manifest, symbols) => {
+                failures.extend(manifest.err());
+                failures.extend(symbols.err());
+                return failures;
+            },
+        };
+
+        let mut manifest_written: Vec<u8> = Vec::new();
+        let mut packets_written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut manifest_written).unwrap();
+        write_shard(&mut packets_written, &symbols).unwrap();
+        if manifest_written != self.manifest || packets_written != self.packets {
+            failures.push(VectorFailure::NotCanonical);
+        }
+
+        let decoded = RaptorQDecoder::from_manifest(&manifest).and_then(|mut decoder| {
+            decoder.consume(symbols)?;
+            return Ok(decoder.get_result());
+        });
+        match decoded {
+            Ok(Some(output)) if output == self.input => (),
+            Ok(Some(_)) => failures.push(VectorFailure::WrongOutput),
+            Ok(None) => failures.push(VectorFailure::DecodeFailed("too few symbols".to_string())),
+            Err(error) => failures.push(VectorFailure::DecodeFailed(format!("{:?}", error))),
+        }
+
+        match TestVector::generate(&self.spec) {
+            Ok(regenerated) if regenerated.manifest == self.manifest && regenerated.packets == self.packets => (),
+            Ok(regenerated) => failures.push(VectorFailure::Regenerated {
+                manifest: regenerated.manifest != self.manifest,
+                packets: regenerated.packets != self.packets,
+            }),
+            Err(_) => failures.push(VectorFailure::Regenerated { manifest: true, packets: true }),
+        }
+        return failures;
+    }
+}
+
+/// Generates the vectors of specs into dir, checking each first. Fails with ErrorKind::InvalidData if one does not
+/// check out, e.g. its packets don't decode.
+pub fn write_vectors(dir: &Path, specs: &[VectorSpec]) -> io::Result<()> {
+    for spec in specs.iter() {
+        let vector = TestVector::generate(spec)?;
+        let failures = vector.check();
+        if !failures.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("vector {} fails: {:?}", spec.name, failures)));
+        }
+        vector.write_to_dir(dir)?;
+    }
+    return Ok(());
+}
+
+/// Checks every vector under dir, in name order. Returns the name of each vector with what is wrong with it.
+pub fn run_vectors(dir: &Path) -> io::Result<Vec<(String, Vec<VectorFailure>)>> {
+    let mut dirs: Vec<_> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+
+    let mut results: Vec<(String, Vec<VectorFailure>)> = Vec::with_capacity(dirs.len());
+    for path in dirs.iter() {
+        let vector = TestVector::read_from_dir(path)?;
+        results.push((vector.spec.name.clone(), vector.check()));
+    }
+    return Ok(results);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_golden_vectors() {
+        let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("spec/vectors").join(format!("v{}", VECTORS_VERSION));
+        let results = run_vectors(&golden).unwrap();
+        let names: Vec<String> = results.iter().map(|x| x.0.clone()).collect();
+        let mut expected: Vec<String> = get_default_specs().into_iter().map(|x| x.name).collect();
+        expected.sort();
+        assert_eq!(names, expected);
+        for (name, failures) in results.iter() {
+            assert!(failures.is_empty(), "vector {} fails: {:?}", name, failures);
+        }
+
+        // generated vectors round trip through a directory
+        let dir = std::env::temp_dir().join(format!("raptorcdn-vectors-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let specs = get_default_specs();
+        write_vectors(&dir, &specs[..1]).unwrap();
+        let vector = TestVector::read_from_dir(&dir.join(&specs[0].name)).unwrap();
+        assert_eq!(vector, TestVector::generate(&specs[0]).unwrap());
+
+        // damage is caught
+        let mut damaged = vector.clone();
+        damaged.input[0] ^= 1;
+        assert_eq!(damaged.check(), vec![VectorFailure::WrongOutput]);
+        let mut damaged = vector.clone();
+        damaged.spec.seed += 1;
+        assert_eq!(damaged.check(), vec![VectorFailure::Regenerated { manifest: true, packets: true }]);
+        let mut damaged = vector.clone();
+        damaged.manifest.truncate(damaged.manifest.len() - 1);
+        assert!(matches!(damaged.check()[..], [VectorFailure::BadManifest(_)]));
+        assert!(VectorSpec::from_text("pad", &vector.spec.to_text().replace("version=1", "version=2")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
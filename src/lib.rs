@@ -0,0 +1,14 @@
+#![allow(clippy::needless_return)]
+
+#[cfg(feature = "client")]
+pub mod client;
+pub mod codec;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod sim;
+#[cfg(feature = "store")]
+pub mod store;
+#[cfg(feature = "transport")]
+pub mod transport;
+#[cfg(test)]
+pub(crate) mod test_util;
@@ -1,5 +1,93 @@
-mod codec;
+#![allow(clippy::needless_return)]
+
+use clap::{Parser, Subcommand};
+
+mod cli;
+
+#[derive(Parser)]
+#[command(name = "raptor-cdn", about = "RaptorQ based CDN node and tools")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Continuously encode and decode random objects, watching resource usage for leaks.
+    Soak(cli::soak::SoakArgs),
+    /// Pack many small files into one object, or extract them again.
+    Bundle(cli::bundle::BundleArgs),
+    /// Manage the encoding plan cache.
+    PlanCache(cli::plan_cache::PlanCacheArgs),
+    /// Print the contents of a manifest or shard file.
+    Inspect(cli::inspect::InspectArgs),
+    /// Measure encode and decode throughput over a matrix of sizes, packet sizes and loss rates.
+    Bench(cli::bench::BenchArgs),
+    /// Serve the files of a directory, picking up changes without restarting. Notifies systemd of readiness when run
+    /// as a Type=notify service.
+    Serve(Box<cli::serve::ServeArgs>),
+    /// Sign a token granting access to an object on a server started with --token-key-file.
+    Token(cli::token::TokenArgs),
+    /// Sign a notice purging an object from servers started with --purge-key-file, and send it to them.
+    Purge(cli::purge::PurgeArgs),
+    /// Encode a file or stdin block by block into a shard and a manifest.
+    Encode(cli::encode::EncodeArgs),
+    /// Decode an object from shards, writing its blocks in order as they are decoded.
+    Decode(cli::decode::DecodeArgs),
+    /// List the shards a storage node holds by object, and with manifests check every block still decodes to its
+    /// hash.
+    Audit(cli::audit::AuditArgs),
+    /// Archive a file for cold storage, as a tar of its manifest and shards with a chosen overhead of symbols.
+    Export(cli::export::ExportArgs),
+    /// Verify an archive written by export, or restore the file from it.
+    Import(cli::import::ImportArgs),
+    /// Check the golden test vectors of a directory decode as they should and still generate the same, or generate
+    /// them.
+    Vectors(cli::vectors::VectorsArgs),
+    /// Check another implementation against this one both ways, through its results for the golden vectors or a
+    /// server of its, and print a compliance matrix.
+    Conformance(cli::conformance::ConformanceArgs),
+    /// Serve encode, fetch, encode job and status requests from local processes as JSON-RPC over a UNIX socket,
+    /// keeping caches warm between them.
+    #[cfg(unix)]
+    Daemon(cli::daemon::DaemonArgs),
+    /// Encode a file on several daemons at once, each encoding a range of its blocks into a shard of its own.
+    #[cfg(unix)]
+    Farm(cli::farm::FarmArgs),
+    /// Watch a daemon's transfers, peers and caches live in the terminal.
+    #[cfg(all(unix, feature = "top"))]
+    Top(cli::top::TopArgs),
+}
 
 fn main() {
-    println!("I do nothing for now.");
-}
\ No newline at end of file
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Soak(args) => cli::soak::run(args),
+        Command::Bundle(args) => cli::bundle::run(args),
+        Command::PlanCache(args) => cli::plan_cache::run(args),
+        Command::Inspect(args) => cli::inspect::run(args),
+        Command::Bench(args) => cli::bench::run(args),
+        Command::Serve(args) => cli::serve::run(*args),
+        Command::Token(args) => cli::token::run(args),
+        Command::Purge(args) => cli::purge::run(args),
+        Command::Encode(args) => cli::encode::run(args),
+        Command::Decode(args) => cli::decode::run(args),
+        Command::Audit(args) => cli::audit::run(args),
+        Command::Export(args) => cli::export::run(args),
+        Command::Import(args) => cli::import::run(args),
+        Command::Vectors(args) => cli::vectors::run(args),
+        Command::Conformance(args) => cli::conformance::run(args),
+        #[cfg(unix)]
+        Command::Daemon(args) => cli::daemon::run(args),
+        #[cfg(unix)]
+        Command::Farm(args) => cli::farm::run(args),
+        #[cfg(all(unix, feature = "top"))]
+        Command::Top(args) => cli::top::run(args),
+    };
+
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
+}
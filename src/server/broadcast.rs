@@ -0,0 +1,142 @@
+//! Broadcast of the objects of catalogs over UDP, e.g. to a multicast group, next to serving them over HTTP. Each
+//! object is a flow of a Carousel repeating its manifest in-band, so receivers with a FlowDemux set to join in
+//! progress need nothing but the datagrams. Flow ids are taken from object ids, so a receiver after one object
+//! knows which flow to look for.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::codec::manifest::ObjectId;
+use crate::transport::carousel::Carousel;
+use crate::transport::queue::SendQueue;
+use crate::transport::udp::{FlowId, Integrity};
+use super::catalog::CatalogEntry;
+
+/// Gets the flow an object is broadcast as: the first 8 bytes of its id, little endian.
+pub fn get_flow_id(object_id: &ObjectId) -> FlowId {
+    return FlowId::from_le_bytes(object_id[..8].try_into().unwrap());
+}
+
+/// Flows added to and removed from a CatalogBroadcast by a sync.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BroadcastChanges {
+    pub added: Vec<FlowId>,
+    pub removed: Vec<FlowId>,
+}
+
+/// Keeps a Carousel cycling the objects of catalogs, see the module documentation.
+pub struct CatalogBroadcast {
+    carousel: Carousel,
+    /// Object each flow carries, to notice objects replaced by others of the same flow.
+    flows: HashMap<FlowId, ObjectId>,
+}
+
+impl CatalogBroadcast {
+    pub fn new(integrity: Integrity) -> CatalogBroadcast {
+        return CatalogBroadcast { carousel: Carousel::new(integrity), flows: HashMap::new() };
+    }
+
+    /// Makes the carousel cycle exactly the objects of entries, e.g. the lists of every catalog served after a
+    /// refresh. Objects already broadcast carry on where they were. Empty objects, and objects whose manifest is
+    /// too large for a block, are left out.
+    pub fn sync(&mut self, entries: &[Arc<CatalogEntry>]) -> BroadcastChanges {
+        let mut changes = BroadcastChanges::default();
+        let mut current: HashSet<FlowId> = HashSet::new();
+        for entry in entries.iter() {
+            let object_id = entry.manifest.object_id;
+            let flow_id = get_flow_id(&object_id);
+            if !current.insert(flow_id) || self.flows.get(&flow_id) == Some(&object_id) {
+                continue;
+            }
+
+            let encoder = entry.producer.lock().unwrap().get_encoder().clone();
+            let added = self.carousel.add(flow_id, encoder, 1, 0).and_then(|()| self.carousel.set_manifest_block(flow_id, &entry.manifest));
+            match added {
+                Ok(()) => {
+                    self.flows.insert(flow_id, object_id);
+                    changes.added.push(flow_id);
+                },
+                Err(_) => {
+                    current.remove(&flow_id);
+                    self.carousel.remove(flow_id);
+                    if self.flows.remove(&flow_id).is_some() {
+                        changes.removed.push(flow_id);
+                    }
+                },
+            }
+        }
+
+        for flow_id in self.carousel.get_flows() {
+            if !current.contains(&flow_id) {
+                self.carousel.remove(flow_id);
+                self.flows.remove(&flow_id);
+                changes.removed.push(flow_id);
+            }
+        }
+        changes.added.sort();
+        changes.removed.sort();
+        return changes;
+    }
+
+    /// Queues count datagrams of the carousel to addr, see Carousel::fill.
+    pub fn fill(&mut self, queue: &mut SendQueue, addr: SocketAddr, count: usize) -> usize {
+        return self.carousel.fill(queue, addr, count);
+    }
+
+    pub fn get_carousel(&self) -> &Carousel {
+        return &self.carousel;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoder::EncoderConfig;
+    use crate::codec::manifest::DecoderLimits;
+    use crate::server::catalog::Catalog;
+    use crate::transport::udp::FlowDemux;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_catalog_broadcast() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-broadcast-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a"), vec![1; 5000]).unwrap();
+        fs::write(root.join("b"), vec![2; 7000]).unwrap();
+        fs::write(root.join("empty"), vec![]).unwrap();
+
+        let catalog = Catalog::new(&root, EncoderConfig::new(1280));
+        catalog.refresh().unwrap();
+        let entries = catalog.list();
+        let flow_ids: Vec<FlowId> = entries.iter().filter(|x| x.manifest.data_size > 0).map(|x| get_flow_id(&x.manifest.object_id)).collect();
+
+        let mut broadcast = CatalogBroadcast::new(Integrity::Crc32c);
+        let mut added = flow_ids.clone();
+        added.sort();
+        assert_eq!(broadcast.sync(&entries), BroadcastChanges { added, removed: vec![] });
+        assert_eq!(broadcast.sync(&entries), BroadcastChanges::default());
+
+        // a receiver joins from the datagrams alone
+        let mut queue = SendQueue::new();
+        let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        assert_eq!(broadcast.fill(&mut queue, addr, 400), 400);
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        demux.set_join_in_progress(100, DecoderLimits::default());
+        while let Some((_, _, datagram)) = queue.pop() {
+            demux.receive(&datagram, Instant::now());
+        }
+        assert_eq!(demux.get_decoder(flow_ids[0]).unwrap().get_result(), Some(vec![1; 5000]));
+        assert_eq!(demux.get_decoder(flow_ids[1]).unwrap().get_result(), Some(vec![2; 7000]));
+
+        // removed files stop being broadcast
+        fs::remove_file(root.join("b")).unwrap();
+        catalog.refresh().unwrap();
+        assert_eq!(broadcast.sync(&catalog.list()), BroadcastChanges { added: vec![], removed: vec![flow_ids[1]] });
+        assert_eq!(broadcast.get_carousel().get_flows(), vec![flow_ids[0]]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,974 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, IoSlice};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use sha2::{Digest, Sha256};
+
+use crate::codec::bundle::{get_bundle_size, Bundler};
+use crate::codec::decoder::RaptorQDecoder;
+use crate::codec::encoder::{EncoderConfig, RaptorQEncoder};
+use crate::codec::manifest::{to_hex, Manifest, ObjectId, Protection};
+use crate::codec::numa::EncodePlacement;
+use crate::codec::producer::{SessionStats, SymbolProducer};
+use super::coalesce::{CoalesceStats, SymbolCoalescer};
+use super::pool::{PoolSettings, PoolStats, SymbolPool};
+
+/// An object served from the catalog.
+pub struct CatalogEntry {
+    /// Path of the file relative to the catalog root, with / separators.
+    pub name: String,
+    pub manifest: Manifest,
+    /// Shared with the entry replacing this one when only its overheads change, see Catalog::set_protection.
+    pub producer: Arc<Mutex<SymbolProducer>>,
+    /// Batches concurrent symbol requests for the object, if the server coalesces them.
+    pub coalescer: SymbolCoalescer,
+    /// Symbols generated ahead of requests, if the server pools them and the object is hot.
+    pub pool: SymbolPool,
+    /// Modification time and size of the file when it was encoded, to notice changes.
+    modified: SystemTime,
+    size: u64,
+    /// Set once the object is purged, see Catalog::purge.
+    purged: AtomicBool,
+}
+
+impl CatalogEntry {
+    /// Whether the object was purged, after which nothing more of it should be sent, e.g. by a stream already open.
+    pub fn is_purged(&self) -> bool {
+        return self.purged.load(Ordering::Relaxed);
+    }
+
+    /// Gets the modification time of the file when it was encoded, which a newer copy of the object has later.
+    pub fn get_modified(&self) -> SystemTime {
+        return self.modified;
+    }
+}
+
+/// Names of the objects that changed in a Catalog::refresh.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CatalogChanges {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+    /// Files newly left out for exceeding the catalog's limits.
+    pub over_limit: Vec<String>,
+    /// Files of purged objects that showed up again, which were deleted.
+    pub purged: Vec<String>,
+    /// Objects whose repair overheads changed with the catalog's protection.
+    pub reprotected: Vec<String>,
+}
+
+impl CatalogChanges {
+    pub fn is_empty(&self) -> bool {
+        return self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty() && self.over_limit.is_empty()
+            && self.purged.is_empty() && self.reprotected.is_empty();
+    }
+}
+
+/// Caps on what a catalog serves, e.g. a tenant's quota. Files are admitted in name order until one would exceed a
+/// limit; it and the files after it are left out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CatalogLimits {
+    pub max_objects: Option<usize>,
+    /// Total size of the files served, in bytes.
+    pub max_storage: Option<u64>,
+}
+
+/// Ending of the names of directories served as one bundle of the files in them, see Catalog::set_bundling.
+pub const BUNDLE_SUFFIX: &str = ".bundle";
+
+/// A file found under a catalog's root, or a directory bundled into one object.
+struct ScannedFile {
+    name: String,
+    path: PathBuf,
+    modified: SystemTime,
+    /// Size of the file, or of the bundle of the directory's files.
+    size: u64,
+}
+
+/// Hidden directory under a catalog's root objects are staged in until they are published, see Catalog::stage.
+pub const STAGING_DIR: &str = ".staging";
+
+/// Repair symbols beyond each block's symbol count StagedObject::verify decodes from.
+const VERIFY_EXTRA_SYMBOLS: usize = 8;
+
+/// An object encoded in the staging directory, not served until Catalog::activate publishes it.
+pub struct StagedObject {
+    entry: CatalogEntry,
+    /// The object's file in the staging directory.
+    path: PathBuf,
+    verified: bool,
+}
+
+impl StagedObject {
+    pub fn get_name(&self) -> &str {
+        return &self.entry.name;
+    }
+
+    pub fn get_manifest(&self) -> &Manifest {
+        return &self.entry.manifest;
+    }
+
+    /// Checks the object decodes from repair symbols alone to the blocks and payload its manifest hashes, and is
+    /// expected_id if given, as activating it requires. Fails with ErrorKind::InvalidData otherwise.
+    pub fn verify(&mut self, expected_id: Option<&ObjectId>) -> io::Result<()> {
+        let manifest = &self.entry.manifest;
+        if expected_id.is_some_and(|x| *x != manifest.object_id) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not the object expected", self.entry.name)));
+        }
+
+        let mut decoder = match RaptorQDecoder::from_manifest(manifest) {
+            Ok(decoder) => decoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad manifest of {}: {:?}", self.entry.name, error))),
+        };
+        let mut blocks = Vec::new();
+        for block_encoder in self.entry.producer.lock().unwrap().get_encoder().get_block_encoders().iter() {
+            blocks.append(&mut block_encoder.generate_repair_blocks(0, block_encoder.get_symbol_count() + VERIFY_EXTRA_SYMBOLS));
+        }
+        if decoder.consume(blocks) != Ok(true) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} does not decode", self.entry.name)));
+        }
+
+        let mut object_hasher = Sha256::new();
+        for (block_id, block_hash) in manifest.block_hashes.iter().enumerate() {
+            let block = decoder.get_block_result(block_id as u32).unwrap();
+            object_hasher.update(block);
+            if Sha256::digest(block)[..] != block_hash[..] {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("block {} of {} does not match its hash", block_id, self.entry.name)));
+            }
+        }
+        if object_hasher.finalize()[..] != manifest.object_id[..] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} does not match its object id", self.entry.name)));
+        }
+
+        self.verified = true;
+        return Ok(());
+    }
+
+    /// Drops the object without publishing it, e.g. once it failed to verify.
+    pub fn discard(self) -> io::Result<()> {
+        return Catalog::remove_file(&self.path);
+    }
+}
+
+/// An object published by Catalog::activate, and what it replaced, until it is committed or rolled back.
+pub struct Publication {
+    name: String,
+    object_id: ObjectId,
+    /// Entry served under the name before, if any.
+    previous: Option<Arc<CatalogEntry>>,
+    /// Where the file replaced was kept, if any.
+    backup: Option<PathBuf>,
+}
+
+impl Publication {
+    pub fn get_name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub fn get_object_id(&self) -> &ObjectId {
+        return &self.object_id;
+    }
+
+    /// Keeps the publication for good, deleting the file it replaced, after which it can't be rolled back.
+    pub fn commit(self) -> io::Result<()> {
+        match self.backup.as_ref() {
+            Some(backup) => return Catalog::remove_file(backup),
+            None => return Ok(()),
+        }
+    }
+}
+
+struct CatalogState {
+    by_name: HashMap<String, Arc<CatalogEntry>>,
+    by_id: HashMap<ObjectId, Arc<CatalogEntry>>,
+    /// Files left out by the last refresh for exceeding the limits.
+    over_limit: HashSet<String>,
+    /// Objects purged, which are not served again even if a file with their contents shows up.
+    purged: HashSet<ObjectId>,
+}
+
+/// The files under a root directory, encoded and ready to serve. Hidden files and directories are skipped.
+/// Call refresh periodically to pick up new, changed and removed files; readers keep using the entries they already
+/// looked up while files are re-encoded.
+/// Files written into the root directly may be picked up half written; publish them through stage and activate
+/// instead to replace an object all at once, with rollback.
+pub struct Catalog {
+    root: PathBuf,
+    config: EncoderConfig,
+    limits: Mutex<CatalogLimits>,
+    /// Repair overheads applied to each object's manifest and producer when it is encoded.
+    protection: Mutex<Vec<Protection>>,
+    /// Threads and NUMA nodes objects are encoded on, None to encode each on the thread refreshing.
+    placement: Option<EncodePlacement>,
+    /// Whether directories named with BUNDLE_SUFFIX are served as one bundle each.
+    bundling: bool,
+    state: RwLock<CatalogState>,
+    /// Held by refresh, activate and rollback, so a refresh never encodes a file a publication is moving.
+    publishing: Mutex<()>,
+    /// Numbers the files of staged objects.
+    staged: AtomicU64,
+}
+
+impl Catalog {
+    pub fn new<P: AsRef<Path>>(root: P, config: EncoderConfig) -> Catalog {
+        return Catalog::with_limits(root, config, CatalogLimits::default());
+    }
+
+    /// Creates a Catalog serving only what fits in limits.
+    pub fn with_limits<P: AsRef<Path>>(root: P, config: EncoderConfig, limits: CatalogLimits) -> Catalog {
+        return Catalog {
+            root: root.as_ref().to_path_buf(),
+            config,
+            limits: Mutex::new(limits),
+            protection: Mutex::new(Vec::new()),
+            placement: None,
+            bundling: false,
+            state: RwLock::new(CatalogState {
+                by_name: HashMap::new(),
+                by_id: HashMap::new(),
+                over_limit: HashSet::new(),
+                purged: HashSet::new(),
+            }),
+            publishing: Mutex::new(()),
+            staged: AtomicU64::new(0),
+        };
+    }
+
+    /// Changes the limits, taking effect at the next refresh. Objects that no longer fit are dropped then; the
+    /// sessions of the others are kept.
+    pub fn set_limits(&self, limits: CatalogLimits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    pub fn get_limits(&self) -> CatalogLimits {
+        return *self.limits.lock().unwrap();
+    }
+
+    /// Sets the repair overheads of byte ranges of every object, see Manifest::protect. Objects served already get
+    /// the new overheads at the next refresh, keeping their sessions.
+    pub fn set_protection(&self, protection: Vec<Protection>) {
+        *self.protection.lock().unwrap() = protection;
+    }
+
+    pub fn get_protection(&self) -> Vec<Protection> {
+        return self.protection.lock().unwrap().clone();
+    }
+
+    /// Encodes the blocks of each object at once on the threads and NUMA nodes of placement, see
+    /// RaptorQEncoder::with_placement. Pays off for objects of many blocks, i.e. of gigabytes.
+    pub fn set_placement(&mut self, placement: EncodePlacement) {
+        self.placement = Some(placement);
+    }
+
+    /// Serves each directory under the root whose name ends with BUNDLE_SUFFIX as one object under that name, a
+    /// bundle of the files directly in it, see codec::bundle, rather than each file on its own. Saves the per object
+    /// overhead of many small files; clients take the files they want out of the decoded bundle by name. Hidden files
+    /// and subdirectories of the directory are left out, and changing any file re-encodes the whole bundle.
+    pub fn set_bundling(&mut self, bundling: bool) {
+        self.bundling = bundling;
+    }
+
+    /// Rescans the root directory, encoding new and changed files and dropping removed ones.
+    pub fn refresh(&self) -> io::Result<CatalogChanges> {
+        let _publishing = self.publishing.lock().unwrap();
+        let mut files: Vec<ScannedFile> = Vec::new();
+        self.scan(&self.root, "", &mut files)?;
+        let over_limit = self.apply_limits(&mut files);
+
+        let mut changes = CatalogChanges::default();
+        let mut unchanged: Vec<Arc<CatalogEntry>> = Vec::new();
+        let mut changed: Vec<(&str, &Path, SystemTime)> = Vec::new();
+        {
+            let state = self.state.read().unwrap();
+            for file in files.iter() {
+                match state.by_name.get(&file.name) {
+                    Some(entry) if entry.modified == file.modified && entry.size == file.size => {
+                        unchanged.push(entry.clone());
+                        continue;
+                    },
+                    Some(_) => changes.updated.push(file.name.clone()),
+                    None => changes.added.push(file.name.clone()),
+                }
+                changed.push((&file.name, &file.path, file.modified));
+            }
+
+            changes.removed = state.by_name.keys().filter(|x| !files.iter().any(|file| file.name == **x)).cloned().collect();
+            changes.over_limit = over_limit.iter().filter(|x| !state.over_limit.contains(*x)).cloned().collect();
+        }
+
+        // encoded without holding the state, so purges and lookups go on meanwhile
+        let mut encoded: Vec<Arc<CatalogEntry>> = Vec::new();
+        for entry in unchanged.iter() {
+            if let Some(reprotected) = self.reprotect(entry) {
+                changes.reprotected.push(entry.name.clone());
+                encoded.push(Arc::new(reprotected));
+            }
+        }
+        for (name, path, modified) in changed {
+            encoded.push(Arc::new(self.encode(name, path, modified)?));
+        }
+
+        let mut state = self.state.write().unwrap();
+        for name in changes.removed.iter().chain(changes.updated.iter()) {
+            if let Some(entry) = state.by_name.remove(name) {
+                // another file may have the same contents
+                if state.by_id.get(&entry.manifest.object_id).is_some_and(|x| Arc::ptr_eq(x, &entry)) {
+                    state.by_id.remove(&entry.manifest.object_id);
+                }
+            }
+        }
+        for entry in encoded {
+            // checked here rather than while encoding, as a purge may have come in meanwhile
+            if state.purged.contains(&entry.manifest.object_id) {
+                changes.added.retain(|x| *x != entry.name);
+                changes.updated.retain(|x| *x != entry.name);
+                changes.reprotected.retain(|x| *x != entry.name);
+                changes.purged.push(entry.name.clone());
+                continue;
+            }
+            state.by_id.insert(entry.manifest.object_id, entry.clone());
+            state.by_name.insert(entry.name.clone(), entry);
+        }
+        state.over_limit = over_limit;
+
+        changes.added.sort();
+        changes.updated.sort();
+        changes.removed.sort();
+        changes.over_limit.sort();
+        changes.purged.sort();
+        changes.reprotected.sort();
+        drop(state);
+
+        for name in changes.purged.iter() {
+            Catalog::remove_file(&self.root.join(name))?;
+        }
+        return Ok(changes);
+    }
+
+    /// Drops the files that don't fit in the limits, returning their names.
+    fn apply_limits(&self, files: &mut Vec<ScannedFile>) -> HashSet<String> {
+        files.sort_by(|x, y| x.name.cmp(&y.name));
+        let limits = self.get_limits();
+
+        let mut storage: u64 = 0;
+        let admitted = files.iter()
+            .position(|file| {
+                storage += file.size;
+                return limits.max_storage.is_some_and(|x| storage > x);
+            })
+            .unwrap_or(files.len());
+        let admitted = limits.max_objects.map_or(admitted, |x| std::cmp::min(x, admitted));
+
+        return files.drain(admitted..).map(|file| file.name).collect();
+    }
+
+    fn scan(&self, dir: &Path, prefix: &str, files: &mut Vec<ScannedFile>) -> io::Result<()> {
+        for dir_entry in fs::read_dir(dir)? {
+            let dir_entry = dir_entry?;
+            let file_name = match dir_entry.file_name().into_string() {
+                Ok(file_name) if !file_name.starts_with('.') => file_name,
+                _ => continue,
+            };
+
+            let name = format!("{}{}", prefix, file_name);
+            let metadata = dir_entry.metadata()?;
+            if metadata.is_dir() && self.bundling && name.ends_with(BUNDLE_SUFFIX) {
+                files.push(Catalog::scan_bundle(name, dir_entry.path(), metadata.modified()?)?);
+            } else if metadata.is_dir() {
+                self.scan(&dir_entry.path(), &format!("{}/", name), files)?;
+            } else if metadata.is_file() {
+                files.push(ScannedFile { name, path: dir_entry.path(), modified: metadata.modified()?, size: metadata.len() });
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Lists the files a directory is bundled from, by name.
+    fn list_bundle(dir: &Path) -> io::Result<Vec<(String, fs::Metadata)>> {
+        let mut files: Vec<(String, fs::Metadata)> = Vec::new();
+        for dir_entry in fs::read_dir(dir)? {
+            let dir_entry = dir_entry?;
+            let metadata = dir_entry.metadata()?;
+            match dir_entry.file_name().into_string() {
+                Ok(file_name) if !file_name.starts_with('.') && metadata.is_file() => files.push((file_name, metadata)),
+                _ => continue,
+            }
+        }
+        files.sort_by(|x, y| x.0.cmp(&y.0));
+        return Ok(files);
+    }
+
+    /// Scans a directory bundled into one object. It counts as modified when the directory or any file in it is.
+    fn scan_bundle(name: String, path: PathBuf, modified: SystemTime) -> io::Result<ScannedFile> {
+        let files = Catalog::list_bundle(&path)?;
+        let mut latest = modified;
+        for (_, metadata) in files.iter() {
+            latest = std::cmp::max(latest, metadata.modified()?);
+        }
+        let size = get_bundle_size(files.iter().map(|(file_name, metadata)| (&file_name[..], metadata.len())));
+        return Ok(ScannedFile { name, path, modified: latest, size });
+    }
+
+    fn encode(&self, name: &str, path: &Path, modified: SystemTime) -> io::Result<CatalogEntry> {
+        if !path.is_dir() {
+            return self.encode_data(name, &fs::read(path)?, modified);
+        }
+
+        let mut contents: Vec<(String, Vec<u8>)> = Vec::new();
+        for (file_name, _) in Catalog::list_bundle(path)? {
+            let data = fs::read(path.join(&file_name))?;
+            contents.push((file_name, data));
+        }
+        let mut bundler = Bundler::new();
+        for (file_name, data) in contents.iter() {
+            if let Err(error) = bundler.add(file_name, data) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("failed to bundle {}/{}: {:?}", name, file_name, error)));
+            }
+        }
+        let mut bundle: Vec<u8> = Vec::new();
+        bundler.write_to(&mut bundle)?;
+        return self.encode_data(name, &bundle, modified);
+    }
+
+    fn encode_data(&self, name: &str, data: &[u8], modified: SystemTime) -> io::Result<CatalogEntry> {
+        let encoded = match self.placement.as_ref() {
+            None => RaptorQEncoder::with_config(self.config, &[IoSlice::new(data)]),
+            Some(placement) => RaptorQEncoder::with_placement(self.config, &[IoSlice::new(data)], placement, None),
+        };
+        let encoder = match encoded {
+            Ok(encoder) => encoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("failed to encode {}: {:?}", name, error))),
+        };
+
+        let mut manifest = Manifest::new(&encoder);
+        for protection in self.protection.lock().unwrap().iter() {
+            manifest.protect(protection);
+        }
+        let mut producer = SymbolProducer::new(encoder);
+        producer.set_block_overheads(&manifest.block_overheads).unwrap();
+
+        return Ok(CatalogEntry {
+            name: name.to_string(),
+            manifest,
+            producer: Arc::new(Mutex::new(producer)),
+            coalescer: SymbolCoalescer::new(),
+            pool: SymbolPool::new(),
+            modified,
+            size: data.len() as u64,
+            purged: AtomicBool::new(false),
+        });
+    }
+
+    /// Gets a copy of entry with the overheads of the current protection, sharing its producer so its sessions carry
+    /// on, or None if its overheads are the same.
+    fn reprotect(&self, entry: &CatalogEntry) -> Option<CatalogEntry> {
+        let mut manifest = entry.manifest.clone();
+        manifest.block_overheads = vec![0; manifest.get_block_count()];
+        for protection in self.protection.lock().unwrap().iter() {
+            manifest.protect(protection);
+        }
+        if manifest.block_overheads == entry.manifest.block_overheads {
+            return None;
+        }
+
+        entry.producer.lock().unwrap().set_block_overheads(&manifest.block_overheads).unwrap();
+        return Some(CatalogEntry {
+            name: entry.name.clone(),
+            manifest,
+            producer: entry.producer.clone(),
+            coalescer: SymbolCoalescer::new(),
+            pool: SymbolPool::new(),
+            modified: entry.modified,
+            size: entry.size,
+            purged: AtomicBool::new(false),
+        });
+    }
+
+    /// Writes data to the staging directory and encodes it as the object to publish under name, a path relative to
+    /// the root with / separators. Nothing is served until the object is verified and activated, so clients never
+    /// see a half written or half encoded object. Fails with ErrorKind::InvalidInput for a name refresh would skip.
+    pub fn stage(&self, name: &str, data: &[u8]) -> io::Result<StagedObject> {
+        if name.split('/').any(|x| x.is_empty() || x.starts_with('.')) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a valid object name", name)));
+        }
+
+        let staging_dir = self.root.join(STAGING_DIR);
+        fs::create_dir_all(&staging_dir)?;
+        let path = staging_dir.join(self.staged.fetch_add(1, Ordering::Relaxed).to_string());
+        fs::write(&path, data)?;
+        // moving the file keeps its modification time, so refresh takes it as the file the entry was encoded from
+        let modified = fs::metadata(&path)?.modified()?;
+        let entry = match self.encode_data(name, data, modified) {
+            Ok(entry) => entry,
+            Err(error) => {
+                Catalog::remove_file(&path)?;
+                return Err(error);
+            },
+        };
+        return Ok(StagedObject { entry, path, verified: false });
+    }
+
+    /// Publishes a verified staged object: moves its file into place and serves it under its name from then on, in
+    /// place of what was served before, all at once. What it replaced is kept for rollback until the publication is
+    /// committed. Fails with ErrorKind::InvalidInput if the object was not verified, PermissionDenied if it was
+    /// purged, and QuotaExceeded if the files under the root would no longer fit in the limits with it, dropping it
+    /// in each case.
+    pub fn activate(&self, staged: StagedObject) -> io::Result<Publication> {
+        if !staged.verified {
+            let name = staged.entry.name.clone();
+            staged.discard()?;
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} was not verified", name)));
+        }
+
+        let _publishing = self.publishing.lock().unwrap();
+        let name = staged.entry.name.clone();
+        let object_id = staged.entry.manifest.object_id;
+        // checked against what the next refresh admits, which would otherwise leave some object out
+        let mut files: Vec<ScannedFile> = Vec::new();
+        self.scan(&self.root, "", &mut files)?;
+        files.retain(|x| x.name != name);
+        let limits = self.get_limits();
+        let storage = files.iter().map(|x| x.size).sum::<u64>() + staged.entry.size;
+        if limits.max_objects.is_some_and(|x| files.len() + 1 > x) || limits.max_storage.is_some_and(|x| storage > x) {
+            staged.discard()?;
+            return Err(io::Error::new(io::ErrorKind::QuotaExceeded, format!("{} does not fit in the catalog's limits", name)));
+        }
+
+        let mut state = self.state.write().unwrap();
+        if state.purged.contains(&object_id) {
+            staged.discard()?;
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} was purged", to_hex(&object_id))));
+        }
+
+        let target = self.root.join(&name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let backup = staged.path.with_extension("previous");
+        let replaced = match fs::rename(&target, &backup) {
+            Ok(()) => true,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => false,
+            Err(error) => return Err(error),
+        };
+        if let Err(error) = fs::rename(&staged.path, &target) {
+            if replaced {
+                fs::rename(&backup, &target)?;
+            }
+            return Err(error);
+        }
+
+        let entry = Arc::new(staged.entry);
+        let previous = state.by_name.insert(name.clone(), entry.clone());
+        if let Some(previous) = previous.as_ref() {
+            if state.by_id.get(&previous.manifest.object_id).is_some_and(|x| Arc::ptr_eq(x, previous)) {
+                state.by_id.remove(&previous.manifest.object_id);
+            }
+        }
+        state.by_id.insert(object_id, entry);
+        return Ok(Publication { name, object_id, previous, backup: replaced.then_some(backup) });
+    }
+
+    /// Stages, verifies and activates data as the object to publish under name, dropping it if any step fails.
+    pub fn publish(&self, name: &str, data: &[u8], expected_id: Option<&ObjectId>) -> io::Result<Publication> {
+        let mut staged = self.stage(name, data)?;
+        if let Err(error) = staged.verify(expected_id) {
+            staged.discard()?;
+            return Err(error);
+        }
+        return self.activate(staged);
+    }
+
+    /// Takes a publication back, serving what it replaced again, or nothing under its name if it replaced nothing.
+    /// A replaced object purged since is not restored. Fails with ErrorKind::InvalidInput if something else was
+    /// published under the name since.
+    pub fn rollback(&self, publication: Publication) -> io::Result<()> {
+        let _publishing = self.publishing.lock().unwrap();
+        let mut state = self.state.write().unwrap();
+        let current = match state.by_name.get(&publication.name) {
+            Some(entry) if entry.manifest.object_id == publication.object_id => entry.clone(),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} was replaced since", publication.name))),
+        };
+
+        let target = self.root.join(&publication.name);
+        let purged = publication.previous.as_ref().is_some_and(|x| state.purged.contains(&x.manifest.object_id));
+        match publication.backup.as_ref() {
+            Some(backup) if !purged => fs::rename(backup, &target)?,
+            Some(backup) => {
+                Catalog::remove_file(backup)?;
+                Catalog::remove_file(&target)?;
+            },
+            None => Catalog::remove_file(&target)?,
+        }
+
+        state.by_name.remove(&publication.name);
+        if state.by_id.get(&publication.object_id).is_some_and(|x| Arc::ptr_eq(x, &current)) {
+            state.by_id.remove(&publication.object_id);
+        }
+        if let Some(previous) = publication.previous.filter(|_| !purged) {
+            state.by_id.insert(previous.manifest.object_id, previous.clone());
+            state.by_name.insert(publication.name, previous);
+        }
+        return Ok(());
+    }
+
+    /// Stops serving an object and deletes the files it was encoded from, returning their names. The object is not
+    /// served again, even if a file with its contents is added later. Purging an object the catalog doesn't have
+    /// still keeps it from being served.
+    pub fn purge(&self, object_id: &ObjectId) -> io::Result<Vec<String>> {
+        let mut removed: Vec<Arc<CatalogEntry>> = Vec::new();
+        {
+            let mut state = self.state.write().unwrap();
+            state.purged.insert(*object_id);
+            state.by_id.remove(object_id);
+            // several files may have the same contents
+            state.by_name.retain(|_, entry| {
+                if entry.manifest.object_id != *object_id {
+                    return true;
+                }
+                removed.push(entry.clone());
+                return false;
+            });
+        }
+
+        let mut names: Vec<String> = Vec::with_capacity(removed.len());
+        for entry in removed {
+            entry.purged.store(true, Ordering::Relaxed);
+            Catalog::remove_file(&self.root.join(&entry.name))?;
+            names.push(entry.name.clone());
+        }
+        names.sort();
+        return Ok(names);
+    }
+
+    /// Deletes a file, or a bundled directory, unless someone else already did.
+    fn remove_file(path: &Path) -> io::Result<()> {
+        let removed = if path.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+        match removed {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+            _ => return Ok(()),
+        }
+    }
+
+    /// Looks up an object by id.
+    pub fn get(&self, object_id: &ObjectId) -> Option<Arc<CatalogEntry>> {
+        return self.state.read().unwrap().by_id.get(object_id).cloned();
+    }
+
+    /// Closes the symbol sessions of every object that produced nothing for ttl, returning how many were closed.
+    pub fn expire_idle_sessions(&self, ttl: Duration) -> usize {
+        let now = Instant::now();
+        return self.list().iter().map(|x| x.producer.lock().unwrap().expire_idle_sessions(ttl, now).len()).sum();
+    }
+
+    /// Evicts the encoder state of every object without open symbol sessions, see BlockEncoder::evict, returning how
+    /// many objects were evicted. Their state is recomputed when a session asks for symbols again.
+    pub fn evict_idle_encoders(&self) -> usize {
+        let mut evicted: usize = 0;
+        for entry in self.list() {
+            let producer = entry.producer.lock().unwrap();
+            let encoder = producer.get_encoder();
+            if producer.get_open_sessions() == 0 && encoder.get_block_encoders().iter().any(|x| x.is_resident()) {
+                encoder.evict();
+                evicted += 1;
+            }
+        }
+        return evicted;
+    }
+
+    /// Estimates the bytes held by the encoders of every object, see RaptorQEncoder::memory_usage.
+    pub fn get_memory_usage(&self) -> usize {
+        return self.list().iter().map(|x| x.producer.lock().unwrap().get_encoder().memory_usage()).sum();
+    }
+
+    /// Gets the number of open symbol sessions and the session stats summed over every object. Stats of an object
+    /// start over when its file changes.
+    pub fn get_session_stats(&self) -> (usize, SessionStats) {
+        let mut open_sessions: usize = 0;
+        let mut total = SessionStats::default();
+        for entry in self.list() {
+            let producer = entry.producer.lock().unwrap();
+            let stats = producer.get_session_stats();
+            open_sessions += producer.get_open_sessions();
+            total.opened += stats.opened;
+            total.closed += stats.closed;
+            total.reaped += stats.reaped;
+        }
+        return (open_sessions, total);
+    }
+
+    /// Gets the coalescing stats summed over every object. Stats of an object start over when its file changes.
+    pub fn get_coalesce_stats(&self) -> CoalesceStats {
+        let mut total = CoalesceStats::default();
+        for entry in self.list() {
+            let stats = entry.coalescer.get_stats();
+            total.requests += stats.requests;
+            total.passes += stats.passes;
+        }
+        return total;
+    }
+
+    /// Refills the symbol pools of every object as SymbolPool::refill does, returning the symbols generated.
+    pub fn refill_pools(&self, settings: &PoolSettings) -> usize {
+        let now = Instant::now();
+        return self.list().iter().map(|entry| {
+            let encoder = entry.producer.lock().unwrap().get_encoder().clone();
+            entry.pool.refill(&encoder, settings, now)
+        }).sum();
+    }
+
+    /// Gets the symbol pool stats summed over every object. Stats of an object start over when its file changes.
+    pub fn get_pool_stats(&self) -> PoolStats {
+        let mut total = PoolStats::default();
+        for entry in self.list() {
+            let stats = entry.pool.get_stats();
+            total.hits += stats.hits;
+            total.misses += stats.misses;
+            total.generated += stats.generated;
+            total.pooled += stats.pooled;
+            total.pooled_bytes += stats.pooled_bytes;
+        }
+        return total;
+    }
+
+    /// Gets every object, ordered by name.
+    pub fn list(&self) -> Vec<Arc<CatalogEntry>> {
+        let mut entries: Vec<Arc<CatalogEntry>> = self.state.read().unwrap().by_name.values().cloned().collect();
+        entries.sort_by(|x, y| x.name.cmp(&y.name));
+        return entries;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::bundle::BundleExtractor;
+
+    #[test]
+    fn test_catalog_refresh() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-catalog-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a"), vec![1; 5000]).unwrap();
+        fs::write(root.join("sub/b"), vec![2; 7000]).unwrap();
+        fs::write(root.join(".hidden"), vec![3; 10]).unwrap();
+
+        let catalog = Catalog::new(&root, EncoderConfig::new(1280));
+        let changes = catalog.refresh().unwrap();
+        assert_eq!(changes.added, vec!["a".to_string(), "sub/b".to_string()]);
+        assert!(catalog.refresh().unwrap().is_empty());
+
+        let entry = catalog.list().remove(0);
+        assert_eq!(entry.manifest.data_size, 5000);
+        assert!(catalog.get(&entry.manifest.object_id).is_some());
+
+        // only objects without open sessions give up their encoder state
+        entry.producer.lock().unwrap().open_session();
+        let resident = catalog.get_memory_usage();
+        assert_eq!(catalog.evict_idle_encoders(), 1);
+        assert!(catalog.get_memory_usage() < resident);
+        assert!(entry.producer.lock().unwrap().get_encoder().get_block_encoders()[0].is_resident());
+        assert_eq!(catalog.evict_idle_encoders(), 0);
+
+        // change the size so the change is noticed even with a coarse modification time
+        fs::write(root.join("a"), vec![4; 6000]).unwrap();
+        fs::remove_file(root.join("sub/b")).unwrap();
+        let changes = catalog.refresh().unwrap();
+        assert_eq!(changes.updated, vec!["a".to_string()]);
+        assert_eq!(changes.removed, vec!["sub/b".to_string()]);
+        assert!(catalog.get(&entry.manifest.object_id).is_none());
+        assert_eq!(catalog.list().len(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_catalog_protection() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-catalog-protection-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a"), vec![1; 5000]).unwrap();
+
+        let catalog = Catalog::new(&root, EncoderConfig::new(1280));
+        catalog.refresh().unwrap();
+        let entry = catalog.list().remove(0);
+        assert_eq!(entry.manifest.block_overheads, vec![0]);
+        let session_id = entry.producer.lock().unwrap().open_session();
+
+        // changed overheads are applied to objects served already without dropping their sessions
+        catalog.set_protection(vec![Protection { range: 0..u64::MAX, overhead_percent: 30 }]);
+        let changes = catalog.refresh().unwrap();
+        assert_eq!(changes.reprotected, vec!["a".to_string()]);
+        assert!(changes.updated.is_empty());
+        let reprotected = catalog.get(&entry.manifest.object_id).unwrap();
+        assert_eq!(reprotected.manifest.block_overheads, vec![30]);
+        assert!(Arc::ptr_eq(&reprotected.producer, &entry.producer));
+        assert_eq!(reprotected.producer.lock().unwrap().next_symbols(session_id, 4).unwrap().len(), 4);
+        assert!(catalog.refresh().unwrap().is_empty());
+
+        catalog.set_protection(Vec::new());
+        assert_eq!(catalog.refresh().unwrap().reprotected, vec!["a".to_string()]);
+        assert_eq!(catalog.list()[0].manifest.block_overheads, vec![0]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_catalog_limits() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-catalog-limits-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a"), vec![1; 3000]).unwrap();
+        fs::write(root.join("b"), vec![2; 3000]).unwrap();
+        fs::write(root.join("c"), vec![3; 3000]).unwrap();
+
+        let limits = CatalogLimits { max_objects: None, max_storage: Some(7000) };
+        let catalog = Catalog::with_limits(&root, EncoderConfig::new(1280), limits);
+        let changes = catalog.refresh().unwrap();
+        assert_eq!(changes.added, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(changes.over_limit, vec!["c".to_string()]);
+        // only reported once
+        assert!(catalog.refresh().unwrap().is_empty());
+
+        let limits = CatalogLimits { max_objects: Some(1), max_storage: None };
+        let catalog = Catalog::with_limits(&root, EncoderConfig::new(1280), limits);
+        assert_eq!(catalog.refresh().unwrap().over_limit, vec!["b".to_string(), "c".to_string()]);
+        assert_eq!(catalog.list().len(), 1);
+
+        catalog.set_limits(CatalogLimits::default());
+        let changes = catalog.refresh().unwrap();
+        assert_eq!(changes.added, vec!["b".to_string(), "c".to_string()]);
+        assert!(changes.over_limit.is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_catalog_bundles() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-catalog-bundle-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("small.bundle/sub")).unwrap();
+        fs::write(root.join("small.bundle/a"), vec![1; 300]).unwrap();
+        fs::write(root.join("small.bundle/b"), vec![2; 500]).unwrap();
+        fs::write(root.join("small.bundle/.hidden"), vec![3; 10]).unwrap();
+        fs::write(root.join("small.bundle/sub/c"), vec![4; 10]).unwrap();
+        fs::write(root.join("d"), vec![5; 1000]).unwrap();
+
+        let mut catalog = Catalog::new(&root, EncoderConfig::new(1280));
+        catalog.set_bundling(true);
+        let changes = catalog.refresh().unwrap();
+        assert_eq!(changes.added, vec!["d".to_string(), "small.bundle".to_string()]);
+        assert!(catalog.refresh().unwrap().is_empty());
+
+        let entry = catalog.list().into_iter().find(|x| x.name == "small.bundle").unwrap();
+        let encoder_blocks = entry.producer.lock().unwrap().get_encoder().generate_encoded_blocks();
+        let mut decoder = match RaptorQDecoder::new(entry.manifest.block_info_vec.clone()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+        assert_eq!(decoder.consume(encoder_blocks), Ok(true));
+        let decoded = decoder.get_result().unwrap();
+        let extractor = BundleExtractor::new(&decoded).unwrap();
+        assert_eq!(extractor.entries().len(), 2);
+        assert_eq!(extractor.get("a"), Some(&vec![1; 300][..]));
+        assert_eq!(extractor.get("b"), Some(&vec![2; 500][..]));
+
+        // any file changing re-encodes the whole bundle
+        fs::write(root.join("small.bundle/a"), vec![6; 400]).unwrap();
+        assert_eq!(catalog.refresh().unwrap().updated, vec!["small.bundle".to_string()]);
+        let object_id = catalog.list().into_iter().find(|x| x.name == "small.bundle").unwrap().manifest.object_id;
+        assert_eq!(catalog.purge(&object_id).unwrap(), vec!["small.bundle".to_string()]);
+        assert!(!root.join("small.bundle").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_catalog_purge() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-catalog-purge-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a"), vec![1; 3000]).unwrap();
+        fs::write(root.join("copy"), vec![1; 3000]).unwrap();
+        fs::write(root.join("b"), vec![2; 3000]).unwrap();
+
+        let catalog = Catalog::new(&root, EncoderConfig::new(1280));
+        catalog.refresh().unwrap();
+        let entry = catalog.list().remove(0);
+        let object_id = entry.manifest.object_id;
+
+        // every file with the object's contents goes, and entries already handed out learn of it
+        assert_eq!(catalog.purge(&object_id).unwrap(), vec!["a".to_string(), "copy".to_string()]);
+        assert!(entry.is_purged());
+        assert!(catalog.get(&object_id).is_none());
+        assert!(!root.join("a").exists() && !root.join("copy").exists());
+        assert!(catalog.refresh().unwrap().removed.is_empty());
+
+        // the object is not served again if it comes back
+        fs::write(root.join("again"), vec![1; 3000]).unwrap();
+        let changes = catalog.refresh().unwrap();
+        assert_eq!(changes.purged, vec!["again".to_string()]);
+        assert!(changes.added.is_empty());
+        assert!(!root.join("again").exists());
+        assert_eq!(catalog.list().len(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_catalog_publish() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-catalog-publish-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a"), vec![1; 3000]).unwrap();
+        let catalog = Catalog::new(&root, EncoderConfig::new(1280));
+        catalog.refresh().unwrap();
+        let old_id = catalog.list()[0].manifest.object_id;
+
+        // nothing staged is served or picked up by a refresh, and only verified objects are activated
+        let mut staged = catalog.stage("a", &[2; 5000]).unwrap();
+        let new_id = staged.get_manifest().object_id;
+        assert!(catalog.refresh().unwrap().is_empty());
+        assert!(catalog.get(&new_id).is_none());
+        assert_eq!(staged.verify(Some(&old_id)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        match catalog.activate(staged) {
+            Ok(_) => panic!("Should have failed to activate an unverified object"),
+            Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidInput),
+        }
+        assert!(catalog.get(&old_id).is_some());
+
+        let publication = catalog.publish("a", &[2; 5000], Some(&new_id)).unwrap();
+        assert!(catalog.get(&old_id).is_none());
+        assert_eq!(catalog.get(&new_id).unwrap().name, "a");
+        assert_eq!(fs::read(root.join("a")).unwrap(), vec![2; 5000]);
+        assert!(catalog.refresh().unwrap().is_empty());
+
+        // rolling back serves the old object from the old file again
+        catalog.rollback(publication).unwrap();
+        assert!(catalog.get(&new_id).is_none());
+        assert!(catalog.get(&old_id).is_some());
+        assert_eq!(fs::read(root.join("a")).unwrap(), vec![1; 3000]);
+        assert!(catalog.refresh().unwrap().is_empty());
+
+        // a new name rolls back to nothing, and a committed publication keeps no copy of what it replaced
+        let publication = catalog.publish("sub/c", &[3; 2000], None).unwrap();
+        assert_eq!(catalog.list().len(), 2);
+        catalog.rollback(publication).unwrap();
+        assert!(!root.join("sub/c").exists());
+        assert_eq!(catalog.list().len(), 1);
+        catalog.publish("a", &[2; 5000], None).unwrap().commit().unwrap();
+        assert_eq!(fs::read_dir(root.join(STAGING_DIR)).unwrap().count(), 0);
+
+        // publishing checks the limits rather than leaving an object out at the next refresh
+        catalog.set_limits(CatalogLimits { max_objects: Some(1), max_storage: Some(6000) });
+        assert_eq!(catalog.publish("b", &[3; 1000], None).err().unwrap().kind(), io::ErrorKind::QuotaExceeded);
+        assert_eq!(catalog.publish("a", &[3; 7000], None).err().unwrap().kind(), io::ErrorKind::QuotaExceeded);
+        assert!(!root.join("b").exists());
+        assert_eq!(fs::read_dir(root.join(STAGING_DIR)).unwrap().count(), 0);
+        catalog.publish("a", &[3; 6000], None).unwrap().commit().unwrap();
+        assert!(catalog.refresh().unwrap().is_empty());
+
+        assert_eq!(catalog.stage(".hidden", &[1; 10]).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(catalog.stage("sub/../a", &[1; 10]).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+//! Failure injection for chaos testing nodes. Only built with the chaos feature, never enable it in production.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use rand::{thread_rng, Rng};
+use raptorq::EncodingPacket;
+
+use crate::codec::encoder::EncodedBlock;
+
+/// Parts per million, the unit of drop and corruption rates.
+const PPM: u32 = 1_000_000;
+
+/// Current failure injection settings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChaosSettings {
+    /// Outgoing symbols dropped, in parts per million.
+    pub drop_ppm: u32,
+    /// Outgoing symbols with a byte flipped, in parts per million.
+    pub corrupt_ppm: u32,
+    /// Delay added to control responses, i.e. everything but symbols, in milliseconds.
+    pub control_delay_ms: u32,
+    /// Whether symbol generation is paused.
+    pub paused: bool,
+}
+
+/// Failure injection shared by the threads of a node. Settings can be changed at any time, e.g. from the control
+/// plane, and apply to the next symbols or control responses.
+pub struct Chaos {
+    drop_ppm: AtomicU32,
+    corrupt_ppm: AtomicU32,
+    control_delay_ms: AtomicU32,
+    paused: Mutex<bool>,
+    /// Signalled when symbol generation is resumed.
+    resumed: Condvar,
+}
+
+impl Chaos {
+    /// Creates a Chaos injecting no failures.
+    pub fn new() -> Chaos {
+        return Chaos {
+            drop_ppm: AtomicU32::new(0),
+            corrupt_ppm: AtomicU32::new(0),
+            control_delay_ms: AtomicU32::new(0),
+            paused: Mutex::new(false),
+            resumed: Condvar::new(),
+        };
+    }
+
+    pub fn get_settings(&self) -> ChaosSettings {
+        return ChaosSettings {
+            drop_ppm: self.drop_ppm.load(Ordering::Relaxed),
+            corrupt_ppm: self.corrupt_ppm.load(Ordering::Relaxed),
+            control_delay_ms: self.control_delay_ms.load(Ordering::Relaxed),
+            paused: *self.paused.lock().unwrap(),
+        };
+    }
+
+    /// Applies new settings. Rates above a million parts per million are clamped.
+    pub fn set_settings(&self, settings: ChaosSettings) {
+        self.drop_ppm.store(std::cmp::min(settings.drop_ppm, PPM), Ordering::Relaxed);
+        self.corrupt_ppm.store(std::cmp::min(settings.corrupt_ppm, PPM), Ordering::Relaxed);
+        self.control_delay_ms.store(settings.control_delay_ms, Ordering::Relaxed);
+
+        let mut paused = self.paused.lock().unwrap();
+        *paused = settings.paused;
+        if !*paused {
+            self.resumed.notify_all();
+        }
+    }
+
+    /// Blocks while symbol generation is paused. Call before generating symbols.
+    pub fn wait_unpaused(&self) {
+        let mut paused = self.paused.lock().unwrap();
+        while *paused {
+            paused = self.resumed.wait(paused).unwrap();
+        }
+    }
+
+    /// Sleeps for the configured control delay. Call before sending a control response.
+    pub fn delay_control(&self) {
+        let delay_ms = self.control_delay_ms.load(Ordering::Relaxed);
+        if delay_ms > 0 {
+            std::thread::sleep(Duration::from_millis(delay_ms as u64));
+        }
+    }
+
+    /// Drops and corrupts outgoing symbols according to the current settings.
+    pub fn apply(&self, blocks: Vec<EncodedBlock>) -> Vec<EncodedBlock> {
+        let drop_ppm = self.drop_ppm.load(Ordering::Relaxed);
+        let corrupt_ppm = self.corrupt_ppm.load(Ordering::Relaxed);
+        let mut rng = thread_rng();
+
+        return blocks.into_iter()
+            .filter_map(|block| {
+                if rng.gen_range(0..PPM) < drop_ppm {
+                    return None;
+                }
+                if rng.gen_range(0..PPM) >= corrupt_ppm || block.data.data().is_empty() {
+                    return Some(block);
+                }
+
+                let (payload_id, mut data) = block.data.split();
+                let index = rng.gen_range(0..data.len());
+                data[index] ^= 0xff;
+                return Some(EncodedBlock {
+                    block_id: block.block_id,
+                    data: EncodingPacket::new(payload_id, data),
+                });
+            })
+            .collect();
+    }
+}
+
+impl Default for Chaos {
+    fn default() -> Chaos {
+        return Chaos::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoder::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_chaos() {
+        let data: Vec<u8> = vec![0; 100 * 1000];
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let blocks = encoder.generate_encoded_blocks();
+
+        let chaos = Arc::new(Chaos::new());
+        assert_eq!(chaos.apply(blocks.clone()), blocks);
+
+        chaos.set_settings(ChaosSettings { corrupt_ppm: PPM, ..Default::default() });
+        let corrupted = chaos.apply(blocks.clone());
+        assert_eq!(corrupted.len(), blocks.len());
+        assert!(corrupted.iter().zip(blocks.iter()).all(|(x, y)| x.data.payload_id() == y.data.payload_id() && x != y));
+
+        chaos.set_settings(ChaosSettings { drop_ppm: 2 * PPM, ..Default::default() });
+        assert_eq!(chaos.get_settings().drop_ppm, PPM);
+        assert!(chaos.apply(blocks).is_empty());
+
+        // a paused generator waits for the control plane to resume it
+        chaos.set_settings(ChaosSettings { paused: true, ..Default::default() });
+        let waiter = chaos.clone();
+        let handle = std::thread::spawn(move || waiter.wait_unpaused());
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(!handle.is_finished());
+        chaos.set_settings(ChaosSettings::default());
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+//! Coalescing of concurrent symbol requests for the same object. While one request is generating, the requests that
+//! arrive queue up and the next of them to run reserves and generates symbols for the whole queue in one pass, taking
+//! the producer lock once. Each request still gets its own session's symbols.
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+
+use crate::codec::encoder::EncodedBlock;
+use crate::codec::producer::{interleave_blocks, SessionId, SymbolProducer, SymbolProducerError};
+
+/// How often requests were coalesced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CoalesceStats {
+    pub requests: u64,
+    /// Generation passes, each serving one or more requests.
+    pub passes: u64,
+}
+
+impl CoalesceStats {
+    /// Gets the fraction of requests served by another request's pass, 0 if there were none.
+    pub fn get_hit_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        return (self.requests - self.passes) as f64 / self.requests as f64;
+    }
+}
+
+type Ticket = u64;
+
+#[derive(Default)]
+struct CoalescerState {
+    next_ticket: Ticket,
+    /// Requests waiting for the next pass.
+    pending: Vec<(Ticket, SessionId, usize)>,
+    /// Results of the last passes, until their requests pick them up.
+    results: HashMap<Ticket, Result<Vec<EncodedBlock>, SymbolProducerError>>,
+    /// Whether a pass is running.
+    generating: bool,
+    stats: CoalesceStats,
+}
+
+/// Batches the symbol requests for one object, see the module documentation.
+#[derive(Default)]
+pub struct SymbolCoalescer {
+    state: Mutex<CoalescerState>,
+    done: Condvar,
+}
+
+impl SymbolCoalescer {
+    pub fn new() -> SymbolCoalescer {
+        return SymbolCoalescer::default();
+    }
+
+    /// Gets the next count symbols of a session from producer, as SymbolProducer::next_symbols does, generating
+    /// them together with any other requests waiting meanwhile.
+    pub fn next_symbols(&self, producer: &Mutex<SymbolProducer>, session_id: SessionId, count: usize) -> Result<Vec<EncodedBlock>, SymbolProducerError> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.pending.push((ticket, session_id, count));
+        state.stats.requests += 1;
+
+        loop {
+            if let Some(result) = state.results.remove(&ticket) {
+                return result;
+            }
+            if state.generating {
+                state = self.done.wait(state).unwrap();
+                continue;
+            }
+
+            // run a pass for everything queued, this request included
+            let batch = std::mem::take(&mut state.pending);
+            state.generating = true;
+            drop(state);
+
+            let (encoder, depth, reserved) = {
+                let mut producer = producer.lock().unwrap();
+                let reserved: Vec<_> = batch.iter().map(|(ticket, session_id, count)| (*ticket, producer.reserve_symbols(*session_id, *count))).collect();
+                (producer.get_encoder().clone(), producer.get_interleave_depth(), reserved)
+            };
+            let results = reserved.into_iter().map(|(ticket, ranges)| {
+                (ticket, ranges.map(|ranges| interleave_blocks(ranges.iter().flat_map(|x| x.generate(&encoder)).collect(), depth)))
+            });
+
+            state = self.state.lock().unwrap();
+            state.results.extend(results);
+            state.generating = false;
+            state.stats.passes += 1;
+            self.done.notify_all();
+        }
+    }
+
+    pub fn get_stats(&self) -> CoalesceStats {
+        return self.state.lock().unwrap().stats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoder::RaptorQEncoder;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_symbol_coalescer() {
+        let data: Vec<u8> = (0..(100 * 1000)).map(|x| x as u8).collect();
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let producer = Arc::new(Mutex::new(SymbolProducer::new(encoder)));
+        let coalescer = Arc::new(SymbolCoalescer::new());
+        let session_ids: Vec<SessionId> = (0..8).map(|_| producer.lock().unwrap().open_session()).collect();
+
+        let threads: Vec<std::thread::JoinHandle<Vec<EncodedBlock>>> = session_ids.iter().map(|session_id| {
+            let (producer, coalescer, session_id) = (producer.clone(), coalescer.clone(), *session_id);
+            std::thread::spawn(move || {
+                let mut blocks: Vec<EncodedBlock> = Vec::new();
+                for _ in 0..20 {
+                    blocks.append(&mut coalescer.next_symbols(&producer, session_id, 10).unwrap());
+                }
+                blocks
+            })
+        }).collect();
+
+        // every request got its own session's symbols, never repeating one
+        for thread in threads {
+            let blocks = thread.join().unwrap();
+            assert_eq!(blocks.len(), 200);
+            let esis: HashSet<u32> = blocks.iter().map(|x| x.data.payload_id().encoding_symbol_id()).collect();
+            assert_eq!(esis.len(), 200);
+        }
+
+        let stats = coalescer.get_stats();
+        assert_eq!(stats.requests, 160);
+        assert!(stats.passes >= 1 && stats.passes <= 160);
+        assert_eq!(coalescer.next_symbols(&producer, 1000, 1), Err(SymbolProducerError::UnknownSession));
+    }
+}
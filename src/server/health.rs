@@ -0,0 +1,90 @@
+//! Telling supervisors whether a server is up. HttpServer answers liveness and readiness probes from a Readiness,
+//! e.g. for Kubernetes, and notify_systemd tells systemd the same for services of Type=notify.
+
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Named checks that must all pass before a server takes traffic, e.g. that its listener is bound and its catalog
+/// loaded. Checks can fail again later, e.g. when a catalog can no longer be reloaded.
+pub struct Readiness {
+    checks: Mutex<Vec<(String, bool)>>,
+}
+
+impl Readiness {
+    pub fn new() -> Readiness {
+        return Readiness { checks: Mutex::new(Vec::new()) };
+    }
+
+    /// Sets whether the named check passes, adding it if there is none of that name yet.
+    pub fn set(&self, name: &str, ready: bool) {
+        let mut checks = self.checks.lock().unwrap();
+        match checks.iter_mut().find(|x| x.0 == name) {
+            Some(check) => check.1 = ready,
+            None => checks.push((name.to_string(), ready)),
+        }
+    }
+
+    /// Whether every check passes.
+    pub fn is_ready(&self) -> bool {
+        return self.checks.lock().unwrap().iter().all(|x| x.1);
+    }
+
+    /// Gets the checks with whether they pass, in the order they were added.
+    pub fn get_checks(&self) -> Vec<(String, bool)> {
+        return self.checks.lock().unwrap().clone();
+    }
+}
+
+impl Default for Readiness {
+    fn default() -> Readiness {
+        return Readiness::new();
+    }
+}
+
+/// Sends state, e.g. "READY=1" or "WATCHDOG=1", to the systemd notification socket, see sd_notify(3). Returns false
+/// without sending anything if the process was not started with one.
+#[cfg(unix)]
+pub fn notify_systemd(state: &str) -> io::Result<bool> {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        None => return Ok(false),
+        Some(path) => path,
+    };
+    let socket = UnixDatagram::unbound()?;
+    match path.to_str().and_then(|x| x.strip_prefix('@')) {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        },
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "abstract sockets are only supported on Linux")),
+        None => {
+            socket.send_to(state.as_bytes(), &path)?;
+        },
+    }
+    return Ok(true);
+}
+
+#[cfg(not(unix))]
+pub fn notify_systemd(_state: &str) -> io::Result<bool> {
+    return Ok(false);
+}
+
+/// Gets how often systemd expects a "WATCHDOG=1" notification from this process, or None if it does not watch it.
+pub fn get_watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = std::env::var("WATCHDOG_PID").ok().and_then(|x| x.parse::<u32>().ok()) {
+        if pid != std::process::id() {
+            return None;
+        }
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    return Some(Duration::from_micros(usec));
+}
+
@@ -0,0 +1,1019 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::codec::encoder::EncodedBlock;
+use crate::codec::manifest::{parse_object_id, to_hex, ObjectId};
+use crate::codec::producer::{interleave_blocks, SessionId, SymbolProducerError};
+use crate::codec::shard::{write_shard, write_shard_records, SHARD_MAGIC};
+use crate::store::object_store::ObjectStore;
+use crate::store::tenant::TenantStore;
+use super::catalog::{Catalog, CatalogEntry};
+use super::health::Readiness;
+use super::manifest_key::ManifestKey;
+use super::pex::PeerExchange;
+use super::purge::{PurgeError, PurgeKey};
+use super::tenant::{RateLimiter, Tenant};
+use super::token::TokenKey;
+#[cfg(feature = "chaos")]
+use super::chaos::{Chaos, ChaosSettings};
+
+/// Most symbols handed out by a single symbols request.
+const MAX_SYMBOLS_PER_REQUEST: usize = 1 << 16;
+
+/// Most bytes read of a request line and headers, so a client can't make us buffer without bound.
+const MAX_REQUEST_HEAD_SIZE: u64 = 16 * 1024;
+
+/// Most bytes of an object returned by a single range request.
+const MAX_RANGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Symbols handed out by a symbols request without a count.
+const DEFAULT_SYMBOLS_PER_REQUEST: usize = 64;
+
+/// Symbols generated and sent per chunk of a symbol stream.
+const SYMBOLS_PER_CHUNK: usize = 16;
+
+/// Rate of a symbol stream requested without one, in bytes per second.
+const DEFAULT_STREAM_RATE: u64 = 1024 * 1024;
+
+/// How long a symbol stream waits for its tenant's bandwidth before trying again.
+const BANDWIDTH_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Minimal HTTP/1.1 transport for a Catalog, one request per connection:
+/// - GET /healthz answers 200 as long as connections are served, for liveness probes
+/// - GET /readyz answers 200 once every check of the server's Readiness passes and 503 Service Unavailable before,
+///   listing the checks either way, for readiness probes
+/// - GET /objects lists objects, one "<object id> <size> <name>" line each
+/// - GET /objects/<object id>/manifest returns the manifest, with when it was issued, the modification time of the
+///   file in seconds since the unix epoch, in the X-Manifest-Issued header
+/// - GET /objects/<object id>/symbols?session=<id>&count=<n>&block=<id> returns the next n symbols of a session as a
+///   shard, only of the given block if any. Without a session a new one is opened; the session id is returned in the
+///   X-Session-Id header either way.
+/// - GET /objects/<object id>/stream?session=<id>&rate=<bytes per second>&count=<n> streams symbols of a session as a
+///   shard in a chunked response, at the rate, until n symbols were sent or the client goes away. This works
+///   wherever HTTP does, e.g. for clients that can only reach out through an HTTP proxy.
+///
+/// A server created with bind_tenants serves the same under /tenants/<name>/ for each tenant instead, e.g.
+/// GET /tenants/<name>/objects. Symbol requests over the tenant's bandwidth get the symbols its budget has left, or
+/// 429 Too Many Requests once it is spent.
+///
+/// With require_tokens, object resources need a token=<token> signed for the object, and for the tenant serving it if
+/// any, and listing objects is refused.
+///
+/// With accept_purges, GET /purge?notice=<notice> purges the object of a signed purge notice from every catalog, see
+/// Catalog::purge, returning the names of the files deleted one per line. Streams of the object still open end
+/// early.
+///
+/// With sign_manifests, manifests come with their signature in the X-Manifest-Signature header, see the
+/// manifest_key module.
+///
+/// With exchange_peers, GET /objects/<object id>/peers?announce=<host:port> returns other peers of the object as a
+/// peer list, see the pex module, answering 404 if none is known. The client asking is announced as a peer if it
+/// gives its address.
+///
+/// With serve_object_store, GET /objects/<object id>/range?offset=<n>&len=<n> returns len bytes of the object at
+/// offset as stored in an ObjectStore, e.g. the one a relay fetches into, as soon as the blocks covering them are
+/// in, answering 404 if they are not yet and 416 Range Not Satisfiable past the end of the object. Objects of the
+/// catalog are opened in the store first, so it finds those it finalized before a restart. Each tenant has its own
+/// view of the store, see store::tenant.
+///
+/// With the chaos feature and control_chaos, GET /chaos?token=<admin token>&drop_ppm=<n>&corrupt_ppm=<n>&
+/// control_delay_ms=<n>&paused=<bool> changes the given failure injection settings and returns the current ones,
+/// answering 403 Forbidden without the admin token.
+pub struct HttpServer {
+    listener: TcpListener,
+    context: Arc<ServerContext>,
+}
+
+/// State shared by the connections of an HttpServer.
+struct ServerContext {
+    /// Catalog served at the root, if any.
+    catalog: Option<Arc<Catalog>>,
+    tenants: HashMap<String, Arc<Tenant>>,
+    token_key: Option<TokenKey>,
+    purge_key: Option<PurgeKey>,
+    peer_exchange: Option<Arc<PeerExchange>>,
+    manifest_key: Option<ManifestKey>,
+    /// Store ranges of objects are read from, if any.
+    object_store: Option<Arc<dyn ObjectStore>>,
+    /// Whether symbol requests for the same object are generated in shared passes.
+    coalesce: bool,
+    /// Whether symbol requests are served from the objects' symbol pools when they can be.
+    pool_symbols: bool,
+    /// Adjacent blocks whose symbols are alternated in a response, see interleave_blocks.
+    interleave_depth: usize,
+    readiness: Arc<Readiness>,
+    #[cfg(feature = "chaos")]
+    chaos: Arc<Chaos>,
+    /// Token GET /chaos must carry, None to not serve it.
+    #[cfg(feature = "chaos")]
+    admin_token: Option<String>,
+}
+
+struct Response<'a> {
+    status: &'static str,
+    content_type: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+    /// Symbols to stream as the body instead, chunked.
+    stream: Option<SymbolStream<'a>>,
+}
+
+impl Response<'_> {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Response<'static> {
+        return Response {
+            status: "200 OK",
+            content_type,
+            headers: Vec::new(),
+            body,
+            stream: None,
+        };
+    }
+
+    fn error(status: &'static str) -> Response<'static> {
+        return Response {
+            status,
+            content_type: "text/plain",
+            headers: Vec::new(),
+            body: format!("{}\n", status).into_bytes(),
+            stream: None,
+        };
+    }
+}
+
+/// Symbols of a session streamed at a fixed rate, see HttpServer.
+struct SymbolStream<'a> {
+    entry: Arc<CatalogEntry>,
+    session_id: SessionId,
+    bandwidth: Option<&'a RateLimiter>,
+    bytes_per_sec: u64,
+    /// Symbols left to send, None to send until the client goes away.
+    remaining: Option<usize>,
+}
+
+impl HttpServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A, catalog: Arc<Catalog>) -> io::Result<HttpServer> {
+        return HttpServer::build(TcpListener::bind(addr)?, Some(catalog), Vec::new());
+    }
+
+    /// Creates a server for several tenants, each served under /tenants/<name>/. Tenants' limits can be changed
+    /// through the handles while serving.
+    pub fn bind_tenants<A: ToSocketAddrs>(addr: A, tenants: Vec<Arc<Tenant>>) -> io::Result<HttpServer> {
+        return HttpServer::build(TcpListener::bind(addr)?, None, tenants);
+    }
+
+    /// Creates a server on a listener bound already, e.g. by transport::net::bind_tcp for an address family.
+    pub fn from_listener(listener: TcpListener, catalog: Arc<Catalog>) -> io::Result<HttpServer> {
+        return HttpServer::build(listener, Some(catalog), Vec::new());
+    }
+
+    /// Creates a server for several tenants, as bind_tenants does, on a listener bound already.
+    pub fn from_listener_tenants(listener: TcpListener, tenants: Vec<Arc<Tenant>>) -> io::Result<HttpServer> {
+        return HttpServer::build(listener, None, tenants);
+    }
+
+    fn build(listener: TcpListener, catalog: Option<Arc<Catalog>>, tenants: Vec<Arc<Tenant>>) -> io::Result<HttpServer> {
+        let readiness = Arc::new(Readiness::new());
+        readiness.set("listener", true);
+        return Ok(HttpServer {
+            listener,
+            context: Arc::new(ServerContext {
+                catalog,
+                tenants: tenants.into_iter().map(|x| (x.get_name().to_string(), x)).collect(),
+                token_key: None,
+                purge_key: None,
+                peer_exchange: None,
+                manifest_key: None,
+                object_store: None,
+                coalesce: false,
+                pool_symbols: false,
+                interleave_depth: 1,
+                readiness,
+                #[cfg(feature = "chaos")]
+                chaos: Arc::new(Chaos::new()),
+                #[cfg(feature = "chaos")]
+                admin_token: None,
+            }),
+        });
+    }
+
+    /// Requires requests for an object's manifest or symbols to carry a token signed with key for that object.
+    pub fn require_tokens(&mut self, key: TokenKey) {
+        // connections only share the context once run is called, which takes self
+        Arc::get_mut(&mut self.context).unwrap().token_key = Some(key);
+    }
+
+    /// Purges objects on notices signed with key, see the purge module.
+    pub fn accept_purges(&mut self, key: PurgeKey) {
+        Arc::get_mut(&mut self.context).unwrap().purge_key = Some(key);
+    }
+
+    /// Signs the manifests served with key, see the manifest_key module.
+    pub fn sign_manifests(&mut self, key: ManifestKey) {
+        Arc::get_mut(&mut self.context).unwrap().manifest_key = Some(key);
+    }
+
+    /// Tells clients of other peers of the objects through exchange, and takes their announcements into it. The
+    /// exchange can be shared with other servers or fed by the application, e.g. with lists signed by a tracker.
+    pub fn exchange_peers(&mut self, exchange: Arc<PeerExchange>) {
+        Arc::get_mut(&mut self.context).unwrap().peer_exchange = Some(exchange);
+    }
+
+    /// Serves ranges of the objects in store, including those only partially fetched into it, see HttpServer.
+    pub fn serve_object_store(&mut self, store: Arc<dyn ObjectStore>) {
+        Arc::get_mut(&mut self.context).unwrap().object_store = Some(store);
+    }
+
+    /// Generates the symbols of concurrent requests for the same object in shared passes, see SymbolCoalescer.
+    /// Fewer passes contend for the object's producer, at the cost of generating a pass on a single thread.
+    pub fn coalesce_symbols(&mut self) {
+        Arc::get_mut(&mut self.context).unwrap().coalesce = true;
+    }
+
+    /// Serves symbol requests from the objects' symbol pools when they hold enough, see SymbolPool. The pools are
+    /// only filled by Catalog::refill_pools, which the application calls periodically.
+    pub fn pool_symbols(&mut self) {
+        Arc::get_mut(&mut self.context).unwrap().pool_symbols = true;
+    }
+
+    /// Alternates the symbols of depth adjacent blocks in responses, so a client relaying them over a lossy link in
+    /// order loses a burst across several blocks, see interleave_blocks. Panics if depth is zero.
+    pub fn interleave_symbols(&mut self, depth: usize) {
+        assert!(depth > 0, "interleave depth must be positive");
+        Arc::get_mut(&mut self.context).unwrap().interleave_depth = depth;
+    }
+
+    /// Gets the checks GET /readyz reports, with the listener's already passing. Whoever loads the catalogs adds
+    /// checks for them, so probes hold traffic back until they are.
+    pub fn get_readiness(&self) -> Arc<Readiness> {
+        return self.context.readiness.clone();
+    }
+
+    /// Gets the failure injection applied to this server's responses.
+    #[cfg(feature = "chaos")]
+    pub fn get_chaos(&self) -> Arc<Chaos> {
+        return self.context.chaos.clone();
+    }
+
+    /// Serves GET /chaos to requests carrying admin_token, so only operators can inject failures. Without it the
+    /// failure injection is only controlled through get_chaos.
+    #[cfg(feature = "chaos")]
+    pub fn control_chaos(&mut self, admin_token: &str) {
+        Arc::get_mut(&mut self.context).unwrap().admin_token = Some(admin_token.to_string());
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        return self.listener.local_addr();
+    }
+
+    /// Accepts connections forever, serving each on its own thread.
+    pub fn run(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let context = self.context.clone();
+            std::thread::spawn(move || {
+                // the client going away mid-request is not our problem
+                let _ = HttpServer::handle_connection(stream, &context);
+            });
+        }
+
+        return Ok(());
+    }
+
+    fn handle_connection(stream: TcpStream, context: &ServerContext) -> io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut reader = BufReader::new((&stream).take(MAX_REQUEST_HEAD_SIZE));
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // skip headers, nothing we serve depends on them
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some("GET"), Some(target)) => HttpServer::route(target, context),
+            (Some(_), Some(_)) => Response::error("405 Method Not Allowed"),
+            _ => Response::error("400 Bad Request"),
+        };
+
+        let mut writer = io::BufWriter::new(&stream);
+        write!(writer, "HTTP/1.1 {}\r\nContent-Type: {}\r\nConnection: close\r\n", response.status, response.content_type)?;
+        match response.stream.is_some() {
+            true => writer.write_all(b"Transfer-Encoding: chunked\r\n")?,
+            false => write!(writer, "Content-Length: {}\r\n", response.body.len())?,
+        }
+        for (name, value) in response.headers.iter() {
+            write!(writer, "{}: {}\r\n", name, value)?;
+        }
+        writer.write_all(b"\r\n")?;
+        match response.stream {
+            None => writer.write_all(&response.body)?,
+            Some(symbol_stream) => HttpServer::write_stream(&mut writer, symbol_stream, context)?,
+        }
+        return writer.flush();
+    }
+
+    /// Writes the chunks of a symbol stream, pacing them to its rate, then the last chunk.
+    #[cfg_attr(not(feature = "chaos"), allow(unused_variables))]
+    fn write_stream<W: Write>(writer: &mut W, mut symbol_stream: SymbolStream, context: &ServerContext) -> io::Result<()> {
+        let write_chunk = |writer: &mut W, chunk: &[u8]| -> io::Result<()> {
+            write!(writer, "{:x}\r\n", chunk.len())?;
+            writer.write_all(chunk)?;
+            writer.write_all(b"\r\n")?;
+            return writer.flush();
+        };
+
+        write_chunk(writer, SHARD_MAGIC)?;
+        let start = Instant::now();
+        let mut sent_bytes: u64 = 0;
+        while symbol_stream.remaining != Some(0) && !symbol_stream.entry.is_purged() {
+            let count = symbol_stream.remaining.map_or(SYMBOLS_PER_CHUNK, |x| x.min(SYMBOLS_PER_CHUNK));
+            let blocks = match HttpServer::next_symbols(context, &symbol_stream.entry, symbol_stream.session_id, count) {
+                Ok(blocks) => blocks,
+                // the head is out, all that is left is ending the stream early
+                Err(_) => break,
+            };
+            #[cfg(feature = "chaos")]
+            let blocks = context.chaos.apply(blocks);
+
+            let mut chunk: Vec<u8> = Vec::new();
+            write_shard_records(&mut chunk, &blocks)?;
+            if let Some(bandwidth) = symbol_stream.bandwidth {
+                // in pieces of at most a second's worth, as the bucket never holds more
+                let mut owed: u64 = blocks.iter().map(|x| x.data.data().len() as u64).sum();
+                while owed > 0 {
+                    let piece = owed.min(bandwidth.get_rate().unwrap_or(u64::MAX).max(1));
+                    while !bandwidth.try_take(piece) {
+                        std::thread::sleep(BANDWIDTH_RETRY_INTERVAL);
+                    }
+                    owed -= piece;
+                }
+            }
+            let due = Duration::from_secs_f64(sent_bytes as f64 / symbol_stream.bytes_per_sec as f64);
+            std::thread::sleep(due.saturating_sub(start.elapsed()));
+            write_chunk(writer, &chunk)?;
+
+            sent_bytes += chunk.len() as u64;
+            symbol_stream.remaining = symbol_stream.remaining.map(|x| x - count);
+        }
+
+        return write_chunk(writer, &[]);
+    }
+
+    fn route<'a>(target: &str, context: &'a ServerContext) -> Response<'a> {
+        let (path, query) = match target.split_once('?') {
+            None => (target, ""),
+            Some(split) => split,
+        };
+        let segments: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+
+        // symbols have their own failures, and the chaos endpoint must stay responsive to turn chaos off
+        #[cfg(feature = "chaos")]
+        if segments != ["chaos"] && segments.last() != Some(&"symbols") {
+            context.chaos.delay_control();
+        }
+
+        match segments[..] {
+            ["healthz"] => return Response::ok("text/plain", b"ok\n".to_vec()),
+            ["readyz"] => {
+                let mut body = String::new();
+                for (name, ready) in context.readiness.get_checks() {
+                    body.push_str(&format!("{} {}\n", name, if ready { "ok" } else { "not ready" }));
+                }
+                let mut response = Response::ok("text/plain", body.into_bytes());
+                if !context.readiness.is_ready() {
+                    response.status = "503 Service Unavailable";
+                }
+                return response;
+            },
+            #[cfg(feature = "chaos")]
+            ["chaos"] if context.admin_token.is_some() => return HttpServer::chaos(context, query),
+            ["purge"] if context.purge_key.is_some() => return HttpServer::purge(context, query),
+            ["tenants", name, ref rest @ ..] => match context.tenants.get(name) {
+                None => return Response::error("404 Not Found"),
+                Some(tenant) => return HttpServer::route_catalog(context, Some(tenant), tenant.get_catalog(), rest, query),
+            },
+            ref rest => match context.catalog.as_ref() {
+                None => return Response::error("404 Not Found"),
+                Some(catalog) => return HttpServer::route_catalog(context, None, catalog, rest, query),
+            },
+        }
+    }
+
+    fn route_catalog<'a>(context: &ServerContext, tenant: Option<&'a Tenant>, catalog: &Catalog, segments: &[&str], query: &str) -> Response<'a> {
+        let bandwidth = tenant.map(|x| x.get_bandwidth_limit());
+        match segments {
+            ["objects"] if context.token_key.is_some() => return Response::error("403 Forbidden"),
+            ["objects"] => {
+                let mut body = String::new();
+                for entry in catalog.list() {
+                    body.push_str(&format!("{} {} {}\n", to_hex(&entry.manifest.object_id), entry.manifest.data_size, entry.name));
+                }
+                return Response::ok("text/plain", body.into_bytes());
+            },
+            ["objects", object_id, "range"] if context.object_store.is_some() => {
+                let object_id = match parse_object_id(object_id) {
+                    None => return Response::error("404 Not Found"),
+                    Some(object_id) => object_id,
+                };
+                if !HttpServer::check_token(context, tenant, &object_id, query) {
+                    return Response::error("403 Forbidden");
+                }
+                return HttpServer::range(context, tenant, catalog, &object_id, query);
+            },
+            ["objects", object_id, resource] => {
+                let entry = match parse_object_id(object_id).and_then(|x| catalog.get(&x)) {
+                    None => return Response::error("404 Not Found"),
+                    Some(entry) => entry,
+                };
+                if !HttpServer::check_token(context, tenant, &entry.manifest.object_id, query) {
+                    return Response::error("403 Forbidden");
+                }
+
+                match *resource {
+                    "manifest" => {
+                        let mut body: Vec<u8> = Vec::new();
+                        if entry.manifest.write_to(&mut body).is_err() {
+                            return Response::error("500 Internal Server Error");
+                        }
+                        let issued = entry.get_modified().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                        let mut response = Response::ok("application/octet-stream", body);
+                        response.headers.push(("X-Manifest-Issued", issued.to_string()));
+                        if let Some(key) = context.manifest_key.as_ref() {
+                            match key.sign(&entry.manifest, issued) {
+                                Ok(signature) => response.headers.push(("X-Manifest-Signature", signature)),
+                                Err(_) => return Response::error("500 Internal Server Error"),
+                            }
+                        }
+                        return response;
+                    },
+                    "symbols" => return HttpServer::symbols(context, &entry, bandwidth, query),
+                    "stream" => return HttpServer::stream(entry, bandwidth, query),
+                    "peers" => return HttpServer::peers(context, &entry, query),
+                    _ => return Response::error("404 Not Found"),
+                }
+            },
+            _ => return Response::error("404 Not Found"),
+        }
+    }
+
+    /// Checks the request carries a token for the object, and the tenant if any, if the server requires them.
+    fn check_token(context: &ServerContext, tenant: Option<&Tenant>, object_id: &ObjectId, query: &str) -> bool {
+        let token_key = match context.token_key.as_ref() {
+            None => return true,
+            Some(token_key) => token_key,
+        };
+        let token = query.split('&').find_map(|x| x.strip_prefix("token="));
+        return token.is_some_and(|x| token_key.verify(tenant.map(|x| x.get_name()), object_id, x).is_ok());
+    }
+
+    fn range(context: &ServerContext, tenant: Option<&Tenant>, catalog: &Catalog, object_id: &ObjectId, query: &str) -> Response<'static> {
+        let mut offset: Option<u64> = None;
+        let mut len: Option<usize> = None;
+        for pair in query.split('&').filter(|x| !x.is_empty()) {
+            let parsed = match pair.split_once('=') {
+                Some(("offset", value)) => value.parse().map(|x| offset = Some(x)).is_ok(),
+                Some(("len", value)) => value.parse().map(|x| len = Some(x)).is_ok(),
+                _ => true,
+            };
+            if !parsed {
+                return Response::error("400 Bad Request");
+            }
+        }
+        let (offset, len) = match (offset, len) {
+            (Some(offset), Some(len)) if len <= MAX_RANGE_SIZE => (offset, len),
+            _ => return Response::error("400 Bad Request"),
+        };
+
+        let shared = context.object_store.clone().unwrap();
+        let store: Arc<dyn ObjectStore> = match tenant {
+            None => shared,
+            Some(tenant) => Arc::new(TenantStore::new(tenant.get_name(), shared)),
+        };
+        if let Some(entry) = catalog.get(object_id) {
+            if store.open(&entry.manifest).is_err() {
+                return Response::error("500 Internal Server Error");
+            }
+        }
+        match store.get_range(object_id, offset, len) {
+            Ok(data) => return Response::ok("application/octet-stream", data),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Response::error("404 Not Found"),
+            Err(error) if error.kind() == io::ErrorKind::InvalidInput => return Response::error("416 Range Not Satisfiable"),
+            Err(_) => return Response::error("500 Internal Server Error"),
+        }
+    }
+
+    #[cfg_attr(not(feature = "chaos"), allow(unused_variables))]
+    fn symbols(context: &ServerContext, entry: &CatalogEntry, bandwidth: Option<&RateLimiter>, query: &str) -> Response<'static> {
+        let mut session_id: Option<u64> = None;
+        let mut count = DEFAULT_SYMBOLS_PER_REQUEST;
+        let mut block_id: Option<u32> = None;
+        for pair in query.split('&').filter(|x| !x.is_empty()) {
+            let parsed = match pair.split_once('=') {
+                Some(("session", value)) => value.parse().map(|x| session_id = Some(x)).is_ok(),
+                Some(("count", value)) => value.parse().map(|x| count = x).is_ok(),
+                Some(("block", value)) => value.parse().map(|x| block_id = Some(x)).is_ok(),
+                _ => true,
+            };
+            if !parsed {
+                return Response::error("400 Bad Request");
+            }
+        }
+        if count > MAX_SYMBOLS_PER_REQUEST {
+            return Response::error("400 Bad Request");
+        }
+        // requests over the tenant's budget get what it has left, rather than nothing every time they are retried
+        if let Some(bandwidth) = bandwidth {
+            count = bandwidth.take_units(count as u64, entry.manifest.config.packet_size as u64) as usize;
+            if count == 0 {
+                return Response::error("429 Too Many Requests");
+            }
+        }
+
+        #[cfg(feature = "chaos")]
+        context.chaos.wait_unpaused();
+
+        let session_id = match session_id {
+            None => entry.producer.lock().unwrap().open_session(),
+            Some(session_id) => session_id,
+        };
+        let pooled = match context.pool_symbols {
+            true => HttpServer::pooled_symbols(entry, session_id, block_id, count),
+            false => None,
+        };
+        let blocks = match (pooled, block_id) {
+            (Some(blocks), None) => blocks.map(|x| interleave_blocks(x, context.interleave_depth)),
+            (Some(blocks), Some(_)) => blocks,
+            (None, None) => HttpServer::next_symbols(context, entry, session_id, count),
+            // symbols of a single block are what a client is missing, so not worth coalescing
+            (None, Some(block_id)) => entry.producer.lock().unwrap().next_block_symbols(session_id, block_id, count),
+        };
+        let blocks = match blocks {
+            Ok(blocks) => blocks,
+            Err(SymbolProducerError::EsiSpaceExhausted) => return Response::error("410 Gone"),
+            Err(_) => return Response::error("404 Not Found"),
+        };
+        #[cfg(feature = "chaos")]
+        let blocks = context.chaos.apply(blocks);
+
+        let mut body: Vec<u8> = Vec::new();
+        if write_shard(&mut body, &blocks).is_err() {
+            return Response::error("500 Internal Server Error");
+        }
+        let mut response = Response::ok("application/octet-stream", body);
+        response.headers.push(("X-Session-Id", session_id.to_string()));
+        return response;
+    }
+
+    /// Takes the symbols of a request from the object's pool, split over blocks as the producer would, or of one
+    /// block if given. None if the pool doesn't hold them all, for the producer to generate them.
+    fn pooled_symbols(entry: &CatalogEntry, session_id: SessionId, block_id: Option<u32>, count: usize) -> Option<Result<Vec<EncodedBlock>, SymbolProducerError>> {
+        let counts = {
+            let mut producer = entry.producer.lock().unwrap();
+            if let Err(error) = producer.touch_session(session_id) {
+                return Some(Err(error));
+            }
+            match block_id {
+                None => producer.get_block_counts(count),
+                Some(block_id) if (block_id as usize) < entry.manifest.get_block_count() => {
+                    let mut counts = vec![0; entry.manifest.get_block_count()];
+                    counts[block_id as usize] = count;
+                    counts
+                },
+                Some(_) => return Some(Err(SymbolProducerError::BadBlockId)),
+            }
+        };
+        return entry.pool.take(&counts).map(Ok);
+    }
+
+    /// Generates the next count symbols of a session, interleaved as configured.
+    fn next_symbols(context: &ServerContext, entry: &CatalogEntry, session_id: SessionId, count: usize) -> Result<Vec<EncodedBlock>, SymbolProducerError> {
+        let blocks = if context.coalesce {
+            entry.coalescer.next_symbols(&entry.producer, session_id, count)
+        } else {
+            let mut producer = entry.producer.lock().unwrap();
+            // generate without holding the producer, so other sessions of the object are served meanwhile
+            producer.reserve_symbols(session_id, count).map(|ranges| {
+                let encoder = producer.get_encoder().clone();
+                drop(producer);
+                ranges.iter().flat_map(|x| x.generate(&encoder)).collect::<Vec<EncodedBlock>>()
+            })
+        };
+        return blocks.map(|x| interleave_blocks(x, context.interleave_depth));
+    }
+
+    fn stream<'a>(entry: Arc<CatalogEntry>, bandwidth: Option<&'a RateLimiter>, query: &str) -> Response<'a> {
+        let mut session_id: Option<u64> = None;
+        let mut bytes_per_sec = DEFAULT_STREAM_RATE;
+        let mut remaining: Option<usize> = None;
+        for pair in query.split('&').filter(|x| !x.is_empty()) {
+            let parsed = match pair.split_once('=') {
+                Some(("session", value)) => value.parse().map(|x| session_id = Some(x)).is_ok(),
+                Some(("rate", value)) => value.parse().map(|x| bytes_per_sec = x).is_ok(),
+                Some(("count", value)) => value.parse().map(|x| remaining = Some(x)).is_ok(),
+                _ => true,
+            };
+            if !parsed {
+                return Response::error("400 Bad Request");
+            }
+        }
+        if bytes_per_sec == 0 {
+            return Response::error("400 Bad Request");
+        }
+
+        let session_id = match session_id {
+            None => entry.producer.lock().unwrap().open_session(),
+            Some(session_id) => session_id,
+        };
+        if entry.producer.lock().unwrap().get_fresh_symbols(session_id, 0) == Err(SymbolProducerError::UnknownSession) {
+            return Response::error("404 Not Found");
+        }
+        let mut response = Response::ok("application/octet-stream", Vec::new());
+        response.headers.push(("X-Session-Id", session_id.to_string()));
+        response.stream = Some(SymbolStream { entry, session_id, bandwidth, bytes_per_sec, remaining });
+        return response;
+    }
+
+    fn peers(context: &ServerContext, entry: &CatalogEntry, query: &str) -> Response<'static> {
+        let exchange = match context.peer_exchange.as_ref() {
+            None => return Response::error("404 Not Found"),
+            Some(exchange) => exchange,
+        };
+        let announce = query.split('&').find_map(|x| x.strip_prefix("announce="));
+        if announce.is_some_and(|x| !exchange.announce(&entry.manifest.object_id, x)) {
+            return Response::error("400 Bad Request");
+        }
+        return match exchange.get_message(&entry.manifest.object_id, announce) {
+            None => Response::error("404 Not Found"),
+            Some(message) => Response::ok("text/plain", message.into_bytes()),
+        };
+    }
+
+    fn purge(context: &ServerContext, query: &str) -> Response<'static> {
+        let notice = match query.split('&').find_map(|x| x.strip_prefix("notice=")) {
+            None => return Response::error("400 Bad Request"),
+            Some(notice) => notice,
+        };
+        let notice = match context.purge_key.as_ref().unwrap().verify(notice) {
+            Ok(notice) => notice,
+            Err(PurgeError::Malformed) => return Response::error("400 Bad Request"),
+            Err(PurgeError::BadSignature) => return Response::error("403 Forbidden"),
+        };
+
+        let catalogs = context.catalog.iter().chain(context.tenants.values().map(|x| x.get_catalog()));
+        let mut body = String::new();
+        for catalog in catalogs {
+            match catalog.purge(&notice.object_id) {
+                Ok(names) => names.iter().for_each(|x| body.push_str(&format!("{}\n", x))),
+                Err(_) => return Response::error("500 Internal Server Error"),
+            }
+        }
+        return Response::ok("text/plain", body.into_bytes());
+    }
+
+    #[cfg(feature = "chaos")]
+    fn chaos(context: &ServerContext, query: &str) -> Response<'static> {
+        let token = query.split('&').find_map(|x| x.strip_prefix("token="));
+        if !token.zip(context.admin_token.as_ref()).is_some_and(|(x, y)| is_same_token(x, y)) {
+            return Response::error("403 Forbidden");
+        }
+
+        let chaos = &context.chaos;
+        let mut settings: ChaosSettings = chaos.get_settings();
+        for pair in query.split('&').filter(|x| !x.is_empty() && !x.starts_with("token=")) {
+            let parsed = match pair.split_once('=') {
+                Some(("drop_ppm", value)) => value.parse().map(|x| settings.drop_ppm = x).is_ok(),
+                Some(("corrupt_ppm", value)) => value.parse().map(|x| settings.corrupt_ppm = x).is_ok(),
+                Some(("control_delay_ms", value)) => value.parse().map(|x| settings.control_delay_ms = x).is_ok(),
+                Some(("paused", value)) => value.parse().map(|x| settings.paused = x).is_ok(),
+                _ => false,
+            };
+            if !parsed {
+                return Response::error("400 Bad Request");
+            }
+        }
+        chaos.set_settings(settings);
+
+        let settings = chaos.get_settings();
+        let body = format!(
+            "drop_ppm={}\ncorrupt_ppm={}\ncontrol_delay_ms={}\npaused={}\n",
+            settings.drop_ppm, settings.corrupt_ppm, settings.control_delay_ms, settings.paused,
+        );
+        return Response::ok("text/plain", body.into_bytes());
+    }
+}
+
+/// Compares tokens in time independent of where they differ, so a client can't guess one a byte at a time.
+#[cfg(feature = "chaos")]
+fn is_same_token(token: &str, expected: &str) -> bool {
+    return token.len() == expected.len() && token.bytes().zip(expected.bytes()).fold(0, |x, (a, b)| x | (a ^ b)) == 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::decoder::RaptorQDecoder;
+    use crate::codec::encoder::{BlockEncoder, EncoderConfig};
+    use crate::codec::manifest::Manifest;
+    use crate::codec::shard::read_shard;
+    use crate::store::memory::MemoryStore;
+
+    /// Sends a GET request, returning the response status line, headers and body.
+    fn get(addr: SocketAddr, target: &str) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: test\r\n\r\n", target).unwrap();
+        let mut response: Vec<u8> = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+
+        let split = response.windows(4).position(|x| x == b"\r\n\r\n").unwrap();
+        return (String::from_utf8(response[..split].to_vec()).unwrap(), response[(split + 4)..].to_vec());
+    }
+
+    #[test]
+    fn test_http_fetch() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-http-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(100 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+
+        let (head, body) = get(addr, "/objects");
+        assert!(head.starts_with("HTTP/1.1 200"));
+        let listing = String::from_utf8(body).unwrap();
+        let object_id = listing.split_whitespace().next().unwrap().to_string();
+
+        let (_, body) = get(addr, &format!("/objects/{}/manifest", object_id));
+        let manifest = Manifest::read_from(&body[..]).unwrap();
+        let mut decoder = match RaptorQDecoder::new(manifest.block_info_vec) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        let (head, body) = get(addr, &format!("/objects/{}/symbols?count=100", object_id));
+        let session_header = head.lines().find(|x| x.starts_with("X-Session-Id: ")).unwrap();
+        let session_id = session_header["X-Session-Id: ".len()..].to_string();
+        let mut decoded = decoder.consume(read_shard(&body[..]).unwrap()).unwrap();
+        while !decoded {
+            let (_, body) = get(addr, &format!("/objects/{}/symbols?session={}&count=10", object_id, session_id));
+            decoded = decoder.consume(read_shard(&body[..]).unwrap()).unwrap();
+        }
+        assert_eq!(decoder.get_result(), Some(data));
+
+        let (_, body) = get(addr, &format!("/objects/{}/symbols?session={}&count=5&block=0", object_id, session_id));
+        assert_eq!(read_shard(&body[..]).unwrap().iter().filter(|x| x.block_id == 0).count(), 5);
+        let (head, _) = get(addr, &format!("/objects/{}/symbols?session={}&block=99", object_id, session_id));
+        assert!(head.starts_with("HTTP/1.1 404"));
+
+        let (head, _) = get(addr, "/objects/00/manifest");
+        assert!(head.starts_with("HTTP/1.1 404"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_http_health() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-http-health-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let server = HttpServer::bind("127.0.0.1:0", Arc::new(Catalog::new(&root, EncoderConfig::new(1280)))).unwrap();
+        let readiness = server.get_readiness();
+        readiness.set("catalog", false);
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+
+        let (head, body) = get(addr, "/healthz");
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(body, b"ok\n");
+        let (head, body) = get(addr, "/readyz");
+        assert!(head.starts_with("HTTP/1.1 503"));
+        assert_eq!(body, b"listener ok\ncatalog not ready\n");
+        readiness.set("catalog", true);
+        let (head, _) = get(addr, "/readyz");
+        assert!(head.starts_with("HTTP/1.1 200"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_http_tokens() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-http-token-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("object"), gen_data(10 * 1000)).unwrap();
+
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+        let object_id = catalog.list()[0].manifest.object_id;
+        let key = TokenKey::new(b"0123456789abcdef0123456789abcdef");
+        let mut server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        server.require_tokens(key.clone());
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+
+        let (head, _) = get(addr, "/objects");
+        assert!(head.starts_with("HTTP/1.1 403"));
+        let (head, _) = get(addr, &format!("/objects/{}/manifest", to_hex(&object_id)));
+        assert!(head.starts_with("HTTP/1.1 403"));
+        let token = TokenKey::new(b"another key").sign(None, &object_id, Duration::from_secs(60));
+        let (head, _) = get(addr, &format!("/objects/{}/symbols?token={}", to_hex(&object_id), token));
+        assert!(head.starts_with("HTTP/1.1 403"));
+
+        let token = key.sign(None, &object_id, Duration::from_secs(60));
+        let (head, _) = get(addr, &format!("/objects/{}/manifest?token={}", to_hex(&object_id), token));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        let (head, body) = get(addr, &format!("/objects/{}/symbols?count=4&token={}", to_hex(&object_id), token));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(read_shard(&body[..]).unwrap().len(), 4);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_http_object_store() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-http-store-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let block_size: usize = 32 * 1024;
+        let data = gen_data(2 * block_size);
+        let encoders: Vec<BlockEncoder> = data.chunks(block_size).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, 1280, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let manifest = Manifest {
+            object_id: [3; 32],
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(1280),
+            block_info_vec: encoders.iter().map(|x| x.get_block_info()).collect(),
+            block_hashes: vec![[0; 32]; encoders.len()],
+            block_overheads: vec![0; encoders.len()],
+            envelope: None,
+        };
+        let store = Arc::new(MemoryStore::new());
+        store.open(&manifest).unwrap();
+        store.put_block(&manifest.object_id, 1, &data[block_size..]).unwrap();
+
+        let mut server = HttpServer::bind("127.0.0.1:0", Arc::new(Catalog::new(&root, EncoderConfig::new(1280)))).unwrap();
+        server.serve_object_store(store);
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+
+        let object_id = to_hex(&manifest.object_id);
+        let (head, body) = get(addr, &format!("/objects/{}/range?offset={}&len=100", object_id, block_size + 10));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(body, &data[(block_size + 10)..(block_size + 110)]);
+        let (head, _) = get(addr, &format!("/objects/{}/range?offset={}&len=100", object_id, block_size - 1));
+        assert!(head.starts_with("HTTP/1.1 404"));
+        let (head, _) = get(addr, &format!("/objects/{}/range?offset={}&len=100", object_id, data.len() - 1));
+        assert!(head.starts_with("HTTP/1.1 416"));
+        let (head, _) = get(addr, &format!("/objects/{}/range?offset=0", object_id));
+        assert!(head.starts_with("HTTP/1.1 400"));
+        let (head, _) = get(addr, &format!("/objects/{}/range?offset=0&len=1", to_hex(&[4; 32])));
+        assert!(head.starts_with("HTTP/1.1 404"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_http_tenants() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-http-tenant-test-{}", std::process::id()));
+        let data = gen_data(10 * 1000);
+        for name in ["a", "b"] {
+            std::fs::create_dir_all(root.join(name)).unwrap();
+            std::fs::write(root.join(name).join("object"), &data).unwrap();
+        }
+
+        let tenants: Vec<Arc<Tenant>> = ["a", "b"].iter().map(|name| {
+            let catalog = Arc::new(Catalog::new(root.join(name), EncoderConfig::new(1280)));
+            catalog.refresh().unwrap();
+            Arc::new(Tenant::new(name, catalog))
+        }).collect();
+        tenants[1].set_bandwidth_limit(Some(10 * 1280));
+        let object_id = to_hex(&tenants[0].get_catalog().list()[0].manifest.object_id);
+        let server = HttpServer::bind_tenants("127.0.0.1:0", tenants.clone()).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+
+        let (head, body) = get(addr, "/tenants/a/objects");
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(String::from_utf8(body).unwrap().starts_with(&object_id));
+        let (head, _) = get(addr, "/objects");
+        assert!(head.starts_with("HTTP/1.1 404"));
+        let (head, _) = get(addr, &format!("/tenants/c/objects/{}/manifest", object_id));
+        assert!(head.starts_with("HTTP/1.1 404"));
+
+        let (head, _) = get(addr, &format!("/tenants/a/objects/{}/symbols?count=20", object_id));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        let (head, body) = get(addr, &format!("/tenants/b/objects/{}/symbols?count=8", object_id));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(read_shard(&body[..]).unwrap().len(), 8);
+        // the rest of the second's budget, then nothing until it refills
+        let (head, body) = get(addr, &format!("/tenants/b/objects/{}/symbols?count=8", object_id));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!((2..8).contains(&read_shard(&body[..]).unwrap().len()));
+        let (head, _) = get(addr, &format!("/tenants/b/objects/{}/symbols?count=8", object_id));
+        assert!(head.starts_with("HTTP/1.1 429"));
+
+        // requests costing more than a second's budget, or symbols larger than it, are cut down rather than refused
+        tenants[1].set_bandwidth_limit(Some(16 * 1280));
+        std::thread::sleep(Duration::from_secs(1));
+        let (head, body) = get(addr, &format!("/tenants/b/objects/{}/symbols", object_id));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(read_shard(&body[..]).unwrap().len(), 16);
+        tenants[1].set_bandwidth_limit(Some(1000));
+        std::thread::sleep(Duration::from_secs(1));
+        let (head, body) = get(addr, &format!("/tenants/b/objects/{}/symbols", object_id));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(read_shard(&body[..]).unwrap().len(), 1);
+
+        // streams go out at the tenant's rate even when it is below a symbol a second
+        let (head, body) = get(addr, &format!("/tenants/b/objects/{}/stream?count=1", object_id));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(body.ends_with(b"0\r\n\r\n"));
+
+        // lifting the limit applies to the running server
+        tenants[1].set_bandwidth_limit(None);
+        let (head, _) = get(addr, &format!("/tenants/b/objects/{}/symbols?count=8", object_id));
+        assert!(head.starts_with("HTTP/1.1 200"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_http_tenant_tokens() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-http-tenant-token-test-{}", std::process::id()));
+        let data = gen_data(10 * 1000);
+        for name in ["a", "b"] {
+            std::fs::create_dir_all(root.join(name)).unwrap();
+            std::fs::write(root.join(name).join("object"), &data).unwrap();
+        }
+
+        let tenants: Vec<Arc<Tenant>> = ["a", "b"].iter().map(|name| {
+            let catalog = Arc::new(Catalog::new(root.join(name), EncoderConfig::new(1280)));
+            catalog.refresh().unwrap();
+            Arc::new(Tenant::new(name, catalog))
+        }).collect();
+        let object_id = tenants[0].get_catalog().list()[0].manifest.object_id;
+        let key = TokenKey::new(b"0123456789abcdef0123456789abcdef");
+        let mut server = HttpServer::bind_tenants("127.0.0.1:0", tenants).unwrap();
+        server.require_tokens(key.clone());
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+
+        // both tenants serve the same object, but a token of tenant a only gets it from tenant a
+        let token = key.sign(Some("a"), &object_id, Duration::from_secs(60));
+        let (head, _) = get(addr, &format!("/tenants/a/objects/{}/manifest?token={}", to_hex(&object_id), token));
+        assert!(head.starts_with("HTTP/1.1 200"));
+        let (head, _) = get(addr, &format!("/tenants/b/objects/{}/manifest?token={}", to_hex(&object_id), token));
+        assert!(head.starts_with("HTTP/1.1 403"));
+        let token = key.sign(None, &object_id, Duration::from_secs(60));
+        let (head, _) = get(addr, &format!("/tenants/b/objects/{}/symbols?token={}", to_hex(&object_id), token));
+        assert!(head.starts_with("HTTP/1.1 403"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "chaos")]
+    #[test]
+    fn test_http_chaos() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-http-chaos-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+        catalog.refresh().unwrap();
+
+        // not served at all without an admin token
+        let server = HttpServer::bind("127.0.0.1:0", catalog.clone()).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+        let (head, _) = get(addr, "/chaos?drop_ppm=1000000");
+        assert!(head.starts_with("HTTP/1.1 404"));
+
+        let mut server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+        server.control_chaos("secret");
+        let chaos = server.get_chaos();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+        for target in ["/chaos?drop_ppm=1000000", "/chaos?token=secreT&drop_ppm=1000000", "/chaos?token=secret2&drop_ppm=1000000"] {
+            let (head, _) = get(addr, target);
+            assert!(head.starts_with("HTTP/1.1 403"));
+        }
+        assert_eq!(chaos.get_settings().drop_ppm, 0);
+        let (head, body) = get(addr, "/chaos?token=secret&drop_ppm=1000000");
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(String::from_utf8(body).unwrap().starts_with("drop_ppm=1000000\n"));
+        assert_eq!(chaos.get_settings().drop_ppm, 1000000);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
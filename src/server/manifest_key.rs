@@ -0,0 +1,85 @@
+//! Signed manifests, so a client getting manifests of an object from several servers can tell those an origin
+//! vouches for from those any cache could have made up, see client::reconcile. A server signing manifests sends,
+//! with each, when it was issued, in seconds since the unix epoch, and a hex HMAC-SHA256 of the manifest as written
+//! by Manifest::write_to and the issue time under a key shared by the servers of the origin and their clients.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io;
+
+use crate::codec::manifest::{to_hex, Manifest};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Key manifests are signed and checked with. Use a different key than for tokens, purges and peer lists.
+#[derive(Clone)]
+pub struct ManifestKey {
+    key: Vec<u8>,
+}
+
+impl ManifestKey {
+    /// Creates a ManifestKey from secret bytes, e.g. the contents of a key file. Use at least 32 random bytes.
+    pub fn new(key: &[u8]) -> ManifestKey {
+        return ManifestKey { key: key.to_vec() };
+    }
+
+    fn mac(&self, manifest: &Manifest, issued: u64) -> io::Result<HmacSha256> {
+        let mut data: Vec<u8> = Vec::new();
+        manifest.write_to(&mut data)?;
+        // HMAC takes keys of any length
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(&data);
+        mac.update(&issued.to_le_bytes());
+        return Ok(mac);
+    }
+
+    /// Signs a manifest issued at issued, returning the signature in hex. Fails if the manifest can't be written, see
+    /// Manifest::write_to.
+    pub fn sign(&self, manifest: &Manifest, issued: u64) -> io::Result<String> {
+        return Ok(to_hex(&self.mac(manifest, issued)?.finalize().into_bytes()));
+    }
+
+    /// Checks a signature made by sign with this key. A manifest that can't be written has no valid signature.
+    pub fn verify(&self, manifest: &Manifest, issued: u64, signature: &str) -> bool {
+        if !signature.len().is_multiple_of(2) {
+            return false;
+        }
+        let signature: Option<Vec<u8>> = signature.as_bytes()
+            .chunks(2)
+            .map(|x| std::str::from_utf8(x).ok().and_then(|x| u8::from_str_radix(x, 16).ok()))
+            .collect();
+        return match (signature, self.mac(manifest, issued)) {
+            (Some(signature), Ok(mac)) => mac.verify_slice(&signature).is_ok(),
+            _ => false,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoder::RaptorQEncoder;
+    use crate::codec::envelope::{Envelope, WrappedKey, MAX_RECIPIENTS, NONCE_SIZE, RECIPIENT_KEY_SIZE, WRAPPED_KEY_SIZE};
+
+    #[test]
+    fn test_sign_manifest() {
+        let data: Vec<u8> = (0..20 * 1000).map(|x| x as u8).collect();
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let wrapped_key = WrappedKey { recipient: [1; RECIPIENT_KEY_SIZE], ephemeral: [2; RECIPIENT_KEY_SIZE], wrapped_key: vec![3; WRAPPED_KEY_SIZE] };
+        let mut manifest = Manifest::new(&encoder);
+        manifest.envelope = Some(Envelope { nonce: [0; NONCE_SIZE], wrapped_keys: vec![wrapped_key.clone()] });
+
+        let key = ManifestKey::new(b"0123456789abcdef0123456789abcdef");
+        let signature = key.sign(&manifest, 100).unwrap();
+        assert!(key.verify(&manifest, 100, &signature));
+        assert!(!key.verify(&manifest, 101, &signature));
+
+        // a manifest write_to refuses can't be signed, nor does any signature check out for it
+        manifest.envelope.as_mut().unwrap().wrapped_keys = vec![wrapped_key; MAX_RECIPIENTS + 1];
+        assert_eq!(key.sign(&manifest, 100).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(!key.verify(&manifest, 100, &signature));
+    }
+}
@@ -0,0 +1,13 @@
+pub mod broadcast;
+pub mod catalog;
+pub mod coalesce;
+pub mod health;
+pub mod http;
+pub mod manifest_key;
+pub mod pex;
+pub mod pool;
+pub mod purge;
+pub mod token;
+pub mod tenant;
+#[cfg(feature = "chaos")]
+pub mod chaos;
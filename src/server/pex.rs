@@ -0,0 +1,323 @@
+//! Peer exchange (PEX): peers of an object telling each other about more peers of it, so a fetch that starts from
+//! one or two addresses finds the rest of the swarm without asking a tracker each time. A peer list is text:
+//!
+//! ```text
+//! <object id> <expires>
+//! <address>
+//! ...
+//! signature <signature>
+//! ```
+//!
+//! with the expiry in seconds since the unix epoch, one "host:port" address per line, and the signature line only
+//! on lists signed by a tracker: a hex HMAC-SHA256 of everything before it under a key shared by the tracker and
+//! clients. Clients holding the key take only signed lists, so a peer can't steer them to addresses of its own;
+//! others take any list. Servers relay the lists they are given as they are, see PeerExchange.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::codec::manifest::{parse_object_id, to_hex, ObjectId};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Most addresses in a peer list, so gossip stays small and a peer can't flood a client with addresses.
+pub const MAX_PEX_PEERS: usize = 50;
+
+/// Longest address in a peer list, as a DNS name with a port.
+const MAX_ADDRESS_LEN: usize = 262;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PexError {
+    Malformed,
+    Expired,
+    /// Not signed, but a key to check it with was given.
+    Unsigned,
+    BadSignature,
+}
+
+/// Peers of an object, as exchanged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerList {
+    pub object_id: ObjectId,
+    /// Seconds since the unix epoch.
+    pub expires: u64,
+    /// Addresses, e.g. "host:port".
+    pub peers: Vec<String>,
+}
+
+impl PeerList {
+    /// Creates a list valid for ttl from now.
+    pub fn new(object_id: ObjectId, peers: Vec<String>, ttl: Duration) -> PeerList {
+        let expires = (SystemTime::now() + ttl).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        return PeerList { object_id, expires, peers };
+    }
+
+    /// Formats the list, unsigned.
+    pub fn to_message(&self) -> String {
+        let mut message = format!("{} {}\n", to_hex(&self.object_id), self.expires);
+        for peer in self.peers.iter() {
+            message.push_str(peer);
+            message.push('\n');
+        }
+        return message;
+    }
+}
+
+/// Whether addr looks like "host:port", without whitespace a list could be smuggled through.
+fn is_address(addr: &str) -> bool {
+    let port = addr.rsplit_once(':').map(|x| x.1.parse::<u16>());
+    return !addr.is_empty() && addr.len() <= MAX_ADDRESS_LEN && !addr.chars().any(|x| x.is_whitespace()) && matches!(port, Some(Ok(_)));
+}
+
+/// Parses a peer list, checking its signature if key is given. Lists of more than MAX_PEX_PEERS addresses are
+/// Malformed, and expired ones are refused whether signed or not.
+pub fn read_peer_list(message: &str, key: Option<&PexKey>) -> Result<PeerList, PexError> {
+    let (body, signature) = match message.trim_end_matches('\n').rsplit_once('\n') {
+        Some((body, last)) if last.starts_with("signature ") => (format!("{}\n", body), Some(&last["signature ".len()..])),
+        _ => (message.to_string(), None),
+    };
+    if let Some(key) = key {
+        let signature = signature.ok_or(PexError::Unsigned)?;
+        let signature = parse_hex(signature).ok_or(PexError::Malformed)?;
+        if key.mac(&body).verify_slice(&signature).is_err() {
+            return Err(PexError::BadSignature);
+        }
+    }
+
+    let mut lines = body.lines();
+    let (object_id, expires) = match lines.next().and_then(|x| x.split_once(' ')) {
+        Some((object_id, expires)) => (object_id, expires),
+        None => return Err(PexError::Malformed),
+    };
+    let object_id = parse_object_id(object_id).ok_or(PexError::Malformed)?;
+    let expires: u64 = expires.parse().map_err(|_| PexError::Malformed)?;
+    let peers: Vec<String> = lines.map(|x| x.to_string()).collect();
+    if peers.len() > MAX_PEX_PEERS || !peers.iter().all(|x| is_address(x)) {
+        return Err(PexError::Malformed);
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if now >= expires {
+        return Err(PexError::Expired);
+    }
+    return Ok(PeerList { object_id, expires, peers });
+}
+
+/// Parses bytes formatted by to_hex.
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    return hex.as_bytes()
+        .chunks(2)
+        .map(|x| std::str::from_utf8(x).ok().and_then(|x| u8::from_str_radix(x, 16).ok()))
+        .collect();
+}
+
+/// Key peer lists are signed and checked with. Use a different key than for tokens and purges.
+#[derive(Clone)]
+pub struct PexKey {
+    key: Vec<u8>,
+}
+
+impl PexKey {
+    /// Creates a PexKey from secret bytes, e.g. the contents of a key file. Use at least 32 random bytes.
+    pub fn new(key: &[u8]) -> PexKey {
+        return PexKey { key: key.to_vec() };
+    }
+
+    fn mac(&self, body: &str) -> HmacSha256 {
+        // HMAC takes keys of any length
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(body.as_bytes());
+        return mac;
+    }
+
+    /// Signs a list, returning it in the form exchanged.
+    pub fn sign(&self, list: &PeerList) -> String {
+        let body = list.to_message();
+        let signature = self.mac(&body).finalize().into_bytes();
+        return format!("{}signature {}\n", body, to_hex(&signature));
+    }
+}
+
+/// Peers a server knows of, per object, and the signed lists it was given to relay.
+#[derive(Default)]
+struct KnownPeers {
+    /// Addresses and when they were last announced, newest last.
+    announced: Vec<(String, Instant)>,
+    signed: Option<String>,
+}
+
+/// What a server tells clients about other peers of its objects, see HttpServer::exchange_peers. Clients announce
+/// themselves when asking, and are passed on to the next clients until they go unannounced for the ttl. A signed
+/// list set for an object is answered instead while it lasts. Fixed peers, e.g. the other nodes of a cluster, are
+/// told of for every object, after the announced ones.
+pub struct PeerExchange {
+    ttl: Duration,
+    objects: Mutex<HashMap<ObjectId, KnownPeers>>,
+    fixed: Mutex<Vec<String>>,
+}
+
+impl PeerExchange {
+    /// Creates an exchange forgetting peers that were not announced for ttl.
+    pub fn new(ttl: Duration) -> PeerExchange {
+        return PeerExchange { ttl, objects: Mutex::new(HashMap::new()), fixed: Mutex::new(Vec::new()) };
+    }
+
+    /// Sets the peers told of for every object, replacing those set before, e.g. when a server reloads its config.
+    /// Only socket addresses are taken, as by announce, and at most MAX_PEX_PEERS. Returns the addresses left out.
+    pub fn set_fixed_peers(&self, peers: &[String]) -> Vec<String> {
+        let (mut taken, refused): (Vec<String>, Vec<String>) = peers.iter().cloned().partition(|x| x.parse::<SocketAddr>().is_ok());
+        let mut left_out = taken.split_off(std::cmp::min(taken.len(), MAX_PEX_PEERS));
+        left_out.extend(refused);
+        *self.fixed.lock().unwrap() = taken;
+        return left_out;
+    }
+
+    pub fn get_fixed_peers(&self) -> Vec<String> {
+        return self.fixed.lock().unwrap().clone();
+    }
+
+    /// Adds or refreshes a peer of an object, e.g. one serving a copy. Only socket addresses are taken, so gossip
+    /// can't make clients resolve names of someone else's choosing. Returns whether addr was taken.
+    pub fn announce(&self, object_id: &ObjectId, addr: &str) -> bool {
+        if addr.parse::<SocketAddr>().is_err() {
+            return false;
+        }
+        let mut objects = self.objects.lock().unwrap();
+        let known = objects.entry(*object_id).or_default();
+        known.announced.retain(|x| x.0 != addr);
+        known.announced.push((addr.to_string(), Instant::now()));
+        if known.announced.len() > MAX_PEX_PEERS {
+            known.announced.remove(0);
+        }
+        return true;
+    }
+
+    /// Sets a list signed by a tracker to relay for its object, replacing any before. The signature is left to the
+    /// clients to check. Fails with Malformed or Expired as read_peer_list does.
+    pub fn set_signed(&self, message: &str) -> Result<ObjectId, PexError> {
+        let list = read_peer_list(message, None)?;
+        self.objects.lock().unwrap().entry(list.object_id).or_default().signed = Some(message.to_string());
+        return Ok(list.object_id);
+    }
+
+    /// Gets the list to answer for an object, leaving out exclude, e.g. the address of the client asking. None if
+    /// no peer of it is known.
+    pub fn get_message(&self, object_id: &ObjectId, exclude: Option<&str>) -> Option<String> {
+        let ttl = self.ttl;
+        let mut peers: Vec<String> = Vec::new();
+        if let Some(known) = self.objects.lock().unwrap().get_mut(object_id) {
+            if known.signed.as_ref().is_some_and(|x| read_peer_list(x, None) == Err(PexError::Expired)) {
+                known.signed = None;
+            }
+            if let Some(signed) = known.signed.as_ref() {
+                return Some(signed.clone());
+            }
+            known.announced.retain(|x| x.1.elapsed() < ttl);
+            peers = known.announced.iter().rev().map(|x| x.0.clone()).collect();
+        }
+
+        for addr in self.fixed.lock().unwrap().iter() {
+            if !peers.contains(addr) {
+                peers.push(addr.clone());
+            }
+        }
+        peers.retain(|x| Some(&x[..]) != exclude);
+        peers.truncate(MAX_PEX_PEERS);
+        if peers.is_empty() {
+            return None;
+        }
+        return Some(PeerList::new(*object_id, peers, ttl).to_message());
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::client::fetch::{Fetch, PexSettings};
+    use crate::client::http::fetch_manifest;
+    use crate::codec::encoder::EncoderConfig;
+    use crate::codec::manifest::DecoderLimits;
+    use crate::server::catalog::Catalog;
+    use crate::server::http::HttpServer;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_peer_exchange() {
+        let key = PexKey::new(b"0123456789abcdef0123456789abcdef");
+        let list = PeerList::new([7; 32], vec!["10.0.0.1:8080".to_string(), "cache.example:80".to_string()], Duration::from_secs(60));
+        let signed = key.sign(&list);
+        assert_eq!(read_peer_list(&signed, Some(&key)), Ok(list.clone()));
+        assert_eq!(read_peer_list(&signed, None), Ok(list.clone()));
+        assert_eq!(read_peer_list(&list.to_message(), Some(&key)), Err(PexError::Unsigned));
+        assert_eq!(read_peer_list(&signed, Some(&PexKey::new(b"another key"))), Err(PexError::BadSignature));
+        let forged = signed.replacen("10.0.0.1:8080", "10.6.6.6:8080", 1);
+        assert_eq!(read_peer_list(&forged, Some(&key)), Err(PexError::BadSignature));
+        let expired = PeerList { expires: 1, ..list.clone() };
+        assert_eq!(read_peer_list(&key.sign(&expired), Some(&key)), Err(PexError::Expired));
+        let too_many = PeerList { peers: vec!["10.0.0.1:1".to_string(); MAX_PEX_PEERS + 1], ..list.clone() };
+        assert_eq!(read_peer_list(&too_many.to_message(), None), Err(PexError::Malformed));
+        assert_eq!(read_peer_list(&format!("{}no port\n", list.to_message()), None), Err(PexError::Malformed));
+
+        // a server passes on the peers announced to it, newest first, and a signed list over them
+        let exchange = PeerExchange::new(Duration::from_secs(60));
+        assert!(exchange.announce(&[7; 32], "10.0.0.1:8080"));
+        assert!(exchange.announce(&[7; 32], "[::1]:8080"));
+        assert!(!exchange.announce(&[7; 32], "cache.example:80"));
+        let answered = read_peer_list(&exchange.get_message(&[7; 32], Some("10.0.0.1:8080")).unwrap(), None).unwrap();
+        assert_eq!(answered.peers, vec!["[::1]:8080".to_string()]);
+        assert_eq!(exchange.get_message(&[8; 32], None), None);
+        assert_eq!(exchange.set_signed(&signed), Ok([7; 32]));
+        assert_eq!(exchange.get_message(&[7; 32], None), Some(signed));
+
+        // fixed peers are told of for every object, and replaced as a whole
+        assert_eq!(exchange.set_fixed_peers(&["10.0.0.2:80".to_string(), "nope".to_string()]), vec!["nope".to_string()]);
+        assert_eq!(read_peer_list(&exchange.get_message(&[8; 32], None).unwrap(), None).unwrap().peers, vec!["10.0.0.2:80".to_string()]);
+        assert_eq!(exchange.get_message(&[8; 32], Some("10.0.0.2:80")), None);
+        assert!(exchange.set_fixed_peers(&[]).is_empty());
+        assert_eq!(exchange.get_message(&[8; 32], None), None);
+
+        // a fetch given one peer finds the one it announced itself to and another that announced itself earlier
+        let root = std::env::temp_dir().join(format!("raptorcdn-pex-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data = gen_data(200 * 1000);
+        std::fs::write(root.join("object"), &data).unwrap();
+        let mut addrs: Vec<String> = Vec::new();
+        let mut object_id: ObjectId = [0; 32];
+        let exchange = Arc::new(PeerExchange::new(Duration::from_secs(60)));
+        for _ in 0..3 {
+            let catalog = Arc::new(Catalog::new(&root, EncoderConfig::new(1280)));
+            catalog.refresh().unwrap();
+            object_id = catalog.list()[0].manifest.object_id;
+            let mut server = HttpServer::bind("127.0.0.1:0", catalog).unwrap();
+            server.exchange_peers(exchange.clone());
+            addrs.push(server.local_addr().unwrap().to_string());
+            std::thread::spawn(move || server.run());
+        }
+        let manifest = fetch_manifest(&addrs[0][..], "", &object_id, None).unwrap();
+        exchange.announce(&object_id, &addrs[1]);
+        let mut fetch = Fetch::new(manifest, vec![addrs[0].clone()], &DecoderLimits::default()).unwrap();
+        fetch.set_symbols_per_request(16);
+        fetch.set_peer_exchange(PexSettings { announce: Some(addrs[2].clone()), ..PexSettings::default() });
+        assert_eq!(fetch.run().unwrap(), &data[..]);
+        let peers: Vec<&str> = fetch.get_peers().iter().map(|x| &x.addr[..]).collect();
+        assert_eq!(peers, vec![&addrs[0][..], &addrs[1][..]]);
+        assert!(fetch.get_peers()[1].requests > 0);
+
+        // one holding the key takes no unsigned lists
+        let manifest = fetch_manifest(&addrs[0][..], "", &object_id, None).unwrap();
+        let mut fetch = Fetch::new(manifest, vec![addrs[0].clone()], &DecoderLimits::default()).unwrap();
+        fetch.set_peer_exchange(PexSettings { key: Some(key), ..PexSettings::default() });
+        fetch.run().unwrap();
+        assert_eq!(fetch.get_peers().len(), 1);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
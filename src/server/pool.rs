@@ -0,0 +1,211 @@
+//! Repair symbols generated ahead of requests, for objects so hot that generating symbols on request shows in their
+//! latency. A SymbolPool keeps symbols of each block ready, taken from cursors of its own at random repair symbol
+//! ids, and hands each to whichever request asks first, so sessions still don't get a symbol twice. A background
+//! task refills the pools of hot objects as requests drain them, and drops those of objects gone cold. Memory is the
+//! price: symbols_per_block symbols of each block of each hot object.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::codec::encoder::{EncodedBlock, RaptorQEncoder};
+
+/// How long requests are counted over to tell whether an object is hot.
+const HOT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Which objects get pools, and how big.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolSettings {
+    /// Symbols kept ready of each block.
+    pub symbols_per_block: usize,
+    /// Symbol requests per second that make an object hot. Pools of objects getting fewer are dropped.
+    pub hot_requests_per_sec: u64,
+    /// How often pools are refilled.
+    pub refill_interval: Duration,
+}
+
+impl Default for PoolSettings {
+    fn default() -> PoolSettings {
+        return PoolSettings { symbols_per_block: 256, hot_requests_per_sec: 10, refill_interval: Duration::from_millis(100) };
+    }
+}
+
+/// How well pools served requests, and what they hold.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Requests served from a pool.
+    pub hits: u64,
+    /// Requests that had to generate their symbols, the object being cold or its pool short.
+    pub misses: u64,
+    /// Symbols generated into pools.
+    pub generated: u64,
+    /// Symbols held now, and their bytes.
+    pub pooled: u64,
+    pub pooled_bytes: u64,
+}
+
+impl PoolStats {
+    /// Gets the fraction of requests served from a pool, 0 if there were none.
+    pub fn get_hit_rate(&self) -> f64 {
+        if self.hits + self.misses == 0 {
+            return 0.0;
+        }
+        return self.hits as f64 / (self.hits + self.misses) as f64;
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// Symbols ready, per block. Empty while the object is cold.
+    blocks: Vec<VecDeque<EncodedBlock>>,
+    /// Next repair symbol id to generate, per block.
+    cursors: Vec<u32>,
+    /// Requests counted since, to tell whether the object is hot.
+    requests: u64,
+    counted_since: Option<Instant>,
+    hot: bool,
+    stats: PoolStats,
+}
+
+/// Symbols of one object generated ahead of requests, see the module documentation.
+#[derive(Default)]
+pub struct SymbolPool {
+    state: Mutex<PoolState>,
+}
+
+impl SymbolPool {
+    pub fn new() -> SymbolPool {
+        return SymbolPool::default();
+    }
+
+    /// Takes counts[block_id] symbols of each block, block after block, if the pool holds them all. Takes nothing
+    /// otherwise, for the request to generate its own. Each call counts towards the object being hot.
+    pub fn take(&self, counts: &[usize]) -> Option<Vec<EncodedBlock>> {
+        let mut state = self.state.lock().unwrap();
+        state.requests += 1;
+        let enough = state.blocks.len() == counts.len() && state.blocks.iter().zip(counts.iter()).all(|(x, count)| x.len() >= *count);
+        if !enough {
+            state.stats.misses += 1;
+            return None;
+        }
+
+        let mut taken: Vec<EncodedBlock> = Vec::with_capacity(counts.iter().sum());
+        for (pool, count) in state.blocks.iter_mut().zip(counts.iter()) {
+            taken.extend(pool.drain(..*count));
+        }
+        let bytes: usize = taken.iter().map(|x| x.data.data().len()).sum();
+        state.stats.hits += 1;
+        state.stats.pooled -= taken.len() as u64;
+        state.stats.pooled_bytes -= bytes as u64;
+        return Some(taken);
+    }
+
+    /// Tops the pool up to settings.symbols_per_block symbols of each block while the object is hot, and empties it
+    /// otherwise. Whether it is hot is decided about once a second, from the requests since. Symbols are generated
+    /// without holding the pool, so requests are served meanwhile. Returns the number of symbols generated.
+    pub fn refill(&self, encoder: &RaptorQEncoder, settings: &PoolSettings, now: Instant) -> usize {
+        let block_encoders = encoder.get_block_encoders();
+        let mut state = self.state.lock().unwrap();
+        let counted_since = *state.counted_since.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(counted_since);
+        if elapsed >= HOT_WINDOW {
+            state.hot = std::mem::take(&mut state.requests) as f64 / elapsed.as_secs_f64() >= settings.hot_requests_per_sec as f64;
+            state.counted_since = Some(now);
+        }
+        if !state.hot {
+            state.blocks.clear();
+            state.stats.pooled = 0;
+            state.stats.pooled_bytes = 0;
+            return 0;
+        }
+        if state.blocks.len() != block_encoders.len() {
+            state.blocks = vec![VecDeque::new(); block_encoders.len()];
+            state.cursors = block_encoders.iter().map(|x| x.gen_repair_index() as u32).collect();
+        }
+
+        // reserve the ids to generate, so a refill running meanwhile generates others
+        let mut ranges: Vec<(usize, u32, usize)> = Vec::new();
+        for (block_id, block_encoder) in block_encoders.iter().enumerate() {
+            let count = settings.symbols_per_block.saturating_sub(state.blocks[block_id].len());
+            if count == 0 {
+                continue;
+            }
+            let cursor = state.cursors[block_id];
+            ranges.push((block_id, cursor, count));
+            state.cursors[block_id] = ((cursor as usize + count) % block_encoder.get_repair_symbol_id_limit()) as u32;
+        }
+        drop(state);
+
+        let generated: Vec<(usize, Vec<EncodedBlock>)> = ranges.into_iter()
+            .map(|(block_id, start, count)| (block_id, block_encoders[block_id].generate_repair_blocks(start, count)))
+            .collect();
+        let count: usize = generated.iter().map(|x| x.1.len()).sum();
+        let bytes: usize = generated.iter().flat_map(|x| x.1.iter()).map(|x| x.data.data().len()).sum();
+
+        let mut state = self.state.lock().unwrap();
+        // the object may have gone cold meanwhile
+        if state.blocks.len() != block_encoders.len() {
+            return 0;
+        }
+        for (block_id, symbols) in generated {
+            state.blocks[block_id].extend(symbols);
+        }
+        state.stats.generated += count as u64;
+        state.stats.pooled += count as u64;
+        state.stats.pooled_bytes += bytes as u64;
+        return count;
+    }
+
+    pub fn get_stats(&self) -> PoolStats {
+        return self.state.lock().unwrap().stats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_symbol_pool() {
+        let data: Vec<u8> = (0..(100 * 1000)).map(|x| x as u8).collect();
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let blocks = encoder.get_block_encoders().len();
+        let settings = PoolSettings { symbols_per_block: 20, hot_requests_per_sec: 2, refill_interval: Duration::from_millis(10) };
+        let pool = SymbolPool::new();
+        let start = Instant::now();
+        let secs = |x: u64| start + Duration::from_secs(x);
+
+        // cold objects have no pool, two requests in a second make it hot
+        assert_eq!(pool.take(&vec![1; blocks]), None);
+        assert_eq!(pool.refill(&encoder, &settings, secs(0)), 0);
+        assert_eq!(pool.refill(&encoder, &settings, secs(1)), 0);
+        assert_eq!(pool.take(&vec![1; blocks]), None);
+        assert_eq!(pool.take(&vec![1; blocks]), None);
+        assert_eq!(pool.refill(&encoder, &settings, secs(2)), 20 * blocks);
+        assert_eq!(pool.get_stats().pooled_bytes, 20 * 1280 * blocks as u64);
+
+        // every symbol goes to one request, a request the pool can't serve whole takes nothing
+        let mut esis: HashSet<(u32, u32)> = HashSet::new();
+        for _ in 0..4 {
+            let taken = pool.take(&vec![5; blocks]).unwrap();
+            assert_eq!(taken.len(), 5 * blocks);
+            assert!(taken.iter().all(|x| esis.insert((x.block_id, x.data.payload_id().encoding_symbol_id()))));
+        }
+        assert_eq!(pool.take(&vec![1; blocks]), None);
+        assert_eq!(pool.refill(&encoder, &settings, secs(3)), 20 * blocks);
+        let taken = pool.take(&vec![20; blocks]).unwrap();
+        assert!(taken.iter().all(|x| esis.insert((x.block_id, x.data.payload_id().encoding_symbol_id()))));
+
+        let stats = pool.get_stats();
+        assert_eq!((stats.hits, stats.misses, stats.generated, stats.pooled), (5, 4, 40 * blocks as u64, 0));
+        assert!((stats.get_hit_rate() - 5.0 / 9.0).abs() < 1e-9);
+
+        // a quiet second makes it cold again
+        assert_eq!(pool.refill(&encoder, &settings, secs(5)), 0);
+        assert_eq!(pool.take(&vec![0; blocks]), None);
+    }
+}
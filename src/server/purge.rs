@@ -0,0 +1,127 @@
+//! Signed purge notices, telling servers and caches to delete an object and stop serving it, e.g. for a takedown or
+//! a recall of bad content. A notice is "<object id>.<issued>.<reason>.<signature>", the issue time in seconds since
+//! the unix epoch, the reason in hex, and the signature a hex HMAC-SHA256 of the rest under a key shared by whoever
+//! issues notices and everything that acts on them. Notices only ever remove content, so they don't expire; a relay
+//! forwards them as they are to the servers behind it.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::codec::manifest::{parse_object_id, to_hex, ObjectId};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PurgeError {
+    Malformed,
+    BadSignature,
+}
+
+/// What to purge and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PurgeNotice {
+    pub object_id: ObjectId,
+    /// Seconds since the unix epoch.
+    pub issued: u64,
+    /// Free text for logs, e.g. a ticket number.
+    pub reason: String,
+}
+
+impl PurgeNotice {
+    /// Creates a notice issued now.
+    pub fn new(object_id: ObjectId, reason: &str) -> PurgeNotice {
+        let issued = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        return PurgeNotice { object_id, issued, reason: reason.to_string() };
+    }
+
+    /// Formats the signed part of the notice.
+    fn body(&self) -> String {
+        return format!("{}.{}.{}", to_hex(&self.object_id), self.issued, to_hex(self.reason.as_bytes()));
+    }
+}
+
+/// Key purge notices are signed and checked with. Use a different key than for tokens, so holding one doesn't allow
+/// the other.
+#[derive(Clone)]
+pub struct PurgeKey {
+    key: Vec<u8>,
+}
+
+impl PurgeKey {
+    /// Creates a PurgeKey from secret bytes, e.g. the contents of a key file. Use at least 32 random bytes.
+    pub fn new(key: &[u8]) -> PurgeKey {
+        return PurgeKey { key: key.to_vec() };
+    }
+
+    fn mac(&self, body: &str) -> HmacSha256 {
+        // HMAC takes keys of any length
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(body.as_bytes());
+        return mac;
+    }
+
+    /// Signs a notice, returning it in the form servers accept.
+    pub fn sign(&self, notice: &PurgeNotice) -> String {
+        let body = notice.body();
+        let signature = self.mac(&body).finalize().into_bytes();
+        return format!("{}.{}", body, to_hex(&signature));
+    }
+
+    /// Checks that a notice was signed with this key, returning what it says.
+    pub fn verify(&self, signed: &str) -> Result<PurgeNotice, PurgeError> {
+        let (body, signature) = match signed.rsplit_once('.') {
+            Some(split) => split,
+            None => return Err(PurgeError::Malformed),
+        };
+        let signature = parse_hex(signature).ok_or(PurgeError::Malformed)?;
+        if self.mac(body).verify_slice(&signature).is_err() {
+            return Err(PurgeError::BadSignature);
+        }
+
+        // signed by us, so anything malformed past this point is a bug on the issuing side
+        let parts: Vec<&str> = body.split('.').collect();
+        let (object_id, issued, reason) = match parts[..] {
+            [object_id, issued, reason] => (object_id, issued, reason),
+            _ => return Err(PurgeError::Malformed),
+        };
+        let object_id = parse_object_id(object_id).ok_or(PurgeError::Malformed)?;
+        let issued = issued.parse().map_err(|_| PurgeError::Malformed)?;
+        let reason = parse_hex(reason).and_then(|x| String::from_utf8(x).ok()).ok_or(PurgeError::Malformed)?;
+        return Ok(PurgeNotice { object_id, issued, reason });
+    }
+}
+
+/// Parses bytes formatted by to_hex.
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    return hex.as_bytes()
+        .chunks(2)
+        .map(|x| std::str::from_utf8(x).ok().and_then(|x| u8::from_str_radix(x, 16).ok()))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_purge_verify() {
+        let key = PurgeKey::new(b"0123456789abcdef0123456789abcdef");
+        let notice = PurgeNotice::new([7; 32], "recall #12: corrupt build");
+
+        let signed = key.sign(&notice);
+        assert_eq!(key.verify(&signed), Ok(notice.clone()));
+        assert_eq!(PurgeKey::new(b"another key").verify(&signed), Err(PurgeError::BadSignature));
+
+        // pointing the notice at another object invalidates the signature
+        let forged = signed.replacen(&to_hex(&[7; 32]), &to_hex(&[8; 32]), 1);
+        assert_eq!(key.verify(&forged), Err(PurgeError::BadSignature));
+
+        assert_eq!(key.verify("garbage"), Err(PurgeError::Malformed));
+        assert_eq!(key.verify("a.b.c.zz"), Err(PurgeError::Malformed));
+    }
+}
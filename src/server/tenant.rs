@@ -0,0 +1,187 @@
+//! Serving several customers from one node. Each tenant has its own catalog, so objects are looked up, limited and
+//! rate limited per tenant even when two tenants serve the same content.
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::catalog::Catalog;
+
+struct Bucket {
+    /// None if unlimited.
+    bytes_per_sec: Option<u64>,
+    /// Bytes available and when they were last topped up.
+    available: f64,
+    last: Instant,
+}
+
+/// Token bucket limiting a rate in bytes per second, with bursts of up to a second's worth. The rate can be changed
+/// while the limiter is in use.
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> RateLimiter {
+        return RateLimiter {
+            bucket: Mutex::new(Bucket {
+                bytes_per_sec: Some(bytes_per_sec),
+                available: bytes_per_sec as f64,
+                last: Instant::now(),
+            }),
+        };
+    }
+
+    /// Creates a RateLimiter that lets everything through until a rate is set.
+    pub fn unlimited() -> RateLimiter {
+        return RateLimiter {
+            bucket: Mutex::new(Bucket { bytes_per_sec: None, available: 0.0, last: Instant::now() }),
+        };
+    }
+
+    /// Changes the rate, None for unlimited. Bytes already available are kept, up to a second's worth of the new rate.
+    pub fn set_rate(&self, bytes_per_sec: Option<u64>) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.available = match (bucket.bytes_per_sec, bytes_per_sec) {
+            (_, None) => 0.0,
+            // start a newly limited bucket full, as a new limiter would
+            (None, Some(rate)) => rate as f64,
+            (Some(_), Some(rate)) => f64::min(bucket.available, rate as f64),
+        };
+        bucket.bytes_per_sec = bytes_per_sec;
+    }
+
+    pub fn get_rate(&self) -> Option<u64> {
+        return self.bucket.lock().unwrap().bytes_per_sec;
+    }
+
+    /// Takes bytes from the bucket if there are enough, returning whether they were taken.
+    pub fn try_take(&self, bytes: u64) -> bool {
+        return self.try_take_at(bytes, Instant::now());
+    }
+
+    fn try_take_at(&self, bytes: u64, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+        if !bucket.refill(now) {
+            return true;
+        }
+
+        if bucket.available < bytes as f64 {
+            return false;
+        }
+        bucket.available -= bytes as f64;
+        return true;
+    }
+
+    /// Takes as many units of unit_size bytes as there are bytes available, up to max_units, returning how many were
+    /// taken. A unit larger than a second's worth is taken alone once the bucket is full, leaving the bucket in debt,
+    /// so rates below the unit size slow takers down rather than refusing them for good.
+    pub fn take_units(&self, max_units: u64, unit_size: u64) -> u64 {
+        return self.take_units_at(max_units, unit_size, Instant::now());
+    }
+
+    fn take_units_at(&self, max_units: u64, unit_size: u64, now: Instant) -> u64 {
+        let mut bucket = self.bucket.lock().unwrap();
+        if !bucket.refill(now) || unit_size == 0 {
+            return max_units;
+        }
+
+        let mut units = u64::min(max_units, (bucket.available / unit_size as f64) as u64);
+        if units == 0 && max_units > 0 && bucket.bytes_per_sec.is_some_and(|x| x < unit_size && bucket.available >= x as f64) {
+            units = 1;
+        }
+        bucket.available -= (units * unit_size) as f64;
+        return units;
+    }
+}
+
+impl Bucket {
+    /// Tops up the bytes available as of now, returning false if unlimited.
+    fn refill(&mut self, now: Instant) -> bool {
+        let bytes_per_sec = match self.bytes_per_sec {
+            None => return false,
+            Some(bytes_per_sec) => bytes_per_sec as f64,
+        };
+        let refill = now.saturating_duration_since(self.last).as_secs_f64() * bytes_per_sec;
+        self.available = f64::min(self.available + refill, bytes_per_sec);
+        self.last = now;
+        return true;
+    }
+}
+
+/// A customer served by an HttpServer under /tenants/<name>/.
+pub struct Tenant {
+    name: String,
+    catalog: Arc<Catalog>,
+    /// Limit on the symbol bytes served.
+    bandwidth: RateLimiter,
+}
+
+impl Tenant {
+    /// Creates a tenant serving catalog. Storage and object count quotas are the catalog's limits.
+    pub fn new(name: &str, catalog: Arc<Catalog>) -> Tenant {
+        return Tenant {
+            name: name.to_string(),
+            catalog,
+            bandwidth: RateLimiter::unlimited(),
+        };
+    }
+
+    /// Limits the symbols served to the tenant's clients to bytes_per_sec across all of its objects, None for no
+    /// limit. Can be changed while the tenant is being served.
+    pub fn set_bandwidth_limit(&self, bytes_per_sec: Option<u64>) {
+        self.bandwidth.set_rate(bytes_per_sec);
+    }
+
+    pub fn get_name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub fn get_catalog(&self) -> &Arc<Catalog> {
+        return &self.catalog;
+    }
+
+    pub fn get_bandwidth_limit(&self) -> &RateLimiter {
+        return &self.bandwidth;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        assert!(limiter.try_take_at(600, start));
+        assert!(!limiter.try_take_at(600, start));
+        assert!(limiter.try_take_at(600, start + Duration::from_millis(200)));
+
+        // bursts are capped at a second's worth
+        assert!(!limiter.try_take_at(1001, start + Duration::from_secs(10)));
+        assert!(limiter.try_take_at(1000, start + Duration::from_secs(10)));
+
+        limiter.set_rate(Some(100));
+        assert!(!limiter.try_take_at(101, start + Duration::from_secs(20)));
+        limiter.set_rate(None);
+        assert!(limiter.try_take_at(u64::MAX, start + Duration::from_secs(20)));
+        assert!(RateLimiter::unlimited().try_take(u64::MAX));
+    }
+
+    #[test]
+    fn test_rate_limiter_units() {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        assert_eq!(limiter.take_units_at(64, 300, start), 3);
+        assert_eq!(limiter.take_units_at(64, 300, start), 0);
+        assert_eq!(limiter.take_units_at(64, 300, start + Duration::from_millis(600)), 2);
+
+        // units larger than a second's worth go out one at a time, as the bucket fills back up
+        limiter.set_rate(Some(100));
+        assert_eq!(limiter.take_units_at(64, 1280, start + Duration::from_secs(10)), 1);
+        assert_eq!(limiter.take_units_at(64, 1280, start + Duration::from_secs(11)), 0);
+        assert_eq!(limiter.take_units_at(64, 1280, start + Duration::from_secs(23)), 1);
+        assert_eq!(RateLimiter::unlimited().take_units(64, 1280), 64);
+    }
+}
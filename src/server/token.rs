@@ -0,0 +1,130 @@
+//! Signed, expiring access tokens for objects, so a server can hand out symbols only to authorized clients without a
+//! proxy in front of it. A token is "<expiry>.<signature>", the expiry in seconds since the unix epoch and the
+//! signature a hex HMAC-SHA256 of the object id and expiry under a key shared by whoever issues and checks tokens.
+//! Tokens for an object of a tenant also sign the tenant's name, length prefixed ahead of the object id, so a token
+//! for one tenant's object is no good for another tenant serving the same content.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::codec::manifest::{to_hex, ObjectId};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    Malformed,
+    Expired,
+    BadSignature,
+}
+
+/// Key tokens are signed and checked with.
+#[derive(Clone)]
+pub struct TokenKey {
+    key: Vec<u8>,
+}
+
+impl TokenKey {
+    /// Creates a TokenKey from secret bytes, e.g. the contents of a key file. Use at least 32 random bytes.
+    pub fn new(key: &[u8]) -> TokenKey {
+        return TokenKey { key: key.to_vec() };
+    }
+
+    fn mac(&self, tenant: Option<&str>, object_id: &ObjectId, expires: u64) -> HmacSha256 {
+        // HMAC takes keys of any length
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        if let Some(tenant) = tenant {
+            mac.update(&(tenant.len() as u64).to_le_bytes());
+            mac.update(tenant.as_bytes());
+        }
+        mac.update(object_id);
+        mac.update(&expires.to_le_bytes());
+        return mac;
+    }
+
+    /// Creates a token for an object of tenant, None for an object served outside of tenants, that is valid for ttl
+    /// from now.
+    pub fn sign(&self, tenant: Option<&str>, object_id: &ObjectId, ttl: Duration) -> String {
+        let expires = (SystemTime::now() + ttl).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        return self.sign_until(tenant, object_id, expires);
+    }
+
+    /// Creates a token for an object of tenant that is valid until expires, in seconds since the unix epoch.
+    pub fn sign_until(&self, tenant: Option<&str>, object_id: &ObjectId, expires: u64) -> String {
+        let signature = self.mac(tenant, object_id, expires).finalize().into_bytes();
+        return format!("{}.{}", expires, to_hex(&signature));
+    }
+
+    /// Checks that a token was signed with this key for the object of tenant and has not expired.
+    pub fn verify(&self, tenant: Option<&str>, object_id: &ObjectId, token: &str) -> Result<(), TokenError> {
+        let (expires, signature) = match token.split_once('.') {
+            Some((expires, signature)) if signature.len() % 2 == 0 => (expires, signature),
+            _ => return Err(TokenError::Malformed),
+        };
+        let expires: u64 = match expires.parse() {
+            Ok(expires) => expires,
+            Err(_) => return Err(TokenError::Malformed),
+        };
+        let signature: Option<Vec<u8>> = signature.as_bytes()
+            .chunks(2)
+            .map(|x| std::str::from_utf8(x).ok().and_then(|x| u8::from_str_radix(x, 16).ok()))
+            .collect();
+        let signature = match signature {
+            Some(signature) => signature,
+            None => return Err(TokenError::Malformed),
+        };
+
+        // check the signature first so a forged token can't learn anything from the expiry check
+        if self.mac(tenant, object_id, expires).verify_slice(&signature).is_err() {
+            return Err(TokenError::BadSignature);
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if now >= expires {
+            return Err(TokenError::Expired);
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_verify() {
+        let key = TokenKey::new(b"0123456789abcdef0123456789abcdef");
+        let object_id: ObjectId = [7; 32];
+
+        let token = key.sign(None, &object_id, Duration::from_secs(60));
+        assert_eq!(key.verify(None, &object_id, &token), Ok(()));
+        assert_eq!(key.verify(None, &[8; 32], &token), Err(TokenError::BadSignature));
+        assert_eq!(TokenKey::new(b"another key").verify(None, &object_id, &token), Err(TokenError::BadSignature));
+
+        let expired = key.sign_until(None, &object_id, 1);
+        assert_eq!(key.verify(None, &object_id, &expired), Err(TokenError::Expired));
+
+        // moving the expiry invalidates the signature
+        let (_, signature) = token.split_once('.').unwrap();
+        assert_eq!(key.verify(None, &object_id, &format!("{}.{}", u64::MAX, signature)), Err(TokenError::BadSignature));
+
+        assert_eq!(key.verify(None, &object_id, "garbage"), Err(TokenError::Malformed));
+        assert_eq!(key.verify(None, &object_id, "1.zz"), Err(TokenError::Malformed));
+    }
+
+    #[test]
+    fn test_token_tenants() {
+        let key = TokenKey::new(b"0123456789abcdef0123456789abcdef");
+        let object_id: ObjectId = [7; 32];
+
+        // a token is only good for the tenant it was signed for, and tokens outside of tenants for none
+        let token = key.sign(Some("a"), &object_id, Duration::from_secs(60));
+        assert_eq!(key.verify(Some("a"), &object_id, &token), Ok(()));
+        assert_eq!(key.verify(Some("b"), &object_id, &token), Err(TokenError::BadSignature));
+        assert_eq!(key.verify(None, &object_id, &token), Err(TokenError::BadSignature));
+        let token = key.sign(None, &object_id, Duration::from_secs(60));
+        assert_eq!(key.verify(Some("a"), &object_id, &token), Err(TokenError::BadSignature));
+    }
+}
@@ -0,0 +1,68 @@
+use rand::{thread_rng, Rng};
+
+use crate::codec::encoder::EncodedBlock;
+
+/// Simulated channel dropping each packet independently with a fixed probability.
+pub struct LossyChannel {
+    /// Probability of dropping a packet, in 0.0..=1.0.
+    loss: f64,
+    /// Packets handed to the channel.
+    sent: u64,
+    /// Packets that made it through.
+    delivered: u64,
+}
+
+impl LossyChannel {
+    /// Creates a channel dropping packets with probability loss. Panics if loss is not in 0.0..=1.0.
+    pub fn new(loss: f64) -> LossyChannel {
+        assert!((0.0..=1.0).contains(&loss), "loss {} is not a probability", loss);
+        return LossyChannel {
+            loss,
+            sent: 0,
+            delivered: 0,
+        };
+    }
+
+    /// Sends packets through the channel, returning the ones that were not dropped.
+    pub fn transmit(&mut self, mut blocks: Vec<EncodedBlock>) -> Vec<EncodedBlock> {
+        let mut rng = thread_rng();
+        self.sent += blocks.len() as u64;
+        blocks.retain(|_| !rng.gen_bool(self.loss));
+        self.delivered += blocks.len() as u64;
+        return blocks;
+    }
+
+    /// Gets the number of packets handed to the channel.
+    pub fn get_sent(&self) -> u64 {
+        return self.sent;
+    }
+
+    /// Gets the number of packets that made it through.
+    pub fn get_delivered(&self) -> u64 {
+        return self.delivered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoder::*;
+
+    #[test]
+    fn test_lossy_channel() {
+        let data: Vec<u8> = vec![0; 100 * 1000];
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let blocks = encoder.generate_encoded_blocks();
+
+        let mut lossless = LossyChannel::new(0.0);
+        assert_eq!(lossless.transmit(blocks.clone()), blocks);
+
+        let mut dead = LossyChannel::new(1.0);
+        assert!(dead.transmit(blocks.clone()).is_empty());
+        assert_eq!(dead.get_sent(), blocks.len() as u64);
+        assert_eq!(dead.get_delivered(), 0);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::codec::encoder::EncodedBlock;
+
+/// Bytes a packet takes on the wire on top of its symbol: block id, payload id and symbol size, as in a shard record.
+const PACKET_HEADER_SIZE: u64 = 10;
+
+/// Identifies a peer of a SimNetwork.
+pub type PeerId = usize;
+
+/// Link characteristics of a simulated peer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerProfile {
+    /// Bytes per second the peer sends at.
+    pub bandwidth: u64,
+    /// One way delay added to every packet.
+    pub latency: Duration,
+    /// Probability of dropping a packet, in 0.0..=1.0.
+    pub loss: f64,
+}
+
+/// Packets sent and delivered by a simulated peer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    pub sent: u64,
+    pub delivered: u64,
+}
+
+struct SimPeer {
+    profile: PeerProfile,
+    /// Virtual time the peer's link finishes sending what it was given.
+    busy_until: Duration,
+    /// Packets on their way, in arrival order, which is send order as links are FIFO.
+    in_flight: VecDeque<(Duration, EncodedBlock)>,
+    stats: PeerStats,
+}
+
+/// Simulated peers sending to a single receiver, on a virtual clock. Time only moves when advance is called and
+/// losses come from a seeded generator, so a run is reproducible, e.g. to compare scheduling policies in tests.
+pub struct SimNetwork {
+    now: Duration,
+    rng: StdRng,
+    peers: Vec<SimPeer>,
+}
+
+impl SimNetwork {
+    /// Creates a network without peers at time zero, drawing losses from a generator seeded with seed.
+    pub fn new(seed: u64) -> SimNetwork {
+        return SimNetwork {
+            now: Duration::ZERO,
+            rng: StdRng::seed_from_u64(seed),
+            peers: Vec::new(),
+        };
+    }
+
+    /// Adds a peer. Panics if the bandwidth is zero or loss is not in 0.0..=1.0.
+    pub fn add_peer(&mut self, profile: PeerProfile) -> PeerId {
+        assert!(profile.bandwidth > 0, "bandwidth must be positive");
+        assert!((0.0..=1.0).contains(&profile.loss), "loss {} is not a probability", profile.loss);
+        self.peers.push(SimPeer {
+            profile,
+            busy_until: self.now,
+            in_flight: VecDeque::new(),
+            stats: PeerStats::default(),
+        });
+        return self.peers.len() - 1;
+    }
+
+    /// Queues packets on a peer's link. Each takes its size over the peer's bandwidth to send, after whatever the
+    /// link is still sending, and arrives latency later unless it is lost.
+    pub fn send(&mut self, peer_id: PeerId, blocks: Vec<EncodedBlock>) {
+        let peer = &mut self.peers[peer_id];
+        peer.busy_until = std::cmp::max(peer.busy_until, self.now);
+        peer.stats.sent += blocks.len() as u64;
+
+        for block in blocks {
+            let size = block.data.data().len() as u64 + PACKET_HEADER_SIZE;
+            peer.busy_until += Duration::from_nanos(size * 1_000_000_000 / peer.profile.bandwidth);
+            if !self.rng.gen_bool(peer.profile.loss) {
+                peer.in_flight.push_back((peer.busy_until + peer.profile.latency, block));
+            }
+        }
+    }
+
+    /// Advances the clock by duration, returning the packets that arrived meanwhile in arrival order.
+    pub fn advance(&mut self, duration: Duration) -> Vec<(PeerId, EncodedBlock)> {
+        self.now += duration;
+        let now = self.now;
+
+        let mut delivered: Vec<(Duration, PeerId, EncodedBlock)> = Vec::new();
+        for (peer_id, peer) in self.peers.iter_mut().enumerate() {
+            while peer.in_flight.front().is_some_and(|(arrival, _)| *arrival <= now) {
+                let (arrival, block) = peer.in_flight.pop_front().unwrap();
+                peer.stats.delivered += 1;
+                delivered.push((arrival, peer_id, block));
+            }
+        }
+
+        // stable, so simultaneous arrivals keep peer order
+        delivered.sort_by_key(|(arrival, _, _)| *arrival);
+        return delivered.into_iter().map(|(_, peer_id, block)| (peer_id, block)).collect();
+    }
+
+    /// Gets the current virtual time.
+    pub fn now(&self) -> Duration {
+        return self.now;
+    }
+
+    /// Returns true if a peer's link has nothing left to send, i.e. it is ready for more packets.
+    pub fn is_idle(&self, peer_id: PeerId) -> bool {
+        return self.peers[peer_id].busy_until <= self.now;
+    }
+
+    /// Gets the virtual time of the next packet arrival, if any packet is in flight.
+    pub fn get_next_arrival(&self) -> Option<Duration> {
+        return self.peers.iter().filter_map(|x| x.in_flight.front().map(|(arrival, _)| *arrival)).min();
+    }
+
+    pub fn get_peer_stats(&self, peer_id: PeerId) -> PeerStats {
+        return self.peers[peer_id].stats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decoder::RaptorQDecoder;
+    use crate::codec::encoder::*;
+
+    #[test]
+    fn test_sim_network_timing() {
+        let data: Vec<u8> = vec![0; 100 * 1000];
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let blocks = encoder.generate_encoded_blocks();
+
+        let mut network = SimNetwork::new(0);
+        let peer_id = network.add_peer(PeerProfile {
+            bandwidth: 1280 + PACKET_HEADER_SIZE,
+            latency: Duration::from_millis(100),
+            loss: 0.0,
+        });
+        network.send(peer_id, blocks[..2].to_vec());
+        assert!(!network.is_idle(peer_id));
+        assert_eq!(network.get_next_arrival(), Some(Duration::from_millis(1100)));
+
+        assert!(network.advance(Duration::from_millis(1099)).is_empty());
+        assert_eq!(network.advance(Duration::from_millis(1)), vec![(peer_id, blocks[0].clone())]);
+        // the second packet is still being sent
+        assert!(!network.is_idle(peer_id));
+        assert_eq!(network.advance(Duration::from_secs(1)), vec![(peer_id, blocks[1].clone())]);
+        assert!(network.is_idle(peer_id));
+        assert_eq!(network.get_peer_stats(peer_id), PeerStats { sent: 2, delivered: 2 });
+    }
+
+    /// Fetches a block from a fast lossy peer and a slow clean one, sending to whichever is idle, returning the
+    /// virtual time decoding finished at.
+    fn fetch_time(seed: u64, data: &[u8]) -> Duration {
+        let encoder = match BlockEncoder::new(0, 1280, data.to_vec()) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut decoder = match RaptorQDecoder::new(vec![encoder.get_block_info()]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        let mut network = SimNetwork::new(seed);
+        let peers: [PeerId; 2] = [
+            network.add_peer(PeerProfile { bandwidth: 1_000_000, latency: Duration::from_millis(20), loss: 0.2 }),
+            network.add_peer(PeerProfile { bandwidth: 100_000, latency: Duration::from_millis(80), loss: 0.0 }),
+        ];
+        // each peer sends its own range of repair symbols
+        let mut cursors: [u32; 2] = [0, 10000];
+
+        loop {
+            for (peer_id, cursor) in peers.iter().zip(cursors.iter_mut()) {
+                if network.is_idle(*peer_id) {
+                    network.send(*peer_id, encoder.generate_repair_blocks(*cursor, 8));
+                    *cursor += 8;
+                }
+            }
+            for (peer_id, block) in network.advance(Duration::from_millis(1)) {
+                if decoder.consume_from(peer_id as u64, vec![block]).unwrap() {
+                    assert_eq!(decoder.get_result().as_deref(), Some(data));
+                    return network.now();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sim_network_deterministic() {
+        let data: Vec<u8> = (0..(200 * 1000)).map(|x| x as u8).collect();
+        let time = fetch_time(7, &data);
+        assert_eq!(fetch_time(7, &data), time);
+        // the fast peer alone needs about a quarter of a second for 200KB at 20% loss
+        assert!(time < Duration::from_millis(400), "took {:?}", time);
+    }
+}
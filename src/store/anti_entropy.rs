@@ -0,0 +1,374 @@
+//! Keeping blocks replicated as disks fail. Each storage node keeps shards of the blocks a PlacementRing places on
+//! it; an AntiEntropy job goes over the blocks of every object expected on the node, and recreates the shard of each
+//! block that is missing or too short to decode from. The block is decoded from symbols fetched from the other nodes
+//! holding it, checked against its hash and encoded again, so the new shard has fresh repair symbols rather than
+//! whatever the other nodes happened to send.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "client")]
+use crate::client::http::fetch_symbols;
+use crate::codec::decoder::BlockDecoder;
+use crate::codec::encoder::{BlockEncoder, EncodedBlock};
+use crate::codec::manifest::{parse_object_id, to_hex, Manifest, ObjectId};
+use crate::codec::shard::{read_shard, write_shard};
+use super::placement::PlacementRing;
+
+/// Symbols asked for, or put in a new shard, beyond a block's source symbols, as decoding from exactly that many can
+/// fail.
+const REPAIR_SLACK_SYMBOLS: usize = 16;
+
+/// How soon a running job notices it was stopped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shards a storage node holds, one per block.
+pub trait ShardStore: Send + Sync {
+    /// Lists the blocks with a shard, as object id and block id.
+    fn list(&self) -> io::Result<Vec<(ObjectId, u32)>>;
+
+    /// Reads the symbols of a block's shard, failing with ErrorKind::NotFound if there is none.
+    fn get_shard(&self, object_id: &ObjectId, block_id: u32) -> io::Result<Vec<EncodedBlock>>;
+
+    /// Stores the shard of a block, replacing any shard it had.
+    fn put_shard(&self, object_id: &ObjectId, block_id: u32, symbols: &[EncodedBlock]) -> io::Result<()>;
+}
+
+/// Keeps shards as files in a directory, <object id>/<block id>.shard, each with the SHA-256 of its contents in
+/// <block id>.sha256 next to it. A shard is written next to its path and renamed into place, so a shard file is always
+/// complete; one that no longer matches its checksum, e.g. after a disk error, fails to read with
+/// ErrorKind::InvalidData.
+pub struct DirShardStore {
+    root: PathBuf,
+}
+
+impl DirShardStore {
+    /// Creates a DirShardStore keeping shards in root, creating it if needed.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<DirShardStore> {
+        fs::create_dir_all(root.as_ref())?;
+        return Ok(DirShardStore { root: root.as_ref().to_path_buf() });
+    }
+
+    pub fn get_path(&self, object_id: &ObjectId, block_id: u32) -> PathBuf {
+        return self.root.join(to_hex(object_id)).join(format!("{}.shard", block_id));
+    }
+
+    /// Gets the path of the checksum of a shard. Shards written before checksums were kept have none, and are read
+    /// unchecked.
+    pub fn get_checksum_path(&self, object_id: &ObjectId, block_id: u32) -> PathBuf {
+        return self.get_path(object_id, block_id).with_extension("sha256");
+    }
+}
+
+impl ShardStore for DirShardStore {
+    fn list(&self) -> io::Result<Vec<(ObjectId, u32)>> {
+        let mut shards: Vec<(ObjectId, u32)> = Vec::new();
+        for object_dir in fs::read_dir(&self.root)? {
+            let object_dir = object_dir?;
+            let object_id = match object_dir.file_name().to_str().and_then(parse_object_id) {
+                Some(object_id) => object_id,
+                None => continue,
+            };
+            for shard in fs::read_dir(object_dir.path())? {
+                let name = shard?.file_name();
+                if let Some(block_id) = name.to_str().and_then(|x| x.strip_suffix(".shard")).and_then(|x| x.parse().ok()) {
+                    shards.push((object_id, block_id));
+                }
+            }
+        }
+        shards.sort_unstable();
+        return Ok(shards);
+    }
+
+    fn get_shard(&self, object_id: &ObjectId, block_id: u32) -> io::Result<Vec<EncodedBlock>> {
+        let data = fs::read(self.get_path(object_id, block_id))?;
+        match fs::read_to_string(self.get_checksum_path(object_id, block_id)) {
+            Ok(checksum) if checksum.trim() != to_hex(&Sha256::digest(&data)) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bad shard: checksum mismatch"));
+            },
+            Ok(_) => {},
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+            Err(error) => return Err(error),
+        }
+        return read_shard(&data[..]);
+    }
+
+    fn put_shard(&self, object_id: &ObjectId, block_id: u32, symbols: &[EncodedBlock]) -> io::Result<()> {
+        let path = self.get_path(object_id, block_id);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut data: Vec<u8> = Vec::new();
+        write_shard(&mut data, symbols)?;
+
+        // the checksum goes in first, so a crash in between leaves a mismatch rather than an unchecked shard
+        let checksum_path = self.get_checksum_path(object_id, block_id);
+        let partial_path = checksum_path.with_extension("sha256.partial");
+        fs::write(&partial_path, format!("{}\n", to_hex(&Sha256::digest(&data))))?;
+        fs::rename(&partial_path, &checksum_path)?;
+        let partial_path = path.with_extension("partial");
+        fs::write(&partial_path, &data)?;
+        return fs::rename(&partial_path, &path);
+    }
+}
+
+/// Encodes a new shard of a block of manifest's object from its payload, with a few repair symbols beyond the
+/// block's symbol count so it decodes on its own.
+pub(crate) fn encode_shard(manifest: &Manifest, block_id: u32, data: Vec<u8>) -> io::Result<Vec<EncodedBlock>> {
+    let block_encoder = match BlockEncoder::with_config(block_id, manifest.config, data) {
+        Ok(block_encoder) => block_encoder,
+        Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("failed to create encoder: {:?}", error))),
+    };
+    let count = block_encoder.get_symbol_count() + REPAIR_SLACK_SYMBOLS;
+    return Ok(block_encoder.generate_repair_blocks(block_encoder.gen_repair_index() as u32, count));
+}
+
+/// Where a node gets symbols of blocks it lost from, e.g. the other nodes over HTTP, see HttpShardSource.
+pub trait ShardSource: Send + Sync {
+    /// Fetches up to count symbols of a block from node.
+    fn fetch_symbols(&self, node: &str, object_id: &ObjectId, block_id: u32, count: usize) -> io::Result<Vec<EncodedBlock>>;
+}
+
+/// Fetches symbols from nodes serving the objects over HTTP, see HttpServer, under prefix, e.g. "" or
+/// "/tenants/<name>", with a token if they require them.
+#[cfg(feature = "client")]
+pub struct HttpShardSource {
+    prefix: String,
+    token: Option<String>,
+}
+
+#[cfg(feature = "client")]
+impl HttpShardSource {
+    pub fn new(prefix: &str, token: Option<&str>) -> HttpShardSource {
+        return HttpShardSource { prefix: prefix.to_string(), token: token.map(|x| x.to_string()) };
+    }
+}
+
+#[cfg(feature = "client")]
+impl ShardSource for HttpShardSource {
+    fn fetch_symbols(&self, node: &str, object_id: &ObjectId, block_id: u32, count: usize) -> io::Result<Vec<EncodedBlock>> {
+        let (_, symbols) = fetch_symbols(node, &self.prefix, object_id, self.token.as_deref(), None, Some(block_id), count)?;
+        return Ok(symbols);
+    }
+}
+
+/// What a pass of an AntiEntropy job found and did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AntiEntropyReport {
+    /// Blocks placed on the node.
+    pub checked: u64,
+    /// Blocks whose shard was missing, unreadable or too short to decode from.
+    pub missing: u64,
+    /// Missing blocks whose shard was recreated.
+    pub repaired: u64,
+    /// Missing blocks that could not be recreated, e.g. as no other node had enough of their symbols.
+    pub failed: Vec<(ObjectId, u32)>,
+}
+
+/// Recreates the missing shards of a storage node, see the module documentation.
+pub struct AntiEntropy {
+    node: String,
+    ring: PlacementRing,
+    store: Arc<dyn ShardStore>,
+    source: Arc<dyn ShardSource>,
+}
+
+impl AntiEntropy {
+    /// Creates a job for node, as named in ring, keeping its shards in store and fetching lost blocks from source.
+    pub fn new(node: &str, ring: PlacementRing, store: Arc<dyn ShardStore>, source: Arc<dyn ShardSource>) -> AntiEntropy {
+        return AntiEntropy { node: node.to_string(), ring, store, source };
+    }
+
+    /// Goes over the blocks of manifests placed on the node once, recreating their shards where needed. Only fails if
+    /// the store can't be listed; blocks that can't be recreated are reported and tried again on the next pass.
+    pub fn run_once(&self, manifests: &[Manifest]) -> io::Result<AntiEntropyReport> {
+        let inventory = self.store.list()?;
+        let mut report = AntiEntropyReport::default();
+        for manifest in manifests.iter() {
+            let block_count = manifest.get_block_count() as u32;
+            for block_id in self.ring.get_node_blocks(&self.node, &manifest.object_id, block_count) {
+                report.checked += 1;
+                let block_info = &manifest.block_info_vec[block_id as usize];
+                let symbol_count = block_info.padded_size / block_info.config.symbol_size() as usize;
+                let local = match inventory.binary_search(&(manifest.object_id, block_id)) {
+                    Ok(_) => self.store.get_shard(&manifest.object_id, block_id).unwrap_or_default(),
+                    Err(_) => Vec::new(),
+                };
+                if local.len() >= symbol_count {
+                    continue;
+                }
+
+                report.missing += 1;
+                match self.repair_block(manifest, block_id, local) {
+                    Ok(()) => report.repaired += 1,
+                    Err(_) => report.failed.push((manifest.object_id, block_id)),
+                }
+            }
+        }
+        return Ok(report);
+    }
+
+    /// Decodes a block from the symbols left locally and those of the other nodes, the ones it is placed on first,
+    /// and stores a shard encoded from it again.
+    fn repair_block(&self, manifest: &Manifest, block_id: u32, local: Vec<EncodedBlock>) -> io::Result<()> {
+        let object_id = &manifest.object_id;
+        let block_info = manifest.block_info_vec[block_id as usize].clone();
+        let new_decoder = || BlockDecoder::new(block_info.clone()).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", error)));
+        let mut decoder = new_decoder()?;
+        let count = decoder.get_symbol_count() + REPAIR_SLACK_SYMBOLS;
+        let _ = decoder.consume(local.into_iter().filter(|x| x.block_id == block_id).collect());
+
+        let placed = self.ring.place(object_id, block_id);
+        let others = self.ring.get_nodes().iter().map(|x| &x[..]).filter(|x| !placed.contains(x));
+        for node in placed.iter().copied().chain(others).filter(|x| *x != self.node) {
+            if !decoder.is_decoded() {
+                let symbols = match self.source.fetch_symbols(node, object_id, block_id, count) {
+                    Ok(symbols) => symbols,
+                    Err(_) => continue,
+                };
+                let _ = decoder.consume(symbols.into_iter().filter(|x| x.block_id == block_id).collect());
+            }
+            // a node handing out bad symbols is no reason to give up on the others
+            if decoder.get_result().is_some_and(|x| Sha256::digest(x)[..] != manifest.block_hashes[block_id as usize][..]) {
+                decoder = new_decoder()?;
+            }
+        }
+
+        let data = match decoder.get_result() {
+            Some(data) => data.to_vec(),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("no node has enough symbols of block {}", block_id))),
+        };
+        return self.store.put_shard(object_id, block_id, &encode_shard(manifest, block_id, data)?);
+    }
+
+    /// Runs a pass every interval on its own thread until stopped, over the manifests manifests returns each time.
+    pub fn spawn<F: Fn() -> Vec<Manifest> + Send + 'static>(self, interval: Duration, manifests: F) -> AntiEntropyJob {
+        let stop = Arc::new(AtomicBool::new(false));
+        let last_report: Arc<Mutex<Option<AntiEntropyReport>>> = Arc::new(Mutex::new(None));
+        let failed_passes = Arc::new(AtomicU64::new(0));
+        let thread = {
+            let (stop, last_report, failed_passes) = (stop.clone(), last_report.clone(), failed_passes.clone());
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match self.run_once(&manifests()) {
+                        Ok(report) => *last_report.lock().unwrap() = Some(report),
+                        Err(_) => {
+                            failed_passes.fetch_add(1, Ordering::Relaxed);
+                        },
+                    }
+                    let pass_end = Instant::now();
+                    while pass_end.elapsed() < interval && !stop.load(Ordering::Relaxed) {
+                        std::thread::sleep(STOP_POLL_INTERVAL.min(interval));
+                    }
+                }
+            })
+        };
+        return AntiEntropyJob { stop, last_report, failed_passes, thread };
+    }
+}
+
+/// An AntiEntropy job running in the background, see AntiEntropy::spawn.
+pub struct AntiEntropyJob {
+    stop: Arc<AtomicBool>,
+    last_report: Arc<Mutex<Option<AntiEntropyReport>>>,
+    failed_passes: Arc<AtomicU64>,
+    thread: JoinHandle<()>,
+}
+
+impl AntiEntropyJob {
+    /// Gets the report of the last pass that could list the store, or None before the first.
+    pub fn get_last_report(&self) -> Option<AntiEntropyReport> {
+        return self.last_report.lock().unwrap().clone();
+    }
+
+    /// Gets how many passes failed as the store could not be listed.
+    pub fn get_failed_passes(&self) -> u64 {
+        return self.failed_passes.load(Ordering::Relaxed);
+    }
+
+    /// Stops the job, waiting for the pass in progress if any.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::RaptorQEncoder;
+    use std::collections::HashMap;
+
+    /// Nodes whose stores are reachable in this process.
+    struct LocalNodes {
+        stores: HashMap<String, Arc<DirShardStore>>,
+    }
+
+    impl ShardSource for LocalNodes {
+        fn fetch_symbols(&self, node: &str, object_id: &ObjectId, block_id: u32, count: usize) -> io::Result<Vec<EncodedBlock>> {
+            let mut symbols = self.stores[node].get_shard(object_id, block_id)?;
+            symbols.truncate(count);
+            return Ok(symbols);
+        }
+    }
+
+    #[test]
+    fn test_anti_entropy() {
+        let root = std::env::temp_dir().join(format!("raptorcdn-anti-entropy-test-{}", std::process::id()));
+        let mut ring = PlacementRing::new(2);
+        let mut stores: HashMap<String, Arc<DirShardStore>> = HashMap::new();
+        for node in ["a", "b", "c"] {
+            ring.add_node(node);
+            stores.insert(node.to_string(), Arc::new(DirShardStore::new(root.join(node)).unwrap()));
+        }
+
+        let mut manifests: Vec<Manifest> = Vec::new();
+        for _ in 0..8 {
+            let encoder = match RaptorQEncoder::new(1280, &gen_data(10 * 1000)) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            };
+            let manifest = Manifest::new(&encoder);
+            for node in ring.place(&manifest.object_id, 0) {
+                stores[node].put_shard(&manifest.object_id, 0, &encoder.generate_encoded_blocks()).unwrap();
+            }
+            manifests.push(manifest);
+        }
+        let source = Arc::new(LocalNodes { stores: stores.clone() });
+        let job = AntiEntropy::new("a", ring.clone(), stores["a"].clone(), source.clone());
+        let report = job.run_once(&manifests).unwrap();
+        assert_eq!((report.missing, report.repaired), (0, 0));
+        assert_eq!(report.checked as usize, stores["a"].list().unwrap().len());
+
+        // a's disk fails
+        fs::remove_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        let report = job.run_once(&manifests).unwrap();
+        assert!(report.checked > 0);
+        assert_eq!((report.missing, report.repaired, report.failed.len()), (report.checked, report.checked, 0));
+        for (object_id, block_id) in stores["a"].list().unwrap() {
+            let manifest = manifests.iter().find(|x| x.object_id == object_id).unwrap();
+            let mut decoder = BlockDecoder::new(manifest.block_info_vec[block_id as usize].clone()).unwrap();
+            assert!(decoder.consume(stores["a"].get_shard(&object_id, block_id).unwrap()).unwrap());
+        }
+
+        // with its replicas gone too, a block can't be recreated, and running in the background changes nothing
+        let (object_id, block_id) = stores["a"].list().unwrap()[0];
+        for node in ring.place(&object_id, block_id) {
+            fs::remove_file(stores[node].get_path(&object_id, block_id)).unwrap();
+        }
+        let background = job.spawn(Duration::from_secs(60), move || manifests.clone());
+        while background.get_last_report().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(background.get_last_report().unwrap().failed, vec![(object_id, block_id)]);
+        background.stop();
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
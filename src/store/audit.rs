@@ -0,0 +1,319 @@
+//! Answering what a storage node holds and whether it can still serve it, e.g. after a disk incident. An inventory
+//! lists the shards of a ShardStore by object with the symbols each holds; an audit also decodes every block of the
+//! objects it has manifests for from its shard alone, and checks the result against the block's hash.
+
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::codec::decoder::{esi_ranges, BlockDecoder};
+use crate::codec::encoder::EncodedBlock;
+use crate::codec::manifest::{Manifest, ObjectId};
+use super::anti_entropy::ShardStore;
+use super::placement::PlacementRing;
+
+/// What the shard of a block holds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShardInventory {
+    pub block_id: u32,
+    /// Distinct symbols of the block.
+    pub symbols: usize,
+    /// Encoding symbol ids of the block held, as sorted, disjoint ranges.
+    pub esi_ranges: Vec<Range<u32>>,
+    /// Symbols of other blocks, which a shard of this block should not hold.
+    pub foreign_symbols: usize,
+    /// Bytes of all the symbols held.
+    pub bytes: u64,
+    /// Why the shard could not be read, in which case it counts as holding nothing.
+    pub error: Option<String>,
+}
+
+impl ShardInventory {
+    fn new(block_id: u32, shard: &io::Result<Vec<EncodedBlock>>) -> ShardInventory {
+        let symbols = match shard {
+            Ok(symbols) => &symbols[..],
+            Err(_) => &[],
+        };
+        let esi_ranges = esi_ranges(symbols.iter().filter(|x| x.block_id == block_id).map(|x| x.data.payload_id().encoding_symbol_id()));
+        return ShardInventory {
+            block_id,
+            symbols: esi_ranges.iter().map(|x| x.len()).sum(),
+            esi_ranges,
+            foreign_symbols: symbols.iter().filter(|x| x.block_id != block_id).count(),
+            bytes: symbols.iter().map(|x| x.data.data().len() as u64).sum(),
+            error: shard.as_ref().err().map(|x| x.to_string()),
+        };
+    }
+}
+
+/// The shards a store holds of an object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectInventory {
+    pub object_id: ObjectId,
+    /// Shards by block id.
+    pub shards: Vec<ShardInventory>,
+}
+
+impl ObjectInventory {
+    /// Gets the bytes of symbols held of the object.
+    pub fn get_bytes(&self) -> u64 {
+        return self.shards.iter().map(|x| x.bytes).sum();
+    }
+}
+
+/// Reads every shard of store, listing them by object. Only fails if the store can't be listed; shards that can't
+/// be read are listed with their error.
+pub fn take_inventory(store: &dyn ShardStore) -> io::Result<Vec<ObjectInventory>> {
+    let mut objects: Vec<ObjectInventory> = Vec::new();
+    for (object_id, block_id) in store.list()? {
+        let shard = ShardInventory::new(block_id, &store.get_shard(&object_id, block_id));
+        match objects.last_mut() {
+            Some(object) if object.object_id == object_id => object.shards.push(shard),
+            _ => objects.push(ObjectInventory { object_id, shards: vec![shard] }),
+        }
+    }
+    return Ok(objects);
+}
+
+/// Whether a block can be served from its shard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// The shard decodes to the block's hash.
+    Ok,
+    /// There is no shard.
+    Missing,
+    /// The shard could not be read, with why.
+    Unreadable(String),
+    /// The shard holds too few valid symbols to decode, with how many more it would need.
+    TooFewSymbols(usize),
+    /// The shard decodes to data that does not match the block's hash.
+    HashMismatch,
+}
+
+/// What an audit found of a block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockAudit {
+    pub block_id: u32,
+    /// Whether the node is expected to hold the block, see Auditor::set_placement.
+    pub expected: bool,
+    pub status: BlockStatus,
+    /// The shard of the block, None if it is missing.
+    pub shard: Option<ShardInventory>,
+    /// Symbols of the block that do not fit it, e.g. of the wrong size.
+    pub invalid_symbols: u32,
+}
+
+/// What an audit found of an object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectAudit {
+    pub object_id: ObjectId,
+    /// Blocks expected or held, by block id.
+    pub blocks: Vec<BlockAudit>,
+}
+
+impl ObjectAudit {
+    /// Returns true if every block the node is expected to hold of the object is Ok.
+    pub fn can_serve(&self) -> bool {
+        return self.blocks.iter().filter(|x| x.expected).all(|x| x.status == BlockStatus::Ok);
+    }
+}
+
+/// What an audit found, see Auditor::run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Objects with a manifest, in the order of the manifests.
+    pub objects: Vec<ObjectAudit>,
+    /// Shards of objects no manifest was given for, which could not be checked.
+    pub unknown: Vec<ObjectInventory>,
+}
+
+impl AuditReport {
+    /// Returns whether the node can serve an object, or None if the audit had no manifest for it.
+    pub fn can_serve(&self, object_id: &ObjectId) -> Option<bool> {
+        return self.objects.iter().find(|x| &x.object_id == object_id).map(|x| x.can_serve());
+    }
+}
+
+/// Checks the shards of a storage node, see the module documentation.
+pub struct Auditor {
+    store: Arc<dyn ShardStore>,
+    /// The node's name in a ring, if it only holds the blocks placed on it.
+    placement: Option<(String, PlacementRing)>,
+}
+
+impl Auditor {
+    /// Creates an Auditor of the shards in store, expecting a shard of every block of each object.
+    pub fn new(store: Arc<dyn ShardStore>) -> Auditor {
+        return Auditor { store, placement: None };
+    }
+
+    /// Only expects shards of the blocks ring places on node, as named in ring. Shards of other blocks are still
+    /// checked, but do not count towards serving the object.
+    pub fn set_placement(&mut self, node: &str, ring: PlacementRing) {
+        self.placement = Some((node.to_string(), ring));
+    }
+
+    /// Checks the shards of the objects of manifests, and lists those of other objects. Only fails if the store
+    /// can't be listed.
+    pub fn run(&self, manifests: &[Manifest]) -> io::Result<AuditReport> {
+        let mut held: BTreeMap<ObjectId, Vec<u32>> = BTreeMap::new();
+        for (object_id, block_id) in self.store.list()? {
+            held.entry(object_id).or_default().push(block_id);
+        }
+
+        let mut report = AuditReport::default();
+        for manifest in manifests.iter() {
+            let block_count = manifest.get_block_count() as u32;
+            let held_blocks = held.remove(&manifest.object_id).unwrap_or_default();
+            let expected_blocks = match &self.placement {
+                Some((node, ring)) => ring.get_node_blocks(node, &manifest.object_id, block_count),
+                None => (0..block_count).collect(),
+            };
+
+            let mut block_ids: Vec<u32> = held_blocks.iter().copied().chain(expected_blocks.iter().copied()).collect();
+            block_ids.sort_unstable();
+            block_ids.dedup();
+            let blocks = block_ids.into_iter().map(|block_id| {
+                let mut audit = match held_blocks.binary_search(&block_id) {
+                    Ok(_) => self.audit_block(manifest, block_id),
+                    Err(_) => BlockAudit { block_id, expected: false, status: BlockStatus::Missing, shard: None, invalid_symbols: 0 },
+                };
+                audit.expected = expected_blocks.binary_search(&block_id).is_ok();
+                return audit;
+            }).collect();
+            report.objects.push(ObjectAudit { object_id: manifest.object_id, blocks });
+        }
+
+        for (object_id, block_ids) in held.into_iter() {
+            let shards = block_ids.into_iter().map(|x| ShardInventory::new(x, &self.store.get_shard(&object_id, x))).collect();
+            report.unknown.push(ObjectInventory { object_id, shards });
+        }
+        return Ok(report);
+    }
+
+    /// Decodes a block from its shard and checks it against its hash.
+    fn audit_block(&self, manifest: &Manifest, block_id: u32) -> BlockAudit {
+        let shard = self.store.get_shard(&manifest.object_id, block_id);
+        let inventory = ShardInventory::new(block_id, &shard);
+        let mut audit = BlockAudit { block_id, expected: false, status: BlockStatus::Missing, shard: None, invalid_symbols: 0 };
+
+        let block_info = match manifest.block_info_vec.get(block_id as usize) {
+            Some(block_info) => block_info.clone(),
+            None => {
+                // a shard of a block the object does not have
+                audit.status = BlockStatus::Unreadable(format!("object has no block {}", block_id));
+                audit.shard = Some(inventory);
+                return audit;
+            },
+        };
+        audit.status = match shard {
+            Err(error) => BlockStatus::Unreadable(error.to_string()),
+            Ok(symbols) => {
+                let symbols: Vec<EncodedBlock> = symbols.into_iter().filter(|x| x.block_id == block_id).collect();
+                match BlockDecoder::new(block_info) {
+                    Err(error) => BlockStatus::Unreadable(format!("bad block info: {:?}", error)),
+                    Ok(mut decoder) => {
+                        // every symbol the shard holds may be needed to decode
+                        decoder.set_symbol_slack(symbols.len());
+                        let _ = decoder.consume(symbols);
+                        audit.invalid_symbols = decoder.get_decode_stats().invalid_symbols;
+                        match decoder.get_result() {
+                            None => BlockStatus::TooFewSymbols(decoder.get_symbols_needed()),
+                            Some(data) if Sha256::digest(data)[..] != manifest.block_hashes[block_id as usize][..] => BlockStatus::HashMismatch,
+                            Some(_) => BlockStatus::Ok,
+                        }
+                    },
+                }
+            },
+        };
+        audit.shard = Some(inventory);
+        return audit;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::{BlockEncoder, EncoderConfig};
+    use crate::store::anti_entropy::DirShardStore;
+    use std::fs;
+
+    #[test]
+    fn test_shard_audit() {
+        let dir = std::env::temp_dir().join(format!("raptorcdn-audit-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = Arc::new(DirShardStore::new(&dir).unwrap());
+
+        let data = gen_data(50 * 1000);
+        let block_encoders: Vec<BlockEncoder> = data.chunks(12 * 1000).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, 1280, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let mut manifest = Manifest {
+            object_id: Sha256::digest(&data).into(),
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(1280),
+            block_info_vec: block_encoders.iter().map(|x| x.get_block_info()).collect(),
+            block_hashes: block_encoders.iter().map(|x| Sha256::digest(x.get_payload()).into()).collect(),
+            block_overheads: vec![0; block_encoders.len()],
+            envelope: None,
+        };
+        let object_id = manifest.object_id;
+        for encoder in block_encoders.iter() {
+            let mut symbols = encoder.generate_source_blocks();
+            symbols.append(&mut encoder.generate_encoded_blocks());
+            store.put_shard(&object_id, encoder.get_block_info().block_id, &symbols).unwrap();
+        }
+        store.put_shard(&[9; 32], 0, &block_encoders[0].generate_source_blocks()).unwrap();
+
+        let inventory = take_inventory(&*store).unwrap();
+        assert_eq!(inventory.len(), 2);
+        let object = inventory.iter().find(|x| x.object_id == object_id).unwrap();
+        assert_eq!(object.shards.iter().map(|x| x.block_id).collect::<Vec<u32>>(), vec![0, 1, 2, 3, 4]);
+        let symbol_count = block_encoders[0].generate_source_blocks().len() as u32;
+        assert_eq!(object.shards[0].esi_ranges[0], 0..symbol_count);
+        assert_eq!(object.shards[0].bytes, object.shards[0].symbols as u64 * 1280);
+
+        let auditor = Auditor::new(store.clone());
+        let report = auditor.run(std::slice::from_ref(&manifest)).unwrap();
+        assert_eq!(report.can_serve(&object_id), Some(true));
+        assert_eq!(report.unknown.len(), 1);
+        assert_eq!(report.unknown[0].object_id, [9; 32]);
+
+        // the disk incident: a shard lost, one cut short, one garbled, and one no longer matching
+        fs::remove_file(store.get_path(&object_id, 1)).unwrap();
+        let mut symbols = block_encoders[2].generate_source_blocks();
+        symbols.truncate(3);
+        store.put_shard(&object_id, 2, &symbols).unwrap();
+        fs::write(store.get_path(&object_id, 3), b"not a shard").unwrap();
+        manifest.block_hashes[4] = [0; 32];
+
+        let report = auditor.run(std::slice::from_ref(&manifest)).unwrap();
+        assert_eq!(report.can_serve(&object_id), Some(false));
+        assert_eq!(report.can_serve(&[9; 32]), None);
+        let statuses: Vec<BlockStatus> = report.objects[0].blocks.iter().map(|x| x.status.clone()).collect();
+        assert_eq!(statuses[0], BlockStatus::Ok);
+        assert_eq!(statuses[1], BlockStatus::Missing);
+        assert_eq!(statuses[2], BlockStatus::TooFewSymbols(symbol_count as usize - 3));
+        assert!(matches!(statuses[3], BlockStatus::Unreadable(_)));
+        assert_eq!(statuses[4], BlockStatus::HashMismatch);
+
+        // a node only holding some blocks can serve its part as long as those are fine
+        let mut ring = PlacementRing::new(1);
+        ring.add_node("a:1");
+        ring.add_node("b:1");
+        let mut auditor = Auditor::new(store.clone());
+        auditor.set_placement("a:1", ring.clone());
+        let report = auditor.run(std::slice::from_ref(&manifest)).unwrap();
+        let expected: Vec<u32> = report.objects[0].blocks.iter().filter(|x| x.expected).map(|x| x.block_id).collect();
+        assert_eq!(expected, ring.get_node_blocks("a:1", &object_id, 5));
+        assert_eq!(report.can_serve(&object_id), Some(expected.iter().all(|x| *x == 0)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::codec::manifest::{to_hex, Manifest, ObjectId};
+use super::object_store::{unknown_object, ObjectStore, StoredObject};
+
+/// Keeps objects as files in a directory, named by object id. Objects being written have a .partial extension until
+/// they are finalized, so a finalized file is always complete. Which blocks of a partial file are in is only tracked
+/// in memory, so a partial file is started over after a restart.
+pub struct FileStore {
+    root: PathBuf,
+    objects: Mutex<HashMap<ObjectId, StoredObject>>,
+}
+
+impl FileStore {
+    /// Creates a FileStore keeping objects in root, creating it if needed.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<FileStore> {
+        fs::create_dir_all(root.as_ref())?;
+        return Ok(FileStore {
+            root: root.as_ref().to_path_buf(),
+            objects: Mutex::new(HashMap::new()),
+        });
+    }
+
+    /// Gets the path of an object's file once finalized.
+    pub fn get_path(&self, object_id: &ObjectId) -> PathBuf {
+        return self.root.join(to_hex(object_id));
+    }
+
+    fn get_partial_path(&self, object_id: &ObjectId) -> PathBuf {
+        return self.root.join(format!("{}.partial", to_hex(object_id)));
+    }
+}
+
+impl ObjectStore for FileStore {
+    fn open(&self, manifest: &Manifest) -> io::Result<()> {
+        let mut objects = self.objects.lock().unwrap();
+        if objects.contains_key(&manifest.object_id) {
+            return Ok(());
+        }
+
+        let path = self.get_path(&manifest.object_id);
+        if fs::metadata(&path).is_ok_and(|x| x.len() == manifest.data_size) {
+            objects.insert(manifest.object_id, StoredObject::new_finalized(manifest));
+            return Ok(());
+        }
+
+        let file = File::create(self.get_partial_path(&manifest.object_id))?;
+        file.set_len(manifest.data_size)?;
+        objects.insert(manifest.object_id, StoredObject::new(manifest));
+        return Ok(());
+    }
+
+    fn put_block(&self, object_id: &ObjectId, block_id: u32, data: &[u8]) -> io::Result<()> {
+        let mut objects = self.objects.lock().unwrap();
+        let object = objects.get_mut(object_id).ok_or_else(unknown_object)?;
+        let region = object.check_put(block_id, data.len())?;
+
+        let mut file = OpenOptions::new().write(true).open(self.get_partial_path(object_id))?;
+        file.seek(SeekFrom::Start(region.byte_offset as u64))?;
+        file.write_all(data)?;
+        object.mark_present(block_id);
+        return Ok(());
+    }
+
+    fn get_range(&self, object_id: &ObjectId, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let objects = self.objects.lock().unwrap();
+        let object = objects.get(object_id).ok_or_else(unknown_object)?;
+        object.check_range(offset, len)?;
+
+        let path = if object.is_finalized() { self.get_path(object_id) } else { self.get_partial_path(object_id) };
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data: Vec<u8> = vec![0; len];
+        file.read_exact(&mut data)?;
+        return Ok(data);
+    }
+
+    fn has_block(&self, object_id: &ObjectId, block_id: u32) -> io::Result<bool> {
+        let objects = self.objects.lock().unwrap();
+        let object = objects.get(object_id).ok_or_else(unknown_object)?;
+        return Ok(object.is_present(block_id));
+    }
+
+    fn finalize(&self, object_id: &ObjectId) -> io::Result<()> {
+        let mut objects = self.objects.lock().unwrap();
+        let object = objects.get_mut(object_id).ok_or_else(unknown_object)?;
+        if object.is_finalized() {
+            return Ok(());
+        }
+
+        object.check_complete()?;
+        let partial_path = self.get_partial_path(object_id);
+        File::open(&partial_path)?.sync_all()?;
+        fs::rename(&partial_path, self.get_path(object_id))?;
+        object.mark_finalized();
+        return Ok(());
+    }
+
+    fn delete(&self, object_id: &ObjectId) -> io::Result<bool> {
+        let mut objects = self.objects.lock().unwrap();
+        // a finalized file may be on disk from before a restart without having been opened
+        let mut deleted = objects.remove(object_id).is_some();
+        for path in [self.get_path(object_id), self.get_partial_path(object_id)] {
+            match fs::remove_file(path) {
+                Ok(()) => deleted = true,
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+                Err(_) => (),
+            }
+        }
+        return Ok(deleted);
+    }
+
+    #[cfg(unix)]
+    fn get_available_space(&self) -> io::Result<Option<u64>> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = match std::ffi::CString::new(self.root.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "root path contains a nul byte")),
+        };
+        let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // blocks available to unprivileged users, in fragment size units
+        return Ok(Some((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64)));
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use crate::codec::manifest::{Manifest, ObjectId};
+use super::object_store::{unknown_object, ObjectStore, StoredObject};
+
+/// Keeps objects in memory, for tests and edges that can refetch anything they lose.
+pub struct MemoryStore {
+    objects: Mutex<HashMap<ObjectId, (StoredObject, Vec<u8>)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        return MemoryStore { objects: Mutex::new(HashMap::new()) };
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        return MemoryStore::new();
+    }
+}
+
+impl ObjectStore for MemoryStore {
+    fn open(&self, manifest: &Manifest) -> io::Result<()> {
+        let mut objects = self.objects.lock().unwrap();
+        objects.entry(manifest.object_id).or_insert_with(|| (StoredObject::new(manifest), vec![0; manifest.data_size as usize]));
+        return Ok(());
+    }
+
+    fn put_block(&self, object_id: &ObjectId, block_id: u32, data: &[u8]) -> io::Result<()> {
+        let mut objects = self.objects.lock().unwrap();
+        let (object, payload) = objects.get_mut(object_id).ok_or_else(unknown_object)?;
+        let region = object.check_put(block_id, data.len())?;
+        payload[region.byte_offset..(region.byte_offset + region.len)].copy_from_slice(data);
+        object.mark_present(block_id);
+        return Ok(());
+    }
+
+    fn get_range(&self, object_id: &ObjectId, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let objects = self.objects.lock().unwrap();
+        let (object, payload) = objects.get(object_id).ok_or_else(unknown_object)?;
+        object.check_range(offset, len)?;
+        return Ok(payload[(offset as usize)..(offset as usize + len)].to_vec());
+    }
+
+    fn has_block(&self, object_id: &ObjectId, block_id: u32) -> io::Result<bool> {
+        let objects = self.objects.lock().unwrap();
+        let (object, _) = objects.get(object_id).ok_or_else(unknown_object)?;
+        return Ok(object.is_present(block_id));
+    }
+
+    fn finalize(&self, object_id: &ObjectId) -> io::Result<()> {
+        let mut objects = self.objects.lock().unwrap();
+        let (object, _) = objects.get_mut(object_id).ok_or_else(unknown_object)?;
+        object.check_complete()?;
+        object.mark_finalized();
+        return Ok(());
+    }
+
+    fn delete(&self, object_id: &ObjectId) -> io::Result<bool> {
+        return Ok(self.objects.lock().unwrap().remove(object_id).is_some());
+    }
+}
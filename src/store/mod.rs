@@ -0,0 +1,10 @@
+pub mod object_store;
+pub mod memory;
+pub mod tenant;
+pub mod file;
+pub mod resume;
+pub mod warm;
+pub mod placement;
+pub mod anti_entropy;
+pub mod audit;
+pub mod read_repair;
@@ -0,0 +1,289 @@
+use std::io;
+
+use crate::codec::decoder::RaptorQDecoder;
+use crate::codec::encoder::BlockRegion;
+use crate::codec::manifest::{Manifest, ObjectId};
+
+/// Where decoded objects end up. Blocks are put as they are decoded, in any order, and the object is finalized once
+/// every block is in. Ranges can be read back as soon as the blocks covering them are in, so a gateway can start
+/// serving an object before it is complete.
+pub trait ObjectStore: Send + Sync {
+    /// Prepares the store to receive the blocks of an object. Opening an object again keeps the blocks already put.
+    fn open(&self, manifest: &Manifest) -> io::Result<()>;
+
+    /// Stores the payload of a block, without padding. Putting a block again overwrites it.
+    fn put_block(&self, object_id: &ObjectId, block_id: u32, data: &[u8]) -> io::Result<()>;
+
+    /// Reads len bytes of the object's payload at offset. Fails with ErrorKind::NotFound if a block covering the
+    /// range is not in yet.
+    fn get_range(&self, object_id: &ObjectId, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+
+    /// Checks whether a block of the object is in, so it doesn't need to be put again. Stores that can't tell
+    /// answer false and get blocks put again.
+    fn has_block(&self, _object_id: &ObjectId, _block_id: u32) -> io::Result<bool> {
+        return Ok(false);
+    }
+
+    /// Marks the object complete, after which its blocks can't be put anymore. Fails with ErrorKind::InvalidInput
+    /// if a block is missing.
+    fn finalize(&self, object_id: &ObjectId) -> io::Result<()>;
+
+    /// Deletes whatever is stored of an object, finalized or not, e.g. once it was purged. Returns whether there was
+    /// anything. The object has to be opened again to put blocks.
+    fn delete(&self, object_id: &ObjectId) -> io::Result<bool>;
+
+    /// Gets how many more bytes the store can take, or None if it can't tell, e.g. as it keeps objects in memory.
+    fn get_available_space(&self) -> io::Result<Option<u64>> {
+        return Ok(None);
+    }
+}
+
+/// Puts the blocks the decoder has recovered so far into an object opened in store, finalizing the object once
+/// every block is in. Blocks the store already has are skipped, so this can be called after every decoded block.
+/// Returns true once finalized; don't call again after that.
+pub fn put_decoded_blocks(store: &dyn ObjectStore, object_id: &ObjectId, decoder: &RaptorQDecoder) -> io::Result<bool> {
+    for region in decoder.block_map() {
+        if let Some(data) = decoder.get_block_result(region.block_id) {
+            if !store.has_block(object_id, region.block_id)? {
+                store.put_block(object_id, region.block_id, data)?;
+            }
+        }
+    }
+
+    if !decoder.is_decoded() {
+        return Ok(false);
+    }
+    store.finalize(object_id)?;
+    return Ok(true);
+}
+
+/// Bookkeeping shared by the ObjectStore implementations: which blocks of an object are in, and where they go.
+pub(crate) struct StoredObject {
+    regions: Vec<BlockRegion>,
+    data_size: u64,
+    present: Vec<bool>,
+    finalized: bool,
+}
+
+impl StoredObject {
+    pub(crate) fn new(manifest: &Manifest) -> StoredObject {
+        return StoredObject {
+            regions: BlockRegion::map(&manifest.block_info_vec),
+            data_size: manifest.data_size,
+            present: vec![false; manifest.get_block_count()],
+            finalized: false,
+        };
+    }
+
+    /// Creates the bookkeeping of an object already finalized, e.g. found on disk.
+    pub(crate) fn new_finalized(manifest: &Manifest) -> StoredObject {
+        let mut object = StoredObject::new(manifest);
+        object.present.iter_mut().for_each(|x| *x = true);
+        object.finalized = true;
+        return object;
+    }
+
+    /// Checks a block can be put, returning where it goes.
+    pub(crate) fn check_put(&self, block_id: u32, len: usize) -> io::Result<BlockRegion> {
+        if self.finalized {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "object is finalized"));
+        }
+        match self.regions.get(block_id as usize) {
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no block {}", block_id))),
+            Some(region) if region.len != len => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("block {} is {} bytes, not {}", block_id, region.len, len)));
+            },
+            Some(region) => return Ok(*region),
+        }
+    }
+
+    pub(crate) fn is_present(&self, block_id: u32) -> bool {
+        return self.present.get(block_id as usize).copied().unwrap_or(false);
+    }
+
+    pub(crate) fn mark_present(&mut self, block_id: u32) {
+        self.present[block_id as usize] = true;
+    }
+
+    /// Checks every block covering a range is in.
+    pub(crate) fn check_range(&self, offset: u64, len: usize) -> io::Result<()> {
+        let end = offset.checked_add(len as u64).filter(|x| *x <= self.data_size);
+        let end = match end {
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "range is past the end of the object")),
+            Some(end) => end,
+        };
+
+        let missing = self.regions.iter()
+            .zip(self.present.iter())
+            .any(|(region, present)| !present && (region.byte_offset as u64) < end && offset < (region.byte_offset + region.len) as u64);
+        if missing {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "range covers blocks not stored yet"));
+        }
+        return Ok(());
+    }
+
+    /// Checks every block is in, so the object can be finalized.
+    pub(crate) fn check_complete(&self) -> io::Result<()> {
+        if let Some(block_id) = self.present.iter().position(|x| !x) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("block {} is missing", block_id)));
+        }
+        return Ok(());
+    }
+
+    pub(crate) fn mark_finalized(&mut self) {
+        self.finalized = true;
+    }
+
+    pub(crate) fn is_finalized(&self) -> bool {
+        return self.finalized;
+    }
+}
+
+/// Error for operations on an object that was never opened.
+pub(crate) fn unknown_object() -> io::Error {
+    return io::Error::new(io::ErrorKind::NotFound, "object not opened");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use super::super::file::FileStore;
+    use super::super::memory::MemoryStore;
+    use crate::codec::encoder::*;
+
+    /// Decodes an object of several blocks into store one block at a time, reading ranges back along the way.
+    fn check_store(store: &dyn ObjectStore) {
+        let packet_size: u16 = 1280;
+        let block_size: usize = 32 * 1024;
+        let data = gen_data(3 * block_size);
+
+        let encoders: Vec<BlockEncoder> = data.chunks(block_size).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, packet_size, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let manifest = Manifest {
+            object_id: [1; 32],
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(packet_size),
+            block_info_vec: encoders.iter().map(|x| x.get_block_info()).collect(),
+            block_hashes: vec![[0; 32]; encoders.len()],
+            block_overheads: vec![0; encoders.len()],
+            envelope: None,
+        };
+        let object_id = manifest.object_id;
+        let mut decoder = match RaptorQDecoder::from_manifest(&manifest) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        };
+
+        assert_eq!(store.put_block(&object_id, 0, &[]).unwrap_err().kind(), io::ErrorKind::NotFound);
+        store.open(&manifest).unwrap();
+
+        // only the middle block
+        decoder.consume(encoders[1].generate_source_blocks()).unwrap();
+        assert!(!put_decoded_blocks(store, &object_id, &decoder).unwrap());
+        assert!(store.has_block(&object_id, 1).unwrap());
+        assert!(!store.has_block(&object_id, 0).unwrap());
+        assert_eq!(store.get_range(&object_id, block_size as u64 + 10, 100).unwrap(), &data[(block_size + 10)..(block_size + 110)]);
+        assert_eq!(store.get_range(&object_id, block_size as u64 - 1, 100).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(store.finalize(&object_id).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.put_block(&object_id, 0, &data[..10]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        for encoder in encoders.iter() {
+            let mut blocks = encoder.generate_encoded_blocks();
+            blocks.append(&mut encoder.generate_encoded_blocks());
+            decoder.consume(blocks).unwrap();
+        }
+        assert!(put_decoded_blocks(store, &object_id, &decoder).unwrap());
+        assert_eq!(store.get_range(&object_id, 0, data.len()).unwrap(), data);
+        assert_eq!(store.get_range(&object_id, 1, data.len()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.put_block(&object_id, 0, &data[..block_size]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // reopening a finalized object keeps it
+        store.open(&manifest).unwrap();
+        assert_eq!(store.get_range(&object_id, 0, data.len()).unwrap(), data);
+
+        assert!(store.delete(&object_id).unwrap());
+        assert_eq!(store.get_range(&object_id, 0, 1).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert!(!store.delete(&object_id).unwrap());
+    }
+
+    /// Counts the blocks put into a MemoryStore.
+    struct CountingStore {
+        store: MemoryStore,
+        puts: std::sync::atomic::AtomicUsize,
+    }
+
+    impl ObjectStore for CountingStore {
+        fn open(&self, manifest: &Manifest) -> io::Result<()> {
+            return self.store.open(manifest);
+        }
+
+        fn put_block(&self, object_id: &ObjectId, block_id: u32, data: &[u8]) -> io::Result<()> {
+            self.puts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            return self.store.put_block(object_id, block_id, data);
+        }
+
+        fn get_range(&self, object_id: &ObjectId, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+            return self.store.get_range(object_id, offset, len);
+        }
+
+        fn has_block(&self, object_id: &ObjectId, block_id: u32) -> io::Result<bool> {
+            return self.store.has_block(object_id, block_id);
+        }
+
+        fn finalize(&self, object_id: &ObjectId) -> io::Result<()> {
+            return self.store.finalize(object_id);
+        }
+
+        fn delete(&self, object_id: &ObjectId) -> io::Result<bool> {
+            return self.store.delete(object_id);
+        }
+    }
+
+    #[test]
+    fn test_put_decoded_blocks_once() {
+        let packet_size: u16 = 1280;
+        let data = gen_data(64 * 1024);
+        let encoders: Vec<BlockEncoder> = data.chunks(32 * 1024).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, packet_size, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let manifest = Manifest {
+            object_id: [2; 32],
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(packet_size),
+            block_info_vec: encoders.iter().map(|x| x.get_block_info()).collect(),
+            block_hashes: vec![[0; 32]; encoders.len()],
+            block_overheads: vec![0; encoders.len()],
+            envelope: None,
+        };
+        let mut decoder = RaptorQDecoder::from_manifest(&manifest).unwrap();
+        let store = CountingStore { store: MemoryStore::new(), puts: std::sync::atomic::AtomicUsize::new(0) };
+        store.open(&manifest).unwrap();
+
+        decoder.consume(encoders[0].generate_source_blocks()).unwrap();
+        assert!(!put_decoded_blocks(&store, &manifest.object_id, &decoder).unwrap());
+        assert!(!put_decoded_blocks(&store, &manifest.object_id, &decoder).unwrap());
+        assert_eq!(store.puts.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        decoder.consume(encoders[1].generate_source_blocks()).unwrap();
+        assert!(put_decoded_blocks(&store, &manifest.object_id, &decoder).unwrap());
+        assert_eq!(store.puts.load(std::sync::atomic::Ordering::Relaxed), 2);
+        assert_eq!(store.get_range(&manifest.object_id, 0, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_object_stores() {
+        check_store(&MemoryStore::new());
+
+        let root = std::env::temp_dir().join(format!("raptorcdn-store-test-{}", std::process::id()));
+        check_store(&FileStore::new(&root).unwrap());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+//! Deciding which nodes hold the symbols of a block without asking anyone, by consistent hashing: nodes and blocks are
+//! hashed onto a ring, and a block is placed on the first nodes found going round the ring from its point. Every
+//! process with the same nodes places every block the same, and adding or removing a node only moves the blocks
+//! that land on it.
+
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+
+use crate::codec::manifest::ObjectId;
+
+/// Points each node gets on the ring unless set otherwise. More points spread blocks more evenly over nodes.
+const DEFAULT_VIRTUAL_NODES: u32 = 128;
+
+/// Maps blocks of objects to the nodes holding them, see the module documentation.
+#[derive(Clone, Debug)]
+pub struct PlacementRing {
+    /// Nodes, e.g. "host:port", in the order they were added.
+    nodes: Vec<String>,
+    /// Points on the ring with the index of their node, sorted by point.
+    ring: Vec<(u64, usize)>,
+    replication: usize,
+    virtual_nodes: u32,
+}
+
+/// Gets the point of a key on the ring.
+fn ring_point(key: &[u8]) -> u64 {
+    let digest = Sha256::digest(key);
+    return u64::from_be_bytes(digest[..8].try_into().unwrap());
+}
+
+impl PlacementRing {
+    /// Creates a ring without nodes placing each block on replication nodes. Panics if replication is zero.
+    pub fn new(replication: usize) -> PlacementRing {
+        assert!(replication > 0, "replication must be positive");
+        return PlacementRing { nodes: Vec::new(), ring: Vec::new(), replication, virtual_nodes: DEFAULT_VIRTUAL_NODES };
+    }
+
+    /// Sets how many points each node gets on the ring, moving nodes already added. Every process placing blocks
+    /// must use the same number. Panics if virtual_nodes is zero.
+    pub fn set_virtual_nodes(&mut self, virtual_nodes: u32) {
+        assert!(virtual_nodes > 0, "virtual nodes must be positive");
+        self.virtual_nodes = virtual_nodes;
+        self.rebuild();
+    }
+
+    /// Adds a node, returning false if it was already there.
+    pub fn add_node(&mut self, node: &str) -> bool {
+        if self.nodes.iter().any(|x| x == node) {
+            return false;
+        }
+        self.nodes.push(node.to_string());
+        self.rebuild();
+        return true;
+    }
+
+    /// Removes a node, returning false if it was not there. Its blocks move to the nodes after it on the ring.
+    pub fn remove_node(&mut self, node: &str) -> bool {
+        let count = self.nodes.len();
+        self.nodes.retain(|x| x != node);
+        if self.nodes.len() == count {
+            return false;
+        }
+        self.rebuild();
+        return true;
+    }
+
+    fn rebuild(&mut self) {
+        self.ring.clear();
+        for (index, node) in self.nodes.iter().enumerate() {
+            for point in 0..self.virtual_nodes {
+                self.ring.push((ring_point(format!("{}#{}", node, point).as_bytes()), index));
+            }
+        }
+        // ties between nodes are broken by name, so placement does not depend on the order nodes were added in
+        let nodes = &self.nodes;
+        self.ring.sort_by(|x, y| x.0.cmp(&y.0).then_with(|| nodes[x.1].cmp(&nodes[y.1])));
+    }
+
+    pub fn get_nodes(&self) -> &[String] {
+        return &self.nodes;
+    }
+
+    pub fn get_replication(&self) -> usize {
+        return self.replication;
+    }
+
+    /// Gets the nodes holding a block of an object, its primary first, fewer than the replication factor only if
+    /// there are fewer nodes.
+    pub fn place(&self, object_id: &ObjectId, block_id: u32) -> Vec<&str> {
+        let mut key = object_id.to_vec();
+        key.extend_from_slice(&block_id.to_le_bytes());
+        let point = ring_point(&key);
+        let start = self.ring.partition_point(|x| x.0 < point);
+
+        let mut placed: Vec<usize> = Vec::with_capacity(self.replication);
+        for (_, index) in self.ring[start..].iter().chain(self.ring[..start].iter()) {
+            if placed.len() == self.replication.min(self.nodes.len()) {
+                break;
+            }
+            if !placed.contains(index) {
+                placed.push(*index);
+            }
+        }
+        return placed.into_iter().map(|x| &self.nodes[x][..]).collect();
+    }
+
+    /// Gets the blocks of an object with block_count blocks that node holds, e.g. to know what to fetch when it
+    /// joins.
+    pub fn get_node_blocks(&self, node: &str, object_id: &ObjectId, block_count: u32) -> Vec<u32> {
+        return (0..block_count).filter(|x| self.place(object_id, *x).contains(&node)).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placement_ring() {
+        let mut ring = PlacementRing::new(2);
+        assert!(ring.place(&[1; 32], 0).is_empty());
+        for node in ["a:1", "b:1", "c:1", "d:1"] {
+            assert!(ring.add_node(node));
+        }
+        assert!(!ring.add_node("a:1"));
+
+        let object_id: ObjectId = [7; 32];
+        let before: Vec<Vec<String>> = (0..1000).map(|x| ring.place(&object_id, x).iter().map(|x| x.to_string()).collect()).collect();
+        for nodes in before.iter() {
+            assert_eq!(nodes.len(), 2);
+            assert_ne!(nodes[0], nodes[1]);
+        }
+        // every node gets a fair share of the primaries
+        for node in ring.get_nodes() {
+            let primaries = before.iter().filter(|x| &x[0] == node).count();
+            assert!(primaries > 150 && primaries < 350, "{} is primary for {} blocks", node, primaries);
+        }
+
+        // placement does not depend on the order nodes were added in
+        let mut reversed = PlacementRing::new(2);
+        for node in ["d:1", "c:1", "b:1", "a:1"] {
+            reversed.add_node(node);
+        }
+        assert_eq!(reversed.place(&object_id, 42), ring.place(&object_id, 42));
+
+        // only blocks on the removed node move
+        assert!(ring.remove_node("c:1"));
+        assert!(!ring.remove_node("c:1"));
+        for (block_id, nodes) in before.iter().enumerate() {
+            let after = ring.place(&object_id, block_id as u32);
+            if !nodes.contains(&"c:1".to_string()) {
+                assert_eq!(&after, nodes);
+            }
+        }
+        assert_eq!(ring.get_node_blocks("c:1", &object_id, 1000), Vec::<u32>::new());
+        let mut wide = ring.clone();
+        wide.replication = 5;
+        assert_eq!(wide.place(&object_id, 0).len(), 3);
+    }
+}
@@ -0,0 +1,242 @@
+//! Repairing shards as they are read. A ReadRepairStore wraps the ShardStore of a node; when a shard read to serve
+//! symbols turns out corrupt, e.g. it no longer matches its checksum or is cut short, the block is recovered from
+//! another local copy or the object's payload, checked against its hash, and the shard written again from it, so the
+//! read succeeds instead of failing. Repairs are counted, see get_stats.
+
+use sha2::{Digest, Sha256};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::codec::decoder::BlockDecoder;
+use crate::codec::encoder::{BlockRegion, EncodedBlock};
+use crate::codec::manifest::{Manifest, ObjectId};
+use super::anti_entropy::{encode_shard, ShardStore};
+use super::object_store::ObjectStore;
+
+/// Looks up the manifest of an object, e.g. in a catalog.
+pub type ManifestLookup = Box<dyn Fn(&ObjectId) -> Option<Manifest> + Send + Sync>;
+
+/// Counts of what a ReadRepairStore ran into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadRepairStats {
+    /// Shards found corrupt when read.
+    pub corrupt: u64,
+    /// Corrupt shards written again, whose reads succeeded.
+    pub repaired: u64,
+    /// Corrupt shards that could not be repaired, whose reads failed.
+    pub failed: u64,
+}
+
+/// A ShardStore repairing corrupt shards as they are read, see the module documentation.
+pub struct ReadRepairStore {
+    store: Arc<dyn ShardStore>,
+    /// Manifests of the objects, which repairs need.
+    manifests: ManifestLookup,
+    /// Other local copies of the shards, e.g. on other disks, tried in order.
+    replicas: Vec<Arc<dyn ShardStore>>,
+    /// Payloads of the objects, tried after the replicas.
+    objects: Option<Arc<dyn ObjectStore>>,
+    corrupt: AtomicU64,
+    repaired: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl ReadRepairStore {
+    /// Creates a ReadRepairStore over store, looking up the manifests of objects to repair with manifests, e.g. in
+    /// a catalog. Without a replica or object store added, corrupt shards are only counted.
+    pub fn new<F: Fn(&ObjectId) -> Option<Manifest> + Send + Sync + 'static>(store: Arc<dyn ShardStore>, manifests: F) -> ReadRepairStore {
+        return ReadRepairStore {
+            store,
+            manifests: Box::new(manifests),
+            replicas: Vec::new(),
+            objects: None,
+            corrupt: AtomicU64::new(0),
+            repaired: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        };
+    }
+
+    /// Adds another local copy of the shards to recover blocks from, e.g. on another disk.
+    pub fn add_replica(&mut self, replica: Arc<dyn ShardStore>) {
+        self.replicas.push(replica);
+    }
+
+    /// Sets where the payloads of the objects are kept, e.g. the files they were encoded from, to recover blocks from
+    /// when no replica has them.
+    pub fn set_object_store(&mut self, objects: Arc<dyn ObjectStore>) {
+        self.objects = Some(objects);
+    }
+
+    pub fn get_stats(&self) -> ReadRepairStats {
+        return ReadRepairStats {
+            corrupt: self.corrupt.load(Ordering::Relaxed),
+            repaired: self.repaired.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        };
+    }
+
+    /// Recovers the payload of a block from a replica or the object store, checked against its hash, and writes its
+    /// shard again. Returns the new shard.
+    fn repair(&self, object_id: &ObjectId, block_id: u32) -> io::Result<Vec<EncodedBlock>> {
+        let manifest = match (self.manifests)(object_id) {
+            Some(manifest) => manifest,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no manifest for the object")),
+        };
+        let block_info = match manifest.block_info_vec.get(block_id as usize) {
+            Some(block_info) => block_info,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("object has no block {}", block_id))),
+        };
+        let is_intact = |data: &[u8]| Sha256::digest(data)[..] == manifest.block_hashes[block_id as usize][..];
+
+        let mut recovered: Option<Vec<u8>> = None;
+        for replica in self.replicas.iter() {
+            let symbols = match replica.get_shard(object_id, block_id) {
+                Ok(symbols) => symbols,
+                Err(_) => continue,
+            };
+            let mut decoder = match BlockDecoder::new(block_info.clone()) {
+                Ok(decoder) => decoder,
+                Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", error))),
+            };
+            decoder.set_symbol_slack(symbols.len());
+            let _ = decoder.consume(symbols.into_iter().filter(|x| x.block_id == block_id).collect());
+            if let Some(data) = decoder.get_result().filter(|x| is_intact(x)) {
+                recovered = Some(data.to_vec());
+                break;
+            }
+        }
+        if recovered.is_none() {
+            if let Some(objects) = self.objects.as_ref() {
+                let region = &BlockRegion::map(&manifest.block_info_vec)[block_id as usize];
+                recovered = objects.get_range(object_id, region.byte_offset as u64, region.len).ok().filter(|x| is_intact(x));
+            }
+        }
+
+        let data = match recovered {
+            Some(data) => data,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("no intact copy of block {}", block_id))),
+        };
+        let symbols = encode_shard(&manifest, block_id, data)?;
+        self.store.put_shard(object_id, block_id, &symbols)?;
+        return Ok(symbols);
+    }
+}
+
+impl ShardStore for ReadRepairStore {
+    fn list(&self) -> io::Result<Vec<(ObjectId, u32)>> {
+        return self.store.list();
+    }
+
+    fn get_shard(&self, object_id: &ObjectId, block_id: u32) -> io::Result<Vec<EncodedBlock>> {
+        let error = match self.store.get_shard(object_id, block_id) {
+            Ok(symbols) => return Ok(symbols),
+            Err(error) => error,
+        };
+        // a missing shard is for AntiEntropy to recreate, only one that is there but damaged is repaired here
+        if error.kind() != io::ErrorKind::InvalidData && error.kind() != io::ErrorKind::UnexpectedEof {
+            return Err(error);
+        }
+
+        self.corrupt.fetch_add(1, Ordering::Relaxed);
+        match self.repair(object_id, block_id) {
+            Ok(symbols) => {
+                self.repaired.fetch_add(1, Ordering::Relaxed);
+                return Ok(symbols);
+            },
+            Err(_) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                return Err(error);
+            },
+        }
+    }
+
+    fn put_shard(&self, object_id: &ObjectId, block_id: u32, symbols: &[EncodedBlock]) -> io::Result<()> {
+        return self.store.put_shard(object_id, block_id, symbols);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::{BlockEncoder, EncoderConfig};
+    use crate::store::anti_entropy::DirShardStore;
+    use crate::store::memory::MemoryStore;
+    use std::fs;
+
+    #[test]
+    fn test_read_repair() {
+        let dir = std::env::temp_dir().join(format!("raptorcdn-read-repair-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = Arc::new(DirShardStore::new(dir.join("disk0")).unwrap());
+        let replica = Arc::new(DirShardStore::new(dir.join("disk1")).unwrap());
+
+        let data = gen_data(30 * 1000);
+        let block_encoders: Vec<BlockEncoder> = data.chunks(12 * 1000).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, 1280, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let manifest = Manifest {
+            object_id: Sha256::digest(&data).into(),
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(1280),
+            block_info_vec: block_encoders.iter().map(|x| x.get_block_info()).collect(),
+            block_hashes: block_encoders.iter().map(|x| Sha256::digest(x.get_payload()).into()).collect(),
+            block_overheads: vec![0; block_encoders.len()],
+            envelope: None,
+        };
+        let object_id = manifest.object_id;
+        for encoder in block_encoders.iter() {
+            let symbols = encoder.generate_source_blocks();
+            store.put_shard(&object_id, encoder.get_block_info().block_id, &symbols).unwrap();
+            replica.put_shard(&object_id, encoder.get_block_info().block_id, &symbols).unwrap();
+        }
+        let objects = Arc::new(MemoryStore::new());
+        objects.open(&manifest).unwrap();
+        for (encoder, region) in block_encoders.iter().zip(BlockRegion::map(&manifest.block_info_vec)) {
+            objects.put_block(&object_id, region.block_id, encoder.get_payload()).unwrap();
+        }
+
+        let known = manifest.clone();
+        let mut repairing = ReadRepairStore::new(store.clone(), move |x| Some(known.clone()).filter(|y| &y.object_id == x));
+        let flip = |block_id: u32| {
+            let path = store.get_path(&object_id, block_id);
+            let mut shard = fs::read(&path).unwrap();
+            let last = shard.len() - 1;
+            shard[last] ^= 1;
+            fs::write(&path, shard).unwrap();
+        };
+
+        // without anything to recover from, the read fails as before
+        flip(0);
+        assert_eq!(repairing.get_shard(&object_id, 0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(repairing.get_stats(), ReadRepairStats { corrupt: 1, repaired: 0, failed: 1 });
+
+        // from the replica, then from the payload once the replica is damaged too
+        repairing.add_replica(replica.clone());
+        repairing.set_object_store(objects);
+        let decode = |symbols: Vec<EncodedBlock>, block_id: u32| {
+            let mut decoder = BlockDecoder::new(manifest.block_info_vec[block_id as usize].clone()).unwrap();
+            decoder.set_symbol_slack(symbols.len());
+            assert!(decoder.consume(symbols).unwrap());
+            return decoder.get_result().unwrap().to_vec();
+        };
+        assert_eq!(decode(repairing.get_shard(&object_id, 0).unwrap(), 0), block_encoders[0].get_payload());
+        assert!(store.get_shard(&object_id, 0).is_ok());
+        fs::write(replica.get_path(&object_id, 1), b"not a shard").unwrap();
+        flip(1);
+        assert_eq!(decode(repairing.get_shard(&object_id, 1).unwrap(), 1), block_encoders[1].get_payload());
+        assert_eq!(repairing.get_stats(), ReadRepairStats { corrupt: 3, repaired: 2, failed: 1 });
+
+        // intact and missing shards are left alone
+        assert!(repairing.get_shard(&object_id, 2).is_ok());
+        fs::remove_file(store.get_path(&object_id, 2)).unwrap();
+        assert_eq!(repairing.get_shard(&object_id, 2).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(repairing.get_stats().corrupt, 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Resuming interrupted fetches. A client persists a small ResumeToken next to the blocks it already put in its
+//! ObjectStore, and later rebuilds its decoder from the token and the store, against the same or other peers,
+//! fetching only the blocks that were not decoded yet. Fetch and MixedFetch do both with save_progress and resume.
+
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::codec::decoder::RaptorQDecoder;
+use crate::codec::encoder::BlockRegion;
+use crate::codec::manifest::{Manifest, ObjectId};
+use super::object_store::ObjectStore;
+
+/// First bytes of a resumption token.
+pub const RESUME_MAGIC: &[u8; 8] = b"RCDNRSM1";
+
+/// How far a fetch got with one block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockProgress {
+    /// Whether the block was decoded and put in the store.
+    pub decoded: bool,
+    /// Distinct symbols received for the block. Symbols of blocks that were not decoded are lost with the decoder,
+    /// so this only tells how far along the block was, e.g. to pick the blocks to ask peers for first.
+    pub symbols_received: u32,
+}
+
+/// What a client needs to resume a fetch: the object, how far each block got and the peers it was fetching from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResumeToken {
+    pub object_id: ObjectId,
+    /// Progress of each block, ordered by block id.
+    pub blocks: Vec<BlockProgress>,
+    /// Peers the fetch was using, e.g. "host:port", to try first when resuming.
+    pub peers: Vec<String>,
+}
+
+impl ResumeToken {
+    /// Records how far a fetch of the object described by manifest got. Only blocks decoded by decoder count as
+    /// decoded, so put them in the store, e.g. with put_decoded_blocks, before persisting the token.
+    pub fn new(manifest: &Manifest, decoder: &RaptorQDecoder, peers: Vec<String>) -> ResumeToken {
+        let blocks = decoder.get_decode_stats().iter().enumerate().map(|(block_id, stats)| BlockProgress {
+            decoded: decoder.get_block_result(block_id as u32).is_some(),
+            symbols_received: stats.source_symbols + stats.repair_symbols,
+        }).collect();
+
+        return ResumeToken {
+            object_id: manifest.object_id,
+            blocks,
+            peers,
+        };
+    }
+
+    /// Gets the number of blocks the fetch decoded.
+    pub fn get_decoded_count(&self) -> usize {
+        return self.blocks.iter().filter(|x| x.decoded).count();
+    }
+
+    /// Rebuilds the decoder of the fetch, reading the blocks it decoded back from store, see restore. Fails with
+    /// ErrorKind::InvalidInput if the token is for another object or a different block count.
+    pub fn resume(&self, manifest: &Manifest, store: &dyn ObjectStore) -> io::Result<RaptorQDecoder> {
+        let mut decoder = match RaptorQDecoder::from_manifest(manifest) {
+            Ok(decoder) => decoder,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad manifest: {:?}", error))),
+        };
+        self.restore(manifest, &mut decoder, store)?;
+        return Ok(decoder);
+    }
+
+    /// Restores the blocks the fetch decoded into decoder, e.g. of a Fetch or MixedFetch picking up where it left
+    /// off, reading them back from store. Returns the ids of the blocks restored. Blocks that are missing from the
+    /// store or don't match their hash in the manifest are left to decode again. Fails with ErrorKind::InvalidInput
+    /// if the token is for another object or a different block count.
+    pub fn restore(&self, manifest: &Manifest, decoder: &mut RaptorQDecoder, store: &dyn ObjectStore) -> io::Result<Vec<u32>> {
+        if self.object_id != manifest.object_id || self.blocks.len() != manifest.get_block_count() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "resumption token is for another object"));
+        }
+
+        let mut restored: Vec<u32> = Vec::new();
+        let regions = BlockRegion::map(&manifest.block_info_vec);
+        for ((region, progress), block_hash) in regions.iter().zip(self.blocks.iter()).zip(manifest.block_hashes.iter()) {
+            if !progress.decoded {
+                continue;
+            }
+            let data = match store.get_range(&self.object_id, region.byte_offset as u64, region.len) {
+                Ok(data) => data,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error),
+            };
+            if Sha256::digest(&data)[..] == block_hash[..] && decoder.restore_block(region.block_id, data).is_ok() {
+                restored.push(region.block_id);
+            }
+        }
+
+        return Ok(restored);
+    }
+
+    /// Writes the token: magic, object id, block count (u32), then for each block a decoded flag (u8) and the
+    /// symbols received (u32), then the peer count (u16) and each peer as its length (u16) and UTF-8 bytes.
+    /// Integers are little endian.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let too_large = |what: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("too many {} for a resumption token", what));
+
+        writer.write_all(RESUME_MAGIC)?;
+        writer.write_all(&self.object_id)?;
+        let block_count = u32::try_from(self.blocks.len()).map_err(|_| too_large("blocks"))?;
+        writer.write_all(&block_count.to_le_bytes())?;
+        for progress in self.blocks.iter() {
+            writer.write_all(&[progress.decoded as u8])?;
+            writer.write_all(&progress.symbols_received.to_le_bytes())?;
+        }
+
+        let peer_count = u16::try_from(self.peers.len()).map_err(|_| too_large("peers"))?;
+        writer.write_all(&peer_count.to_le_bytes())?;
+        for peer in self.peers.iter() {
+            let len = u16::try_from(peer.len()).map_err(|_| too_large("bytes in a peer"))?;
+            writer.write_all(&len.to_le_bytes())?;
+            writer.write_all(peer.as_bytes())?;
+        }
+
+        return writer.flush();
+    }
+
+    /// Reads a token written by write_to. Fails with ErrorKind::InvalidData if it is not a resumption token.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<ResumeToken> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad resumption token: {}", reason));
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != RESUME_MAGIC {
+            return Err(invalid("bad magic"));
+        }
+
+        let mut object_id: ObjectId = [0; 32];
+        reader.read_exact(&mut object_id)?;
+        let block_count = u32::from_le_bytes(read_array(&mut reader)?);
+        let mut blocks: Vec<BlockProgress> = Vec::new();
+        for _ in 0..block_count {
+            let decoded = match read_array(&mut reader)? {
+                [0u8] => false,
+                [1u8] => true,
+                _ => return Err(invalid("bad decoded flag")),
+            };
+            let symbols_received = u32::from_le_bytes(read_array(&mut reader)?);
+            blocks.push(BlockProgress { decoded, symbols_received });
+        }
+
+        let peer_count = u16::from_le_bytes(read_array(&mut reader)?);
+        let mut peers: Vec<String> = Vec::with_capacity(peer_count as usize);
+        for _ in 0..peer_count {
+            let mut peer = vec![0u8; u16::from_le_bytes(read_array(&mut reader)?) as usize];
+            reader.read_exact(&mut peer)?;
+            match String::from_utf8(peer) {
+                Ok(peer) => peers.push(peer),
+                Err(_) => return Err(invalid("peer is not UTF-8")),
+            }
+        }
+
+        return Ok(ResumeToken { object_id, blocks, peers });
+    }
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut array = [0u8; N];
+    reader.read_exact(&mut array)?;
+    return Ok(array);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::{BlockEncoder, EncoderConfig};
+    use crate::store::memory::MemoryStore;
+    use crate::store::object_store::put_decoded_blocks;
+
+    #[test]
+    fn test_resume_token() {
+        let data = gen_data(90 * 1000);
+        let encoders: Vec<BlockEncoder> = data.chunks(30 * 1000).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, 1280, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let manifest = Manifest {
+            object_id: Sha256::digest(&data).into(),
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(1280),
+            block_info_vec: encoders.iter().map(|x| x.get_block_info()).collect(),
+            block_hashes: encoders.iter().map(|x| Sha256::digest(x.get_payload()).into()).collect(),
+            block_overheads: vec![0; encoders.len()],
+            envelope: None,
+        };
+
+        // the fetch decodes the first and last blocks and gets a few symbols of the middle one before it stops
+        let store = MemoryStore::new();
+        store.open(&manifest).unwrap();
+        let mut decoder = RaptorQDecoder::from_manifest(&manifest).unwrap();
+        decoder.consume(encoders[0].generate_source_blocks()).unwrap();
+        decoder.consume(encoders[1].generate_source_blocks()[..5].to_vec()).unwrap();
+        decoder.consume(encoders[2].generate_source_blocks()).unwrap();
+        put_decoded_blocks(&store, &manifest.object_id, &decoder).unwrap();
+
+        let token = ResumeToken::new(&manifest, &decoder, vec!["10.0.0.1:8080".to_string()]);
+        assert_eq!(token.get_decoded_count(), 2);
+        assert_eq!(token.blocks[1], BlockProgress { decoded: false, symbols_received: 5 });
+        let mut written: Vec<u8> = Vec::new();
+        token.write_to(&mut written).unwrap();
+        let token = ResumeToken::read_from(&written[..]).unwrap();
+        assert_eq!(token.peers, vec!["10.0.0.1:8080".to_string()]);
+
+        // resuming needs only the middle block
+        let mut resumed = token.resume(&manifest, &store).unwrap();
+        assert_eq!(resumed.get_block_needs().iter().map(|x| x.block_id).collect::<Vec<u32>>(), vec![1]);
+        assert!(resumed.consume(encoders[1].generate_source_blocks()).unwrap());
+        assert_eq!(resumed.get_result(), Some(data));
+
+        // a block that doesn't match its hash is decoded again
+        let mut tampered = manifest.clone();
+        tampered.block_hashes[0] = [0; 32];
+        let resumed = token.resume(&tampered, &store).unwrap();
+        assert_eq!(resumed.get_block_needs().len(), 2);
+
+        match ResumeToken::read_from(&written[1..]) {
+            Ok(_) => panic!("Should have failed to read a token without magic"),
+            Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidData),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::io;
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+
+use crate::codec::manifest::{Manifest, ObjectId};
+use super::object_store::ObjectStore;
+
+/// Gets the key an object of a tenant is stored under: a SHA-256 of the tenant's name, length prefixed, and the
+/// object id. Tenants storing the same content get different keys, so none can read, overwrite or delete another's.
+pub fn get_tenant_key(tenant: &str, object_id: &ObjectId) -> ObjectId {
+    let mut hasher = Sha256::new();
+    hasher.update((tenant.len() as u64).to_le_bytes());
+    hasher.update(tenant.as_bytes());
+    hasher.update(object_id);
+    return hasher.finalize().into();
+}
+
+/// A tenant's view of a store shared by several tenants. Objects are stored under get_tenant_key rather than their
+/// id, so each tenant only ever sees the objects it stored itself.
+pub struct TenantStore {
+    tenant: String,
+    store: Arc<dyn ObjectStore>,
+}
+
+impl TenantStore {
+    pub fn new(tenant: &str, store: Arc<dyn ObjectStore>) -> TenantStore {
+        return TenantStore { tenant: tenant.to_string(), store };
+    }
+
+    pub fn get_tenant(&self) -> &str {
+        return &self.tenant;
+    }
+
+    fn key(&self, object_id: &ObjectId) -> ObjectId {
+        return get_tenant_key(&self.tenant, object_id);
+    }
+}
+
+impl ObjectStore for TenantStore {
+    fn open(&self, manifest: &Manifest) -> io::Result<()> {
+        let mut manifest = manifest.clone();
+        manifest.object_id = self.key(&manifest.object_id);
+        return self.store.open(&manifest);
+    }
+
+    fn put_block(&self, object_id: &ObjectId, block_id: u32, data: &[u8]) -> io::Result<()> {
+        return self.store.put_block(&self.key(object_id), block_id, data);
+    }
+
+    fn get_range(&self, object_id: &ObjectId, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        return self.store.get_range(&self.key(object_id), offset, len);
+    }
+
+    fn has_block(&self, object_id: &ObjectId, block_id: u32) -> io::Result<bool> {
+        return self.store.has_block(&self.key(object_id), block_id);
+    }
+
+    fn finalize(&self, object_id: &ObjectId) -> io::Result<()> {
+        return self.store.finalize(&self.key(object_id));
+    }
+
+    fn delete(&self, object_id: &ObjectId) -> io::Result<bool> {
+        return self.store.delete(&self.key(object_id));
+    }
+
+    fn get_available_space(&self) -> io::Result<Option<u64>> {
+        return self.store.get_available_space();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::memory::MemoryStore;
+    use super::super::object_store::put_decoded_blocks;
+    use crate::codec::decoder::RaptorQDecoder;
+    use crate::codec::encoder::RaptorQEncoder;
+
+    #[test]
+    fn test_tenant_store() {
+        let data = vec![7; 10 * 1000];
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let manifest = Manifest::new(&encoder);
+        let object_id = manifest.object_id;
+        let mut decoder = RaptorQDecoder::from_manifest(&manifest).unwrap();
+        decoder.consume(encoder.get_block_encoders()[0].generate_source_blocks()).unwrap();
+
+        let shared: Arc<dyn ObjectStore> = Arc::new(MemoryStore::new());
+        let a = TenantStore::new("a", shared.clone());
+        let b = TenantStore::new("b", shared.clone());
+        a.open(&manifest).unwrap();
+        assert!(put_decoded_blocks(&a, &object_id, &decoder).unwrap());
+        assert_eq!(a.get_range(&object_id, 0, data.len()).unwrap(), data);
+
+        // the same object id is another object to tenant b, and to the store itself
+        assert_eq!(b.get_range(&object_id, 0, 1).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert!(!b.delete(&object_id).unwrap());
+        assert_eq!(shared.get_range(&object_id, 0, 1).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(shared.get_range(&get_tenant_key("a", &object_id), 0, data.len()).unwrap(), data);
+        assert_ne!(get_tenant_key("a", &object_id), get_tenant_key("b", &object_id));
+
+        assert!(a.delete(&object_id).unwrap());
+    }
+}
@@ -0,0 +1,82 @@
+//! Warm-starting fetches from a local copy of an object, e.g. a partial download or an older version of the same
+//! file. Blocks of the copy that match their hash in the manifest are restored straight into the decoder, so only
+//! the missing and changed blocks need symbols from the network.
+
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::codec::decoder::RaptorQDecoder;
+use crate::codec::encoder::BlockRegion;
+use crate::codec::manifest::Manifest;
+
+/// Restores every block of the object described by manifest that local holds at the block's offset and matches the
+/// block's hash, returning the ids of the blocks restored. Blocks already decoded are skipped, and a copy shorter
+/// than the object only contributes the blocks it holds whole. Only blocks at their own offset are found, so an
+/// older version with bytes inserted or removed contributes the blocks before the change.
+pub fn warm_start<R: Read + Seek>(manifest: &Manifest, decoder: &mut RaptorQDecoder, mut local: R) -> io::Result<Vec<u32>> {
+    let mut restored: Vec<u32> = Vec::new();
+    let regions = BlockRegion::map(&manifest.block_info_vec);
+    for (region, block_hash) in regions.iter().zip(manifest.block_hashes.iter()) {
+        if decoder.get_block_result(region.block_id).is_some() {
+            continue;
+        }
+
+        let mut data: Vec<u8> = vec![0; region.len];
+        local.seek(SeekFrom::Start(region.byte_offset as u64))?;
+        match local.read_exact(&mut data) {
+            Ok(()) => (),
+            // blocks are in file order, so none of the following ones are there either
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error),
+        }
+        if Sha256::digest(&data)[..] == block_hash[..] {
+            if decoder.restore_block(region.block_id, data).is_err() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "decoder is for another object"));
+            }
+            restored.push(region.block_id);
+        }
+    }
+
+    return Ok(restored);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::{BlockEncoder, EncoderConfig};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_warm_start() {
+        let data = gen_data(120 * 1000);
+        let encoders: Vec<BlockEncoder> = data.chunks(30 * 1000).enumerate().map(|(i, chunk)| {
+            match BlockEncoder::new(i as u32, 1280, chunk.to_vec()) {
+                Ok(succ) => succ,
+                Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+            }
+        }).collect();
+        let manifest = Manifest {
+            object_id: Sha256::digest(&data).into(),
+            data_size: data.len() as u64,
+            config: EncoderConfig::new(1280),
+            block_info_vec: encoders.iter().map(|x| x.get_block_info()).collect(),
+            block_hashes: encoders.iter().map(|x| Sha256::digest(x.get_payload()).into()).collect(),
+            block_overheads: vec![0; encoders.len()],
+            envelope: None,
+        };
+
+        // an older copy with the second block changed, cut off halfway through the last block
+        let mut local = data[..105 * 1000].to_vec();
+        local[40 * 1000] ^= 1;
+        let mut decoder = RaptorQDecoder::from_manifest(&manifest).unwrap();
+        assert_eq!(warm_start(&manifest, &mut decoder, Cursor::new(&local)).unwrap(), vec![0, 2]);
+        assert_eq!(decoder.get_block_needs().iter().map(|x| x.block_id).collect::<Vec<u32>>(), vec![1, 3]);
+
+        // only the blocks left need symbols, and a second pass skips the restored ones
+        decoder.consume(encoders[1].generate_source_blocks()).unwrap();
+        assert!(warm_start(&manifest, &mut decoder, Cursor::new(&local)).unwrap().is_empty());
+        assert!(decoder.consume(encoders[3].generate_source_blocks()).unwrap());
+        assert_eq!(decoder.get_result(), Some(data));
+    }
+}
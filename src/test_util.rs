@@ -0,0 +1,12 @@
+//! Helpers shared by the unit tests.
+
+use rand::Rng;
+
+/// Generates len random bytes.
+pub(crate) fn gen_data(len: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::with_capacity(len);
+    for _ in 0..len {
+        data.push(rand::thread_rng().gen());
+    }
+    return data;
+}
@@ -0,0 +1,433 @@
+//! Continuous broadcast of a set of objects over a one-way link, e.g. satellite, where receivers tune in at
+//! arbitrary times and can't ask for anything. The carousel cycles repair symbols of every object forever, so a
+//! receiver that joins late just keeps listening until it has enough symbols of each block, whichever they are.
+//!
+//! Objects share the link by weight among those of the highest priority present. Priorities are strict: lower
+//! ones get nothing while a higher priority object is in the carousel, e.g. an urgent bulletin preempts the regular
+//! schedule until it is removed. Weights and priorities can be changed between datagrams.
+//!
+//! Objects given a manifest with set_manifest or set_manifest_block repeat it in-band among their symbols, so
+//! receivers with a FlowDemux set to join in progress need no side channel at all.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+
+use crate::codec::encoder::{BlockEncoder, EncodedBlock, RaptorQEncoder};
+use crate::codec::manifest::Manifest;
+use crate::codec::producer::{SessionId, SessionStats, SymbolProducer};
+use super::queue::{SendQueue, TrafficClass};
+use super::udp::{encode_datagram, encode_manifest_datagram, FlowId, Integrity};
+
+/// Symbols of a block generated at a time, unless set with set_burst.
+const DEFAULT_BURST: usize = 16;
+
+/// Symbols of an object sent between repeats of its manifest, unless set with set_manifest_interval.
+const DEFAULT_MANIFEST_INTERVAL: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CarouselError {
+    /// The flow is not in the carousel.
+    UnknownFlow,
+    /// The manifest does not fit in a datagram, or a block for set_manifest_block, or can't be written, e.g. its
+    /// envelope has too many recipients.
+    ManifestTooLarge,
+    /// The object has no blocks, i.e. is empty, so there are no symbols to cycle.
+    EmptyObject,
+}
+
+/// How an object's manifest is repeated among its symbols.
+enum InBandManifest {
+    /// Whole, in a manifest datagram.
+    Datagram(Vec<u8>),
+    /// As repair symbols of the manifest's block, and the next repair symbol to send.
+    Block(Box<BlockEncoder>, u32),
+}
+
+struct CarouselObject {
+    producer: SymbolProducer,
+    session_id: SessionId,
+    weight: u32,
+    priority: u8,
+    /// Smooth weighted round robin credit among the objects of the same priority.
+    credit: i64,
+    /// Block the next burst is generated for, taking blocks in turn.
+    next_block_id: u32,
+    /// Symbols generated and not sent yet.
+    pending: VecDeque<EncodedBlock>,
+    sent: u64,
+    /// Manifest repeated among the symbols, and the symbols sent since it was last sent.
+    manifest: Option<InBandManifest>,
+    since_manifest: u64,
+}
+
+impl CarouselObject {
+    /// Takes the next symbol, or None if the producer refused to generate more.
+    fn next_symbol(&mut self, burst: usize) -> Option<EncodedBlock> {
+        if self.pending.is_empty() {
+            // the producer wraps around once every repair symbol id was sent, counting the symbols sent again
+            let block_count = self.producer.get_encoder().get_block_encoders().len() as u32;
+            let blocks = self.producer.next_block_symbols(self.session_id, self.next_block_id, burst).ok()?;
+            self.pending.extend(blocks);
+            self.next_block_id = (self.next_block_id + 1) % block_count;
+        }
+        self.sent += 1;
+        return self.pending.pop_front();
+    }
+
+    fn next_manifest_datagram(&mut self, flow_id: FlowId, integrity: Integrity) -> Option<Vec<u8>> {
+        match self.manifest.as_mut()? {
+            InBandManifest::Datagram(datagram) => return Some(datagram.clone()),
+            InBandManifest::Block(block_encoder, cursor) => {
+                let block = block_encoder.generate_repair_blocks(*cursor, 1).remove(0);
+                *cursor = ((*cursor as usize + 1) % block_encoder.get_repair_symbol_id_limit()) as u32;
+                return Some(encode_datagram(flow_id, &block, integrity));
+            },
+        }
+    }
+}
+
+/// Cycles symbols of a set of objects, see the module documentation. Each object is a flow, so receivers route its
+/// datagrams with a FlowDemux.
+pub struct Carousel {
+    objects: BTreeMap<FlowId, CarouselObject>,
+    integrity: Integrity,
+    burst: usize,
+    manifest_interval: u64,
+}
+
+impl Carousel {
+    pub fn new(integrity: Integrity) -> Carousel {
+        return Carousel {
+            objects: BTreeMap::new(),
+            integrity,
+            burst: DEFAULT_BURST,
+            manifest_interval: DEFAULT_MANIFEST_INTERVAL,
+        };
+    }
+
+    /// Sets how many symbols of a block are sent in a row before moving on to the object's next block. Larger
+    /// bursts generate symbols more efficiently, smaller ones spread a loss burst over more blocks. Panics if burst
+    /// is zero.
+    pub fn set_burst(&mut self, burst: usize) {
+        assert!(burst > 0, "burst must be positive");
+        self.burst = burst;
+    }
+
+    /// Adds an object to the carousel as flow flow_id, replacing any object of the same flow. Fails with
+    /// EmptyObject for an object without blocks, whose receivers have nothing to wait for. Panics if weight is zero.
+    pub fn add(&mut self, flow_id: FlowId, encoder: RaptorQEncoder, weight: u32, priority: u8) -> Result<(), CarouselError> {
+        assert!(weight > 0, "weight must be positive");
+        if encoder.get_block_encoders().is_empty() {
+            return Err(CarouselError::EmptyObject);
+        }
+        let mut producer = SymbolProducer::new(encoder);
+        let session_id = producer.open_session();
+        self.objects.insert(flow_id, CarouselObject {
+            producer,
+            session_id,
+            weight,
+            priority,
+            credit: 0,
+            next_block_id: 0,
+            pending: VecDeque::new(),
+            sent: 0,
+            manifest: None,
+            since_manifest: 0,
+        });
+        return Ok(());
+    }
+
+    /// Sets how many symbols of an object are sent between repeats of its manifest, or between symbols of its
+    /// manifest block. Joiners wait for a manifest before they can decode, but buffer symbols meanwhile, so this
+    /// mostly bounds their buffers. Panics if interval is zero.
+    pub fn set_manifest_interval(&mut self, interval: u64) {
+        assert!(interval > 0, "manifest interval must be positive");
+        self.manifest_interval = interval;
+    }
+
+    /// Repeats manifest in-band among the symbols of an object, starting with its next datagram.
+    pub fn set_manifest(&mut self, flow_id: FlowId, manifest: &Manifest) -> Result<(), CarouselError> {
+        let object = match self.objects.get_mut(&flow_id) {
+            None => return Err(CarouselError::UnknownFlow),
+            Some(object) => object,
+        };
+        match encode_manifest_datagram(flow_id, manifest, self.integrity) {
+            Err(_) => return Err(CarouselError::ManifestTooLarge),
+            Ok(datagram) => object.manifest = Some(InBandManifest::Datagram(datagram)),
+        }
+        object.since_manifest = u64::MAX;
+        return Ok(());
+    }
+
+    /// Repeats manifest in-band as symbols of its block, see Manifest::encode_block, starting with the object's
+    /// next datagram. Unlike set_manifest, a lost datagram costs the receiver one symbol, not a whole manifest, and
+    /// manifests too large for a datagram can be sent, but receivers need several symbols to decode it.
+    pub fn set_manifest_block(&mut self, flow_id: FlowId, manifest: &Manifest) -> Result<(), CarouselError> {
+        let object = match self.objects.get_mut(&flow_id) {
+            None => return Err(CarouselError::UnknownFlow),
+            Some(object) => object,
+        };
+        match manifest.encode_block(object.producer.get_encoder().get_packet_size()) {
+            Err(_) => return Err(CarouselError::ManifestTooLarge),
+            Ok(block_encoder) => object.manifest = Some(InBandManifest::Block(Box::new(block_encoder), 0)),
+        }
+        object.since_manifest = u64::MAX;
+        return Ok(());
+    }
+
+    /// Removes an object from the carousel, returning its encoder.
+    pub fn remove(&mut self, flow_id: FlowId) -> Option<RaptorQEncoder> {
+        return self.objects.remove(&flow_id).map(|x| x.producer.get_encoder().clone());
+    }
+
+    /// Sets the share of an object among those of its priority. Returns false if the object is not in the
+    /// carousel. Panics if weight is zero.
+    pub fn set_weight(&mut self, flow_id: FlowId, weight: u32) -> bool {
+        assert!(weight > 0, "weight must be positive");
+        match self.objects.get_mut(&flow_id) {
+            None => return false,
+            Some(object) => {
+                object.weight = weight;
+                return true;
+            },
+        }
+    }
+
+    /// Moves an object to another priority, where it starts with no credit. Returns false if the object is not in
+    /// the carousel.
+    pub fn set_priority(&mut self, flow_id: FlowId, priority: u8) -> bool {
+        match self.objects.get_mut(&flow_id) {
+            None => return false,
+            Some(object) => {
+                object.priority = priority;
+                object.credit = 0;
+                return true;
+            },
+        }
+    }
+
+    /// Takes the next datagram to broadcast and the flow it belongs to, or None if the carousel is empty or the
+    /// object whose turn it is produced no symbol.
+    pub fn next_datagram(&mut self) -> Option<(FlowId, Vec<u8>)> {
+        let priority = self.objects.values().map(|x| x.priority).max()?;
+
+        // smooth weighted round robin: every object earns its weight, the richest sends and pays the total
+        let mut total_weight: i64 = 0;
+        let mut selected: Option<(FlowId, i64)> = None;
+        for (flow_id, object) in self.objects.iter_mut().filter(|(_, x)| x.priority == priority) {
+            object.credit += object.weight as i64;
+            total_weight += object.weight as i64;
+            if selected.is_none_or(|(_, credit)| object.credit > credit) {
+                selected = Some((*flow_id, object.credit));
+            }
+        }
+
+        let (flow_id, _) = selected?;
+        let object = self.objects.get_mut(&flow_id).unwrap();
+        object.credit -= total_weight;
+        if object.since_manifest >= self.manifest_interval {
+            if let Some(datagram) = object.next_manifest_datagram(flow_id, self.integrity) {
+                object.since_manifest = 0;
+                return Some((flow_id, datagram));
+            }
+        }
+        let block = object.next_symbol(self.burst)?;
+        object.since_manifest = object.since_manifest.saturating_add(1);
+        return Some((flow_id, encode_datagram(flow_id, &block, self.integrity)));
+    }
+
+    /// Queues count datagrams as bulk traffic to addr, e.g. a multicast group, returning how many were queued,
+    /// fewer only if the carousel is empty.
+    pub fn fill(&mut self, queue: &mut SendQueue, addr: SocketAddr, count: usize) -> usize {
+        for queued in 0..count {
+            match self.next_datagram() {
+                None => return queued,
+                Some((_, datagram)) => queue.push(TrafficClass::Bulk, addr, datagram),
+            }
+        }
+        return count;
+    }
+
+    /// Gets the symbols of an object sent so far, not counting its manifest, or None if it is not in the carousel.
+    pub fn get_sent(&self, flow_id: FlowId) -> Option<u64> {
+        return self.objects.get(&flow_id).map(|x| x.sent);
+    }
+
+    /// Gets the session stats of an object's producer, whose reused_symbols counts symbols sent again once every
+    /// repair symbol id of their block was sent, or None if it is not in the carousel. Receivers gain nothing from
+    /// reused symbols, so a rising count means the object has been cycled long enough.
+    pub fn get_session_stats(&self, flow_id: FlowId) -> Option<SessionStats> {
+        return self.objects.get(&flow_id).map(|x| x.producer.get_session_stats());
+    }
+
+    pub fn get_flows(&self) -> Vec<FlowId> {
+        return self.objects.keys().copied().collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::decoder::RaptorQDecoder;
+    use crate::codec::envelope::{Envelope, WrappedKey, NONCE_SIZE, RECIPIENT_KEY_SIZE};
+    use crate::codec::manifest::DecoderLimits;
+    use crate::transport::udp::FlowDemux;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_carousel() {
+        let data: Vec<Vec<u8>> = vec![gen_data(60 * 1000), gen_data(20 * 1000), gen_data(5 * 1000)];
+        let encoders: Vec<RaptorQEncoder> = data.iter().map(|x| match RaptorQEncoder::new(1280, x) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        }).collect();
+
+        let mut carousel = Carousel::new(Integrity::None);
+        carousel.add(0, encoders[0].clone(), 3, 0).unwrap();
+        carousel.add(1, encoders[1].clone(), 1, 0).unwrap();
+
+        // the carousel ran for a while before the receiver tuned in
+        for _ in 0..100 {
+            carousel.next_datagram().unwrap();
+        }
+        assert_eq!((carousel.get_sent(0), carousel.get_sent(1)), (Some(75), Some(25)));
+
+        let start = Instant::now();
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        for (flow_id, encoder) in encoders.iter().enumerate() {
+            demux.register(flow_id as FlowId, RaptorQDecoder::new(encoder.get_block_info_vec()).unwrap(), start);
+        }
+
+        // an urgent object preempts the others until removed, then the schedule carries on
+        carousel.add(2, encoders[2].clone(), 1, 1).unwrap();
+        for _ in 0..10 {
+            let (flow_id, datagram) = carousel.next_datagram().unwrap();
+            assert_eq!(flow_id, 2);
+            demux.receive(&datagram, start);
+        }
+        assert!(carousel.remove(2).is_some());
+        assert!(!carousel.set_weight(2, 1));
+
+        // weights change live, and every object decodes from whatever symbols came after tuning in
+        assert!(carousel.set_weight(1, 3));
+        let mut sent = [0; 2];
+        for _ in 0..160 {
+            let (flow_id, datagram) = carousel.next_datagram().unwrap();
+            sent[flow_id as usize] += 1;
+            demux.receive(&datagram, start);
+        }
+        assert_eq!(sent, [80, 80]);
+        assert_eq!(carousel.get_session_stats(0).map(|x| (x.opened, x.reused_symbols)), Some((1, 0)));
+        assert_eq!(carousel.get_session_stats(2), None);
+        for (flow_id, flow_data) in data.iter().enumerate() {
+            assert_eq!(demux.get_decoder(flow_id as FlowId).unwrap().get_result().as_ref(), Some(flow_data));
+        }
+
+        let mut queue = SendQueue::new();
+        assert_eq!(carousel.fill(&mut queue, "127.0.0.1:9".parse().unwrap(), 5), 5);
+        assert_eq!(queue.get_queued(TrafficClass::Bulk), 5);
+        carousel.remove(0);
+        carousel.remove(1);
+        assert_eq!(carousel.fill(&mut queue, "127.0.0.1:9".parse().unwrap(), 5), 0);
+    }
+
+    #[test]
+    fn test_carousel_join_in_progress() {
+        let data = gen_data(30 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut carousel = Carousel::new(Integrity::Crc32c);
+        carousel.add(5, encoder.clone(), 1, 0).unwrap();
+        carousel.set_manifest_interval(30);
+        assert_eq!(carousel.set_manifest(5, &Manifest::new(&encoder)), Ok(()));
+        assert_eq!(carousel.set_manifest(6, &Manifest::new(&encoder)), Err(CarouselError::UnknownFlow));
+
+        // the receiver tunes in just after a manifest and buffers what it gets until the next one, too few symbols
+        // to decode, so it decodes from the symbols after
+        let datagrams: Vec<Vec<u8>> = (0..70).map(|_| carousel.next_datagram().unwrap().1).collect();
+        let start = Instant::now();
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        demux.set_join_in_progress(20, DecoderLimits::default());
+        let mut decoded_at: Option<usize> = None;
+        for (index, datagram) in datagrams.iter().enumerate().skip(1) {
+            if demux.receive(datagram, start) == Some((5, true)) && decoded_at.is_none() {
+                decoded_at = Some(index);
+            }
+        }
+        assert!(decoded_at.is_some_and(|x| x > 31));
+        assert_eq!(demux.get_decoder(5).unwrap().get_result(), Some(data));
+        let stats = demux.get_stats();
+        assert_eq!((stats.joined, stats.buffered, stats.buffer_dropped), (1, 30, 10));
+
+        // without join in progress, both manifests and symbols of unknown flows are dropped
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        for datagram in datagrams.iter() {
+            assert!(demux.receive(datagram, start).is_none());
+        }
+        assert_eq!(demux.get_stats().unknown_flow, 70);
+    }
+
+    #[test]
+    fn test_carousel_manifest_block() {
+        let data = gen_data(30 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut carousel = Carousel::new(Integrity::None);
+        carousel.add(5, encoder.clone(), 1, 0).unwrap();
+        carousel.set_manifest_interval(2);
+        assert_eq!(carousel.set_manifest_block(5, &Manifest::new(&encoder)), Ok(()));
+
+        // every third datagram is a manifest symbol, and a third of them are lost on the way
+        let start = Instant::now();
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        demux.set_join_in_progress(100, DecoderLimits::default());
+        for index in 0..90 {
+            let (_, datagram) = carousel.next_datagram().unwrap();
+            if index % 9 != 0 {
+                demux.receive(&datagram, start);
+            }
+        }
+        assert_eq!(demux.get_decoder(5).unwrap().get_result(), Some(data));
+        let stats = demux.get_stats();
+        assert_eq!(stats.joined, 1);
+        assert!(stats.manifest_symbols > 0);
+    }
+
+    #[test]
+    fn test_carousel_unwritable_manifest() {
+        let data = gen_data(30 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let mut carousel = Carousel::new(Integrity::None);
+        carousel.add(5, encoder.clone(), 1, 0).unwrap();
+
+        // a manifest write_to refuses is reported, rather than aborting the sender
+        let wrapped_key = WrappedKey { recipient: [1; RECIPIENT_KEY_SIZE], ephemeral: [2; RECIPIENT_KEY_SIZE], wrapped_key: vec![3; 5] };
+        let mut manifest = Manifest::new(&encoder);
+        manifest.envelope = Some(Envelope { nonce: [0; NONCE_SIZE], wrapped_keys: vec![wrapped_key] });
+        assert_eq!(carousel.set_manifest(5, &manifest), Err(CarouselError::ManifestTooLarge));
+        assert_eq!(carousel.set_manifest_block(5, &manifest), Err(CarouselError::ManifestTooLarge));
+    }
+
+    #[test]
+    fn test_carousel_empty_object() {
+        let encoder = match RaptorQEncoder::new(1280, &[]) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        assert!(encoder.get_block_encoders().is_empty());
+
+        // an empty object is refused, rather than making next_datagram panic on a flow without symbols
+        let mut carousel = Carousel::new(Integrity::None);
+        assert_eq!(carousel.add(1, encoder, 1, 0), Err(CarouselError::EmptyObject));
+        assert!(carousel.get_flows().is_empty());
+        assert_eq!(carousel.next_datagram(), None);
+    }
+}
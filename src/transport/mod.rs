@@ -0,0 +1,8 @@
+pub mod carousel;
+pub mod net;
+pub mod queue;
+pub mod udp;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+pub mod uring;
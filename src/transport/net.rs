@@ -0,0 +1,259 @@
+//! IPv6 and dual-stack sockets. Listeners take an AddressFamily, so one can be IPv4 only, IPv6 only, or an IPv6
+//! socket taking IPv4 clients as well, whatever the system's default. Dialing TCP follows happy eyeballs (RFC 8305):
+//! the addresses a name resolves to are tried alternating between families, each attempt starting shortly after
+//! the previous one unless it failed sooner, and the first connection made wins, so a host with a broken IPv6 path
+//! costs a short delay rather than a timeout.
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long a connection attempt has before the next starts, per RFC 8305.
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Which addresses a listener takes clients on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AddressFamily {
+    /// Whatever the address is, with the system's default for IPv6 sockets.
+    #[default]
+    Any,
+    /// IPv4 addresses only.
+    V4,
+    /// IPv6 addresses only, the socket taking no IPv4 clients.
+    V6,
+    /// An IPv6 socket taking IPv4 clients as well, as IPv4-mapped addresses, e.g. on [::]:8080.
+    Dual,
+}
+
+impl FromStr for AddressFamily {
+    type Err = String;
+
+    /// Parses any, ipv4, ipv6 or dual.
+    fn from_str(family: &str) -> Result<AddressFamily, String> {
+        match family {
+            "any" => return Ok(AddressFamily::Any),
+            "ipv4" => return Ok(AddressFamily::V4),
+            "ipv6" => return Ok(AddressFamily::V6),
+            "dual" => return Ok(AddressFamily::Dual),
+            _ => return Err(format!("unknown address family {}, expected any, ipv4, ipv6 or dual", family)),
+        }
+    }
+}
+
+/// Gets the first address addr resolves to that family can listen on. Fails with ErrorKind::InvalidInput if there
+/// is none.
+fn resolve_for<A: ToSocketAddrs>(addr: A, family: AddressFamily) -> io::Result<SocketAddr> {
+    let found = addr.to_socket_addrs()?.find(|x| match family {
+        AddressFamily::Any => true,
+        AddressFamily::V4 => x.is_ipv4(),
+        AddressFamily::V6 | AddressFamily::Dual => x.is_ipv6(),
+    });
+    return found.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("no {:?} address to listen on", family)));
+}
+
+/// Creates an IPv6 socket of socket_type bound to addr, taking IPv4 clients unless v6_only, with SO_REUSEADDR as
+/// std sets it on listeners.
+#[cfg(unix)]
+fn bind_v6(addr: std::net::SocketAddrV6, v6_only: bool, socket_type: libc::c_int) -> io::Result<std::os::fd::OwnedFd> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    let fd = unsafe { libc::socket(libc::AF_INET6, socket_type, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let set_option = |level: libc::c_int, name: libc::c_int, value: libc::c_int| {
+        let value_size = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        if unsafe { libc::setsockopt(socket.as_raw_fd(), level, name, &value as *const libc::c_int as *const libc::c_void, value_size) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(());
+    };
+    if unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if socket_type == libc::SOCK_STREAM {
+        set_option(libc::SOL_SOCKET, libc::SO_REUSEADDR, 1)?;
+    }
+    set_option(libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, v6_only as libc::c_int)?;
+
+    let mut sockaddr: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+    sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+    sockaddr.sin6_port = addr.port().to_be();
+    sockaddr.sin6_flowinfo = addr.flowinfo();
+    sockaddr.sin6_addr.s6_addr = addr.ip().octets();
+    sockaddr.sin6_scope_id = addr.scope_id();
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd"))]
+    {
+        sockaddr.sin6_len = std::mem::size_of::<libc::sockaddr_in6>() as u8;
+    }
+    let sockaddr_size = std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+    if unsafe { libc::bind(socket.as_raw_fd(), &sockaddr as *const libc::sockaddr_in6 as *const libc::sockaddr, sockaddr_size) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if socket_type == libc::SOCK_STREAM && unsafe { libc::listen(socket.as_raw_fd(), 128) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(socket);
+}
+
+/// Binds a TCP listener to the first address addr resolves to that family can listen on. V6 and Dual are only
+/// available on UNIX, elsewhere they fail with ErrorKind::Unsupported.
+pub fn bind_tcp<A: ToSocketAddrs>(addr: A, family: AddressFamily) -> io::Result<TcpListener> {
+    let addr = resolve_for(addr, family)?;
+    match (family, addr) {
+        #[cfg(unix)]
+        (AddressFamily::V6 | AddressFamily::Dual, SocketAddr::V6(addr)) => {
+            return Ok(TcpListener::from(bind_v6(addr, family == AddressFamily::V6, libc::SOCK_STREAM)?));
+        },
+        #[cfg(not(unix))]
+        (AddressFamily::V6 | AddressFamily::Dual, _) => return Err(io::Error::new(io::ErrorKind::Unsupported, "address families need UNIX")),
+        _ => return TcpListener::bind(addr),
+    }
+}
+
+/// Binds a UDP socket to the first address addr resolves to that family can listen on, as bind_tcp does. Send to
+/// IPv4 peers from a Dual socket through map_addr.
+pub fn bind_udp<A: ToSocketAddrs>(addr: A, family: AddressFamily) -> io::Result<UdpSocket> {
+    let addr = resolve_for(addr, family)?;
+    match (family, addr) {
+        #[cfg(unix)]
+        (AddressFamily::V6 | AddressFamily::Dual, SocketAddr::V6(addr)) => {
+            return Ok(UdpSocket::from(bind_v6(addr, family == AddressFamily::V6, libc::SOCK_DGRAM)?));
+        },
+        #[cfg(not(unix))]
+        (AddressFamily::V6 | AddressFamily::Dual, _) => return Err(io::Error::new(io::ErrorKind::Unsupported, "address families need UNIX")),
+        _ => return UdpSocket::bind(addr),
+    }
+}
+
+/// Gets the address to send to addr at from a socket bound to local: IPv4 addresses are mapped into IPv6 for IPv6
+/// sockets, which is how dual-stack sockets reach IPv4 peers. Other addresses are returned as they are.
+pub fn map_addr(addr: SocketAddr, local: SocketAddr) -> SocketAddr {
+    match (addr, local) {
+        (SocketAddr::V4(v4), SocketAddr::V6(_)) => return SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port()),
+        _ => return addr,
+    }
+}
+
+/// Gets the address a peer is known by: IPv4-mapped IPv6 addresses, as dual-stack sockets report IPv4 peers, are
+/// turned back into IPv4, so a peer has the same name whichever socket it reached.
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    return SocketAddr::new(addr.ip().to_canonical(), addr.port());
+}
+
+/// Orders addresses for dialing per RFC 8305: alternating between families, starting with the family of the first,
+/// which resolvers put first by preference, each family keeping its order.
+pub fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(|x| x.is_ipv6());
+    let (mut first, mut second): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs.into_iter().partition(|x| x.is_ipv6() == first_v6);
+    let mut ordered: Vec<SocketAddr> = Vec::with_capacity(first.len() + second.len());
+    first.reverse();
+    second.reverse();
+    while !first.is_empty() || !second.is_empty() {
+        ordered.extend(first.pop());
+        ordered.extend(second.pop());
+    }
+    return ordered;
+}
+
+/// Connects to addr, trying the addresses it resolves to as the module documentation describes, each attempt for
+/// at most timeout. Fails with the error of the last attempt if none connects.
+pub fn connect_tcp<A: ToSocketAddrs>(addr: A, timeout: Duration) -> io::Result<TcpStream> {
+    let addrs = interleave_families(addr.to_socket_addrs()?.collect());
+    if addrs.len() == 1 {
+        return TcpStream::connect_timeout(&addrs[0], timeout);
+    }
+
+    let (sender, receiver) = mpsc::channel::<io::Result<TcpStream>>();
+    let mut pending: usize = 0;
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing");
+    for addr in addrs.into_iter() {
+        let sender = sender.clone();
+        // an attempt that loses the race drops its connection as the send fails
+        std::thread::spawn(move || sender.send(TcpStream::connect_timeout(&addr, timeout)));
+        pending += 1;
+
+        // wait out the attempt delay, or less if an attempt fails sooner, which starts the next at once
+        match receiver.recv_timeout(CONNECTION_ATTEMPT_DELAY) {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(error)) => {
+                pending -= 1;
+                last_error = error;
+            },
+            Err(_) => {},
+        }
+    }
+
+    while pending > 0 {
+        match receiver.recv() {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(error)) => last_error = error,
+            Err(_) => break,
+        }
+        pending -= 1;
+    }
+    return Err(last_error);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::time::Instant;
+
+    #[test]
+    fn test_dual_stack() {
+        let v4 = |port: u16| SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let v6 = |port: u16| SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+        assert_eq!(interleave_families(vec![v6(1), v6(2), v4(3), v4(4), v6(5)]), vec![v6(1), v4(3), v6(2), v4(4), v6(5)]);
+        assert_eq!(interleave_families(vec![v4(1), v6(2)]), vec![v4(1), v6(2)]);
+        let mapped = map_addr(v4(7), v6(0));
+        assert_eq!(mapped.to_string(), "[::ffff:127.0.0.1]:7");
+        assert_eq!(map_addr(v4(7), v4(0)), v4(7));
+        assert_eq!(canonical_addr(mapped), v4(7));
+        assert_eq!("dual".parse(), Ok(AddressFamily::Dual));
+        assert!("ipv5".parse::<AddressFamily>().is_err());
+        assert_eq!(bind_tcp("127.0.0.1:0", AddressFamily::V6).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // a dual-stack listener takes clients of both families, an IPv6 only one refuses IPv4 clients
+        let listener = bind_tcp("[::]:0", AddressFamily::Dual).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let peer = canonical_addr(stream.peer_addr().unwrap());
+                stream.write_all(&[peer.is_ipv4() as u8]).unwrap();
+            }
+        });
+        for (addr, is_ipv4) in [(v4(port), 1u8), (v6(port), 0u8)] {
+            let mut answer = [0u8; 1];
+            connect_tcp(addr, Duration::from_secs(5)).unwrap().read_exact(&mut answer).unwrap();
+            assert_eq!(answer[0], is_ipv4);
+        }
+        let v6_only = bind_tcp("[::1]:0", AddressFamily::V6).unwrap();
+        assert!(TcpStream::connect(v4(v6_only.local_addr().unwrap().port())).is_err());
+
+        // a dead address in the list costs no more than the others failing
+        let dead = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let live = TcpListener::bind("[::1]:0").unwrap();
+        let start = Instant::now();
+        let stream = connect_tcp(&[dead, live.local_addr().unwrap()][..], Duration::from_secs(5)).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), live.local_addr().unwrap());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(connect_tcp(dead, Duration::from_secs(5)).is_err());
+
+        // UDP from a dual-stack socket reaches IPv4 peers through mapped addresses
+        let socket = bind_udp("[::]:0", AddressFamily::Dual).unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = map_addr(receiver.local_addr().unwrap(), socket.local_addr().unwrap());
+        socket.send_to(b"hello", target).unwrap();
+        let mut buf = [0u8; 16];
+        let (len, from) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(from.port(), socket.local_addr().unwrap().port());
+    }
+}
@@ -0,0 +1,259 @@
+//! Outgoing datagram queues for a sender sharing one uplink between control and data traffic. Each traffic class
+//! has its own queue and they are drained by deficit round robin, so a saturated uplink full of bulk symbols still
+//! gets manifests and handshakes out within a round.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+use crate::codec::encoder::EncodedBlock;
+use super::net::map_addr;
+use super::udp::{encode_datagram, FlowId, Integrity};
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+use super::uring::Uring;
+
+/// Bytes a traffic class may send per unit of weight each round, about one full size datagram.
+const QUANTUM_SIZE: usize = 1500;
+
+const NUM_TRAFFIC_CLASSES: usize = 3;
+
+/// What a datagram carries, deciding how soon it is sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TrafficClass {
+    /// Manifests, handshakes and other small messages a transfer waits on.
+    Control = 0,
+    /// Symbols a receiver asked for, e.g. in answer to a repair request.
+    Repair = 1,
+    /// Symbols sent unprompted, e.g. by a carousel.
+    Bulk = 2,
+}
+
+const TRAFFIC_CLASSES: [TrafficClass; NUM_TRAFFIC_CLASSES] = [TrafficClass::Control, TrafficClass::Repair, TrafficClass::Bulk];
+
+/// Datagrams waiting to be sent, per traffic class. Each round, a class may send up to its weight in full size
+/// datagrams, with unused allowance carried over only while it has datagrams waiting, so classes share the uplink
+/// by weight when all are busy and an idle class costs the others nothing.
+pub struct SendQueue {
+    queues: [VecDeque<(SocketAddr, Vec<u8>)>; NUM_TRAFFIC_CLASSES],
+    queued_bytes: [usize; NUM_TRAFFIC_CLASSES],
+    weights: [u32; NUM_TRAFFIC_CLASSES],
+    /// Bytes each class may still send this round.
+    deficits: [usize; NUM_TRAFFIC_CLASSES],
+    /// Class whose turn it is, and whether it got its allowance for this turn yet.
+    current: usize,
+    turn_started: bool,
+}
+
+impl SendQueue {
+    /// Creates an empty SendQueue weighting control 16, repair 4 and bulk 1.
+    pub fn new() -> SendQueue {
+        return SendQueue {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            queued_bytes: [0; NUM_TRAFFIC_CLASSES],
+            weights: [16, 4, 1],
+            deficits: [0; NUM_TRAFFIC_CLASSES],
+            current: 0,
+            turn_started: false,
+        };
+    }
+
+    /// Sets how many full size datagrams a class may send per round. Panics if weight is zero.
+    pub fn set_weight(&mut self, class: TrafficClass, weight: u32) {
+        assert!(weight > 0, "weight must be positive");
+        self.weights[class as usize] = weight;
+    }
+
+    pub fn push(&mut self, class: TrafficClass, addr: SocketAddr, datagram: Vec<u8>) {
+        self.queued_bytes[class as usize] += datagram.len();
+        self.queues[class as usize].push_back((addr, datagram));
+    }
+
+    /// Queues symbols of a flow, one per datagram, as send_flow would send them.
+    pub fn push_flow(&mut self, class: TrafficClass, addr: SocketAddr, flow_id: FlowId, blocks: &[EncodedBlock], integrity: Integrity) {
+        for block in blocks.iter() {
+            self.push(class, addr, encode_datagram(flow_id, block, integrity));
+        }
+    }
+
+    /// Picks the class to send from next, without taking its datagram.
+    fn select(&mut self) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+
+        loop {
+            let class = self.current;
+            match self.queues[class].front() {
+                None => self.deficits[class] = 0,
+                Some((_, datagram)) if self.deficits[class] >= datagram.len() => return Some(class),
+                Some(_) if !self.turn_started => {
+                    self.deficits[class] += self.weights[class] as usize * QUANTUM_SIZE;
+                    self.turn_started = true;
+                    continue;
+                },
+                // keep the allowance, datagrams larger than a quantum go out after a few rounds
+                Some(_) => (),
+            }
+            self.current = (self.current + 1) % NUM_TRAFFIC_CLASSES;
+            self.turn_started = false;
+        }
+    }
+
+    /// Takes the datagram of a class picked by select.
+    fn take(&mut self, class: usize) -> (SocketAddr, Vec<u8>) {
+        let (addr, datagram) = self.queues[class].pop_front().unwrap();
+        self.deficits[class] -= datagram.len();
+        self.queued_bytes[class] -= datagram.len();
+        return (addr, datagram);
+    }
+
+    /// Takes the next datagram to send.
+    pub fn pop(&mut self) -> Option<(TrafficClass, SocketAddr, Vec<u8>)> {
+        let class = self.select()?;
+        let (addr, datagram) = self.take(class);
+        return Some((TRAFFIC_CLASSES[class], addr, datagram));
+    }
+
+    /// Sends queued datagrams until the queue is empty or, on a non-blocking socket, the socket would block,
+    /// returning how many were sent. A datagram that could not be sent stays first in line.
+    /// IPv4 addresses are mapped for a dual-stack socket, see net::map_addr.
+    pub fn send_to(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        let local = socket.local_addr()?;
+        let mut sent: usize = 0;
+        while let Some(class) = self.select() {
+            let (addr, datagram) = self.queues[class].front().unwrap();
+            match socket.send_to(datagram, map_addr(*addr, local)) {
+                Ok(_) => sent += 1,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+            self.take(class);
+        }
+        return Ok(sent);
+    }
+
+    /// Sends queued datagrams as send_to does, submitting them to uring in batches instead of a syscall each.
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    pub fn send_uring(&mut self, uring: &mut Uring, socket: &UdpSocket) -> io::Result<usize> {
+        let mut sent: usize = 0;
+        loop {
+            let mut batch: Vec<(usize, SocketAddr, Vec<u8>)> = Vec::new();
+            while batch.len() < uring.get_entries() {
+                match self.select() {
+                    Some(class) => {
+                        let (addr, datagram) = self.take(class);
+                        batch.push((class, addr, datagram));
+                    },
+                    None => break,
+                }
+            }
+            if batch.is_empty() {
+                return Ok(sent);
+            }
+
+            let datagrams: Vec<(SocketAddr, &[u8])> = batch.iter().map(|(_, addr, datagram)| (*addr, &datagram[..])).collect();
+            let result = uring.send_batch(socket, &datagrams);
+            let batch_sent = *result.as_ref().unwrap_or(&0);
+            sent += batch_sent;
+            let stopped = batch_sent < batch.len();
+            // what wasn't sent goes back first in line, with the allowance it took
+            for (class, addr, datagram) in batch.drain(batch_sent..).rev() {
+                self.deficits[class] += datagram.len();
+                self.queued_bytes[class] += datagram.len();
+                self.queues[class].push_front((addr, datagram));
+            }
+            match result {
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(sent),
+                Err(error) => return Err(error),
+                Ok(_) if stopped => return Ok(sent),
+                Ok(_) => (),
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.queues.iter().all(|x| x.is_empty());
+    }
+
+    /// Gets the number of datagrams of a class waiting to be sent.
+    pub fn get_queued(&self, class: TrafficClass) -> usize {
+        return self.queues[class as usize].len();
+    }
+
+    /// Gets the bytes of datagrams of a class waiting to be sent.
+    pub fn get_queued_bytes(&self, class: TrafficClass) -> usize {
+        return self.queued_bytes[class as usize];
+    }
+}
+
+impl Default for SendQueue {
+    fn default() -> SendQueue {
+        return SendQueue::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoder::*;
+    use crate::transport::udp::decode_datagram;
+
+    #[test]
+    fn test_send_queue_weighted() {
+        let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let mut queue = SendQueue::new();
+        for class in TRAFFIC_CLASSES.iter() {
+            for _ in 0..100 {
+                queue.push(*class, addr, vec![0; QUANTUM_SIZE]);
+            }
+        }
+
+        // a round sends each class's weight in full size datagrams
+        let mut sent = [0; NUM_TRAFFIC_CLASSES];
+        for _ in 0..(2 * (16 + 4 + 1)) {
+            sent[queue.pop().unwrap().0 as usize] += 1;
+        }
+        assert_eq!(sent, [32, 8, 2]);
+        assert_eq!(queue.get_queued(TrafficClass::Bulk), 98);
+        assert_eq!(queue.get_queued_bytes(TrafficClass::Control), 68 * QUANTUM_SIZE);
+
+        // an idle class does not slow the others down
+        let mut queue = SendQueue::new();
+        for _ in 0..10 {
+            queue.push(TrafficClass::Bulk, addr, vec![0; QUANTUM_SIZE]);
+        }
+        queue.push(TrafficClass::Bulk, addr, vec![0; 4 * QUANTUM_SIZE]);
+        for _ in 0..11 {
+            assert_eq!(queue.pop().unwrap().0, TrafficClass::Bulk);
+        }
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn test_send_queue_control_first() {
+        let data: Vec<u8> = (0..(100 * 1000)).map(|x| x as u8).collect();
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let blocks = encoder.generate_encoded_blocks();
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+
+        let mut queue = SendQueue::new();
+        queue.push_flow(TrafficClass::Bulk, addr, 1, &blocks, Integrity::None);
+        queue.push(TrafficClass::Control, addr, b"manifest".to_vec());
+        queue.push_flow(TrafficClass::Repair, addr, 2, &blocks[..1], Integrity::None);
+
+        // the bulk flow was queued first, but the control message and repair symbol go out right away
+        assert_eq!(queue.send_to(&sender).unwrap(), blocks.len() + 2);
+        assert!(queue.is_empty());
+        let mut buf = [0; 2048];
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"manifest");
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(decode_datagram(&buf[..len]).unwrap().0, 2);
+    }
+}
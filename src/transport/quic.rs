@@ -0,0 +1,389 @@
+//! QUIC transport pushing objects to a receiver. A sender announces each object by writing its manifest on a
+//! bidirectional stream, then sends its symbols as unreliable datagrams, one symbol each, which suits a fountain code
+//! better than a stream would. The receiver answers on the stream once the object's session is set up and again once
+//! it is decoded, so the sender knows when to stop.
+//!
+//! Decoder sessions are keyed by connection and object id, never by the sender's address, so a sender whose address
+//! changes mid-transfer, e.g. a laptop moving from WiFi to LTE, keeps its symbol flow going: QUIC migrates the
+//! connection to the new path and the session carries on with the symbols it already has.
+
+use bytes::Bytes;
+use quinn::{Connection, Endpoint, RecvStream, ServerConfig};
+use raptorq::{EncodingPacket, PayloadId};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+use crate::codec::decoder::RaptorQDecoder;
+use crate::codec::encoder::EncodedBlock;
+use crate::codec::manifest::{validate_manifest, DecoderLimits, Manifest, ObjectId};
+
+/// Size of the datagram header: object id, block id (u32, little endian) and serialized payload id.
+pub const QUIC_DATAGRAM_HEADER_SIZE: usize = 32 + 4 + 4;
+
+/// Most objects a connection may have in flight, so a sender can't make the receiver allocate decoders without end.
+pub const MAX_SESSIONS_PER_CONNECTION: usize = 64;
+
+/// Largest manifest an announcement may carry.
+const MAX_MANIFEST_SIZE: usize = 1 << 20;
+
+/// Written on the announcement stream once the session is set up, so symbols sent from then on are decoded.
+const ANNOUNCE_ACCEPTED: u8 = 0;
+
+/// Written on the announcement stream once the object is decoded.
+const ANNOUNCE_DECODED: u8 = 1;
+
+/// Identifies a connection across migrations, see quinn::Connection::stable_id.
+pub type ConnectionId = usize;
+
+/// An object a QuicReceiver decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedObject {
+    pub connection_id: ConnectionId,
+    pub object_id: ObjectId,
+    pub data: Vec<u8>,
+    /// State of the session when the object was decoded.
+    pub stats: QuicSessionStats,
+}
+
+/// State of a decoder session, see QuicReceiver::get_session_stats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuicSessionStats {
+    /// Address the last symbol came from.
+    pub remote: SocketAddr,
+    /// Times the sender's address changed while the session was open.
+    pub migrations: u32,
+    pub symbols: u64,
+}
+
+struct QuicSession {
+    manifest: Manifest,
+    decoder: RaptorQDecoder,
+    stats: QuicSessionStats,
+    decoded: Option<oneshot::Sender<()>>,
+}
+
+type SessionTable = HashMap<(ConnectionId, ObjectId), Arc<Mutex<QuicSession>>>;
+
+/// Writes a datagram carrying one symbol of an object.
+pub fn encode_quic_datagram(object_id: &ObjectId, block: &EncodedBlock) -> Vec<u8> {
+    let mut datagram: Vec<u8> = Vec::with_capacity(QUIC_DATAGRAM_HEADER_SIZE + block.data.data().len());
+    datagram.extend_from_slice(object_id);
+    datagram.extend_from_slice(&block.block_id.to_le_bytes());
+    datagram.extend_from_slice(&block.data.payload_id().serialize());
+    datagram.extend_from_slice(block.data.data());
+    return datagram;
+}
+
+/// Reads a datagram written by encode_quic_datagram, or None if it is too short to be one.
+pub fn decode_quic_datagram(datagram: &[u8]) -> Option<(ObjectId, EncodedBlock)> {
+    if datagram.len() <= QUIC_DATAGRAM_HEADER_SIZE {
+        return None;
+    }
+
+    let object_id: ObjectId = datagram[..32].try_into().unwrap();
+    let block_id = u32::from_le_bytes(datagram[32..36].try_into().unwrap());
+    let payload_id = PayloadId::deserialize(&datagram[36..40].try_into().unwrap());
+    let data = EncodingPacket::new(payload_id, datagram[QUIC_DATAGRAM_HEADER_SIZE..].to_vec());
+    return Some((object_id, EncodedBlock { block_id, data }));
+}
+
+/// Receives objects pushed by QuicSenders, decoding each in a session of its connection and object id.
+#[derive(Clone)]
+pub struct QuicReceiver {
+    endpoint: Endpoint,
+    sessions: Arc<Mutex<SessionTable>>,
+    limits: DecoderLimits,
+    decoded: Sender<DecodedObject>,
+}
+
+impl QuicReceiver {
+    /// Binds a QUIC endpoint on addr, sending every object decoded to decoded. Manifests are checked against limits
+    /// before any decoder is allocated. Must be called within a tokio runtime.
+    pub fn bind(addr: SocketAddr, server_config: ServerConfig, limits: DecoderLimits, decoded: Sender<DecodedObject>) -> io::Result<QuicReceiver> {
+        return Ok(QuicReceiver {
+            endpoint: Endpoint::server(server_config, addr)?,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            limits,
+            decoded,
+        });
+    }
+
+    pub fn get_local_addr(&self) -> io::Result<SocketAddr> {
+        return self.endpoint.local_addr();
+    }
+
+    /// Accepts connections until the endpoint is closed, serving each on a task of its own.
+    pub async fn run(&self) {
+        while let Some(incoming) = self.endpoint.accept().await {
+            let receiver = self.clone();
+            tokio::spawn(async move {
+                if let Ok(connection) = incoming.await {
+                    receiver.serve(connection).await;
+                }
+            });
+        }
+    }
+
+    /// Closes the endpoint, ending run and every connection.
+    pub fn close(&self) {
+        self.endpoint.close(0u32.into(), b"closing");
+    }
+
+    /// Gets the number of decoder sessions open, across connections.
+    pub fn get_session_count(&self) -> usize {
+        return self.sessions.lock().unwrap().len();
+    }
+
+    /// Gets the state of the session of an object on a connection, while it is open.
+    pub fn get_session_stats(&self, connection_id: ConnectionId, object_id: &ObjectId) -> Option<QuicSessionStats> {
+        let session = self.sessions.lock().unwrap().get(&(connection_id, *object_id)).cloned()?;
+        let stats = session.lock().unwrap().stats;
+        return Some(stats);
+    }
+
+    /// Serves a connection until it closes, then drops its sessions.
+    async fn serve(&self, connection: Connection) {
+        let connection_id = connection.stable_id();
+        let announcements = {
+            let receiver = self.clone();
+            let connection = connection.clone();
+            tokio::spawn(async move {
+                while let Ok((send, recv)) = connection.accept_bi().await {
+                    let receiver = receiver.clone();
+                    let connection = connection.clone();
+                    tokio::spawn(async move {
+                        let _ = receiver.announce(&connection, send, recv).await;
+                    });
+                }
+            })
+        };
+
+        while let Ok(datagram) = connection.read_datagram().await {
+            self.receive(&connection, &datagram);
+        }
+        announcements.abort();
+        self.sessions.lock().unwrap().retain(|key, _| key.0 != connection_id);
+    }
+
+    /// Sets up the session of the object whose manifest is on recv, then answers once it is set up and once it is
+    /// decoded.
+    async fn announce(&self, connection: &Connection, mut send: quinn::SendStream, mut recv: RecvStream) -> io::Result<()> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad announcement: {}", reason));
+
+        let written = recv.read_to_end(MAX_MANIFEST_SIZE).await.map_err(|error| io::Error::other(error.to_string()))?;
+        let manifest = Manifest::read_from(&written[..])?;
+        if validate_manifest(&manifest, &self.limits).is_err() {
+            return Err(invalid("manifest out of limits"));
+        }
+        let decoder = match RaptorQDecoder::from_manifest(&manifest) {
+            Ok(decoder) => decoder,
+            Err(error) => return Err(invalid(&format!("{:?}", error))),
+        };
+
+        let connection_id = connection.stable_id();
+        let (done, decoded) = oneshot::channel();
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            if sessions.keys().filter(|key| key.0 == connection_id).count() >= MAX_SESSIONS_PER_CONNECTION {
+                return Err(invalid("too many objects in flight"));
+            }
+            let stats = QuicSessionStats { remote: connection.remote_address(), migrations: 0, symbols: 0 };
+            let session = QuicSession { manifest, decoder, stats, decoded: Some(done) };
+            sessions.entry((connection_id, session.manifest.object_id)).or_insert_with(|| Arc::new(Mutex::new(session)));
+        }
+
+        send.write_all(&[ANNOUNCE_ACCEPTED]).await?;
+        if decoded.await.is_ok() {
+            send.write_all(&[ANNOUNCE_DECODED]).await?;
+            let _ = send.finish();
+        }
+        return Ok(());
+    }
+
+    /// Feeds a datagram to its session, closing it once the object is decoded. Datagrams of objects not announced, or
+    /// already decoded, are dropped.
+    fn receive(&self, connection: &Connection, datagram: &[u8]) {
+        let (object_id, block) = match decode_quic_datagram(datagram) {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let connection_id = connection.stable_id();
+        let session = match self.sessions.lock().unwrap().get(&(connection_id, object_id)) {
+            Some(session) => session.clone(),
+            None => return,
+        };
+
+        // decode outside the table lock, so other sessions aren't held up
+        let mut session = session.lock().unwrap();
+        if session.decoded.is_none() {
+            return;
+        }
+        let remote = connection.remote_address();
+        if remote != session.stats.remote {
+            session.stats.remote = remote;
+            session.stats.migrations += 1;
+        }
+        session.stats.symbols += 1;
+        if session.decoder.consume(vec![block]) != Ok(true) {
+            return;
+        }
+
+        let data = match session.decoder.get_result() {
+            Some(data) if <ObjectId>::from(Sha256::digest(&data)) == session.manifest.object_id => data,
+            _ => return,
+        };
+        if let Some(done) = session.decoded.take() {
+            let _ = done.send(());
+        }
+        self.sessions.lock().unwrap().remove(&(connection_id, object_id));
+        let _ = self.decoded.send(DecodedObject { connection_id, object_id, data, stats: session.stats });
+    }
+}
+
+/// Pushes objects to a QuicReceiver over a connection.
+pub struct QuicSender {
+    connection: Connection,
+}
+
+/// An object announced by QuicSender::announce.
+pub struct Announcement {
+    object_id: ObjectId,
+    recv: RecvStream,
+}
+
+impl QuicSender {
+    /// Connects endpoint to the receiver at addr, whose certificate must be valid for server_name.
+    pub async fn connect(endpoint: &Endpoint, addr: SocketAddr, server_name: &str) -> io::Result<QuicSender> {
+        let connecting = endpoint.connect(addr, server_name).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error.to_string()))?;
+        return Ok(QuicSender { connection: connecting.await? });
+    }
+
+    pub fn get_connection(&self) -> &Connection {
+        return &self.connection;
+    }
+
+    /// Announces an object by its manifest, returning once the receiver is ready for its symbols.
+    pub async fn announce(&self, manifest: &Manifest) -> io::Result<Announcement> {
+        let (mut send, mut recv) = self.connection.open_bi().await?;
+        let mut written: Vec<u8> = Vec::new();
+        manifest.write_to(&mut written)?;
+        send.write_all(&written).await?;
+        let _ = send.finish();
+
+        let mut answer = [0u8; 1];
+        recv.read_exact(&mut answer).await.map_err(|error| io::Error::new(io::ErrorKind::ConnectionAborted, error.to_string()))?;
+        if answer[0] != ANNOUNCE_ACCEPTED {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected answer to announcement"));
+        }
+        return Ok(Announcement { object_id: manifest.object_id, recv });
+    }
+
+    /// Sends symbols of an object announced on this connection, waiting for room in the send buffer rather than dropping any. Fails
+    /// with ErrorKind::InvalidInput if a symbol does not fit in a datagram of the connection's path.
+    pub async fn send_symbols(&self, object_id: &ObjectId, blocks: &[EncodedBlock]) -> io::Result<()> {
+        for block in blocks.iter() {
+            let datagram = encode_quic_datagram(object_id, block);
+            if self.connection.max_datagram_size().is_none_or(|max| datagram.len() > max) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "symbol too large for a datagram"));
+            }
+            self.connection.send_datagram_wait(Bytes::from(datagram)).await.map_err(|error| io::Error::other(error.to_string()))?;
+        }
+        return Ok(());
+    }
+}
+
+impl Announcement {
+    pub fn get_object_id(&self) -> &ObjectId {
+        return &self.object_id;
+    }
+
+    /// Waits until the receiver decoded the object.
+    pub async fn decoded(mut self) -> io::Result<()> {
+        let mut answer = [0u8; 1];
+        self.recv.read_exact(&mut answer).await.map_err(|error| io::Error::new(io::ErrorKind::ConnectionAborted, error.to_string()))?;
+        if answer[0] != ANNOUNCE_DECODED {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected answer to announcement"));
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::RaptorQEncoder;
+    use quinn::rustls::pki_types::PrivatePkcs8KeyDer;
+    use quinn::rustls::RootCertStore;
+    use quinn::ClientConfig;
+    use std::net::UdpSocket;
+    use std::sync::mpsc;
+
+    fn gen_configs() -> (ServerConfig, ClientConfig) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let server_config = ServerConfig::with_single_cert(vec![cert.clone()], key.into()).unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        return (server_config, ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_quic_migration() {
+        let (server_config, client_config) = gen_configs();
+        let (decoded_sender, decoded) = mpsc::channel();
+        let receiver = QuicReceiver::bind("127.0.0.1:0".parse().unwrap(), server_config, DecoderLimits::default(), decoded_sender).unwrap();
+        let addr = receiver.get_local_addr().unwrap();
+        let running = {
+            let receiver = receiver.clone();
+            tokio::spawn(async move { receiver.run().await })
+        };
+
+        let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(client_config);
+        let sender = QuicSender::connect(&endpoint, addr, "localhost").await.unwrap();
+
+        let data = gen_data(100 * 1000);
+        let encoder = match RaptorQEncoder::new(1000, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {:?}", error),
+        };
+        let manifest = Manifest::new(&encoder);
+        let announcement = sender.announce(&manifest).await.unwrap();
+        assert_eq!(receiver.get_session_count(), 1);
+        let block_encoder = &encoder.get_block_encoders()[0];
+        let source = block_encoder.generate_source_blocks();
+        sender.send_symbols(&manifest.object_id, &source[..50]).await.unwrap();
+
+        // the sender moves to a new address, and the rest of the symbols go to the same session
+        endpoint.rebind(UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+        let moved = endpoint.local_addr().unwrap();
+        let done = tokio::spawn(announcement.decoded());
+        sender.send_symbols(&manifest.object_id, &source[50..]).await.unwrap();
+        let mut start_symbol = 0;
+        while !done.is_finished() && start_symbol < 1000 {
+            sender.send_symbols(&manifest.object_id, &block_encoder.generate_repair_blocks(start_symbol, 10)).await.unwrap();
+            start_symbol += 10;
+            tokio::task::yield_now().await;
+        }
+        done.await.unwrap().unwrap();
+
+        let object = decoded.recv().unwrap();
+        assert_eq!(object.object_id, manifest.object_id);
+        assert_eq!(object.data, data);
+        assert_eq!(object.stats.remote, moved);
+        assert!(object.stats.migrations >= 1);
+        assert_eq!(receiver.get_session_count(), 0);
+        assert_eq!(decode_quic_datagram(&[0; QUIC_DATAGRAM_HEADER_SIZE]), None);
+
+        receiver.close();
+        running.await.unwrap();
+    }
+}
@@ -0,0 +1,657 @@
+//! UDP transport carrying many transfers over one socket. Each datagram holds a single symbol and a header naming
+//! the flow it belongs to, and a FlowDemux on the receiving socket routes symbols to the flow's decoder.
+//!
+//! Datagrams can optionally carry a CRC32C of the whole datagram at a fixed offset, which is cheap to compute with
+//! SSE4.2 or ARM CRC instructions, for catching corruption without the cost of a MAC.
+
+use raptorq::{EncodingPacket, PayloadId};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::codec::decoder::RaptorQDecoder;
+use crate::codec::encoder::EncodedBlock;
+use crate::codec::manifest::{is_manifest_block, validate_manifest, DecoderLimits, Manifest, ManifestDecoder};
+use super::net::map_addr;
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+use super::uring::Uring;
+
+/// Identifies a transfer among the ones sharing a socket, agreed on out of band, e.g. a server session id.
+pub type FlowId = u64;
+
+/// Version of the datagram layout, the first byte of every datagram.
+pub const WIRE_VERSION: u8 = 1;
+
+/// Size of the datagram header: version (u8), flags (u8), 2 reserved zero bytes, flow id (u64), block id (u32) and
+/// serialized payload id. Integers are little endian.
+pub const DATAGRAM_HEADER_SIZE: usize = 20;
+
+/// Flag set when a CRC32C follows the header, see Integrity::Crc32c.
+const FLAG_CRC32C: u8 = 1;
+
+/// Size of the CRC32C following the header when FLAG_CRC32C is set.
+const CRC32C_SIZE: usize = 4;
+
+/// Flag set when the datagram carries a manifest instead of a symbol.
+const FLAG_MANIFEST: u8 = 2;
+
+/// Per datagram integrity check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Integrity {
+    /// Nothing beyond the UDP checksum.
+    None,
+    /// A CRC32C (u32) right after the header, computed over the whole datagram with the CRC bytes zeroed.
+    Crc32c,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DatagramError {
+    /// Too short, of another version or with unknown flags.
+    Malformed,
+    /// The CRC32C does not match the contents.
+    BadChecksum,
+}
+
+/// Largest datagram FlowDemux::recv_from accepts.
+pub const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// Largest datagram IPv4 can carry, which manifest datagrams must fit in.
+const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// Writes a datagram carrying one symbol of a flow.
+pub fn encode_datagram(flow_id: FlowId, block: &EncodedBlock, integrity: Integrity) -> Vec<u8> {
+    return encode_body(flow_id, 0, block.block_id, block.data.payload_id().serialize(), block.data.data(), integrity);
+}
+
+/// Writes a datagram carrying the manifest of a flow, so receivers can join a carousel without a side channel, see
+/// FlowDemux::set_join_in_progress. Fails if the manifest can't be written, see Manifest::write_to, or with
+/// ErrorKind::InvalidInput if it does not fit in a datagram.
+pub fn encode_manifest_datagram(flow_id: FlowId, manifest: &Manifest, integrity: Integrity) -> io::Result<Vec<u8>> {
+    let mut body: Vec<u8> = Vec::new();
+    manifest.write_to(&mut body)?;
+    if DATAGRAM_HEADER_SIZE + CRC32C_SIZE + body.len() > MAX_UDP_PAYLOAD {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "manifest does not fit in a datagram"));
+    }
+    // the block id and payload id are unused
+    return Ok(encode_body(flow_id, FLAG_MANIFEST, 0, [0; 4], &body, integrity));
+}
+
+fn encode_body(flow_id: FlowId, flags: u8, block_id: u32, payload_id: [u8; 4], body: &[u8], integrity: Integrity) -> Vec<u8> {
+    let flags = match integrity {
+        Integrity::None => flags,
+        Integrity::Crc32c => flags | FLAG_CRC32C,
+    };
+
+    let mut datagram: Vec<u8> = Vec::with_capacity(DATAGRAM_HEADER_SIZE + CRC32C_SIZE + body.len());
+    datagram.extend_from_slice(&[WIRE_VERSION, flags, 0, 0]);
+    datagram.extend_from_slice(&flow_id.to_le_bytes());
+    datagram.extend_from_slice(&block_id.to_le_bytes());
+    datagram.extend_from_slice(&payload_id);
+    if integrity == Integrity::Crc32c {
+        datagram.extend_from_slice(&[0; CRC32C_SIZE]);
+        datagram.extend_from_slice(body);
+        let crc = crc32c::crc32c(&datagram);
+        datagram[DATAGRAM_HEADER_SIZE..(DATAGRAM_HEADER_SIZE + CRC32C_SIZE)].copy_from_slice(&crc.to_le_bytes());
+    } else {
+        datagram.extend_from_slice(body);
+    }
+    return datagram;
+}
+
+/// What a datagram carries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DatagramBody {
+    Symbol(EncodedBlock),
+    Manifest(Box<Manifest>),
+}
+
+/// Reads a datagram written by encode_datagram with either integrity check, verifying the CRC32C if it has one.
+/// Manifest datagrams are Malformed, see decode_datagram_body.
+pub fn decode_datagram(datagram: &[u8]) -> Result<(FlowId, Integrity, EncodedBlock), DatagramError> {
+    match decode_datagram_body(datagram)? {
+        (flow_id, integrity, DatagramBody::Symbol(block)) => return Ok((flow_id, integrity, block)),
+        (_, _, DatagramBody::Manifest(_)) => return Err(DatagramError::Malformed),
+    }
+}
+
+/// Reads a datagram written by encode_datagram or encode_manifest_datagram, verifying the CRC32C if it has one.
+pub fn decode_datagram_body(datagram: &[u8]) -> Result<(FlowId, Integrity, DatagramBody), DatagramError> {
+    if datagram.len() <= DATAGRAM_HEADER_SIZE || datagram[0] != WIRE_VERSION || datagram[2..4] != [0, 0] {
+        return Err(DatagramError::Malformed);
+    }
+
+    let (integrity, body_offset) = match datagram[1] & !FLAG_MANIFEST {
+        0 => (Integrity::None, DATAGRAM_HEADER_SIZE),
+        FLAG_CRC32C if datagram.len() > DATAGRAM_HEADER_SIZE + CRC32C_SIZE => (Integrity::Crc32c, DATAGRAM_HEADER_SIZE + CRC32C_SIZE),
+        _ => return Err(DatagramError::Malformed),
+    };
+    if integrity == Integrity::Crc32c {
+        let crc = u32::from_le_bytes(datagram[DATAGRAM_HEADER_SIZE..body_offset].try_into().unwrap());
+        let computed = crc32c::crc32c_append(crc32c::crc32c(&datagram[..DATAGRAM_HEADER_SIZE]), &[0; CRC32C_SIZE]);
+        if crc32c::crc32c_append(computed, &datagram[body_offset..]) != crc {
+            return Err(DatagramError::BadChecksum);
+        }
+    }
+
+    let flow_id = FlowId::from_le_bytes(datagram[4..12].try_into().unwrap());
+    if datagram[1] & FLAG_MANIFEST != 0 {
+        match Manifest::read_from(&datagram[body_offset..]) {
+            Ok(manifest) => return Ok((flow_id, integrity, DatagramBody::Manifest(Box::new(manifest)))),
+            Err(_) => return Err(DatagramError::Malformed),
+        }
+    }
+    let block_id = u32::from_le_bytes(datagram[12..16].try_into().unwrap());
+    let payload_id = PayloadId::deserialize(datagram[16..20].try_into().unwrap());
+    return Ok((flow_id, integrity, DatagramBody::Symbol(EncodedBlock {
+        block_id,
+        data: EncodingPacket::new(payload_id, datagram[body_offset..].to_vec()),
+    })));
+}
+
+/// Sends symbols of a flow, one per datagram. An IPv4 addr is mapped for a dual-stack socket, see net::map_addr.
+pub fn send_flow(socket: &UdpSocket, addr: SocketAddr, flow_id: FlowId, blocks: &[EncodedBlock], integrity: Integrity) -> io::Result<()> {
+    let addr = map_addr(addr, socket.local_addr()?);
+    for block in blocks.iter() {
+        socket.send_to(&encode_datagram(flow_id, block, integrity), addr)?;
+    }
+    return Ok(());
+}
+
+/// Counts of datagrams a FlowDemux received.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DemuxStats {
+    /// Datagrams routed to a flow.
+    pub routed: u64,
+    /// Datagrams for flows that are not registered, e.g. already complete or expired.
+    pub unknown_flow: u64,
+    /// Datagrams that could not be parsed, or that the flow's decoder rejected.
+    pub malformed: u64,
+    /// Datagrams with a CRC32C that did not match, or without one when checksums are required.
+    pub corrupt: u64,
+    /// Flows dropped for being idle.
+    pub expired: u64,
+    /// Manifests of unregistered flows dropped for receiving no manifest symbols for the idle timeout.
+    pub expired_manifests: u64,
+    /// Flows registered from a manifest datagram, see FlowDemux::set_join_in_progress.
+    pub joined: u64,
+    /// Symbols of unregistered flows kept until their manifest arrives.
+    pub buffered: u64,
+    /// Buffered symbols dropped to make room for newer ones, or for their flow's manifest being rejected, and
+    /// symbols of manifest blocks dropped for too many manifests being decoded at once.
+    pub buffer_dropped: u64,
+    /// Symbols of manifest blocks of unregistered flows, see Manifest::encode_block.
+    pub manifest_symbols: u64,
+}
+
+struct Flow {
+    decoder: RaptorQDecoder,
+    last_active: Instant,
+}
+
+/// Manifest of an unregistered flow being decoded from its blocks.
+struct ManifestJoin {
+    decoder: ManifestDecoder,
+    last_active: Instant,
+}
+
+/// Settings of a FlowDemux letting flows join in progress.
+struct JoinInProgress {
+    max_buffered: usize,
+    limits: DecoderLimits,
+    /// Symbols of unregistered flows, oldest first.
+    buffered: VecDeque<(FlowId, EncodedBlock)>,
+    /// Manifests of unregistered flows being decoded from their blocks.
+    manifests: HashMap<FlowId, ManifestJoin>,
+}
+
+/// Routes datagrams arriving on one socket to per-flow decoders. Flows that receive nothing for the idle timeout
+/// are dropped by expire_idle, so transfers whose senders went away don't hold their decoders forever, and so are
+/// manifests being decoded for flows to join, so stalled ones don't hold the slots of later joins.
+///
+/// Flows are keyed by flow id alone, never by the sender's address, so a transfer carries on when the sender's
+/// address changes mid-transfer, e.g. a peer moving from WiFi to LTE, or symbols of one flow come from several
+/// peers.
+pub struct FlowDemux {
+    flows: HashMap<FlowId, Flow>,
+    idle_timeout: Duration,
+    /// Whether datagrams without a CRC32C are dropped.
+    require_crc32c: bool,
+    join_in_progress: Option<JoinInProgress>,
+    stats: DemuxStats,
+}
+
+impl FlowDemux {
+    pub fn new(idle_timeout: Duration) -> FlowDemux {
+        return FlowDemux {
+            flows: HashMap::new(),
+            idle_timeout,
+            require_crc32c: false,
+            join_in_progress: None,
+            stats: DemuxStats::default(),
+        };
+    }
+
+    /// Drops datagrams without a CRC32C, counting them as corrupt, for senders known to always add one.
+    pub fn set_require_crc32c(&mut self, require_crc32c: bool) {
+        self.require_crc32c = require_crc32c;
+    }
+
+    /// Lets receivers tune in to a carousel mid-stream without learning manifests out of band. A manifest datagram
+    /// of an unregistered flow registers a decoder for it, if the manifest passes validate_manifest with limits.
+    /// Until then, up to max_buffered symbols of unregistered flows, over all flows, are kept and fed to the decoder
+    /// when it is registered, dropping the oldest once full. Manifests sent as blocks, see Manifest::encode_block,
+    /// are decoded from their symbols, for at most max_buffered flows at once. Manifests of registered flows are
+    /// ignored, and removing a flow lets its next manifest register it again. Off by default, when symbols of
+    /// unregistered flows and all manifests are dropped.
+    pub fn set_join_in_progress(&mut self, max_buffered: usize, limits: DecoderLimits) {
+        self.join_in_progress = Some(JoinInProgress { max_buffered, limits, buffered: VecDeque::new(), manifests: HashMap::new() });
+    }
+
+    /// Starts accepting symbols for a flow, replacing any flow with the same id.
+    pub fn register(&mut self, flow_id: FlowId, decoder: RaptorQDecoder, now: Instant) {
+        self.flows.insert(flow_id, Flow { decoder, last_active: now });
+    }
+
+    /// Stops accepting symbols for a flow, returning its decoder.
+    pub fn remove(&mut self, flow_id: FlowId) -> Option<RaptorQDecoder> {
+        return self.flows.remove(&flow_id).map(|x| x.decoder);
+    }
+
+    /// Routes a datagram to its flow's decoder. Returns the flow and whether it is now decoded, or None if the
+    /// datagram was dropped or buffered. A decoded flow stays registered until removed.
+    pub fn receive(&mut self, datagram: &[u8], now: Instant) -> Option<(FlowId, bool)> {
+        let (flow_id, body) = match decode_datagram_body(datagram) {
+            Ok((_, Integrity::None, _)) if self.require_crc32c => {
+                self.stats.corrupt += 1;
+                return None;
+            },
+            Ok((flow_id, _, body)) => (flow_id, body),
+            Err(DatagramError::BadChecksum) => {
+                self.stats.corrupt += 1;
+                return None;
+            },
+            Err(DatagramError::Malformed) => {
+                self.stats.malformed += 1;
+                return None;
+            },
+        };
+        let block = match body {
+            DatagramBody::Manifest(manifest) => return self.join(flow_id, &manifest, now),
+            DatagramBody::Symbol(block) if is_manifest_block(block.block_id) => return self.decode_manifest(flow_id, block, now),
+            DatagramBody::Symbol(block) => block,
+        };
+        let flow = match self.flows.get_mut(&flow_id) {
+            None => {
+                self.buffer(flow_id, block);
+                return None;
+            },
+            Some(flow) => flow,
+        };
+
+        match flow.decoder.consume(vec![block]) {
+            Ok(decoded) => {
+                flow.last_active = now;
+                self.stats.routed += 1;
+                return Some((flow_id, decoded));
+            },
+            Err(_) => {
+                self.stats.malformed += 1;
+                return None;
+            },
+        }
+    }
+
+    /// Keeps a symbol of an unregistered flow until its manifest arrives, if flows can join in progress.
+    fn buffer(&mut self, flow_id: FlowId, block: EncodedBlock) {
+        let join = match self.join_in_progress.as_mut() {
+            Some(join) if join.max_buffered > 0 => join,
+            _ => {
+                self.stats.unknown_flow += 1;
+                return;
+            },
+        };
+        if join.buffered.len() == join.max_buffered {
+            join.buffered.pop_front();
+            self.stats.buffer_dropped += 1;
+        }
+        join.buffered.push_back((flow_id, block));
+        self.stats.buffered += 1;
+    }
+
+    /// Feeds a symbol of a manifest block of an unregistered flow to the flow's manifest decoder, registering the
+    /// flow once the manifest is decoded.
+    fn decode_manifest(&mut self, flow_id: FlowId, block: EncodedBlock, now: Instant) -> Option<(FlowId, bool)> {
+        if self.flows.contains_key(&flow_id) {
+            return None;
+        }
+        let join = match self.join_in_progress.as_mut() {
+            None => {
+                self.stats.unknown_flow += 1;
+                return None;
+            },
+            Some(join) => join,
+        };
+        if !join.manifests.contains_key(&flow_id) && join.manifests.len() >= join.max_buffered {
+            self.stats.buffer_dropped += 1;
+            return None;
+        }
+
+        self.stats.manifest_symbols += 1;
+        let manifest_join = join.manifests.entry(flow_id).or_insert_with(|| ManifestJoin { decoder: ManifestDecoder::new(), last_active: now });
+        let accepted = manifest_join.decoder.get_accepted();
+        match manifest_join.decoder.consume(vec![block]) {
+            Ok(None) => {
+                // only symbols the decoder takes keep the join alive, so symbols of another manifest don't hold it
+                if manifest_join.decoder.get_accepted() != accepted {
+                    manifest_join.last_active = now;
+                }
+                return None;
+            },
+            Ok(Some(manifest)) => return self.join(flow_id, &manifest, now),
+            Err(_) => {
+                // start over, the block may have been corrupt
+                join.manifests.remove(&flow_id);
+                self.stats.malformed += 1;
+                return None;
+            },
+        }
+    }
+
+    /// Registers a flow from its manifest, feeding it the symbols buffered for it.
+    fn join(&mut self, flow_id: FlowId, manifest: &Manifest, now: Instant) -> Option<(FlowId, bool)> {
+        let join = match self.join_in_progress.as_mut() {
+            None => {
+                self.stats.unknown_flow += 1;
+                return None;
+            },
+            Some(join) => join,
+        };
+        if self.flows.contains_key(&flow_id) {
+            return None;
+        }
+
+        join.manifests.remove(&flow_id);
+        let mut blocks: Vec<EncodedBlock> = Vec::new();
+        join.buffered.retain(|(x, block)| {
+            if *x == flow_id {
+                blocks.push(block.clone());
+            }
+            return *x != flow_id;
+        });
+        let decoder = match validate_manifest(manifest, &join.limits) {
+            Ok(()) => RaptorQDecoder::from_manifest(manifest).ok(),
+            Err(_) => None,
+        };
+        let mut decoder = match decoder {
+            None => {
+                self.stats.malformed += 1;
+                self.stats.buffer_dropped += blocks.len() as u64;
+                return None;
+            },
+            Some(decoder) => decoder,
+        };
+
+        // symbols the decoder rejects were corrupt or for another object, the flow is still worth joining
+        let mut decoded = false;
+        for block in blocks {
+            match decoder.consume(vec![block]) {
+                Ok(succ) => {
+                    decoded = succ;
+                    self.stats.routed += 1;
+                },
+                Err(_) => self.stats.malformed += 1,
+            }
+        }
+        self.flows.insert(flow_id, Flow { decoder, last_active: now });
+        self.stats.joined += 1;
+        return Some((flow_id, decoded));
+    }
+
+    /// Receives one datagram from socket and routes it as receive does.
+    pub fn recv_from(&mut self, socket: &UdpSocket) -> io::Result<Option<(FlowId, bool)>> {
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+        let (len, _) = socket.recv_from(&mut buf)?;
+        return Ok(self.receive(&buf[..len], Instant::now()));
+    }
+
+    /// Receives a batch of datagrams from socket through uring, see Uring::recv_batch, and routes each as receive
+    /// does, returning the flows they were routed to. bufs should be MAX_DATAGRAM_SIZE long to take any datagram.
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    pub fn recv_uring(&mut self, uring: &mut Uring, socket: &UdpSocket, bufs: &mut [Vec<u8>]) -> io::Result<Vec<(FlowId, bool)>> {
+        let received = uring.recv_batch(socket, bufs)?;
+        let now = Instant::now();
+        return Ok(received.iter().zip(bufs.iter()).filter_map(|((len, _), buf)| self.receive(&buf[..*len], now)).collect());
+    }
+
+    /// Drops flows that received nothing for the idle timeout, returning their ids. Manifests of unregistered flows
+    /// that received no symbols for the idle timeout are dropped too, see DemuxStats::expired_manifests.
+    pub fn expire_idle(&mut self, now: Instant) -> Vec<FlowId> {
+        let idle_timeout = self.idle_timeout;
+        if let Some(join) = self.join_in_progress.as_mut() {
+            let joins = join.manifests.len();
+            join.manifests.retain(|_, manifest_join| now.saturating_duration_since(manifest_join.last_active) < idle_timeout);
+            self.stats.expired_manifests += (joins - join.manifests.len()) as u64;
+        }
+
+        let expired: Vec<FlowId> = self.flows.iter()
+            .filter(|(_, flow)| now.saturating_duration_since(flow.last_active) >= idle_timeout)
+            .map(|(flow_id, _)| *flow_id)
+            .collect();
+        for flow_id in expired.iter() {
+            self.flows.remove(flow_id);
+        }
+
+        self.stats.expired += expired.len() as u64;
+        return expired;
+    }
+
+    /// Gets the decoder of a flow, e.g. to take the result once decoded.
+    pub fn get_decoder(&self, flow_id: FlowId) -> Option<&RaptorQDecoder> {
+        return self.flows.get(&flow_id).map(|x| &x.decoder);
+    }
+
+    pub fn get_stats(&self) -> DemuxStats {
+        return self.stats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::*;
+    use crate::codec::manifest::{MANIFEST_BLOCK_ID_BASE, MANIFEST_SWITCH_SYMBOLS};
+
+    #[test]
+    fn test_flow_demux() {
+        let packet_size: u16 = 1280;
+        let data: Vec<Vec<u8>> = vec![gen_data(50 * 1000), gen_data(70 * 1000)];
+        let encoders: Vec<RaptorQEncoder> = data.iter().map(|x| match RaptorQEncoder::new(packet_size, x) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        }).collect();
+
+        let start = Instant::now();
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        for (flow_id, encoder) in encoders.iter().enumerate() {
+            match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+                Ok(decoder) => demux.register(flow_id as FlowId, decoder, start),
+                Err(error) => panic!("Failed to create decoder, error {:?}", error),
+            }
+        }
+
+        // interleave both flows, as they would arrive on a shared socket
+        let mut datagrams: Vec<Vec<u8>> = Vec::new();
+        for _ in 0..2 {
+            let blocks: Vec<Vec<EncodedBlock>> = encoders.iter().map(|x| x.generate_encoded_blocks()).collect();
+            for i in 0..blocks.iter().map(|x| x.len()).max().unwrap() {
+                for (flow_id, flow_blocks) in blocks.iter().enumerate() {
+                    if let Some(block) = flow_blocks.get(i) {
+                        datagrams.push(encode_datagram(flow_id as FlowId, block, Integrity::None));
+                    }
+                }
+            }
+        }
+        datagrams.push(encode_datagram(7, &encoders[0].generate_encoded_blocks()[0], Integrity::None));
+        datagrams.push(vec![WIRE_VERSION + 1; 100]);
+
+        for datagram in datagrams.iter() {
+            demux.receive(datagram, start);
+        }
+        for (flow_id, flow_data) in data.iter().enumerate() {
+            assert_eq!(demux.get_decoder(flow_id as FlowId).unwrap().get_result().as_ref(), Some(flow_data));
+        }
+        let stats = demux.get_stats();
+        assert_eq!(stats.routed, datagrams.len() as u64 - 2);
+        assert_eq!(stats.unknown_flow, 1);
+        assert_eq!(stats.malformed, 1);
+
+        // flow 0 keeps receiving, flow 1 goes quiet
+        demux.receive(&datagrams[0], start + Duration::from_secs(4));
+        assert_eq!(demux.expire_idle(start + Duration::from_secs(6)), vec![1]);
+        assert!(demux.remove(0).is_some());
+        assert_eq!(demux.get_stats().expired, 1);
+    }
+
+    #[test]
+    fn test_datagram_crc32c() {
+        let data = gen_data(10 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let block = encoder.generate_encoded_blocks().remove(0);
+
+        let datagram = encode_datagram(3, &block, Integrity::Crc32c);
+        assert_eq!(datagram.len(), DATAGRAM_HEADER_SIZE + CRC32C_SIZE + block.data.data().len());
+        assert_eq!(decode_datagram(&datagram), Ok((3, Integrity::Crc32c, block.clone())));
+
+        // any flipped bit, header or symbol, is caught
+        for index in [0, 5, DATAGRAM_HEADER_SIZE + 1, datagram.len() - 1] {
+            let mut corrupted = datagram.clone();
+            corrupted[index] ^= 0x10;
+            assert!(decode_datagram(&corrupted).is_err());
+        }
+
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        demux.set_require_crc32c(true);
+        match RaptorQDecoder::new(encoder.get_block_info_vec()) {
+            Ok(decoder) => demux.register(3, decoder, Instant::now()),
+            Err(error) => panic!("Failed to create decoder, error {:?}", error),
+        }
+        assert!(demux.receive(&encode_datagram(3, &block, Integrity::None), Instant::now()).is_none());
+        assert!(demux.receive(&datagram, Instant::now()).is_some());
+        assert_eq!(demux.get_stats().corrupt, 1);
+    }
+
+    #[test]
+    fn test_flow_demux_migration() {
+        let data = gen_data(50 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let blocks = encoder.get_block_encoders()[0].generate_source_blocks();
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        demux.register(4, RaptorQDecoder::new(encoder.get_block_info_vec()).unwrap(), Instant::now());
+
+        // the sender moves to another address halfway through, the flow carries on
+        let (first, second) = blocks.split_at(blocks.len() / 2);
+        for (blocks, sender) in [(first, UdpSocket::bind("127.0.0.1:0").unwrap()), (second, UdpSocket::bind("127.0.0.1:0").unwrap())] {
+            send_flow(&sender, receiver.local_addr().unwrap(), 4, blocks, Integrity::None).unwrap();
+            for _ in 0..blocks.len() {
+                assert!(demux.recv_from(&receiver).unwrap().is_some());
+            }
+        }
+        assert_eq!(demux.get_decoder(4).unwrap().get_result(), Some(data));
+        assert_eq!(demux.get_stats().routed, blocks.len() as u64);
+    }
+
+    #[test]
+    fn test_flow_demux_expire_manifest_join() {
+        let data = gen_data(30 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let manifest_blocks = Manifest::new(&encoder).encode_block(1280).unwrap().generate_source_blocks();
+
+        // symbols of manifest blocks that never complete hold every join slot
+        let start = Instant::now();
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        demux.set_join_in_progress(2, DecoderLimits::default());
+        let stalled = EncodedBlock { block_id: MANIFEST_BLOCK_ID_BASE | 4, data: EncodingPacket::new(PayloadId::new(0, 0), vec![0; 1280]) };
+        for flow_id in [1, 2] {
+            assert!(demux.receive(&encode_datagram(flow_id, &stalled, Integrity::None), start).is_none());
+        }
+        for block in manifest_blocks.iter() {
+            assert!(demux.receive(&encode_datagram(5, block, Integrity::None), start).is_none());
+        }
+        assert!(demux.get_decoder(5).is_none());
+
+        // once idle they are evicted, and the flow joins
+        assert!(demux.expire_idle(start + Duration::from_secs(4)).is_empty());
+        assert_eq!(demux.get_stats().expired_manifests, 0);
+        assert!(demux.expire_idle(start + Duration::from_secs(6)).is_empty());
+        assert_eq!(demux.get_stats().expired_manifests, 2);
+        for block in manifest_blocks.iter() {
+            demux.receive(&encode_datagram(5, block, Integrity::None), start + Duration::from_secs(6));
+        }
+        assert!(demux.get_decoder(5).is_some());
+        assert_eq!(demux.get_stats().joined, 1);
+    }
+
+    #[test]
+    fn test_flow_demux_stale_manifest_block() {
+        let data = gen_data(30 * 1000);
+        let encoder = match RaptorQEncoder::new(1280, &data) {
+            Ok(succ) => succ,
+            Err(error) => panic!("Failed to create encoder, error {}", error as u32),
+        };
+        let block_encoder = Manifest::new(&encoder).encode_block(1280).unwrap();
+        let mut manifest_blocks = block_encoder.generate_source_blocks();
+        manifest_blocks.append(&mut block_encoder.generate_repair_blocks(0, MANIFEST_SWITCH_SYMBOLS));
+        let stale = EncodedBlock { block_id: MANIFEST_BLOCK_ID_BASE | 4, data: EncodingPacket::new(PayloadId::new(0, 0), vec![0; 1280]) };
+        assert_ne!(stale.block_id, manifest_blocks[0].block_id);
+
+        // symbols of the manifest being sent don't keep a join on a stale symbol alive
+        let start = Instant::now();
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        demux.set_join_in_progress(2, DecoderLimits::default());
+        assert!(demux.receive(&encode_datagram(5, &stale, Integrity::None), start).is_none());
+        for block in manifest_blocks[..MANIFEST_SWITCH_SYMBOLS - 1].iter() {
+            assert!(demux.receive(&encode_datagram(5, block, Integrity::None), start + Duration::from_secs(4)).is_none());
+        }
+        assert!(demux.expire_idle(start + Duration::from_secs(6)).is_empty());
+        assert_eq!(demux.get_stats().expired_manifests, 1);
+
+        // and once enough of them arrive the decoder drops the stale block for theirs
+        assert!(demux.receive(&encode_datagram(5, &stale, Integrity::None), start).is_none());
+        for block in manifest_blocks.iter() {
+            demux.receive(&encode_datagram(5, block, Integrity::None), start);
+        }
+        assert!(demux.get_decoder(5).is_some());
+        assert_eq!(demux.get_stats().joined, 1);
+    }
+
+    #[test]
+    fn test_flow_demux_forged_manifest_block() {
+        let start = Instant::now();
+        let mut demux = FlowDemux::new(Duration::from_secs(5));
+        demux.set_join_in_progress(4, DecoderLimits::default());
+
+        // unaligned symbols, a symbol count past a block's and no symbols at all
+        let forged = [(MANIFEST_BLOCK_ID_BASE | 4, 1001), (u32::MAX, 1024), (MANIFEST_BLOCK_ID_BASE, 1024)];
+        for (flow_id, &(block_id, symbol_size)) in forged.iter().enumerate() {
+            let block = EncodedBlock { block_id, data: EncodingPacket::new(PayloadId::new(0, 0), vec![0; symbol_size]) };
+            assert!(demux.receive(&encode_datagram(flow_id as FlowId, &block, Integrity::None), start).is_none());
+            assert!(demux.get_decoder(flow_id as FlowId).is_none());
+        }
+        assert_eq!(demux.get_stats().malformed, forged.len() as u64);
+    }
+}
@@ -0,0 +1,363 @@
+//! io_uring paths for the IO a busy node does most of: sending and receiving symbol datagrams, and reading and
+//! writing shard files. A Uring submits a whole batch of datagrams or file chunks with one syscall and waits for
+//! them together, so one thread can keep a multi-gigabit uplink busy instead of a thread per socket each making a
+//! syscall per datagram. Datagrams of a batch are linked, so they go out in order and the first that fails cancels
+//! the rest, keeping the semantics of the std paths: SendQueue::send_uring and FlowDemux::recv_uring behave as
+//! send_to and recv_from do.
+//!
+//! io_uring needs Linux 5.6 or newer and may be disabled, e.g. through kernel.io_uring_disabled or a container's
+//! seccomp filter, in which case Uring::new fails with ErrorKind::Unsupported and callers stay on the std paths.
+
+use io_uring::{opcode, squeue, types, IoUring};
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::os::unix::io::AsRawFd;
+
+use crate::codec::encoder::EncodedBlock;
+use crate::codec::shard::{read_shard, write_shard};
+use super::net::map_addr;
+
+/// Default size of a ring's submission queue, the most datagrams or file chunks submitted at once.
+pub const DEFAULT_RING_ENTRIES: u32 = 256;
+
+/// Bytes per read or write submitted for file IO.
+const FILE_CHUNK_SIZE: usize = 1 << 20;
+
+/// An io_uring instance, see the module documentation. Not shared between threads; give each its own.
+pub struct Uring {
+    ring: IoUring,
+    entries: usize,
+}
+
+impl Uring {
+    /// Creates a ring submitting up to entries operations at once. Fails with ErrorKind::Unsupported if the kernel
+    /// has no io_uring or it is disabled.
+    pub fn new(entries: u32) -> io::Result<Uring> {
+        match IoUring::new(entries) {
+            Ok(ring) => return Ok(Uring { ring, entries: entries as usize }),
+            Err(error) if matches!(error.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, format!("io_uring unavailable: {}", error)));
+            },
+            Err(error) => return Err(error),
+        }
+    }
+
+    /// Gets the most operations submitted at once.
+    pub fn get_entries(&self) -> usize {
+        return self.entries;
+    }
+
+    /// Submits entries, whose user data must be their index, and waits for all of them, returning their results in
+    /// order: what the syscall would return, or -errno.
+    ///
+    /// Safety: the buffers entries point to must stay valid until this returns.
+    unsafe fn complete(&mut self, entries: &[squeue::Entry]) -> io::Result<Vec<i32>> {
+        {
+            let mut submission = self.ring.submission();
+            for entry in entries.iter() {
+                if submission.push(entry).is_err() {
+                    return Err(io::Error::other("io_uring submission queue full"));
+                }
+            }
+        }
+
+        let mut results = vec![0; entries.len()];
+        let mut completed: usize = 0;
+        while completed < entries.len() {
+            match self.ring.submit_and_wait(entries.len() - completed) {
+                Ok(_) => (),
+                // operations are in flight on the caller's buffers, wait them out whatever happens
+                Err(error) if error.kind() == io::ErrorKind::Interrupted || error.raw_os_error() == Some(libc::EBUSY) => (),
+                Err(error) => return Err(error),
+            }
+            for entry in self.ring.completion() {
+                results[entry.user_data() as usize] = entry.result();
+                completed += 1;
+            }
+        }
+        return Ok(results);
+    }
+
+    /// Sends datagrams on socket, each to its address and in order, returning how many were sent. Sending stops at
+    /// the first datagram that fails; as with Write::write, its error is returned only if it is the first, otherwise
+    /// the count so far is, and the error comes up again when that datagram is sent next. On a non-blocking socket
+    /// that is ErrorKind::WouldBlock once the socket buffer is full. IPv4 addresses are mapped for a dual-stack
+    /// socket, see net::map_addr.
+    pub fn send_batch(&mut self, socket: &UdpSocket, datagrams: &[(SocketAddr, &[u8])]) -> io::Result<usize> {
+        let local = socket.local_addr()?;
+        let fd = types::Fd(socket.as_raw_fd());
+        let flags = if is_nonblocking(socket)? { libc::MSG_DONTWAIT as u32 } else { 0 };
+        let mut sent: usize = 0;
+        for batch in datagrams.chunks(self.entries) {
+            let mut addrs: Vec<(libc::sockaddr_storage, libc::socklen_t)> = batch.iter().map(|(addr, _)| to_sockaddr(map_addr(*addr, local))).collect();
+            let mut iovecs: Vec<libc::iovec> = batch.iter()
+                .map(|(_, datagram)| libc::iovec { iov_base: datagram.as_ptr() as *mut libc::c_void, iov_len: datagram.len() })
+                .collect();
+            let msgs: Vec<libc::msghdr> = addrs.iter_mut().zip(iovecs.iter_mut())
+                .map(|((addr, addr_len), iovec)| new_msghdr(addr, *addr_len, iovec))
+                .collect();
+            let entries: Vec<squeue::Entry> = msgs.iter().enumerate()
+                .map(|(index, msg)| link(opcode::SendMsg::new(fd, msg).flags(flags).build(), index, batch.len()))
+                .collect();
+
+            let results = unsafe { self.complete(&entries)? };
+            for result in results.into_iter() {
+                if result < 0 {
+                    if sent == 0 {
+                        return Err(io::Error::from_raw_os_error(-result));
+                    }
+                    return Ok(sent);
+                }
+                sent += 1;
+            }
+        }
+        return Ok(sent);
+    }
+
+    /// Receives datagrams from socket into bufs, in order, returning the length and sender of each one received.
+    /// Waits for the first datagram, then takes those already queued without waiting, up to one per buffer.
+    /// Datagrams longer than their buffer are truncated. A blocking socket's read timeout does not apply, io_uring
+    /// waits for as long as it takes; a non-blocking socket fails with ErrorKind::WouldBlock if nothing is queued.
+    pub fn recv_batch(&mut self, socket: &UdpSocket, bufs: &mut [Vec<u8>]) -> io::Result<Vec<(usize, SocketAddr)>> {
+        let count = std::cmp::min(bufs.len(), self.entries);
+        let bufs = &mut bufs[..count];
+        if bufs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let fd = types::Fd(socket.as_raw_fd());
+        let nonblocking = is_nonblocking(socket)?;
+        let mut addrs: Vec<libc::sockaddr_storage> = bufs.iter().map(|_| unsafe { mem::zeroed() }).collect();
+        let mut iovecs: Vec<libc::iovec> = bufs.iter_mut()
+            .map(|buf| libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() })
+            .collect();
+        let mut msgs: Vec<libc::msghdr> = addrs.iter_mut().zip(iovecs.iter_mut())
+            .map(|(addr, iovec)| new_msghdr(addr, mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t, iovec))
+            .collect();
+        let entries: Vec<squeue::Entry> = msgs.iter_mut().enumerate()
+            .map(|(index, msg)| {
+                // only the first waits, the rest end the batch once the socket's queue is empty
+                let flags = if index == 0 && !nonblocking { 0 } else { libc::MSG_DONTWAIT as u32 };
+                return link(opcode::RecvMsg::new(fd, msg).flags(flags).build(), index, count);
+            })
+            .collect();
+
+        let results = unsafe { self.complete(&entries)? };
+        let mut received: Vec<(usize, SocketAddr)> = Vec::new();
+        for (index, result) in results.into_iter().enumerate() {
+            if result < 0 {
+                if index == 0 {
+                    return Err(io::Error::from_raw_os_error(-result));
+                }
+                break;
+            }
+            received.push((result as usize, from_sockaddr(&addrs[index])?));
+        }
+        return Ok(received);
+    }
+
+    /// Writes data to file at offset, submitting it in chunks at once.
+    pub fn write_at(&mut self, file: &File, offset: u64, data: &[u8]) -> io::Result<()> {
+        return self.transfer(file, offset, data.as_ptr() as *mut u8, data.len(), true);
+    }
+
+    /// Fills buf from file at offset, submitting reads in chunks at once. Fails with ErrorKind::UnexpectedEof if the
+    /// file ends first.
+    pub fn read_exact_at(&mut self, file: &File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        return self.transfer(file, offset, buf.as_mut_ptr(), buf.len(), false);
+    }
+
+    /// Reads or writes len bytes at ptr from or to file at offset, resubmitting what short reads and writes left.
+    fn transfer(&mut self, file: &File, offset: u64, ptr: *mut u8, len: usize, write: bool) -> io::Result<()> {
+        let fd = types::Fd(file.as_raw_fd());
+        // (position in the buffer, length) of the chunks left
+        let mut pending: Vec<(usize, usize)> = (0..len).step_by(FILE_CHUNK_SIZE).map(|x| (x, std::cmp::min(FILE_CHUNK_SIZE, len - x))).collect();
+        while !pending.is_empty() {
+            let batch: Vec<(usize, usize)> = pending.drain(..std::cmp::min(pending.len(), self.entries)).collect();
+            let entries: Vec<squeue::Entry> = batch.iter().enumerate()
+                .map(|(index, (position, chunk_len))| {
+                    let chunk = unsafe { ptr.add(*position) };
+                    let entry = match write {
+                        true => opcode::Write::new(fd, chunk, *chunk_len as u32).offset(offset + *position as u64).build(),
+                        false => opcode::Read::new(fd, chunk, *chunk_len as u32).offset(offset + *position as u64).build(),
+                    };
+                    return entry.user_data(index as u64);
+                })
+                .collect();
+
+            let results = unsafe { self.complete(&entries)? };
+            for ((position, chunk_len), result) in batch.into_iter().zip(results) {
+                if result < 0 {
+                    return Err(io::Error::from_raw_os_error(-result));
+                }
+                match (result as usize, write) {
+                    (0, true) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")),
+                    (0, false) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                    (done, _) if done < chunk_len => pending.push((position + done, chunk_len - done)),
+                    _ => (),
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Writes blocks to file as a shard, see shard::write_shard, serializing them in memory first.
+    pub fn write_shard(&mut self, file: &File, blocks: &[EncodedBlock]) -> io::Result<()> {
+        let mut data: Vec<u8> = Vec::new();
+        write_shard(&mut data, blocks)?;
+        return self.write_at(file, 0, &data);
+    }
+
+    /// Reads the encoded blocks of a whole shard file, see shard::read_shard.
+    pub fn read_shard(&mut self, file: &File) -> io::Result<Vec<EncodedBlock>> {
+        let mut data = vec![0u8; file.metadata()?.len() as usize];
+        self.read_exact_at(file, 0, &mut data)?;
+        return read_shard(&data[..]);
+    }
+}
+
+/// Whether socket is non-blocking. io_uring waits on sockets whatever their mode unless told not to per operation.
+fn is_nonblocking(socket: &UdpSocket) -> io::Result<bool> {
+    let flags = unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(flags & libc::O_NONBLOCK != 0);
+}
+
+/// Numbers the index-th of count entries, linking it to the next so they run in order and a failure cancels the
+/// rest.
+fn link(entry: squeue::Entry, index: usize, count: usize) -> squeue::Entry {
+    let entry = entry.user_data(index as u64);
+    if index + 1 < count {
+        return entry.flags(squeue::Flags::IO_LINK);
+    }
+    return entry;
+}
+
+fn new_msghdr(addr: &mut libc::sockaddr_storage, addr_len: libc::socklen_t, iovec: &mut libc::iovec) -> libc::msghdr {
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = addr_len;
+    msg.msg_iov = iovec;
+    msg.msg_iovlen = 1;
+    return msg;
+}
+
+fn to_sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    match addr {
+        SocketAddr::V4(addr) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+            sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+            sockaddr.sin_port = addr.port().to_be();
+            sockaddr.sin_addr = libc::in_addr { s_addr: u32::from_ne_bytes(addr.ip().octets()) };
+            return (storage, mem::size_of::<libc::sockaddr_in>() as libc::socklen_t);
+        },
+        SocketAddr::V6(addr) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+            sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sockaddr.sin6_port = addr.port().to_be();
+            sockaddr.sin6_flowinfo = addr.flowinfo();
+            sockaddr.sin6_addr = libc::in6_addr { s6_addr: addr.ip().octets() };
+            sockaddr.sin6_scope_id = addr.scope_id();
+            return (storage, mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t);
+        },
+    }
+}
+
+fn from_sockaddr(storage: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let sockaddr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(sockaddr.sin_addr.s_addr.to_ne_bytes());
+            return Ok(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sockaddr.sin_port))));
+        },
+        libc::AF_INET6 => {
+            let sockaddr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sockaddr.sin6_addr.s6_addr);
+            let port = u16::from_be(sockaddr.sin6_port);
+            return Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, sockaddr.sin6_flowinfo, sockaddr.sin6_scope_id)));
+        },
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown address family")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gen_data;
+    use crate::codec::encoder::{EncoderConfig, RaptorQEncoder};
+    use crate::codec::decoder::RaptorQDecoder;
+    use crate::transport::queue::{SendQueue, TrafficClass};
+    use crate::transport::udp::{FlowDemux, Integrity, MAX_DATAGRAM_SIZE};
+    use std::fs;
+    use std::io::IoSlice;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_uring() {
+        let mut uring = match Uring::new(8) {
+            Ok(uring) => uring,
+            // nothing to test where io_uring is disabled
+            Err(error) if error.kind() == io::ErrorKind::Unsupported => return,
+            Err(error) => panic!("failed to create ring: {}", error),
+        };
+
+        // datagrams in order, over more than one submission
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let datagrams: Vec<Vec<u8>> = (0..12).map(|x| vec![x as u8; 100 + x]).collect();
+        let batch: Vec<(SocketAddr, &[u8])> = datagrams.iter().map(|x| (receiver.local_addr().unwrap(), &x[..])).collect();
+        assert_eq!(uring.send_batch(&sender, &batch).unwrap(), 12);
+
+        let mut bufs: Vec<Vec<u8>> = vec![vec![0u8; MAX_DATAGRAM_SIZE]; 10];
+        let received = uring.recv_batch(&receiver, &mut bufs).unwrap();
+        assert_eq!(received.len(), 8);
+        let received: Vec<Vec<u8>> = received.iter().zip(bufs.iter()).map(|((len, addr), buf)| {
+            assert_eq!(*addr, sender.local_addr().unwrap());
+            return buf[..*len].to_vec();
+        }).collect();
+        assert_eq!(&received[..], &datagrams[..8]);
+        assert_eq!(uring.recv_batch(&receiver, &mut bufs).unwrap().len(), 4);
+        receiver.set_nonblocking(true).unwrap();
+        assert_eq!(uring.recv_batch(&receiver, &mut bufs).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        // a flow through a SendQueue and a FlowDemux
+        let data = gen_data(10 * 1000);
+        let encoder = RaptorQEncoder::with_config(EncoderConfig::new(1280), &[IoSlice::new(&data)]).unwrap();
+        let mut queue = SendQueue::new();
+        queue.push_flow(TrafficClass::Bulk, receiver.local_addr().unwrap(), 7, &encoder.generate_encoded_blocks(), Integrity::Crc32c);
+        let queued = queue.get_queued(TrafficClass::Bulk);
+        assert_eq!(queue.send_uring(&mut uring, &sender).unwrap(), queued);
+        assert!(queue.is_empty());
+
+        receiver.set_nonblocking(false).unwrap();
+        let mut demux = FlowDemux::new(Duration::from_secs(60));
+        demux.register(7, RaptorQDecoder::new(encoder.get_block_info_vec()).unwrap(), Instant::now());
+        let mut routed: Vec<(u64, bool)> = Vec::new();
+        while !routed.iter().any(|x| x.1) {
+            routed.extend(demux.recv_uring(&mut uring, &receiver, &mut bufs).unwrap());
+        }
+        assert_eq!(demux.get_decoder(7).unwrap().get_result().unwrap(), data);
+
+        // shard files
+        let dir = std::env::temp_dir().join(format!("raptorcdn-uring-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shard");
+        let blocks = encoder.generate_encoded_blocks();
+        uring.write_shard(&File::create(&path).unwrap(), &blocks).unwrap();
+        assert_eq!(read_shard(File::open(&path).unwrap()).unwrap(), blocks);
+        assert_eq!(uring.read_shard(&File::open(&path).unwrap()).unwrap(), blocks);
+
+        let data = gen_data(3 * FILE_CHUNK_SIZE + 100);
+        let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(dir.join("data")).unwrap();
+        uring.write_at(&file, 10, &data).unwrap();
+        let mut read = vec![0u8; data.len()];
+        uring.read_exact_at(&file, 10, &mut read).unwrap();
+        assert!(read == data);
+        assert_eq!(uring.read_exact_at(&file, 11, &mut read).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}